@@ -0,0 +1,212 @@
+//! Benchmarks of each frontend phase on its own, over the generated programs of
+//! `test_support`, run with `cargo bench --features bench`.
+//!
+//! `cargo bench --features bench -- --bench-gate` measures only the smallest size of every
+//! program instead, and fails if a phase got slower than in `benches/baseline.json` by more
+//! than `--threshold` percent, 25 by default. `--bench-record` writes that baseline again.
+
+use criterion::{black_box, BatchSize, BenchmarkId, Criterion, Throughput};
+use norem::frontend::ast::Expr;
+use norem::frontend::infer::Infer;
+use norem::frontend::lexer::{Lexer, Token};
+use norem::frontend::parser::{parse_program, Parser};
+use norem::frontend::renamer::Renamer;
+use norem::utils::driver::{self, BenchResult, BENCH_ITERATIONS, BENCH_WARMUP};
+use norem::utils::test_support::{bench_inputs, DEEP_STACK};
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+static BASELINE: &str = "benches/baseline.json";
+
+/// the percentage a phase may get slower by before the gate fails
+const GATE_THRESHOLD: f64 = 25.0;
+
+/// each sample of the gate repeats a phase for at least this long
+const GATE_SAMPLE: Duration = Duration::from_millis(20);
+
+/// the gate measures everything again this many times at most while a phase looks slower,
+/// keeping the fastest time, so that a busy machine doesn't fail it on its own
+const GATE_ATTEMPTS: usize = 3;
+
+fn lex(source: &str) -> Vec<Token> {
+    Lexer::new(source).collect()
+}
+
+fn parse(source: &str) -> Expr {
+    let mut par = Parser::new(source);
+    parse_program(&mut par).0.unwrap()
+}
+
+fn rename(expr: Expr) -> Expr {
+    Renamer::new().visit_expr(expr)
+}
+
+fn infer(expr: &Expr) {
+    Infer::new().infer_expr(expr).unwrap();
+}
+
+fn bench_phases(c: &mut Criterion) {
+    for (kind, sizes, generate) in bench_inputs() {
+        let mut group = c.benchmark_group(kind);
+        for size in sizes {
+            let source = generate(size);
+            let ast = parse(&source);
+            let renamed = rename(ast.clone());
+            group.throughput(Throughput::Bytes(source.len() as u64));
+            group.bench_with_input(BenchmarkId::new("lex", size), &source, |b, source| {
+                b.iter(|| lex(black_box(source)))
+            });
+            group.bench_with_input(BenchmarkId::new("parse", size), &source, |b, source| {
+                b.iter(|| parse(black_box(source)))
+            });
+            group.bench_with_input(BenchmarkId::new("rename", size), &ast, |b, ast| {
+                b.iter_batched(|| ast.clone(), rename, BatchSize::LargeInput)
+            });
+            group.bench_with_input(BenchmarkId::new("infer", size), &renamed, |b, expr| {
+                b.iter(|| infer(black_box(expr)))
+            });
+        }
+        group.finish();
+    }
+}
+
+/// the average time of `phase` over a sample, run until it takes `GATE_SAMPLE`
+fn sample<T>(mut setup: impl FnMut() -> T, mut phase: impl FnMut(T)) -> Duration {
+    let mut total = Duration::ZERO;
+    let mut runs = 0;
+    while total < GATE_SAMPLE {
+        let input = setup();
+        let start = Instant::now();
+        phase(input);
+        total += start.elapsed();
+        runs += 1;
+    }
+    total / runs
+}
+
+fn measure<T>(name: String, mut setup: impl FnMut() -> T, mut phase: impl FnMut(T)) -> BenchResult {
+    for _ in 0..BENCH_WARMUP {
+        sample(&mut setup, &mut phase);
+    }
+    let times: Vec<_> = (0..BENCH_ITERATIONS)
+        .map(|_| sample(&mut setup, &mut phase))
+        .collect();
+    BenchResult {
+        name,
+        warmup: BENCH_WARMUP,
+        iterations: BENCH_ITERATIONS,
+        mean: times.iter().sum::<Duration>() / BENCH_ITERATIONS as u32,
+        min: times.into_iter().min().unwrap(),
+    }
+}
+
+/// the results to record as the baseline, each phase with the median of its fastest times
+/// over `GATE_ATTEMPTS` measurements, which the gate can reach again on the same machine
+fn baseline_results() -> Vec<BenchResult> {
+    let attempts: Vec<_> = (0..GATE_ATTEMPTS).map(|_| gate_results()).collect();
+    let mut results = attempts[0].clone();
+    for (k, res) in results.iter_mut().enumerate() {
+        let mut mins: Vec<_> = attempts.iter().map(|results| results[k].min).collect();
+        mins.sort();
+        res.min = mins[mins.len() / 2];
+    }
+    results
+}
+
+/// every phase on the smallest size of every program
+fn gate_results() -> Vec<BenchResult> {
+    let mut results = Vec::new();
+    for (kind, sizes, generate) in bench_inputs() {
+        let source = generate(sizes[0]);
+        let ast = parse(&source);
+        let renamed = rename(ast.clone());
+        results.push(measure(
+            format!("{kind}/lex"),
+            || &source,
+            |source| {
+                black_box(lex(source));
+            },
+        ));
+        results.push(measure(
+            format!("{kind}/parse"),
+            || &source,
+            |source| {
+                black_box(parse(source));
+            },
+        ));
+        results.push(measure(
+            format!("{kind}/rename"),
+            || ast.clone(),
+            |ast| {
+                black_box(rename(ast));
+            },
+        ));
+        results.push(measure(format!("{kind}/infer"), || &renamed, infer));
+    }
+    results
+}
+
+fn bench_gate(threshold: f64) -> bool {
+    let baseline = match fs::read_to_string(BASELINE) {
+        Ok(text) => driver::parse_bench_json(&text),
+        Err(err) => {
+            println!("can't read {BASELINE}: {err}");
+            return false;
+        }
+    };
+    let Some(baseline) = baseline else {
+        println!("{BASELINE} isn't a benchmark result, write it again with `--bench-record`");
+        return false;
+    };
+    let mut results = gate_results();
+    let mut regressions = driver::bench_regressions(&baseline, &results, threshold);
+    for _ in 1..GATE_ATTEMPTS {
+        if regressions.is_empty() {
+            break;
+        }
+        for (res, again) in results.iter_mut().zip(gate_results()) {
+            res.min = res.min.min(again.min);
+        }
+        regressions = driver::bench_regressions(&baseline, &results, threshold);
+    }
+    print!("{}", driver::bench_table(&results));
+    for (name, slower) in regressions.iter() {
+        println!("{name} is {slower:.1}% slower than the baseline");
+    }
+    regressions.is_empty()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let threshold = match args.iter().position(|arg| arg == "--threshold") {
+        Some(i) => match args.get(i + 1).and_then(|arg| arg.parse().ok()) {
+            Some(threshold) => threshold,
+            None => {
+                println!("`--threshold` takes a percentage");
+                process::exit(2);
+            }
+        },
+        None => GATE_THRESHOLD,
+    };
+    if args.iter().any(|arg| arg == "--bench-record") {
+        let results = baseline_results();
+        print!("{}", driver::bench_table(&results));
+        fs::write(BASELINE, driver::bench_json(&results)).unwrap();
+    } else if args.iter().any(|arg| arg == "--bench-gate") {
+        if !bench_gate(threshold) {
+            process::exit(1);
+        }
+    } else {
+        // the deepest programs are at the recursion limit, with little room left for criterion
+        thread::Builder::new()
+            .stack_size(DEEP_STACK)
+            .spawn(|| {
+                let mut c = Criterion::default().configure_from_args();
+                bench_phases(&mut c);
+                c.final_summary();
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
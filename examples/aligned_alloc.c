@@ -0,0 +1,10 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void* addr_mod(void* arg0, void* arg1) {
+    return (void*)((uintptr_t)arg0 % (uintptr_t)arg1);
+}
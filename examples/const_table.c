@@ -0,0 +1,16 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void print_str(void* arg0) {
+    printf("%s\n", (const char*)arg0);
+}
+
+void poke(void* arg0) {
+    // the fault handler exits without flushing
+    fflush(stdout);
+    ((void**)arg0)[0] = (void*)0;
+}
@@ -0,0 +1,10 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void* read_int(void* arg0) {
+    return arg0;
+}
@@ -0,0 +1,11 @@
+#include <stdint.h>
+#include <stdbool.h>
+
+void* is_zero(void* arg0) {
+    return (void*)(intptr_t)((int64_t)arg0 == 0);
+}
+
+// one error in every thousand iterations
+void* is_error(void* arg0) {
+    return (void*)(intptr_t)((int64_t)arg0 % 1000 == 999);
+}
@@ -0,0 +1,5 @@
+#include <stdio.h>
+
+void print_str(void* arg0) {
+    printf("[%s]\n", (const char*)arg0);
+}
@@ -0,0 +1,27 @@
+#include <stdio.h>
+#include <stdint.h>
+#include <stdarg.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void print_str(void* arg0) {
+    printf("%s\n", (const char*)arg0);
+}
+
+void print_char(void* arg0) {
+    printf("%c\n", (int)(int64_t)arg0);
+}
+
+// declared `stdcall` in the IR, which x86-64 ignores
+void* twice(void* arg0) {
+    return (void*)(2 * (int64_t)arg0);
+}
+
+void log_values(void* fmt, ...) {
+    va_list args;
+    va_start(args, fmt);
+    vprintf((const char*)fmt, args);
+    va_end(args);
+}
@@ -3,9 +3,8 @@
 #include <stdint.h>
 #include <stdbool.h>
 
-void* print_int(void* arg0) {
+void print_int(void* arg0) {
     printf("%ld\n", (int64_t)arg0);
-    return NULL;
 }
 
 void* scan_int() {
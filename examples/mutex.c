@@ -0,0 +1,18 @@
+#include <stdio.h>
+#include <stdint.h>
+
+static int64_t counter = 0;
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void* bump(void) {
+    int64_t old = counter;
+    counter = old + 1;
+    return (void*)old;
+}
+
+void* count(void) {
+    return (void*)counter;
+}
@@ -0,0 +1,15 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void print_row(void* arg0, void* arg1) {
+    int64_t n = (int64_t)arg0;
+    int64_t col = (int64_t)arg1;
+    for (int64_t i = 1; i <= n; i++) {
+        putchar(i == col ? 'Q' : '.');
+    }
+    putchar('\n');
+}
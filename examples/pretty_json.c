@@ -0,0 +1,28 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void emit(void* arg0) {
+    fputs((const char*)arg0, stdout);
+}
+
+void emit_int(void* arg0) {
+    printf("%ld", (int64_t)arg0);
+}
+
+void emit_quoted(void* arg0) {
+    putchar('"');
+    for (const char* s = (const char*)arg0; *s; s++) {
+        if (*s == '"' || *s == '\\') {
+            putchar('\\');
+        }
+        putchar(*s);
+    }
+    putchar('"');
+}
+
+void newline(void* arg0) {
+    putchar('\n');
+    for (int64_t i = 0; i < (int64_t)arg0; i++) {
+        fputs("  ", stdout);
+    }
+}
@@ -0,0 +1,10 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void print_bits(void* arg0) {
+    printf("%016lx\n", (uint64_t)arg0);
+}
+
+void* opaque(void* arg0) {
+    return arg0;
+}
@@ -0,0 +1,28 @@
+#include <stdio.h>
+#include <stdint.h>
+#include <stdbool.h>
+#include <unistd.h>
+
+static int64_t inside = 0;
+static int64_t most = 0;
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+// stays inside for a while, so the other workers pile up at the semaphore
+void* enter(void) {
+    int64_t now = __atomic_add_fetch(&inside, 1, __ATOMIC_SEQ_CST);
+    int64_t old = __atomic_load_n(&most, __ATOMIC_SEQ_CST);
+    while (now > old && !__atomic_compare_exchange_n(&most, &old, now, false, __ATOMIC_SEQ_CST, __ATOMIC_SEQ_CST)) {}
+    usleep(20000);
+    return (void*)now;
+}
+
+void* leave(void) {
+    return (void*)__atomic_sub_fetch(&inside, 1, __ATOMIC_SEQ_CST);
+}
+
+void* peak(void) {
+    return (void*)__atomic_load_n(&most, __ATOMIC_SEQ_CST);
+}
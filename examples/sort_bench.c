@@ -0,0 +1,14 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void print_bool(void* arg0) {
+    printf("%s\n", arg0 ? "true" : "false");
+}
+
+void* rem(void* arg0, void* arg1) {
+    return (void*)((int64_t)arg0 % (int64_t)arg1);
+}
@@ -0,0 +1,19 @@
+#include <stdio.h>
+#include <stdint.h>
+#include <stdbool.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void print_str(void* arg0) {
+    printf("%s\n", (const char*)arg0);
+}
+
+void* same_addr(void* arg0, void* arg1) {
+    return (void*)(int64_t)(arg0 == arg1);
+}
+
+void print_bool(void* arg0) {
+    printf("%s\n", arg0 ? "true" : "false");
+}
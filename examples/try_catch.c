@@ -0,0 +1,21 @@
+#include <stdio.h>
+#include <stdint.h>
+
+void print_int(void* arg0) {
+    printf("%ld\n", (int64_t)arg0);
+}
+
+void print_str(void* arg0) {
+    printf("[%s]\n", (const char*)arg0);
+}
+
+void* half(void* arg0) {
+    int64_t n = (int64_t)arg0;
+    if (n < 0) {
+        return (void*)-2;
+    }
+    if (n % 2 != 0) {
+        return (void*)-1;
+    }
+    return (void*)(n / 2);
+}
@@ -0,0 +1,20 @@
+#include <stdio.h>
+#include <stdint.h>
+#include <stdarg.h>
+
+static const char* formats[] = { "%ld %.2f %s\n", "%d %ld\n", "done\n" };
+
+void* format(void* arg0) {
+    return (void*)formats[(int64_t)arg0];
+}
+
+void* greeting() {
+    return "hello";
+}
+
+void log_values(void* fmt, ...) {
+    va_list args;
+    va_start(args, fmt);
+    vprintf((const char*)fmt, args);
+    va_end(args);
+}
@@ -0,0 +1,418 @@
+
+#include <stdio.h>
+#include <stdarg.h>
+#include <stdlib.h>
+#include <stdint.h>
+#include <stdbool.h>
+#include <string.h>
+#include <math.h>
+#include <ucontext.h>
+#include <pthread.h>
+#include <semaphore.h>
+#include <errno.h>
+#include <limits.h>
+#include <dirent.h>
+#include <signal.h>
+#include <sys/mman.h>
+
+static inline double to_real(void* x) { double r; memcpy(&r, &x, sizeof(double)); return r; }
+static inline void* from_real(double x) { void* r; memcpy(&r, &x, sizeof(double)); return r; }
+
+#ifdef __OPTIMIZE__
+#define NOREM_UNREACHABLE() __builtin_unreachable()
+#else
+#define NOREM_UNREACHABLE() __builtin_trap()
+#endif
+
+// code after a cold label is moved out of the hot path
+#define NOREM_COLD(label) label: __attribute__((cold, unused));
+
+__attribute__((cold, noreturn)) static void norem_panic(const char* msg) { puts(msg); exit(1); }
+
+// `aligned_alloc` wants the size to be a multiple of the alignment
+static inline void* norem_alloc_aligned(void* size, void* align) {
+    size_t s = (size_t)size, a = (size_t)align;
+    return aligned_alloc(a, (s + a - 1) / a * a);
+}
+
+// coroutines run a closure on their own stack, switching with `swapcontext`.
+// `value` carries the argument of `coroutine_resume` in and the argument of `@yield` out
+typedef struct norem_coroutine {
+    ucontext_t ctx, caller;
+    void** func;
+    void* value;
+    void* result;
+    bool done;
+    struct norem_coroutine* prev;
+} norem_coroutine;
+#define NOREM_STACK_SIZE (256 * 1024)
+// every thread runs its own coroutines
+static _Thread_local norem_coroutine* norem_running = NULL;
+static void norem_coroutine_entry() {
+    norem_coroutine* co = norem_running;
+    co->result = ((void* (*)(void*, void*))co->func[0])(co->func, co->value);
+    co->done = true;
+}
+void* coroutine_new(void* func) {
+    norem_coroutine* co = calloc(1, sizeof(norem_coroutine));
+    co->func = func;
+    getcontext(&co->ctx);
+    co->ctx.uc_stack.ss_sp = malloc(NOREM_STACK_SIZE);
+    co->ctx.uc_stack.ss_size = NOREM_STACK_SIZE;
+    co->ctx.uc_link = &co->caller;
+    makecontext(&co->ctx, norem_coroutine_entry, 0);
+    return co;
+}
+// the value yielded, or the value resumed with once the function has returned
+void* coroutine_resume(void* coro, void* value) {
+    norem_coroutine* co = coro;
+    if (co->done) { puts("resumed a finished coroutine!"); exit(1); }
+    co->value = value;
+    co->prev = norem_running;
+    norem_running = co;
+    swapcontext(&co->caller, &co->ctx);
+    norem_running = co->prev;
+    if (co->done) free(co->ctx.uc_stack.ss_sp);
+    return co->value;
+}
+void* coroutine_done(void* coro) { return (void*)(int64_t)((norem_coroutine*)coro)->done; }
+void* coroutine_result(void* coro) { return ((norem_coroutine*)coro)->result; }
+static void* norem_yield(void* value) {
+    norem_coroutine* co = norem_running;
+    if (co == NULL) { puts("yield outside of a coroutine!"); exit(1); }
+    co->value = value;
+    swapcontext(&co->ctx, &co->caller);
+    return co->value;
+}
+
+// a thread runs a closure without arguments, `norem_join` takes its result
+typedef struct norem_thread {
+    pthread_t id;
+    void** func;
+    void* result;
+} norem_thread;
+static void* norem_thread_entry(void* arg) {
+    norem_thread* th = arg;
+    th->result = ((void* (*)(void*))th->func[0])(th->func);
+    return NULL;
+}
+// a stack size of 0 keeps the default of the system
+static void* norem_spawn(void* func, void* stack_size) {
+    norem_thread* th = malloc(sizeof(norem_thread));
+    th->func = func;
+    pthread_attr_t attr;
+    pthread_attr_init(&attr);
+    int64_t size = (int64_t)stack_size;
+    if (size < 0) { puts("negative stack size!"); exit(1); }
+    if (size > 0) {
+        // some systems only take multiples of the page size
+        size = (size + 65535) / 65536 * 65536;
+        if (size < PTHREAD_STACK_MIN) size = PTHREAD_STACK_MIN;
+        pthread_attr_setstacksize(&attr, size);
+    }
+    if (pthread_create(&th->id, &attr, norem_thread_entry, th) != 0) {
+        puts("failed to spawn a thread!");
+        exit(1);
+    }
+    pthread_attr_destroy(&attr);
+    return th;
+}
+static void* norem_join(void* thread) {
+    norem_thread* th = thread;
+    if (pthread_join(th->id, NULL) != 0) { puts("failed to join a thread!"); exit(1); }
+    void* result = th->result;
+    free(th);
+    return result;
+}
+
+// a bounded queue for any number of senders and receivers
+typedef struct norem_chan {
+    pthread_mutex_t lock;
+    pthread_cond_t not_empty;
+    pthread_cond_t not_full;
+    void** buf;
+    int64_t cap;
+    int64_t head;
+    int64_t len;
+    bool closed;
+} norem_chan;
+static void* norem_chan_new(void* capacity) {
+    int64_t cap = (int64_t)capacity;
+    if (cap <= 0) { puts("channel capacity must be positive!"); exit(1); }
+    norem_chan* ch = malloc(sizeof(norem_chan));
+    pthread_mutex_init(&ch->lock, NULL);
+    pthread_cond_init(&ch->not_empty, NULL);
+    pthread_cond_init(&ch->not_full, NULL);
+    ch->buf = malloc(cap * sizeof(void*));
+    ch->cap = cap;
+    ch->head = 0;
+    ch->len = 0;
+    ch->closed = false;
+    return ch;
+}
+static void* norem_chan_send(void* chan, void* value) {
+    norem_chan* ch = chan;
+    pthread_mutex_lock(&ch->lock);
+    while (ch->len == ch->cap && !ch->closed) pthread_cond_wait(&ch->not_full, &ch->lock);
+    if (ch->closed) { puts("sent to a closed channel!"); exit(1); }
+    ch->buf[(ch->head + ch->len) % ch->cap] = value;
+    ch->len++;
+    pthread_cond_signal(&ch->not_empty);
+    pthread_mutex_unlock(&ch->lock);
+    return NULL;
+}
+// `none` is the empty option, `some` the closure wrapping a value into one
+static void* norem_chan_recv(void* chan, void* none, void* some) {
+    norem_chan* ch = chan;
+    pthread_mutex_lock(&ch->lock);
+    while (ch->len == 0 && !ch->closed) pthread_cond_wait(&ch->not_empty, &ch->lock);
+    if (ch->len == 0) {
+        pthread_mutex_unlock(&ch->lock);
+        return none;
+    }
+    void* value = ch->buf[ch->head];
+    ch->head = (ch->head + 1) % ch->cap;
+    ch->len--;
+    pthread_cond_signal(&ch->not_full);
+    pthread_mutex_unlock(&ch->lock);
+    return ((void* (*)(void*, void*))((void**)some)[0])(some, value);
+}
+static void* norem_chan_close(void* chan) {
+    norem_chan* ch = chan;
+    pthread_mutex_lock(&ch->lock);
+    ch->closed = true;
+    pthread_cond_broadcast(&ch->not_empty);
+    pthread_cond_broadcast(&ch->not_full);
+    pthread_mutex_unlock(&ch->lock);
+    return NULL;
+}
+
+static void* norem_mutex_new() {
+    pthread_mutex_t* lock = malloc(sizeof(pthread_mutex_t));
+    pthread_mutex_init(lock, NULL);
+    return lock;
+}
+static void* norem_mutex_lock(void* mutex) {
+    if (pthread_mutex_lock(mutex) != 0) { puts("failed to lock a mutex!"); exit(1); }
+    return NULL;
+}
+static void* norem_mutex_unlock(void* mutex) {
+    if (pthread_mutex_unlock(mutex) != 0) { puts("failed to unlock a mutex!"); exit(1); }
+    return NULL;
+}
+static void norem_mutex_cleanup(pthread_mutex_t** lock) { pthread_mutex_unlock(*lock); }
+// the cleanup unlocks the mutex however the scope is left, also when the thread is unwound
+static void* norem_with_mutex(void* mutex, void* func) {
+    norem_mutex_lock(mutex);
+    __attribute__((cleanup(norem_mutex_cleanup))) pthread_mutex_t* guard = mutex;
+    return ((void* (*)(void*))((void**)func)[0])(func);
+}
+
+static void* norem_sem_new(void* count) {
+    sem_t* sem = malloc(sizeof(sem_t));
+    if (sem_init(sem, 0, (unsigned)(int64_t)count) != 0) { puts("failed to make a semaphore!"); exit(1); }
+    return sem;
+}
+static void* norem_sem_post(void* sem) {
+    if (sem_post(sem) != 0) { puts("failed to post a semaphore!"); exit(1); }
+    return NULL;
+}
+// a signal may interrupt the wait, which is then just retried
+static void* norem_sem_wait(void* sem) {
+    while (sem_wait(sem) != 0) {
+        if (errno != EINTR) { puts("failed to wait on a semaphore!"); exit(1); }
+    }
+    return NULL;
+}
+static void* norem_sem_trywait(void* sem) {
+    while (sem_trywait(sem) != 0) {
+        if (errno == EAGAIN) return (void*)0;
+        if (errno != EINTR) { puts("failed to wait on a semaphore!"); exit(1); }
+    }
+    return (void*)1;
+}
+
+// `ok` and `err` are the closures wrapping a value into a result
+static void* norem_file_error(void* err) {
+    const char* msg = strerror(errno);
+    char* copy = malloc(strlen(msg) + 1);
+    strcpy(copy, msg);
+    return ((void* (*)(void*, void*))((void**)err)[0])(err, copy);
+}
+static void* norem_read_file(void* path, void* ok, void* err) {
+    FILE* file = fopen(path, "rb");
+    if (file == NULL) return norem_file_error(err);
+    size_t cap = 4096;
+    size_t len = 0;
+    char* buf = malloc(cap);
+    size_t got;
+    while ((got = fread(buf + len, 1, cap - len - 1, file)) > 0) {
+        len += got;
+        if (cap - len == 1) {
+            cap *= 2;
+            buf = realloc(buf, cap);
+        }
+    }
+    if (ferror(file)) {
+        int saved = errno;
+        free(buf);
+        fclose(file);
+        errno = saved;
+        return norem_file_error(err);
+    }
+    fclose(file);
+    buf[len] = '\0';
+    return ((void* (*)(void*, void*))((void**)ok)[0])(ok, buf);
+}
+static void* norem_write_file(void* path, void* text, void* ok, void* err) {
+    FILE* file = fopen(path, "wb");
+    if (file == NULL) return norem_file_error(err);
+    size_t len = strlen(text);
+    if (fwrite(text, 1, len, file) != len) {
+        int saved = errno;
+        fclose(file);
+        errno = saved;
+        return norem_file_error(err);
+    }
+    if (fclose(file) != 0) return norem_file_error(err);
+    return ((void* (*)(void*, void*))((void**)ok)[0])(ok, (void*)0);
+}
+static int norem_compare_names(const void* a, const void* b) {
+    return strcmp(*(char* const*)a, *(char* const*)b);
+}
+// `nil` is the empty list and `cons` the closure putting a name in front of a list,
+// the names are sorted since `readdir` gives them in no particular order
+static void* norem_list_dir(void* path, void* ok, void* err, void* nil, void* cons) {
+    DIR* dir = opendir(path);
+    if (dir == NULL) return norem_file_error(err);
+    size_t cap = 16;
+    size_t len = 0;
+    char** names = malloc(cap * sizeof(char*));
+    struct dirent* entry;
+    errno = 0;
+    while ((entry = readdir(dir)) != NULL) {
+        if (strcmp(entry->d_name, ".") == 0 || strcmp(entry->d_name, "..") == 0) continue;
+        if (len == cap) {
+            cap *= 2;
+            names = realloc(names, cap * sizeof(char*));
+        }
+        names[len] = malloc(strlen(entry->d_name) + 1);
+        strcpy(names[len], entry->d_name);
+        len++;
+    }
+    if (errno != 0) {
+        int saved = errno;
+        closedir(dir);
+        errno = saved;
+        return norem_file_error(err);
+    }
+    closedir(dir);
+    qsort(names, len, sizeof(char*), norem_compare_names);
+    void* list = nil;
+    for (size_t i = len; i > 0; i--) {
+        list = ((void* (*)(void*, void*, void*))((void**)cons)[0])(cons, names[i - 1], list);
+    }
+    free(names);
+    return ((void* (*)(void*, void*))((void**)ok)[0])(ok, list);
+}
+// paths are split on `/` like `basename` and `dirname` do, but the argument is never modified
+static char* norem_path_copy(const char* path, size_t len) {
+    char* res = malloc(len + 1);
+    memcpy(res, path, len);
+    res[len] = '\0';
+    return res;
+}
+static size_t norem_path_trim(const char* path, size_t len) {
+    while (len > 1 && path[len - 1] == '/') len--;
+    return len;
+}
+static void* norem_path_join(void* dir, void* path) {
+    size_t len1 = strlen(dir);
+    size_t len2 = strlen(path);
+    if (len1 == 0 || ((char*)path)[0] == '/') return norem_path_copy(path, len2);
+    if (len2 == 0) return norem_path_copy(dir, len1);
+    int slash = ((char*)dir)[len1 - 1] != '/';
+    char* res = malloc(len1 + slash + len2 + 1);
+    memcpy(res, dir, len1);
+    if (slash) res[len1] = '/';
+    memcpy(res + len1 + slash, path, len2 + 1);
+    return res;
+}
+static void* norem_path_base(void* path) {
+    size_t len = norem_path_trim(path, strlen(path));
+    if (len == 0) return norem_path_copy(".", 1);
+    size_t start = len;
+    while (start > 0 && ((char*)path)[start - 1] != '/') start--;
+    if (start == len) return norem_path_copy("/", 1);
+    return norem_path_copy((char*)path + start, len - start);
+}
+static void* norem_path_dir(void* path) {
+    size_t len = norem_path_trim(path, strlen(path));
+    while (len > 0 && ((char*)path)[len - 1] != '/') len--;
+    if (len == 0) return norem_path_copy(".", 1);
+    return norem_path_copy(path, norem_path_trim(path, len));
+}
+static void* norem_format(void* fmt, ...) {
+    va_list args;
+    va_start(args, fmt);
+    int len = vsnprintf(NULL, 0, fmt, args);
+    va_end(args);
+    char* res = malloc(len + 1);
+    va_start(args, fmt);
+    vsnprintf(res, len + 1, fmt, args);
+    va_end(args);
+    return res;
+}
+
+// a `Simd[Real, 2]` is a record of two doubles, just like a tuple `(Real, Real)`
+#ifdef __SSE2__
+#include <emmintrin.h>
+static inline void* norem_simd_add(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    _mm_storeu_pd((double*)r, _mm_add_pd(_mm_loadu_pd((double*)a), _mm_loadu_pd((double*)b)));
+    return r;
+}
+static inline void* norem_simd_mul(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    _mm_storeu_pd((double*)r, _mm_mul_pd(_mm_loadu_pd((double*)a), _mm_loadu_pd((double*)b)));
+    return r;
+}
+#else
+static inline void* norem_simd_add(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    for(int i = 0; i < 2; i++) r[i] = from_real(to_real(((void**)a)[i]) + to_real(((void**)b)[i]));
+    return r;
+}
+static inline void* norem_simd_mul(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    for(int i = 0; i < 2; i++) r[i] = from_real(to_real(((void**)a)[i]) * to_real(((void**)b)[i]));
+    return r;
+}
+#endif
+static const char norem_str_0[] = "a";
+static const char norem_str_30[] = "%lld %s";
+void* norem_format(void* arg0, ...);
+int main(int argc, char* argv[])
+{
+if(sizeof(void*) != 8)
+{
+puts("check failed: 'void*' is not 64-bits!");
+exit(1);
+}
+if(sizeof(int64_t) != 8)
+{
+puts("check failed: 'int64_t' is not 64-bits!");
+exit(1);
+}
+if(sizeof(double) != 8)
+{
+puts("check failed: 'double' is not 64-bits!");
+exit(1);
+}
+void* r_18 = norem_format((void*)(void*)norem_str_30, (int64_t)3, (void*)norem_str_0);
+return 0;
+}
+/*
+this file is generated by norem compiler,
+reading and editing are not recommanded.
+*/
@@ -1,14 +1,49 @@
 use super::*;
-use crate::frontend::ast::LitVal;
+use crate::frontend::ast::{CallConv, FfiType, LitVal};
+use std::collections::BTreeSet;
+use std::sync;
+
+lazy_static::lazy_static! {
+    // the fields of every constant made so far, a constant only refers to older ones
+    static ref CONSTS: sync::Mutex<Vec<Vec<Atom>>> = sync::Mutex::new(Vec::new());
+}
+
+/// a tuple or a constructor whose fields are all known at compile time. the backend lays
+/// out its memory before the program starts, and the program can only read it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConstId(usize);
+
+impl ConstId {
+    /// a new constant with these fields, which are literals or other constants
+    pub fn new(fields: Vec<Atom>) -> ConstId {
+        assert!(fields.iter().all(|field| !field.is_var()));
+        let mut consts = CONSTS.lock().unwrap();
+        consts.push(fields);
+        ConstId(consts.len() - 1)
+    }
+
+    pub fn fields(&self) -> Vec<Atom> {
+        CONSTS.lock().unwrap()[self.0].clone()
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Atom {
     Var(Ident),
     Int(i64),
+    /// an IEEE-754 double, infinities and NaN are valid values
     Real(f64),
     Bool(bool),
     Char(char),
+    /// a string literal, emitted once as a static array per unique string
+    Str(InternStr),
     Unit,
+    /// a constant aggregate, emitted once as read-only static data
+    Const(ConstId),
 }
 
 impl From<LitVal> for Atom {
@@ -18,6 +53,7 @@ impl From<LitVal> for Atom {
             LitVal::Real(x) => Atom::Real(x),
             LitVal::Bool(x) => Atom::Bool(x),
             LitVal::Char(x) => Atom::Char(x),
+            LitVal::Str(x) => Atom::Str(x),
             LitVal::Unit => Atom::Unit,
         }
     }
@@ -25,20 +61,18 @@ impl From<LitVal> for Atom {
 
 impl Atom {
     pub fn is_lit(&self) -> bool {
-        match self {
-            Atom::Int(_) => true,
-            Atom::Real(_) => true,
-            Atom::Bool(_) => true,
-            Atom::Char(_) => true,
-            Atom::Unit => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Atom::Int(_)
+                | Atom::Real(_)
+                | Atom::Bool(_)
+                | Atom::Char(_)
+                | Atom::Str(_)
+                | Atom::Unit
+        )
     }
     pub fn is_var(&self) -> bool {
-        match self {
-            Atom::Var(_) => true,
-            _ => false,
-        }
+        matches!(self, Atom::Var(_))
     }
     pub fn unwrap_lit(self) -> LitVal {
         match self {
@@ -46,6 +80,7 @@ impl Atom {
             Atom::Real(x) => LitVal::Real(x),
             Atom::Bool(x) => LitVal::Bool(x),
             Atom::Char(x) => LitVal::Char(x),
+            Atom::Str(x) => LitVal::Str(x),
             _ => panic!("failed to unwrap literal!"),
         }
     }
@@ -61,6 +96,15 @@ impl Atom {
 pub enum UnOpPrim {
     Move,
     INeg,
+    Trunc32,
+    SignExt32,
+    ZeroExt32,
+    BNot,
+    Sinh,
+    Cosh,
+    Tanh,
+    Erf,
+    Erfc,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -68,6 +112,55 @@ pub enum BinOpPrim {
     IAdd,
     ISub,
     IMul,
+    /// integer division, the divisor is checked by the lowering pass
+    IDiv,
+    IRem,
+    ICmpEq,
+    ICmpNe,
+    ICmpLt,
+    ICmpLe,
+    ICmpGt,
+    ICmpGe,
+    RAdd,
+    RSub,
+    RMul,
+    RDiv,
+    Prefetch,
+    /// branch prediction hint, the result is the first argument
+    Expect,
+    SimdAdd,
+    SimdMul,
+    /// allocate the bytes of the first argument aligned to the second one
+    AllocAligned,
+}
+
+impl BinOpPrim {
+    /// a pure primitive can be removed if its result is not used
+    pub fn is_pure(&self) -> bool {
+        !matches!(self, BinOpPrim::Prefetch)
+    }
+}
+
+/// how an external call crosses into C, besides the calling convention
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExtAbi {
+    /// the types of the trailing arguments of a variadic call, passed unboxed
+    pub varargs: Option<Vec<FfiType>>,
+    /// the function returns `void`, the result is bound to unit instead
+    pub unit_ret: bool,
+}
+
+/// the branches of an `Ifte` or `Switch` that are unlikely to be taken, like a failed match.
+/// branches are numbered in order, `brch1` and `brch2` of an `Ifte`,
+/// or the `brchs` of a `Switch` followed by its `dflt`.
+/// a pass that merges branches keeps the result cold only if all of them were cold.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cold(pub BTreeSet<usize>);
+
+impl Cold {
+    pub fn is_cold(&self, index: usize) -> bool {
+        self.0.contains(&index)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -95,15 +188,26 @@ pub enum MExpr {
         args: Vec<Atom>,
         cont: Box<MExpr>,
     },
+    /// a call whose result is returned right away, see `tail_call::mark_tail_calls`
+    TailCall {
+        func: Atom,
+        args: Vec<Atom>,
+    },
     ExtCall {
         bind: Ident,
         func: InternStr,
+        call_conv: CallConv,
+        abi: ExtAbi,
         args: Vec<Atom>,
         cont: Box<MExpr>,
     },
     Retn {
         arg1: Atom,
     },
+    /// abort the program with a message, nothing after it is ever run
+    Panic {
+        msg: InternStr,
+    },
     Alloc {
         bind: Ident,
         size: usize,
@@ -132,6 +236,7 @@ pub enum MExpr {
         arg1: Atom,
         brch1: Box<MExpr>,
         brch2: Box<MExpr>,
+        cold: Cold,
         cont: Box<MExpr>,
     },
     Switch {
@@ -139,23 +244,25 @@ pub enum MExpr {
         arg1: Atom,
         brchs: Vec<(usize, MExpr)>,
         dflt: Option<Box<MExpr>>,
+        cold: Cold,
         cont: Box<MExpr>,
     },
 }
 
 impl MExpr {
     pub fn is_retn(&self) -> bool {
-        match self {
-            MExpr::Retn { .. } => true,
-            _ => false,
-        }
+        matches!(self, MExpr::Retn { .. })
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, MExpr::Panic { .. })
     }
 
     pub fn is_tail_call(&self) -> bool {
-        if let MExpr::Call { cont, .. } = self {
-            cont.is_retn()
-        } else {
-            false
+        match self {
+            MExpr::Call { cont, .. } => cont.is_retn(),
+            MExpr::TailCall { .. } => true,
+            _ => false,
         }
     }
 
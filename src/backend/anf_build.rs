@@ -1,4 +1,5 @@
 use super::*;
+use crate::frontend::ast::CallConv;
 
 pub fn i(x: i64) -> Atom {
     Atom::Int(x)
@@ -24,7 +25,7 @@ pub fn name(x: &str) -> Ident {
 pub fn fun(func: &str, pars: Vec<&str>, body: MExpr) -> MDecl {
     MDecl {
         func: name(func),
-        pars: pars.into_iter().map(|par| name(par)).collect(),
+        pars: pars.into_iter().map(name).collect(),
         body,
     }
 }
@@ -87,6 +88,8 @@ pub fn chain(vec: Vec<MExpr>) -> MExpr {
             MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             } => {
@@ -95,11 +98,15 @@ pub fn chain(vec: Vec<MExpr>) -> MExpr {
                 MExpr::ExtCall {
                     bind,
                     func,
+                    call_conv,
+                    abi,
                     args,
                     cont,
                 }
             }
             MExpr::Retn { arg1 } => MExpr::Retn { arg1 },
+            MExpr::Panic { msg } => MExpr::Panic { msg },
+            MExpr::TailCall { func, args } => MExpr::TailCall { func, args },
             MExpr::Alloc { bind, size, cont } => {
                 assert!(cont.is_retn());
                 let cont = Box::new(e1);
@@ -155,6 +162,7 @@ pub fn chain(vec: Vec<MExpr>) -> MExpr {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 assert!(cont.is_retn());
@@ -164,6 +172,7 @@ pub fn chain(vec: Vec<MExpr>) -> MExpr {
                     arg1,
                     brch1,
                     brch2,
+                    cold,
                     cont,
                 }
             }
@@ -172,6 +181,7 @@ pub fn chain(vec: Vec<MExpr>) -> MExpr {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 assert!(cont.is_retn());
@@ -181,6 +191,7 @@ pub fn chain(vec: Vec<MExpr>) -> MExpr {
                     arg1,
                     brchs,
                     dflt,
+                    cold,
                     cont,
                 }
             }
@@ -288,6 +299,7 @@ pub fn ifte(bind: &str, arg1: Atom, brch1: MExpr, brch2: MExpr) -> MExpr {
         arg1,
         brch1,
         brch2,
+        cold: Cold::default(),
         cont,
     }
 }
@@ -296,12 +308,13 @@ pub fn switch(bind: &str, arg1: Atom, brchs: Vec<(usize, MExpr)>, dflt: Option<M
     let cont = Box::new(MExpr::Retn {
         arg1: Atom::Var(bind),
     });
-    let dflt = dflt.map(|x| Box::new(x));
+    let dflt = dflt.map(Box::new);
     MExpr::Switch {
         bind,
         arg1,
         brchs,
         dflt,
+        cold: Cold::default(),
         cont,
     }
 }
@@ -318,14 +331,32 @@ pub fn call(bind: &str, func: &str, args: Vec<Atom>) -> MExpr {
         cont,
     }
 }
-/*
+pub fn tail_call(func: &str, args: Vec<Atom>) -> MExpr {
+    let func = Atom::Var(name(func));
+    MExpr::TailCall { func, args }
+}
 pub fn call_ext(bind: &str, func: &str, args: Vec<Atom>) -> MExpr {
-    todo!()
+    let bind = name(bind);
+    let cont = Box::new(MExpr::Retn {
+        arg1: Atom::Var(bind),
+    });
+    MExpr::ExtCall {
+        bind,
+        func: InternStr::new(func),
+        call_conv: CallConv::C,
+        abi: ExtAbi::default(),
+        args,
+        cont,
+    }
 }
-*/
 pub fn retn(arg1: Atom) -> MExpr {
     MExpr::Retn { arg1 }
 }
+pub fn panic(msg: &str) -> MExpr {
+    MExpr::Panic {
+        msg: InternStr::new(msg),
+    }
+}
 
 #[test]
 #[ignore]
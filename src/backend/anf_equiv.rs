@@ -14,7 +14,7 @@ impl AlphaEquiv {
 
     fn eq_ident(&mut self, ident1: &Ident, ident2: &Ident) -> bool {
         // println!("unify {ident1} and {ident2}");
-        if let Some(bind) = self.map.get(&ident1) {
+        if let Some(bind) = self.map.get(ident1) {
             bind == ident2
         } else {
             self.map.insert(*ident1, *ident2);
@@ -127,6 +127,21 @@ impl AlphaEquiv {
                     && self.eq_expr(cont, cont_)
             }
             (MExpr::Retn { arg1 }, MExpr::Retn { arg1: arg1_ }) => self.eq_atom(arg1, arg1_),
+            (
+                MExpr::TailCall { func, args },
+                MExpr::TailCall {
+                    func: func_,
+                    args: args_,
+                },
+            ) => {
+                self.eq_atom(func, func_)
+                    && args.len() == args_.len()
+                    && args
+                        .iter()
+                        .zip(args_.iter())
+                        .all(|(arg, arg_)| self.eq_atom(arg, arg_))
+            }
+            (MExpr::Panic { msg }, MExpr::Panic { msg: msg_ }) => msg == msg_,
             (
                 MExpr::Alloc { bind, size, cont },
                 MExpr::Alloc {
@@ -192,6 +207,7 @@ impl AlphaEquiv {
                     && index == index_
                     && self.eq_expr(cont, cont_)
             }
+            // cold branches are only hints, they don't change what is computed
             (
                 MExpr::Ifte {
                     bind,
@@ -199,6 +215,7 @@ impl AlphaEquiv {
                     brch1,
                     brch2,
                     cont,
+                    ..
                 },
                 MExpr::Ifte {
                     bind: bind_,
@@ -206,6 +223,7 @@ impl AlphaEquiv {
                     brch1: brch1_,
                     brch2: brch2_,
                     cont: cont_,
+                    ..
                 },
             ) => {
                 self.eq_ident(bind, bind_)
@@ -221,6 +239,7 @@ impl AlphaEquiv {
                     brchs,
                     dflt,
                     cont,
+                    ..
                 },
                 MExpr::Switch {
                     bind: bind_,
@@ -228,6 +247,7 @@ impl AlphaEquiv {
                     brchs: brchs_,
                     dflt: dflt_,
                     cont: cont_,
+                    ..
                 },
             ) => {
                 self.eq_ident(bind, bind_)
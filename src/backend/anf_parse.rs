@@ -0,0 +1,1245 @@
+//! Reading the printed IR back, so a dump can be edited by hand and fed to the backend.
+//!
+//! The text is exactly what `printer::print_ir` writes. The first line is the version header,
+//! the rest is one `expr`, usually the `letrec ... in ... end` of a whole program:
+//!
+//! ```text
+//! program ::= "// norem-ir" VERSION NEWLINE expr
+//! expr    ::= "letrec" decl* "in" expr "end"
+//!           | "let" var "=" rhs
+//!           | "store" atom "[" INT "]" ":=" atom ";" expr
+//!           | "return" atom ["(" atoms ")"]
+//! decl    ::= "fun" var "(" vars ")" "=" expr
+//! rhs     ::= unop "(" atom ")" ";" expr
+//!           | binop "(" atom "," atom ")" ";" expr
+//!           | atom "(" atoms ")" ";" expr
+//!           | [callconv] "#" NAME "(" atoms ")" ["varargs" "(" ffitypes ")"] ["void"] ";" expr
+//!           | "alloc" "[" INT "]" ";" expr
+//!           | "load" atom "[" INT "]" ";" expr
+//!           | "offset" atom "[" INT "]" ";" expr
+//!           | "if" "(" atom ")" "then" ["cold"] expr "else" ["cold"] expr ";" expr
+//!           | "switch" "(" atom ")" "{" case* ["default" ["cold"] ":" expr] "}" expr
+//! case    ::= "case" INT ["cold"] ":" expr
+//! atom    ::= var | INT | REAL | CHAR | STRING | "true" | "false" | "()" | "{" atoms "}"
+//! ```
+//!
+//! A `var` is written as `name_index`, or just `name` for index 0.
+//! Reals always have a fraction or an exponent, or are one of `inf`, `-inf`, `NaN` and `-NaN`,
+//! the payload of a NaN is not kept.
+//! Chars and strings use Rust escapes, like `'\n'` or `"\u{7f}"`.
+//! A constant is written as its fields in braces, like `{0, "key", {1}}`, without variables.
+//! `//` starts a comment, and whitespace is insignificant.
+//! The names of primitives and the words above are reserved in the positions they appear.
+//!
+//! Attributes like `@optimize` and `@nounroll` are not part of the IR,
+//! a program read back is optimized as if it had none.
+
+use super::*;
+use crate::frontend::ast::{CallConv, FfiType};
+use crate::frontend::diagnostic::Diagnostic;
+use crate::frontend::lexer::is_ident_body;
+use crate::frontend::position::{Position, Span};
+use crate::utils::source_map::FileId;
+use std::collections::BTreeSet;
+
+/// the version written in the header, bumped whenever the syntax changes
+pub const IR_VERSION: u32 = 3;
+
+pub static IR_HEADER: &str = "// norem-ir";
+
+const UNOPS: &[UnOpPrim] = &[
+    UnOpPrim::Move,
+    UnOpPrim::INeg,
+    UnOpPrim::Trunc32,
+    UnOpPrim::SignExt32,
+    UnOpPrim::ZeroExt32,
+    UnOpPrim::BNot,
+    UnOpPrim::Sinh,
+    UnOpPrim::Cosh,
+    UnOpPrim::Tanh,
+    UnOpPrim::Erf,
+    UnOpPrim::Erfc,
+];
+
+const BINOPS: &[BinOpPrim] = &[
+    BinOpPrim::IAdd,
+    BinOpPrim::ISub,
+    BinOpPrim::IMul,
+    BinOpPrim::IDiv,
+    BinOpPrim::IRem,
+    BinOpPrim::ICmpEq,
+    BinOpPrim::ICmpNe,
+    BinOpPrim::ICmpLt,
+    BinOpPrim::ICmpLe,
+    BinOpPrim::ICmpGt,
+    BinOpPrim::ICmpGe,
+    BinOpPrim::RAdd,
+    BinOpPrim::RSub,
+    BinOpPrim::RMul,
+    BinOpPrim::RDiv,
+    BinOpPrim::Prefetch,
+    BinOpPrim::Expect,
+    BinOpPrim::SimdAdd,
+    BinOpPrim::SimdMul,
+    BinOpPrim::AllocAligned,
+];
+
+const FFI_TYPES: &[FfiType] = &[
+    FfiType::Int,
+    FfiType::Real,
+    FfiType::Bool,
+    FfiType::Char,
+    FfiType::Ptr,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Word(String),
+    Int(i64),
+    Real(f64),
+    Char(char),
+    Str(String),
+    Punct(&'static str),
+    End,
+}
+
+impl Tok {
+    fn describe(&self) -> String {
+        match self {
+            Tok::Word(word) => format!("`{word}`"),
+            Tok::Int(x) => format!("`{x}`"),
+            Tok::Real(x) => format!("`{x:?}`"),
+            Tok::Char(x) => format!("`{x:?}`"),
+            Tok::Str(x) => format!("`{x:?}`"),
+            Tok::Punct(punct) => format!("`{punct}`"),
+            Tok::End => "end of file".to_string(),
+        }
+    }
+}
+
+/// The source lexer can't be reused here, it has no char literals,
+/// reads `x_1` as a wildcard followed by a number, and `-1` as an operator.
+struct Tokenizer<'src> {
+    source: &'src str,
+    chars: std::iter::Peekable<std::str::CharIndices<'src>>,
+    row: usize,
+    col: usize,
+    file: FileId,
+}
+
+impl<'src> Tokenizer<'src> {
+    fn new(source: &'src str, file: FileId) -> Tokenizer<'src> {
+        Tokenizer {
+            source,
+            chars: source.char_indices().peekable(),
+            row: 0,
+            col: 0,
+            file,
+        }
+    }
+
+    fn position(&mut self) -> Position {
+        let abs = self.chars.peek().map_or(self.source.len(), |(i, _)| *i);
+        Position::new(self.row, self.col, abs)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, ch)| *ch)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let (_, ch) = self.chars.next()?;
+        if ch == '\n' {
+            self.row += 1;
+            self.col = 0;
+        } else {
+            self.col += 1;
+        }
+        Some(ch)
+    }
+
+    fn span(&self, start: Position, end: Position) -> Span {
+        Span {
+            file: self.file,
+            ..Span::new(start, end)
+        }
+    }
+
+    fn error(&mut self, start: Position, msg: &str) -> Diagnostic {
+        let end = self.position();
+        Diagnostic::error("invalid IR token").line_span(self.span(start, end), msg)
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.peek() {
+                Some(ch) if ch.is_whitespace() => {
+                    self.next_char();
+                }
+                Some('/') if self.source[self.position().abs..].starts_with("//") => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.next_char();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Tok, Span)>, Diagnostic> {
+        let mut toks = Vec::new();
+        loop {
+            self.skip_trivia();
+            let start = self.position();
+            let tok = match self.peek() {
+                None => {
+                    toks.push((Tok::End, self.span(start, start)));
+                    return Ok(toks);
+                }
+                Some(ch) if ch.is_ascii_digit() || ch == '-' => self.number(start)?,
+                Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => Tok::Word(self.word()),
+                Some('\'') => {
+                    self.next_char();
+                    let ch = self.escaped(start, '\'')?;
+                    if self.next_char() != Some('\'') {
+                        return Err(self.error(start, "a char literal holds exactly one char"));
+                    }
+                    Tok::Char(ch)
+                }
+                Some('"') => {
+                    self.next_char();
+                    let mut string = String::new();
+                    while self.peek() != Some('"') {
+                        string.push(self.escaped(start, '"')?);
+                    }
+                    self.next_char();
+                    Tok::Str(string)
+                }
+                Some(':') => {
+                    self.next_char();
+                    if self.peek() == Some('=') {
+                        self.next_char();
+                        Tok::Punct(":=")
+                    } else {
+                        Tok::Punct(":")
+                    }
+                }
+                Some(ch) => {
+                    self.next_char();
+                    match ch {
+                        '(' => Tok::Punct("("),
+                        ')' => Tok::Punct(")"),
+                        '[' => Tok::Punct("["),
+                        ']' => Tok::Punct("]"),
+                        '{' => Tok::Punct("{"),
+                        '}' => Tok::Punct("}"),
+                        ',' => Tok::Punct(","),
+                        ';' => Tok::Punct(";"),
+                        '=' => Tok::Punct("="),
+                        '#' => Tok::Punct("#"),
+                        _ => return Err(self.error(start, "unexpected character")),
+                    }
+                }
+            };
+            let end = self.position();
+            toks.push((tok, self.span(start, end)));
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(ch) = self.peek().filter(|ch| is_ident_body(*ch)) {
+            word.push(ch);
+            self.next_char();
+        }
+        word
+    }
+
+    fn number(&mut self, start: Position) -> Result<Tok, Diagnostic> {
+        let mut text = String::new();
+        if self.peek() == Some('-') {
+            text.push('-');
+            self.next_char();
+            if matches!(self.peek(), Some('i' | 'N')) {
+                return match self.word().as_str() {
+                    "inf" => Ok(Tok::Real(f64::NEG_INFINITY)),
+                    "NaN" => Ok(Tok::Real(-f64::NAN)),
+                    _ => Err(self.error(start, "expected a number after `-`")),
+                };
+            }
+        }
+        let mut real = false;
+        while let Some(ch) = self.peek() {
+            match ch {
+                '0'..='9' => {}
+                '.' | 'e' => real = true,
+                '+' | '-' if text.ends_with('e') => {}
+                _ => break,
+            }
+            text.push(ch);
+            self.next_char();
+        }
+        let tok = if real {
+            text.parse().ok().map(Tok::Real)
+        } else {
+            text.parse().ok().map(Tok::Int)
+        };
+        tok.ok_or_else(|| self.error(start, "this is not a valid number"))
+    }
+
+    /// the next char of a literal ended by `quote`, with Rust escapes
+    fn escaped(&mut self, start: Position, quote: char) -> Result<char, Diagnostic> {
+        let ch = match self.next_char() {
+            Some(ch) if ch == quote => None,
+            Some('\\') => match self.next_char() {
+                Some('n') => Some('\n'),
+                Some('r') => Some('\r'),
+                Some('t') => Some('\t'),
+                Some('0') => Some('\0'),
+                Some(ch @ ('\\' | '\'' | '"')) => Some(ch),
+                Some('u') if self.next_char() == Some('{') => {
+                    let mut hex = String::new();
+                    while let Some(ch) = self.next_char().filter(|ch| *ch != '}') {
+                        hex.push(ch);
+                    }
+                    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                }
+                _ => None,
+            },
+            ch => ch,
+        };
+        ch.ok_or_else(|| self.error(start, "this literal is not closed properly"))
+    }
+}
+
+/// split `name_index` into its parts, the index of a name without one is 0
+fn read_ident(word: &str) -> Ident {
+    if let Some((name, index)) = word.rsplit_once('_') {
+        let digits = !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit());
+        if !name.is_empty() && digits {
+            if let Ok(index) = index.parse() {
+                Ident::reserve(index);
+                return Ident {
+                    name: InternStr::new(name),
+                    index,
+                };
+            }
+        }
+    }
+    Ident::from(InternStr::new(word))
+}
+
+struct IrParser {
+    toks: Vec<(Tok, Span)>,
+    cursor: usize,
+}
+
+type ParseResult<T> = Result<T, Diagnostic>;
+
+impl IrParser {
+    fn peek(&self) -> &Tok {
+        &self.toks[self.cursor].0
+    }
+
+    fn peek_nth(&self, n: usize) -> &Tok {
+        let index = (self.cursor + n).min(self.toks.len() - 1);
+        &self.toks[index].0
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.toks[self.cursor].0.clone();
+        if self.cursor + 1 < self.toks.len() {
+            self.cursor += 1;
+        }
+        tok
+    }
+
+    fn error<T>(&self, expect: &str) -> ParseResult<T> {
+        let (tok, span) = &self.toks[self.cursor];
+        let msg = format!("expected {expect}, found {}", tok.describe());
+        Err(Diagnostic::error("invalid IR").line_span(*span, msg))
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Tok::Word(w) if w == word)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(p) if *p == punct)
+    }
+
+    fn word(&mut self, word: &str) -> ParseResult<()> {
+        if self.is_word(word) {
+            self.next();
+            Ok(())
+        } else {
+            self.error(&format!("`{word}`"))
+        }
+    }
+
+    fn punct(&mut self, punct: &str) -> ParseResult<()> {
+        if self.is_punct(punct) {
+            self.next();
+            Ok(())
+        } else {
+            self.error(&format!("`{punct}`"))
+        }
+    }
+
+    fn option_word(&mut self, word: &str) -> bool {
+        let found = self.is_word(word);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn name(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Tok::Word(word) => {
+                let word = word.clone();
+                self.next();
+                Ok(word)
+            }
+            _ => self.error("a name"),
+        }
+    }
+
+    fn ident(&mut self) -> ParseResult<Ident> {
+        self.name().map(|word| read_ident(&word))
+    }
+
+    fn int(&mut self) -> ParseResult<i64> {
+        match self.peek() {
+            Tok::Int(x) => {
+                let x = *x;
+                self.next();
+                Ok(x)
+            }
+            _ => self.error("an integer"),
+        }
+    }
+
+    fn index(&mut self) -> ParseResult<usize> {
+        match self.peek() {
+            Tok::Int(x) if *x >= 0 => {
+                let x = *x as usize;
+                self.next();
+                Ok(x)
+            }
+            _ => self.error("a non-negative integer"),
+        }
+    }
+
+    fn string(&mut self) -> ParseResult<InternStr> {
+        match self.peek() {
+            Tok::Str(x) => {
+                let x = InternStr::new(x.as_str());
+                self.next();
+                Ok(x)
+            }
+            _ => self.error("a string"),
+        }
+    }
+
+    fn atom(&mut self) -> ParseResult<Atom> {
+        let atom = match self.peek() {
+            Tok::Int(x) => Atom::Int(*x),
+            Tok::Real(x) => Atom::Real(*x),
+            Tok::Char(x) => Atom::Char(*x),
+            Tok::Str(x) => Atom::Str(InternStr::new(x.as_str())),
+            Tok::Punct("(") => {
+                self.next();
+                self.punct(")")?;
+                return Ok(Atom::Unit);
+            }
+            Tok::Punct("{") => {
+                self.next();
+                let mut fields = Vec::new();
+                while !self.is_punct("}") {
+                    if !fields.is_empty() {
+                        self.punct(",")?;
+                    }
+                    let cursor = self.cursor;
+                    let field = self.atom()?;
+                    if field.is_var() {
+                        self.cursor = cursor;
+                        return self.error("a literal or a constant");
+                    }
+                    fields.push(field);
+                }
+                self.punct("}")?;
+                return Ok(Atom::Const(ConstId::new(fields)));
+            }
+            Tok::Word(word) => match word.as_str() {
+                "true" => Atom::Bool(true),
+                "false" => Atom::Bool(false),
+                "inf" => Atom::Real(f64::INFINITY),
+                "NaN" => Atom::Real(f64::NAN),
+                _ => return self.ident().map(Atom::Var),
+            },
+            _ => return self.error("an atom"),
+        };
+        self.next();
+        Ok(atom)
+    }
+
+    /// a comma separated list in parentheses
+    fn list<T>(&mut self, item: impl Fn(&mut Self) -> ParseResult<T>) -> ParseResult<Vec<T>> {
+        self.punct("(")?;
+        let mut items = Vec::new();
+        while !self.is_punct(")") {
+            if !items.is_empty() {
+                self.punct(",")?;
+            }
+            items.push(item(self)?);
+        }
+        self.punct(")")?;
+        Ok(items)
+    }
+
+    fn cold(&mut self, cold: &mut Cold, index: usize) {
+        if self.option_word("cold") {
+            cold.0.insert(index);
+        }
+    }
+
+    fn cont(&mut self) -> ParseResult<Box<MExpr>> {
+        self.punct(";")?;
+        Ok(Box::new(self.expr()?))
+    }
+
+    fn decl(&mut self) -> ParseResult<MDecl> {
+        self.word("fun")?;
+        let func = self.ident()?;
+        let pars = self.list(Self::ident)?;
+        self.punct("=")?;
+        let body = self.expr()?;
+        Ok(MDecl { func, pars, body })
+    }
+
+    fn expr(&mut self) -> ParseResult<MExpr> {
+        match self.peek() {
+            Tok::Word(word) if word == "letrec" => {
+                self.next();
+                let mut decls = Vec::new();
+                while !self.option_word("in") {
+                    decls.push(self.decl()?);
+                }
+                let cont = Box::new(self.expr()?);
+                self.word("end")?;
+                Ok(MExpr::LetIn { decls, cont })
+            }
+            Tok::Word(word) if word == "return" => {
+                self.next();
+                let arg1 = self.atom()?;
+                if self.peek() == &Tok::Punct("(") {
+                    let args = self.list(Self::atom)?;
+                    return Ok(MExpr::TailCall { func: arg1, args });
+                }
+                Ok(MExpr::Retn { arg1 })
+            }
+            Tok::Word(word) if word == "panic" => {
+                self.next();
+                let msg = self.string()?;
+                Ok(MExpr::Panic { msg })
+            }
+            Tok::Word(word) if word == "store" => {
+                self.next();
+                let arg1 = self.atom()?;
+                self.punct("[")?;
+                let index = self.index()?;
+                self.punct("]")?;
+                self.punct(":=")?;
+                let arg2 = self.atom()?;
+                let cont = self.cont()?;
+                Ok(MExpr::Store {
+                    arg1,
+                    index,
+                    arg2,
+                    cont,
+                })
+            }
+            Tok::Word(word) if word == "let" => {
+                self.next();
+                let bind = self.ident()?;
+                self.punct("=")?;
+                self.rhs(bind)
+            }
+            _ => self.error("`letrec`, `let`, `store` or `return`"),
+        }
+    }
+
+    fn rhs(&mut self, bind: Ident) -> ParseResult<MExpr> {
+        let word = match self.peek() {
+            Tok::Word(word) => word.clone(),
+            Tok::Punct("#") => return self.ext_call(bind),
+            // a literal can't be called, but that is left to the validator
+            _ => String::new(),
+        };
+        if self.peek_nth(1) == &Tok::Punct("#") {
+            return self.ext_call(bind);
+        }
+        let call = self.peek_nth(1) == &Tok::Punct("(");
+        if let Some(prim) = UNOPS.iter().find(|prim| call && prim.to_string() == word) {
+            self.next();
+            self.punct("(")?;
+            let arg1 = self.atom()?;
+            self.punct(")")?;
+            let cont = self.cont()?;
+            return Ok(MExpr::UnOp {
+                bind,
+                prim: *prim,
+                arg1,
+                cont,
+            });
+        }
+        if let Some(prim) = BINOPS.iter().find(|prim| call && prim.to_string() == word) {
+            self.next();
+            self.punct("(")?;
+            let arg1 = self.atom()?;
+            self.punct(",")?;
+            let arg2 = self.atom()?;
+            self.punct(")")?;
+            let cont = self.cont()?;
+            return Ok(MExpr::BinOp {
+                bind,
+                prim: *prim,
+                arg1,
+                arg2,
+                cont,
+            });
+        }
+        match word.as_str() {
+            "alloc" => {
+                self.next();
+                self.punct("[")?;
+                let size = self.index()?;
+                self.punct("]")?;
+                let cont = self.cont()?;
+                Ok(MExpr::Alloc { bind, size, cont })
+            }
+            "load" => {
+                self.next();
+                let arg1 = self.atom()?;
+                self.punct("[")?;
+                let index = self.index()?;
+                self.punct("]")?;
+                let cont = self.cont()?;
+                Ok(MExpr::Load {
+                    bind,
+                    arg1,
+                    index,
+                    cont,
+                })
+            }
+            "offset" => {
+                self.next();
+                let arg1 = self.atom()?;
+                self.punct("[")?;
+                let index = self.int()? as isize;
+                self.punct("]")?;
+                let cont = self.cont()?;
+                Ok(MExpr::Offset {
+                    bind,
+                    arg1,
+                    index,
+                    cont,
+                })
+            }
+            "if" if call => {
+                self.next();
+                self.punct("(")?;
+                let arg1 = self.atom()?;
+                self.punct(")")?;
+                let mut cold = Cold::default();
+                self.word("then")?;
+                self.cold(&mut cold, 0);
+                let brch1 = Box::new(self.expr()?);
+                self.word("else")?;
+                self.cold(&mut cold, 1);
+                let brch2 = Box::new(self.expr()?);
+                let cont = self.cont()?;
+                Ok(MExpr::Ifte {
+                    bind,
+                    arg1,
+                    brch1,
+                    brch2,
+                    cold,
+                    cont,
+                })
+            }
+            "switch" if call => {
+                self.next();
+                self.punct("(")?;
+                let arg1 = self.atom()?;
+                self.punct(")")?;
+                self.punct("{")?;
+                let mut cold = Cold::default();
+                let mut brchs = Vec::new();
+                while self.option_word("case") {
+                    let i = self.index()?;
+                    self.cold(&mut cold, brchs.len());
+                    self.punct(":")?;
+                    brchs.push((i, self.expr()?));
+                }
+                let dflt = if self.option_word("default") {
+                    self.cold(&mut cold, brchs.len());
+                    self.punct(":")?;
+                    Some(Box::new(self.expr()?))
+                } else {
+                    None
+                };
+                self.punct("}")?;
+                let cont = Box::new(self.expr()?);
+                Ok(MExpr::Switch {
+                    bind,
+                    arg1,
+                    brchs,
+                    dflt,
+                    cold,
+                    cont,
+                })
+            }
+            _ => {
+                let func = self.atom()?;
+                let args = self.list(Self::atom)?;
+                let cont = self.cont()?;
+                Ok(MExpr::Call {
+                    bind,
+                    func,
+                    args,
+                    cont,
+                })
+            }
+        }
+    }
+
+    fn ext_call(&mut self, bind: Ident) -> ParseResult<MExpr> {
+        let call_conv = if self.is_punct("#") {
+            CallConv::C
+        } else {
+            let name = self.name()?;
+            match CallConv::from_name(&name) {
+                Some(conv) => conv,
+                None => {
+                    self.cursor -= 1;
+                    return self.error("a calling convention");
+                }
+            }
+        };
+        self.punct("#")?;
+        let func = InternStr::new(self.name()?);
+        let args = self.list(Self::atom)?;
+        let mut abi = ExtAbi::default();
+        if self.option_word("varargs") {
+            abi.varargs = Some(self.list(|par| {
+                let name = par.name()?;
+                match FFI_TYPES.iter().find(|ty| ty.to_string() == name) {
+                    Some(ty) => Ok(*ty),
+                    None => {
+                        par.cursor -= 1;
+                        par.error("`Int`, `Real`, `Bool`, `Char` or `Ptr`")
+                    }
+                }
+            })?);
+        }
+        abi.unit_ret = self.option_word("void");
+        let cont = self.cont()?;
+        Ok(MExpr::ExtCall {
+            bind,
+            func,
+            call_conv,
+            abi,
+            args,
+            cont,
+        })
+    }
+}
+
+/// check the version header, it has to be the first line
+fn check_header(src: &str, file: FileId) -> Result<(), Diagnostic> {
+    let line = src.lines().next().unwrap_or("");
+    let end = Position::new(0, line.chars().count(), line.len());
+    let span = Span {
+        file,
+        ..Span::new(Position::new(0, 0, 0), end)
+    };
+    let expect = format!("the first line should be `{IR_HEADER} {IR_VERSION}`");
+    match line.strip_prefix(IR_HEADER) {
+        Some(version) => match version.trim().parse::<u32>() {
+            Ok(IR_VERSION) => Ok(()),
+            Ok(version) => Err(
+                Diagnostic::error(format!("unsupported IR version {version}")).line_span(
+                    span,
+                    format!("this compiler only reads IR version {IR_VERSION}"),
+                ),
+            ),
+            Err(_) => Err(Diagnostic::error("invalid IR version header").line_span(span, expect)),
+        },
+        None => Err(Diagnostic::error("missing IR version header").line_span(span, expect)),
+    }
+}
+
+/// parse the printed IR and validate it
+pub fn parse_ir(src: &str) -> Result<MExpr, Vec<Diagnostic>> {
+    parse_ir_in(src, FileId::default())
+}
+
+/// same as `parse_ir`, with the spans of diagnostics pointing into `file`
+pub fn parse_ir_in(src: &str, file: FileId) -> Result<MExpr, Vec<Diagnostic>> {
+    check_header(src, file).map_err(|diag| vec![diag])?;
+    let toks = Tokenizer::new(src, file)
+        .tokenize()
+        .map_err(|diag| vec![diag])?;
+    let mut par = IrParser { toks, cursor: 0 };
+    let expr = par.expr().map_err(|diag| vec![diag])?;
+    if par.peek() != &Tok::End {
+        return Err(vec![par.error::<()>("end of file").unwrap_err()]);
+    }
+    let diags = validate_ir(&expr);
+    if diags.is_empty() {
+        Ok(expr)
+    } else {
+        Err(diags)
+    }
+}
+
+/// The backend assumes every variable is bound before it's used, and never shadowed.
+/// Separate branches may bind the same variable, normalizing a match does that.
+struct Validator {
+    scope: Vec<Ident>,
+    diags: Vec<Diagnostic>,
+}
+
+/// check the invariants that a hand-written IR could break
+pub fn validate_ir(expr: &MExpr) -> Vec<Diagnostic> {
+    let mut valid = Validator {
+        scope: Vec::new(),
+        diags: Vec::new(),
+    };
+    valid.visit_expr(expr);
+    valid.diags
+}
+
+impl Validator {
+    fn bind(&mut self, ident: &Ident) {
+        if self.scope.contains(ident) {
+            let diag = Diagnostic::error(format!("variable `{ident}` is bound again"))
+                .line("it shadows a binding that is still in scope");
+            self.diags.push(diag);
+        }
+        self.scope.push(*ident);
+    }
+
+    fn visit_atom(&mut self, atom: &Atom) {
+        if let Atom::Var(var) = atom {
+            if !self.scope.contains(var) {
+                let diag = Diagnostic::error(format!("unbound variable `{var}`"))
+                    .line("it is not a parameter or bound before this use");
+                self.diags.push(diag);
+            }
+        }
+    }
+
+    /// visit `expr` in the current scope, the bindings inside don't leak out
+    fn visit_scoped(&mut self, expr: &MExpr) {
+        let depth = self.scope.len();
+        self.visit_expr(expr);
+        self.scope.truncate(depth);
+    }
+
+    fn visit_expr(&mut self, expr: &MExpr) {
+        match expr {
+            MExpr::LetIn { decls, cont } => {
+                for decl in decls {
+                    self.bind(&decl.func);
+                }
+                for decl in decls {
+                    let depth = self.scope.len();
+                    decl.pars.iter().for_each(|par| self.bind(par));
+                    self.visit_expr(&decl.body);
+                    self.scope.truncate(depth);
+                }
+                self.visit_expr(cont);
+            }
+            MExpr::UnOp {
+                bind, arg1, cont, ..
+            } => {
+                self.visit_atom(arg1);
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+            MExpr::BinOp {
+                bind,
+                arg1,
+                arg2,
+                cont,
+                ..
+            } => {
+                self.visit_atom(arg1);
+                self.visit_atom(arg2);
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+            MExpr::Call {
+                bind,
+                func,
+                args,
+                cont,
+            } => {
+                if !func.is_var() {
+                    let diag = Diagnostic::error(format!("calling the literal `{func}`"))
+                        .line("only variables can be called");
+                    self.diags.push(diag);
+                }
+                self.visit_atom(func);
+                args.iter().for_each(|arg| self.visit_atom(arg));
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+            MExpr::ExtCall {
+                bind,
+                func,
+                abi,
+                args,
+                cont,
+                ..
+            } => {
+                args.iter().for_each(|arg| self.visit_atom(arg));
+                let variadic = abi.varargs.as_ref().map_or(0, |varargs| varargs.len());
+                if variadic > args.len() {
+                    let diag = Diagnostic::error(format!("too few arguments for `{func}`"))
+                        .line("there are more variadic types than arguments");
+                    self.diags.push(diag);
+                }
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+            MExpr::Retn { arg1 } => {
+                self.visit_atom(arg1);
+            }
+            MExpr::TailCall { func, args } => {
+                if !func.is_var() {
+                    let diag = Diagnostic::error(format!("calling the literal `{func}`"))
+                        .line("only variables can be called");
+                    self.diags.push(diag);
+                }
+                self.visit_atom(func);
+                args.iter().for_each(|arg| self.visit_atom(arg));
+            }
+            MExpr::Panic { .. } => {}
+            MExpr::Alloc { bind, cont, .. } => {
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+            MExpr::Load {
+                bind, arg1, cont, ..
+            }
+            | MExpr::Offset {
+                bind, arg1, cont, ..
+            } => {
+                self.visit_atom(arg1);
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+            MExpr::Store {
+                arg1, arg2, cont, ..
+            } => {
+                self.visit_atom(arg1);
+                self.visit_atom(arg2);
+                self.visit_expr(cont);
+            }
+            MExpr::Ifte {
+                bind,
+                arg1,
+                brch1,
+                brch2,
+                cont,
+                ..
+            } => {
+                self.visit_atom(arg1);
+                self.visit_scoped(brch1);
+                self.visit_scoped(brch2);
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+            MExpr::Switch {
+                bind,
+                arg1,
+                brchs,
+                dflt,
+                cont,
+                ..
+            } => {
+                self.visit_atom(arg1);
+                let mut cases = BTreeSet::new();
+                for (i, brch) in brchs {
+                    if !cases.insert(*i) {
+                        let diag = Diagnostic::error(format!("duplicate case `{i}` in a switch"))
+                            .line("only the first one could ever be taken");
+                        self.diags.push(diag);
+                    }
+                    self.visit_scoped(brch);
+                }
+                if let Some(dflt) = dflt {
+                    self.visit_scoped(dflt);
+                }
+                self.bind(bind);
+                self.visit_expr(cont);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn round_trip(expr: &MExpr) -> MExpr {
+    use crate::utils::printer::print_ir;
+    let text = print_ir(expr);
+    let expr = parse_ir(&text).unwrap_or_else(|diags| {
+        let diags: String = diags.iter().map(|diag| diag.report(&text, 10)).collect();
+        panic!("failed to read back\n{text}\n{diags}")
+    });
+    assert_eq!(print_ir(&expr), text);
+    expr
+}
+
+#[test]
+fn ir_round_trip_test() {
+    use crate::backend::clos_conv::ClosConv;
+    use crate::backend::simple_opt::{ConstFold, DeadElim, LinearInline};
+    use crate::backend::tail_call::mark_tail_calls;
+    use crate::frontend::parser::{parse_expr, Parser};
+    use crate::utils::driver::lower_expr;
+    // every stage of the optimizer, over every example
+    let mut paths: Vec<_> = std::fs::read_dir("examples")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "nrm"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    // the larger examples nest too deep for the default stack of a test thread
+    std::thread::Builder::new()
+        .stack_size(16 * 1024 * 1024)
+        .spawn(move || {
+            for path in paths {
+                let source = std::fs::read_to_string(&path).unwrap();
+                let mut par = Parser::new(&source);
+                let expr = parse_expr(&mut par).unwrap();
+                let (expr, ctx, _) = lower_expr(expr, false).unwrap();
+                let mut expr = round_trip(&expr);
+                for _ in 0..2 {
+                    expr = round_trip(&DeadElim::run_with_ctx(expr, &ctx));
+                    expr = round_trip(&ConstFold::run_with_ctx(expr, &ctx));
+                    expr = round_trip(&LinearInline::run_with_ctx(expr, &ctx));
+                    expr = round_trip(&ClosConv::run(expr));
+                }
+                round_trip(&mark_tail_calls(expr));
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn ir_atom_test() {
+    use crate::backend::anf_build::*;
+    let entry = ConstId::new(vec![i(1), r(2.5)]);
+    let table = ConstId::new(vec![
+        i(0),
+        Atom::Str(InternStr::new("key")),
+        Atom::Const(entry),
+    ]);
+    let expr = chain(vec![
+        call(
+            "x",
+            "f",
+            vec![i(-3), r(1.0), r(-0.0), r(1e-7), r(f64::INFINITY)],
+        ),
+        call(
+            "y",
+            "f",
+            vec![r(f64::NEG_INFINITY), r(f64::NAN), r(-f64::NAN)],
+        ),
+        call(
+            "z",
+            "f",
+            vec![c('\''), c('\n'), c('\u{7f}'), b(true), unit()],
+        ),
+        call("w", "f", vec![Atom::Str(InternStr::new("a\"b\\c\td\0"))]),
+        call("k", "f", vec![Atom::Const(table)]),
+        retn(i(0)),
+    ]);
+    let expr = MExpr::LetIn {
+        decls: vec![fun("f", vec!["a"], retn(v("a")))],
+        cont: Box::new(expr),
+    };
+    let text = crate::utils::printer::print_ir(&expr);
+    assert!(text.contains("f(-3, 1.0, -0.0, 1e-7, inf)"));
+    assert!(text.contains("f(-inf, NaN, -NaN)"));
+    assert!(text.contains(r#"f('\'', '\n', '\u{7f}', true, ())"#));
+    assert!(text.contains(r#"f({0, "key", {1, 2.5}})"#));
+    let expr = round_trip(&expr);
+    let atoms = |bind: &str| {
+        let mut expr = &expr;
+        loop {
+            match expr {
+                MExpr::LetIn { cont, .. } => expr = cont,
+                MExpr::Call {
+                    bind: bind_, args, ..
+                } if bind_.name.as_str() == bind => return args.clone(),
+                MExpr::Call { cont, .. } => expr = cont,
+                _ => unreachable!(),
+            }
+        }
+    };
+    // reals keep their bits, including the sign of zeros and NaNs
+    let bits: Vec<u64> = atoms("x")
+        .into_iter()
+        .chain(atoms("y"))
+        .filter_map(|atom| match atom {
+            Atom::Real(x) => Some(x.to_bits()),
+            _ => None,
+        })
+        .collect();
+    let expect = [
+        1.0,
+        -0.0,
+        1e-7,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+        -f64::NAN,
+    ];
+    assert_eq!(bits, expect.map(f64::to_bits));
+    assert_eq!(atoms("w"), vec![Atom::Str(InternStr::new("a\"b\\c\td\0"))]);
+    // a constant read back is a new one with the same fields
+    let Atom::Const(read) = atoms("k")[0] else {
+        panic!("expected a constant");
+    };
+    assert_eq!(read.fields()[..2], table.fields()[..2]);
+}
+
+#[test]
+fn ir_panic_test() {
+    use crate::backend::anf_build::*;
+    let expr = chain(vec![
+        ifte("x", v("c"), panic("no \"way\"!"), retn(i(1))),
+        retn(v("x")),
+    ]);
+    let expr = MExpr::LetIn {
+        decls: vec![fun("f", vec!["c"], expr)],
+        cont: Box::new(retn(i(0))),
+    };
+    let text = crate::utils::printer::print_ir(&expr);
+    assert!(text.contains(r#"panic "no \"way\"!""#));
+    let expr = round_trip(&expr);
+    assert!(format!("{expr}").contains(r#"panic "no \"way\"!""#));
+    let text = format!("{IR_HEADER} {IR_VERSION}\npanic 0");
+    let diags = parse_ir(&text).unwrap_err();
+    assert!(diags[0]
+        .report(&text, 10)
+        .contains("expected a string, found `0`"));
+}
+
+#[test]
+fn ir_version_test() {
+    let body = "\nreturn 0\n";
+    assert!(parse_ir(&format!("{IR_HEADER} {IR_VERSION}{body}")).is_ok());
+    let diags = parse_ir(&format!("{IR_HEADER} {}{body}", IR_VERSION + 1)).unwrap_err();
+    assert_eq!(diags.len(), 1);
+    let msg = format!("unsupported IR version {}", IR_VERSION + 1);
+    assert!(diags[0].report(body, 10).contains(&msg));
+    let diags = parse_ir(body.trim_start()).unwrap_err();
+    assert!(diags[0]
+        .report(body, 10)
+        .contains("missing IR version header"));
+    let diags = parse_ir(&format!("{IR_HEADER} one{body}")).unwrap_err();
+    assert!(diags[0]
+        .report(body, 10)
+        .contains("invalid IR version header"));
+}
+
+#[test]
+fn ir_comment_test() {
+    let text = format!(
+        "{IR_HEADER} {IR_VERSION}\n\
+        // comments are skipped\n\
+        let x_1 = iadd(1, 2); // anywhere\n\
+        let y_2 = offset x_1[-1];\n\
+        return y_2\n"
+    );
+    let expr = parse_ir(&text).unwrap();
+    let expect = format!(
+        "{IR_HEADER} {IR_VERSION}\nlet x_1 = iadd(1,2);\nlet y_2 = offset x_1[-1];\nreturn y_2\n"
+    );
+    assert_eq!(crate::utils::printer::print_ir(&expr), expect);
+    // identifiers read back are never generated again
+    assert!(Ident::generate('x').index > 2);
+}
+
+#[test]
+fn ir_syntax_error_test() {
+    let report = |body: &str| {
+        let text = format!("{IR_HEADER} {IR_VERSION}\n{body}");
+        let diags = parse_ir(&text).unwrap_err();
+        assert_eq!(diags.len(), 1);
+        diags[0].report(&text, 10)
+    };
+    assert!(report("let x_1 = iadd(1);\nreturn x_1").contains("expected `,`, found `)`"));
+    assert!(report("return 0 return 1").contains("expected end of file, found `return`"));
+    assert!(report("let x_1 = fastcal #f();\nreturn 0").contains("a calling convention"));
+    assert!(report("let x_1 = #f() varargs(Long);\nreturn 0").contains("`Ptr`"));
+    assert!(report("let x_1 = alloc[-1];\nreturn 0").contains("non-negative integer"));
+    assert!(report("return 'ab'").contains("exactly one char"));
+    assert!(report("return \"open").contains("not closed properly"));
+    assert!(report("return 1.2.3").contains("not a valid number"));
+    assert!(report("let x_1 = move(1);\nreturn {1, x_1}")
+        .contains("expected a literal or a constant, found `x_1`"));
+}
+
+#[test]
+fn ir_validate_test() {
+    let titles = |body: &str| {
+        let text = format!("{IR_HEADER} {IR_VERSION}\n{body}");
+        parse_ir(&text)
+            .unwrap_err()
+            .iter()
+            .map(|diag| diag.report(&text, 10).lines().next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        titles("let x_1 = iadd(y_2,1);\nreturn x_1"),
+        vec!["[Error]: unbound variable `y_2`"]
+    );
+    // a branch doesn't bind anything outside of it
+    assert_eq!(
+        titles("let x_1 = if(true) then\n let y_2 = move(1);\n return y_2\nelse\n return 0\n;\nreturn y_2"),
+        vec!["[Error]: unbound variable `y_2`"]
+    );
+    assert_eq!(
+        titles("letrec\n fun f_1(x_2) =\n  let x_2 = move(1);\n  return x_2\nin\n return f_1\nend"),
+        vec!["[Error]: variable `x_2` is bound again"]
+    );
+    assert_eq!(
+        titles("let x_1 = switch(0) {\n case 1:\n return 0\n case 1:\n return 1\n}\nreturn x_1"),
+        vec!["[Error]: duplicate case `1` in a switch"]
+    );
+    assert_eq!(
+        titles("let x_1 = #printf(\"%d\") varargs(Int, Int);\nreturn 0"),
+        vec!["[Error]: too few arguments for `printf`"]
+    );
+    assert_eq!(
+        titles("let x_1 = 1(2);\nreturn x_1"),
+        vec!["[Error]: calling the literal `1`"]
+    );
+    // the same variable in separate branches, as normalizing a match does
+    let text = format!(
+        "{IR_HEADER} {IR_VERSION}\n\
+        let x_1 = if(true) then\n let y_2 = move(1);\n return y_2\n\
+        else\n let y_2 = move(2);\n return y_2\n;\nreturn x_1"
+    );
+    assert!(parse_ir(&text).is_ok());
+}
@@ -1,22 +1,28 @@
 use super::*;
 use crate::utils::env_map::FreeSet;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 
 pub struct ClosConv {
     toplevel: Vec<MDecl>,
-    lifted: HashSet<Ident>,
     freevar: FreeSet<Ident>,
 }
 
+impl Default for ClosConv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ClosConv {
     pub fn new() -> ClosConv {
         ClosConv {
             toplevel: Vec::new(),
-            lifted: HashSet::new(),
             freevar: FreeSet::new(),
         }
     }
     pub fn run(expr: MExpr) -> MExpr {
+        let expr = share_envs(expr);
         let mut pass = ClosConv::new();
         let expr = pass.visit_expr(expr);
         MExpr::LetIn {
@@ -34,9 +40,7 @@ impl ClosConv {
 
     fn visit_arg(&mut self, atom: Atom) -> Atom {
         if let Atom::Var(sym) = atom {
-            if !self.lifted.contains(&sym) {
-                self.freevar.insert(sym);
-            }
+            self.freevar.insert(sym);
         }
         atom
     }
@@ -96,7 +100,6 @@ impl ClosConv {
 
                 for func in &func_names {
                     self.freevar.remove(func);
-                    self.lifted.insert(*func);
                 }
 
                 // collect as vector to maintain the order
@@ -179,6 +182,12 @@ impl ClosConv {
 
                 let cont = Box::new(self.visit_expr(*cont));
 
+                // the functions are bound by the offsets below, a closure nested in `cont`
+                // that uses one of them captures it from there
+                for func in &func_names {
+                    self.freevar.remove(func);
+                }
+
                 // generate shared closure 'c'
                 let c = Ident::generate('c');
 
@@ -206,13 +215,11 @@ impl ClosConv {
                 );
 
                 // here is the (record creation) part
-                let cont = MExpr::Alloc {
+                MExpr::Alloc {
                     bind: c,
                     size: func_names.len() + freevars.len(),
                     cont: Box::new(cont),
-                };
-
-                cont
+                }
             }
             MExpr::Call {
                 bind,
@@ -249,6 +256,25 @@ impl ClosConv {
                     }),
                 }
             }
+            MExpr::TailCall { func, args } => {
+                let func = self.visit_arg(func);
+                let args: Vec<_> = args.into_iter().map(|arg| self.visit_arg(arg)).collect();
+                // same as a call, the closure is passed as the first argument
+                let f2 = Ident::generate('f');
+                MExpr::Load {
+                    bind: f2,
+                    arg1: func,
+                    index: 0,
+                    cont: Box::new(MExpr::TailCall {
+                        func: Atom::Var(f2),
+                        args: {
+                            let mut args = args;
+                            args.insert(0, func);
+                            args
+                        },
+                    }),
+                }
+            }
             MExpr::Retn { arg1 } => {
                 let arg1 = self.visit_arg(arg1);
                 MExpr::Retn { arg1 }
@@ -262,6 +288,155 @@ impl ClosConv {
     }
 }
 
+/// a block sharing the record of its enclosing block keeps all the variables of that record
+/// alive as long as its closures, so it only shares if it uses all but this many of them
+const SHARE_SLACK: usize = 2;
+
+/// Move a block of functions into the block of the function it is declared in, if the block
+/// only captures variables that the enclosing block captures too, or functions of that block.
+/// Closure conversion then lays out its functions in the record of the enclosing block,
+/// next to the functions already there, so creating its closures allocates nothing and each
+/// captured variable is still a single load from the record. This is repeated outwards, a
+/// block is moved as far as the variables it captures allow.
+/// The record of the enclosing block stays alive as long as the closures of the moved block,
+/// so a block that would keep more than `SHARE_SLACK` unused variables alive is left in place.
+pub fn share_envs(expr: MExpr) -> MExpr {
+    // the variables a block captures are found by name, so every name must be bound once
+    let expr = expr.rename();
+    let mut collect = EnvCollect {
+        freevar: FreeSet::new(),
+        envs: HashMap::new(),
+    };
+    let expr = collect.visit_expr(expr);
+    let mut pass = EnvShare {
+        envs: collect.envs,
+        outer: None,
+        moved: Vec::new(),
+    };
+    pass.visit_expr(expr)
+}
+
+/// the variables captured by each block, by the first function of the block
+struct EnvCollect {
+    freevar: FreeSet<Ident>,
+    envs: HashMap<Ident, HashSet<Ident>>,
+}
+
+impl EnvCollect {
+    fn visit_bind(&mut self, bind: Ident) -> Ident {
+        self.freevar.remove(&bind);
+        bind
+    }
+
+    fn visit_arg(&mut self, atom: Atom) -> Atom {
+        if let Atom::Var(sym) = atom {
+            self.freevar.insert(sym);
+        }
+        atom
+    }
+
+    fn visit_expr(&mut self, expr: MExpr) -> MExpr {
+        match expr {
+            MExpr::LetIn { decls, cont } => {
+                // the same free variables as `ClosConv` stores in the record of the block
+                self.freevar.enter_scope();
+                let decls: Vec<MDecl> = decls
+                    .into_iter()
+                    .map(|decl| {
+                        self.freevar.enter_scope();
+                        let decl = decl.walk_body(|body| self.visit_expr(body));
+                        self.freevar.leave_scope();
+                        for par in decl.pars.iter() {
+                            self.freevar.remove(par);
+                        }
+                        decl
+                    })
+                    .collect();
+                for decl in decls.iter() {
+                    self.freevar.remove(&decl.func);
+                }
+                if let Some(first) = decls.first() {
+                    self.envs
+                        .insert(first.func, self.freevar.iter().cloned().collect());
+                }
+                self.freevar.leave_scope();
+                let cont = Box::new(self.visit_expr(*cont));
+                for decl in decls.iter() {
+                    self.freevar.remove(&decl.func);
+                }
+                MExpr::LetIn { decls, cont }
+            }
+            other => other
+                .walk_cont(|cont| self.visit_expr(cont))
+                .walk_bind(|bind| self.visit_bind(bind))
+                .walk_brch(|brch| self.visit_expr(brch))
+                .walk_arg(|arg| self.visit_arg(arg)),
+        }
+    }
+}
+
+/// the block of the function being visited, as seen by a block declared in it
+struct Outer {
+    // what a block may capture to share the record: the captured variables and the
+    // functions of the enclosing block
+    shared: HashSet<Ident>,
+    // the captured variables of the record, which may belong to a block further out
+    record: HashSet<Ident>,
+}
+
+struct EnvShare {
+    envs: HashMap<Ident, HashSet<Ident>>,
+    outer: Option<Outer>,
+    // the functions of the blocks moved into the block being visited
+    moved: Vec<MDecl>,
+}
+
+impl EnvShare {
+    fn visit_expr(&mut self, expr: MExpr) -> MExpr {
+        match expr {
+            MExpr::LetIn { decls, cont } => {
+                let empty = HashSet::new();
+                let env = decls
+                    .first()
+                    .and_then(|first| self.envs.get(&first.func))
+                    .unwrap_or(&empty);
+                let share = self.outer.as_ref().is_some_and(|outer| {
+                    env.iter().all(|x| outer.shared.contains(x))
+                        && outer.record.iter().filter(|x| !env.contains(*x)).count() <= SHARE_SLACK
+                });
+                let record = match &self.outer {
+                    Some(outer) if share => outer.record.clone(),
+                    _ => env.clone(),
+                };
+                let mut shared = env.clone();
+                shared.extend(decls.iter().map(|decl| decl.func));
+                let outer = self.outer.replace(Outer { shared, record });
+                let moved = mem::take(&mut self.moved);
+                let mut decls: Vec<MDecl> = decls
+                    .into_iter()
+                    .map(|decl| decl.walk_body(|body| self.visit_expr(body)))
+                    .collect();
+                decls.append(&mut self.moved);
+                self.moved = moved;
+                self.outer = outer;
+                let cont = self.visit_expr(*cont);
+                if share {
+                    self.moved.append(&mut decls);
+                    cont
+                } else {
+                    MExpr::LetIn {
+                        decls,
+                        cont: Box::new(cont),
+                    }
+                }
+            }
+            other => other
+                .walk_cont(|cont| self.visit_expr(cont))
+                .walk_brch(|brch| self.visit_expr(brch)),
+        }
+    }
+}
+
 #[test]
 fn clos_conv_test() {
     use super::anf_build::*;
@@ -417,3 +592,121 @@ fn clos_conv_test() {
     );
     assert_eq!(expr1, expr2);
 }
+
+#[test]
+fn clos_conv_shared_env_test() {
+    use super::anf_build::*;
+    use super::dce::subexprs;
+    // fun f1(x) => fun f2(y) => fun f3(z) => body
+    let nested = |body: MExpr| {
+        let f3 = let_in(vec![fun("f3", vec!["z"], body)], vec![retn(v("f3"))]);
+        let f2 = let_in(vec![fun("f2", vec!["y"], f3)], vec![retn(v("f2"))]);
+        let_in(vec![fun("f1", vec!["x"], f2)], vec![retn(v("f1"))])
+    };
+    fn allocs(expr: &MExpr, sizes: &mut Vec<usize>) {
+        if let MExpr::Alloc { size, .. } = expr {
+            sizes.push(*size);
+        }
+        if let MExpr::LetIn { decls, .. } = expr {
+            for decl in decls.iter() {
+                allocs(&decl.body, sizes);
+            }
+        }
+        for sub in subexprs(expr) {
+            allocs(sub, sizes);
+        }
+    }
+    let sizes = |expr: &MExpr| {
+        let mut sizes = Vec::new();
+        allocs(expr, &mut sizes);
+        sizes.sort();
+        sizes
+    };
+
+    // `f3` captures `y`, a parameter of `f2`, so it needs a record of its own
+    let body = chain(vec![
+        iadd("a", v("x"), v("y")),
+        iadd("r", v("a"), v("z")),
+        retn(v("r")),
+    ]);
+    let expr = ClosConv::run(nested(body));
+    assert_eq!(sizes(&expr), vec![1, 2, 3]);
+
+    // `f3` only captures `x`, which the record of `f2` holds already,
+    // so `f3` is laid out in that record and creating it allocates nothing
+    let body = chain(vec![iadd("r", v("x"), v("z")), retn(v("r"))]);
+    let expr = ClosConv::run(nested(body));
+    assert_eq!(sizes(&expr), vec![1, 3]);
+    // `x` is still a single load from the record that `f3` is called with
+    let MExpr::LetIn { decls, .. } = &expr else {
+        unreachable!()
+    };
+    let f3 = decls.iter().find(|decl| &*decl.func.name == "f3").unwrap();
+    let mut body = &f3.body;
+    while let MExpr::Offset { cont, .. } = body {
+        body = cont;
+    }
+    let MExpr::Load { bind, arg1, .. } = body else {
+        unreachable!()
+    };
+    assert_eq!((&*bind.name, arg1), ("x", &Atom::Var(f3.pars[0])));
+
+    // a record is only shared if it keeps at most `SHARE_SLACK` unused variables alive
+    let program = |unused: &[&str]| {
+        let f2 = let_in(
+            vec![fun(
+                "f2",
+                vec!["y"],
+                chain(vec![iadd("r", v("p"), v("y")), retn(v("r"))]),
+            )],
+            vec![retn(v("f2"))],
+        );
+        let uses = unused
+            .iter()
+            .map(|var| iadd("_", v(var), i(1)))
+            .chain([f2])
+            .collect();
+        let defs = ["p"].iter().chain(unused).map(|var| iadd(var, i(1), i(2)));
+        chain(
+            defs.chain([let_in(
+                vec![fun("f1", vec!["x"], chain(uses))],
+                vec![retn(v("f1"))],
+            )])
+            .collect(),
+        )
+    };
+    let expr = ClosConv::run(program(&["q", "s"]));
+    assert_eq!(sizes(&expr), vec![5]);
+    let expr = ClosConv::run(program(&["q", "s", "t"]));
+    assert_eq!(sizes(&expr), vec![2, 5]);
+}
+
+#[test]
+fn clos_conv_capture_block_function_test() {
+    use super::anf_build::*;
+    // a closure defined after a block uses a function of the block
+    let expr = let_in(
+        vec![fun("f1", vec!["x"], retn(v("x")))],
+        vec![let_in(
+            vec![fun(
+                "f2",
+                vec![],
+                chain(vec![call("r", "f1", vec![i(1)]), retn(v("r"))]),
+            )],
+            vec![retn(v("f2"))],
+        )],
+    );
+    let expr = ClosConv::run(expr);
+    let MExpr::LetIn { decls, .. } = expr else {
+        unreachable!()
+    };
+    // `f1` is loaded from the environment of `f2`, instead of being used as a closure
+    let f2 = decls.iter().find(|decl| &*decl.func.name == "f2").unwrap();
+    let MExpr::Offset { cont, .. } = &f2.body else {
+        unreachable!()
+    };
+    let MExpr::Load { bind, index, .. } = cont.as_ref() else {
+        unreachable!()
+    };
+    assert_eq!((&*bind.name, *index), ("f1", 1));
+}
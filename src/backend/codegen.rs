@@ -1,53 +1,92 @@
 use super::*;
+use crate::frontend::ast::{CallConv, FfiType};
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Result, Write};
 
 pub struct Codegen {
-    ext_map: HashMap<Ident, usize>,
+    // arity of external functions, whether they are variadic and whether they return `void`
+    ext_map: BTreeMap<InternStr, (usize, bool, bool)>,
+    nounroll: HashSet<InternStr>,
     bind_vec: Vec<Ident>,
+    // variables bound by `BinOpPrim::Expect`, with their expected value
+    expect_map: HashMap<Ident, Atom>,
     is_main: bool,
+    // the function emitted, its parameters start with its closure
+    decl: Option<(Ident, Vec<Ident>)>,
+    // whether the result of the expression emitted is returned by the function
+    tail: bool,
+    // variables holding the code pointer of the function emitted, loaded from its closure
+    self_funcs: HashSet<Ident>,
+    // whether the function emitted jumps back to its start
+    self_jump: bool,
+    // whether there is constant data to protect before `main` starts
+    has_consts: bool,
     text: String,
 }
 
 impl Codegen {
-    pub fn new(map: HashMap<Ident, usize>) -> Codegen {
+    pub fn new(map: BTreeMap<InternStr, (usize, bool, bool)>) -> Codegen {
         Codegen {
             ext_map: map,
+            nounroll: HashSet::new(),
             bind_vec: Vec::new(),
+            expect_map: HashMap::new(),
             is_main: false,
+            decl: None,
+            tail: false,
+            self_funcs: HashSet::new(),
+            self_jump: false,
+            has_consts: false,
             text: String::new(),
         }
     }
     pub fn run(expr: &MExpr) -> String {
-        let mut pass = Codegen::new(HashMap::new());
+        let mut pass = Codegen::new(BTreeMap::new());
+        pass.visit_toplevel(expr).unwrap();
+        pass.text
+    }
+
+    /// functions in `nounroll` are marked so that the C compiler won't unroll loops in them.
+    /// they are looked up by name, since closure conversion renames every function.
+    pub fn run_with_nounroll(expr: &MExpr, nounroll: HashSet<InternStr>) -> String {
+        let mut pass = Codegen::new(BTreeMap::new());
+        pass.nounroll = nounroll;
         pass.visit_toplevel(expr).unwrap();
         pass.text
     }
 
     fn visit_toplevel(&mut self, expr: &MExpr) -> Result {
-        match expr {
-            MExpr::LetIn { decls, cont } => {
-                self.text.push_str(C_PROLOGUE);
-                self.visit_extern_header()?;
-                for decl in decls {
-                    self.visit_decl_header(decl)?;
-                }
-                for decl in decls {
-                    self.visit_decl(decl)?;
-                }
-                self.text.push_str("int main(int argc, char* argv[])\n{\n");
-                self.text.push_str(C_SYS_CHECK);
-                self.is_main = true;
-                self.visit_expr(cont)?;
-                self.text.push_str("}\n");
-                self.text.push_str(C_EPILOGUE);
-                Ok(())
-            }
-            _ => {
-                panic!("after closure conversion there should be one and only one toplevel!");
-            }
+        let (decls, cont) = match expr {
+            MExpr::LetIn { decls, cont } => (&decls[..], cont.as_ref()),
+            // every function was inlined, the toplevel block has been removed
+            other => (&[][..], other),
+        };
+        self.text.push_str(C_PROLOGUE);
+        // without prototypes, C would assume that external functions return `int`
+        for decl in decls {
+            self.collect_externs(&decl.body);
+        }
+        self.collect_externs(cont);
+        self.visit_string_table(expr)?;
+        self.visit_const_table(expr)?;
+        self.visit_extern_header()?;
+        for decl in decls {
+            self.visit_decl_header(decl)?;
+        }
+        for decl in decls {
+            self.visit_decl(decl)?;
         }
+        self.text.push_str("int main(int argc, char* argv[])\n{\n");
+        self.text.push_str(C_SYS_CHECK);
+        if self.has_consts {
+            self.text.push_str("norem_const_protect();\n");
+        }
+        self.is_main = true;
+        self.visit_expr(cont)?;
+        self.text.push_str("}\n");
+        self.text.push_str(C_EPILOGUE);
+        Ok(())
     }
 
     fn visit_expr(&mut self, expr: &MExpr) -> Result {
@@ -64,47 +103,159 @@ impl Codegen {
                 // temp is used for pointer type coercing
                 let temp = Ident::generate('f');
                 let pars = args.iter().map(|_| "void*").format(", ");
-                write!(self.text, "void* (*{temp})({pars}) = {func};\n")?;
-                let args = args.iter().map(|arg| format!("(void*){arg}")).format(", ");
-                write!(self.text, "void* {bind} = {temp}({args});\n")?;
+                writeln!(self.text, "void* (*{temp})({pars}) = {func};")?;
+                let args = args
+                    .iter()
+                    .map(|arg| format!("(void*){}", value_arg(arg)))
+                    .format(", ");
+                writeln!(self.text, "void* {bind} = {temp}({args});")?;
                 self.visit_expr(cont)
             }
+            MExpr::TailCall { func, args } => {
+                if !self.tail {
+                    // the result goes to the join point of a branch, or ends `main`
+                    let bind = Ident::generate('r');
+                    let call = MExpr::Call {
+                        bind,
+                        func: *func,
+                        args: args.clone(),
+                        cont: Box::new(MExpr::Retn {
+                            arg1: Atom::Var(bind),
+                        }),
+                    };
+                    return self.visit_expr(&call);
+                }
+                if self.is_self_call(func, args) {
+                    return self.visit_self_jump(args);
+                }
+                let temp = Ident::generate('f');
+                let pars = args.iter().map(|_| "void*").format(", ");
+                writeln!(self.text, "void* (*{temp})({pars}) = {func};")?;
+                let args = args
+                    .iter()
+                    .map(|arg| format!("(void*){}", value_arg(arg)))
+                    .format(", ");
+                writeln!(self.text, "return {temp}({args});")
+            }
             MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             } => {
-                let args = args.iter().map(|arg| format!("(void*){arg}")).format(", ");
-                write!(self.text, "void* {bind} = {func}({args});\n")?;
+                let fixed = args.len() - abi.varargs.as_ref().map_or(0, Vec::len);
+                let varargs = abi.varargs.iter().flatten();
+                let args = args[..fixed]
+                    .iter()
+                    .map(|arg| format!("(void*){}", value_arg(arg)))
+                    .chain(
+                        args[fixed..]
+                            .iter()
+                            .zip(varargs)
+                            .map(|(arg, typ)| ffi_arg(arg, *typ)),
+                    )
+                    .join(", ");
+                let ret = if abi.unit_ret { "void" } else { "void*" };
+                let callee = if *call_conv == CallConv::C {
+                    func.to_string()
+                } else {
+                    // call through a function pointer carrying the calling convention
+                    let temp = Ident::generate('f');
+                    let conv = call_conv_attr(*call_conv);
+                    let pars = (0..fixed)
+                        .map(|_| "void*")
+                        .chain(abi.varargs.as_ref().map(|_| "..."))
+                        .join(", ");
+                    writeln!(
+                        self.text,
+                        "{ret} ({conv} *{temp})({pars}) = ({ret} ({conv} *)({pars})){func};"
+                    )?;
+                    temp.to_string()
+                };
+                if abi.unit_ret {
+                    // a `void` function has no result, bind unit instead
+                    writeln!(self.text, "{callee}({args});")?;
+                    writeln!(self.text, "void* {bind} = (void*)0;")?;
+                } else {
+                    writeln!(self.text, "void* {bind} = {callee}({args});")?;
+                }
                 self.visit_expr(cont)
             }
             MExpr::Retn { arg1 } => {
                 match self.bind_vec.last() {
                     Some(ret_addr) => {
-                        write!(self.text, "{ret_addr} = {arg1};\n")
+                        let arg1 = value_arg(arg1);
+                        writeln!(self.text, "{ret_addr} = {arg1};")
                     }
                     None => {
                         // toplevel
                         if self.is_main {
-                            write!(self.text, "return 0;\n")
+                            writeln!(self.text, "return 0;")
                         } else {
-                            write!(self.text, "return {arg1};\n")
+                            let arg1 = value_arg(arg1);
+                            writeln!(self.text, "return {arg1};")
                         }
                     }
                 }
             }
+            MExpr::Panic { msg } => {
+                let msg = c_escape(msg.as_str());
+                writeln!(self.text, "norem_panic(\"{msg}\");")
+            }
             MExpr::UnOp {
                 bind,
                 prim,
                 arg1,
                 cont,
             } => {
-                let (op, rhs) = match prim {
-                    UnOpPrim::Move => ("", "void*"),
-                    UnOpPrim::INeg => ("-", "int64_t"),
-                };
-                write!(self.text, "void* {bind} = (void*)({op}({rhs})({arg1}));\n")?;
+                match prim {
+                    UnOpPrim::Move | UnOpPrim::INeg | UnOpPrim::BNot => {
+                        let (op, rhs) = match prim {
+                            UnOpPrim::Move => ("", "void*"),
+                            UnOpPrim::INeg => ("-", "int64_t"),
+                            UnOpPrim::BNot => ("!", "int64_t"),
+                            _ => unreachable!(),
+                        };
+                        let arg1 = value_arg(arg1);
+                        // the result of `!` is an `int`
+                        writeln!(
+                            self.text,
+                            "void* {bind} = (void*)(int64_t)({op}({rhs})({arg1}));"
+                        )?;
+                    }
+                    UnOpPrim::Trunc32 | UnOpPrim::SignExt32 | UnOpPrim::ZeroExt32 => {
+                        // truncating and zero-extending are the same on a 64-bit value
+                        let cast = match prim {
+                            UnOpPrim::Trunc32 | UnOpPrim::ZeroExt32 => "uint32_t",
+                            UnOpPrim::SignExt32 => "int32_t",
+                            _ => unreachable!(),
+                        };
+                        let arg1 = value_arg(arg1);
+                        writeln!(
+                            self.text,
+                            "void* {bind} = (void*)(int64_t)({cast})(int64_t)({arg1});"
+                        )?;
+                    }
+                    UnOpPrim::Sinh
+                    | UnOpPrim::Cosh
+                    | UnOpPrim::Tanh
+                    | UnOpPrim::Erf
+                    | UnOpPrim::Erfc => {
+                        // real numbers are passed around as the bits of a `double`
+                        let func = match prim {
+                            UnOpPrim::Sinh => "sinh",
+                            UnOpPrim::Cosh => "cosh",
+                            UnOpPrim::Tanh => "tanh",
+                            UnOpPrim::Erf => "erf",
+                            UnOpPrim::Erfc => "erfc",
+                            _ => unreachable!(),
+                        };
+                        let arg1 = real_arg(arg1);
+                        writeln!(self.text, "void* {bind} = from_real({func}({arg1}));")?;
+                    }
+                }
                 self.visit_expr(cont)
             }
             MExpr::BinOp {
@@ -118,18 +269,65 @@ impl Codegen {
                     BinOpPrim::IAdd => ("int64_t", "+", "int64_t"),
                     BinOpPrim::ISub => ("int64_t", "-", "int64_t"),
                     BinOpPrim::IMul => ("int64_t", "*", "int64_t"),
+                    BinOpPrim::IDiv => ("int64_t", "/", "int64_t"),
+                    BinOpPrim::IRem => ("int64_t", "%", "int64_t"),
+                    BinOpPrim::ICmpEq => ("int64_t", "==", "int64_t"),
+                    BinOpPrim::ICmpNe => ("int64_t", "!=", "int64_t"),
+                    BinOpPrim::ICmpLt => ("int64_t", "<", "int64_t"),
+                    BinOpPrim::ICmpLe => ("int64_t", "<=", "int64_t"),
+                    BinOpPrim::ICmpGt => ("int64_t", ">", "int64_t"),
+                    BinOpPrim::ICmpGe => ("int64_t", ">=", "int64_t"),
+                    BinOpPrim::RAdd | BinOpPrim::RSub | BinOpPrim::RMul | BinOpPrim::RDiv => {
+                        let op = match prim {
+                            BinOpPrim::RAdd => "+",
+                            BinOpPrim::RSub => "-",
+                            BinOpPrim::RMul => "*",
+                            BinOpPrim::RDiv => "/",
+                            _ => unreachable!(),
+                        };
+                        let arg1 = real_arg(arg1);
+                        let arg2 = real_arg(arg2);
+                        writeln!(self.text, "void* {bind} = from_real({arg1} {op} {arg2});")?;
+                        return self.visit_expr(cont);
+                    }
+                    BinOpPrim::Prefetch => {
+                        self.visit_prefetch(arg1, arg2)?;
+                        writeln!(self.text, "void* {bind} = (void*)0;")?;
+                        return self.visit_expr(cont);
+                    }
+                    BinOpPrim::Expect => {
+                        self.expect_map.insert(*bind, *arg2);
+                        writeln!(
+                            self.text,
+                            "void* {bind} = (void*)__builtin_expect((int64_t)({arg1}), (int64_t)({arg2}));"
+                        )?;
+                        return self.visit_expr(cont);
+                    }
+                    BinOpPrim::SimdAdd | BinOpPrim::SimdMul => {
+                        let func = match prim {
+                            BinOpPrim::SimdAdd => "norem_simd_add",
+                            BinOpPrim::SimdMul => "norem_simd_mul",
+                            _ => unreachable!(),
+                        };
+                        writeln!(self.text, "void* {bind} = {func}({arg1}, {arg2});")?;
+                        return self.visit_expr(cont);
+                    }
+                    BinOpPrim::AllocAligned => {
+                        writeln!(
+                            self.text,
+                            "void* {bind} = norem_alloc_aligned({arg1}, {arg2});"
+                        )?;
+                        return self.visit_expr(cont);
+                    }
                 };
-                write!(
+                writeln!(
                     self.text,
-                    "void* {bind} = (void*)(({lhs})({arg1}){op}({rhs})({arg2}));\n"
+                    "void* {bind} = (void*)(int64_t)(({lhs})({arg1}){op}({rhs})({arg2}));"
                 )?;
                 self.visit_expr(cont)
             }
             MExpr::Alloc { bind, size, cont } => {
-                write!(
-                    self.text,
-                    "void* {bind} = malloc({size} * sizeof(void*));\n"
-                )?;
+                writeln!(self.text, "void* {bind} = malloc({size} * sizeof(void*));")?;
                 self.visit_expr(cont)
             }
             MExpr::Store {
@@ -138,7 +336,9 @@ impl Codegen {
                 arg2,
                 cont,
             } => {
-                write!(self.text, "((void**){arg1})[{index}] = (void*)({arg2});\n")?;
+                let arg1 = value_arg(arg1);
+                let arg2 = value_arg(arg2);
+                writeln!(self.text, "((void**){arg1})[{index}] = (void*)({arg2});")?;
                 self.visit_expr(cont)
             }
             MExpr::Load {
@@ -147,7 +347,13 @@ impl Codegen {
                 index,
                 cont,
             } => {
-                write!(self.text, "void* {bind} = ((void**){arg1})[{index}];\n")?;
+                if let Some((_, pars)) = &self.decl {
+                    if *index == 0 && pars.first().map(|c| Atom::Var(*c)) == Some(*arg1) {
+                        self.self_funcs.insert(*bind);
+                    }
+                }
+                let arg1 = value_arg(arg1);
+                writeln!(self.text, "void* {bind} = ((void**){arg1})[{index}];")?;
                 self.visit_expr(cont)
             }
             MExpr::Offset {
@@ -156,7 +362,7 @@ impl Codegen {
                 index,
                 cont,
             } => {
-                write!(self.text, "void* {bind} = &((void**){arg1})[{index}];\n")?;
+                writeln!(self.text, "void* {bind} = &((void**){arg1})[{index}];")?;
                 self.visit_expr(cont)
             }
             MExpr::Ifte {
@@ -164,14 +370,42 @@ impl Codegen {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 self.bind_vec.push(*bind);
-                write!(self.text, "if({arg1})\n{{\n")?;
-                self.visit_expr(brch1)?;
+                let tail = self.enter_brchs(bind, cont);
+                writeln!(self.text, "void* {bind};")?;
+                let (cold1, cold2) = (cold.is_cold(0), cold.is_cold(1));
+                let (first, second) = match arg1 {
+                    // an explicit hint wins over the cold branches
+                    Atom::Var(var) if self.expect_map.contains_key(var) => {
+                        let expect = self.expect_map[var];
+                        write!(
+                            self.text,
+                            "if(__builtin_expect((int64_t)({arg1}), (int64_t)({expect})))\n{{\n"
+                        )?;
+                        (brch1, brch2)
+                    }
+                    // the hot branch comes first, the cold one is laid out after it
+                    _ if cold1 && !cold2 => {
+                        write!(self.text, "if(__builtin_expect(!({arg1}), 1))\n{{\n")?;
+                        (brch2, brch1)
+                    }
+                    _ if cold2 && !cold1 => {
+                        write!(self.text, "if(__builtin_expect(!!({arg1}), 1))\n{{\n")?;
+                        (brch1, brch2)
+                    }
+                    _ => {
+                        write!(self.text, "if({arg1})\n{{\n")?;
+                        (brch1, brch2)
+                    }
+                };
+                self.visit_expr(first)?;
                 write!(self.text, "}}\nelse\n{{\n")?;
-                self.visit_expr(brch2)?;
-                write!(self.text, "}}\n")?;
+                self.visit_expr(second)?;
+                writeln!(self.text, "}}")?;
+                self.tail = tail;
                 self.bind_vec.pop();
                 self.visit_expr(cont)
             }
@@ -180,67 +414,821 @@ impl Codegen {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 self.bind_vec.push(*bind);
-                write!(self.text, "void* {bind};\n")?;
+                let tail = self.enter_brchs(bind, cont);
+                writeln!(self.text, "void* {bind};")?;
                 write!(self.text, "switch((int64_t){arg1})\n{{\n")?;
-                for (i, brch) in brchs.iter() {
-                    write!(self.text, "case {i}:\n")?;
+                // hot cases first, cold cases are laid out after them
+                let (hot, cold_brchs): (Vec<_>, Vec<_>) = brchs
+                    .iter()
+                    .enumerate()
+                    .partition(|(k, _)| !cold.is_cold(*k));
+                for (k, (i, brch)) in hot.into_iter().chain(cold_brchs) {
+                    writeln!(self.text, "case {i}:")?;
+                    if cold.is_cold(k) {
+                        writeln!(self.text, "NOREM_COLD({bind}_cold{k})")?;
+                    }
                     self.visit_expr(brch)?;
-                    write!(self.text, "break;\n")?;
+                    writeln!(self.text, "break;")?;
                 }
                 if let Some(dflt) = dflt {
-                    write!(self.text, "default:\n")?;
+                    writeln!(self.text, "default:")?;
+                    let k = brchs.len();
+                    if cold.is_cold(k) {
+                        writeln!(self.text, "NOREM_COLD({bind}_cold{k})")?;
+                    }
                     self.visit_expr(dflt)?;
+                } else {
+                    // the switch is exhaustive
+                    write!(self.text, "default:\nNOREM_UNREACHABLE();\n")?;
                 }
-                write!(self.text, "}}\n")?;
+                writeln!(self.text, "}}")?;
+                self.tail = tail;
                 self.bind_vec.pop();
                 self.visit_expr(cont)
             }
         }
     }
 
+    /// the branches are in tail position if the join point returns their result,
+    /// returns whether the expression with the branches was
+    fn enter_brchs(&mut self, bind: &Ident, cont: &MExpr) -> bool {
+        let tail = self.tail;
+        self.tail = tail && matches!(cont, MExpr::Retn { arg1: Atom::Var(x) } if x == bind);
+        tail
+    }
+
+    /// a call of the function emitted, through a code pointer loaded from its own closure
+    fn is_self_call(&self, func: &Atom, args: &[Atom]) -> bool {
+        let Some((_, pars)) = &self.decl else {
+            return false;
+        };
+        match func {
+            Atom::Var(func) => {
+                self.self_funcs.contains(func)
+                    && args.len() == pars.len()
+                    && args.first() == pars.first().map(|c| Atom::Var(*c)).as_ref()
+            }
+            _ => false,
+        }
+    }
+
+    /// a self tail call assigns the parameters and jumps back to the start of the function,
+    /// so that a recursion of any depth runs in a single frame
+    fn visit_self_jump(&mut self, args: &[Atom]) -> Result {
+        let (func, pars) = self.decl.clone().unwrap();
+        // every argument is read before any parameter is assigned
+        let mut temps = Vec::new();
+        for (par, arg) in pars.iter().zip(args).skip(1) {
+            if *arg != Atom::Var(*par) {
+                let temp = Ident::generate('a');
+                let arg = value_arg(arg);
+                writeln!(self.text, "void* {temp} = (void*){arg};")?;
+                temps.push((par, temp));
+            }
+        }
+        for (par, temp) in temps {
+            writeln!(self.text, "{par} = {temp};")?;
+        }
+        self.self_jump = true;
+        writeln!(self.text, "goto {func}_entry;")
+    }
+
+    /// the locality hint of `__builtin_prefetch` must be a constant
+    fn visit_prefetch(&mut self, ptr: &Atom, hint: &Atom) -> Result {
+        match hint {
+            Atom::Int(hint) => {
+                let hint = (*hint).clamp(0, 3);
+                writeln!(self.text, "__builtin_prefetch((void*)({ptr}), 0, {hint});")
+            }
+            _ => {
+                write!(self.text, "switch((int64_t){hint})\n{{\n")?;
+                for hint in 0..3 {
+                    writeln!(self.text, "case {hint}:")?;
+                    writeln!(self.text, "__builtin_prefetch((void*)({ptr}), 0, {hint});")?;
+                    writeln!(self.text, "break;")?;
+                }
+                writeln!(self.text, "default:")?;
+                writeln!(self.text, "__builtin_prefetch((void*)({ptr}), 0, 3);")?;
+                writeln!(self.text, "}}")
+            }
+        }
+    }
+
+    fn collect_externs(&mut self, expr: &MExpr) {
+        match expr {
+            MExpr::ExtCall {
+                func,
+                abi,
+                args,
+                cont,
+                ..
+            } => {
+                let fixed = args.len() - abi.varargs.as_ref().map_or(0, Vec::len);
+                let proto = (fixed, abi.varargs.is_some(), abi.unit_ret);
+                self.ext_map.insert(*func, proto);
+                self.collect_externs(cont);
+            }
+            MExpr::LetIn { decls, cont } => {
+                for decl in decls {
+                    self.collect_externs(&decl.body);
+                }
+                self.collect_externs(cont);
+            }
+            MExpr::Ifte {
+                brch1, brch2, cont, ..
+            } => {
+                self.collect_externs(brch1);
+                self.collect_externs(brch2);
+                self.collect_externs(cont);
+            }
+            MExpr::Switch {
+                brchs, dflt, cont, ..
+            } => {
+                for (_, brch) in brchs {
+                    self.collect_externs(brch);
+                }
+                if let Some(dflt) = dflt {
+                    self.collect_externs(dflt);
+                }
+                self.collect_externs(cont);
+            }
+            MExpr::Retn { .. } | MExpr::TailCall { .. } | MExpr::Panic { .. } => {}
+            MExpr::UnOp { cont, .. }
+            | MExpr::BinOp { cont, .. }
+            | MExpr::Call { cont, .. }
+            | MExpr::Alloc { cont, .. }
+            | MExpr::Load { cont, .. }
+            | MExpr::Store { cont, .. }
+            | MExpr::Offset { cont, .. } => self.collect_externs(cont),
+        }
+    }
+
+    /// every string literal is emitted once, so equal literals share an address
+    fn visit_string_table(&mut self, expr: &MExpr) -> Result {
+        let mut strs = BTreeSet::new();
+        collect_strs(expr.clone(), &mut strs);
+        for s in strs {
+            writeln!(
+                self.text,
+                "static const char norem_str_{}[] = \"{}\";",
+                s.index(),
+                c_escape(s.as_str())
+            )?;
+        }
+        Ok(())
+    }
+
+    /// the constants share one page-aligned array, which is made read-only at startup.
+    /// each constant is a macro for its offset, so that it can be used like an array.
+    fn visit_const_table(&mut self, expr: &MExpr) -> Result {
+        let mut consts = BTreeSet::new();
+        collect_consts(expr.clone(), &mut consts);
+        if consts.is_empty() {
+            return Ok(());
+        }
+        self.has_consts = true;
+        let mut fields = Vec::new();
+        for id in consts {
+            writeln!(
+                self.text,
+                "#define norem_const_{} (norem_const_data + {})",
+                id.index(),
+                fields.len()
+            )?;
+            fields.extend(id.fields().iter().map(const_field));
+        }
+        // a whole number of the largest pages, so that no other data is made read-only
+        let size = fields.len().div_ceil(C_PAGE_WORDS) * C_PAGE_WORDS;
+        let fields = fields.iter().format(", ");
+        writeln!(
+            self.text,
+            "static void* norem_const_data[{size}] \
+            __attribute__((aligned({C_PAGE_ALIGN}))) = {{ {fields} }};"
+        )?;
+        self.text.push_str(C_CONST_PROTECT);
+        Ok(())
+    }
+
     fn visit_extern_header(&mut self) -> Result {
-        for (func, arity) in self.ext_map.iter() {
-            let func = func.name;
-            let pars = (0..*arity).map(|i| format!("void* arg{i}")).format(&", ");
-            write!(self.text, "void* {func}({pars});\n")?;
+        for (func, (arity, variadic, unit_ret)) in self.ext_map.iter() {
+            let ret = if *unit_ret { "void" } else { "void*" };
+            let pars = (0..*arity)
+                .map(|i| format!("void* arg{i}"))
+                .chain(variadic.then(|| "...".to_string()))
+                .format(", ");
+            writeln!(self.text, "{ret} {func}({pars});")?;
         }
         Ok(())
     }
 
     fn visit_decl_header(&mut self, decl: &MDecl) -> Result {
         let MDecl { func, pars, .. } = decl;
-        let pars = pars.iter().map(|par| format!("void* {par}")).format(&", ");
-        write!(self.text, "void* {func}({pars});\n")
+        let pars = pars.iter().map(|par| format!("void* {par}")).format(", ");
+        writeln!(self.text, "void* {func}({pars});")
     }
 
     fn visit_decl(&mut self, decl: &MDecl) -> Result {
         let MDecl { func, pars, body } = decl;
-        let pars = pars.iter().map(|par| format!("void* {par}")).format(&", ");
+        let pars = pars.iter().map(|par| format!("void* {par}")).format(", ");
+        if self.nounroll.contains(&func.name) {
+            self.text.push_str(C_NOUNROLL);
+        }
         write!(self.text, "void* {func}({pars})\n{{\n")?;
         assert!(self.bind_vec.is_empty());
+        let entry = self.text.len();
+        self.decl = Some((*func, decl.pars.clone()));
+        self.tail = true;
         self.visit_expr(body)?;
+        if self.self_jump {
+            self.text.insert_str(entry, &format!("{func}_entry:;\n"));
+        }
+        self.decl = None;
+        self.tail = false;
+        self.self_funcs.clear();
+        self.self_jump = false;
         self.bind_vec.clear();
-        write!(self.text, "}}\n")
+        writeln!(self.text, "}}")
+    }
+}
+
+fn real_arg(arg: &Atom) -> String {
+    match arg {
+        Atom::Real(x) => real_lit(*x),
+        other => format!("to_real({other})"),
+    }
+}
+
+/// a C expression of type `double` with exactly the value `x`, including the sign of zeros.
+/// NaN keeps its sign but not its payload, which is never set by the arithmetic primitives.
+fn real_lit(x: f64) -> String {
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    if x.is_nan() {
+        format!("({sign}NAN)")
+    } else if x.is_infinite() {
+        format!("({sign}INFINITY)")
+    } else {
+        // `Debug` prints the shortest representation that reads back to the same bits
+        format!("{x:?}")
+    }
+}
+
+fn collect_strs(expr: MExpr, strs: &mut BTreeSet<InternStr>) -> MExpr {
+    expr.walk_arg(|arg| {
+        collect_atom_strs(arg, strs);
+        arg
+    })
+    .walk_decl(|decl| decl.walk_body(|body| collect_strs(body, strs)))
+    .walk_brch(|brch| collect_strs(brch, strs))
+    .walk_cont(|cont| collect_strs(cont, strs))
+}
+
+/// the strings used by an atom, including the fields of a constant
+fn collect_atom_strs(arg: Atom, strs: &mut BTreeSet<InternStr>) {
+    match arg {
+        Atom::Str(s) => {
+            strs.insert(s);
+        }
+        Atom::Const(id) => {
+            for field in id.fields() {
+                collect_atom_strs(field, strs);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// the constants used by an expression, and the constants in their fields.
+/// a constant is always made after its fields, so they come first in the set.
+fn collect_consts(expr: MExpr, consts: &mut BTreeSet<ConstId>) -> MExpr {
+    fn visit_atom(arg: Atom, consts: &mut BTreeSet<ConstId>) {
+        if let Atom::Const(id) = arg {
+            if consts.insert(id) {
+                for field in id.fields() {
+                    visit_atom(field, consts);
+                }
+            }
+        }
+    }
+    expr.walk_arg(|arg| {
+        visit_atom(arg, consts);
+        arg
+    })
+    .walk_decl(|decl| decl.walk_body(|body| collect_consts(body, consts)))
+    .walk_brch(|brch| collect_consts(brch, consts))
+    .walk_cont(|cont| collect_consts(cont, consts))
+}
+
+/// a field of a constant, as a constant expression of C
+fn const_field(arg: &Atom) -> String {
+    match arg {
+        Atom::Int(x) => format!("(void*){:#x}", *x as u64),
+        Atom::Real(x) => format!("(void*){:#x}", x.to_bits()),
+        Atom::Bool(x) => format!("(void*){}", *x as i32),
+        Atom::Char(x) => format!("(void*){}", *x as u32),
+        Atom::Str(x) => format!("(void*)norem_str_{}", x.index()),
+        Atom::Unit => "(void*)0".to_string(),
+        Atom::Const(x) => format!("(void*)norem_const_{}", x.index()),
+        Atom::Var(x) => unreachable!("a constant can't contain the variable {x}"),
+    }
+}
+
+/// the contents of a C string literal, bytes outside printable ASCII are written in octal,
+/// so no escape sequence can run into the following character
+fn c_escape(s: &str) -> String {
+    let mut res = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b'"' => res.push_str("\\\""),
+            b'\\' => res.push_str("\\\\"),
+            b' '..=b'~' => res.push(byte as char),
+            _ => res.push_str(&format!("\\{byte:03o}")),
+        }
+    }
+    res
+}
+
+/// a variadic argument passed unboxed, C promotes `bool` and `char32_t` to `int`
+fn ffi_arg(arg: &Atom, typ: FfiType) -> String {
+    match (arg, typ) {
+        (arg, FfiType::Real) => real_arg(arg),
+        (Atom::Var(x), FfiType::Int) => format!("(int64_t){x}"),
+        (Atom::Var(x), FfiType::Bool | FfiType::Char) => format!("(int)(int64_t){x}"),
+        (Atom::Var(x), FfiType::Ptr) => format!("(void*){x}"),
+        (Atom::Int(x), _) => format!("(int64_t){x}"),
+        (Atom::Bool(x), _) => format!("{}", *x as i32),
+        (Atom::Char(x), _) => format!("{}", *x as u32),
+        (Atom::Str(x), FfiType::Ptr) => format!("(void*)norem_str_{}", x.index()),
+        (Atom::Const(x), FfiType::Ptr) => format!("(void*)norem_const_{}", x.index()),
+        (arg, typ) => unreachable!("{arg} can't be passed as {typ:?}"),
     }
 }
 
-pub static C_PROLOGUE: &'static str = r#"
+/// an atom used as a value, reals are passed around as their bits
+fn value_arg(arg: &Atom) -> String {
+    match arg {
+        Atom::Real(x) => format!("from_real({})", real_lit(*x)),
+        Atom::Str(x) => format!("(void*)norem_str_{}", x.index()),
+        Atom::Const(x) => format!("(void*)norem_const_{}", x.index()),
+        Atom::Char(x) => format!("(void*){}", *x as u32),
+        Atom::Unit => "(void*)0".to_string(),
+        other => format!("{other}"),
+    }
+}
+
+fn call_conv_attr(conv: CallConv) -> &'static str {
+    match conv {
+        CallConv::C => "",
+        CallConv::Stdcall => "__attribute__((stdcall))",
+        CallConv::Fastcall => "__attribute__((fastcall))",
+        CallConv::Vectorcall => "__attribute__((vectorcall))",
+        CallConv::Aapcs => "__attribute__((pcs(\"aapcs\")))",
+    }
+}
+
+pub static C_PROLOGUE: &str = r#"
 #include <stdio.h>
+#include <stdarg.h>
 #include <stdlib.h>
 #include <stdint.h>
 #include <stdbool.h>
+#include <string.h>
+#include <math.h>
+#include <ucontext.h>
+#include <pthread.h>
+#include <semaphore.h>
+#include <errno.h>
+#include <limits.h>
+#include <dirent.h>
+#include <signal.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+static inline double to_real(void* x) { double r; memcpy(&r, &x, sizeof(double)); return r; }
+static inline void* from_real(double x) { void* r; memcpy(&r, &x, sizeof(double)); return r; }
+
+#ifdef __OPTIMIZE__
+#define NOREM_UNREACHABLE() __builtin_unreachable()
+#else
+#define NOREM_UNREACHABLE() __builtin_trap()
+#endif
+
+// code after a cold label is moved out of the hot path
+#define NOREM_COLD(label) label: __attribute__((cold, unused));
+
+__attribute__((cold, noreturn)) static void norem_panic(const char* msg) { puts(msg); exit(1); }
+
+// `aligned_alloc` wants the size to be a multiple of the alignment
+static inline void* norem_alloc_aligned(void* size, void* align) {
+    size_t s = (size_t)size, a = (size_t)align;
+    return aligned_alloc(a, (s + a - 1) / a * a);
+}
+
+// coroutines run a closure on their own stack, switching with `swapcontext`.
+// `value` carries the argument of `coroutine_resume` in and the argument of `@yield` out
+typedef struct norem_coroutine {
+    ucontext_t ctx, caller;
+    void** func;
+    void* value;
+    void* result;
+    bool done;
+    struct norem_coroutine* prev;
+} norem_coroutine;
+#define NOREM_STACK_SIZE (256 * 1024)
+// every thread runs its own coroutines
+static _Thread_local norem_coroutine* norem_running = NULL;
+static void norem_coroutine_entry() {
+    norem_coroutine* co = norem_running;
+    co->result = ((void* (*)(void*, void*))co->func[0])(co->func, co->value);
+    co->done = true;
+}
+void* coroutine_new(void* func) {
+    norem_coroutine* co = calloc(1, sizeof(norem_coroutine));
+    co->func = func;
+    getcontext(&co->ctx);
+    co->ctx.uc_stack.ss_sp = malloc(NOREM_STACK_SIZE);
+    co->ctx.uc_stack.ss_size = NOREM_STACK_SIZE;
+    co->ctx.uc_link = &co->caller;
+    makecontext(&co->ctx, norem_coroutine_entry, 0);
+    return co;
+}
+// the value yielded, or the value resumed with once the function has returned
+void* coroutine_resume(void* coro, void* value) {
+    norem_coroutine* co = coro;
+    if (co->done) { puts("resumed a finished coroutine!"); exit(1); }
+    co->value = value;
+    co->prev = norem_running;
+    norem_running = co;
+    swapcontext(&co->caller, &co->ctx);
+    norem_running = co->prev;
+    if (co->done) free(co->ctx.uc_stack.ss_sp);
+    return co->value;
+}
+void* coroutine_done(void* coro) { return (void*)(int64_t)((norem_coroutine*)coro)->done; }
+void* coroutine_result(void* coro) { return ((norem_coroutine*)coro)->result; }
+static void* norem_yield(void* value) {
+    norem_coroutine* co = norem_running;
+    if (co == NULL) { puts("yield outside of a coroutine!"); exit(1); }
+    co->value = value;
+    swapcontext(&co->ctx, &co->caller);
+    return co->value;
+}
+
+// a thread runs a closure without arguments, `norem_join` takes its result
+typedef struct norem_thread {
+    pthread_t id;
+    void** func;
+    void* result;
+} norem_thread;
+static void* norem_thread_entry(void* arg) {
+    norem_thread* th = arg;
+    th->result = ((void* (*)(void*))th->func[0])(th->func);
+    return NULL;
+}
+// a stack size of 0 keeps the default of the system
+static void* norem_spawn(void* func, void* stack_size) {
+    norem_thread* th = malloc(sizeof(norem_thread));
+    th->func = func;
+    pthread_attr_t attr;
+    pthread_attr_init(&attr);
+    int64_t size = (int64_t)stack_size;
+    if (size < 0) { puts("negative stack size!"); exit(1); }
+    if (size > 0) {
+        // some systems only take multiples of the page size
+        size = (size + 65535) / 65536 * 65536;
+        if (size < PTHREAD_STACK_MIN) size = PTHREAD_STACK_MIN;
+        pthread_attr_setstacksize(&attr, size);
+    }
+    if (pthread_create(&th->id, &attr, norem_thread_entry, th) != 0) {
+        puts("failed to spawn a thread!");
+        exit(1);
+    }
+    pthread_attr_destroy(&attr);
+    return th;
+}
+static void* norem_join(void* thread) {
+    norem_thread* th = thread;
+    if (pthread_join(th->id, NULL) != 0) { puts("failed to join a thread!"); exit(1); }
+    void* result = th->result;
+    free(th);
+    return result;
+}
+
+// a bounded queue for any number of senders and receivers
+typedef struct norem_chan {
+    pthread_mutex_t lock;
+    pthread_cond_t not_empty;
+    pthread_cond_t not_full;
+    void** buf;
+    int64_t cap;
+    int64_t head;
+    int64_t len;
+    bool closed;
+} norem_chan;
+static void* norem_chan_new(void* capacity) {
+    int64_t cap = (int64_t)capacity;
+    if (cap <= 0) { puts("channel capacity must be positive!"); exit(1); }
+    norem_chan* ch = malloc(sizeof(norem_chan));
+    pthread_mutex_init(&ch->lock, NULL);
+    pthread_cond_init(&ch->not_empty, NULL);
+    pthread_cond_init(&ch->not_full, NULL);
+    ch->buf = malloc(cap * sizeof(void*));
+    ch->cap = cap;
+    ch->head = 0;
+    ch->len = 0;
+    ch->closed = false;
+    return ch;
+}
+static void* norem_chan_send(void* chan, void* value) {
+    norem_chan* ch = chan;
+    pthread_mutex_lock(&ch->lock);
+    while (ch->len == ch->cap && !ch->closed) pthread_cond_wait(&ch->not_full, &ch->lock);
+    if (ch->closed) { puts("sent to a closed channel!"); exit(1); }
+    ch->buf[(ch->head + ch->len) % ch->cap] = value;
+    ch->len++;
+    pthread_cond_signal(&ch->not_empty);
+    pthread_mutex_unlock(&ch->lock);
+    return NULL;
+}
+// `none` is the empty option, `some` the closure wrapping a value into one
+static void* norem_chan_recv(void* chan, void* none, void* some) {
+    norem_chan* ch = chan;
+    pthread_mutex_lock(&ch->lock);
+    while (ch->len == 0 && !ch->closed) pthread_cond_wait(&ch->not_empty, &ch->lock);
+    if (ch->len == 0) {
+        pthread_mutex_unlock(&ch->lock);
+        return none;
+    }
+    void* value = ch->buf[ch->head];
+    ch->head = (ch->head + 1) % ch->cap;
+    ch->len--;
+    pthread_cond_signal(&ch->not_full);
+    pthread_mutex_unlock(&ch->lock);
+    return ((void* (*)(void*, void*))((void**)some)[0])(some, value);
+}
+static void* norem_chan_close(void* chan) {
+    norem_chan* ch = chan;
+    pthread_mutex_lock(&ch->lock);
+    ch->closed = true;
+    pthread_cond_broadcast(&ch->not_empty);
+    pthread_cond_broadcast(&ch->not_full);
+    pthread_mutex_unlock(&ch->lock);
+    return NULL;
+}
+
+static void* norem_mutex_new() {
+    pthread_mutex_t* lock = malloc(sizeof(pthread_mutex_t));
+    pthread_mutex_init(lock, NULL);
+    return lock;
+}
+static void* norem_mutex_lock(void* mutex) {
+    if (pthread_mutex_lock(mutex) != 0) { puts("failed to lock a mutex!"); exit(1); }
+    return NULL;
+}
+static void* norem_mutex_unlock(void* mutex) {
+    if (pthread_mutex_unlock(mutex) != 0) { puts("failed to unlock a mutex!"); exit(1); }
+    return NULL;
+}
+static void norem_mutex_cleanup(pthread_mutex_t** lock) { pthread_mutex_unlock(*lock); }
+// the cleanup unlocks the mutex however the scope is left, also when the thread is unwound
+static void* norem_with_mutex(void* mutex, void* func) {
+    norem_mutex_lock(mutex);
+    __attribute__((cleanup(norem_mutex_cleanup))) pthread_mutex_t* guard = mutex;
+    return ((void* (*)(void*))((void**)func)[0])(func);
+}
+
+static void* norem_sem_new(void* count) {
+    sem_t* sem = malloc(sizeof(sem_t));
+    if (sem_init(sem, 0, (unsigned)(int64_t)count) != 0) { puts("failed to make a semaphore!"); exit(1); }
+    return sem;
+}
+static void* norem_sem_post(void* sem) {
+    if (sem_post(sem) != 0) { puts("failed to post a semaphore!"); exit(1); }
+    return NULL;
+}
+// a signal may interrupt the wait, which is then just retried
+static void* norem_sem_wait(void* sem) {
+    while (sem_wait(sem) != 0) {
+        if (errno != EINTR) { puts("failed to wait on a semaphore!"); exit(1); }
+    }
+    return NULL;
+}
+static void* norem_sem_trywait(void* sem) {
+    while (sem_trywait(sem) != 0) {
+        if (errno == EAGAIN) return (void*)0;
+        if (errno != EINTR) { puts("failed to wait on a semaphore!"); exit(1); }
+    }
+    return (void*)1;
+}
+
+// `ok` and `err` are the closures wrapping a value into a result
+static void* norem_file_error(void* err) {
+    const char* msg = strerror(errno);
+    char* copy = malloc(strlen(msg) + 1);
+    strcpy(copy, msg);
+    return ((void* (*)(void*, void*))((void**)err)[0])(err, copy);
+}
+static void* norem_read_file(void* path, void* ok, void* err) {
+    FILE* file = fopen(path, "rb");
+    if (file == NULL) return norem_file_error(err);
+    size_t cap = 4096;
+    size_t len = 0;
+    char* buf = malloc(cap);
+    size_t got;
+    while ((got = fread(buf + len, 1, cap - len - 1, file)) > 0) {
+        len += got;
+        if (cap - len == 1) {
+            cap *= 2;
+            buf = realloc(buf, cap);
+        }
+    }
+    if (ferror(file)) {
+        int saved = errno;
+        free(buf);
+        fclose(file);
+        errno = saved;
+        return norem_file_error(err);
+    }
+    fclose(file);
+    buf[len] = '\0';
+    return ((void* (*)(void*, void*))((void**)ok)[0])(ok, buf);
+}
+static void* norem_write_file(void* path, void* text, void* ok, void* err) {
+    FILE* file = fopen(path, "wb");
+    if (file == NULL) return norem_file_error(err);
+    size_t len = strlen(text);
+    if (fwrite(text, 1, len, file) != len) {
+        int saved = errno;
+        fclose(file);
+        errno = saved;
+        return norem_file_error(err);
+    }
+    if (fclose(file) != 0) return norem_file_error(err);
+    return ((void* (*)(void*, void*))((void**)ok)[0])(ok, (void*)0);
+}
+static int norem_compare_names(const void* a, const void* b) {
+    return strcmp(*(char* const*)a, *(char* const*)b);
+}
+// `nil` is the empty list and `cons` the closure putting a name in front of a list,
+// the names are sorted since `readdir` gives them in no particular order
+static void* norem_list_dir(void* path, void* ok, void* err, void* nil, void* cons) {
+    DIR* dir = opendir(path);
+    if (dir == NULL) return norem_file_error(err);
+    size_t cap = 16;
+    size_t len = 0;
+    char** names = malloc(cap * sizeof(char*));
+    struct dirent* entry;
+    errno = 0;
+    while ((entry = readdir(dir)) != NULL) {
+        if (strcmp(entry->d_name, ".") == 0 || strcmp(entry->d_name, "..") == 0) continue;
+        if (len == cap) {
+            cap *= 2;
+            names = realloc(names, cap * sizeof(char*));
+        }
+        names[len] = malloc(strlen(entry->d_name) + 1);
+        strcpy(names[len], entry->d_name);
+        len++;
+    }
+    if (errno != 0) {
+        int saved = errno;
+        closedir(dir);
+        errno = saved;
+        return norem_file_error(err);
+    }
+    closedir(dir);
+    qsort(names, len, sizeof(char*), norem_compare_names);
+    void* list = nil;
+    for (size_t i = len; i > 0; i--) {
+        list = ((void* (*)(void*, void*, void*))((void**)cons)[0])(cons, names[i - 1], list);
+    }
+    free(names);
+    return ((void* (*)(void*, void*))((void**)ok)[0])(ok, list);
+}
+// paths are split on `/` like `basename` and `dirname` do, but the argument is never modified
+static char* norem_path_copy(const char* path, size_t len) {
+    char* res = malloc(len + 1);
+    memcpy(res, path, len);
+    res[len] = '\0';
+    return res;
+}
+static size_t norem_path_trim(const char* path, size_t len) {
+    while (len > 1 && path[len - 1] == '/') len--;
+    return len;
+}
+static void* norem_path_join(void* dir, void* path) {
+    size_t len1 = strlen(dir);
+    size_t len2 = strlen(path);
+    if (len1 == 0 || ((char*)path)[0] == '/') return norem_path_copy(path, len2);
+    if (len2 == 0) return norem_path_copy(dir, len1);
+    int slash = ((char*)dir)[len1 - 1] != '/';
+    char* res = malloc(len1 + slash + len2 + 1);
+    memcpy(res, dir, len1);
+    if (slash) res[len1] = '/';
+    memcpy(res + len1 + slash, path, len2 + 1);
+    return res;
+}
+static void* norem_path_base(void* path) {
+    size_t len = norem_path_trim(path, strlen(path));
+    if (len == 0) return norem_path_copy(".", 1);
+    size_t start = len;
+    while (start > 0 && ((char*)path)[start - 1] != '/') start--;
+    if (start == len) return norem_path_copy("/", 1);
+    return norem_path_copy((char*)path + start, len - start);
+}
+static void* norem_path_dir(void* path) {
+    size_t len = norem_path_trim(path, strlen(path));
+    while (len > 0 && ((char*)path)[len - 1] != '/') len--;
+    if (len == 0) return norem_path_copy(".", 1);
+    return norem_path_copy(path, norem_path_trim(path, len));
+}
+static void* norem_format(void* fmt, ...) {
+    va_list args;
+    va_start(args, fmt);
+    int len = vsnprintf(NULL, 0, fmt, args);
+    va_end(args);
+    char* res = malloc(len + 1);
+    va_start(args, fmt);
+    vsnprintf(res, len + 1, fmt, args);
+    va_end(args);
+    return res;
+}
+
+// a `Simd[Real, 2]` is a record of two doubles, just like a tuple `(Real, Real)`
+#ifdef __SSE2__
+#include <emmintrin.h>
+static inline void* norem_simd_add(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    _mm_storeu_pd((double*)r, _mm_add_pd(_mm_loadu_pd((double*)a), _mm_loadu_pd((double*)b)));
+    return r;
+}
+static inline void* norem_simd_mul(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    _mm_storeu_pd((double*)r, _mm_mul_pd(_mm_loadu_pd((double*)a), _mm_loadu_pd((double*)b)));
+    return r;
+}
+#else
+static inline void* norem_simd_add(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    for(int i = 0; i < 2; i++) r[i] = from_real(to_real(((void**)a)[i]) + to_real(((void**)b)[i]));
+    return r;
+}
+static inline void* norem_simd_mul(void* a, void* b) {
+    void** r = malloc(2 * sizeof(void*));
+    for(int i = 0; i < 2; i++) r[i] = from_real(to_real(((void**)a)[i]) * to_real(((void**)b)[i]));
+    return r;
+}
+#endif
+"#;
+
+/// the alignment of constant data in bytes, the largest page size it can be protected with.
+/// pages are 4 KiB on most systems, but 16 KiB or 64 KiB on many aarch64 ones
+const C_PAGE_ALIGN: usize = 65536;
+
+/// the number of fields in `C_PAGE_ALIGN` bytes of constant data
+const C_PAGE_WORDS: usize = C_PAGE_ALIGN / 8;
+
+/// writing to constant data faults, which is reported instead of crashing,
+/// any other fault crashes as usual once the handler returns.
+/// the handler only calls async-signal-safe functions.
+/// with larger pages than the alignment, the data is left writable with a warning
+pub static C_CONST_PROTECT: &str = r#"static void norem_const_trap(int sig, siginfo_t* info, void* ctx) {
+    void** addr = info->si_addr;
+    size_t len = sizeof(norem_const_data) / sizeof(void*);
+    if (addr >= norem_const_data && addr < norem_const_data + len) {
+        static const char msg[] = "mutation of constant data!\n";
+        write(2, msg, sizeof(msg) - 1);
+        _exit(1);
+    }
+    signal(sig, SIG_DFL);
+}
+static void norem_const_protect(void) {
+    long page = sysconf(_SC_PAGESIZE);
+    size_t size = page > 0 ? sizeof(norem_const_data) / page * page : 0;
+    if (size == 0 || (uintptr_t)norem_const_data % page != 0
+        || mprotect(norem_const_data, size, PROT_READ) != 0) {
+        fputs("warning: constant data is not protected\n", stderr);
+        return;
+    }
+    struct sigaction act = {0};
+    act.sa_sigaction = norem_const_trap;
+    act.sa_flags = SA_SIGINFO;
+    sigaction(SIGSEGV, &act, NULL);
+    sigaction(SIGBUS, &act, NULL);
+}
 "#;
 
-pub static C_EPILOGUE: &'static str = r#"/*
+pub static C_EPILOGUE: &str = r#"/*
 this file is generated by norem compiler,
 reading and editing are not recommanded.
 */
 "#;
 
-pub static C_SYS_CHECK: &'static str = r#"if(sizeof(void*) != 8)
+pub static C_NOUNROLL: &str = "__attribute__((optimize(\"no-unroll-loops\")))\n";
+
+pub static C_SYS_CHECK: &str = r#"if(sizeof(void*) != 8)
 {
 puts("check failed: 'void*' is not 64-bits!");
 exit(1);
@@ -273,3 +1261,114 @@ fn codegen_test() {
     println!("{text1}");
     // todo: more tests
 }
+
+#[test]
+fn codegen_nounroll_test() {
+    use super::anf_build::*;
+    let decl1 = fun("f1", vec!["x1"], retn(v("x1")));
+    let decl2 = fun("f2", vec!["x2"], retn(v("x2")));
+    let nounroll = HashSet::from([decl1.func.name]);
+    let expr1 = MExpr::LetIn {
+        decls: vec![decl1, decl2],
+        cont: Box::new(retn(i(0))),
+    };
+    let text1 = Codegen::run_with_nounroll(&expr1, nounroll);
+    assert_eq!(text1.matches(C_NOUNROLL).count(), 1);
+    let idx = text1.find(C_NOUNROLL).unwrap();
+    assert!(text1[idx + C_NOUNROLL.len()..].starts_with("void* f1"));
+}
+
+#[test]
+fn codegen_prefetch_test() {
+    use super::anf_build::*;
+    use super::simple_opt::DeadElim;
+    let expr1 = chain(vec![
+        binop("u1", BinOpPrim::Prefetch, v("x"), i(7)),
+        binop("u2", BinOpPrim::Prefetch, v("x"), v("h")),
+        retn(i(0)),
+    ]);
+    // prefetching is kept even if the result is unused
+    let expr1 = DeadElim::run(expr1);
+    let text1 = Codegen::run(&expr1);
+    assert!(text1.contains("__builtin_prefetch((void*)(x), 0, 3);\nvoid* u1 = (void*)0;"));
+    assert!(text1.contains("switch((int64_t)h)"));
+    assert_eq!(text1.matches("__builtin_prefetch(").count(), 5);
+}
+
+#[test]
+fn codegen_expect_test() {
+    use super::anf_build::*;
+    let expr1 = chain(vec![
+        binop("c", BinOpPrim::Expect, v("x"), b(false)),
+        ifte("r", v("c"), retn(i(1)), retn(i(0))),
+        retn(v("r")),
+    ]);
+    let text1 = Codegen::run(&expr1);
+    assert!(text1.contains("void* c = (void*)__builtin_expect((int64_t)(x), (int64_t)(false));"));
+    assert!(text1.contains("if(__builtin_expect((int64_t)(c), (int64_t)(false)))"));
+}
+
+#[test]
+fn codegen_real_special_test() {
+    use super::anf_build::*;
+    let expr1 = chain(vec![
+        binop("x", BinOpPrim::RDiv, r(f64::NEG_INFINITY), r(-0.0)),
+        binop("y", BinOpPrim::RAdd, v("x"), r(f64::NAN)),
+        ifte("w", v("y"), retn(r(1e-300)), retn(v("y"))),
+        retn(v("w")),
+    ]);
+    let text1 = Codegen::run(&expr1);
+    assert!(text1.contains("void* x = from_real((-INFINITY) / -0.0);"));
+    assert!(text1.contains("void* y = from_real(to_real(x) + (NAN));"));
+    assert!(text1.contains("w = from_real(1e-300);"));
+}
+
+#[test]
+fn codegen_simd_test() {
+    use super::anf_build::*;
+    let expr1 = chain(vec![
+        binop("z", BinOpPrim::SimdAdd, v("x"), v("y")),
+        binop("w", BinOpPrim::SimdMul, v("z"), v("y")),
+        retn(v("w")),
+    ]);
+    let text1 = Codegen::run(&expr1);
+    assert!(text1.contains("void* z = norem_simd_add(x, y);"));
+    assert!(text1.contains("void* w = norem_simd_mul(z, y);"));
+}
+
+#[test]
+fn codegen_int_width_test() {
+    use super::anf_build::*;
+    let expr1 = chain(vec![
+        unop("x", UnOpPrim::Trunc32, v("a")),
+        unop("y", UnOpPrim::SignExt32, v("x")),
+        unop("z", UnOpPrim::ZeroExt32, v("y")),
+        retn(v("z")),
+    ]);
+    let text1 = Codegen::run(&expr1);
+    assert!(text1.contains("void* x = (void*)(int64_t)(uint32_t)(int64_t)(a);"));
+    assert!(text1.contains("void* y = (void*)(int64_t)(int32_t)(int64_t)(x);"));
+    assert!(text1.contains("void* z = (void*)(int64_t)(uint32_t)(int64_t)(y);"));
+}
+
+#[test]
+fn codegen_cold_branch_test() {
+    use super::anf_build::*;
+    // the cold branch of an `Ifte` is laid out after the hot one
+    let mut expr1 = ifte("r", v("x"), retn(i(1)), retn(i(0)));
+    if let MExpr::Ifte { cold, .. } = &mut expr1 {
+        *cold = Cold(BTreeSet::from([0]));
+    }
+    let text1 = Codegen::run(&expr1);
+    assert!(text1.contains("if(__builtin_expect(!(x), 1))\n{\nr = 0;\n}\nelse\n{\nr = 1;\n}"));
+
+    // and the cold cases of a `Switch` after the hot ones
+    let mut expr2 = switch("r", v("x"), vec![(0, retn(i(1))), (1, retn(i(2)))], None);
+    if let MExpr::Switch { cold, .. } = &mut expr2 {
+        *cold = Cold(BTreeSet::from([0]));
+    }
+    let text2 = Codegen::run(&expr2);
+    let hot = text2.find("case 1:").unwrap();
+    let cold = text2.find("case 0:\nNOREM_COLD(r_cold0)").unwrap();
+    assert!(hot < cold);
+}
@@ -0,0 +1,162 @@
+use super::*;
+use crate::backend::simple_opt::PassCtx;
+use crate::frontend::ast::OptLevel;
+use std::collections::HashMap;
+
+/// Remove the `let x = move(y)` bindings, substituting `y` for `x` in what follows.
+/// A move copies the value of `y`, and a pointer copied this way still points to the
+/// same memory, so a `store` through either name is seen through both and the
+/// substitution is safe across stores.
+pub fn copy_propagate(expr: MExpr) -> MExpr {
+    copy_propagate_with_ctx(expr, &PassCtx::default())
+}
+
+/// Same as `copy_propagate`, the moves in `@optimize(never)` functions are kept
+pub fn copy_propagate_with_ctx(expr: MExpr, ctx: &PassCtx) -> MExpr {
+    let mut pass = CopyProp {
+        copies: HashMap::new(),
+        ctx,
+    };
+    pass.visit_expr(expr)
+}
+
+struct CopyProp<'a> {
+    /// the variables bound by the moves visited so far, and what they are copies of
+    copies: HashMap<Ident, Atom>,
+    ctx: &'a PassCtx,
+}
+
+impl CopyProp<'_> {
+    fn visit_arg(&self, arg: Atom) -> Atom {
+        match arg {
+            // a chain of moves was already resolved when its moves were recorded
+            Atom::Var(var) => self.copies.get(&var).copied().unwrap_or(arg),
+            arg => arg,
+        }
+    }
+
+    /// a branch or a function body only sees the moves in scope where it is
+    fn visit_scoped(&mut self, expr: MExpr, frozen: bool) -> MExpr {
+        let copies = self.copies.clone();
+        let expr = if frozen {
+            self.visit_frozen(expr)
+        } else {
+            self.visit_expr(expr)
+        };
+        self.copies = copies;
+        expr
+    }
+
+    /// substitute the moves from outside a frozen body, without removing its own
+    fn visit_frozen(&mut self, expr: MExpr) -> MExpr {
+        expr.walk_arg(|arg| self.visit_arg(arg))
+            .walk_brch(|brch| self.visit_scoped(brch, true))
+            .walk_decl(|decl| decl.walk_body(|body| self.visit_scoped(body, true)))
+            .walk_cont(|cont| self.visit_frozen(cont))
+    }
+
+    fn visit_expr(&mut self, expr: MExpr) -> MExpr {
+        let expr = expr.walk_arg(|arg| self.visit_arg(arg));
+        if let MExpr::UnOp {
+            bind,
+            prim: UnOpPrim::Move,
+            arg1,
+            cont,
+        } = expr
+        {
+            self.copies.insert(bind, arg1);
+            return self.visit_expr(*cont);
+        }
+        expr.walk_decl(|decl| {
+            let frozen = self.ctx.opt_level(&decl.func) == Some(OptLevel::Never);
+            decl.walk_body(|body| self.visit_scoped(body, frozen))
+        })
+        .walk_brch(|brch| self.visit_scoped(brch, false))
+        .walk_cont(|cont| self.visit_expr(cont))
+    }
+}
+
+#[test]
+fn copy_prop_test() {
+    use super::anf_build::*;
+    // a chain of moves is resolved to the original variable
+    let expr = chain(vec![
+        _move("x", v("a")),
+        _move("y", v("x")),
+        _move("z", v("y")),
+        iadd("r", v("z"), v("x")),
+        retn(v("r")),
+    ]);
+    let expr = copy_propagate(expr);
+    let expected = chain(vec![iadd("r", v("a"), v("a")), retn(v("r"))]);
+    assert_eq!(format!("{expr}"), format!("{expected}"));
+
+    // a move before a branch is seen inside it, a move inside a branch stays there
+    let expr = chain(vec![
+        _move("x", v("a")),
+        ifte(
+            "r",
+            v("x"),
+            chain(vec![_move("y", v("x")), retn(v("y"))]),
+            chain(vec![_move("y", i(1)), retn(v("y"))]),
+        ),
+        iadd("s", v("r"), v("x")),
+        retn(v("s")),
+    ]);
+    let expr = copy_propagate(expr);
+    let expected = chain(vec![
+        ifte("r", v("a"), retn(v("a")), retn(i(1))),
+        iadd("s", v("r"), v("a")),
+        retn(v("s")),
+    ]);
+    assert_eq!(format!("{expr}"), format!("{expected}"));
+
+    // a store through a copy of a pointer writes the same memory, so it is kept
+    // and the loads after it read from the original pointer
+    let expr = chain(vec![
+        alloc("p", 1),
+        _move("q", v("p")),
+        store(v("q"), 0, i(1)),
+        load("x", v("p"), 0),
+        load("y", v("q"), 0),
+        iadd("r", v("x"), v("y")),
+        retn(v("r")),
+    ]);
+    let expr = copy_propagate(expr);
+    let expected = chain(vec![
+        alloc("p", 1),
+        store(v("p"), 0, i(1)),
+        load("x", v("p"), 0),
+        load("y", v("p"), 0),
+        iadd("r", v("x"), v("y")),
+        retn(v("r")),
+    ]);
+    assert_eq!(format!("{expr}"), format!("{expected}"));
+
+    // moves into a function body are substituted, the function's own are removed too
+    let expr = chain(vec![
+        _move("x", v("a")),
+        let_in(
+            vec![fun(
+                "f",
+                vec!["p"],
+                chain(vec![
+                    _move("q", v("p")),
+                    iadd("r", v("q"), v("x")),
+                    retn(v("r")),
+                ]),
+            )],
+            vec![retn(v("f"))],
+        ),
+    ]);
+    let expr = copy_propagate(expr);
+    let expected = let_in(
+        vec![fun(
+            "f",
+            vec!["p"],
+            chain(vec![iadd("r", v("p"), v("a")), retn(v("r"))]),
+        )],
+        vec![retn(v("f"))],
+    );
+    assert_eq!(format!("{expr}"), format!("{expected}"));
+}
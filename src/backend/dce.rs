@@ -0,0 +1,229 @@
+use super::*;
+use crate::backend::simple_opt::PassCtx;
+use crate::frontend::ast::OptLevel;
+use std::collections::{HashMap, HashSet};
+
+/// Remove the bindings whose result is never used and whose computation has no effect.
+/// Uses are collected from the end of each continuation backwards, so removing a binding
+/// also removes the uses of its arguments, which may be dead in turn.
+/// Unlike `DeadElim`, calls to functions known to be pure are removed too.
+pub fn eliminate_dead_bindings(expr: MExpr) -> MExpr {
+    eliminate_dead_bindings_with_ctx(expr, &PassCtx::default())
+}
+
+/// Same as `eliminate_dead_bindings`, the bodies of `@optimize(never)` functions are kept
+pub fn eliminate_dead_bindings_with_ctx(expr: MExpr, ctx: &PassCtx) -> MExpr {
+    let mut pass = Dce {
+        used: HashSet::new(),
+        pure: pure_funcs(&expr),
+        ctx,
+    };
+    pass.visit_expr(expr)
+}
+
+/// The functions whose calls can be removed: they don't call externals, don't store,
+/// don't panic, and only call other pure functions. Recursive functions are never pure,
+/// since removing a call that never returns would change the program.
+fn pure_funcs(expr: &MExpr) -> HashSet<Ident> {
+    let mut decls = HashMap::new();
+    collect_decls(expr, &mut decls);
+    let mut pure = HashSet::new();
+    loop {
+        let new: Vec<Ident> = decls
+            .iter()
+            .filter(|(func, body)| !pure.contains(*func) && has_no_effect(body, &pure))
+            .map(|(func, _)| *func)
+            .collect();
+        if new.is_empty() {
+            return pure;
+        }
+        pure.extend(new);
+    }
+}
+
+fn collect_decls<'a>(expr: &'a MExpr, decls: &mut HashMap<Ident, &'a MExpr>) {
+    if let MExpr::LetIn { decls: funcs, .. } = expr {
+        for decl in funcs {
+            decls.insert(decl.func, &decl.body);
+            collect_decls(&decl.body, decls);
+        }
+    }
+    for sub in subexprs(expr) {
+        collect_decls(sub, decls);
+    }
+}
+
+/// whether running `expr` has no effect besides computing its result,
+/// the functions declared inside it are checked on their own
+fn has_no_effect(expr: &MExpr, pure: &HashSet<Ident>) -> bool {
+    let this = match expr {
+        MExpr::ExtCall { .. } | MExpr::Store { .. } | MExpr::Panic { .. } => false,
+        MExpr::BinOp { prim, .. } => prim.is_pure(),
+        MExpr::Call {
+            func: Atom::Var(func),
+            ..
+        }
+        | MExpr::TailCall {
+            func: Atom::Var(func),
+            ..
+        } => pure.contains(func),
+        MExpr::Call { .. } | MExpr::TailCall { .. } => false,
+        _ => true,
+    };
+    this && subexprs(expr)
+        .into_iter()
+        .all(|sub| has_no_effect(sub, pure))
+}
+
+/// the branches and the continuation of an expression, but not its declarations
+pub(super) fn subexprs(expr: &MExpr) -> Vec<&MExpr> {
+    match expr {
+        MExpr::Retn { .. } | MExpr::TailCall { .. } | MExpr::Panic { .. } => Vec::new(),
+        MExpr::Ifte {
+            brch1, brch2, cont, ..
+        } => vec![brch1, brch2, cont],
+        MExpr::Switch {
+            brchs, dflt, cont, ..
+        } => brchs
+            .iter()
+            .map(|(_, brch)| brch)
+            .chain(dflt.iter().map(|dflt| dflt.as_ref()))
+            .chain([cont.as_ref()])
+            .collect(),
+        MExpr::LetIn { cont, .. }
+        | MExpr::UnOp { cont, .. }
+        | MExpr::BinOp { cont, .. }
+        | MExpr::Call { cont, .. }
+        | MExpr::ExtCall { cont, .. }
+        | MExpr::Alloc { cont, .. }
+        | MExpr::Load { cont, .. }
+        | MExpr::Store { cont, .. }
+        | MExpr::Offset { cont, .. } => vec![cont],
+    }
+}
+
+struct Dce<'a> {
+    /// variables used by what was visited so far, which is everything after the current
+    /// binding. a shadowed name is kept alive by any of its uses, which is still correct
+    used: HashSet<Ident>,
+    pure: HashSet<Ident>,
+    ctx: &'a PassCtx,
+}
+
+impl Dce<'_> {
+    fn visit_arg(&mut self, arg: Atom) -> Atom {
+        if let Atom::Var(var) = arg {
+            self.used.insert(var);
+        }
+        arg
+    }
+
+    /// record the variables used by a frozen body, without eliminating anything
+    fn visit_frozen(&mut self, expr: MExpr) -> MExpr {
+        expr.walk_arg(|arg| self.visit_arg(arg))
+            .walk_brch(|brch| self.visit_frozen(brch))
+            .walk_decl(|decl| decl.walk_body(|body| self.visit_frozen(body)))
+            .walk_cont(|cont| self.visit_frozen(cont))
+    }
+
+    fn visit_expr(&mut self, expr: MExpr) -> MExpr {
+        // the continuation first, it holds every use of the binding
+        let expr = expr.walk_cont(|cont| self.visit_expr(cont));
+        let dead = match &expr {
+            MExpr::UnOp { bind, .. } => !self.used.contains(bind),
+            MExpr::BinOp { bind, prim, .. } => !self.used.contains(bind) && prim.is_pure(),
+            MExpr::Call {
+                bind,
+                func: Atom::Var(func),
+                ..
+            } => !self.used.contains(bind) && self.pure.contains(func),
+            // externals may have any effect, and a store is only done for its effect
+            _ => false,
+        };
+        if dead {
+            if let MExpr::UnOp { cont, .. } | MExpr::BinOp { cont, .. } | MExpr::Call { cont, .. } =
+                expr
+            {
+                return *cont;
+            }
+            unreachable!();
+        }
+        expr.walk_decl(|decl| {
+            if self.ctx.opt_level(&decl.func) == Some(OptLevel::Never) {
+                decl.walk_body(|body| self.visit_frozen(body))
+            } else {
+                decl.walk_body(|body| self.visit_expr(body))
+            }
+        })
+        .walk_brch(|brch| self.visit_expr(brch))
+        .walk_arg(|arg| self.visit_arg(arg))
+    }
+}
+
+#[test]
+fn dce_test() {
+    use super::anf_build::*;
+    // a chain of unused arithmetic is removed from the end backwards
+    let expr = chain(vec![
+        iadd("x", v("a"), i(1)),
+        imul("y", v("x"), i(2)),
+        isub("z", v("a"), i(3)),
+        retn(v("z")),
+    ]);
+    let expr = eliminate_dead_bindings(expr);
+    let expected = chain(vec![isub("z", v("a"), i(3)), retn(v("z"))]);
+    assert_eq!(format!("{expr}"), format!("{expected}"));
+
+    // a pure function is only called for its result, externals and stores always stay
+    let decls = vec![
+        fun(
+            "f",
+            vec!["p"],
+            chain(vec![iadd("q", v("p"), i(1)), retn(v("q"))]),
+        ),
+        fun(
+            "g",
+            vec!["p"],
+            chain(vec![store(v("p"), 0, i(1)), retn(unit())]),
+        ),
+        fun(
+            "h",
+            vec!["p"],
+            chain(vec![call("q", "h", vec![v("p")]), retn(v("q"))]),
+        ),
+    ];
+    let expr = let_in(
+        decls.clone(),
+        vec![
+            call("x", "f", vec![i(1)]),
+            call("y", "g", vec![v("a")]),
+            call("z", "h", vec![i(2)]),
+            call_ext("w", "print", vec![i(3)]),
+            store(v("a"), 0, i(4)),
+            retn(i(0)),
+        ],
+    );
+    let expr = eliminate_dead_bindings(expr);
+    let expected = let_in(
+        decls,
+        vec![
+            call("y", "g", vec![v("a")]),
+            call("z", "h", vec![i(2)]),
+            call_ext("w", "print", vec![i(3)]),
+            store(v("a"), 0, i(4)),
+            retn(i(0)),
+        ],
+    );
+    assert_eq!(format!("{expr}"), format!("{expected}"));
+
+    // bindings used in a branch or a nested function are kept
+    let expr = chain(vec![
+        iadd("x", v("a"), i(1)),
+        iadd("y", v("a"), i(2)),
+        ifte("r", v("a"), retn(v("y")), retn(i(0))),
+        let_in(vec![fun("k", vec![], retn(v("x")))], vec![retn(v("k"))]),
+    ]);
+    let expected = format!("{expr}");
+    let expr = eliminate_dead_bindings(expr);
+    assert_eq!(format!("{expr}"), expected);
+}
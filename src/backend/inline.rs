@@ -0,0 +1,238 @@
+use super::*;
+use crate::backend::dce::subexprs;
+use crate::backend::simple_opt::{concat, PassCtx};
+use std::collections::HashMap;
+
+/// the functions with at most this many IR nodes are inlined by default
+pub const INLINE_THRESHOLD: usize = 16;
+
+/// how many inlined bodies may be nested in each other, a recursive function is
+/// unrolled this many times at most
+const INLINE_DEPTH: usize = 2;
+
+/// Inline the calls to the small functions of `decls`, and to the small functions
+/// declared inside `expr`, those with at most `threshold` nodes. Unlike `LinearInline`,
+/// a function called many times is copied into every call site, with fresh names,
+/// and it is kept for its other uses, which `DeadElim` removes if there are none left.
+pub fn inline_small_functions(expr: MExpr, decls: &[MDecl], threshold: usize) -> MExpr {
+    inline_small_functions_with_ctx(expr, decls, threshold, &PassCtx::default())
+}
+
+/// Same as `inline_small_functions`, the functions with an `@optimize` attribute are
+/// neither inlined nor inlined into
+pub fn inline_small_functions_with_ctx(
+    expr: MExpr,
+    decls: &[MDecl],
+    threshold: usize,
+    ctx: &PassCtx,
+) -> MExpr {
+    let mut pass = SmallInline {
+        small: HashMap::new(),
+        threshold,
+        depth: 0,
+        frozen: false,
+        ctx,
+    };
+    pass.add_decls(decls);
+    pass.visit_expr(expr)
+}
+
+/// the number of nodes of an expression, with the functions declared in it
+pub fn size(expr: &MExpr) -> usize {
+    let decls = match expr {
+        MExpr::LetIn { decls, .. } => decls.iter().map(|decl| size(&decl.body)).sum(),
+        _ => 0,
+    };
+    1 + decls + subexprs(expr).into_iter().map(size).sum::<usize>()
+}
+
+struct SmallInline<'a> {
+    /// the functions in scope that are small enough to be inlined
+    small: HashMap<Ident, MDecl>,
+    threshold: usize,
+    /// the number of inlined bodies around the expression visited
+    depth: usize,
+    /// inside a function that nothing may be inlined into
+    frozen: bool,
+    ctx: &'a PassCtx,
+}
+
+impl SmallInline<'_> {
+    fn add_decls(&mut self, decls: &[MDecl]) {
+        for decl in decls {
+            if size(&decl.body) <= self.threshold && !self.ctx.no_inline(&decl.func) {
+                self.small.insert(decl.func, decl.clone());
+            }
+        }
+    }
+
+    /// the body of `decl` taking `args`, with fresh names for everything it binds.
+    /// the parameters are renamed before they are bound, so an argument naming one of
+    /// them, like in a recursive call `f(b, a)` inlined in `f(a, b)`, isn't captured
+    fn instantiate(decl: &MDecl, args: Vec<Atom>) -> MExpr {
+        assert_eq!(decl.pars.len(), args.len());
+        let MDecl { pars, body, .. } = decl.clone().rename();
+        pars.into_iter()
+            .zip(args)
+            .rev()
+            .fold(body, |cont, (par, arg)| MExpr::UnOp {
+                bind: par,
+                prim: UnOpPrim::Move,
+                arg1: arg,
+                cont: Box::new(cont),
+            })
+    }
+
+    fn visit_expr(&mut self, expr: MExpr) -> MExpr {
+        match expr {
+            MExpr::LetIn { decls, cont } => {
+                self.add_decls(&decls);
+                let decls = decls
+                    .into_iter()
+                    .map(|decl| self.visit_decl(decl))
+                    .collect();
+                let cont = Box::new(self.visit_expr(*cont));
+                MExpr::LetIn { decls, cont }
+            }
+            MExpr::Call {
+                bind,
+                func: Atom::Var(func),
+                args,
+                cont,
+            } if !self.frozen && self.depth < INLINE_DEPTH && self.small.contains_key(&func) => {
+                let body = Self::instantiate(&self.small[&func], args);
+                self.depth += 1;
+                let body = self.visit_expr(body);
+                self.depth -= 1;
+                let cont = self.visit_expr(*cont);
+                concat(body, bind, cont)
+            }
+            expr => expr
+                .walk_brch(|brch| self.visit_expr(brch))
+                .walk_cont(|cont| self.visit_expr(cont)),
+        }
+    }
+
+    fn visit_decl(&mut self, decl: MDecl) -> MDecl {
+        let frozen = self.frozen;
+        self.frozen = frozen || self.ctx.no_inline(&decl.func);
+        let decl = decl.walk_body(|body| self.visit_expr(body));
+        self.frozen = frozen;
+        decl
+    }
+}
+
+#[test]
+fn inline_small_functions_test() {
+    use super::anf_build::*;
+    // a small function is inlined at every call site, and kept
+    let expr = let_in(
+        vec![fun(
+            "sq",
+            vec!["x"],
+            chain(vec![imul("y", v("x"), v("x")), retn(v("y"))]),
+        )],
+        vec![
+            call("a", "sq", vec![i(3)]),
+            call("b", "sq", vec![v("a")]),
+            retn(v("b")),
+        ],
+    );
+    let expr = inline_small_functions(expr, &[], INLINE_THRESHOLD);
+    let expected = let_in(
+        vec![fun(
+            "sq",
+            vec!["x"],
+            chain(vec![imul("y", v("x"), v("x")), retn(v("y"))]),
+        )],
+        vec![
+            _move("x1", i(3)),
+            imul("y1", v("x1"), v("x1")),
+            _move("a", v("y1")),
+            _move("x2", v("a")),
+            imul("y2", v("x2"), v("x2")),
+            _move("b", v("y2")),
+            retn(v("b")),
+        ],
+    );
+    assert_eq!(expr, expected);
+
+    // a function bigger than the threshold isn't
+    let expr = call("a", "sq", vec![i(3)]);
+    let decls = [fun(
+        "sq",
+        vec!["x"],
+        chain(vec![imul("y", v("x"), v("x")), retn(v("y"))]),
+    )];
+    assert_eq!(size(&decls[0].body), 2);
+    let expr = chain(vec![expr, retn(v("a"))]);
+    assert_eq!(
+        inline_small_functions(expr.clone(), &decls, 1),
+        expr.clone()
+    );
+    assert_eq!(
+        inline_small_functions(expr, &decls, 2),
+        chain(vec![
+            _move("x1", i(3)),
+            imul("y1", v("x1"), v("x1")),
+            _move("a", v("y1")),
+            retn(v("a")),
+        ])
+    );
+
+    // a recursive function is only unrolled as deep as the limit
+    let f = fun(
+        "f",
+        vec!["n"],
+        chain(vec![
+            isub("m", v("n"), i(1)),
+            call("r", "f", vec![v("m")]),
+            retn(v("r")),
+        ]),
+    );
+    let expr = chain(vec![call("a", "f", vec![i(5)]), retn(v("a"))]);
+    let expr = inline_small_functions(expr, &[f], INLINE_THRESHOLD);
+    let expected = chain(vec![
+        _move("n1", i(5)),
+        isub("m1", v("n1"), i(1)),
+        _move("n2", v("m1")),
+        isub("m2", v("n2"), i(1)),
+        call("r2", "f", vec![v("m2")]),
+        _move("r1", v("r2")),
+        _move("a", v("r1")),
+        retn(v("a")),
+    ]);
+    assert_eq!(expr, expected);
+
+    // the arguments of a recursive call inlined in its own body are the parameters
+    // of the outer copy, not of the inlined one
+    let swap = fun(
+        "swap",
+        vec!["a", "b", "n"],
+        chain(vec![
+            isub("m", v("n"), i(1)),
+            call("r", "swap", vec![v("b"), v("a"), v("m")]),
+            retn(v("r")),
+        ]),
+    );
+    let expr = chain(vec![
+        call("x", "swap", vec![v("a"), v("b"), i(3)]),
+        retn(v("x")),
+    ]);
+    let expr = inline_small_functions(expr, &[swap], INLINE_THRESHOLD);
+    let expected = chain(vec![
+        _move("a1", v("a")),
+        _move("b1", v("b")),
+        _move("n1", i(3)),
+        isub("m1", v("n1"), i(1)),
+        _move("a2", v("b1")),
+        _move("b2", v("a1")),
+        _move("n2", v("m1")),
+        isub("m2", v("n2"), i(1)),
+        call("r2", "swap", vec![v("b2"), v("a2"), v("m2")]),
+        _move("r1", v("r2")),
+        _move("x", v("r1")),
+        retn(v("x")),
+    ]);
+    assert_eq!(expr, expected);
+}
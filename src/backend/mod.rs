@@ -4,8 +4,16 @@ use crate::utils::intern::{Ident, InternStr};
 pub mod anf;
 pub mod anf_build;
 pub mod anf_equiv;
+pub mod anf_parse;
 pub mod visitor;
 pub mod normalize;
 pub mod simple_opt;
+pub mod dce;
+pub mod inline;
+pub mod tail_call;
+pub mod switch_check;
+pub mod copy_prop;
 pub mod clos_conv;
 pub mod codegen;
+
+pub use anf_parse::parse_ir;
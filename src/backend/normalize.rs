@@ -1,5 +1,9 @@
+use super::switch_check::{validate_switch, TagTypes};
 use super::*;
 use crate::frontend::ast::*;
+use crate::frontend::diagnostic::Diagnostic;
+use crate::frontend::lexer::is_opr_char;
+use crate::frontend::position::{Span, Spanned};
 use std::collections::{HashMap, HashSet};
 
 #[allow(dead_code)]
@@ -29,6 +33,35 @@ pub struct Normalize {
     cons_env: HashMap<Ident, DataCons>,
     data_env: HashMap<Ident, DataDecl>,
     type_env: HashMap<Ident, TypeDecl>,
+    // map a field to its record and its index in the record
+    field_env: HashMap<Ident, (Ident, usize)>,
+    // number of fields of a record
+    record_env: HashMap<Ident, usize>,
+    // calling convention of external functions
+    ext_env: HashMap<InternStr, CallConv>,
+    // external functions returning `()`, which are `void` in C
+    ext_void: HashSet<InternStr>,
+    // external functions declared with a trailing `...`
+    ext_variadic: HashSet<InternStr>,
+    // types of the variadic arguments of each call, given by the type checker
+    varargs: HashMap<Span, Vec<FfiType>>,
+    // whether the type checker accepted the program, otherwise an argument of `@format`
+    // must be a literal, the only values whose type is known here
+    typed: bool,
+    // functions marked `@cold`, a branch that always calls one is cold
+    cold_funcs: HashSet<Ident>,
+    // check dynamic divisors for zero at runtime
+    div_check: bool,
+    // errors found while lowering, like a literal zero divisor
+    diags: Vec<Diagnostic>,
+    // the data type of every tag switched on, see `validate_switch`
+    tag_types: TagTypes,
+}
+
+impl Default for Normalize {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Normalize {
@@ -37,6 +70,17 @@ impl Normalize {
             cons_env: HashMap::new(),
             data_env: HashMap::new(),
             type_env: HashMap::new(),
+            field_env: HashMap::new(),
+            record_env: HashMap::new(),
+            ext_env: HashMap::new(),
+            ext_void: HashSet::new(),
+            ext_variadic: HashSet::new(),
+            varargs: HashMap::new(),
+            typed: true,
+            cold_funcs: HashSet::new(),
+            div_check: true,
+            diags: Vec::new(),
+            tag_types: TagTypes::new(),
         }
     }
     pub fn run(expr: &Expr) -> MExpr {
@@ -44,6 +88,70 @@ impl Normalize {
         pass.normalize_top(expr)
     }
 
+    /// calls to variadic externs are lowered with the types of their variadic arguments,
+    /// which the type checker records by the span of each call. `varargs` is `None` if the
+    /// type checker didn't accept the program.
+    /// `div_check` guards every division by a non-literal divisor with a runtime check,
+    /// a literal zero divisor is always an error.
+    pub fn run_checked(
+        expr: &Expr,
+        varargs: Option<HashMap<Span, Vec<FfiType>>>,
+        div_check: bool,
+    ) -> Result<MExpr, Vec<Diagnostic>> {
+        let mut pass = Normalize::new();
+        pass.typed = varargs.is_some();
+        pass.varargs = varargs.unwrap_or_default();
+        pass.div_check = div_check;
+        let res = pass.normalize_top(expr);
+        // the switches are built exhaustive, a diagnostic here is a bug of the lowering
+        let diags = validate_switch(&res, &pass.tag_types);
+        pass.diags.extend(diags);
+        if pass.diags.is_empty() {
+            Ok(res)
+        } else {
+            Err(pass.diags)
+        }
+    }
+
+    /// the number of fields allocated to build `expr`, if it only contains literals
+    fn const_size(&self, expr: &Expr) -> Option<usize> {
+        match expr {
+            Expr::Lit { .. } => Some(0),
+            Expr::Anno { expr, .. } => self.const_size(expr),
+            Expr::Cons { args, .. } => args
+                .iter()
+                .map(|arg| self.const_size(arg))
+                .sum::<Option<usize>>()
+                .map(|size| size + args.len() + 1),
+            Expr::Tuple { elems, .. } => elems
+                .iter()
+                .map(|elem| self.const_size(elem))
+                .sum::<Option<usize>>()
+                .map(|size| size + elems.len()),
+            _ => None,
+        }
+    }
+
+    /// lay out an expression accepted by `const_size`, with the same fields as `normalize`
+    fn make_const(&self, expr: &Expr) -> Atom {
+        match expr {
+            Expr::Lit { lit, .. } => (*lit).into(),
+            Expr::Anno { expr, .. } => self.make_const(expr),
+            Expr::Cons { cons, args, .. } => {
+                let tag = Atom::Int(self.get_cons_index(cons) as i64);
+                let fields = std::iter::once(tag)
+                    .chain(args.iter().map(|arg| self.make_const(arg)))
+                    .collect();
+                Atom::Const(ConstId::new(fields))
+            }
+            Expr::Tuple { elems, .. } => {
+                let fields = elems.iter().map(|elem| self.make_const(elem)).collect();
+                Atom::Const(ConstId::new(fields))
+            }
+            _ => unreachable!(),
+        }
+    }
+
     fn get_cons_index(&self, cons: &Ident) -> usize {
         let data = self.cons_env[cons].data;
         self.data_env[&data]
@@ -69,7 +177,237 @@ impl Normalize {
     fn normalize(&mut self, expr: &Expr, hole: Ident, ctx: MExpr) -> MExpr {
         match expr {
             Expr::Lit { lit, .. } => subst(ctx, hole, (*lit).into()),
-            Expr::Var { var, .. } => subst(ctx, hole, Atom::Var(*var)),
+            Expr::Var { var, .. } => subst(ctx, hole, Atom::Var(mangle(*var))),
+            // an ascription is only checked, there is nothing left of it at runtime
+            Expr::Anno { expr, .. } => self.normalize(expr, hole, ctx),
+            Expr::Hole { .. } => {
+                panic!("typed holes are reported by the driver before lowering!");
+            }
+            Expr::Module { .. } => {
+                panic!("modules are turned into blocks by the renamer!");
+            }
+            Expr::Try { .. } => {
+                panic!("`try` is turned into a case by the renamer!");
+            }
+            // switching coroutines is done by the runtime, the call can't be folded or removed
+            Expr::Prim {
+                prim: Builtin::Yield,
+                args,
+                span,
+            } => {
+                let call = Expr::ExtCall {
+                    func: InternStr::new(YIELD),
+                    args: args.clone(),
+                    span: *span,
+                };
+                self.normalize(&call, hole, ctx)
+            }
+            // threads are started and joined by the runtime, a stack size of 0 is the default
+            Expr::Prim {
+                prim: prim @ (Builtin::Spawn | Builtin::SpawnWithStack | Builtin::Join),
+                args,
+                span,
+            } => {
+                let mut args = args.clone();
+                if *prim == Builtin::Spawn {
+                    let lit = LitVal::Int(0);
+                    args.push(Expr::Lit { lit, span: *span });
+                }
+                let func = if *prim == Builtin::Join { JOIN } else { SPAWN };
+                let call = Expr::ExtCall {
+                    func: InternStr::new(func),
+                    args,
+                    span: *span,
+                };
+                self.normalize(&call, hole, ctx)
+            }
+            // channels are bounded queues in the runtime, mutexes are `pthread_mutex_t`,
+            // semaphores are POSIX `sem_t`, files are read and written with stdio
+            // and paths are split and joined on `/`
+            Expr::Prim {
+                prim:
+                    prim @ (Builtin::ChanNew
+                    | Builtin::ChanSend
+                    | Builtin::ChanRecv
+                    | Builtin::ChanClose
+                    | Builtin::MutexNew
+                    | Builtin::MutexLock
+                    | Builtin::MutexUnlock
+                    | Builtin::WithMutex
+                    | Builtin::SemNew
+                    | Builtin::SemPost
+                    | Builtin::SemWait
+                    | Builtin::SemTryWait
+                    | Builtin::ReadFile
+                    | Builtin::WriteFile
+                    | Builtin::ListDir
+                    | Builtin::PathJoin
+                    | Builtin::PathBase
+                    | Builtin::PathDir),
+                args,
+                span,
+            } => {
+                let func = match prim {
+                    Builtin::ChanNew => CHAN_NEW,
+                    Builtin::ChanSend => CHAN_SEND,
+                    Builtin::ChanRecv => CHAN_RECV,
+                    Builtin::ChanClose => CHAN_CLOSE,
+                    Builtin::MutexNew => MUTEX_NEW,
+                    Builtin::MutexLock => MUTEX_LOCK,
+                    Builtin::MutexUnlock => MUTEX_UNLOCK,
+                    Builtin::WithMutex => WITH_MUTEX,
+                    Builtin::SemNew => SEM_NEW,
+                    Builtin::SemPost => SEM_POST,
+                    Builtin::SemWait => SEM_WAIT,
+                    Builtin::SemTryWait => SEM_TRY_WAIT,
+                    Builtin::ReadFile => READ_FILE,
+                    Builtin::WriteFile => WRITE_FILE,
+                    Builtin::ListDir => LIST_DIR,
+                    Builtin::PathJoin => PATH_JOIN,
+                    Builtin::PathBase => PATH_BASE,
+                    _ => PATH_DIR,
+                };
+                let call = Expr::ExtCall {
+                    func: InternStr::new(func),
+                    args: args.clone(),
+                    span: *span,
+                };
+                self.normalize(&call, hole, ctx)
+            }
+            // the format is rewritten for `snprintf`, the arguments are passed as C varargs
+            Expr::Prim {
+                prim: Builtin::Format,
+                args,
+                span,
+            } => {
+                let fmt = match args.first() {
+                    Some(Expr::Lit {
+                        lit: LitVal::Str(fmt),
+                        span,
+                    }) => parse_format(fmt.as_str()).ok().map(|fmt| (fmt, *span)),
+                    _ => None,
+                };
+                // the type checker already reports a bad format, but it may have been skipped
+                let (types, c_fmt, fmt_span) = match fmt {
+                    Some(((types, c_fmt), fmt_span)) if types.len() + 1 == args.len() => {
+                        (types, c_fmt, fmt_span)
+                    }
+                    _ => {
+                        let diag = Diagnostic::error("bad format")
+                            .line_span(*span, "the format doesn't fit the arguments of `@format`");
+                        self.diags.push(diag);
+                        return ctx;
+                    }
+                };
+                for (typ, arg) in types.iter().zip(&args[1..]) {
+                    let msg = match format_arg_fits(typ, arg) {
+                        Some(true) => continue,
+                        Some(false) => format!("this argument doesn't fit its `{typ}` specifier"),
+                        None if self.typed => continue,
+                        None => format!(
+                            "the type checker skipped this program, \
+                            so this argument of `@format` must be a literal `{typ}`"
+                        ),
+                    };
+                    let diag = Diagnostic::error("bad format").line_span(*arg.span(), msg);
+                    self.diags.push(diag);
+                }
+                let argvars: Vec<Ident> = args.iter().map(|_| Ident::generate('x')).collect();
+                let varargs = types
+                    .iter()
+                    .map(|typ| match typ {
+                        LitType::Int => FfiType::Int,
+                        LitType::Real => FfiType::Real,
+                        LitType::Char => FfiType::Char,
+                        _ => FfiType::Ptr,
+                    })
+                    .collect();
+                let abi = ExtAbi {
+                    varargs: Some(varargs),
+                    unit_ret: false,
+                };
+                let res = MExpr::ExtCall {
+                    bind: hole,
+                    func: InternStr::new(FORMAT),
+                    call_conv: CallConv::C,
+                    abi,
+                    args: argvars.iter().map(|arg| Atom::Var(*arg)).collect(),
+                    cont: Box::new(ctx),
+                };
+                let fmt = Expr::Lit {
+                    lit: LitVal::Str(InternStr::new(&c_fmt)),
+                    span: fmt_span,
+                };
+                argvars
+                    .into_iter()
+                    .zip(std::iter::once(&fmt).chain(&args[1..]))
+                    .fold(res, |res, (bind, arg)| self.normalize(arg, bind, res))
+            }
+            // dividing by zero is undefined behavior in C
+            Expr::Prim {
+                prim: prim @ (Builtin::IDiv | Builtin::IRem),
+                args,
+                span,
+            } => {
+                /*
+                    normalize(@idiv(e1,e2), hole, ctx) =
+                    normalize(e2, x2, normalize(e1, x1,
+                        let c = icmpeq(x2, 0);
+                        let _ = ifte(c, panic "division by zero!", ());
+                        let hole = idiv(x1,x2) in ctx
+                    ))
+                */
+                assert!(args.len() == 2);
+                let prim = match prim {
+                    Builtin::IDiv => BinOpPrim::IDiv,
+                    Builtin::IRem => BinOpPrim::IRem,
+                    _ => unreachable!(),
+                };
+                let x1 = Ident::generate('x');
+                let x2 = Ident::generate('x');
+                let mut stmt = MExpr::BinOp {
+                    bind: hole,
+                    prim,
+                    arg1: Atom::Var(x1),
+                    arg2: Atom::Var(x2),
+                    cont: Box::new(ctx),
+                };
+                match &args[1] {
+                    Expr::Lit {
+                        lit: LitVal::Int(0),
+                        ..
+                    } => {
+                        let diag = Diagnostic::error("division by zero")
+                            .line_span(*span, "the divisor of this division is always zero");
+                        self.diags.push(diag);
+                    }
+                    Expr::Lit { .. } => {}
+                    _ if self.div_check => {
+                        let c = Ident::generate('c');
+                        let brch1 = Box::new(self.trap(DIV_BY_ZERO));
+                        let brch2 = Box::new(MExpr::Retn { arg1: Atom::Unit });
+                        let cold = self.cold_brchs([&*brch1, &*brch2]);
+                        let check = MExpr::Ifte {
+                            bind: Ident::generate('r'),
+                            arg1: Atom::Var(c),
+                            brch1,
+                            brch2,
+                            cold,
+                            cont: Box::new(stmt),
+                        };
+                        stmt = MExpr::BinOp {
+                            bind: c,
+                            prim: BinOpPrim::ICmpEq,
+                            arg1: Atom::Var(x2),
+                            arg2: Atom::Int(0),
+                            cont: Box::new(check),
+                        };
+                    }
+                    _ => {}
+                }
+                let stmt = self.normalize(&args[0], x1, stmt);
+                self.normalize(&args[1], x2, stmt)
+            }
             Expr::Prim { prim, args, .. } => {
                 // normalize(@iadd(e1,e2), hole, ctx) =
                 // normalize(e2,x2,normalize(e1,x1, let hole = iadd(x1,x2) in ctx))
@@ -84,16 +422,67 @@ impl Normalize {
                     Builtin::IAdd => OpPrim::Binary(BinOpPrim::IAdd),
                     Builtin::ISub => OpPrim::Binary(BinOpPrim::ISub),
                     Builtin::IMul => OpPrim::Binary(BinOpPrim::IMul),
-                    Builtin::IDiv => todo!(),
-                    Builtin::IRem => todo!(),
                     Builtin::INeg => OpPrim::Unary(UnOpPrim::INeg),
-                    Builtin::RAdd => todo!(),
-                    Builtin::RSub => todo!(),
-                    Builtin::RMul => todo!(),
-                    Builtin::RDiv => todo!(),
+                    Builtin::Trunc32 => OpPrim::Unary(UnOpPrim::Trunc32),
+                    Builtin::SignExt32 => OpPrim::Unary(UnOpPrim::SignExt32),
+                    Builtin::ZeroExt32 => OpPrim::Unary(UnOpPrim::ZeroExt32),
+                    Builtin::ICmpEq => OpPrim::Binary(BinOpPrim::ICmpEq),
+                    Builtin::ICmpNe => OpPrim::Binary(BinOpPrim::ICmpNe),
+                    Builtin::ICmpLt => OpPrim::Binary(BinOpPrim::ICmpLt),
+                    Builtin::ICmpLe => OpPrim::Binary(BinOpPrim::ICmpLe),
+                    Builtin::ICmpGt => OpPrim::Binary(BinOpPrim::ICmpGt),
+                    Builtin::ICmpGe => OpPrim::Binary(BinOpPrim::ICmpGe),
+                    Builtin::RAdd => OpPrim::Binary(BinOpPrim::RAdd),
+                    Builtin::RSub => OpPrim::Binary(BinOpPrim::RSub),
+                    Builtin::RMul => OpPrim::Binary(BinOpPrim::RMul),
+                    Builtin::RDiv => OpPrim::Binary(BinOpPrim::RDiv),
+                    Builtin::Sinh => OpPrim::Unary(UnOpPrim::Sinh),
+                    Builtin::Cosh => OpPrim::Unary(UnOpPrim::Cosh),
+                    Builtin::Tanh => OpPrim::Unary(UnOpPrim::Tanh),
+                    Builtin::Erf => OpPrim::Unary(UnOpPrim::Erf),
+                    Builtin::Erfc => OpPrim::Unary(UnOpPrim::Erfc),
                     Builtin::BAnd => todo!(),
                     Builtin::BOr => todo!(),
-                    Builtin::BNot => todo!(),
+                    Builtin::BNot => OpPrim::Unary(UnOpPrim::BNot),
+                    Builtin::Prefetch => OpPrim::Binary(BinOpPrim::Prefetch),
+                    Builtin::Expect => OpPrim::Binary(BinOpPrim::Expect),
+                    Builtin::SimdAdd => OpPrim::Binary(BinOpPrim::SimdAdd),
+                    Builtin::SimdMul => OpPrim::Binary(BinOpPrim::SimdMul),
+                    // a vector is stored just like a tuple of its elements
+                    Builtin::SimdLoad | Builtin::SimdStore => OpPrim::Unary(UnOpPrim::Move),
+                    // every value is a `void*` at runtime
+                    Builtin::CastOpaquePtr => OpPrim::Unary(UnOpPrim::Move),
+                    Builtin::AllocAligned => OpPrim::Binary(BinOpPrim::AllocAligned),
+                    // a value already holds its raw bits, `from_real` copies them
+                    // with `memcpy`, so there is nothing to reinterpret
+                    Builtin::Bitcast(_, _) => OpPrim::Unary(UnOpPrim::Move),
+                    Builtin::Yield
+                    | Builtin::Spawn
+                    | Builtin::Join
+                    | Builtin::SpawnWithStack
+                    | Builtin::ChanNew
+                    | Builtin::ChanSend
+                    | Builtin::ChanRecv
+                    | Builtin::ChanClose
+                    | Builtin::MutexNew
+                    | Builtin::MutexLock
+                    | Builtin::MutexUnlock
+                    | Builtin::WithMutex
+                    | Builtin::SemNew
+                    | Builtin::SemPost
+                    | Builtin::SemWait
+                    | Builtin::SemTryWait
+                    | Builtin::ReadFile
+                    | Builtin::WriteFile
+                    | Builtin::ListDir
+                    | Builtin::PathJoin
+                    | Builtin::PathBase
+                    | Builtin::PathDir
+                    | Builtin::Format
+                    | Builtin::ListMin
+                    | Builtin::ListMax
+                    | Builtin::IDiv
+                    | Builtin::IRem => unreachable!(),
                 };
 
                 let stmt = match prim {
@@ -155,30 +544,52 @@ impl Normalize {
                 let res = argvars
                     .iter()
                     .cloned()
-                    .zip(args.into_iter())
+                    .zip(args)
                     .fold(res, |res, (bind, arg)| self.normalize(arg, bind, res));
                 res
             }
-            Expr::ExtCall { func, args, .. } => {
+            Expr::ExtCall { func, args, span } => {
                 // normalize(f(e1,..,en), hole, ctx) =
                 // normalize(en,xn,
                 //   ...
                 //     normalize(e1,x1,
                 //       let hole = f(x1,...,xn) in ctx))...)
                 let argvars: Vec<Ident> = args.iter().map(|_| Ident::generate('x')).collect();
+                let call_conv = self.ext_env.get(func).copied().unwrap_or(CallConv::C);
+                let varargs = self.ext_variadic.contains(func).then(|| {
+                    self.varargs
+                        .get(span)
+                        .cloned()
+                        .expect("variadic calls should be typed by the type checker!")
+                });
+                let abi = ExtAbi {
+                    varargs,
+                    unit_ret: self.ext_void.contains(func),
+                };
                 let res = MExpr::ExtCall {
                     bind: hole,
                     func: *func,
+                    call_conv,
+                    abi,
                     args: argvars.iter().map(|arg| Atom::Var(*arg)).collect(),
                     cont: Box::new(ctx),
                 };
                 let res = argvars
                     .iter()
                     .cloned()
-                    .zip(args.into_iter())
+                    .zip(args)
                     .fold(res, |res, (bind, arg)| self.normalize(arg, bind, res));
                 res
             }
+            // a big constant table is laid out at compile time instead of built at startup
+            Expr::Cons { .. } | Expr::Tuple { .. }
+                if self
+                    .const_size(expr)
+                    .is_some_and(|size| size >= CONST_DATA_MIN) =>
+            {
+                let atom = self.make_const(expr);
+                subst(ctx, hole, atom)
+            }
             Expr::Cons { cons, args, .. } => {
                 // normalize(ci(e1,..,en), hole, ctx) =
                 // normalize(en,xn,
@@ -229,19 +640,225 @@ impl Normalize {
                 let res = argvars
                     .iter()
                     .cloned()
-                    .zip(args.into_iter())
+                    .zip(args)
                     .fold(res, |res, (bind, arg)| self.normalize(arg, bind, res));
 
                 res
             }
+            Expr::Tuple { elems, .. } => {
+                // normalize((e1,..,en), hole, ctx) =
+                // normalize(en,xn,
+                //   ...
+                //     normalize(e1,x1,
+                //       let m = alloc(n);
+                //       store m[0] = x1;
+                //       ......
+                //       store m[n-1] = xn;
+                //       let hole = move(m);
+                //       ctx )...)
+                let m = Ident::generate('m');
+                let elemvars: Vec<Ident> = elems.iter().map(|_| Ident::generate('x')).collect();
+                let res = MExpr::UnOp {
+                    bind: hole,
+                    prim: UnOpPrim::Move,
+                    arg1: Atom::Var(m),
+                    cont: Box::new(ctx),
+                };
+
+                let res = elemvars
+                    .iter()
+                    .enumerate()
+                    .fold(res, |cont, (i, x)| MExpr::Store {
+                        arg1: Atom::Var(m),
+                        index: i,
+                        arg2: Atom::Var(*x),
+                        cont: Box::new(cont),
+                    });
+
+                let res = MExpr::Alloc {
+                    bind: m,
+                    size: elems.len(),
+                    cont: Box::new(res),
+                };
+
+                elemvars
+                    .iter()
+                    .cloned()
+                    .zip(elems.iter())
+                    .fold(res, |res, (bind, elem)| self.normalize(elem, bind, res))
+            }
+            Expr::Proj { expr, index, .. } => {
+                // normalize(e.i, hole, ctx) =
+                // normalize(e,x, let hole = load x[i] in ctx)
+                let x = Ident::generate('x');
+                let res = MExpr::Load {
+                    bind: hole,
+                    arg1: Atom::Var(x),
+                    index: *index,
+                    cont: Box::new(ctx),
+                };
+                self.normalize(expr, x, res)
+            }
+            Expr::Record { fields, .. } => {
+                // like a tuple, but stored in the order of the record declaration
+                // normalize({f1 = e1,..,fn = en}, hole, ctx) =
+                // normalize(en,xn,
+                //   ...
+                //     normalize(e1,x1,
+                //       let m = alloc(n);
+                //       store m[index(f1)] = x1;
+                //       ......
+                //       store m[index(fn)] = xn;
+                //       let hole = move(m);
+                //       ctx )...)
+                let m = Ident::generate('m');
+                let fieldvars: Vec<Ident> = fields.iter().map(|_| Ident::generate('x')).collect();
+                let res = MExpr::UnOp {
+                    bind: hole,
+                    prim: UnOpPrim::Move,
+                    arg1: Atom::Var(m),
+                    cont: Box::new(ctx),
+                };
+
+                let res = fields
+                    .iter()
+                    .zip(fieldvars.iter())
+                    .fold(res, |cont, (init, x)| MExpr::Store {
+                        arg1: Atom::Var(m),
+                        index: self.field_env[&init.field].1,
+                        arg2: Atom::Var(*x),
+                        cont: Box::new(cont),
+                    });
+
+                let res = MExpr::Alloc {
+                    bind: m,
+                    size: fields.len(),
+                    cont: Box::new(res),
+                };
+
+                fieldvars
+                    .iter()
+                    .cloned()
+                    .zip(fields.iter())
+                    .fold(res, |res, (bind, init)| {
+                        self.normalize(&init.expr, bind, res)
+                    })
+            }
+            Expr::Field { expr, field, .. } => {
+                // normalize(e.f, hole, ctx) =
+                // normalize(e,x, let hole = load x[index(f)] in ctx)
+                let x = Ident::generate('x');
+                let res = MExpr::Load {
+                    bind: hole,
+                    arg1: Atom::Var(x),
+                    index: self.field_env[field].1,
+                    cont: Box::new(ctx),
+                };
+                self.normalize(expr, x, res)
+            }
+            Expr::Update { expr, fields, .. } => {
+                // the record is copied, the replaced fields are stored instead of loaded
+                // normalize({e with f1 = e1,..,fn = en}, hole, ctx) =
+                // normalize(e,r,
+                //   normalize(e1,x1,
+                //     ...
+                //       normalize(en,xn,
+                //         let m = alloc(size);
+                //         store m[0] = (x_i if index(f_i) = 0, else load r[0]);
+                //         ......
+                //         let hole = move(m);
+                //         ctx )...)
+                let r = Ident::generate('r');
+                let m = Ident::generate('m');
+                let record = self.field_env[&fields[0].field].0;
+                let size = self.record_env[&record];
+                let fieldvars: Vec<Ident> = fields.iter().map(|_| Ident::generate('x')).collect();
+                let res = MExpr::UnOp {
+                    bind: hole,
+                    prim: UnOpPrim::Move,
+                    arg1: Atom::Var(m),
+                    cont: Box::new(ctx),
+                };
+
+                let res = (0..size).rev().fold(res, |cont, index| {
+                    let replaced = fields
+                        .iter()
+                        .zip(fieldvars.iter())
+                        .rfind(|(init, _)| self.field_env[&init.field].1 == index);
+                    match replaced {
+                        Some((_, x)) => MExpr::Store {
+                            arg1: Atom::Var(m),
+                            index,
+                            arg2: Atom::Var(*x),
+                            cont: Box::new(cont),
+                        },
+                        None => {
+                            let y = Ident::generate('y');
+                            MExpr::Load {
+                                bind: y,
+                                arg1: Atom::Var(r),
+                                index,
+                                cont: Box::new(MExpr::Store {
+                                    arg1: Atom::Var(m),
+                                    index,
+                                    arg2: Atom::Var(y),
+                                    cont: Box::new(cont),
+                                }),
+                            }
+                        }
+                    }
+                });
+
+                let res = MExpr::Alloc {
+                    bind: m,
+                    size,
+                    cont: Box::new(res),
+                };
+
+                let res = fieldvars
+                    .iter()
+                    .cloned()
+                    .zip(fields.iter())
+                    .rev()
+                    .fold(res, |res, (bind, init)| {
+                        self.normalize(&init.expr, bind, res)
+                    });
+                self.normalize(expr, r, res)
+            }
             Expr::Let {
                 bind, expr, cont, ..
             } => {
                 // normalize(let x = e1; e2, hole, ctx) =
                 // normalize(e1,x,normalize(e2,hole,ctx))
                 let res = self.normalize(cont, hole, ctx);
-                let res = self.normalize(expr, *bind, res);
-                res
+                self.normalize(expr, *bind, res)
+            }
+            Expr::Ifte {
+                cond, trbr, flbr, ..
+            } => {
+                /*
+                    normalize(if cond then trbr else flbr, hole, ctx) =
+                    normalize(cond, x,
+                        let hole = ifte(x,
+                            normalize_top(trbr),
+                            normalize_top(flbr)
+                        );
+                        ctx
+                    )
+                */
+                let x = Ident::generate('x');
+                let brch1 = Box::new(self.normalize_top(trbr));
+                let brch2 = Box::new(self.normalize_top(flbr));
+                let cold = self.cold_brchs([&*brch1, &*brch2]);
+                let res = MExpr::Ifte {
+                    bind: hole,
+                    arg1: Atom::Var(x),
+                    brch1,
+                    brch2,
+                    cold,
+                    cont: Box::new(ctx),
+                };
+                self.normalize(cond, x, res)
             }
             Expr::Case { expr, rules, .. } => {
                 /*
@@ -270,38 +887,115 @@ impl Normalize {
                         end
                     )
                 */
-                let etop = Ident::generate('o');
+                // `case (e_1, ..., e_n) of` with only tuples and wildcards as patterns
+                // matches the elements in n columns, and the tuple is never built
+                let elems = match expr.as_ref() {
+                    Expr::Tuple { elems, .. } if is_columns(rules, elems.len()) => Some(elems),
+                    _ => None,
+                };
+                let objs: Vec<Ident> = match elems {
+                    Some(elems) => elems.iter().map(|_| Ident::generate('o')).collect(),
+                    None => vec![Ident::generate('o')],
+                };
 
                 let mut decls: Vec<MDecl> = Vec::new();
 
-                let (matrix, acts): (Vec<Vec<_>>, Vec<_>) = rules
-                    .into_iter()
-                    .map(|rule| {
-                        let Rule { patn, body, .. } = rule;
-                        let func = Ident::generate('a');
-                        let pars = patn.get_freevars();
-                        let args = pars.iter().map(|var| Atom::Var(*var)).collect();
-
-                        let decl = MDecl {
-                            func,
-                            pars,
-                            body: self.normalize_top(body),
+                let funcs: Vec<Ident> = rules.iter().map(|_| Ident::generate('a')).collect();
+                // an or-pattern becomes one row per alternative, all of them call the same action.
+                // bindings are hoisted out of the rows, so the alternatives after the first one
+                // bind fresh variables. the rows of rule `i` start at `firsts[i]`
+                let mut matrix: Vec<Vec<Pattern>> = Vec::new();
+                let mut acts = Vec::new();
+                let mut firsts = Vec::new();
+                for (rule, func) in rules.iter().zip(funcs.iter()) {
+                    let pars = rule.patn.get_freevars();
+                    firsts.push(matrix.len());
+                    for (k, alt) in rule.patn.expand_or().into_iter().enumerate() {
+                        let mut map = HashMap::new();
+                        let alt = if k == 0 {
+                            alt
+                        } else {
+                            uniquify_pattern(&alt, &mut map)
                         };
-                        decls.push(decl);
-
-                        let act = MExpr::make_tail_call(func, args);
-
-                        (vec![patn.clone()], act)
-                    })
-                    .unzip();
+                        let args = pars
+                            .iter()
+                            .map(|var| Atom::Var(*map.get(var).unwrap_or(var)))
+                            .collect();
+                        match (elems, alt) {
+                            (Some(_), Pattern::Tuple { pats, .. }) => matrix.push(pats),
+                            (Some(_), Pattern::Wild { span }) => {
+                                matrix.push(vec![Pattern::Wild { span }; objs.len()])
+                            }
+                            (_, alt) => matrix.push(vec![alt]),
+                        }
+                        acts.push(MExpr::make_tail_call(*func, args));
+                    }
+                }
+                firsts.push(matrix.len());
 
-                let objs = vec![etop];
+                for (i, (rule, func)) in rules.iter().zip(funcs).enumerate() {
+                    let Rule {
+                        patn, guard, body, ..
+                    } = rule;
+                    let body = self.normalize_top(body);
+                    let body = match guard {
+                        None => body,
+                        Some(guard) => {
+                            /*
+                                a_i(..) = if guard then e_i else f_i();
+                                f_i() = compile_match((o), (pattern_i+1) ...... (pattern_n), ...)
+                            */
+                            let next = Ident::generate('f');
+                            let rest = PatnMatrix {
+                                objs: objs.clone(),
+                                matrix: matrix[firsts[i + 1]..].to_vec(),
+                                acts: acts[firsts[i + 1]..].to_vec(),
+                            };
+                            decls.push(MDecl {
+                                func: next,
+                                pars: Vec::new(),
+                                body: self.compile_match_top(&rest),
+                            });
+                            let x = Ident::generate('x');
+                            let r = Ident::generate('r');
+                            let brch2 = MExpr::make_tail_call(next, Vec::new());
+                            let cold = self.cold_brchs([&body, &brch2]);
+                            let res = MExpr::Ifte {
+                                bind: r,
+                                arg1: Atom::Var(x),
+                                brch1: Box::new(body),
+                                brch2: Box::new(brch2),
+                                cold,
+                                cont: Box::new(MExpr::Retn { arg1: Atom::Var(r) }),
+                            };
+                            self.normalize(guard, x, res)
+                        }
+                    };
+                    decls.push(MDecl {
+                        func,
+                        pars: patn.get_freevars(),
+                        body,
+                    });
+                }
 
-                let mat = PatnMatrix { objs, matrix, acts };
+                let mat = PatnMatrix {
+                    objs: objs.clone(),
+                    matrix,
+                    acts,
+                };
                 let cont = Box::new(self.compile_match(&mat, hole, ctx));
-                self.normalize(expr, etop, MExpr::LetIn { decls, cont })
+                let cont = MExpr::LetIn { decls, cont };
+                match elems {
+                    // the elements are evaluated from left to right
+                    Some(elems) => elems
+                        .iter()
+                        .zip(objs)
+                        .rev()
+                        .fold(cont, |cont, (elem, obj)| self.normalize(elem, obj, cont)),
+                    None => self.normalize(expr, objs[0], cont),
+                }
             }
-            Expr::Blk { decls, cont, .. } => {
+            Expr::Blk { decls, cont, .. } | Expr::LetRec { decls, cont, .. } => {
                 /*
                     normalize(
                         block
@@ -320,17 +1014,58 @@ impl Normalize {
                         ......
                         fun fn(xn,...,zn) = normalize_top(bodyn);
                     in
-                        normalize_top(cont)
+                        normalize(cont, hole, ctx)
                     end,
+
+                    the context goes into the block, so a block in the middle of an
+                    expression, like the fold `@listmin` is expanded to, keeps what follows it
                 */
+                // external functions and records may be declared after their first use
+                for decl in decls {
+                    if let Decl::Extern {
+                        name, attrs, typ, ..
+                    } = decl
+                    {
+                        for attr in attrs {
+                            if let Attr::CallConv { conv, .. } = attr {
+                                self.ext_env.insert(*name, *conv);
+                            }
+                        }
+                        if let Type::Fun { res, variadic, .. } = typ.unquantified() {
+                            if matches!(
+                                **res,
+                                Type::Lit {
+                                    lit: LitType::Unit,
+                                    ..
+                                }
+                            ) {
+                                self.ext_void.insert(*name);
+                            }
+                            if *variadic {
+                                self.ext_variadic.insert(*name);
+                            }
+                        }
+                    }
+                    if let Decl::Func { name, attrs, .. } = decl {
+                        if attrs.iter().any(|attr| matches!(attr, Attr::Cold { .. })) {
+                            self.cold_funcs.insert(mangle(*name));
+                        }
+                    }
+                    if let Decl::Record { name, fields, .. } = decl {
+                        for (index, field) in fields.iter().enumerate() {
+                            self.field_env.insert(field.field, (*name, index));
+                        }
+                        self.record_env.insert(*name, fields.len());
+                    }
+                }
                 let decls = decls
-                    .into_iter()
+                    .iter()
                     .filter_map(|decl| match decl {
                         Decl::Func {
                             name, pars, body, ..
                         } => Some(MDecl {
-                            func: *name,
-                            pars: pars.clone(),
+                            func: mangle(*name),
+                            pars: pars.iter().map(|(par, _)| *par).collect(),
                             body: self.normalize_top(body),
                         }),
                         Decl::Data {
@@ -370,15 +1105,93 @@ impl Normalize {
                             );
                             None
                         }
-                        Decl::Extern { .. } => None,
+                        // an abstract type is laid out like its representation
+                        Decl::Record { .. }
+                        | Decl::Abstract { .. }
+                        | Decl::Extern { .. }
+                        | Decl::Fixity { .. } => None,
+                        Decl::Val { .. } => {
+                            panic!("values are turned into lets by the renamer!");
+                        }
                     })
                     .collect();
-                let cont = Box::new(self.normalize_top(cont));
+                let cont = Box::new(self.normalize(cont, hole, ctx));
                 MExpr::LetIn { decls, cont }
             }
         }
     }
 
+    /// a branch is cold if every path through it panics or calls a `@cold` function
+    fn is_cold(&self, expr: &MExpr) -> bool {
+        self.is_cold_with(expr, &mut Vec::new())
+    }
+
+    // functions are called through a `move` of their name, `aliases` are such copies
+    fn is_cold_with(&self, expr: &MExpr, aliases: &mut Vec<Ident>) -> bool {
+        match expr {
+            MExpr::Panic { .. } => true,
+            MExpr::Call {
+                func: Atom::Var(func),
+                ..
+            } if self.cold_funcs.contains(func) || aliases.contains(func) => true,
+            MExpr::TailCall {
+                func: Atom::Var(func),
+                ..
+            } if self.cold_funcs.contains(func) || aliases.contains(func) => true,
+            MExpr::UnOp {
+                bind,
+                prim: UnOpPrim::Move,
+                arg1: Atom::Var(func),
+                cont,
+            } if self.cold_funcs.contains(func) => {
+                aliases.push(*bind);
+                self.is_cold_with(cont, aliases)
+            }
+            MExpr::Ifte {
+                brch1, brch2, cont, ..
+            } => {
+                (self.is_cold_with(brch1, aliases) && self.is_cold_with(brch2, aliases))
+                    || self.is_cold_with(cont, aliases)
+            }
+            MExpr::Switch {
+                brchs, dflt, cont, ..
+            } => {
+                let all = brchs
+                    .iter()
+                    .all(|(_, brch)| self.is_cold_with(brch, aliases))
+                    && dflt.iter().all(|dflt| self.is_cold_with(dflt, aliases));
+                all || self.is_cold_with(cont, aliases)
+            }
+            MExpr::Retn { .. } | MExpr::TailCall { .. } => false,
+            MExpr::LetIn { cont, .. }
+            | MExpr::UnOp { cont, .. }
+            | MExpr::BinOp { cont, .. }
+            | MExpr::Call { cont, .. }
+            | MExpr::ExtCall { cont, .. }
+            | MExpr::Alloc { cont, .. }
+            | MExpr::Load { cont, .. }
+            | MExpr::Store { cont, .. }
+            | MExpr::Offset { cont, .. } => self.is_cold_with(cont, aliases),
+        }
+    }
+
+    fn cold_brchs<'a>(&self, brchs: impl IntoIterator<Item = &'a MExpr>) -> Cold {
+        let set = brchs
+            .into_iter()
+            .enumerate()
+            .filter(|(_, brch)| self.is_cold(brch))
+            .map(|(index, _)| index)
+            .collect();
+        Cold(set)
+    }
+
+    /// report an error and exit
+    fn trap(&self, msg: &str) -> MExpr {
+        MExpr::Panic {
+            msg: InternStr::new(msg),
+        }
+    }
+
     pub fn compile_match_top(&mut self, mat: &PatnMatrix) -> MExpr {
         let r = Ident::generate('r');
         self.compile_match(mat, r, MExpr::Retn { arg1: Atom::Var(r) })
@@ -386,9 +1199,30 @@ impl Normalize {
 
     pub fn compile_match(&mut self, mat: &PatnMatrix, hole: Ident, ctx: MExpr) -> MExpr {
         if mat.is_empty() {
-            panic!("pattern match not exhaustive!")
+            // no rule matches, fail at runtime
+            self.trap(MATCH_FAILURE)
+        } else if let Some((mat, aliases)) = mat.strip_aliases() {
+            // an alias is bound to the object it matches, which is already evaluated
+            let cont = self.compile_match(&mat, hole, ctx);
+            aliases
+                .into_iter()
+                .fold(cont, |cont, (bind, obj)| MExpr::UnOp {
+                    bind,
+                    prim: UnOpPrim::Move,
+                    arg1: Atom::Var(obj),
+                    cont: Box::new(cont),
+                })
         } else if mat.first_row_aways_match() {
-            let cont = mat.acts[0].clone();
+            // the action is a tail call, its result goes on to `ctx`
+            let MExpr::Call { func, args, .. } = mat.acts[0].clone() else {
+                unreachable!("actions are tail calls")
+            };
+            let cont = MExpr::Call {
+                bind: hole,
+                func,
+                args,
+                cont: Box::new(ctx),
+            };
             mat.matrix[0]
                 .iter()
                 .zip(mat.objs.iter())
@@ -396,6 +1230,9 @@ impl Normalize {
                     Pattern::Var { var, .. } => Some((var, obj)),
                     Pattern::Lit { .. } => unreachable!(),
                     Pattern::Cons { .. } => unreachable!(),
+                    Pattern::Tuple { .. } | Pattern::Or { .. } | Pattern::As { .. } => {
+                        unreachable!()
+                    }
                     Pattern::Wild { .. } => None,
                 })
                 .fold(cont, |cont, (var, obj)| MExpr::UnOp {
@@ -407,16 +1244,16 @@ impl Normalize {
         } else {
             let j = self.get_best_col();
             match self.get_col_type(mat, j) {
-                ColType::Any => self.match_default(mat, j),
+                ColType::Any => self.match_default(mat, j, hole, ctx),
+                ColType::Tuple(arity) => self.match_tuple(mat, j, arity, hole, ctx),
                 ColType::Data(data) => {
                     let cons_set = mat.get_cons_set(j);
                     let mut exhaustive = true;
-                    let brchs = self.data_env[&data]
+                    let brchs: Vec<_> = self.data_env[&data]
                         .cons
                         .clone()
                         .into_iter()
                         .enumerate()
-                        .into_iter()
                         .flat_map(|(i, cons)| {
                             if cons_set.contains(&cons) {
                                 let arity = self.cons_env[&cons].pars.len();
@@ -428,11 +1265,17 @@ impl Normalize {
                         })
                         .collect();
                     let dflt = if exhaustive {
+                        // all tags are covered, codegen treats default as unreachable
+                        assert!(verify_tag_switch(&brchs, self.data_env[&data].cons.len()));
                         None
                     } else {
-                        Some(Box::new(self.match_default(mat, j)))
+                        Some(Box::new(self.match_default_top(mat, j)))
                     };
+                    let cold =
+                        self.cold_brchs(brchs.iter().map(|(_, brch)| brch).chain(dflt.as_deref()));
                     let t = Ident::generate('t');
+                    let cons = self.data_env[&data].cons.clone();
+                    self.tag_types.insert(t, (data, cons));
                     MExpr::Load {
                         bind: t,
                         arg1: Atom::Var(mat.objs[j]),
@@ -442,12 +1285,45 @@ impl Normalize {
                             arg1: Atom::Var(t),
                             brchs,
                             dflt,
+                            cold,
                             cont: Box::new(ctx),
                         }),
                     }
                 }
                 ColType::Lit(LitType::Int) => {
-                    todo!()
+                    let mut lits: Vec<i64> = Vec::new();
+                    for row in mat.matrix.iter() {
+                        if let Pattern::Lit {
+                            lit: LitVal::Int(x),
+                            ..
+                        } = &row[j]
+                        {
+                            if !lits.contains(x) {
+                                lits.push(*x);
+                            }
+                        }
+                    }
+                    let brchs: Vec<_> = lits
+                        .into_iter()
+                        .map(|x| {
+                            // integer literals in patterns are never negative
+                            let x = usize::try_from(x).unwrap();
+                            (x, self.match_int(mat, j, x))
+                        })
+                        .collect();
+                    // integers are never exhaustive, rows without literal become default,
+                    // and the match failure is synthesized if there is no such row
+                    let dflt = Some(Box::new(self.match_default_top(mat, j)));
+                    let cold =
+                        self.cold_brchs(brchs.iter().map(|(_, brch)| brch).chain(dflt.as_deref()));
+                    MExpr::Switch {
+                        bind: hole,
+                        arg1: Atom::Var(mat.objs[j]),
+                        brchs,
+                        dflt,
+                        cold,
+                        cont: Box::new(ctx),
+                    }
                 }
                 ColType::Lit(LitType::Real) => {
                     panic!("pattern match on real numbers are not allowed!");
@@ -461,6 +1337,9 @@ impl Normalize {
                 ColType::Lit(LitType::Unit) => {
                     todo!()
                 }
+                ColType::Lit(LitType::Str) => {
+                    unreachable!("string literals are not patterns!");
+                }
             }
         }
     }
@@ -483,22 +1362,37 @@ impl Normalize {
                             panic!("pattern match not well-typed!")
                         }
                     }
-                    ColType::Data(_) => {
+                    ColType::Data(_) | ColType::Tuple(_) => {
                         panic!("pattern match not well-typed!")
                     }
                 },
                 Pattern::Cons { cons, .. } => match res {
-                    ColType::Any => res = ColType::Data(self.cons_env[&cons].data),
+                    ColType::Any => res = ColType::Data(self.cons_env[cons].data),
                     ColType::Lit(_) => {
                         panic!("pattern match not well-typed!")
                     }
                     ColType::Data(data) => {
-                        if data != self.cons_env[&cons].data {
+                        if data != self.cons_env[cons].data {
+                            panic!("pattern match not well-typed!")
+                        }
+                    }
+                    ColType::Tuple(_) => {
+                        panic!("pattern match not well-typed!")
+                    }
+                },
+                Pattern::Tuple { pats, .. } => match res {
+                    ColType::Any => res = ColType::Tuple(pats.len()),
+                    ColType::Tuple(arity) => {
+                        if arity != pats.len() {
                             panic!("pattern match not well-typed!")
                         }
                     }
+                    ColType::Lit(_) | ColType::Data(_) => {
+                        panic!("pattern match not well-typed!")
+                    }
                 },
                 Pattern::Wild { .. } => {}
+                Pattern::Or { .. } | Pattern::As { .. } => unreachable!(),
             }
         }
         res
@@ -526,7 +1420,7 @@ impl Normalize {
                 Pattern::Var { var, span } => {
                     let new = row[..j]
                         .iter()
-                        .chain(std::iter::repeat(&Pattern::Wild { span: *span }).take(arity))
+                        .chain(std::iter::repeat_n(&Pattern::Wild { span: *span }, arity))
                         .chain(row[j + 1..].iter())
                         .cloned()
                         .collect();
@@ -546,10 +1440,11 @@ impl Normalize {
                     Some((new, act.clone()))
                 }
                 Pattern::Cons { .. } => None,
+                Pattern::Tuple { .. } | Pattern::Or { .. } | Pattern::As { .. } => unreachable!(),
                 Pattern::Wild { span } => {
                     let new = row[..j]
                         .iter()
-                        .chain(std::iter::repeat(&Pattern::Wild { span: *span }).take(arity))
+                        .chain(std::iter::repeat_n(&Pattern::Wild { span: *span }, arity))
                         .chain(row[j + 1..].iter())
                         .cloned()
                         .collect();
@@ -577,7 +1472,7 @@ impl Normalize {
                 cont: Box::new(cont),
             });
 
-        let cont = new_objs
+        new_objs
             .into_iter()
             .enumerate()
             .fold(cont, |cont, (i, obj)| MExpr::Load {
@@ -585,20 +1480,116 @@ impl Normalize {
                 arg1: Atom::Var(matchee),
                 index: i + 1,
                 cont: Box::new(cont),
-            });
-
-        cont
+            })
     }
 
-    pub fn match_default(&mut self, mat: &PatnMatrix, j: usize) -> MExpr {
+    // tuple patterns always match, so instead of a switch we just load the fields
+    // (without tag) and keep matching on them
+    pub fn match_tuple(
+        &mut self,
+        mat: &PatnMatrix,
+        j: usize,
+        arity: usize,
+        hole: Ident,
+        ctx: MExpr,
+    ) -> MExpr {
         let matchee = mat.objs[j];
+        let new_objs: Vec<Ident> = (0..arity).map(|_| Ident::generate('o')).collect();
         let mut bindings: Vec<(Ident, Ident)> = Vec::new();
 
         let (matrix, acts): (Vec<Vec<_>>, _) = mat
             .matrix
             .iter()
             .zip(mat.acts.iter())
-            .flat_map(|(row, act)| match &row[j] {
+            .map(|(row, act)| match &row[j] {
+                Pattern::Var { var, span } => {
+                    let new = row[..j]
+                        .iter()
+                        .chain(std::iter::repeat_n(&Pattern::Wild { span: *span }, arity))
+                        .chain(row[j + 1..].iter())
+                        .cloned()
+                        .collect();
+                    bindings.push((*var, matchee));
+                    (new, act.clone())
+                }
+                Pattern::Tuple { pats, .. } => {
+                    assert_eq!(pats.len(), arity);
+                    let new = row[..j]
+                        .iter()
+                        .chain(pats.iter())
+                        .chain(row[j + 1..].iter())
+                        .cloned()
+                        .collect();
+                    (new, act.clone())
+                }
+                Pattern::Wild { span } => {
+                    let new = row[..j]
+                        .iter()
+                        .chain(std::iter::repeat_n(&Pattern::Wild { span: *span }, arity))
+                        .chain(row[j + 1..].iter())
+                        .cloned()
+                        .collect();
+                    (new, act.clone())
+                }
+                Pattern::Lit { .. }
+                | Pattern::Cons { .. }
+                | Pattern::Or { .. }
+                | Pattern::As { .. } => {
+                    unreachable!()
+                }
+            })
+            .unzip();
+
+        let objs = mat.objs[..j]
+            .iter()
+            .chain(new_objs.iter())
+            .chain(mat.objs[j + 1..].iter())
+            .cloned()
+            .collect();
+
+        let new_mat = PatnMatrix { objs, matrix, acts };
+        let cont = self.compile_match(&new_mat, hole, ctx);
+
+        let cont = bindings
+            .into_iter()
+            .fold(cont, |cont, (var, obj)| MExpr::UnOp {
+                bind: var,
+                prim: UnOpPrim::Move,
+                arg1: Atom::Var(obj),
+                cont: Box::new(cont),
+            });
+
+        new_objs
+            .into_iter()
+            .enumerate()
+            .fold(cont, |cont, (i, obj)| MExpr::Load {
+                bind: obj,
+                arg1: Atom::Var(matchee),
+                index: i,
+                cont: Box::new(cont),
+            })
+    }
+
+    pub fn match_int(&mut self, mat: &PatnMatrix, j: usize, lit: usize) -> MExpr {
+        let matchee = mat.objs[j];
+        let mut bindings: Vec<(Ident, Ident)> = Vec::new();
+
+        let (matrix, acts): (Vec<Vec<_>>, _) = mat
+            .matrix
+            .iter()
+            .zip(mat.acts.iter())
+            .flat_map(|(row, act)| match &row[j] {
+                Pattern::Lit {
+                    lit: LitVal::Int(x),
+                    ..
+                } if usize::try_from(*x) == Ok(lit) => {
+                    let new = row[..j]
+                        .iter()
+                        .chain(row[j + 1..].iter())
+                        .cloned()
+                        .collect();
+                    Some((new, act.clone()))
+                }
                 Pattern::Lit { .. } => None,
                 Pattern::Var { var, .. } => {
                     let new = row[..j]
@@ -609,7 +1600,12 @@ impl Normalize {
                     bindings.push((*var, matchee));
                     Some((new, act.clone()))
                 }
-                Pattern::Cons { .. } => None,
+                Pattern::Cons { .. }
+                | Pattern::Tuple { .. }
+                | Pattern::Or { .. }
+                | Pattern::As { .. } => {
+                    unreachable!()
+                }
                 Pattern::Wild { .. } => {
                     let new = row[..j]
                         .iter()
@@ -630,19 +1626,180 @@ impl Normalize {
         let new_mat = PatnMatrix { objs, matrix, acts };
         let cont = self.compile_match_top(&new_mat);
 
-        let cont = bindings
+        bindings
             .into_iter()
             .fold(cont, |cont, (var, obj)| MExpr::UnOp {
                 bind: var,
                 prim: UnOpPrim::Move,
                 arg1: Atom::Var(obj),
                 cont: Box::new(cont),
-            });
+            })
+    }
+
+    /// the rows that don't test column `j`, as the default branch of a switch,
+    /// whose result goes to the join point of the switch
+    pub fn match_default_top(&mut self, mat: &PatnMatrix, j: usize) -> MExpr {
+        let r = Ident::generate('r');
+        self.match_default(mat, j, r, MExpr::Retn { arg1: Atom::Var(r) })
+    }
+
+    pub fn match_default(&mut self, mat: &PatnMatrix, j: usize, hole: Ident, ctx: MExpr) -> MExpr {
+        let matchee = mat.objs[j];
+        let mut bindings: Vec<(Ident, Ident)> = Vec::new();
+
+        let (matrix, acts): (Vec<Vec<_>>, _) = mat
+            .matrix
+            .iter()
+            .zip(mat.acts.iter())
+            .flat_map(|(row, act)| match &row[j] {
+                Pattern::Lit { .. } => None,
+                Pattern::Var { var, .. } => {
+                    let new = row[..j]
+                        .iter()
+                        .chain(row[j + 1..].iter())
+                        .cloned()
+                        .collect();
+                    bindings.push((*var, matchee));
+                    Some((new, act.clone()))
+                }
+                Pattern::Cons { .. } => None,
+                Pattern::Tuple { .. } => None,
+                Pattern::Or { .. } | Pattern::As { .. } => unreachable!(),
+                Pattern::Wild { .. } => {
+                    let new = row[..j]
+                        .iter()
+                        .chain(row[j + 1..].iter())
+                        .cloned()
+                        .collect();
+                    Some((new, act.clone()))
+                }
+            })
+            .unzip();
 
-        cont
+        let objs = mat.objs[..j]
+            .iter()
+            .chain(mat.objs[j + 1..].iter())
+            .cloned()
+            .collect();
+
+        let new_mat = PatnMatrix { objs, matrix, acts };
+        let cont = self.compile_match(&new_mat, hole, ctx);
+
+        bindings
+            .into_iter()
+            .fold(cont, |cont, (var, obj)| MExpr::UnOp {
+                bind: var,
+                prim: UnOpPrim::Move,
+                arg1: Atom::Var(obj),
+                cont: Box::new(cont),
+            })
     }
 }
 
+/// message of the panic when no rule of a `case` matches
+pub static MATCH_FAILURE: &str = "pattern match failed!";
+
+/// message of the panic when a divisor is zero at runtime
+pub static DIV_BY_ZERO: &str = "division by zero!";
+
+/// name of the C function that suspends the running coroutine
+pub static YIELD: &str = "norem_yield";
+
+/// name of the C function that starts a thread, given a closure and a stack size
+pub static SPAWN: &str = "norem_spawn";
+
+/// name of the C function that waits for a thread and returns its result
+pub static JOIN: &str = "norem_join";
+
+/// names of the C functions on channels, `norem_chan_recv` also takes the option constructors
+pub static CHAN_NEW: &str = "norem_chan_new";
+pub static CHAN_SEND: &str = "norem_chan_send";
+pub static CHAN_RECV: &str = "norem_chan_recv";
+pub static CHAN_CLOSE: &str = "norem_chan_close";
+
+/// names of the C functions on mutexes, `norem_with_mutex` also takes the closure to call
+pub static MUTEX_NEW: &str = "norem_mutex_new";
+pub static MUTEX_LOCK: &str = "norem_mutex_lock";
+pub static MUTEX_UNLOCK: &str = "norem_mutex_unlock";
+pub static WITH_MUTEX: &str = "norem_with_mutex";
+
+/// names of the C functions on semaphores
+pub static SEM_NEW: &str = "norem_sem_new";
+pub static SEM_POST: &str = "norem_sem_post";
+pub static SEM_WAIT: &str = "norem_sem_wait";
+pub static SEM_TRY_WAIT: &str = "norem_sem_trywait";
+
+/// names of the C functions reading and writing whole files and listing directories,
+/// they also take the result constructors, and `norem_list_dir` the list constructors
+pub static READ_FILE: &str = "norem_read_file";
+pub static WRITE_FILE: &str = "norem_write_file";
+pub static LIST_DIR: &str = "norem_list_dir";
+
+/// names of the C functions manipulating paths, they always give a new string
+pub static PATH_JOIN: &str = "norem_path_join";
+pub static PATH_BASE: &str = "norem_path_base";
+pub static PATH_DIR: &str = "norem_path_dir";
+
+/// the number of fields from which a constant aggregate is laid out at compile time,
+/// smaller ones are still built when they are evaluated
+pub static CONST_DATA_MIN: usize = 16;
+
+/// name of the C function formatting `@format` into a new string, it takes the format
+/// rewritten by `parse_format` and the arguments as C varargs
+pub static FORMAT: &str = "norem_format";
+
+/// check that an exhaustive switch over constructor tags has exactly one branch
+/// for each tag in `0..tag_num`
+/// operator functions like `<+>` get a name that is valid in C, like `op_lt_plus_gt`
+fn mangle(var: Ident) -> Ident {
+    if !var.name.starts_with(is_opr_char) {
+        return var;
+    }
+    let name = var
+        .name
+        .chars()
+        .map(|ch| match ch {
+            ':' => "colon",
+            '!' => "bang",
+            '#' => "hash",
+            '$' => "dollar",
+            '%' => "percent",
+            '&' => "amp",
+            '*' => "star",
+            '+' => "plus",
+            '.' => "dot",
+            '/' => "slash",
+            '<' => "lt",
+            '=' => "eq",
+            '>' => "gt",
+            '?' => "qmark",
+            '@' => "at",
+            '\\' => "bslash",
+            '^' => "caret",
+            '|' => "bar",
+            '-' => "minus",
+            '~' => "tilde",
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>()
+        .join("_");
+    Ident {
+        name: InternStr::new(format!("op_{name}")),
+        index: var.index,
+    }
+}
+
+pub fn verify_tag_switch(brchs: &[(usize, MExpr)], tag_num: usize) -> bool {
+    let mut seen = vec![false; tag_num];
+    for (i, _) in brchs {
+        match seen.get_mut(*i) {
+            Some(flag) if !*flag => *flag = true,
+            _ => return false,
+        }
+    }
+    seen.into_iter().all(|flag| flag)
+}
+
 pub fn subst(expr: MExpr, hole: Ident, atom: Atom) -> MExpr {
     // subst(expr,hole,atom) ~=~ let hole = move(atom); expr
     // it will be substituted in constant-fold pass anyway
@@ -658,6 +1815,7 @@ pub fn subst(expr: MExpr, hole: Ident, atom: Atom) -> MExpr {
 enum ColType {
     Any,
     Data(Ident),
+    Tuple(usize),
     Lit(LitType),
 }
 
@@ -669,6 +1827,66 @@ pub struct PatnMatrix {
     acts: Vec<MExpr>,
 }
 
+/// every alternative of every rule is a tuple of `arity` elements or a wildcard,
+/// so the scrutinee can be matched one element at a time
+fn is_columns(rules: &[Rule], arity: usize) -> bool {
+    rules.iter().all(|rule| {
+        rule.patn.expand_or().iter().all(|alt| match alt {
+            Pattern::Tuple { pats, .. } => pats.len() == arity,
+            Pattern::Wild { .. } => true,
+            _ => false,
+        })
+    })
+}
+
+/// whether an argument of `@format` fits the type of its specifier, as far as it is known
+/// without the type checker: a literal has its own type, and a constructor, a tuple, a record
+/// or a function is never a literal. `None` is an argument whose type isn't known here.
+fn format_arg_fits(typ: &LitType, arg: &Expr) -> Option<bool> {
+    match arg {
+        Expr::Lit { lit, .. } => Some(lit.get_lit_type() == *typ),
+        Expr::Anno { expr, .. } => format_arg_fits(typ, expr),
+        Expr::Cons { .. } | Expr::Tuple { .. } | Expr::Record { .. } | Expr::Fun { .. } => {
+            Some(false)
+        }
+        _ => None,
+    }
+}
+
+/// rename every variable bound by `patn`, recording the new names in `map`
+fn uniquify_pattern(patn: &Pattern, map: &mut HashMap<Ident, Ident>) -> Pattern {
+    match patn {
+        Pattern::Var { var, span } => {
+            let new = var.uniquify();
+            map.insert(*var, new);
+            Pattern::Var {
+                var: new,
+                span: *span,
+            }
+        }
+        Pattern::Lit { .. } | Pattern::Wild { .. } => patn.clone(),
+        Pattern::Cons { cons, pars, span } => Pattern::Cons {
+            cons: *cons,
+            pars: pars.iter().map(|par| uniquify_pattern(par, map)).collect(),
+            span: *span,
+        },
+        Pattern::Tuple { pats, span } => Pattern::Tuple {
+            pats: pats.iter().map(|pat| uniquify_pattern(pat, map)).collect(),
+            span: *span,
+        },
+        Pattern::Or { .. } => unreachable!("or-patterns are expanded first"),
+        Pattern::As { bind, patn, span } => {
+            let new = bind.uniquify();
+            map.insert(*bind, new);
+            Pattern::As {
+                bind: new,
+                patn: Box::new(uniquify_pattern(patn, map)),
+                span: *span,
+            }
+        }
+    }
+}
+
 impl PatnMatrix {
     #[allow(dead_code)]
     fn get_row_num(&self) -> usize {
@@ -702,11 +1920,47 @@ impl PatnMatrix {
                 Pattern::Cons { cons, .. } => {
                     set.insert(*cons);
                 }
+                Pattern::Tuple { .. } => {}
                 Pattern::Wild { .. } => {}
+                Pattern::Or { .. } | Pattern::As { .. } => unreachable!(),
             }
         }
         set
     }
+
+    /// replace every alias `x @ p` by `p`, with the aliases and the objects they bind.
+    /// `None` if there are no aliases
+    fn strip_aliases(&self) -> Option<(PatnMatrix, Vec<(Ident, Ident)>)> {
+        let mut aliases = Vec::new();
+        let matrix = self
+            .matrix
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(self.objs.iter())
+                    .map(|(mut patn, obj)| {
+                        while let Pattern::As {
+                            bind, patn: inner, ..
+                        } = patn
+                        {
+                            aliases.push((*bind, *obj));
+                            patn = inner;
+                        }
+                        patn.clone()
+                    })
+                    .collect()
+            })
+            .collect();
+        if aliases.is_empty() {
+            return None;
+        }
+        let mat = PatnMatrix {
+            objs: self.objs.clone(),
+            matrix,
+            acts: self.acts.clone(),
+        };
+        Some((mat, aliases))
+    }
 }
 
 #[test]
@@ -770,6 +2024,95 @@ f(42)
     assert_eq!(expr1, expr2);
 }
 
+#[test]
+fn normalize_operator_test() {
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+
+    let string = r#"
+begin
+    infixl 6 <+>;
+    fun (<+>)(a, b) => @iadd(a, b)
+in
+    1 <+> 2
+end
+    "#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let expr1 = Normalize::run(&expr1);
+    let MExpr::LetIn { decls, .. } = &expr1 else {
+        panic!("expected a let-block, found {expr1}");
+    };
+    // operator functions get a name that is valid in C
+    assert_eq!(&*decls[0].func.name, "op_lt_plus_gt");
+    assert!(format!("{expr1}").contains(&format!("{}(", decls[0].func)));
+}
+
+#[test]
+fn normalize_tuple_test() {
+    use super::anf_build::*;
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+
+    let string = r#"
+(1, 2).1
+    "#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let expr1 = Normalize::run(&expr1);
+    let expr2 = chain(vec![
+        _move("x1", i(2)),
+        _move("x2", i(1)),
+        alloc("m", 2),
+        store(v("m"), 1, v("x1")),
+        store(v("m"), 0, v("x2")),
+        _move("t", v("m")),
+        load("r", v("t"), 1),
+        retn(v("r")),
+    ]);
+    assert_eq!(expr1, expr2);
+}
+
+#[test]
+fn normalize_ifte_test() {
+    use super::anf_build::*;
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+
+    let string = r#"
+if true then 1 else if false then 2 else 3
+    "#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let expr1 = Normalize::run(&expr1);
+    let expr2 = chain(vec![
+        _move("x1", b(true)),
+        ifte(
+            "r1",
+            v("x1"),
+            chain(vec![_move("r2", i(1)), retn(v("r2"))]),
+            chain(vec![
+                _move("x2", b(false)),
+                ifte(
+                    "r3",
+                    v("x2"),
+                    chain(vec![_move("r4", i(2)), retn(v("r4"))]),
+                    chain(vec![_move("r5", i(3)), retn(v("r5"))]),
+                ),
+                retn(v("r3")),
+            ]),
+        ),
+        retn(v("r1")),
+    ]);
+    assert_eq!(expr1, expr2);
+}
+
 #[test]
 #[ignore]
 fn normalize_pattern_match_test() {
@@ -801,3 +2144,349 @@ end
     let expr1 = Normalize::run(&expr1);
     println!("{expr1}");
 }
+
+#[test]
+fn normalize_tuple_pattern_test() {
+    use super::anf_build::*;
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+    let string = r#"
+case ((1, 2), 3) of
+| ((x, _), z) => { @iadd(x, z) }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let expr1 = Normalize::run(&expr1);
+    // the outer tuple is matched in place, the inner one is built
+    let expr2 = chain(vec![
+        _move("x2", i(2)),
+        _move("x3", i(1)),
+        alloc("m1", 2),
+        store(v("m1"), 1, v("x2")),
+        store(v("m1"), 0, v("x3")),
+        _move("o3", v("m1")),
+        _move("o2", i(3)),
+        let_in(
+            vec![fun(
+                "a",
+                vec!["z", "x"],
+                chain(vec![
+                    _move("x6", v("z")),
+                    _move("x7", v("x")),
+                    iadd("r1", v("x7"), v("x6")),
+                    retn(v("r1")),
+                ]),
+            )],
+            vec![
+                load("o4", v("o3"), 1),
+                load("o5", v("o3"), 0),
+                _move("z", v("o2")),
+                _move("x", v("o5")),
+                call("r2", "a", vec![v("z"), v("x")]),
+                retn(v("r2")),
+            ],
+        ),
+    ]);
+    assert_eq!(expr1, expr2);
+}
+
+#[test]
+fn normalize_as_pattern_test() {
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+    // the alias is bound to the object being matched, the tuple is built once
+    let string = r#"
+case (1, 2) of
+| p @ (x, _) => { @iadd(x, p.1) }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr = rnm.visit_expr(expr);
+    let text = format!("{}", Normalize::run(&expr));
+    assert_eq!(text.matches("alloc[").count(), 1);
+    assert!(text
+        .lines()
+        .any(|line| line.trim_start().starts_with("let p_") && line.contains("= move(o_")));
+}
+
+#[cfg(test)]
+fn find_switch(expr: &MExpr) -> Option<&MExpr> {
+    match expr {
+        MExpr::Switch { .. } => Some(expr),
+        MExpr::LetIn { decls, cont } => decls
+            .iter()
+            .find_map(|decl| find_switch(&decl.body))
+            .or_else(|| find_switch(cont)),
+        MExpr::UnOp { cont, .. }
+        | MExpr::BinOp { cont, .. }
+        | MExpr::Call { cont, .. }
+        | MExpr::ExtCall { cont, .. }
+        | MExpr::Alloc { cont, .. }
+        | MExpr::Load { cont, .. }
+        | MExpr::Store { cont, .. }
+        | MExpr::Offset { cont, .. } => find_switch(cont),
+        MExpr::Ifte {
+            brch1, brch2, cont, ..
+        } => find_switch(brch1)
+            .or_else(|| find_switch(brch2))
+            .or_else(|| find_switch(cont)),
+        MExpr::Retn { .. } | MExpr::TailCall { .. } | MExpr::Panic { .. } => None,
+    }
+}
+
+#[test]
+fn normalize_switch_default_test() {
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+
+    // exhaustive match on three constructors, no default arm
+    let string = r#"
+begin
+    data Color =
+    | Red
+    | Green
+    | Blue
+    end
+    fun to-int(c) => {
+        case c of
+        | Red => { 0 }
+        | Green => { 1 }
+        | Blue => { 2 }
+        end
+    }
+in
+    to-int(Green)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let expr1 = Normalize::run(&expr1);
+    match find_switch(&expr1) {
+        Some(MExpr::Switch { brchs, dflt, .. }) => {
+            assert_eq!(brchs.len(), 3);
+            assert!(dflt.is_none());
+        }
+        _ => panic!("test failed!"),
+    }
+    assert!(!format!("{expr1}").contains("default:"));
+
+    // integer match falls through to the match failure
+    let string = r#"
+case 5 of
+| 1 => { 10 }
+| 2 => { 20 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let expr1 = rnm.visit_expr(expr1);
+    let expr1 = Normalize::run(&expr1);
+    match find_switch(&expr1) {
+        Some(MExpr::Switch { brchs, dflt, .. }) => {
+            assert_eq!(
+                brchs.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+                vec![1, 2]
+            );
+            match dflt.as_deref() {
+                Some(MExpr::Panic { msg }) => assert_eq!(*msg, InternStr::new(MATCH_FAILURE)),
+                _ => panic!("test failed!"),
+            }
+        }
+        _ => panic!("test failed!"),
+    }
+
+    // corrupted switches are rejected by the verifier
+    let brchs = vec![(0, MExpr::Retn { arg1: Atom::Unit })];
+    assert!(verify_tag_switch(&brchs, 1));
+    assert!(!verify_tag_switch(&brchs, 2));
+    let brchs = vec![
+        (0, MExpr::Retn { arg1: Atom::Unit }),
+        (0, MExpr::Retn { arg1: Atom::Unit }),
+        (3, MExpr::Retn { arg1: Atom::Unit }),
+    ];
+    assert!(!verify_tag_switch(&brchs, 3));
+}
+
+#[test]
+fn normalize_cold_branch_test() {
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+
+    // the match failure is the only cold branch
+    let string = r#"
+case 5 of
+| 1 => { 10 }
+| 2 => { 20 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let expr1 = Normalize::run(&expr1);
+    match find_switch(&expr1) {
+        Some(MExpr::Switch { cold, .. }) => assert!(cold.0.iter().eq([2].iter())),
+        _ => panic!("test failed!"),
+    }
+
+    // so is a branch that calls a `@cold` function on every path
+    let string = r#"
+begin
+    @cold
+    fun fail(x) => { x }
+    fun check(x) => {
+        if @icmplt(x, 0) then { let y = fail(x); y } else x
+    }
+in
+    check(1)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let expr1 = rnm.visit_expr(expr1);
+    let text = format!("{}", Normalize::run(&expr1));
+    assert!(text.contains(") then cold"));
+    assert!(!text.contains("else cold"));
+}
+
+#[test]
+fn normalize_div_check_test() {
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+
+    // a dynamic divisor is checked, and the failing branch is cold
+    let string = r#"
+fun(x, y) => @iadd(@idiv(x, y), @irem(x, 3))
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let res = Normalize::run_checked(&expr1, Some(HashMap::new()), true).unwrap();
+    let text = format!("{res}");
+    assert_eq!(text.matches(DIV_BY_ZERO).count(), 1);
+    assert!(text.contains(") then cold"));
+
+    // unless the check is turned off
+    let res = Normalize::run_checked(&expr1, Some(HashMap::new()), false).unwrap();
+    assert!(!format!("{res}").contains(DIV_BY_ZERO));
+
+    // a literal zero divisor is an error
+    let string = r#"
+@irem(5, 0)
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let expr1 = rnm.visit_expr(expr1);
+    let diags = Normalize::run_checked(&expr1, Some(HashMap::new()), false).unwrap_err();
+    assert_eq!(diags.len(), 1);
+}
+
+#[test]
+fn normalize_const_data_test() {
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+    use itertools::Itertools;
+    // the table nests too deep for the default stack of a test thread
+    std::thread::Builder::new()
+        .stack_size(16 * 1024 * 1024)
+        .spawn(move || {
+
+            // a big literal table is laid out at compile time, building it stores nothing
+            let elems = (0..500).map(|i| format!("(\"key{i}\", {i})")).join(", ");
+            let string = format!(
+                "begin data List[T] = | Cons(T, List[T]) | Nil end in let table = [{elems}]; table end"
+            );
+            let mut par = Parser::new(&string);
+            let expr1 = parse_expr(&mut par).unwrap();
+            let mut rnm = Renamer::new();
+            let expr1 = rnm.visit_expr(expr1);
+            let res = Normalize::run(&expr1);
+            let text = format!("{res}");
+            assert_eq!(text.matches("alloc[").count(), 0);
+            assert_eq!(text.matches("store ").count(), 0);
+
+            // each cell is a constant, with the tag of its constructor first
+            let MExpr::UnOp {
+                arg1: Atom::Const(table),
+                ..
+            } = find_move(&res).unwrap()
+            else {
+                panic!("expected a constant, found {res}");
+            };
+            let fields = table.fields();
+            assert_eq!(fields[0], Atom::Int(0));
+            let Atom::Const(entry) = fields[1] else {
+                panic!("expected a constant entry, found {}", fields[1]);
+            };
+            let key = InternStr::new("key0");
+            assert_eq!(entry.fields(), vec![Atom::Str(key), Atom::Int(0)]);
+
+            // a small one is still built where it is used
+            let string = "(1, 2, 3)";
+            let mut par = Parser::new(string);
+            let expr1 = parse_expr(&mut par).unwrap();
+            let expr1 = rnm.visit_expr(expr1);
+            let text = format!("{}", Normalize::run(&expr1));
+            assert_eq!(text.matches("store ").count(), 3);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[cfg(test)]
+fn find_move(expr: &MExpr) -> Option<&MExpr> {
+    match expr {
+        MExpr::UnOp {
+            prim: UnOpPrim::Move,
+            arg1: Atom::Const(_),
+            ..
+        } => Some(expr),
+        MExpr::UnOp { cont, .. } => find_move(cont),
+        MExpr::LetIn { cont, .. } => find_move(cont),
+        _ => None,
+    }
+}
+
+#[test]
+fn normalize_case_columns_test() {
+    use crate::frontend::parser::*;
+    use crate::frontend::renamer::Renamer;
+
+    // the elements of a tuple scrutinee are matched in place
+    let string = r#"
+case (1, 2) of
+| (0, _) => { 1 }
+| (_, 0) => { 2 }
+| _ => { 3 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr1 = rnm.visit_expr(expr1);
+    let text = format!("{}", Normalize::run(&expr1));
+    assert!(!text.contains("alloc"));
+    assert!(!text.contains("load"));
+
+    // a pattern binding the whole tuple still needs it
+    let string = r#"
+case (1, 2) of
+| (0, _) => { 1 }
+| p => { 2 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let expr1 = rnm.visit_expr(expr1);
+    let text = format!("{}", Normalize::run(&expr1));
+    assert!(text.contains("alloc[2]"));
+}
@@ -1,7 +1,33 @@
 use super::*;
+use crate::frontend::ast::OptLevel;
 use crate::utils::env_map::{EnvMap, FreeSet};
 use std::collections::{HashMap, HashSet};
 
+/// Context shared by the optimization passes.
+/// It holds the `@optimize(...)` attributes of toplevel functions, by function name.
+#[derive(Clone, Debug, Default)]
+pub struct PassCtx {
+    attrs: HashMap<InternStr, OptLevel>,
+}
+
+impl PassCtx {
+    pub fn new(attrs: HashMap<InternStr, OptLevel>) -> PassCtx {
+        PassCtx { attrs }
+    }
+    /// the optimization level of a function, `None` means fully optimized
+    pub fn opt_level(&self, func: &Ident) -> Option<OptLevel> {
+        self.attrs.get(&func.name).copied()
+    }
+    /// the body of the function must be copied through untouched
+    fn is_frozen(&self, func: &Ident) -> bool {
+        self.opt_level(func) == Some(OptLevel::Never)
+    }
+    /// the function is neither inlined nor inlined into
+    pub fn no_inline(&self, func: &Ident) -> bool {
+        self.opt_level(func).is_some()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ConstFold {
     atom_map: EnvMap<Ident, Atom>,
@@ -9,20 +35,25 @@ pub struct ConstFold {
     store_map: EnvMap<(Ident, usize), Atom>,
     offset_map: EnvMap<Ident, (Ident, usize)>,
     ret_stack: Vec<(Ident, MExpr)>,
+    ctx: PassCtx,
 }
 
 impl ConstFold {
     pub fn run(expr: MExpr) -> MExpr {
-        let mut pass = ConstFold::new();
+        ConstFold::run_with_ctx(expr, &PassCtx::default())
+    }
+    pub fn run_with_ctx(expr: MExpr, ctx: &PassCtx) -> MExpr {
+        let mut pass = ConstFold::new(ctx.clone());
         pass.visit_expr(expr)
     }
-    fn new() -> ConstFold {
+    fn new(ctx: PassCtx) -> ConstFold {
         ConstFold {
             atom_map: EnvMap::new(),
             alloc_map: EnvMap::new(),
             store_map: EnvMap::new(),
             offset_map: EnvMap::new(),
             ret_stack: Vec::new(),
+            ctx,
         }
     }
     #[allow(dead_code)]
@@ -30,8 +61,8 @@ impl ConstFold {
         self.offset_map
             .get(&var)
             .map(|(var2, index2)| {
-                assert!(self.alloc_map.contains_key(&var2));
-                assert!(self.alloc_map[&var2] > *index2 + index);
+                assert!(self.alloc_map.contains_key(var2));
+                assert!(self.alloc_map[var2] > *index2 + index);
                 (*var2, *index2 + index)
             })
             .unwrap_or_else(|| {
@@ -53,16 +84,24 @@ impl ConstFold {
         self.offset_map.leave_scope();
     }
     fn visit_brch(&mut self, brch: MExpr) -> MExpr {
+        // the `return` of a branch goes to its own join point, not to a folded one outside
+        let ret_stack = std::mem::take(&mut self.ret_stack);
         self.enter_scope();
         let res = self.visit_expr(brch);
         self.leave_scope();
+        self.ret_stack = ret_stack;
         res
     }
     fn visit_decl(&mut self, decl: MDecl) -> MDecl {
+        if self.ctx.is_frozen(&decl.func) {
+            return decl;
+        }
         let MDecl { func, pars, body } = decl;
+        let ret_stack = std::mem::take(&mut self.ret_stack);
         self.enter_scope();
         let body = self.visit_expr(body);
         self.leave_scope();
+        self.ret_stack = ret_stack;
         MDecl { func, pars, body }
     }
     fn visit_arg(&mut self, arg: Atom) -> Atom {
@@ -96,10 +135,43 @@ impl ConstFold {
                         self.atom_map.insert(bind, *arg1);
                         return self.visit_expr(*cont);
                     }
-                    (INeg, Int(a)) => {
+                    // -a, an overflow is left to the runtime
+                    (INeg, Int(a)) if a.checked_neg().is_some() => {
                         self.atom_map.insert(bind, Int(-a));
                         return self.visit_expr(*cont);
                     }
+                    (Trunc32 | ZeroExt32, Int(a)) => {
+                        self.atom_map.insert(bind, Int(*a as u32 as i64));
+                        return self.visit_expr(*cont);
+                    }
+                    (SignExt32, Int(a)) => {
+                        self.atom_map.insert(bind, Int(*a as i32 as i64));
+                        return self.visit_expr(*cont);
+                    }
+                    (BNot, Bool(a)) => {
+                        self.atom_map.insert(bind, Bool(!a));
+                        return self.visit_expr(*cont);
+                    }
+                    (Sinh, Real(a)) => {
+                        self.atom_map.insert(bind, Real(a.sinh()));
+                        return self.visit_expr(*cont);
+                    }
+                    (Cosh, Real(a)) => {
+                        self.atom_map.insert(bind, Real(a.cosh()));
+                        return self.visit_expr(*cont);
+                    }
+                    (Tanh, Real(a)) => {
+                        self.atom_map.insert(bind, Real(a.tanh()));
+                        return self.visit_expr(*cont);
+                    }
+                    (Erf, Real(a)) => {
+                        self.atom_map.insert(bind, Real(erf(*a)));
+                        return self.visit_expr(*cont);
+                    }
+                    (Erfc, Real(a)) => {
+                        self.atom_map.insert(bind, Real(erfc(*a)));
+                        return self.visit_expr(*cont);
+                    }
                     _ => {}
                 }
                 MExpr::UnOp {
@@ -119,8 +191,8 @@ impl ConstFold {
                 use Atom::*;
                 use BinOpPrim::*;
                 match &(prim, arg1, arg2) {
-                    // a + b
-                    (IAdd, Int(a), Int(b)) => {
+                    // a + b, an overflow is left to the runtime
+                    (IAdd, Int(a), Int(b)) if a.checked_add(*b).is_some() => {
                         self.atom_map.insert(bind, Int(a + b));
                         return self.visit_expr(*cont);
                     }
@@ -130,7 +202,7 @@ impl ConstFold {
                         return self.visit_expr(*cont);
                     }
                     // a - b
-                    (ISub, Int(a), Int(b)) => {
+                    (ISub, Int(a), Int(b)) if a.checked_sub(*b).is_some() => {
                         self.atom_map.insert(bind, Int(a - b));
                         return self.visit_expr(*cont);
                     }
@@ -145,7 +217,7 @@ impl ConstFold {
                         return self.visit_expr(*cont);
                     }
                     // a * b
-                    (IMul, Int(a), Int(b)) => {
+                    (IMul, Int(a), Int(b)) if a.checked_mul(*b).is_some() => {
                         self.atom_map.insert(bind, Int(a * b));
                         return self.visit_expr(*cont);
                     }
@@ -159,6 +231,68 @@ impl ConstFold {
                         self.atom_map.insert(bind, Var(*x));
                         return self.visit_expr(*cont);
                     }
+                    // a / b and a % b, a zero divisor or an overflow is left to fail at runtime
+                    (IDiv, Int(a), Int(b)) if a.checked_div(*b).is_some() => {
+                        self.atom_map.insert(bind, Int(a / b));
+                        return self.visit_expr(*cont);
+                    }
+                    (IRem, Int(a), Int(b)) if a.checked_rem(*b).is_some() => {
+                        self.atom_map.insert(bind, Int(a % b));
+                        return self.visit_expr(*cont);
+                    }
+                    // x / 1 = x
+                    (IDiv, Var(x), Int(1)) => {
+                        self.atom_map.insert(bind, Var(*x));
+                        return self.visit_expr(*cont);
+                    }
+                    (ICmpEq, Int(a), Int(b)) => {
+                        self.atom_map.insert(bind, Bool(a == b));
+                        return self.visit_expr(*cont);
+                    }
+                    (ICmpNe, Int(a), Int(b)) => {
+                        self.atom_map.insert(bind, Bool(a != b));
+                        return self.visit_expr(*cont);
+                    }
+                    (ICmpLt, Int(a), Int(b)) => {
+                        self.atom_map.insert(bind, Bool(a < b));
+                        return self.visit_expr(*cont);
+                    }
+                    (ICmpLe, Int(a), Int(b)) => {
+                        self.atom_map.insert(bind, Bool(a <= b));
+                        return self.visit_expr(*cont);
+                    }
+                    (ICmpGt, Int(a), Int(b)) => {
+                        self.atom_map.insert(bind, Bool(a > b));
+                        return self.visit_expr(*cont);
+                    }
+                    (ICmpGe, Int(a), Int(b)) => {
+                        self.atom_map.insert(bind, Bool(a >= b));
+                        return self.visit_expr(*cont);
+                    }
+                    // real arithmetic follows IEEE-754 exactly, so division by zero gives an
+                    // infinity or NaN instead of an error. the result never depends on the
+                    // rounding mode at runtime, since it is always round-to-nearest in C.
+                    (RAdd, Real(a), Real(b)) => {
+                        self.atom_map.insert(bind, Real(a + b));
+                        return self.visit_expr(*cont);
+                    }
+                    (RSub, Real(a), Real(b)) => {
+                        self.atom_map.insert(bind, Real(a - b));
+                        return self.visit_expr(*cont);
+                    }
+                    (RMul, Real(a), Real(b)) => {
+                        self.atom_map.insert(bind, Real(a * b));
+                        return self.visit_expr(*cont);
+                    }
+                    (RDiv, Real(a), Real(b)) => {
+                        self.atom_map.insert(bind, Real(a / b));
+                        return self.visit_expr(*cont);
+                    }
+                    // a known condition needs no hint
+                    (Expect, Bool(a), _) => {
+                        self.atom_map.insert(bind, Bool(*a));
+                        return self.visit_expr(*cont);
+                    }
                     _ => {}
                 }
                 MExpr::BinOp {
@@ -240,6 +374,7 @@ impl ConstFold {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 if let Atom::Bool(p) = arg1 {
@@ -255,6 +390,7 @@ impl ConstFold {
                         arg1,
                         brch1,
                         brch2,
+                        cold,
                         cont,
                     }
                 }
@@ -264,6 +400,7 @@ impl ConstFold {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 if let Atom::Int(x) = arg1 {
@@ -283,6 +420,7 @@ impl ConstFold {
                         arg1,
                         brchs,
                         dflt,
+                        cold,
                         cont,
                     }
                 }
@@ -295,6 +433,25 @@ impl ConstFold {
                     MExpr::Retn { arg1 }
                 }
             }
+            MExpr::TailCall { func, args } => {
+                // the result goes to the join point instead, it's a call like any other
+                if let Some((bind, cont)) = self.ret_stack.pop() {
+                    let cont = Box::new(cont);
+                    MExpr::Call {
+                        bind,
+                        func,
+                        args,
+                        cont,
+                    }
+                } else {
+                    MExpr::TailCall { func, args }
+                }
+            }
+            MExpr::Panic { msg } => {
+                // the join point of a folded branch is never reached
+                self.ret_stack.pop();
+                MExpr::Panic { msg }
+            }
             other => other,
         };
         expr.walk_brch(|brch| self.visit_brch(brch))
@@ -311,9 +468,9 @@ where
     let mut new = HashSet::new();
     loop {
         for key in &set {
-            if let Some(val) = graph.get(&key) {
+            if let Some(val) = graph.get(key) {
                 for x in val {
-                    if !set.contains(&x) {
+                    if !set.contains(x) {
                         new.insert(*x);
                     }
                 }
@@ -331,22 +488,77 @@ where
     Dead-Code Elimination Pass
 */
 
+// rust std doesn't provide the error function, so we evaluate it ourselves.
+// for small inputs we use the series
+//   erf(x) = 2/sqrt(pi) * exp(-x^2) * sum_{n>=0} 2^n x^(2n+1) / (1*3*...*(2n+1))
+// whose terms are all positive, and for large inputs the continued fraction
+//   erfc(x) = exp(-x^2)/sqrt(pi) * 1/(x + (1/2)/(x + 1/(x + (3/2)/(x + ...))))
+fn erf_series(x: f64) -> f64 {
+    let x2 = x * x;
+    let mut sum = 0.0;
+    let mut term = x;
+    let mut n = 0.0;
+    while term > sum * f64::EPSILON {
+        sum += term;
+        n += 1.0;
+        term *= 2.0 * x2 / (2.0 * n + 1.0);
+    }
+    std::f64::consts::FRAC_2_SQRT_PI * (-x2).exp() * sum
+}
+
+fn erfc_cont_frac(x: f64) -> f64 {
+    let mut t = x;
+    for k in (1..=60).rev() {
+        t = x + (k as f64 / 2.0) / t;
+    }
+    std::f64::consts::FRAC_2_SQRT_PI / 2.0 * (-x * x).exp() / t
+}
+
+fn erf(x: f64) -> f64 {
+    if x.is_nan() {
+        x
+    } else if x < 0.0 {
+        -erf(-x)
+    } else if x < 3.0 {
+        erf_series(x)
+    } else {
+        1.0 - erfc_cont_frac(x)
+    }
+}
+
+fn erfc(x: f64) -> f64 {
+    if x.is_nan() {
+        x
+    } else if x < 0.0 {
+        2.0 - erfc(-x)
+    } else if x < 3.0 {
+        1.0 - erf_series(x)
+    } else {
+        erfc_cont_frac(x)
+    }
+}
+
 pub struct DeadElim {
     free_set: FreeSet<Ident>,
     load_map: EnvMap<Ident, HashSet<usize>>,
     ret_used: Vec<bool>,
+    ctx: PassCtx,
 }
 
 impl DeadElim {
     pub fn run(expr: MExpr) -> MExpr {
-        let mut pass = DeadElim::new();
+        DeadElim::run_with_ctx(expr, &PassCtx::default())
+    }
+    pub fn run_with_ctx(expr: MExpr, ctx: &PassCtx) -> MExpr {
+        let mut pass = DeadElim::new(ctx.clone());
         pass.visit_expr(expr)
     }
-    fn new() -> DeadElim {
+    fn new(ctx: PassCtx) -> DeadElim {
         DeadElim {
             free_set: FreeSet::new(),
             load_map: EnvMap::new(),
             ret_used: vec![true],
+            ctx,
         }
     }
     fn enter_scope(&mut self) {
@@ -363,6 +575,13 @@ impl DeadElim {
         }
         arg
     }
+    /// record the variables used by a frozen body, without eliminating anything
+    fn visit_frozen(&mut self, expr: MExpr) -> MExpr {
+        expr.walk_arg(|arg| self.visit_arg(arg))
+            .walk_brch(|brch| self.visit_frozen(brch))
+            .walk_decl(|decl| decl.walk_body(|body| self.visit_frozen(body)))
+            .walk_cont(|cont| self.visit_frozen(cont))
+    }
     fn visit_expr(&mut self, expr: MExpr) -> MExpr {
         let expr = expr.walk_cont(|cont| self.visit_expr(cont));
         let expr = match expr {
@@ -380,7 +599,11 @@ impl DeadElim {
                     .map(|decl| {
                         let MDecl { func, pars, body } = decl;
                         self.enter_scope();
-                        let body = self.visit_expr(body);
+                        let body = if self.ctx.is_frozen(&func) {
+                            self.visit_frozen(body)
+                        } else {
+                            self.visit_expr(body)
+                        };
                         let set = self
                             .free_set
                             .iter()
@@ -392,11 +615,8 @@ impl DeadElim {
                     })
                     .unzip();
 
-                let graph: HashMap<Ident, HashSet<Ident>> = decls
-                    .iter()
-                    .map(|decl| decl.func)
-                    .zip(sets.into_iter())
-                    .collect();
+                let graph: HashMap<Ident, HashSet<Ident>> =
+                    decls.iter().map(|decl| decl.func).zip(sets).collect();
 
                 let reachable = fix_point(used, graph);
                 let decls: Vec<MDecl> = decls
@@ -433,7 +653,7 @@ impl DeadElim {
                 arg2,
                 cont,
             } => {
-                if !self.free_set.contains(&bind) {
+                if !self.free_set.contains(&bind) && prim.is_pure() {
                     return *cont;
                 }
                 MExpr::BinOp {
@@ -463,11 +683,15 @@ impl DeadElim {
             MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             } => MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             },
@@ -478,6 +702,8 @@ impl DeadElim {
                     MExpr::Retn { arg1: Atom::Unit }
                 }
             }
+            MExpr::TailCall { func, args } => MExpr::TailCall { func, args },
+            MExpr::Panic { msg } => MExpr::Panic { msg },
             MExpr::Alloc { bind, size, cont } => {
                 if !self.free_set.contains(&bind) {
                     return *cont;
@@ -495,10 +721,9 @@ impl DeadElim {
                 }
                 /*
                 let var = arg1.unwrap_variable();
-                // todo: better api (like `get_mut`) to avoid clone
-                let mut set = self.load_map.get(&var).unwrap().clone();
-                set.insert(index);
-                self.load_map.insert(var, set);
+                self.load_map.entry(var).and_modify(|set| {
+                    set.insert(index);
+                });
                 */
                 MExpr::Load {
                     bind,
@@ -558,6 +783,7 @@ impl DeadElim {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 if !self.free_set.contains(&bind) {
@@ -570,6 +796,7 @@ impl DeadElim {
                     arg1,
                     brch1,
                     brch2,
+                    cold,
                     cont,
                 }
             }
@@ -578,6 +805,7 @@ impl DeadElim {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 if !self.free_set.contains(&bind) {
@@ -590,6 +818,7 @@ impl DeadElim {
                     arg1,
                     brchs,
                     dflt,
+                    cold,
                     cont,
                 }
             }
@@ -622,17 +851,22 @@ struct LinearInlineScan {
     // n >= m always
     occur: HashMap<Ident, (usize, usize)>,
     set: HashSet<Ident>,
+    ctx: PassCtx,
+    // inside a function that nothing may be inlined into
+    frozen: bool,
 }
 
 impl LinearInlineScan {
-    fn new() -> LinearInlineScan {
+    fn new(ctx: PassCtx) -> LinearInlineScan {
         LinearInlineScan {
             occur: HashMap::new(),
             set: HashSet::new(),
+            ctx,
+            frozen: false,
         }
     }
-    fn run(expr: MExpr) -> (MExpr, HashSet<Ident>) {
-        let mut pass = LinearInlineScan::new();
+    fn run(expr: MExpr, ctx: &PassCtx) -> (MExpr, HashSet<Ident>) {
+        let mut pass = LinearInlineScan::new(ctx.clone());
         let res = pass.visit_expr(expr);
         (res, pass.set)
     }
@@ -647,9 +881,9 @@ impl LinearInlineScan {
         let expr = match expr {
             MExpr::LetIn { decls, cont } => {
                 for decl in decls.iter() {
-                    let (n, m) = self.occur.get(&decl.func).unwrap_or(&(0, 0)).clone();
+                    let (n, m) = *self.occur.get(&decl.func).unwrap_or(&(0, 0));
                     assert!(n >= m);
-                    if n == 1 && m == 1 {
+                    if n == 1 && m == 1 && !self.ctx.no_inline(&decl.func) {
                         self.set.insert(decl.func);
                     }
                 }
@@ -663,7 +897,10 @@ impl LinearInlineScan {
             } => {
                 let var = func.unwrap_var();
                 let (_n, m) = self.occur.get_mut(&var).unwrap();
-                *m += 1;
+                // call sites in a frozen body are not inlined
+                if !self.frozen {
+                    *m += 1;
+                }
                 MExpr::Call {
                     bind,
                     func,
@@ -689,12 +926,15 @@ impl LinearInlineScan {
 
     fn visit_decl(&mut self, decl: MDecl) -> MDecl {
         let MDecl { func, pars, body } = decl;
+        let frozen = self.frozen;
+        self.frozen = frozen || self.ctx.no_inline(&func);
         let body = self.visit_expr(body);
+        self.frozen = frozen;
         MDecl { func, pars, body }
     }
 }
 
-fn concat(expr: MExpr, bind: Ident, cont: MExpr) -> MExpr {
+pub(super) fn concat(expr: MExpr, bind: Ident, cont: MExpr) -> MExpr {
     match expr {
         MExpr::Retn { arg1 } => MExpr::UnOp {
             bind,
@@ -706,7 +946,7 @@ fn concat(expr: MExpr, bind: Ident, cont: MExpr) -> MExpr {
     }
 }
 
-fn inline_call(decl: MDecl, bind: Ident, func: Ident, args: Vec<Atom>, cont: Box<MExpr>) -> MExpr {
+fn inline_call(decl: MDecl, bind: Ident, func: Ident, args: Vec<Atom>, cont: MExpr) -> MExpr {
     let MDecl {
         func: func2,
         pars,
@@ -716,14 +956,14 @@ fn inline_call(decl: MDecl, bind: Ident, func: Ident, args: Vec<Atom>, cont: Box
     assert_eq!(args.len(), pars.len());
     let new_expr = pars
         .into_iter()
-        .zip(args.into_iter())
+        .zip(args)
         .fold(body, |cont, (x, a)| MExpr::UnOp {
             bind: x,
             prim: UnOpPrim::Move,
             arg1: a,
             cont: Box::new(cont),
         });
-    concat(new_expr, bind, *cont)
+    concat(new_expr, bind, cont)
 }
 
 struct InlinePerform {
@@ -774,7 +1014,7 @@ impl InlinePerform {
                 if self.linear_set.contains(&func) {
                     let decl = self.inline_map.remove(&func).unwrap();
                     let decl = self.visit_decl(decl);
-                    inline_call(decl, bind, func, args, cont)
+                    inline_call(decl, bind, func, args, *cont)
                 } else {
                     MExpr::Call {
                         bind,
@@ -786,12 +1026,10 @@ impl InlinePerform {
             }
             other => other,
         };
-        let expr = expr
-            .walk_brch(|brch| self.visit_expr(brch))
-            .walk_decl(|decl| self.visit_decl(decl))
-            .walk_cont(|cont| self.visit_expr(cont));
 
-        expr
+        expr.walk_brch(|brch| self.visit_expr(brch))
+            .walk_decl(|decl| self.visit_decl(decl))
+            .walk_cont(|cont| self.visit_expr(cont))
     }
 
     fn visit_decl(&mut self, decl: MDecl) -> MDecl {
@@ -804,11 +1042,108 @@ impl InlinePerform {
 pub struct LinearInline;
 impl LinearInline {
     pub fn run(expr: MExpr) -> MExpr {
-        let (expr, set) = LinearInlineScan::run(expr);
+        LinearInline::run_with_ctx(expr, &PassCtx::default())
+    }
+    pub fn run_with_ctx(expr: MExpr, ctx: &PassCtx) -> MExpr {
+        let (expr, set) = LinearInlineScan::run(expr, ctx);
         InlinePerform::run(expr, set)
     }
 }
 
+/// every path through the expression ends in a `panic`
+fn diverges(expr: &MExpr) -> bool {
+    match expr {
+        MExpr::Panic { .. } => true,
+        MExpr::Retn { .. } | MExpr::TailCall { .. } => false,
+        MExpr::Ifte {
+            brch1, brch2, cont, ..
+        } => (diverges(brch1) && diverges(brch2)) || diverges(cont),
+        MExpr::Switch {
+            brchs, dflt, cont, ..
+        } => {
+            (brchs.iter().all(|(_, brch)| diverges(brch)) && dflt.iter().all(|dflt| diverges(dflt)))
+                || diverges(cont)
+        }
+        MExpr::LetIn { cont, .. }
+        | MExpr::UnOp { cont, .. }
+        | MExpr::BinOp { cont, .. }
+        | MExpr::Call { cont, .. }
+        | MExpr::ExtCall { cont, .. }
+        | MExpr::Alloc { cont, .. }
+        | MExpr::Load { cont, .. }
+        | MExpr::Store { cont, .. }
+        | MExpr::Offset { cont, .. } => diverges(cont),
+    }
+}
+
+/// Removes the code after an `if` or `switch` whose branches all panic, as it is never run.
+/// Whatever was only used by that code is left for `DeadElim` to remove.
+pub struct UnreachElim {
+    ctx: PassCtx,
+}
+
+impl UnreachElim {
+    pub fn run(expr: MExpr) -> MExpr {
+        UnreachElim::run_with_ctx(expr, &PassCtx::default())
+    }
+    pub fn run_with_ctx(expr: MExpr, ctx: &PassCtx) -> MExpr {
+        let pass = UnreachElim { ctx: ctx.clone() };
+        pass.visit_expr(expr)
+    }
+    fn visit_expr(&self, expr: MExpr) -> MExpr {
+        let expr = expr
+            .walk_brch(|brch| self.visit_expr(brch))
+            .walk_decl(|decl| {
+                if self.ctx.is_frozen(&decl.func) {
+                    decl
+                } else {
+                    decl.walk_body(|body| self.visit_expr(body))
+                }
+            });
+        match expr {
+            MExpr::Ifte {
+                bind,
+                arg1,
+                brch1,
+                brch2,
+                cold,
+                ..
+            } if diverges(&brch1) && diverges(&brch2) => MExpr::Ifte {
+                bind,
+                arg1,
+                brch1,
+                brch2,
+                cold,
+                cont: Box::new(MExpr::Retn {
+                    arg1: Atom::Var(bind),
+                }),
+            },
+            MExpr::Switch {
+                bind,
+                arg1,
+                brchs,
+                dflt,
+                cold,
+                ..
+            } if brchs.iter().all(|(_, brch)| diverges(brch))
+                && dflt.iter().all(|dflt| diverges(dflt)) =>
+            {
+                MExpr::Switch {
+                    bind,
+                    arg1,
+                    brchs,
+                    dflt,
+                    cold,
+                    cont: Box::new(MExpr::Retn {
+                        arg1: Atom::Var(bind),
+                    }),
+                }
+            }
+            other => other.walk_cont(|cont| self.visit_expr(cont)),
+        }
+    }
+}
+
 #[test]
 fn const_fold_test() {
     use super::anf_build::*;
@@ -910,6 +1245,108 @@ fn const_fold_test() {
     */
 }
 
+#[test]
+fn const_fold_int_width_test() {
+    use super::anf_build::*;
+
+    let expr1 = chain(vec![unop("x", UnOpPrim::Trunc32, i(-1)), retn(v("x"))]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(i(0xFFFF_FFFF)));
+
+    let expr1 = chain(vec![
+        unop("x", UnOpPrim::SignExt32, i(0x1_8000_0000)),
+        unop("y", UnOpPrim::ZeroExt32, v("x")),
+        retn(v("y")),
+    ]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(i(0x8000_0000)));
+}
+
+#[test]
+fn const_fold_hyperbolic_test() {
+    use super::anf_build::*;
+
+    let expr1 = chain(vec![unop("x", UnOpPrim::Sinh, r(0.0)), retn(v("x"))]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(r(0.0)));
+
+    let expr1 = chain(vec![unop("x", UnOpPrim::Cosh, r(0.0)), retn(v("x"))]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(r(1.0)));
+
+    let expr1 = chain(vec![unop("x", UnOpPrim::Tanh, r(0.0)), retn(v("x"))]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(r(0.0)));
+
+    // tanh saturates towards 1.0 for large inputs
+    let expr1 = chain(vec![unop("x", UnOpPrim::Tanh, r(1e6)), retn(v("x"))]);
+    match ConstFold::run(expr1) {
+        MExpr::Retn {
+            arg1: Atom::Real(x),
+        } => assert!((x - 1.0).abs() < 1e-12),
+        other => panic!("tanh not folded: {other}"),
+    }
+}
+
+#[test]
+fn const_fold_erf_test() {
+    use super::anf_build::*;
+
+    let expr1 = chain(vec![unop("x", UnOpPrim::Erf, r(0.0)), retn(v("x"))]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(r(0.0)));
+
+    let expr1 = chain(vec![
+        unop("x", UnOpPrim::Erf, r(f64::INFINITY)),
+        retn(v("x")),
+    ]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(r(1.0)));
+
+    let expr1 = chain(vec![unop("x", UnOpPrim::Erfc, r(0.0)), retn(v("x"))]);
+    let expr1 = ConstFold::run(expr1);
+    assert_eq!(expr1, retn(r(1.0)));
+
+    // erf(1.0) = 0.8427007929497149
+    let expr1 = chain(vec![unop("x", UnOpPrim::Erf, r(1.0)), retn(v("x"))]);
+    match ConstFold::run(expr1) {
+        MExpr::Retn {
+            arg1: Atom::Real(x),
+        } => assert!((x - 0.8427007929497149).abs() < 1e-14),
+        other => panic!("erf not folded: {other}"),
+    }
+
+    for x in [-4.0, -1.5, -0.3, 0.0, 0.7, 2.0, 2.99, 3.0, 5.5, 30.0] {
+        assert!((erf(x) + erfc(x) - 1.0).abs() < 1e-14);
+    }
+}
+
+#[test]
+fn const_fold_real_test() {
+    use super::anf_build::*;
+
+    let fold = |prim, x: f64, y: f64| match ConstFold::run(binop("z", prim, r(x), r(y))) {
+        MExpr::Retn {
+            arg1: Atom::Real(z),
+        } => z.to_bits(),
+        other => panic!("{prim} not folded: {other}"),
+    };
+    // division by zero is not an error
+    assert_eq!(fold(BinOpPrim::RDiv, 1.0, 0.0), f64::INFINITY.to_bits());
+    assert_eq!(
+        fold(BinOpPrim::RDiv, 1.0, -0.0),
+        f64::NEG_INFINITY.to_bits()
+    );
+    assert!(f64::from_bits(fold(BinOpPrim::RDiv, 0.0, 0.0)).is_nan());
+    assert!(f64::from_bits(fold(BinOpPrim::RSub, f64::INFINITY, f64::INFINITY)).is_nan());
+    // signed zeros are kept
+    assert_eq!(fold(BinOpPrim::RMul, -1.0, 0.0), (-0.0f64).to_bits());
+    assert_eq!(fold(BinOpPrim::RAdd, -0.0, -0.0), (-0.0f64).to_bits());
+    assert_eq!(fold(BinOpPrim::RAdd, -0.0, 0.0), 0.0f64.to_bits());
+    // overflow goes to infinity
+    assert_eq!(fold(BinOpPrim::RMul, 1e308, 10.0), f64::INFINITY.to_bits());
+}
+
 #[test]
 fn dead_elim_test() {
     use super::anf_build::*;
@@ -1075,3 +1512,129 @@ fn inliner_inline_test() {
     );
     assert_eq!(expr1, expr2);
 }
+
+#[test]
+fn optimize_attr_test() {
+    use super::anf_build::*;
+    // two identical functions, one of them marked with @optimize(...)
+    let twin = |func: &str, x: &str, a: &str, b: &str| {
+        fun(
+            func,
+            vec![x],
+            chain(vec![
+                iadd(a, i(1), i(2)),
+                _move("dead", v(a)),
+                imul(b, v(a), v(x)),
+                retn(v(b)),
+            ]),
+        )
+    };
+    let optimize = |expr: MExpr, ctx: &PassCtx| {
+        let expr = DeadElim::run_with_ctx(expr, ctx);
+        let expr = ConstFold::run_with_ctx(expr, ctx);
+        LinearInline::run_with_ctx(expr, ctx)
+    };
+    let expr = let_in(
+        vec![twin("f1", "x1", "a1", "b1"), twin("f2", "x2", "a2", "b2")],
+        vec![
+            call("t1", "f1", vec![i(42)]),
+            call("t2", "f2", vec![i(42)]),
+            iadd("r", v("t1"), v("t2")),
+            retn(v("r")),
+        ],
+    );
+
+    // f1 is copied through untouched, while f2 is folded and inlined
+    let ctx = PassCtx::new(HashMap::from([(name("f1").name, OptLevel::Never)]));
+    let expr1 = optimize(expr.clone(), &ctx);
+    let expr2 = let_in(
+        vec![twin("f1", "x1", "a1", "b1")],
+        vec![
+            call("t1", "f1", vec![i(42)]),
+            _move("x2", i(42)),
+            imul("b2", i(3), v("x2")),
+            _move("t2", v("b2")),
+            iadd("r", v("t1"), v("t2")),
+            retn(v("r")),
+        ],
+    );
+    assert_eq!(expr1, expr2);
+
+    // f1 is folded but not inlined
+    let ctx = PassCtx::new(HashMap::from([(name("f1").name, OptLevel::Size)]));
+    let expr1 = optimize(expr, &ctx);
+    let expr2 = let_in(
+        vec![fun(
+            "f1",
+            vec!["x1"],
+            chain(vec![imul("b1", i(3), v("x1")), retn(v("b1"))]),
+        )],
+        vec![
+            call("t1", "f1", vec![i(42)]),
+            _move("x2", i(42)),
+            imul("b2", i(3), v("x2")),
+            _move("t2", v("b2")),
+            iadd("r", v("t1"), v("t2")),
+            retn(v("r")),
+        ],
+    );
+    assert_eq!(expr1, expr2);
+}
+
+#[test]
+fn unreach_elim_test() {
+    use super::anf_build::*;
+
+    // nothing after a branching that always panics is run
+    let expr1 = chain(vec![
+        iadd("x", i(1), i(1)),
+        ifte("y", v("?"), panic("a"), chain(vec![panic("b")])),
+        iadd("z", v("y"), v("x")),
+        retn(v("z")),
+    ]);
+    let expr1 = UnreachElim::run(expr1);
+    let expr2 = chain(vec![
+        iadd("x", i(1), i(1)),
+        ifte("y", v("?"), panic("a"), panic("b")),
+        retn(v("y")),
+    ]);
+    assert_eq!(expr1, expr2);
+
+    // the same for nested branchings, in the branch of another one
+    let expr1 = chain(vec![
+        ifte(
+            "x",
+            v("?"),
+            chain(vec![
+                ifte("y", v("?"), panic("a"), panic("b")),
+                iadd("z", v("y"), i(1)),
+                retn(v("z")),
+            ]),
+            retn(i(0)),
+        ),
+        retn(v("x")),
+    ]);
+    let expr1 = UnreachElim::run(expr1);
+    let expr2 = chain(vec![
+        ifte(
+            "x",
+            v("?"),
+            chain(vec![
+                ifte("y", v("?"), panic("a"), panic("b")),
+                retn(v("y")),
+            ]),
+            retn(i(0)),
+        ),
+        retn(v("x")),
+    ]);
+    assert_eq!(expr1, expr2);
+
+    // a branching that returns on one side is kept whole
+    let expr1 = chain(vec![
+        ifte("y", v("?"), panic("a"), retn(i(1))),
+        iadd("z", v("y"), i(1)),
+        retn(v("z")),
+    ]);
+    let expr2 = UnreachElim::run(expr1.clone());
+    assert_eq!(expr1, expr2);
+}
@@ -0,0 +1,99 @@
+use super::*;
+use crate::frontend::diagnostic::{self, Diagnostic};
+use std::collections::HashMap;
+
+/// the data type each tag switch is on, by the variable holding the tag,
+/// together with the constructors of the type in the order of their tags.
+/// the IR carries no types, so this is given by the pass that builds the switches.
+pub type TagTypes = HashMap<Ident, (Ident, Vec<Ident>)>;
+
+/// Check every `Switch` on the tag of a data type against the constructors of the type.
+/// A switch without a default must have a case for every constructor, or it falls
+/// through at runtime, which is an error. A case that is no tag of the type is never taken.
+pub fn validate_switch(expr: &MExpr, tag_types: &TagTypes) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    validate(expr, tag_types, &mut diags);
+    diags
+}
+
+fn validate(expr: &MExpr, tag_types: &TagTypes, diags: &mut Vec<Diagnostic>) {
+    if let MExpr::LetIn { decls, .. } = expr {
+        for decl in decls {
+            validate(&decl.body, tag_types, diags);
+        }
+    }
+    if let MExpr::Switch {
+        arg1: Atom::Var(tag),
+        brchs,
+        dflt,
+        ..
+    } = expr
+    {
+        if let Some((data, cons)) = tag_types.get(tag) {
+            let data = diagnostic::internal_ident(data);
+            for (i, _) in brchs.iter().filter(|(i, _)| *i >= cons.len()) {
+                let diag = Diagnostic::warn(format!("case `{i}` in a switch is never taken"))
+                    .line(format!("`{data}` has only {} constructors", cons.len()));
+                diags.push(diag);
+            }
+            let missing: Vec<String> = cons
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| brchs.iter().all(|(j, _)| i != j))
+                .map(|(_, cons)| format!("`{}`", diagnostic::internal_ident(cons)))
+                .collect();
+            if dflt.is_none() && !missing.is_empty() {
+                let diag = Diagnostic::error("non-exhaustive switch")
+                    .line(format!(
+                        "a switch on `{data}` has no default and no case for {}",
+                        missing.join(", ")
+                    ))
+                    .line("the missing constructors would fall through at runtime");
+                diags.push(diag);
+            }
+        }
+    }
+    for sub in dce::subexprs(expr) {
+        validate(sub, tag_types, diags);
+    }
+}
+
+#[test]
+fn validate_switch_test() {
+    use super::anf_build::*;
+    let tag_types: TagTypes = [(name("t"), (name("List"), vec![name("Nil"), name("Cons")]))]
+        .into_iter()
+        .collect();
+    let switch_on = |brchs: Vec<usize>, dflt: Option<MExpr>| {
+        let brchs = brchs.into_iter().map(|k| (k, retn(i(k as i64)))).collect();
+        let_in(
+            vec![fun(
+                "f",
+                vec!["xs"],
+                chain(vec![
+                    load("t", v("xs"), 0),
+                    switch("r", v("t"), brchs, dflt),
+                ]),
+            )],
+            vec![retn(i(0))],
+        )
+    };
+
+    // every constructor has a case, or the default takes the others
+    assert!(validate_switch(&switch_on(vec![0, 1], None), &tag_types).is_empty());
+    assert!(validate_switch(&switch_on(vec![1], Some(panic("m"))), &tag_types).is_empty());
+
+    // `Nil` falls through
+    let diags = validate_switch(&switch_on(vec![1], None), &tag_types);
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0].is_error());
+
+    // there is no third constructor
+    let diags = validate_switch(&switch_on(vec![0, 1, 2], None), &tag_types);
+    assert_eq!(diags.len(), 1);
+    assert!(!diags[0].is_error());
+
+    // a switch on an integer isn't checked
+    let expr = chain(vec![switch("r", v("n"), vec![(5, retn(i(1)))], None)]);
+    assert!(validate_switch(&expr, &tag_types).is_empty());
+}
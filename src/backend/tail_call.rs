@@ -0,0 +1,79 @@
+use super::*;
+
+/// Turn every `let r = f(...); return r` into a `TailCall`, whose result is returned
+/// right away. In a branch it goes to the join point like the `return` it replaces,
+/// only a tail call of the whole function body can reuse the frame of the caller,
+/// which the C backend does for a function calling itself.
+pub fn mark_tail_calls(expr: MExpr) -> MExpr {
+    match expr {
+        MExpr::Call {
+            bind,
+            func,
+            args,
+            cont,
+        } => match *cont {
+            MExpr::Retn {
+                arg1: Atom::Var(arg1),
+            } if arg1 == bind => MExpr::TailCall { func, args },
+            cont => MExpr::Call {
+                bind,
+                func,
+                args,
+                cont: Box::new(mark_tail_calls(cont)),
+            },
+        },
+        expr => expr
+            .walk_decl(|decl| decl.walk_body(mark_tail_calls))
+            .walk_brch(mark_tail_calls)
+            .walk_cont(mark_tail_calls),
+    }
+}
+
+#[test]
+fn mark_tail_calls_test() {
+    use super::anf_build::*;
+    // only a call whose result is returned as it is becomes a tail call
+    let expr = let_in(
+        vec![fun(
+            "f",
+            vec!["n"],
+            chain(vec![
+                call("a", "g", vec![v("n")]),
+                call("b", "g", vec![v("a")]),
+                iadd("c", v("b"), i(1)),
+                call("d", "f", vec![v("c")]),
+                retn(v("d")),
+            ]),
+        )],
+        vec![
+            call("x", "f", vec![i(1)]),
+            ifte(
+                "y",
+                v("x"),
+                chain(vec![call("z", "f", vec![v("x")]), retn(v("z"))]),
+                retn(i(0)),
+            ),
+            call("w", "f", vec![v("y")]),
+            retn(v("x")),
+        ],
+    );
+    let expected = let_in(
+        vec![fun(
+            "f",
+            vec!["n"],
+            chain(vec![
+                call("a", "g", vec![v("n")]),
+                call("b", "g", vec![v("a")]),
+                iadd("c", v("b"), i(1)),
+                tail_call("f", vec![v("c")]),
+            ]),
+        )],
+        vec![
+            call("x", "f", vec![i(1)]),
+            ifte("y", v("x"), tail_call("f", vec![v("x")]), retn(i(0))),
+            call("w", "f", vec![v("y")]),
+            retn(v("x")),
+        ],
+    );
+    assert_eq!(mark_tail_calls(expr), expected);
+}
@@ -52,6 +52,8 @@ impl MExpr {
             MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             } => {
@@ -59,11 +61,15 @@ impl MExpr {
                 MExpr::ExtCall {
                     bind,
                     func,
+                    call_conv,
+                    abi,
                     args,
                     cont,
                 }
             }
             MExpr::Retn { arg1 } => MExpr::Retn { arg1 },
+            MExpr::Panic { msg } => MExpr::Panic { msg },
+            MExpr::TailCall { func, args } => MExpr::TailCall { func, args },
             MExpr::Alloc { bind, size, cont } => {
                 let bind = f(bind);
                 MExpr::Alloc { bind, size, cont }
@@ -112,6 +118,7 @@ impl MExpr {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 let bind = f(bind);
@@ -120,6 +127,7 @@ impl MExpr {
                     arg1,
                     brch1,
                     brch2,
+                    cold,
                     cont,
                 }
             }
@@ -128,6 +136,7 @@ impl MExpr {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 let bind = f(bind);
@@ -136,6 +145,7 @@ impl MExpr {
                     arg1,
                     brchs,
                     dflt,
+                    cold,
                     cont,
                 }
             }
@@ -194,6 +204,8 @@ impl MExpr {
             MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             } => {
@@ -201,6 +213,8 @@ impl MExpr {
                 MExpr::ExtCall {
                     bind,
                     func,
+                    call_conv,
+                    abi,
                     args,
                     cont,
                 }
@@ -209,6 +223,12 @@ impl MExpr {
                 let arg1 = f(arg1);
                 MExpr::Retn { arg1 }
             }
+            MExpr::Panic { msg } => MExpr::Panic { msg },
+            MExpr::TailCall { func, args } => {
+                let func = f(func);
+                let args = args.into_iter().map(f).collect();
+                MExpr::TailCall { func, args }
+            }
             MExpr::Alloc { bind, size, cont } => MExpr::Alloc { bind, size, cont },
             MExpr::Load {
                 bind,
@@ -258,6 +278,7 @@ impl MExpr {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 let arg1 = f(arg1);
@@ -266,6 +287,7 @@ impl MExpr {
                     arg1,
                     brch1,
                     brch2,
+                    cold,
                     cont,
                 }
             }
@@ -274,6 +296,7 @@ impl MExpr {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 let arg1 = f(arg1);
@@ -282,16 +305,17 @@ impl MExpr {
                     arg1,
                     brchs,
                     dflt,
+                    cold,
                     cont,
                 }
             }
         }
     }
 
-    pub fn walk_decl<F: FnMut(MDecl) -> MDecl>(self, mut f: F) -> MExpr {
+    pub fn walk_decl<F: FnMut(MDecl) -> MDecl>(self, f: F) -> MExpr {
         match self {
             MExpr::LetIn { decls, cont } => {
-                let decls = decls.into_iter().map(|decl| f(decl)).collect();
+                let decls = decls.into_iter().map(f).collect();
                 MExpr::LetIn { decls, cont }
             }
             other => other,
@@ -305,6 +329,7 @@ impl MExpr {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 let brch1 = Box::new(f(*brch1));
@@ -314,6 +339,7 @@ impl MExpr {
                     arg1,
                     brch1,
                     brch2,
+                    cold,
                     cont,
                 }
             }
@@ -322,6 +348,7 @@ impl MExpr {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 let brchs = brchs.into_iter().map(|(i, brch)| (i, f(brch))).collect();
@@ -331,6 +358,7 @@ impl MExpr {
                     arg1,
                     brchs,
                     dflt,
+                    cold,
                     cont,
                 }
             }
@@ -391,6 +419,8 @@ impl MExpr {
             MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             } => {
@@ -398,11 +428,15 @@ impl MExpr {
                 MExpr::ExtCall {
                     bind,
                     func,
+                    call_conv,
+                    abi,
                     args,
                     cont,
                 }
             }
             MExpr::Retn { arg1 } => MExpr::Retn { arg1 },
+            MExpr::Panic { msg } => MExpr::Panic { msg },
+            MExpr::TailCall { func, args } => MExpr::TailCall { func, args },
             MExpr::Alloc { bind, size, cont } => {
                 let cont = Box::new(f(*cont));
                 MExpr::Alloc { bind, size, cont }
@@ -454,6 +488,7 @@ impl MExpr {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
                 let cont = Box::new(f(*cont));
@@ -462,6 +497,7 @@ impl MExpr {
                     arg1,
                     brch1,
                     brch2,
+                    cold,
                     cont,
                 }
             }
@@ -470,6 +506,7 @@ impl MExpr {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 let cont = Box::new(f(*cont));
@@ -478,6 +515,7 @@ impl MExpr {
                     arg1,
                     brchs,
                     dflt,
+                    cold,
                     cont,
                 }
             }
@@ -566,3 +604,15 @@ impl MExpr {
         Renamer::run(self)
     }
 }
+
+impl MDecl {
+    /// fresh names for the parameters and everything bound in the body,
+    /// the name of the function itself is kept
+    pub fn rename(self) -> MDecl {
+        let MDecl { func, pars, body } = self;
+        let mut pass = Renamer::new();
+        let pars = pars.into_iter().map(|par| pass.visit_bind(par)).collect();
+        let body = pass.visit_expr(body);
+        MDecl { func, pars, body }
+    }
+}
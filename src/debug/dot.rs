@@ -0,0 +1,249 @@
+use crate::backend::anf::*;
+use itertools::Itertools;
+use std::fmt::{Result, Write};
+
+/// Convert the body of a function into a Graphviz DOT graph.
+/// `cont` edges are black, branch edges are red (then/case) and blue (else/default),
+/// and edges into local function bodies are dashed.
+pub fn mexpr_to_dot(decl: &MDecl) -> String {
+    let mut pass = DotGraph {
+        count: 0,
+        text: String::new(),
+    };
+    pass.visit_graph(decl).unwrap();
+    pass.text
+}
+
+struct DotGraph {
+    count: usize,
+    text: String,
+}
+
+impl DotGraph {
+    fn new_node(&mut self, label: String) -> std::result::Result<usize, std::fmt::Error> {
+        let node = self.count;
+        self.count += 1;
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(self.text, "  n{node} [label=\"{label}\"];")?;
+        Ok(node)
+    }
+
+    fn edge(&mut self, from: usize, to: usize, attr: &str) -> Result {
+        writeln!(self.text, "  n{from} -> n{to} [{attr}];")
+    }
+
+    fn visit_graph(&mut self, decl: &MDecl) -> Result {
+        let MDecl { func, pars, body } = decl;
+        writeln!(self.text, "digraph \"{func}\" {{")?;
+        writeln!(self.text, "  node [shape=box];")?;
+        let pars = pars.iter().format(", ");
+        let entry = self.new_node(format!("fun {func}({pars})"))?;
+        let body = self.visit_expr(body)?;
+        self.edge(entry, body, "color=black")?;
+        writeln!(self.text, "}}")
+    }
+
+    fn visit_decl(&mut self, decl: &MDecl) -> std::result::Result<usize, std::fmt::Error> {
+        let MDecl { func, pars, body } = decl;
+        let pars = pars.iter().format(", ");
+        let entry = self.new_node(format!("fun {func}({pars})"))?;
+        let body = self.visit_expr(body)?;
+        self.edge(entry, body, "color=black")?;
+        Ok(entry)
+    }
+
+    /// returns the node of `expr`
+    fn visit_expr(&mut self, expr: &MExpr) -> std::result::Result<usize, std::fmt::Error> {
+        let (node, cont) = match expr {
+            MExpr::LetIn { decls, cont } => {
+                let funcs = decls.iter().map(|decl| decl.func).format(", ");
+                let node = self.new_node(format!("letrec {funcs}"))?;
+                for decl in decls {
+                    let entry = self.visit_decl(decl)?;
+                    self.edge(node, entry, "style=dashed")?;
+                }
+                (node, cont)
+            }
+            MExpr::UnOp {
+                bind,
+                prim,
+                arg1,
+                cont,
+            } => (self.new_node(format!("{bind} = {prim}({arg1})"))?, cont),
+            MExpr::BinOp {
+                bind,
+                prim,
+                arg1,
+                arg2,
+                cont,
+            } => (
+                self.new_node(format!("{bind} = {prim}({arg1}, {arg2})"))?,
+                cont,
+            ),
+            MExpr::Call {
+                bind,
+                func,
+                args,
+                cont,
+            } => {
+                let args = args.iter().format(", ");
+                (
+                    self.new_node(format!("{bind} = call {func}({args})"))?,
+                    cont,
+                )
+            }
+            MExpr::ExtCall {
+                bind,
+                func,
+                args,
+                cont,
+                ..
+            } => {
+                let args = args.iter().format(", ");
+                (
+                    self.new_node(format!("{bind} = extcall {func}({args})"))?,
+                    cont,
+                )
+            }
+            MExpr::Retn { arg1 } => {
+                return self.new_node(format!("return {arg1}"));
+            }
+            MExpr::TailCall { func, args } => {
+                let args = args.iter().format(", ");
+                return self.new_node(format!("return call {func}({args})"));
+            }
+            MExpr::Panic { msg } => {
+                return self.new_node(format!("panic {:?}", msg.as_str()));
+            }
+            MExpr::Alloc { bind, size, cont } => {
+                (self.new_node(format!("{bind} = alloc[{size}]"))?, cont)
+            }
+            MExpr::Load {
+                bind,
+                arg1,
+                index,
+                cont,
+            } => (
+                self.new_node(format!("{bind} = load {arg1}[{index}]"))?,
+                cont,
+            ),
+            MExpr::Store {
+                arg1,
+                index,
+                arg2,
+                cont,
+            } => (
+                self.new_node(format!("store {arg1}[{index}] := {arg2}"))?,
+                cont,
+            ),
+            MExpr::Offset {
+                bind,
+                arg1,
+                index,
+                cont,
+            } => (
+                self.new_node(format!("{bind} = offset {arg1}[{index}]"))?,
+                cont,
+            ),
+            MExpr::Ifte {
+                bind,
+                arg1,
+                brch1,
+                brch2,
+                cold,
+                cont,
+            } => {
+                let node = self.new_node(format!("{bind} = ifte({arg1})"))?;
+                let brch1 = self.visit_expr(brch1)?;
+                let style = cold_style(cold, 0);
+                self.edge(node, brch1, &format!("color=red, label=\"then\"{style}"))?;
+                let brch2 = self.visit_expr(brch2)?;
+                let style = cold_style(cold, 1);
+                self.edge(node, brch2, &format!("color=blue, label=\"else\"{style}"))?;
+                (node, cont)
+            }
+            MExpr::Switch {
+                bind,
+                arg1,
+                brchs,
+                dflt,
+                cold,
+                cont,
+            } => {
+                let node = self.new_node(format!("{bind} = switch({arg1})"))?;
+                for (k, (i, brch)) in brchs.iter().enumerate() {
+                    let brch = self.visit_expr(brch)?;
+                    let style = cold_style(cold, k);
+                    self.edge(node, brch, &format!("color=red, label=\"case {i}\"{style}"))?;
+                }
+                if let Some(dflt) = dflt {
+                    let dflt = self.visit_expr(dflt)?;
+                    let style = cold_style(cold, brchs.len());
+                    let attrs = format!("color=blue, label=\"default\"{style}");
+                    self.edge(node, dflt, &attrs)?;
+                }
+                (node, cont)
+            }
+        };
+        let cont = self.visit_expr(cont)?;
+        self.edge(node, cont, "color=black")?;
+        Ok(node)
+    }
+}
+
+/// cold branches are drawn dashed
+fn cold_style(cold: &Cold, index: usize) -> &'static str {
+    if cold.is_cold(index) {
+        ", style=dashed"
+    } else {
+        ""
+    }
+}
+
+#[test]
+fn mexpr_to_dot_test() {
+    use crate::backend::anf_build::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let decl = fun(
+        "f",
+        vec!["x", "y"],
+        chain(vec![
+            ifte(
+                "z",
+                v("x"),
+                chain(vec![iadd("a", v("y"), i(1)), retn(v("a"))]),
+                retn(i(0)),
+            ),
+            switch(
+                "w",
+                v("z"),
+                vec![(0, retn(i(1))), (1, retn(i(2)))],
+                Some(retn(i(3))),
+            ),
+            retn(v("w")),
+        ]),
+    );
+    let text = mexpr_to_dot(&decl);
+    assert!(text.starts_with("digraph"));
+    assert_eq!(text.matches("color=red").count(), 3);
+    assert_eq!(text.matches("color=blue").count(), 2);
+    assert_eq!(text.matches(" -> ").count(), 9);
+
+    // verify the syntax with graphviz, if it is installed
+    if let Ok(mut child) = Command::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+    {
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use std::collections::HashMap;
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub enum LitVal {
@@ -6,6 +7,8 @@ pub enum LitVal {
     Real(f64),
     Bool(bool),
     Char(char),
+    /// a string literal, equal literals share the interned string
+    Str(InternStr),
     Unit,
 }
 
@@ -16,6 +19,7 @@ impl LitVal {
             LitVal::Real(_) => LitType::Real,
             LitVal::Bool(_) => LitType::Bool,
             LitVal::Char(_) => LitType::Char,
+            LitVal::Str(_) => LitType::Str,
             LitVal::Unit => LitType::Unit,
         }
     }
@@ -29,16 +33,238 @@ pub enum Builtin {
     IDiv,
     IRem,
     INeg,
+    Trunc32,
+    SignExt32,
+    ZeroExt32,
+    ICmpEq,
+    ICmpNe,
+    ICmpLt,
+    ICmpLe,
+    ICmpGt,
+    ICmpGe,
     RAdd,
     RSub,
     RMul,
     RDiv,
+    Sinh,
+    Cosh,
+    Tanh,
+    Erf,
+    Erfc,
     BAnd,
     BOr,
     BNot,
+    Prefetch,
+    Expect,
+    SimdAdd,
+    SimdMul,
+    SimdLoad,
+    SimdStore,
+    CastOpaquePtr,
+    /// `@allocaligned(size, align)` allocates `size` bytes aligned to `align`
+    AllocAligned,
+    /// `@yield(x)` suspends the running coroutine, handing `x` to whoever resumed it
+    Yield,
+    /// `@spawn(f)` runs the closure `f` on a new thread
+    Spawn,
+    /// `@join(t)` waits for the thread `t` to finish, and takes its result
+    Join,
+    /// `@spawnwithstack(f, size)` is like `@spawn`, with a stack of at least `size` bytes
+    SpawnWithStack,
+    /// `@channew(n)` makes a channel holding at most `n` values
+    ChanNew,
+    /// `@chansend(c, x)` puts `x` into the channel `c`, waiting while it's full
+    ChanSend,
+    /// `@chanrecv(c)` takes a value out of `c` as `Some(x)`, or `None` once it's closed and empty
+    ChanRecv,
+    /// `@chanclose(c)` closes the channel `c`, the values in it can still be received
+    ChanClose,
+    /// `@mutexnew()` makes an unlocked mutex
+    MutexNew,
+    /// `@mutexlock(m)` locks `m`, waiting while another thread holds it
+    MutexLock,
+    /// `@mutexunlock(m)` unlocks `m`, which the current thread must hold
+    MutexUnlock,
+    /// `@withmutex(m, f)` calls `f()` with `m` locked, and unlocks it afterwards
+    WithMutex,
+    /// `@semnew(n)` makes a counting semaphore with the count `n`
+    SemNew,
+    /// `@sempost(s)` increments the count of `s`, waking a thread waiting on it
+    SemPost,
+    /// `@semwait(s)` decrements the count of `s`, waiting while it is zero
+    SemWait,
+    /// `@semtrywait(s)` decrements the count of `s` if it isn't zero, and tells whether it did
+    SemTryWait,
+    /// `@readfile(path)` reads the whole file at `path` as `Ok(text)`, or `Err(message)`
+    ReadFile,
+    /// `@writefile(path, text)` replaces the file at `path` with `text`, giving `Ok(())`
+    /// or `Err(message)`
+    WriteFile,
+    /// `@listdir(path)` gives the names of the entries in the directory at `path`,
+    /// without `.` and `..`, as `Ok(names)` or `Err(message)`
+    ListDir,
+    /// `@pathjoin(dir, path)` appends `path` to `dir` with one `/` between them,
+    /// an absolute `path` replaces `dir`
+    PathJoin,
+    /// `@pathbase(path)` gives the last component of `path`, ignoring trailing slashes
+    PathBase,
+    /// `@pathdir(path)` gives everything but the last component of `path`, or `.` if nothing
+    PathDir,
+    /// `@format("x = %d", x)` formats its arguments like `printf` into a new string,
+    /// the format must be a literal so that the arguments are checked against it
+    Format,
+    /// `@listmin(xs)` is `Some` of the smallest integer of the list, or `None` if it is empty.
+    /// the parser turns it into a fold over `Cons` and `Nil`
+    ListMin,
+    /// `@listmax(xs)` is `Some` of the largest integer of the list, or `None` if it is empty
+    ListMax,
+    /// reinterpret the bits of a value of the first type as the second type,
+    /// like `@bitcast[Int, Real](x)`
+    Bitcast(LitType, LitType),
 }
 
 impl Builtin {
+    /// every primitive, for generating the language reference.
+    /// `Bitcast` is listed with an example of its type parameters.
+    pub const ALL: &'static [Builtin] = &[
+        Builtin::IAdd,
+        Builtin::ISub,
+        Builtin::IMul,
+        Builtin::IDiv,
+        Builtin::IRem,
+        Builtin::INeg,
+        Builtin::Trunc32,
+        Builtin::SignExt32,
+        Builtin::ZeroExt32,
+        Builtin::ICmpEq,
+        Builtin::ICmpNe,
+        Builtin::ICmpLt,
+        Builtin::ICmpLe,
+        Builtin::ICmpGt,
+        Builtin::ICmpGe,
+        Builtin::RAdd,
+        Builtin::RSub,
+        Builtin::RMul,
+        Builtin::RDiv,
+        Builtin::Sinh,
+        Builtin::Cosh,
+        Builtin::Tanh,
+        Builtin::Erf,
+        Builtin::Erfc,
+        Builtin::BAnd,
+        Builtin::BOr,
+        Builtin::BNot,
+        Builtin::Prefetch,
+        Builtin::Expect,
+        Builtin::SimdAdd,
+        Builtin::SimdMul,
+        Builtin::SimdLoad,
+        Builtin::SimdStore,
+        Builtin::CastOpaquePtr,
+        Builtin::AllocAligned,
+        Builtin::Yield,
+        Builtin::Spawn,
+        Builtin::Join,
+        Builtin::SpawnWithStack,
+        Builtin::ChanNew,
+        Builtin::ChanSend,
+        Builtin::ChanRecv,
+        Builtin::ChanClose,
+        Builtin::MutexNew,
+        Builtin::MutexLock,
+        Builtin::MutexUnlock,
+        Builtin::WithMutex,
+        Builtin::SemNew,
+        Builtin::SemPost,
+        Builtin::SemWait,
+        Builtin::SemTryWait,
+        Builtin::ReadFile,
+        Builtin::WriteFile,
+        Builtin::ListDir,
+        Builtin::PathJoin,
+        Builtin::PathBase,
+        Builtin::PathDir,
+        Builtin::Format,
+        Builtin::ListMin,
+        Builtin::ListMax,
+        Builtin::Bitcast(LitType::Int, LitType::Real),
+    ];
+
+    /// a one-line description for the language reference
+    pub fn doc(&self) -> &'static str {
+        match self {
+            Builtin::IAdd => "integer addition, wrapping on overflow",
+            Builtin::ISub => "integer subtraction, wrapping on overflow",
+            Builtin::IMul => "integer multiplication, wrapping on overflow",
+            Builtin::IDiv => "integer division, rounding toward zero",
+            Builtin::IRem => "remainder of integer division, with the sign of the dividend",
+            Builtin::INeg => "integer negation",
+            Builtin::Trunc32 => "keep the low 32 bits of an integer, clearing the rest",
+            Builtin::SignExt32 => "sign-extend the low 32 bits of an integer to 64 bits",
+            Builtin::ZeroExt32 => "zero-extend the low 32 bits of an integer to 64 bits",
+            Builtin::ICmpEq => "integer equality",
+            Builtin::ICmpNe => "integer inequality",
+            Builtin::ICmpLt => "integer less than",
+            Builtin::ICmpLe => "integer less than or equal",
+            Builtin::ICmpGt => "integer greater than",
+            Builtin::ICmpGe => "integer greater than or equal",
+            Builtin::RAdd => "IEEE-754 addition",
+            Builtin::RSub => "IEEE-754 subtraction",
+            Builtin::RMul => "IEEE-754 multiplication",
+            Builtin::RDiv => "IEEE-754 division, dividing by zero gives an infinity or NaN",
+            Builtin::Sinh => "hyperbolic sine",
+            Builtin::Cosh => "hyperbolic cosine",
+            Builtin::Tanh => "hyperbolic tangent",
+            Builtin::Erf => "error function",
+            Builtin::Erfc => "complementary error function, `1 - erf(x)` without cancellation",
+            Builtin::BAnd => "boolean and, both operands are evaluated",
+            Builtin::BOr => "boolean or, both operands are evaluated",
+            Builtin::BNot => "boolean negation",
+            Builtin::Prefetch => {
+                "hint to prefetch the memory of a value, the integer is the locality"
+            }
+            Builtin::Expect => "branch prediction hint, the result is the first argument",
+            Builtin::SimdAdd => "lane-wise addition of vectors",
+            Builtin::SimdMul => "lane-wise multiplication of vectors",
+            Builtin::SimdLoad => "make a vector from a tuple of its lanes",
+            Builtin::SimdStore => "make a tuple from the lanes of a vector",
+            Builtin::CastOpaquePtr => "convert a value to `Ptr`, or a `Ptr` back to a value",
+            Builtin::AllocAligned => {
+                "allocate bytes with an alignment, which must be a power of two"
+            }
+            Builtin::Yield => {
+                "suspend the running coroutine with a value, the result is the value it is resumed with"
+            }
+            Builtin::Spawn => "run a closure on a new thread, it can only capture `Send` values",
+            Builtin::Join => "wait for a thread to finish and take the result of its closure",
+            Builtin::SpawnWithStack => "run a closure on a new thread with a stack of at least the size in bytes",
+            Builtin::ChanNew => "make a channel with a capacity, its values must be `Send`",
+            Builtin::ChanSend => "send a value over a channel, waiting while the channel is full",
+            Builtin::ChanRecv => {
+                "receive a value from a channel, `None` once it is closed and empty"
+            }
+            Builtin::ChanClose => "close a channel, sending to it afterwards is an error",
+            Builtin::MutexNew => "make an unlocked mutex",
+            Builtin::MutexLock => "lock a mutex, waiting while another thread holds it",
+            Builtin::MutexUnlock => "unlock a mutex held by the current thread",
+            Builtin::WithMutex => "call a closure without arguments with the mutex locked",
+            Builtin::SemNew => "make a counting semaphore with an initial count",
+            Builtin::SemPost => "increment the count of a semaphore",
+            Builtin::SemWait => "decrement the count of a semaphore, waiting while it is zero",
+            Builtin::SemTryWait => "decrement the count of a semaphore without waiting, if it can",
+            Builtin::ReadFile => "read a whole file, the error is the message of `errno`",
+            Builtin::WriteFile => "write a string to a file, the error is the message of `errno`",
+            Builtin::ListDir => "list the entries of a directory in alphabetical order",
+            Builtin::PathJoin => "join two paths, an absolute second path is kept as it is",
+            Builtin::PathBase => "the last component of a path, like `basename`",
+            Builtin::PathDir => "a path without its last component, like `dirname`",
+            Builtin::Format => "format like `printf`, taking one more argument per specifier",
+            Builtin::ListMin => "the smallest integer of a list, if it isn't empty",
+            Builtin::ListMax => "the largest integer of a list, if it isn't empty",
+            Builtin::Bitcast(_, _) => "reinterpret the bits as a type of the same size",
+        }
+    }
+
     pub fn get_arity(&self) -> usize {
         match self {
             Builtin::IAdd => 2,
@@ -46,16 +272,159 @@ impl Builtin {
             Builtin::IMul => 2,
             Builtin::IDiv => 2,
             Builtin::INeg => 1,
+            Builtin::Trunc32 => 1,
+            Builtin::SignExt32 => 1,
+            Builtin::ZeroExt32 => 1,
             Builtin::IRem => 2,
+            Builtin::ICmpEq => 2,
+            Builtin::ICmpNe => 2,
+            Builtin::ICmpLt => 2,
+            Builtin::ICmpLe => 2,
+            Builtin::ICmpGt => 2,
+            Builtin::ICmpGe => 2,
             Builtin::RAdd => 2,
             Builtin::RSub => 2,
             Builtin::RMul => 2,
             Builtin::RDiv => 2,
+            Builtin::Sinh => 1,
+            Builtin::Cosh => 1,
+            Builtin::Tanh => 1,
+            Builtin::Erf => 1,
+            Builtin::Erfc => 1,
             Builtin::BAnd => 2,
             Builtin::BOr => 2,
             Builtin::BNot => 1,
+            Builtin::Prefetch => 2,
+            Builtin::Expect => 2,
+            Builtin::SimdAdd => 2,
+            Builtin::SimdMul => 2,
+            Builtin::SimdLoad => 1,
+            Builtin::SimdStore => 1,
+            Builtin::CastOpaquePtr => 1,
+            Builtin::AllocAligned => 2,
+            Builtin::Yield => 1,
+            Builtin::Spawn => 1,
+            Builtin::Join => 1,
+            Builtin::SpawnWithStack => 2,
+            Builtin::ChanNew => 1,
+            Builtin::ChanSend => 2,
+            Builtin::ChanRecv => 1,
+            Builtin::ChanClose => 1,
+            Builtin::MutexNew => 0,
+            Builtin::MutexLock => 1,
+            Builtin::MutexUnlock => 1,
+            Builtin::WithMutex => 2,
+            Builtin::SemNew => 1,
+            Builtin::SemPost => 1,
+            Builtin::SemWait => 1,
+            Builtin::SemTryWait => 1,
+            Builtin::ReadFile => 1,
+            Builtin::WriteFile => 2,
+            Builtin::ListDir => 1,
+            Builtin::PathJoin => 2,
+            Builtin::PathBase => 1,
+            Builtin::PathDir => 1,
+            // and one argument for each specifier of the format
+            Builtin::Format => 1,
+            Builtin::ListMin => 1,
+            Builtin::ListMax => 1,
+            Builtin::Bitcast(_, _) => 1,
         }
     }
+
+    /// the binary operator for this primitive and its precedence, higher binds tighter.
+    /// all binary operators are left-associative, the precedences are on the same
+    /// scale as fixity declarations.
+    pub fn as_infix(&self) -> Option<(&'static str, u8)> {
+        let res = match self {
+            Builtin::ICmpEq => ("==", 4),
+            Builtin::ICmpNe => ("!=", 4),
+            Builtin::ICmpLt => ("<", 4),
+            Builtin::ICmpLe => ("<=", 4),
+            Builtin::ICmpGt => (">", 4),
+            Builtin::ICmpGe => (">=", 4),
+            Builtin::IAdd => ("+", 6),
+            Builtin::ISub => ("-", 6),
+            Builtin::IMul => ("*", 7),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    pub fn from_infix(op: &str) -> Option<Builtin> {
+        let res = match op {
+            "==" => Builtin::ICmpEq,
+            "!=" => Builtin::ICmpNe,
+            "<" => Builtin::ICmpLt,
+            "<=" => Builtin::ICmpLe,
+            ">" => Builtin::ICmpGt,
+            ">=" => Builtin::ICmpGe,
+            "+" => Builtin::IAdd,
+            "-" => Builtin::ISub,
+            "*" => Builtin::IMul,
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// the prefix operator for this primitive, which binds tighter than any binary operator
+    pub fn as_prefix(&self) -> Option<&'static str> {
+        match self {
+            Builtin::INeg => Some("-"),
+            Builtin::BNot => Some("!"),
+            _ => None,
+        }
+    }
+
+    pub fn from_prefix(op: &str) -> Option<Builtin> {
+        match op {
+            "-" => Some(Builtin::INeg),
+            "!" => Some(Builtin::BNot),
+            _ => None,
+        }
+    }
+}
+
+/// the types of the arguments taken by the format of `@format`, and the format as passed
+/// to `snprintf`. `%d` takes an `Int`, `%f`, `%e` and `%g` a `Real`, `%s` a `Str` and
+/// `%c` a `Char`, with flags, a width and a precision before the letter. `%%` is a `%`.
+pub fn parse_format(fmt: &str) -> Result<(Vec<LitType>, String), String> {
+    let mut types = Vec::new();
+    let mut c_fmt = String::new();
+    let mut chars = fmt.chars();
+    while let Some(ch) = chars.next() {
+        c_fmt.push(ch);
+        if ch != '%' {
+            continue;
+        }
+        let mut spec = String::new();
+        let conv = loop {
+            match chars.next() {
+                Some(ch @ ('-' | '+' | ' ' | '#' | '.' | '0'..='9')) => spec.push(ch),
+                Some(ch) => break ch,
+                None => return Err("the format ends in the middle of a specifier".to_string()),
+            }
+        };
+        let typ = match conv {
+            '%' if spec.is_empty() => {
+                c_fmt.push('%');
+                continue;
+            }
+            'd' => LitType::Int,
+            'f' | 'e' | 'g' => LitType::Real,
+            's' => LitType::Str,
+            'c' => LitType::Char,
+            _ => return Err(format!("`%{spec}{conv}` is not a format specifier")),
+        };
+        c_fmt.push_str(&spec);
+        if typ == LitType::Int {
+            // integers are passed as `int64_t`
+            c_fmt.push_str("ll");
+        }
+        c_fmt.push(conv);
+        types.push(typ);
+    }
+    Ok((types, c_fmt))
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -93,6 +462,32 @@ pub enum Expr {
         args: Vec<Expr>,
         span: Span,
     },
+    Tuple {
+        elems: Vec<Expr>,
+        span: Span,
+    },
+    Proj {
+        expr: Box<Expr>,
+        index: usize,
+        span: Span,
+    },
+    /// `{ x = 1, y = 2 }`, the record is resolved from the field names
+    Record {
+        fields: Vec<FieldInit>,
+        span: Span,
+    },
+    /// `e.x`, accessing a field of a record
+    Field {
+        expr: Box<Expr>,
+        field: Ident,
+        span: Span,
+    },
+    /// `{ e with x = 1 }`, a copy of the record `e` with some fields replaced
+    Update {
+        expr: Box<Expr>,
+        fields: Vec<FieldInit>,
+        span: Span,
+    },
     Let {
         bind: Ident,
         expr: Box<Expr>,
@@ -102,6 +497,22 @@ pub enum Expr {
     Case {
         expr: Box<Expr>,
         rules: Vec<Rule>,
+        /// the case of `let (a, b) = e; cont`, with a single rule. a missing pattern is only
+        /// warned about, the program aborts if the value doesn't match
+        is_let: bool,
+        span: Span,
+    },
+    /// `try { e } catch | Err(x) => { h } end`, the handlers match the result `e` if it failed.
+    /// the renamer turns it into `case e of | Ok(x) => { x } | Err(x) => { h } end`
+    Try {
+        expr: Box<Expr>,
+        handlers: Vec<Rule>,
+        span: Span,
+    },
+    Ifte {
+        cond: Box<Expr>,
+        trbr: Box<Expr>,
+        flbr: Box<Expr>,
         span: Span,
     },
     Blk {
@@ -109,6 +520,32 @@ pub enum Expr {
         cont: Box<Expr>,
         span: Span,
     },
+    /// `letrec decls in cont`, like a block but `cont` extends as far as possible
+    LetRec {
+        decls: Vec<Decl>,
+        cont: Box<Expr>,
+        span: Span,
+    },
+    /// `(e : T)`, `e` is checked against the type `T`
+    Anno {
+        expr: Box<Expr>,
+        typ: Type,
+        span: Span,
+    },
+    /// `?name`, an expression not written yet, the type checker reports what it should be
+    Hole {
+        name: InternStr,
+        span: Span,
+    },
+    /// the declarations of the module `name` with `cont` in their scope, where they are
+    /// also named like `name.x`. the driver makes one for every file imported,
+    /// and the renamer turns it into a block
+    Module {
+        name: InternStr,
+        decls: Vec<Decl>,
+        cont: Box<Expr>,
+        span: Span,
+    },
 }
 
 impl Spanned for Expr {
@@ -121,9 +558,20 @@ impl Spanned for Expr {
             Expr::App { span, .. } => span,
             Expr::ExtCall { span, .. } => span,
             Expr::Cons { span, .. } => span,
+            Expr::Tuple { span, .. } => span,
+            Expr::Proj { span, .. } => span,
+            Expr::Record { span, .. } => span,
+            Expr::Field { span, .. } => span,
+            Expr::Update { span, .. } => span,
             Expr::Let { span, .. } => span,
             Expr::Case { span, .. } => span,
+            Expr::Try { span, .. } => span,
+            Expr::Ifte { span, .. } => span,
             Expr::Blk { span, .. } => span,
+            Expr::LetRec { span, .. } => span,
+            Expr::Anno { span, .. } => span,
+            Expr::Hole { span, .. } => span,
+            Expr::Module { span, .. } => span,
         }
     }
     fn span_mut(&mut self) -> &mut Span {
@@ -135,9 +583,20 @@ impl Spanned for Expr {
             Expr::App { span, .. } => span,
             Expr::ExtCall { span, .. } => span,
             Expr::Cons { span, .. } => span,
+            Expr::Tuple { span, .. } => span,
+            Expr::Proj { span, .. } => span,
+            Expr::Record { span, .. } => span,
+            Expr::Field { span, .. } => span,
+            Expr::Update { span, .. } => span,
             Expr::Let { span, .. } => span,
             Expr::Case { span, .. } => span,
+            Expr::Try { span, .. } => span,
+            Expr::Ifte { span, .. } => span,
             Expr::Blk { span, .. } => span,
+            Expr::LetRec { span, .. } => span,
+            Expr::Anno { span, .. } => span,
+            Expr::Hole { span, .. } => span,
+            Expr::Module { span, .. } => span,
         }
     }
 }
@@ -152,9 +611,66 @@ impl Expr {
             Expr::App { .. } => true,
             Expr::ExtCall { .. } => true,
             Expr::Cons { .. } => true,
+            Expr::Tuple { .. } => true,
+            Expr::Proj { .. } => true,
+            Expr::Record { .. } => true,
+            Expr::Field { .. } => true,
+            Expr::Update { .. } => true,
+            Expr::Anno { .. } => true,
+            Expr::Hole { .. } => true,
             Expr::Let { .. } => false,
             Expr::Case { .. } => false,
+            Expr::Try { .. } => false,
+            Expr::Ifte { .. } => false,
             Expr::Blk { .. } => false,
+            Expr::LetRec { .. } => false,
+            Expr::Module { .. } => false,
+        }
+    }
+
+    /// the direct subexpressions, including guards and the bodies of local functions
+    pub fn subexprs(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Lit { .. } | Expr::Var { .. } | Expr::Hole { .. } => Vec::new(),
+            Expr::Prim { args, .. } | Expr::ExtCall { args, .. } | Expr::Cons { args, .. } => {
+                args.iter().collect()
+            }
+            Expr::App { func, args, .. } => std::iter::once(func.as_ref()).chain(args).collect(),
+            Expr::Tuple { elems, .. } => elems.iter().collect(),
+            Expr::Fun { body, .. } => vec![body],
+            Expr::Proj { expr, .. } | Expr::Field { expr, .. } | Expr::Anno { expr, .. } => {
+                vec![expr]
+            }
+            Expr::Record { fields, .. } => fields.iter().map(|field| &field.expr).collect(),
+            Expr::Update { expr, fields, .. } => std::iter::once(expr.as_ref())
+                .chain(fields.iter().map(|field| &field.expr))
+                .collect(),
+            Expr::Let { expr, cont, .. } => vec![expr, cont],
+            Expr::Case { expr, rules, .. }
+            | Expr::Try {
+                expr,
+                handlers: rules,
+                ..
+            } => std::iter::once(expr.as_ref())
+                .chain(
+                    rules
+                        .iter()
+                        .flat_map(|rule| rule.guard.iter().chain([&rule.body])),
+                )
+                .collect(),
+            Expr::Ifte {
+                cond, trbr, flbr, ..
+            } => vec![cond, trbr, flbr],
+            Expr::Blk { decls, cont, .. }
+            | Expr::LetRec { decls, cont, .. }
+            | Expr::Module { decls, cont, .. } => decls
+                .iter()
+                .filter_map(|decl| match decl {
+                    Decl::Func { body, .. } | Decl::Val { body, .. } => Some(body.as_ref()),
+                    _ => None,
+                })
+                .chain([cont.as_ref()])
+                .collect(),
         }
     }
 }
@@ -162,6 +678,8 @@ impl Expr {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Rule {
     pub patn: Pattern,
+    /// `| p if guard => ...`, the rule is skipped if the guard is false
+    pub guard: Option<Expr>,
     pub body: Expr,
     pub span: Span,
 }
@@ -181,17 +699,29 @@ pub enum Pattern {
         pars: Vec<Pattern>,
         span: Span,
     },
+    Tuple {
+        pats: Vec<Pattern>,
+        span: Span,
+    },
     Wild {
         span: Span,
     },
+    /// `p1 | p2 | ...`, every alternative binds the same variables
+    Or {
+        alts: Vec<Pattern>,
+        span: Span,
+    },
+    /// `x @ p`, binds `x` to the whole value matched by `p`
+    As {
+        bind: Ident,
+        patn: Box<Pattern>,
+        span: Span,
+    },
 }
 
 impl Pattern {
     pub fn is_wild_or_var(&self) -> bool {
-        match self {
-            Pattern::Var { .. } | Pattern::Wild { .. } => true,
-            _ => false,
-        }
+        matches!(self, Pattern::Var { .. } | Pattern::Wild { .. })
     }
 }
 impl Pattern {
@@ -208,13 +738,71 @@ impl Pattern {
                 }
                 Pattern::Lit { .. } => {}
                 Pattern::Cons { pars, .. } => {
-                    stack.extend(pars.into_iter());
+                    stack.extend(pars);
+                }
+                Pattern::Tuple { pats, .. } => {
+                    stack.extend(pats);
                 }
                 Pattern::Wild { .. } => {}
+                // the alternatives bind the same variables
+                Pattern::Or { alts, .. } => {
+                    stack.push(&alts[0]);
+                }
+                Pattern::As { bind, patn, .. } => {
+                    if !vec.contains(bind) {
+                        vec.push(*bind);
+                    }
+                    stack.push(patn);
+                }
             }
         }
         vec
     }
+
+    /// the patterns without any or-pattern, which together match the same values
+    pub fn expand_or(&self) -> Vec<Pattern> {
+        // every combination of the alternatives of the sub-patterns
+        fn product(pats: &[Pattern]) -> Vec<Vec<Pattern>> {
+            pats.iter().fold(vec![Vec::new()], |prefixes, pat| {
+                let alts = pat.expand_or();
+                prefixes
+                    .iter()
+                    .flat_map(|prefix| {
+                        alts.iter().map(move |alt| {
+                            let mut prefix = prefix.clone();
+                            prefix.push(alt.clone());
+                            prefix
+                        })
+                    })
+                    .collect()
+            })
+        }
+        match self {
+            Pattern::Var { .. } | Pattern::Lit { .. } | Pattern::Wild { .. } => vec![self.clone()],
+            Pattern::Cons { cons, pars, span } => product(pars)
+                .into_iter()
+                .map(|pars| Pattern::Cons {
+                    cons: *cons,
+                    pars,
+                    span: *span,
+                })
+                .collect(),
+            Pattern::Tuple { pats, span } => product(pats)
+                .into_iter()
+                .map(|pats| Pattern::Tuple { pats, span: *span })
+                .collect(),
+            Pattern::Or { alts, .. } => alts.iter().flat_map(Pattern::expand_or).collect(),
+            Pattern::As { bind, patn, span } => patn
+                .expand_or()
+                .into_iter()
+                .map(|patn| Pattern::As {
+                    bind: *bind,
+                    patn: Box::new(patn),
+                    span: *span,
+                })
+                .collect(),
+        }
+    }
 }
 
 impl Spanned for Pattern {
@@ -223,7 +811,10 @@ impl Spanned for Pattern {
             Pattern::Var { span, .. } => span,
             Pattern::Lit { span, .. } => span,
             Pattern::Cons { span, .. } => span,
+            Pattern::Tuple { span, .. } => span,
             Pattern::Wild { span } => span,
+            Pattern::Or { span, .. } => span,
+            Pattern::As { span, .. } => span,
         }
     }
     fn span_mut(&mut self) -> &mut Span {
@@ -231,7 +822,171 @@ impl Spanned for Pattern {
             Pattern::Var { span, .. } => span,
             Pattern::Lit { span, .. } => span,
             Pattern::Cons { span, .. } => span,
+            Pattern::Tuple { span, .. } => span,
             Pattern::Wild { span } => span,
+            Pattern::Or { span, .. } => span,
+            Pattern::As { span, .. } => span,
+        }
+    }
+}
+
+/// calling convention of an external function
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CallConv {
+    C,
+    Stdcall,
+    Fastcall,
+    Vectorcall,
+    Aapcs,
+}
+
+impl CallConv {
+    pub const ALL: &'static [CallConv] = &[
+        CallConv::C,
+        CallConv::Stdcall,
+        CallConv::Fastcall,
+        CallConv::Vectorcall,
+        CallConv::Aapcs,
+    ];
+
+    pub fn from_name(str: &str) -> Option<CallConv> {
+        match str {
+            "c" => Some(CallConv::C),
+            "stdcall" => Some(CallConv::Stdcall),
+            "fastcall" => Some(CallConv::Fastcall),
+            "vectorcall" => Some(CallConv::Vectorcall),
+            "aapcs" => Some(CallConv::Aapcs),
+            _ => None,
+        }
+    }
+}
+
+/// how much a function marked with `@optimize(...)` is optimized
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OptLevel {
+    /// copied through every optimization pass untouched
+    Never,
+    /// optimized without inlining
+    Size,
+}
+
+impl OptLevel {
+    pub const ALL: &'static [OptLevel] = &[OptLevel::Never, OptLevel::Size];
+
+    pub fn from_name(str: &str) -> Option<OptLevel> {
+        match str {
+            "never" => Some(OptLevel::Never),
+            "size" => Some(OptLevel::Size),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Attr {
+    Test {
+        span: Span,
+    },
+    Bench {
+        span: Span,
+    },
+    NoUnroll {
+        span: Span,
+    },
+    Cold {
+        span: Span,
+    },
+    CallConv {
+        conv: CallConv,
+        span: Span,
+    },
+    Optimize {
+        level: OptLevel,
+        span: Span,
+    },
+    /// `@deprecated_since("1.2.0", "bar")`, every use of the function is warned about
+    Deprecated {
+        since: InternStr,
+        replacement: InternStr,
+        span: Span,
+    },
+}
+
+impl Attr {
+    /// every attribute, for generating the language reference
+    pub fn all() -> Vec<Attr> {
+        let span = Span::default();
+        vec![
+            Attr::Test { span },
+            Attr::Bench { span },
+            Attr::NoUnroll { span },
+            Attr::Cold { span },
+            Attr::CallConv {
+                conv: CallConv::C,
+                span,
+            },
+            Attr::Optimize {
+                level: OptLevel::Never,
+                span,
+            },
+            Attr::Deprecated {
+                since: InternStr::new(""),
+                replacement: InternStr::new(""),
+                span,
+            },
+        ]
+    }
+
+    /// a one-line description for the language reference
+    pub fn doc(&self) -> &'static str {
+        match self {
+            Attr::Test { .. } => "run the function with `norem test`, it takes no arguments",
+            Attr::Bench { .. } => "time the function with `norem bench`, it takes no arguments",
+            Attr::NoUnroll { .. } => "never unroll or inline the recursion of the function",
+            Attr::Cold { .. } => {
+                "the function is rarely called, branches calling it are laid out last"
+            }
+            Attr::CallConv { .. } => "calling convention of an extern function",
+            Attr::Optimize { .. } => "how much the optimizer may change the function",
+            Attr::Deprecated { .. } => {
+                "warn at every use of the function, with the version and the replacement"
+            }
+        }
+    }
+
+    /// the attribute written as `name`, for those without arguments
+    pub fn from_name(name: &str, span: Span) -> Option<Attr> {
+        match name {
+            "@test" => Some(Attr::Test { span }),
+            "@bench" => Some(Attr::Bench { span }),
+            "@nounroll" => Some(Attr::NoUnroll { span }),
+            "@cold" => Some(Attr::Cold { span }),
+            _ => None,
+        }
+    }
+}
+
+impl Spanned for Attr {
+    fn span(&self) -> &Span {
+        match self {
+            Attr::Test { span } => span,
+            Attr::Bench { span } => span,
+            Attr::NoUnroll { span } => span,
+            Attr::Cold { span } => span,
+            Attr::CallConv { span, .. } => span,
+            Attr::Optimize { span, .. } => span,
+            Attr::Deprecated { span, .. } => span,
+        }
+    }
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Attr::Test { span } => span,
+            Attr::Bench { span } => span,
+            Attr::NoUnroll { span } => span,
+            Attr::Cold { span } => span,
+            Attr::CallConv { span, .. } => span,
+            Attr::Optimize { span, .. } => span,
+            Attr::Deprecated { span, .. } => span,
         }
     }
 }
@@ -240,7 +995,21 @@ impl Spanned for Pattern {
 pub enum Decl {
     Func {
         name: Ident,
-        pars: Vec<Ident>,
+        attrs: Vec<Attr>,
+        /// parameters with optional type annotations, like `fun f(x: Int, y) => ...`
+        pars: Vec<(Ident, Option<Type>)>,
+        /// the optional return type annotation, like `fun f(x): Int => ...`
+        ret: Option<Type>,
+        body: Box<Expr>,
+        span: Span,
+    },
+    /// `let pi: Real = 3.14159;`, a value computed once before the rest of the block.
+    /// the renamer turns the values of a block into `let`s, in the order they depend on
+    /// each other, so only functions may be mutually recursive
+    Val {
+        name: Ident,
+        /// the optional type annotation, like `let x: Int = ...`
+        typ: Option<Type>,
         body: Box<Expr>,
         span: Span,
     },
@@ -250,27 +1019,104 @@ pub enum Decl {
         vars: Vec<Varient>,
         span: Span,
     },
+    /// `data Point = { x: Int, y: Int }`, a record with named fields
+    Record {
+        name: Ident,
+        fields: Vec<FieldDecl>,
+        span: Span,
+    },
     Type {
         name: Ident,
         pars: Vec<Ident>,
         typ: Type,
         span: Span,
     },
+    /// `type Handle;`, a type whose representation is the `type` or `data` of the same
+    /// name in the same block. only the declarations of the block see the representation,
+    /// the rest of the program, like the importers of a module, can't construct or match it
+    Abstract {
+        name: Ident,
+        pars: Vec<Ident>,
+        span: Span,
+    },
     Extern {
         name: InternStr,
+        attrs: Vec<Attr>,
         pars: Vec<Ident>,
         typ: Type,
         span: Span,
     },
+    /// `infixl 6 <+>;`, the operator is the function named `<+>`
+    Fixity {
+        oper: Ident,
+        fixity: Fixity,
+        span: Span,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+/// precedence and associativity of a binary operator, higher precedence binds tighter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixity {
+    pub prec: u8,
+    pub assoc: Assoc,
+}
+
+/// the highest precedence a fixity declaration can have
+pub const MAX_PREC: u8 = 9;
+
+/// the pipeline operator, `x |> f(y)` is `f(y, x)` and `x |> f` is `f(x)`: the left operand
+/// becomes the last argument of an application on the right, or the only argument of
+/// anything else. it binds looser than the builtin operators
+pub const PIPE: &str = "|>";
+pub const PIPE_FIXITY: Fixity = Fixity {
+    prec: 1,
+    assoc: Assoc::Left,
+};
+
+/// constructors of the list type, which list literals and patterns are made of
+pub const LIST_CONS: &str = "Cons";
+pub const LIST_NIL: &str = "Nil";
+
+/// the option type `@chanrecv` returns, and its constructors
+pub const OPTION: &str = "Option";
+pub const OPTION_SOME: &str = "Some";
+pub const OPTION_NONE: &str = "None";
+
+/// the result type `@readfile` and `@writefile` return, and its constructors
+pub const RESULT: &str = "Result";
+pub const RESULT_OK: &str = "Ok";
+pub const RESULT_ERR: &str = "Err";
+
+/// `module Foo;` and the `import Bar;` after it, at the start of a file.
+/// a file with a module name has only declarations, the others are main programs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Header {
+    pub module: Option<(InternStr, Span)>,
+    pub imports: Vec<(InternStr, Span)>,
+}
+
+/// the name of the declaration `name` of the module `module`, like `Foo.bar`
+pub fn qualified(module: InternStr, name: Ident) -> Ident {
+    Ident::from(InternStr::new(format!("{module}.{}", name.name)))
 }
 
 impl Decl {
     pub fn get_name(&self) -> Ident {
         match self {
             Decl::Func { name, .. } => *name,
+            Decl::Val { name, .. } => *name,
             Decl::Data { name, .. } => *name,
+            Decl::Record { name, .. } => *name,
             Decl::Type { name, .. } => *name,
+            Decl::Abstract { name, .. } => *name,
             Decl::Extern { name, .. } => Ident::from(*name),
+            Decl::Fixity { oper, .. } => *oper,
         }
     }
 }
@@ -282,34 +1128,83 @@ pub struct Varient {
     pub span: Span,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldDecl {
+    pub field: Ident,
+    pub typ: Type,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldInit {
+    pub field: Ident,
+    pub expr: Expr,
+    pub span: Span,
+}
+
 impl Spanned for Decl {
     fn span(&self) -> &Span {
         match self {
             Decl::Func { span, .. } => span,
+            Decl::Val { span, .. } => span,
             Decl::Data { span, .. } => span,
+            Decl::Record { span, .. } => span,
             Decl::Type { span, .. } => span,
+            Decl::Abstract { span, .. } => span,
             Decl::Extern { span, .. } => span,
+            Decl::Fixity { span, .. } => span,
         }
     }
     fn span_mut(&mut self) -> &mut Span {
         match self {
             Decl::Func { span, .. } => span,
+            Decl::Val { span, .. } => span,
             Decl::Data { span, .. } => span,
+            Decl::Record { span, .. } => span,
             Decl::Type { span, .. } => span,
+            Decl::Abstract { span, .. } => span,
             Decl::Extern { span, .. } => span,
+            Decl::Fixity { span, .. } => span,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub enum LitType {
     Int,
     Real,
     Bool,
     Char,
+    Str,
     Unit,
 }
 
+impl LitType {
+    /// the size of the value in bytes, as seen by C code
+    pub fn byte_size(&self) -> usize {
+        match self {
+            LitType::Int => 8,
+            LitType::Real => 8,
+            LitType::Bool => 1,
+            LitType::Char => 4,
+            // a pointer to the characters
+            LitType::Str => 8,
+            LitType::Unit => 0,
+        }
+    }
+}
+
+/// the C type of an argument passed to the `...` of a variadic extern,
+/// such arguments are unboxed since C can't tell their types
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FfiType {
+    Int,
+    Real,
+    Bool,
+    Char,
+    Ptr,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Type {
     Lit {
@@ -320,9 +1215,11 @@ pub enum Type {
         var: Ident,
         span: Span,
     },
+    /// `fun(Ptr, ...) -> ()` is variadic, which is only allowed for externs
     Fun {
         pars: Vec<Type>,
         res: Box<Type>,
+        variadic: bool,
         span: Span,
     },
     App {
@@ -330,8 +1227,154 @@ pub enum Type {
         args: Vec<Type>,
         span: Span,
     },
+    Tuple {
+        elems: Vec<Type>,
+        span: Span,
+    },
+    /// `Simd[Real, 2]`, a vector of `lanes` elements for SIMD arithmetic
+    SimdVec {
+        lanes: usize,
+        elem: LitType,
+        span: Span,
+    },
+    /// `Ptr`, an opaque handle from C, it can only be passed around
+    OpaquePtr {
+        span: Span,
+    },
+    /// `Coroutine[A, B]`, a coroutine passing values of `A` back and forth,
+    /// whose function finally returns a `B`
+    Coroutine {
+        arg: Box<Type>,
+        res: Box<Type>,
+        span: Span,
+    },
+    /// `Thread[A]`, a running thread whose closure returns an `A`
+    Thread {
+        res: Box<Type>,
+        span: Span,
+    },
+    /// `Chan[A]`, a channel passing values of `A` between threads
+    Chan {
+        elem: Box<Type>,
+        span: Span,
+    },
+    /// `Mutex`, a lock shared between threads
+    Mutex {
+        span: Span,
+    },
+    /// `Sem`, a counting semaphore shared between threads
+    Sem {
+        span: Span,
+    },
+    /// `forall T U. fun(T) -> U`, polymorphic in `vars`.
+    /// only the type of an extern or a type alias can be a scheme
+    Scheme {
+        vars: Vec<Ident>,
+        body: Box<Type>,
+        span: Span,
+    },
+}
+
+impl Type {
+    /// the type under the quantified variables of a scheme, or the type itself
+    pub fn unquantified(&self) -> &Type {
+        match self {
+            Type::Scheme { body, .. } => body,
+            typ => typ,
+        }
+    }
+
+    /// the body of a scheme with its variables replaced by fresh ones,
+    /// any other type is returned as is
+    pub fn instantiate(&self, mut fresh_vars: impl FnMut() -> Ident) -> Type {
+        match self {
+            Type::Scheme { vars, body, .. } => {
+                let map = vars.iter().map(|var| (*var, fresh_vars())).collect();
+                body.rename_vars(&map)
+            }
+            typ => typ.clone(),
+        }
+    }
+
+    /// replace the type variables in `map`, the variables of an inner scheme shadow them
+    fn rename_vars(&self, map: &HashMap<Ident, Ident>) -> Type {
+        let rename = |typ: &Type| typ.rename_vars(map);
+        match self {
+            Type::Var { var, span } => Type::Var {
+                var: map.get(var).copied().unwrap_or(*var),
+                span: *span,
+            },
+            Type::Lit { .. }
+            | Type::SimdVec { .. }
+            | Type::OpaquePtr { .. }
+            | Type::Mutex { .. }
+            | Type::Sem { .. } => self.clone(),
+            Type::Fun {
+                pars,
+                res,
+                variadic,
+                span,
+            } => Type::Fun {
+                pars: pars.iter().map(rename).collect(),
+                res: Box::new(rename(res)),
+                variadic: *variadic,
+                span: *span,
+            },
+            Type::App { cons, args, span } => Type::App {
+                cons: *cons,
+                args: args.iter().map(rename).collect(),
+                span: *span,
+            },
+            Type::Tuple { elems, span } => Type::Tuple {
+                elems: elems.iter().map(rename).collect(),
+                span: *span,
+            },
+            Type::Coroutine { arg, res, span } => Type::Coroutine {
+                arg: Box::new(rename(arg)),
+                res: Box::new(rename(res)),
+                span: *span,
+            },
+            Type::Thread { res, span } => Type::Thread {
+                res: Box::new(rename(res)),
+                span: *span,
+            },
+            Type::Chan { elem, span } => Type::Chan {
+                elem: Box::new(rename(elem)),
+                span: *span,
+            },
+            Type::Scheme { vars, body, span } => {
+                let mut map = map.clone();
+                for var in vars {
+                    map.remove(var);
+                }
+                Type::Scheme {
+                    vars: vars.clone(),
+                    body: Box::new(body.rename_vars(&map)),
+                    span: *span,
+                }
+            }
+        }
+    }
 }
 
+/// the only vector shape for now, two `Real`s fill a 128-bit SSE2 register
+pub const SIMD_LANES: usize = 2;
+
+/// the builtin type of coroutines, which is a type constructor of two arguments
+pub const COROUTINE: &str = "Coroutine";
+
+/// the builtin type of threads, which is a type constructor of one argument
+pub const THREAD: &str = "Thread";
+
+/// the builtin type of channels, which is a type constructor of one argument
+pub const CHAN: &str = "Chan";
+
+/// the builtin type of mutexes
+pub const MUTEX: &str = "Mutex";
+
+/// the builtin type of semaphores
+pub const SEM: &str = "Sem";
+
 impl Spanned for Type {
     fn span(&self) -> &Span {
         match self {
@@ -339,6 +1382,15 @@ impl Spanned for Type {
             Type::Var { span, .. } => span,
             Type::Fun { span, .. } => span,
             Type::App { span, .. } => span,
+            Type::Tuple { span, .. } => span,
+            Type::SimdVec { span, .. } => span,
+            Type::OpaquePtr { span } => span,
+            Type::Coroutine { span, .. } => span,
+            Type::Thread { span, .. } => span,
+            Type::Chan { span, .. } => span,
+            Type::Mutex { span } => span,
+            Type::Sem { span } => span,
+            Type::Scheme { span, .. } => span,
         }
     }
     fn span_mut(&mut self) -> &mut Span {
@@ -347,6 +1399,15 @@ impl Spanned for Type {
             Type::Var { span, .. } => span,
             Type::Fun { span, .. } => span,
             Type::App { span, .. } => span,
+            Type::Tuple { span, .. } => span,
+            Type::SimdVec { span, .. } => span,
+            Type::OpaquePtr { span } => span,
+            Type::Coroutine { span, .. } => span,
+            Type::Thread { span, .. } => span,
+            Type::Chan { span, .. } => span,
+            Type::Mutex { span } => span,
+            Type::Sem { span } => span,
+            Type::Scheme { span, .. } => span,
         }
     }
 }
@@ -0,0 +1,419 @@
+use super::*;
+use std::collections::HashMap;
+
+/// Whether `expr1` and `expr2` are the same expression, ignoring spans.
+/// A free variable of `expr1` in `map` stands for the variable of `expr2` it is mapped to,
+/// any other free variable must be the same in both. Variables bound inside the
+/// expressions are related where they are bound, so `fun(x) => x` and `fun(y) => y` are equal.
+pub fn equiv_expr(expr1: &Expr, expr2: &Expr, map: HashMap<Ident, Ident>) -> bool {
+    let mut pass = AstEquiv { map };
+    pass.eq_expr(expr1, expr2)
+}
+
+struct AstEquiv {
+    map: HashMap<Ident, Ident>,
+}
+
+impl AstEquiv {
+    fn eq_ident(&self, ident1: &Ident, ident2: &Ident) -> bool {
+        self.map.get(ident1).unwrap_or(ident1) == ident2
+    }
+
+    fn bind(&mut self, ident1: &Ident, ident2: &Ident) {
+        self.map.insert(*ident1, *ident2);
+    }
+
+    fn eq_exprs(&mut self, exprs1: &[Expr], exprs2: &[Expr]) -> bool {
+        exprs1.len() == exprs2.len()
+            && exprs1
+                .iter()
+                .zip(exprs2.iter())
+                .all(|(expr1, expr2)| self.eq_expr(expr1, expr2))
+    }
+
+    fn eq_field_inits(&mut self, fields1: &[FieldInit], fields2: &[FieldInit]) -> bool {
+        fields1.len() == fields2.len()
+            && fields1.iter().zip(fields2.iter()).all(|(init1, init2)| {
+                init1.field == init2.field && self.eq_expr(&init1.expr, &init2.expr)
+            })
+    }
+
+    fn eq_expr(&mut self, expr1: &Expr, expr2: &Expr) -> bool {
+        match (expr1, expr2) {
+            (Expr::Lit { lit: lit1, .. }, Expr::Lit { lit: lit2, .. }) => lit1 == lit2,
+            (Expr::Var { var: var1, .. }, Expr::Var { var: var2, .. }) => self.eq_ident(var1, var2),
+            (
+                Expr::Prim {
+                    prim: prim1,
+                    args: args1,
+                    ..
+                },
+                Expr::Prim {
+                    prim: prim2,
+                    args: args2,
+                    ..
+                },
+            ) => prim1 == prim2 && self.eq_exprs(args1, args2),
+            (
+                Expr::Fun {
+                    pars: pars1,
+                    body: body1,
+                    ..
+                },
+                Expr::Fun {
+                    pars: pars2,
+                    body: body2,
+                    ..
+                },
+            ) => {
+                pars1.len() == pars2.len() && {
+                    pars1
+                        .iter()
+                        .zip(pars2.iter())
+                        .for_each(|(par1, par2)| self.bind(par1, par2));
+                    self.eq_expr(body1, body2)
+                }
+            }
+            (
+                Expr::App {
+                    func: func1,
+                    args: args1,
+                    ..
+                },
+                Expr::App {
+                    func: func2,
+                    args: args2,
+                    ..
+                },
+            ) => self.eq_expr(func1, func2) && self.eq_exprs(args1, args2),
+            (
+                Expr::ExtCall {
+                    func: func1,
+                    args: args1,
+                    ..
+                },
+                Expr::ExtCall {
+                    func: func2,
+                    args: args2,
+                    ..
+                },
+            ) => func1 == func2 && self.eq_exprs(args1, args2),
+            (
+                Expr::Cons {
+                    cons: cons1,
+                    args: args1,
+                    ..
+                },
+                Expr::Cons {
+                    cons: cons2,
+                    args: args2,
+                    ..
+                },
+            ) => cons1 == cons2 && self.eq_exprs(args1, args2),
+            (Expr::Tuple { elems: elems1, .. }, Expr::Tuple { elems: elems2, .. }) => {
+                self.eq_exprs(elems1, elems2)
+            }
+            (
+                Expr::Proj {
+                    expr: expr1,
+                    index: index1,
+                    ..
+                },
+                Expr::Proj {
+                    expr: expr2,
+                    index: index2,
+                    ..
+                },
+            ) => index1 == index2 && self.eq_expr(expr1, expr2),
+            (
+                Expr::Record {
+                    fields: fields1, ..
+                },
+                Expr::Record {
+                    fields: fields2, ..
+                },
+            ) => self.eq_field_inits(fields1, fields2),
+            (
+                Expr::Field {
+                    expr: expr1,
+                    field: field1,
+                    ..
+                },
+                Expr::Field {
+                    expr: expr2,
+                    field: field2,
+                    ..
+                },
+            ) => field1 == field2 && self.eq_expr(expr1, expr2),
+            (
+                Expr::Update {
+                    expr: expr1,
+                    fields: fields1,
+                    ..
+                },
+                Expr::Update {
+                    expr: expr2,
+                    fields: fields2,
+                    ..
+                },
+            ) => self.eq_expr(expr1, expr2) && self.eq_field_inits(fields1, fields2),
+            (
+                Expr::Let {
+                    bind: bind1,
+                    expr: expr1,
+                    cont: cont1,
+                    ..
+                },
+                Expr::Let {
+                    bind: bind2,
+                    expr: expr2,
+                    cont: cont2,
+                    ..
+                },
+            ) => {
+                self.eq_expr(expr1, expr2) && {
+                    self.bind(bind1, bind2);
+                    self.eq_expr(cont1, cont2)
+                }
+            }
+            (
+                Expr::Case {
+                    expr: expr1,
+                    rules: rules1,
+                    ..
+                },
+                Expr::Case {
+                    expr: expr2,
+                    rules: rules2,
+                    ..
+                },
+            ) => {
+                self.eq_expr(expr1, expr2)
+                    && rules1.len() == rules2.len()
+                    && rules1.iter().zip(rules2.iter()).all(|(rule1, rule2)| {
+                        self.eq_pattern(&rule1.patn, &rule2.patn)
+                            && match (&rule1.guard, &rule2.guard) {
+                                (None, None) => true,
+                                (Some(guard1), Some(guard2)) => self.eq_expr(guard1, guard2),
+                                _ => false,
+                            }
+                            && self.eq_expr(&rule1.body, &rule2.body)
+                    })
+            }
+            (
+                Expr::Ifte {
+                    cond: cond1,
+                    trbr: trbr1,
+                    flbr: flbr1,
+                    ..
+                },
+                Expr::Ifte {
+                    cond: cond2,
+                    trbr: trbr2,
+                    flbr: flbr2,
+                    ..
+                },
+            ) => {
+                self.eq_expr(cond1, cond2)
+                    && self.eq_expr(trbr1, trbr2)
+                    && self.eq_expr(flbr1, flbr2)
+            }
+            (
+                Expr::Blk {
+                    decls: decls1,
+                    cont: cont1,
+                    ..
+                },
+                Expr::Blk {
+                    decls: decls2,
+                    cont: cont2,
+                    ..
+                },
+            )
+            | (
+                Expr::LetRec {
+                    decls: decls1,
+                    cont: cont1,
+                    ..
+                },
+                Expr::LetRec {
+                    decls: decls2,
+                    cont: cont2,
+                    ..
+                },
+            ) => self.eq_decls(decls1, decls2) && self.eq_expr(cont1, cont2),
+            (
+                Expr::Anno {
+                    expr: expr1,
+                    typ: typ1,
+                    ..
+                },
+                Expr::Anno {
+                    expr: expr2,
+                    typ: typ2,
+                    ..
+                },
+            ) => format!("{typ1}") == format!("{typ2}") && self.eq_expr(expr1, expr2),
+            (Expr::Hole { name: name1, .. }, Expr::Hole { name: name2, .. }) => name1 == name2,
+            (_, _) => false,
+        }
+    }
+
+    // only blocks of functions are compared, a block declaring types is never equal
+    fn eq_decls(&mut self, decls1: &[Decl], decls2: &[Decl]) -> bool {
+        if decls1.len() != decls2.len() {
+            return false;
+        }
+        // the functions of a block are mutually recursive
+        for (decl1, decl2) in decls1.iter().zip(decls2.iter()) {
+            match (decl1, decl2) {
+                (Decl::Func { name: name1, .. }, Decl::Func { name: name2, .. }) => {
+                    self.bind(name1, name2)
+                }
+                (_, _) => return false,
+            }
+        }
+        decls1.iter().zip(decls2.iter()).all(|(decl1, decl2)| {
+            let Decl::Func {
+                attrs: attrs1,
+                pars: pars1,
+                ret: ret1,
+                body: body1,
+                ..
+            } = decl1
+            else {
+                unreachable!()
+            };
+            let Decl::Func {
+                attrs: attrs2,
+                pars: pars2,
+                ret: ret2,
+                body: body2,
+                ..
+            } = decl2
+            else {
+                unreachable!()
+            };
+            // annotations are compared as printed, which leaves out their spans
+            let same_attrs = attrs1.len() == attrs2.len()
+                && attrs1
+                    .iter()
+                    .zip(attrs2.iter())
+                    .all(|(attr1, attr2)| format!("{attr1}") == format!("{attr2}"));
+            let same_type = |typ1: &Option<Type>, typ2: &Option<Type>| {
+                typ1.as_ref().map(|typ| format!("{typ}"))
+                    == typ2.as_ref().map(|typ| format!("{typ}"))
+            };
+            same_attrs
+                && same_type(ret1, ret2)
+                && pars1.len() == pars2.len()
+                && pars1
+                    .iter()
+                    .zip(pars2.iter())
+                    .all(|((par1, typ1), (par2, typ2))| {
+                        self.bind(par1, par2);
+                        same_type(typ1, typ2)
+                    })
+                && self.eq_expr(body1, body2)
+        })
+    }
+
+    /// patterns bind their variables as they are compared
+    fn eq_pattern(&mut self, patn1: &Pattern, patn2: &Pattern) -> bool {
+        match (patn1, patn2) {
+            (Pattern::Var { var: var1, .. }, Pattern::Var { var: var2, .. }) => {
+                self.bind(var1, var2);
+                true
+            }
+            (Pattern::Lit { lit: lit1, .. }, Pattern::Lit { lit: lit2, .. }) => lit1 == lit2,
+            (
+                Pattern::Cons {
+                    cons: cons1,
+                    pars: pars1,
+                    ..
+                },
+                Pattern::Cons {
+                    cons: cons2,
+                    pars: pars2,
+                    ..
+                },
+            ) => cons1 == cons2 && self.eq_patterns(pars1, pars2),
+            (Pattern::Tuple { pats: pats1, .. }, Pattern::Tuple { pats: pats2, .. }) => {
+                self.eq_patterns(pats1, pats2)
+            }
+            (Pattern::Wild { .. }, Pattern::Wild { .. }) => true,
+            (Pattern::Or { alts: alts1, .. }, Pattern::Or { alts: alts2, .. }) => {
+                self.eq_patterns(alts1, alts2)
+            }
+            (
+                Pattern::As {
+                    bind: bind1,
+                    patn: patn1,
+                    ..
+                },
+                Pattern::As {
+                    bind: bind2,
+                    patn: patn2,
+                    ..
+                },
+            ) => {
+                self.bind(bind1, bind2);
+                self.eq_pattern(patn1, patn2)
+            }
+            (_, _) => false,
+        }
+    }
+
+    fn eq_patterns(&mut self, patns1: &[Pattern], patns2: &[Pattern]) -> bool {
+        patns1.len() == patns2.len()
+            && patns1
+                .iter()
+                .zip(patns2.iter())
+                .all(|(patn1, patn2)| self.eq_pattern(patn1, patn2))
+    }
+}
+
+#[test]
+fn equiv_expr_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let parse = |string: &str| {
+        let mut par = Parser::new(string);
+        let expr = parse_expr(&mut par).unwrap();
+        Renamer::new().visit_expr(expr)
+    };
+
+    // bound variables are related where they are bound, spans are ignored
+    let expr1 = parse("fun(x) => let y = @iadd(x, 1); (y, x)");
+    let expr2 = parse("fun(a) =>\n  let b = @iadd(a, 1);\n  (b, a)");
+    assert!(equiv_expr(&expr1, &expr2, HashMap::new()));
+    let expr3 = parse("fun(a) => let b = @iadd(a, 1); (a, b)");
+    assert!(!equiv_expr(&expr1, &expr3, HashMap::new()));
+
+    let expr1 = parse("case (1, 2) of | (x, _) => { x } | p => { p.1 } end");
+    let expr2 = parse("case (1, 2) of | (z, _) => { z } | q => { q.1 } end");
+    assert!(equiv_expr(&expr1, &expr2, HashMap::new()));
+    let expr3 = parse("case (1, 2) of | (_, z) => { z } | q => { q.1 } end");
+    assert!(!equiv_expr(&expr1, &expr3, HashMap::new()));
+
+    // free variables are equal only if they are mapped to each other
+    let Expr::Fun {
+        pars: pars1,
+        body: body1,
+        ..
+    } = parse("fun(x) => @iadd(x, 1)")
+    else {
+        panic!("test failed!");
+    };
+    let Expr::Fun {
+        pars: pars2,
+        body: body2,
+        ..
+    } = parse("fun(x) => @iadd(x, 1)")
+    else {
+        panic!("test failed!");
+    };
+    assert!(!equiv_expr(&body1, &body2, HashMap::new()));
+    let map = HashMap::from([(pars1[0], pars2[0])]);
+    assert!(equiv_expr(&body1, &body2, map));
+}
@@ -0,0 +1,358 @@
+use super::diagnostic::Diagnostic;
+use super::*;
+
+/// Fold every primitive whose arguments are all literals into a literal,
+/// and every `if` whose condition is a literal into one of its branches.
+pub fn const_fold_expr(expr: Expr) -> Expr {
+    const_fold_diags(expr).0
+}
+
+/// Like `const_fold_expr`, with a warning for each primitive that would fail at runtime.
+/// Such primitives are left unfolded.
+pub fn const_fold_diags(expr: Expr) -> (Expr, Vec<Diagnostic>) {
+    let mut pass = ConstFold { diags: Vec::new() };
+    let expr = pass.visit_expr(expr);
+    (expr, pass.diags)
+}
+
+struct ConstFold {
+    diags: Vec<Diagnostic>,
+}
+
+impl ConstFold {
+    fn visit_expr(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Lit { .. } | Expr::Var { .. } | Expr::Hole { .. } => expr,
+            Expr::Prim { prim, args, span } => {
+                let args: Vec<Expr> = args.into_iter().map(|arg| self.visit_expr(arg)).collect();
+                let lits: Option<Vec<LitVal>> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Expr::Lit { lit, .. } => Some(*lit),
+                        _ => None,
+                    })
+                    .collect();
+                match lits.and_then(|lits| self.fold_prim(prim, &lits, span)) {
+                    Some(lit) => Expr::Lit { lit, span },
+                    None => Expr::Prim { prim, args, span },
+                }
+            }
+            Expr::Fun { pars, body, span } => {
+                let body = Box::new(self.visit_expr(*body));
+                Expr::Fun { pars, body, span }
+            }
+            Expr::App { func, args, span } => {
+                let func = Box::new(self.visit_expr(*func));
+                let args = args.into_iter().map(|arg| self.visit_expr(arg)).collect();
+                Expr::App { func, args, span }
+            }
+            Expr::ExtCall { func, args, span } => {
+                let args = args.into_iter().map(|arg| self.visit_expr(arg)).collect();
+                Expr::ExtCall { func, args, span }
+            }
+            Expr::Cons { cons, args, span } => {
+                let args = args.into_iter().map(|arg| self.visit_expr(arg)).collect();
+                Expr::Cons { cons, args, span }
+            }
+            Expr::Tuple { elems, span } => {
+                let elems = elems
+                    .into_iter()
+                    .map(|elem| self.visit_expr(elem))
+                    .collect();
+                Expr::Tuple { elems, span }
+            }
+            Expr::Proj { expr, index, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                Expr::Proj { expr, index, span }
+            }
+            Expr::Record { fields, span } => {
+                let fields = self.visit_field_inits(fields);
+                Expr::Record { fields, span }
+            }
+            Expr::Field { expr, field, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                Expr::Field { expr, field, span }
+            }
+            Expr::Update { expr, fields, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                let fields = self.visit_field_inits(fields);
+                Expr::Update { expr, fields, span }
+            }
+            Expr::Anno { expr, typ, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                Expr::Anno { expr, typ, span }
+            }
+            Expr::Let {
+                bind,
+                expr,
+                cont,
+                span,
+            } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                let cont = Box::new(self.visit_expr(*cont));
+                Expr::Let {
+                    bind,
+                    expr,
+                    cont,
+                    span,
+                }
+            }
+            Expr::Case {
+                expr,
+                rules,
+                is_let,
+                span,
+            } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                let rules = rules
+                    .into_iter()
+                    .map(|rule| Rule {
+                        guard: rule.guard.map(|guard| self.visit_expr(guard)),
+                        body: self.visit_expr(rule.body),
+                        ..rule
+                    })
+                    .collect();
+                Expr::Case {
+                    expr,
+                    rules,
+                    is_let,
+                    span,
+                }
+            }
+            Expr::Try {
+                expr,
+                handlers,
+                span,
+            } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                let handlers = handlers
+                    .into_iter()
+                    .map(|rule| Rule {
+                        guard: rule.guard.map(|guard| self.visit_expr(guard)),
+                        body: self.visit_expr(rule.body),
+                        ..rule
+                    })
+                    .collect();
+                Expr::Try {
+                    expr,
+                    handlers,
+                    span,
+                }
+            }
+            Expr::Ifte {
+                cond,
+                trbr,
+                flbr,
+                span,
+            } => {
+                let cond = self.visit_expr(*cond);
+                let trbr = self.visit_expr(*trbr);
+                let flbr = self.visit_expr(*flbr);
+                match cond {
+                    // the other branch is never taken
+                    Expr::Lit {
+                        lit: LitVal::Bool(true),
+                        ..
+                    } => trbr,
+                    Expr::Lit {
+                        lit: LitVal::Bool(false),
+                        ..
+                    } => flbr,
+                    cond => Expr::Ifte {
+                        cond: Box::new(cond),
+                        trbr: Box::new(trbr),
+                        flbr: Box::new(flbr),
+                        span,
+                    },
+                }
+            }
+            Expr::Blk { decls, cont, span } => {
+                let decls = decls
+                    .into_iter()
+                    .map(|decl| self.visit_decl(decl))
+                    .collect();
+                let cont = Box::new(self.visit_expr(*cont));
+                Expr::Blk { decls, cont, span }
+            }
+            Expr::LetRec { decls, cont, span } => {
+                let decls = decls
+                    .into_iter()
+                    .map(|decl| self.visit_decl(decl))
+                    .collect();
+                let cont = Box::new(self.visit_expr(*cont));
+                Expr::LetRec { decls, cont, span }
+            }
+            Expr::Module {
+                name,
+                decls,
+                cont,
+                span,
+            } => {
+                let decls = decls
+                    .into_iter()
+                    .map(|decl| self.visit_decl(decl))
+                    .collect();
+                let cont = Box::new(self.visit_expr(*cont));
+                Expr::Module {
+                    name,
+                    decls,
+                    cont,
+                    span,
+                }
+            }
+        }
+    }
+
+    fn visit_field_inits(&mut self, fields: Vec<FieldInit>) -> Vec<FieldInit> {
+        fields
+            .into_iter()
+            .map(|init| FieldInit {
+                expr: self.visit_expr(init.expr),
+                ..init
+            })
+            .collect()
+    }
+
+    fn visit_decl(&mut self, decl: Decl) -> Decl {
+        match decl {
+            Decl::Func {
+                name,
+                attrs,
+                pars,
+                ret,
+                body,
+                span,
+            } => {
+                // `@optimize(never)` functions are copied through untouched
+                let never = attrs.iter().any(|attr| {
+                    matches!(
+                        attr,
+                        Attr::Optimize {
+                            level: OptLevel::Never,
+                            ..
+                        }
+                    )
+                });
+                let body = if never {
+                    body
+                } else {
+                    Box::new(self.visit_expr(*body))
+                };
+                Decl::Func {
+                    name,
+                    attrs,
+                    pars,
+                    ret,
+                    body,
+                    span,
+                }
+            }
+            decl => decl,
+        }
+    }
+
+    /// the result of a primitive on literals, `None` if it can't be folded
+    fn fold_prim(&mut self, prim: Builtin, args: &[LitVal], span: Span) -> Option<LitVal> {
+        use Builtin::*;
+        use LitVal::*;
+        let res = match (prim, args) {
+            (IAdd, [Int(a), Int(b)]) => self.checked(a.checked_add(*b), span)?,
+            (ISub, [Int(a), Int(b)]) => self.checked(a.checked_sub(*b), span)?,
+            (IMul, [Int(a), Int(b)]) => self.checked(a.checked_mul(*b), span)?,
+            (IDiv | IRem, [Int(_), Int(0)]) => {
+                let diag = Diagnostic::warn("division by zero")
+                    .line_span(span, "this will fail at runtime, so it is left unfolded");
+                self.diags.push(diag);
+                return None;
+            }
+            // both round toward zero, just like C
+            (IDiv, [Int(a), Int(b)]) => self.checked(a.checked_div(*b), span)?,
+            (IRem, [Int(a), Int(b)]) => self.checked(a.checked_rem(*b), span)?,
+            (INeg, [Int(a)]) => self.checked(a.checked_neg(), span)?,
+            (Trunc32 | ZeroExt32, [Int(a)]) => Int(*a as u32 as i64),
+            (SignExt32, [Int(a)]) => Int(*a as i32 as i64),
+            (ICmpEq, [Int(a), Int(b)]) => Bool(a == b),
+            (ICmpNe, [Int(a), Int(b)]) => Bool(a != b),
+            (ICmpLt, [Int(a), Int(b)]) => Bool(a < b),
+            (ICmpLe, [Int(a), Int(b)]) => Bool(a <= b),
+            (ICmpGt, [Int(a), Int(b)]) => Bool(a > b),
+            (ICmpGe, [Int(a), Int(b)]) => Bool(a >= b),
+            // IEEE-754, dividing by zero gives an infinity or NaN, which is not an error
+            (RAdd, [Real(a), Real(b)]) => Real(a + b),
+            (RSub, [Real(a), Real(b)]) => Real(a - b),
+            (RMul, [Real(a), Real(b)]) => Real(a * b),
+            (RDiv, [Real(a), Real(b)]) => Real(a / b),
+            (BAnd, [Bool(a), Bool(b)]) => Bool(*a && *b),
+            (BOr, [Bool(a), Bool(b)]) => Bool(*a || *b),
+            (BNot, [Bool(a)]) => Bool(!a),
+            // a known condition needs no hint
+            (Expect, [Bool(a), _]) => Bool(*a),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// the result of a checked integer operation, with a warning on overflow
+    fn checked(&mut self, res: Option<i64>, span: Span) -> Option<LitVal> {
+        if res.is_none() {
+            let diag = Diagnostic::warn("integer overflow")
+                .line_span(span, "this overflows 64 bits, so it is left unfolded");
+            self.diags.push(diag);
+        }
+        res.map(LitVal::Int)
+    }
+}
+
+#[cfg(test)]
+fn fold_source(string: &str) -> (String, Vec<String>) {
+    use super::parser::*;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let (expr, diags) = const_fold_diags(expr);
+    let diags = diags.iter().map(|diag| diag.minimal_report(10)).collect();
+    (format!("{expr}"), diags)
+}
+
+#[test]
+fn const_fold_expr_test() {
+    let (res, diags) = fold_source("@iadd(@imul(2, 3), @ineg(4))");
+    assert_eq!(res, "2");
+    assert!(diags.is_empty());
+
+    let (res, _) = fold_source("@icmplt(@isub(1, 2), 0)");
+    assert_eq!(res, "true");
+
+    let (res, _) = fold_source("@rdiv(1.0, 4.0)");
+    assert_eq!(res, "0.25");
+
+    let (res, _) = fold_source("@idiv(-7, 2)");
+    assert_eq!(res, "-3");
+
+    // only literal arguments are folded
+    let (res, _) = fold_source("fun(x) => @iadd(x, @iadd(1, 2))");
+    assert_eq!(res, "\\x -> x + 3");
+
+    let (res, _) = fold_source("if @icmpeq(1, 1) then 42 else #abort()");
+    assert_eq!(res, "42");
+
+    let (res, _) = fold_source("fun(x) => if @band(true, false) then x else 0");
+    assert_eq!(res, "\\x -> 0");
+}
+
+#[test]
+fn const_fold_failure_test() {
+    // a fold that fails at runtime is left to fail there
+    let (res, diags) = fold_source("@idiv(1, @isub(2, 2))");
+    assert_eq!(res, "@idiv(1, 0)");
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0].starts_with("[Warn]: division by zero"));
+
+    let (res, diags) = fold_source("@iadd(9223372036854775807, 1)");
+    assert_eq!(res, "9223372036854775807 + 1");
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0].starts_with("[Warn]: integer overflow"));
+
+    let (_, diags) = fold_source("@idiv(@isub(-9223372036854775807, 1), -1)");
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0].starts_with("[Warn]: integer overflow"));
+}
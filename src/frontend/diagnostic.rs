@@ -1,11 +1,117 @@
+use super::lexer::BOM;
 use super::*;
+use crate::utils::source_map::{Location, SourceMap};
+use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// internal names are wrapped in these markers when a message is built,
+// and normalized when the diagnostic is rendered
+const MARK_BEGIN: char = '\u{E000}';
+const MARK_END: char = '\u{E001}';
+const KIND_TYPE_VAR: char = 't';
+const KIND_IDENT: char = 'i';
+
+static VERBOSE_INTERNALS: AtomicBool = AtomicBool::new(false);
+
+/// Show raw internal names (like `t_37`) in rendered diagnostics, for debugging the compiler
+pub fn set_verbose_internals(flag: bool) {
+    VERBOSE_INTERNALS.store(flag, Ordering::Relaxed);
+}
+
+/// Mark a type variable in a message, it is renamed to `a`, `b`, ... when rendered
+pub fn type_var(var: &Ident) -> String {
+    format!("{MARK_BEGIN}{KIND_TYPE_VAR}{var}{MARK_END}")
+}
+
+/// Mark an identifier in a message. when rendered, the counter suffix is dropped,
+/// or replaced by a per-diagnostic index if several identifiers share the same name.
+pub fn internal_ident(ident: &Ident) -> String {
+    format!(
+        "{MARK_BEGIN}{KIND_IDENT}{}\u{0}{}{MARK_END}",
+        ident.name, ident.index
+    )
+}
+
+/// Replace every marked internal name in `text`, the numbering only depends on
+/// the order of appearance, so the result is stable under unrelated changes.
+pub fn normalize_internals(text: &str) -> String {
+    let verbose = VERBOSE_INTERNALS.load(Ordering::Relaxed);
+    let marks = parse_marks(text);
+
+    let mut type_vars: HashMap<&str, String> = HashMap::new();
+    let mut idents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (kind, raw) in marks.iter().filter_map(|mark| mark.ok()) {
+        if kind == KIND_TYPE_VAR {
+            let n = type_vars.len();
+            type_vars.entry(raw).or_insert_with(|| type_var_name(n));
+        } else {
+            let (name, _) = raw.split_once('\u{0}').unwrap();
+            let seen = idents.entry(name).or_default();
+            if !seen.contains(&raw) {
+                seen.push(raw);
+            }
+        }
+    }
+
+    let mut output = String::new();
+    for mark in marks {
+        match mark {
+            Err(text) => output.push_str(text),
+            Ok((KIND_TYPE_VAR, raw)) if verbose => output.push_str(raw),
+            Ok((KIND_TYPE_VAR, raw)) => output.push_str(&type_vars[raw]),
+            Ok((_, raw)) => {
+                let (name, index) = raw.split_once('\u{0}').unwrap();
+                let seen = &idents[name];
+                if verbose && index != "0" {
+                    output.push_str(&format!("{name}_{index}"));
+                } else if verbose || seen.len() == 1 {
+                    output.push_str(name);
+                } else {
+                    let n = seen.iter().position(|x| *x == raw).unwrap() + 1;
+                    output.push_str(&format!("{name}#{n}"));
+                }
+            }
+        }
+    }
+    output
+}
+
+/// split text into plain pieces `Err(text)` and marked names `Ok((kind, raw))`
+fn parse_marks(text: &str) -> Vec<Result<(char, &str), &str>> {
+    let mut vec = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(MARK_BEGIN) {
+        let end = rest[start..].find(MARK_END).unwrap() + start;
+        if start > 0 {
+            vec.push(Err(&rest[..start]));
+        }
+        let mark = &rest[start + MARK_BEGIN.len_utf8()..end];
+        let kind = mark.chars().next().unwrap();
+        vec.push(Ok((kind, &mark[kind.len_utf8()..])));
+        rest = &rest[end + MARK_END.len_utf8()..];
+    }
+    if !rest.is_empty() {
+        vec.push(Err(rest));
+    }
+    vec
+}
+
+fn type_var_name(n: usize) -> String {
+    let ch = (b'a' + (n % 26) as u8) as char;
+    if n < 26 {
+        ch.to_string()
+    } else {
+        format!("{ch}{}", n / 26)
+    }
+}
 
 #[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
 pub enum DiagLevel {
     Error,
     Warn,
     Info,
+    Note,
 }
 
 impl fmt::Display for DiagLevel {
@@ -14,6 +120,7 @@ impl fmt::Display for DiagLevel {
             DiagLevel::Error => write!(f, "Error"),
             DiagLevel::Warn => write!(f, "Warn"),
             DiagLevel::Info => write!(f, "Info"),
+            DiagLevel::Note => write!(f, "Note"),
         }
     }
 }
@@ -23,6 +130,8 @@ pub struct Description {
     verbosity: u8,
     message: String,
     span: Option<Span>,
+    // a secondary description, rendered indented under the primary ones
+    note: bool,
 }
 
 impl Description {
@@ -31,6 +140,7 @@ impl Description {
             verbosity: 10,
             message: msg.into(),
             span: None,
+            note: false,
         }
     }
 
@@ -43,6 +153,35 @@ impl Description {
         self.verbosity = verbosity;
         self
     }
+
+    pub fn as_note(mut self) -> Description {
+        self.note = true;
+        self
+    }
+}
+
+/// indent every line of a note under the primary message
+fn indent_note(text: &str) -> String {
+    text.lines().map(|line| format!("  {line}\n")).collect()
+}
+
+/// A machine-applicable change to the source, replacing the text at `span` with `text`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+/// Apply `edits` to `source`, the edits must not overlap
+pub fn apply_edits(source: &str, edits: &[Edit]) -> String {
+    let mut output = source.to_string();
+    let mut edits: Vec<&Edit> = edits.iter().collect();
+    // from the back, so the earlier positions stay valid
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.span.start.abs));
+    for edit in edits {
+        output.replace_range(edit.span.start.abs..edit.span.end.abs, &edit.text);
+    }
+    output
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -50,6 +189,8 @@ pub struct Diagnostic {
     level: DiagLevel,
     title: String,
     descriptions: Vec<Description>,
+    // edits fixing the problem, if it can be fixed mechanically
+    fix: Vec<Edit>,
 }
 
 impl Diagnostic {
@@ -58,6 +199,7 @@ impl Diagnostic {
             level: DiagLevel::Error,
             title: title.into(),
             descriptions: Vec::new(),
+            fix: Vec::new(),
         }
     }
 
@@ -66,6 +208,7 @@ impl Diagnostic {
             level: DiagLevel::Warn,
             title: title.into(),
             descriptions: Vec::new(),
+            fix: Vec::new(),
         }
     }
 
@@ -74,9 +217,14 @@ impl Diagnostic {
             level: DiagLevel::Info,
             title: title.into(),
             descriptions: Vec::new(),
+            fix: Vec::new(),
         }
     }
 
+    pub fn is_error(&self) -> bool {
+        self.level == DiagLevel::Error
+    }
+
     pub fn line<S: Into<String>>(mut self, msg: S) -> Diagnostic {
         self.descriptions.push(Description::message(msg));
         self
@@ -108,6 +256,24 @@ impl Diagnostic {
         self
     }
 
+    /// a secondary span, like where a conflicting type was inferred
+    pub fn note_span<S: Into<String>>(mut self, span: Span, msg: S) -> Diagnostic {
+        self.descriptions
+            .push(Description::message(msg).with_span(span).as_note());
+        self
+    }
+
+    /// the edits that fix the problem, see `apply_edits`
+    pub fn with_fix(mut self, edits: Vec<Edit>) -> Diagnostic {
+        self.fix = edits;
+        self
+    }
+
+    /// the edits that fix the problem, empty if it can't be fixed mechanically
+    pub fn fix(&self) -> &[Edit] {
+        &self.fix
+    }
+
     /// minimal_report shows only span, instead of source code.
     pub fn minimal_report(&self, verbosity: u8) -> String {
         let mut output = format!("[{}]: {}\n", self.level, &self.title);
@@ -116,6 +282,14 @@ impl Diagnostic {
                 // ignore those description with higher verbosity
                 continue;
             }
+            if descr.note {
+                let mut note = format!("[{}]: {}\n", DiagLevel::Note, descr.message);
+                if let Some(span) = &descr.span {
+                    note.push_str(&format!("{span}\n"));
+                }
+                output.push_str(&indent_note(&note));
+                continue;
+            }
             match &descr.span {
                 Some(span) => {
                     output.push_str(&format!("{}:\n{}\n", span, descr.message,));
@@ -126,80 +300,134 @@ impl Diagnostic {
                 }
             }
         }
-        output
+        normalize_internals(&output)
     }
 
     pub fn report(&self, source: &str, verbosity: u8) -> String {
+        self.render(verbosity, |_| (source, None))
+    }
+
+    /// report with spans from any file of `map`, each snippet is headed by its location
+    pub fn report_in(&self, map: &SourceMap, verbosity: u8) -> String {
+        self.render(verbosity, |span| {
+            (map.source(span.file), Some(map.lookup(*span)))
+        })
+    }
+
+    fn render<'a, F>(&self, verbosity: u8, lookup: F) -> String
+    where
+        F: Fn(&Span) -> (&'a str, Option<Location<'a>>),
+    {
         let mut output = format!("[{}]: {}\n", self.level, &self.title);
-        let text = source.lines().collect::<Vec<&str>>();
         for descr in &self.descriptions {
             if descr.verbosity > verbosity {
                 // ignore those description with higher verbosity
                 continue;
             }
-            match &descr.span {
-                Some(span) => {
-                    let row_range = std::ops::Range {
-                        start: span.start.row,
-                        end: span.end.row + 1,
-                    };
-
-                    let mut vec: Vec<(usize, usize)> = Vec::new();
-                    if span.start.row == span.end.row {
-                        vec.push((span.start.col, span.end.col))
-                    } else {
-                        for row in row_range.clone() {
-                            if row == span.start.row {
-                                vec.push((span.start.col, text[row].len()))
-                            } else if row == span.end.row {
-                                vec.push((0, span.end.col))
-                            } else {
-                                vec.push((0, text[row].len()))
-                            }
-                        }
-                    }
-
-                    //println!("range = {:?}",range);
-                    let head_width = (1 + span.end.row).to_string().len();
-
-                    let zipped = row_range.zip(vec.into_iter());
-
-                    for (row, (s, e)) in zipped {
-                        // print header "xxx | ", where xxx is the line number
-                        output.push_str(&format!("{:>.*} | {}\n", head_width, row + 1, text[row]));
-
-                        output.push_str(&format!("{:>.*} | ", head_width, ' '));
-
-                        for _ in 0..s {
-                            output.push(' ');
-                        }
-
-                        if row == span.start.row {
-                            output.push('^');
-                            for _ in s + 1..e {
-                                output.push('~');
-                            }
-                        } else if row == span.end.row {
-                            for _ in s..e - 1 {
-                                output.push('~');
-                            }
-                            output.push('^');
-                        } else {
-                            for _ in s..e {
-                                output.push('~');
-                            }
-                        }
-                        output.push('\n');
-                    }
-                    output.push_str(&descr.message);
-                    output.push('\n');
+            if descr.note {
+                // the message of a note comes before its snippet
+                let mut note = format!("[{}]: {}\n", DiagLevel::Note, descr.message);
+                if let Some(span) = &descr.span {
+                    note.push_str(&Self::snippet(span, &lookup));
                 }
-                None => {
-                    output.push_str(&descr.message);
-                    output.push('\n');
+                output.push_str(&indent_note(&note));
+                continue;
+            }
+            if let Some(span) = &descr.span {
+                output.push_str(&Self::snippet(span, &lookup));
+            }
+            output.push_str(&descr.message);
+            output.push('\n');
+        }
+        normalize_internals(&output)
+    }
+
+    /// the source code of the span, with the location if there is any
+    fn snippet<'a, F>(span: &Span, lookup: &F) -> String
+    where
+        F: Fn(&Span) -> (&'a str, Option<Location<'a>>),
+    {
+        let mut output = String::new();
+        let (source, location) = lookup(span);
+        if let Some(location) = location {
+            output.push_str(&format!("--> {location}\n"));
+        }
+        // columns don't count the byte-order mark, and `lines` ends a line at "\n" or "\r\n"
+        // just like the lexer does
+        let source = source.strip_prefix(BOM).unwrap_or(source);
+        let mut text = source.lines().collect::<Vec<&str>>();
+        if text.is_empty() {
+            text.push("");
+        }
+        let width = |row: usize| text[row].chars().count();
+        // a span running to the end of the file may end past the last line, or at the start
+        // of a line after it, clamp it to the lines that exist
+        let last = text.len() - 1;
+        let clamp = |pos: &Position| {
+            if pos.row > last {
+                (last, width(last))
+            } else {
+                (pos.row, pos.col)
+            }
+        };
+        let (start_row, start_col) = clamp(&span.start);
+        let (mut end_row, mut end_col) = clamp(&span.end);
+        if end_row > start_row && end_col == 0 {
+            end_row -= 1;
+            end_col = width(end_row);
+        }
+        let row_range = std::ops::Range {
+            start: start_row,
+            end: end_row + 1,
+        };
+
+        let mut vec: Vec<(usize, usize)> = Vec::new();
+        if start_row == end_row {
+            vec.push((start_col, end_col.max(start_col)))
+        } else {
+            for row in row_range.clone() {
+                if row == start_row {
+                    vec.push((start_col, width(row)))
+                } else if row == end_row {
+                    vec.push((0, end_col))
+                } else {
+                    vec.push((0, width(row)))
                 }
             }
         }
+
+        //println!("range = {:?}",range);
+        let head_width = (1 + end_row).to_string().len();
+
+        let zipped = row_range.zip(vec);
+
+        for (row, (s, e)) in zipped {
+            // print header "xxx | ", where xxx is the line number
+            output.push_str(&format!("{:>.*} | {}\n", head_width, row + 1, text[row]));
+
+            output.push_str(&format!("{:>.*} | ", head_width, ' '));
+
+            for _ in 0..s {
+                output.push(' ');
+            }
+
+            if row == start_row {
+                output.push('^');
+                for _ in s + 1..e {
+                    output.push('~');
+                }
+            } else if row == end_row {
+                for _ in s..e.saturating_sub(1) {
+                    output.push('~');
+                }
+                output.push('^');
+            } else {
+                for _ in s..e {
+                    output.push('~');
+                }
+            }
+            output.push('\n');
+        }
         output
     }
 }
@@ -239,3 +467,98 @@ something spanned error discreption
 "#
     );
 }
+
+#[test]
+fn diagnostic_note_test() {
+    let source = "let x = 1;\nx";
+    let span1 = Span::new(Position::new(0, 8, 8), Position::new(0, 9, 9));
+    let span2 = Span::new(Position::new(1, 0, 11), Position::new(1, 1, 12));
+    let diag = Diagnostic::error("Error Name")
+        .line_span(span2, "primary discreption")
+        .note_span(span1, "secondary discreption");
+    assert_eq!(
+        diag.report(source, 10),
+        r#"[Error]: Error Name
+2 | x
+  | ^
+primary discreption
+  [Note]: secondary discreption
+  1 | let x = 1;
+    |         ^
+"#
+    );
+    assert_eq!(
+        diag.minimal_report(10),
+        r#"[Error]: Error Name
+from line 2, col 1 to line 2, col 2:
+primary discreption
+  [Note]: secondary discreption
+  from line 1, col 9 to line 1, col 10
+"#
+    );
+}
+
+#[test]
+fn diagnostic_internals_test() {
+    let t1 = Ident::generate('t');
+    let t2 = Ident::generate('t');
+    let x1 = Ident::generate('x');
+    let x2 = Ident::generate('x');
+    let f = Ident::from(InternStr::new("foo")).uniquify();
+    let diag = Diagnostic::error("type error")
+        .line(format!(
+            "expect {}, found fun({}) -> {}",
+            type_var(&t2),
+            type_var(&t1),
+            type_var(&t2)
+        ))
+        .line(format!(
+            "in {}, {} and {}",
+            internal_ident(&f),
+            internal_ident(&x1),
+            internal_ident(&x2)
+        ));
+    let expect = r#"[Error]: type error
+expect a, found fun(b) -> a
+in foo, x#1 and x#2
+"#;
+    assert_eq!(diag.minimal_report(10), expect);
+
+    // the same message with other internal names renders the same
+    let t3 = Ident::generate('t');
+    let x3 = Ident::generate('x');
+    let diag = Diagnostic::error("type error")
+        .line(format!(
+            "expect {}, found fun({}) -> {}",
+            type_var(&t1),
+            type_var(&t3),
+            type_var(&t1)
+        ))
+        .line(format!(
+            "in {}, {} and {}",
+            internal_ident(&f),
+            internal_ident(&x3),
+            internal_ident(&x2)
+        ));
+    assert_eq!(diag.minimal_report(10), expect);
+}
+
+#[test]
+fn diagnostic_source_map_test() {
+    use super::lexer::{lexer_diagnostics, Lexer, Token};
+
+    let mut map = SourceMap::new();
+    map.add_file("main.nrm", "let x = 1;\nx");
+    let file = map.add_file("util.nrm", "fun f(x) =>\n    `x");
+    let tokens: Vec<Token> = Lexer::new(map.source(file)).with_file(file).collect();
+    let diags = lexer_diagnostics(&tokens);
+    assert_eq!(
+        diags[0].report_in(&map, 10),
+        r#"[Error]: lexer error
+--> util.nrm:2:5
+2 |     `x
+  |     ^
+unrecognized token
+"#
+    );
+}
@@ -0,0 +1,964 @@
+use super::ast_equiv::equiv_expr;
+use super::diagnostic::{Diagnostic, Edit};
+use super::*;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fmt;
+
+/// constructors (with their arity) of each `data` declaration, in declaration order
+pub type DataEnv = HashMap<Ident, Vec<(Ident, usize)>>;
+
+/// simplified pattern, variables are just wildcards here
+#[derive(Clone, Debug, PartialEq)]
+enum Pat {
+    Wild,
+    Lit(LitVal),
+    Cons(Ident, Vec<Pat>),
+    Tuple(Vec<Pat>),
+}
+
+impl From<&Pattern> for Pat {
+    fn from(patn: &Pattern) -> Self {
+        match patn {
+            Pattern::Var { .. } | Pattern::Wild { .. } => Pat::Wild,
+            Pattern::Lit { lit, .. } => Pat::Lit(*lit),
+            Pattern::Cons { cons, pars, .. } => {
+                Pat::Cons(*cons, pars.iter().map(Pat::from).collect())
+            }
+            Pattern::Tuple { pats, .. } => Pat::Tuple(pats.iter().map(Pat::from).collect()),
+            Pattern::Or { .. } => unreachable!("or-patterns are expanded first"),
+            Pattern::As { patn, .. } => Pat::from(patn.as_ref()),
+        }
+    }
+}
+
+impl fmt::Display for Pat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pat::Wild => write!(f, "_"),
+            Pat::Lit(lit) => write!(f, "{lit}"),
+            // print the source name, without the index added by renamer
+            Pat::Cons(cons, pars) if pars.is_empty() => write!(f, "{}", cons.name),
+            Pat::Cons(cons, pars) => write!(f, "{}({})", cons.name, pars.iter().format(", ")),
+            Pat::Tuple(pats) if pats.len() == 1 => write!(f, "({},)", pats[0]),
+            Pat::Tuple(pats) => write!(f, "({})", pats.iter().format(", ")),
+        }
+    }
+}
+
+/// what kind of values the first column of a matrix matches on
+enum Head {
+    /// every constructor of the data type, and whether all of them appeared
+    Data(Vec<(Ident, usize)>, bool),
+    Tuple(usize),
+    /// literals, wildcards or unknown constructors, never complete
+    Open,
+}
+
+struct Checker<'a> {
+    env: &'a DataEnv,
+    cons_data: HashMap<Ident, Ident>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(env: &'a DataEnv) -> Checker<'a> {
+        let cons_data = env
+            .iter()
+            .flat_map(|(data, conss)| conss.iter().map(|(cons, _)| (*cons, *data)))
+            .collect();
+        Checker { env, cons_data }
+    }
+
+    fn head(&self, rows: &[Vec<Pat>]) -> Head {
+        for row in rows {
+            match &row[0] {
+                Pat::Cons(cons, _) => {
+                    if let Some(data) = self.cons_data.get(cons) {
+                        let conss = self.env[data].clone();
+                        let complete = conss.iter().all(|(cons, _)| {
+                            rows.iter()
+                                .any(|row| matches!(&row[0], Pat::Cons(cons2, _) if cons2 == cons))
+                        });
+                        return Head::Data(conss, complete);
+                    }
+                }
+                Pat::Tuple(pats) => return Head::Tuple(pats.len()),
+                Pat::Lit(_) | Pat::Wild => {}
+            }
+        }
+        Head::Open
+    }
+
+    /// rows whose first pattern matches `head`, with the sub-patterns spliced in
+    fn specialize(rows: &[Vec<Pat>], head: &Pat, arity: usize) -> Vec<Vec<Pat>> {
+        rows.iter()
+            .filter_map(|row| {
+                let pars = match (&row[0], head) {
+                    (Pat::Wild, _) => vec![Pat::Wild; arity],
+                    (Pat::Cons(cons, pars), Pat::Cons(cons2, _)) if cons == cons2 => pars.clone(),
+                    (Pat::Tuple(pats), Pat::Tuple(_)) => pats.clone(),
+                    (Pat::Lit(lit), Pat::Lit(lit2)) if lit == lit2 => Vec::new(),
+                    _ => return None,
+                };
+                Some(pars.into_iter().chain(row[1..].iter().cloned()).collect())
+            })
+            .collect()
+    }
+
+    /// rows whose first pattern is a wildcard, with that column removed
+    fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+        rows.iter()
+            .filter(|row| row[0] == Pat::Wild)
+            .map(|row| row[1..].to_vec())
+            .collect()
+    }
+
+    /// is there any value matched by `row` but not by any of `rows`?
+    fn useful(&self, rows: &[Vec<Pat>], row: &[Pat]) -> bool {
+        if row.is_empty() {
+            return rows.is_empty();
+        }
+        match &row[0] {
+            Pat::Cons(_, pars) => {
+                let mat = Self::specialize(rows, &row[0], pars.len());
+                let row: Vec<Pat> = pars.iter().chain(row[1..].iter()).cloned().collect();
+                self.useful(&mat, &row)
+            }
+            Pat::Tuple(pats) => {
+                let mat = Self::specialize(rows, &row[0], pats.len());
+                let row: Vec<Pat> = pats.iter().chain(row[1..].iter()).cloned().collect();
+                self.useful(&mat, &row)
+            }
+            Pat::Lit(_) => {
+                let mat = Self::specialize(rows, &row[0], 0);
+                self.useful(&mat, &row[1..])
+            }
+            Pat::Wild => match self.head(rows) {
+                Head::Data(conss, true) => conss.iter().any(|(cons, arity)| {
+                    let head = Pat::Cons(*cons, Vec::new());
+                    let mat = Self::specialize(rows, &head, *arity);
+                    let row: Vec<Pat> = std::iter::repeat_n(Pat::Wild, *arity)
+                        .chain(row[1..].iter().cloned())
+                        .collect();
+                    self.useful(&mat, &row)
+                }),
+                Head::Tuple(arity) => {
+                    let mat = Self::specialize(rows, &Pat::Tuple(Vec::new()), arity);
+                    let row: Vec<Pat> = std::iter::repeat_n(Pat::Wild, arity)
+                        .chain(row[1..].iter().cloned())
+                        .collect();
+                    self.useful(&mat, &row)
+                }
+                Head::Data(_, false) | Head::Open => self.useful(&Self::default(rows), &row[1..]),
+            },
+        }
+    }
+
+    /// find `n` patterns matched by none of `rows`, as an example of missing case
+    fn missing(&self, rows: &[Vec<Pat>], n: usize) -> Option<Vec<Pat>> {
+        if n == 0 {
+            return if rows.is_empty() {
+                Some(Vec::new())
+            } else {
+                None
+            };
+        }
+        match self.head(rows) {
+            Head::Data(conss, true) => conss.iter().find_map(|(cons, arity)| {
+                let mat = Self::specialize(rows, &Pat::Cons(*cons, Vec::new()), *arity);
+                let mut res = self.missing(&mat, arity + n - 1)?;
+                let rest = res.split_off(*arity);
+                Some(std::iter::once(Pat::Cons(*cons, res)).chain(rest).collect())
+            }),
+            Head::Data(conss, false) => {
+                let rest = self.missing(&Self::default(rows), n - 1)?;
+                let (cons, arity) = conss
+                    .iter()
+                    .find(|(cons, _)| {
+                        !rows
+                            .iter()
+                            .any(|row| matches!(&row[0], Pat::Cons(cons2, _) if cons2 == cons))
+                    })
+                    .unwrap();
+                let head = Pat::Cons(*cons, vec![Pat::Wild; *arity]);
+                Some(std::iter::once(head).chain(rest).collect())
+            }
+            Head::Tuple(arity) => {
+                let mat = Self::specialize(rows, &Pat::Tuple(Vec::new()), arity);
+                let mut res = self.missing(&mat, arity + n - 1)?;
+                let rest = res.split_off(arity);
+                Some(std::iter::once(Pat::Tuple(res)).chain(rest).collect())
+            }
+            Head::Open => {
+                let rest = self.missing(&Self::default(rows), n - 1)?;
+                Some(std::iter::once(Pat::Wild).chain(rest).collect())
+            }
+        }
+    }
+}
+
+/// Check the rules of a `case` expression, `ty` is the type of scrutinee.
+/// Returns an error if some value is not matched by any rule,
+/// and a warning for each rule that can never be reached.
+pub fn check_exhaustiveness(rules: &[Rule], ty: &Type, env: &DataEnv) -> Vec<Diagnostic> {
+    let checker = Checker::new(env);
+    let mut diags = Vec::new();
+
+    let mut rows: Vec<Vec<Pat>> = Vec::new();
+    for rule in rules {
+        // an or-pattern is a row for each alternative
+        let alts: Vec<Vec<Pat>> = rule
+            .patn
+            .expand_or()
+            .iter()
+            .map(|alt| vec![Pat::from(alt)])
+            .collect();
+        if !alts.iter().any(|row| checker.useful(&rows, row)) {
+            let diag = Diagnostic::warn("redundant rule")
+                .line_span(rule.span, "this rule will never be matched");
+            diags.push(diag);
+        }
+        // a guard may fail, so a guarded rule doesn't cover anything
+        if rule.guard.is_none() {
+            rows.extend(alts);
+        }
+    }
+
+    // the only value of unit type is matched by `()`
+    let unit = rows.iter().any(|row| row[0] == Pat::Lit(LitVal::Unit));
+    let is_unit = matches!(
+        ty,
+        Type::Lit {
+            lit: LitType::Unit,
+            ..
+        }
+    );
+    if let Some(witness) = checker.missing(&rows, 1).filter(|_| !(unit && is_unit)) {
+        let mut diag = Diagnostic::error("non-exhaustive pattern match");
+        if let Some(span) = Span::merge_all(rules.iter().map(|rule| rule.span)) {
+            diag = diag.line_span(span, format!("pattern `{}` is not covered", witness[0]));
+        } else {
+            diag = diag.line(format!("pattern `{}` is not covered", witness[0]));
+        }
+        diags.push(diag);
+        if rows.iter().any(|row| matches!(row[0], Pat::Lit(_))) {
+            let diag = Diagnostic::warn("literal patterns are never exhaustive")
+                .line("help: add a wildcard rule `| _ => ...` at the end");
+            diags.push(diag);
+        }
+    }
+    diags
+}
+
+/// A `let` whose pattern may not match is only warned about, it aborts at runtime instead
+pub fn check_let_pattern(rule: &Rule, ty: &Type, env: &DataEnv) -> Option<Diagnostic> {
+    let checker = Checker::new(env);
+    let rows: Vec<Vec<Pat>> = rule
+        .patn
+        .expand_or()
+        .iter()
+        .map(|alt| vec![Pat::from(alt)])
+        .collect();
+    let unit = rows.iter().any(|row| row[0] == Pat::Lit(LitVal::Unit));
+    let is_unit = matches!(
+        ty,
+        Type::Lit {
+            lit: LitType::Unit,
+            ..
+        }
+    );
+    let witness = checker.missing(&rows, 1).filter(|_| !(unit && is_unit))?;
+    let diag = Diagnostic::warn("refutable pattern in `let`")
+        .line_span(
+            rule.span,
+            format!("pattern `{}` is not covered", witness[0]),
+        )
+        .line("the program aborts if the value doesn't match, use `case` to handle it");
+    Some(diag)
+}
+
+/// Run `check_exhaustiveness` on every `case` expression in a renamed program.
+pub fn check_expr(expr: &Expr) -> Vec<Diagnostic> {
+    let mut pass = CheckPass {
+        env: DataEnv::new(),
+        cons_pars: HashMap::new(),
+        diags: Vec::new(),
+    };
+    pass.visit_expr(expr);
+    pass.diags
+}
+
+struct CheckPass {
+    env: DataEnv,
+    // declared field types of each constructor
+    cons_pars: HashMap<Ident, Vec<Type>>,
+    diags: Vec<Diagnostic>,
+}
+
+impl CheckPass {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Lit { .. } | Expr::Var { .. } | Expr::Hole { .. } => {}
+            Expr::Prim { args, .. }
+            | Expr::App { args, .. }
+            | Expr::ExtCall { args, .. }
+            | Expr::Cons { args, .. } => {
+                if let Expr::App { func, .. } = expr {
+                    self.visit_expr(func);
+                }
+                args.iter().for_each(|arg| self.visit_expr(arg));
+            }
+            Expr::Tuple { elems, .. } => elems.iter().for_each(|elem| self.visit_expr(elem)),
+            Expr::Fun { body, .. } => self.visit_expr(body),
+            Expr::Proj { expr, .. } | Expr::Field { expr, .. } | Expr::Anno { expr, .. } => {
+                self.visit_expr(expr)
+            }
+            Expr::Record { fields, .. } => {
+                fields.iter().for_each(|field| self.visit_expr(&field.expr))
+            }
+            Expr::Update { expr, fields, .. } => {
+                self.visit_expr(expr);
+                fields.iter().for_each(|field| self.visit_expr(&field.expr));
+            }
+            Expr::Ifte {
+                cond, trbr, flbr, ..
+            } => {
+                self.visit_expr(cond);
+                self.visit_expr(trbr);
+                self.visit_expr(flbr);
+            }
+            Expr::Let { expr, cont, .. } => {
+                self.visit_expr(expr);
+                self.visit_expr(cont);
+            }
+            // the handlers are checked once the renamer has made a case of them
+            Expr::Try { .. } => {
+                for sub in expr.subexprs() {
+                    self.visit_expr(sub);
+                }
+            }
+            Expr::Case {
+                expr,
+                rules,
+                is_let,
+                span,
+            } => {
+                self.visit_expr(expr);
+                let ty = self.scrutinee_type(rules, *span);
+                if *is_let {
+                    self.diags
+                        .extend(check_let_pattern(&rules[0], &ty, &self.env));
+                } else {
+                    let diags = check_exhaustiveness(rules, &ty, &self.env);
+                    self.diags.extend(diags);
+                }
+                let diags = self.check_duplicate_bodies(rules);
+                self.diags.extend(diags);
+                for rule in rules {
+                    if let Some(guard) = &rule.guard {
+                        self.visit_expr(guard);
+                    }
+                    self.visit_expr(&rule.body);
+                }
+            }
+            Expr::Blk { decls, cont, .. }
+            | Expr::LetRec { decls, cont, .. }
+            | Expr::Module { decls, cont, .. } => {
+                for decl in decls {
+                    if let Decl::Data { name, vars, .. } = decl {
+                        let conss = vars.iter().map(|var| (var.cons, var.pars.len())).collect();
+                        self.env.insert(*name, conss);
+                        for var in vars {
+                            self.cons_pars.insert(var.cons, var.pars.clone());
+                        }
+                    }
+                }
+                for decl in decls {
+                    if let Decl::Func { body, .. } = decl {
+                        self.visit_expr(body);
+                    }
+                }
+                self.visit_expr(cont);
+            }
+        }
+    }
+
+    /// Warn about unguarded rules with identical bodies, which could be one rule with an
+    /// or-pattern. Only rules that can be merged without changing the meaning are grouped.
+    fn check_duplicate_bodies(&self, rules: &[Rule]) -> Vec<Diagnostic> {
+        let mut diags = Vec::new();
+        let mut merged = vec![false; rules.len()];
+        for i in 0..rules.len() {
+            if merged[i] || rules[i].guard.is_some() {
+                continue;
+            }
+            let mut group = vec![i];
+            for j in i + 1..rules.len() {
+                if !merged[j] && rules[j].guard.is_none() && self.can_merge(rules, &group, j) {
+                    group.push(j);
+                }
+            }
+            if group.len() < 2 {
+                continue;
+            }
+            group.iter().for_each(|&k| merged[k] = true);
+
+            let mut diag =
+                Diagnostic::warn(format!("these {} rules have identical bodies", group.len()));
+            for &k in &group {
+                diag = diag.line_span(rules[k].span, "this rule");
+            }
+            // the alternatives go after the first pattern, and the other rules are deleted
+            let alts: String = group[1..]
+                .iter()
+                .map(|&k| format!(" | {}", source_pattern(&rules[k].patn)))
+                .collect();
+            let end = rules[i].patn.span().end;
+            let file = rules[i].span.file;
+            let mut edits = vec![Edit {
+                span: Span {
+                    start: end,
+                    end,
+                    file,
+                },
+                text: alts,
+            }];
+            for &k in &group[1..] {
+                let span = Span {
+                    start: rules[k - 1].span.end,
+                    end: rules[k].span.end,
+                    file,
+                };
+                edits.push(Edit {
+                    span,
+                    text: String::new(),
+                });
+            }
+            let diag = diag
+                .line("help: consider merging them into one rule with an or-pattern")
+                .with_fix(edits);
+            diags.push(diag);
+        }
+        diags
+    }
+
+    /// whether rule `j` can join the rules of `group` as another alternative of the first one
+    fn can_merge(&self, rules: &[Rule], group: &[usize], j: usize) -> bool {
+        let first = group[0];
+        let mut binds1 = bound_paths(&rules[first].patn);
+        let mut binds2 = bound_paths(&rules[j].patn);
+        binds1.sort_by_key(|(var, _)| var.name);
+        binds2.sort_by_key(|(var, _)| var.name);
+        // the same names, each with the same type
+        if binds1.len() != binds2.len() {
+            return false;
+        }
+        let mut map = HashMap::new();
+        for ((var1, path1), (var2, path2)) in binds1.iter().zip(binds2.iter()) {
+            if var1.name != var2.name || !self.same_type(path1, path2) {
+                return false;
+            }
+            map.insert(*var1, *var2);
+        }
+        if !equiv_expr(&rules[first].body, &rules[j].body, map) {
+            return false;
+        }
+        // rule `j` moves up to the first rule, past the rules in between
+        (first + 1..j)
+            .filter(|k| !group.contains(k))
+            .all(|k| disjoint(&rules[k].patn, &rules[j].patn))
+    }
+
+    // the type checker doesn't handle `case` yet, so the types are only known to be equal
+    // if the variables are at the same place, or are fields declared with the same closed type
+    fn same_type(
+        &self,
+        path1: &[(Option<Ident>, usize)],
+        path2: &[(Option<Ident>, usize)],
+    ) -> bool {
+        if path1 == path2 {
+            return true;
+        }
+        let field_type = |path: &[(Option<Ident>, usize)]| match path.last() {
+            Some((Some(cons), index)) => self
+                .cons_pars
+                .get(cons)
+                .and_then(|pars| pars.get(*index))
+                .filter(|typ| is_ground(typ)),
+            _ => None,
+        };
+        match (field_type(path1), field_type(path2)) {
+            (Some(typ1), Some(typ2)) => format!("{typ1}") == format!("{typ2}"),
+            _ => false,
+        }
+    }
+
+    // the type checker doesn't handle `case` yet, so we guess the type from the patterns
+    fn scrutinee_type(&self, rules: &[Rule], span: Span) -> Type {
+        for mut patn in rules.iter().flat_map(|rule| rule.patn.expand_or()) {
+            // an alias matches what its pattern matches
+            while let Pattern::As { patn: inner, .. } = patn {
+                patn = *inner;
+            }
+            match patn {
+                Pattern::Lit { lit, span } => {
+                    return Type::Lit {
+                        lit: lit.get_lit_type(),
+                        span,
+                    };
+                }
+                Pattern::Cons { cons, span, .. } => {
+                    if let Some((data, _)) = self
+                        .env
+                        .iter()
+                        .find(|(_, conss)| conss.iter().any(|(cons2, _)| *cons2 == cons))
+                    {
+                        return Type::App {
+                            cons: *data,
+                            args: Vec::new(),
+                            span,
+                        };
+                    }
+                }
+                _ => {}
+            }
+        }
+        Type::Var {
+            var: Ident::generate('t'),
+            span,
+        }
+    }
+}
+
+/// the steps from the scrutinee to a sub-pattern, each with the constructor
+/// (or `None` for a tuple) and the position of the field
+type Path = Vec<(Option<Ident>, usize)>;
+
+/// the variables bound by a pattern, each with its path
+fn bound_paths(patn: &Pattern) -> Vec<(Ident, Path)> {
+    fn visit(patn: &Pattern, path: &mut Path, res: &mut Vec<(Ident, Path)>) {
+        match patn {
+            Pattern::Var { var, .. } => res.push((*var, path.clone())),
+            Pattern::Lit { .. } | Pattern::Wild { .. } => {}
+            Pattern::Cons { cons, pars, .. } => {
+                for (index, par) in pars.iter().enumerate() {
+                    path.push((Some(*cons), index));
+                    visit(par, path, res);
+                    path.pop();
+                }
+            }
+            Pattern::Tuple { pats, .. } => {
+                for (index, pat) in pats.iter().enumerate() {
+                    path.push((None, index));
+                    visit(pat, path, res);
+                    path.pop();
+                }
+            }
+            // the alternatives bind the same variables with the same types
+            Pattern::Or { alts, .. } => visit(&alts[0], path, res),
+            Pattern::As { bind, patn, .. } => {
+                res.push((*bind, path.clone()));
+                visit(patn, path, res);
+            }
+        }
+    }
+    let mut res = Vec::new();
+    visit(patn, &mut Vec::new(), &mut res);
+    res
+}
+
+/// whether no value is matched by both patterns
+fn disjoint(patn1: &Pattern, patn2: &Pattern) -> bool {
+    match (patn1, patn2) {
+        (Pattern::As { patn, .. }, other) | (other, Pattern::As { patn, .. }) => {
+            disjoint(patn, other)
+        }
+        (Pattern::Or { alts, .. }, other) | (other, Pattern::Or { alts, .. }) => {
+            alts.iter().all(|alt| disjoint(alt, other))
+        }
+        (Pattern::Lit { lit: lit1, .. }, Pattern::Lit { lit: lit2, .. }) => lit1 != lit2,
+        (
+            Pattern::Cons {
+                cons: cons1,
+                pars: pars1,
+                ..
+            },
+            Pattern::Cons {
+                cons: cons2,
+                pars: pars2,
+                ..
+            },
+        ) => {
+            cons1 != cons2
+                || pars1
+                    .iter()
+                    .zip(pars2)
+                    .any(|(par1, par2)| disjoint(par1, par2))
+        }
+        (Pattern::Tuple { pats: pats1, .. }, Pattern::Tuple { pats: pats2, .. }) => pats1
+            .iter()
+            .zip(pats2)
+            .any(|(pat1, pat2)| disjoint(pat1, pat2)),
+        // variables and wildcards match anything
+        (_, _) => false,
+    }
+}
+
+/// whether a type has no type variables
+fn is_ground(typ: &Type) -> bool {
+    match typ {
+        Type::Var { .. } => false,
+        Type::Lit { .. }
+        | Type::SimdVec { .. }
+        | Type::OpaquePtr { .. }
+        | Type::Mutex { .. }
+        | Type::Sem { .. } => true,
+        Type::Fun { pars, res, .. } => pars.iter().all(is_ground) && is_ground(res),
+        Type::App { args, .. } => args.iter().all(is_ground),
+        Type::Tuple { elems, .. } => elems.iter().all(is_ground),
+        Type::Coroutine { arg, res, .. } => is_ground(arg) && is_ground(res),
+        Type::Thread { res, .. } => is_ground(res),
+        Type::Chan { elem, .. } => is_ground(elem),
+        Type::Scheme { .. } => false,
+    }
+}
+
+/// a pattern printed with the names of the source, without the indices of the renamer
+fn source_pattern(patn: &Pattern) -> Pattern {
+    let name = |ident: &Ident| Ident::from(ident.name);
+    match patn {
+        Pattern::Var { var, span } => Pattern::Var {
+            var: name(var),
+            span: *span,
+        },
+        Pattern::Lit { .. } | Pattern::Wild { .. } => patn.clone(),
+        Pattern::Cons { cons, pars, span } => Pattern::Cons {
+            cons: name(cons),
+            pars: pars.iter().map(source_pattern).collect(),
+            span: *span,
+        },
+        Pattern::Tuple { pats, span } => Pattern::Tuple {
+            pats: pats.iter().map(source_pattern).collect(),
+            span: *span,
+        },
+        Pattern::Or { alts, span } => Pattern::Or {
+            alts: alts.iter().map(source_pattern).collect(),
+            span: *span,
+        },
+        Pattern::As { bind, patn, span } => Pattern::As {
+            bind: name(bind),
+            patn: Box::new(source_pattern(patn)),
+            span: *span,
+        },
+    }
+}
+
+#[cfg(test)]
+fn check_source(string: &str) -> Vec<String> {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let expr = rnm.visit_expr(expr);
+    check_expr(&expr)
+        .iter()
+        .map(|diag| diag.minimal_report(10))
+        .collect()
+}
+
+#[test]
+fn exhaustiveness_test() {
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun f(x) =>
+        case x of
+        | Cons(y, Nil) => { 1 }
+        | Nil => { 0 }
+        end
+in
+    f(Nil)
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].starts_with("[Error]: non-exhaustive pattern match"));
+    assert!(res[0].contains("pattern `Cons(_, Cons(_, _))` is not covered"));
+
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun f(x) =>
+        case x of
+        | Cons(y, ys) => { 1 }
+        | _ => { 0 }
+        | Nil => { 2 }
+        end
+in
+    f(Nil)
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].starts_with("[Warn]: redundant rule"));
+
+    let string = r#"
+case 42 of
+| 1 => { 1 }
+| 2 => { 2 }
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 2);
+    assert!(res[0].starts_with("[Error]: non-exhaustive pattern match"));
+    assert!(res[1].starts_with("[Warn]: literal patterns are never exhaustive"));
+
+    let string = r#"
+case (1, 2) of
+| (1, x) => { x }
+| (y, _) => { y }
+end
+"#;
+    assert!(check_source(string).is_empty());
+}
+
+#[test]
+fn exhaustiveness_columns_test() {
+    // a case on several values covers the cross product of their constructors
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun equal(xs, ys) =>
+        case (xs, ys) of
+        | (Cons(x, xs), Cons(y, ys)) => { @band(@icmpeq(x, y), equal(xs, ys)) }
+        | (Nil, Nil) => { true }
+        | (Nil, _) => { false }
+        end
+in
+    equal(Nil, Nil)
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].starts_with("[Error]: non-exhaustive pattern match"));
+    assert!(res[0].contains("pattern `(Cons(_, _), Nil)` is not covered"));
+}
+
+#[test]
+fn exhaustiveness_guard_test() {
+    // a guard may fail, so guarded rules alone cover nothing
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun f(x) =>
+        case x of
+        | Cons(y, ys) if @icmpgt(y, 0) => { 1 }
+        | Nil => { 0 }
+        end
+in
+    f(Nil)
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].contains("pattern `Cons(_, _)` is not covered"));
+
+    // and a rule after a guarded one is still reachable
+    let string = r#"
+case 42 of
+| x if @icmpgt(x, 0) => { 1 }
+| _ => { 0 }
+end
+"#;
+    assert!(check_source(string).is_empty());
+}
+
+#[test]
+fn exhaustiveness_or_pattern_test() {
+    // each alternative covers its own row
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun f(x) =>
+        case x of
+        | Cons(_, Nil) | Nil => { 1 }
+        | Cons(_, Cons(_, _)) => { 2 }
+        end
+in
+    f(Nil)
+end
+"#;
+    assert!(check_source(string).is_empty());
+
+    // a rule is redundant only if every alternative is
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun f(x) =>
+        case x of
+        | Nil => { 0 }
+        | Nil | Cons(_, Nil) => { 1 }
+        | Nil | Cons(_, Nil) => { 2 }
+        | _ => { 3 }
+        end
+in
+    f(Nil)
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].starts_with("[Warn]: redundant rule"));
+    assert!(res[0].contains("from line 11"));
+}
+
+#[test]
+fn exhaustiveness_let_test() {
+    // a refutable pattern in a `let` is only a warning
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun head(xs) =>
+        let Cons(h, _) = xs;
+        h
+in
+    let (a, b) = (1, 2);
+    head(Cons(a, Nil))
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].starts_with("[Warn]: refutable pattern in `let`"));
+    assert!(res[0].contains("pattern `Nil` is not covered"));
+}
+
+#[cfg(test)]
+fn duplicate_fixes(string: &str) -> Vec<String> {
+    use super::diagnostic::apply_edits;
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let expr = Renamer::new().visit_expr(expr);
+    check_expr(&expr)
+        .iter()
+        .filter(|diag| !diag.fix().is_empty())
+        .map(|diag| apply_edits(string, diag.fix()))
+        .collect()
+}
+
+#[test]
+fn duplicate_bodies_test() {
+    let string = r#"
+begin
+    data Shape =
+    | Circle(Int)
+    | Square(Int)
+    | Rect(Int, Int)
+    | Dot
+    end
+    fun f(s) =>
+        case s of
+        | Circle(n) => { @iadd(n, 1) }
+        | Dot => { 0 }
+        | Square(n) => { @iadd(n, 1) }
+        | Rect(n, _) => { @iadd(n, 1) }
+        end
+in
+    f(Dot)
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].starts_with("[Warn]: these 3 rules have identical bodies"));
+    assert!(res[0].contains("help: consider merging them into one rule with an or-pattern"));
+
+    // the fix leaves a single rule with an or-pattern, which checks cleanly
+    let fixed = duplicate_fixes(string);
+    assert_eq!(fixed.len(), 1);
+    assert!(fixed[0].contains(
+        "        | Circle(n) | Square(n) | Rect(n, _) => { @iadd(n, 1) }\n        | Dot => { 0 }\n        end"
+    ));
+    assert!(check_source(&fixed[0]).is_empty());
+}
+
+#[test]
+fn duplicate_bodies_illegal_test() {
+    // the bodies bind different names, so there is no or-pattern for them
+    let string = r#"
+begin
+    data Pair =
+    | A(Int)
+    | B(Int)
+    end
+    fun f(p) =>
+        case p of
+        | A(x) => { x }
+        | B(y) => { y }
+        end
+in
+    f(A(1))
+end
+"#;
+    assert!(check_source(string).is_empty());
+
+    // the same name with possibly different types
+    let string = r#"
+begin
+    data Pair[T] =
+    | A(Int)
+    | B(T)
+    end
+    fun f(p) =>
+        case p of
+        | A(x) => { x }
+        | B(x) => { x }
+        end
+in
+    f(A(1))
+end
+"#;
+    assert!(check_source(string).is_empty());
+
+    // moving the last rule past an overlapping one would change which rule matches
+    let string = r#"
+case (1, 2) of
+| (1, x) => { x }
+| (_, 2) => { 0 }
+| (_, x) => { x }
+end
+"#;
+    assert!(check_source(string).is_empty());
+    let string = r#"
+case (1, 2) of
+| (1, x) => { x }
+| (2, 0) => { 0 }
+| (3, x) => { x }
+| _ => { 1 }
+end
+"#;
+    let res = check_source(string);
+    assert_eq!(res.len(), 1);
+    assert!(res[0].starts_with("[Warn]: these 2 rules have identical bodies"));
+}
@@ -1,12 +1,14 @@
 use itertools::Itertools;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::fmt::Display;
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 
+use super::diagnostic::{self, Diagnostic};
 use super::*;
+use crate::utils::cancel::CancellationToken;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum TypeCell {
@@ -53,6 +55,9 @@ pub enum TypeBase<P> {
     Cell(Rc<RefCell<TypeCell>>),
     Fun(Vec<TypeBase<P>>, Box<TypeBase<P>>),
     App(Ident, Vec<TypeBase<P>>),
+    Tuple(Vec<TypeBase<P>>),
+    SimdVec(usize, LitType),
+    OpaquePtr,
 }
 
 impl<P> TypeBase<P> {
@@ -65,7 +70,21 @@ impl<P> TypeBase<P> {
             Box::new(TypeBase::Lit(lit)),
         )
     }
+    fn cmpop(lit: LitType) -> Self {
+        TypeBase::Fun(
+            vec![TypeBase::Lit(lit), TypeBase::Lit(lit)],
+            Box::new(TypeBase::Lit(LitType::Bool)),
+        )
+    }
     fn get_builtin_type(prim: Builtin) -> Self {
+        let vec = || TypeBase::SimdVec(SIMD_LANES, LitType::Real);
+        let tuple = || {
+            TypeBase::Tuple(
+                (0..SIMD_LANES)
+                    .map(|_| TypeBase::Lit(LitType::Real))
+                    .collect(),
+            )
+        };
         match prim {
             Builtin::IAdd => TypeBase::binop(LitType::Int),
             Builtin::ISub => TypeBase::binop(LitType::Int),
@@ -73,38 +92,155 @@ impl<P> TypeBase<P> {
             Builtin::IDiv => TypeBase::binop(LitType::Int),
             Builtin::IRem => TypeBase::binop(LitType::Int),
             Builtin::INeg => TypeBase::uniop(LitType::Int),
+            Builtin::Trunc32 | Builtin::SignExt32 | Builtin::ZeroExt32 => {
+                TypeBase::uniop(LitType::Int)
+            }
+            Builtin::ICmpEq => TypeBase::cmpop(LitType::Int),
+            Builtin::ICmpNe => TypeBase::cmpop(LitType::Int),
+            Builtin::ICmpLt => TypeBase::cmpop(LitType::Int),
+            Builtin::ICmpLe => TypeBase::cmpop(LitType::Int),
+            Builtin::ICmpGt => TypeBase::cmpop(LitType::Int),
+            Builtin::ICmpGe => TypeBase::cmpop(LitType::Int),
             Builtin::RAdd => TypeBase::binop(LitType::Real),
             Builtin::RSub => TypeBase::binop(LitType::Real),
             Builtin::RMul => TypeBase::binop(LitType::Real),
             Builtin::RDiv => TypeBase::binop(LitType::Real),
+            Builtin::Sinh => TypeBase::uniop(LitType::Real),
+            Builtin::Cosh => TypeBase::uniop(LitType::Real),
+            Builtin::Tanh => TypeBase::uniop(LitType::Real),
+            Builtin::Erf => TypeBase::uniop(LitType::Real),
+            Builtin::Erfc => TypeBase::uniop(LitType::Real),
             Builtin::BAnd => TypeBase::binop(LitType::Bool),
             Builtin::BOr => TypeBase::binop(LitType::Bool),
             Builtin::BNot => TypeBase::uniop(LitType::Bool),
+            // polymorphic, see `Infer::infer_expr`
+            Builtin::Prefetch
+            | Builtin::CastOpaquePtr
+            | Builtin::Yield
+            | Builtin::Spawn
+            | Builtin::Join
+            | Builtin::SpawnWithStack
+            | Builtin::ChanNew
+            | Builtin::ChanSend
+            | Builtin::ChanRecv
+            | Builtin::ChanClose
+            | Builtin::WithMutex
+            | Builtin::ReadFile
+            | Builtin::WriteFile
+            | Builtin::ListDir
+            | Builtin::Format
+            | Builtin::ListMin
+            | Builtin::ListMax => unreachable!(),
+            Builtin::MutexNew => TypeBase::Fun(Vec::new(), Box::new(mutex_type())),
+            Builtin::MutexLock | Builtin::MutexUnlock => {
+                TypeBase::Fun(vec![mutex_type()], Box::new(TypeBase::Lit(LitType::Unit)))
+            }
+            Builtin::SemNew => {
+                TypeBase::Fun(vec![TypeBase::Lit(LitType::Int)], Box::new(sem_type()))
+            }
+            Builtin::SemPost | Builtin::SemWait => {
+                TypeBase::Fun(vec![sem_type()], Box::new(TypeBase::Lit(LitType::Unit)))
+            }
+            Builtin::SemTryWait => {
+                TypeBase::Fun(vec![sem_type()], Box::new(TypeBase::Lit(LitType::Bool)))
+            }
+            Builtin::Expect => TypeBase::binop(LitType::Bool),
+            Builtin::PathJoin => TypeBase::binop(LitType::Str),
+            Builtin::PathBase | Builtin::PathDir => TypeBase::uniop(LitType::Str),
+            Builtin::AllocAligned => TypeBase::Fun(
+                vec![TypeBase::Lit(LitType::Int), TypeBase::Lit(LitType::Int)],
+                Box::new(TypeBase::OpaquePtr),
+            ),
+            Builtin::Bitcast(from, to) => {
+                TypeBase::Fun(vec![TypeBase::Lit(from)], Box::new(TypeBase::Lit(to)))
+            }
+            Builtin::SimdAdd | Builtin::SimdMul => {
+                TypeBase::Fun(vec![vec(), vec()], Box::new(vec()))
+            }
+            // vectors have the same layout as tuples of their elements
+            Builtin::SimdLoad => TypeBase::Fun(vec![tuple()], Box::new(vec())),
+            Builtin::SimdStore => TypeBase::Fun(vec![vec()], Box::new(tuple())),
         }
     }
 }
 
-impl<P> Display for TypeBase<P> {
+impl<P> TypeBase<P> {
+    /// display the type for diagnostics, type variables are marked so they get
+    /// renumbered when the diagnostic is rendered
+    pub fn diag(&self) -> String {
+        format!(
+            "{}",
+            TypeDisplay {
+                ty: self,
+                mark: true
+            }
+        )
+    }
+}
+
+struct TypeDisplay<'a, P> {
+    ty: &'a TypeBase<P>,
+    mark: bool,
+}
+
+impl<'a, P> TypeDisplay<'a, P> {
+    fn sub<'b, Q>(&self, ty: &'b TypeBase<Q>) -> TypeDisplay<'b, Q> {
+        TypeDisplay {
+            ty,
+            mark: self.mark,
+        }
+    }
+    fn var(&self, var: &Ident) -> String {
+        if self.mark {
+            diagnostic::type_var(var)
+        } else {
+            format!("{var}")
+        }
+    }
+}
+
+impl<'a, P> Display for TypeDisplay<'a, P> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
+        match self.ty {
             TypeBase::Lit(lit) => write!(f, "{lit}"),
-            TypeBase::Var(var, _) => write!(f, "{var}"),
+            TypeBase::Var(var, _) => write!(f, "{}", self.var(var)),
             TypeBase::Cell(cell) => match cell.borrow().deref() {
-                TypeCell::Unbound(name, _) => write!(f, "{name}"),
-                TypeCell::Link(link) => write!(f, "{link}"),
+                TypeCell::Unbound(name, _) => write!(f, "{}", self.var(name)),
+                TypeCell::Link(link) => write!(f, "{}", self.sub(link)),
             },
             TypeBase::Fun(pars, res) => {
-                let pars = pars.iter().format(&", ");
-                write!(f, "fun({pars}) -> {res}")
+                let pars = pars.iter().map(|par| self.sub(par)).format(", ");
+                write!(f, "fun({pars}) -> {}", self.sub(res))
             }
+            // printed like the source syntax, `Point` or `List[Int]`
+            TypeBase::App(cons, args) if args.is_empty() => write!(f, "{}", cons.name),
             TypeBase::App(cons, args) => {
-                let args = args.iter().format(&", ");
-                write!(f, "{cons}({args})")
+                let args = args.iter().map(|arg| self.sub(arg)).format(", ");
+                write!(f, "{}[{args}]", cons.name)
+            }
+            TypeBase::Tuple(elems) => {
+                let elems = elems.iter().map(|elem| self.sub(elem)).format(", ");
+                write!(f, "({elems})")
             }
+            TypeBase::SimdVec(lanes, elem) => write!(f, "Simd[{elem}, {lanes}]"),
+            TypeBase::OpaquePtr => write!(f, "Ptr"),
         }
     }
 }
 
+impl<P> Display for TypeBase<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            TypeDisplay {
+                ty: self,
+                mark: false
+            }
+        )
+    }
+}
+
 impl From<MonoType> for PolyType {
     // todo: maybe use unsafe cast?
     fn from(mty: MonoType) -> Self {
@@ -119,11 +255,16 @@ impl From<MonoType> for PolyType {
             TypeBase::App(cons, args) => {
                 TypeBase::App(cons, args.into_iter().map(|arg| arg.into()).collect())
             }
+            TypeBase::Tuple(elems) => {
+                TypeBase::Tuple(elems.into_iter().map(|elem| elem.into()).collect())
+            }
+            TypeBase::SimdVec(lanes, elem) => TypeBase::SimdVec(lanes, elem),
+            TypeBase::OpaquePtr => TypeBase::OpaquePtr,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum InferError {
     VarNotInScope,
     CantUnifyLiteralTypes,
@@ -131,6 +272,62 @@ pub enum InferError {
     CantUnifyConstructor,
     CantUnify,
     OccurCheckFailed,
+    CantProjectNonTuple,
+    ProjectionOutOfRange,
+    ExternNotInScope,
+    FieldNotInScope,
+    /// the body of a function doesn't fit its annotated return type
+    ReturnTypeMismatch(Span),
+    BitcastSizeMismatch(Span),
+    AlignmentNotPowerOfTwo(Span),
+    /// an argument passed to `...` doesn't have a type C can take as is
+    NotFfiSafe(Span),
+    /// the closure passed to a new thread at `span` captures a variable that isn't `Send`
+    NotSend {
+        capture: Ident,
+        span: Span,
+    },
+    /// the value sent over a channel at `span` isn't `Send`
+    ChanNotSend(Span),
+    /// the format of `@format` at `span` isn't a valid literal, or doesn't fit its arguments
+    BadFormat(Span, String),
+    /// the type inferred at `found` conflicts with the type expected by `expect`
+    Mismatch {
+        found: Span,
+        expect: Span,
+    },
+    /// an expression doesn't fit the type ascribed to it
+    AnnoMismatch(Box<AnnoMismatch>),
+    NotSupportedYet,
+    /// the token given by `with_cancel` was cancelled
+    Cancelled,
+}
+
+/// the types of an expression and of the part of its ascription that don't fit,
+/// both are marked for `normalize_internals`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnnoMismatch {
+    /// the expression, and the type inferred for it
+    pub found: Span,
+    pub inferred: String,
+    /// the part of the ascribed type it was checked against
+    pub expect: Span,
+    pub ascribed: String,
+}
+
+/// a typed hole `?name` and what the type checker knows at it,
+/// the types are marked for `normalize_internals`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypedHole {
+    pub name: InternStr,
+    pub span: Span,
+    /// the type of the expression that fills the hole,
+    /// `None` if type checking stopped before reaching it
+    pub typ: Option<String>,
+    /// the innermost named function the hole is in
+    pub func: Option<Ident>,
+    /// the variables in scope at the hole, from the outermost, with their types
+    pub scope: Vec<(Ident, String)>,
 }
 
 pub struct DataCons {}
@@ -140,25 +337,84 @@ pub struct TypeDecl {}
 
 type InferResult<T> = Result<T, InferError>;
 
+/// a function declaration: its name, parameters, return type and body
+type FuncDecl<'a> = (
+    Ident,
+    &'a [(Ident, Option<Type>)],
+    &'a Option<Type>,
+    &'a Expr,
+);
+
 #[allow(dead_code)]
 pub struct Infer {
     val_env: HashMap<Ident, PolyType>,
+    ext_env: HashMap<InternStr, PolyType>,
     cons_env: HashMap<Ident, DataCons>,
     data_env: HashMap<Ident, DataDecl>,
     type_env: HashMap<Ident, TypeDecl>,
+    // map a field to its record and its type
+    field_env: HashMap<Ident, (Ident, MonoType)>,
+    // the parameters and representations of the abstract types whose block is being
+    // inferred, outside of it an abstract type is only equal to itself
+    abstract_env: HashMap<Ident, (Vec<Ident>, Type)>,
+    // where the type of a variable was inferred, for diagnostics
+    var_sites: HashMap<Ident, Span>,
+    // externs declared with a trailing `...`
+    ext_variadic: HashSet<InternStr>,
+    // the types of the variadic arguments of each call, by the span of the call
+    varargs: HashMap<Span, Vec<FfiType>>,
     level: usize,
     error: Vec<InferError>,
+    // an expression assumed to have any type without looking inside, see `localize`
+    hole: Option<*const Expr>,
+    // the declaration groups and the continuations of blocks being inferred,
+    // the innermost one is where an error happened
+    sites: Vec<*const Expr>,
+    // the types given to the typed holes met so far, by their spans, together with
+    // the variables known there, so the function being inferred isn't generalized yet
+    typed_holes: HashMap<Span, (MonoType, HashMap<Ident, PolyType>)>,
+    // checked before each function body and each let
+    cancel: Option<CancellationToken>,
+}
+
+impl Default for Infer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Infer {
     pub fn new() -> Infer {
         Infer {
             val_env: HashMap::new(),
+            ext_env: HashMap::new(),
             cons_env: HashMap::new(),
             data_env: HashMap::new(),
             type_env: HashMap::new(),
+            field_env: HashMap::new(),
+            abstract_env: HashMap::new(),
+            var_sites: HashMap::new(),
+            ext_variadic: HashSet::new(),
+            varargs: HashMap::new(),
             level: 0,
             error: Vec::new(),
+            hole: None,
+            sites: Vec::new(),
+            typed_holes: HashMap::new(),
+            cancel: None,
+        }
+    }
+
+    /// stop with `InferError::Cancelled` once `token` is cancelled
+    pub fn with_cancel(mut self, token: CancellationToken) -> Infer {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn check_cancel(&self) -> InferResult<()> {
+        match &self.cancel {
+            Some(token) if token.is_cancelled() => Err(InferError::Cancelled),
+            _ => Ok(()),
         }
     }
     fn new_cell(&self) -> Rc<RefCell<TypeCell>> {
@@ -179,7 +435,7 @@ impl Infer {
     fn update_level(&self, cell: &Rc<RefCell<TypeCell>>, ty: &MonoType) -> InferResult<()> {
         assert!(!cell.borrow().is_bound());
         match ty {
-            TypeBase::Lit(_lit) => Ok(()),
+            TypeBase::Lit(_) | TypeBase::SimdVec(_, _) | TypeBase::OpaquePtr => Ok(()),
             TypeBase::Var(_var, _) => unreachable!(),
             TypeBase::Cell(cell2) => {
                 if Rc::ptr_eq(cell, cell2) {
@@ -208,11 +464,19 @@ impl Infer {
                 }
                 Ok(())
             }
+            TypeBase::Tuple(elems) => {
+                for elem in elems {
+                    self.update_level(cell, elem)?;
+                }
+                Ok(())
+            }
         }
     }
 
     fn unify(&self, ty1: &MonoType, ty2: &MonoType) -> InferResult<()> {
         // println!("unify {:?} ~ {:?}",ty1,ty2);
+        // links are followed first, a cell linked to the other side isn't a cycle
+        let (ty1, ty2) = (resolve(ty1.clone()), resolve(ty2.clone()));
         match (&ty1, &ty2) {
             (TypeBase::Lit(a), TypeBase::Lit(b)) => {
                 if a != b {
@@ -228,6 +492,14 @@ impl Infer {
                 Ok(()) // do nothing
             }
             (TypeBase::Cell(cell), ty) | (ty, TypeBase::Cell(cell)) => self.assign(cell, ty),
+            (TypeBase::App(cons, args), ty) | (ty, TypeBase::App(cons, args))
+                if self.abstract_env.contains_key(cons)
+                    && !matches!(ty, TypeBase::App(cons2, _) if cons2 == cons) =>
+            {
+                let (pars, repr) = &self.abstract_env[cons];
+                let vars = pars.iter().copied().zip(args.iter().cloned()).collect();
+                self.unify(&self.annotation(repr, &vars), ty)
+            }
             (TypeBase::Fun(pars_a, res_a), TypeBase::Fun(pars_b, res_b)) => {
                 if pars_a.len() != pars_b.len() {
                     return Err(InferError::CantUnifyDiffArgLens);
@@ -248,13 +520,30 @@ impl Infer {
                 }
                 Ok(())
             }
+            (TypeBase::Tuple(elems_a), TypeBase::Tuple(elems_b)) => {
+                if elems_a.len() != elems_b.len() {
+                    return Err(InferError::CantUnifyDiffArgLens);
+                }
+                for (elem_a, elem_b) in elems_a.iter().zip(elems_b.iter()) {
+                    self.unify(elem_a, elem_b)?;
+                }
+                Ok(())
+            }
+            (TypeBase::SimdVec(lanes_a, elem_a), TypeBase::SimdVec(lanes_b, elem_b)) => {
+                if (lanes_a, elem_a) != (lanes_b, elem_b) {
+                    Err(InferError::CantUnify)
+                } else {
+                    Ok(())
+                }
+            }
+            (TypeBase::OpaquePtr, TypeBase::OpaquePtr) => Ok(()),
             (_ty1, _ty2) => Err(InferError::CantUnify),
         }
     }
 
     fn generalize(&self, mty: &MonoType) -> PolyType {
         let mut map = HashMap::new();
-        self.generalize_aux(&mut map, &mty)
+        self.generalize_aux(&mut map, mty)
     }
 
     fn generalize_aux(&self, map: &mut HashMap<Ident, Ident>, mty: &MonoType) -> PolyType {
@@ -267,12 +556,12 @@ impl Infer {
                 match cell.borrow().deref() {
                     TypeCell::Unbound(name, level) => {
                         if *level > self.level {
-                            if let Some(var) = map.get(&name) {
+                            if let Some(var) = map.get(name) {
                                 TypeBase::Var(*var, ())
                             } else {
                                 // todo: more than 26 type parameters?!!
                                 assert!(map.len() < 26);
-                                let n = map.len() as u8 + 'a' as u8;
+                                let n = map.len() as u8 + b'a';
                                 let var = Ident::generate(n as char);
                                 map.insert(*name, var);
                                 TypeBase::Var(var, ())
@@ -299,6 +588,69 @@ impl Infer {
                     .collect();
                 TypeBase::App(*cons, args2)
             }
+            TypeBase::Tuple(elems) => {
+                let elems2 = elems
+                    .iter()
+                    .map(|elem| self.generalize_aux(map, elem))
+                    .collect();
+                TypeBase::Tuple(elems2)
+            }
+            TypeBase::SimdVec(lanes, elem) => TypeBase::SimdVec(*lanes, *elem),
+            TypeBase::OpaquePtr => TypeBase::OpaquePtr,
+        }
+    }
+
+    /// the type written in an annotation, `vars` are the type parameters in scope,
+    /// other type names are taken as data types
+    fn annotation(&self, typ: &Type, vars: &HashMap<Ident, MonoType>) -> MonoType {
+        match typ {
+            Type::Lit { lit, .. } => TypeBase::Lit(*lit),
+            Type::Var { var, .. } => match vars.get(var) {
+                Some(ty) => ty.clone(),
+                None => TypeBase::App(*var, Vec::new()),
+            },
+            Type::Fun { pars, res, .. } => {
+                let pars = pars.iter().map(|par| self.annotation(par, vars)).collect();
+                TypeBase::Fun(pars, Box::new(self.annotation(res, vars)))
+            }
+            Type::App { cons, args, .. } => {
+                let args = args.iter().map(|arg| self.annotation(arg, vars)).collect();
+                TypeBase::App(*cons, args)
+            }
+            Type::Tuple { elems, .. } => {
+                let elems = elems
+                    .iter()
+                    .map(|elem| self.annotation(elem, vars))
+                    .collect();
+                TypeBase::Tuple(elems)
+            }
+            Type::SimdVec { lanes, elem, .. } => TypeBase::SimdVec(*lanes, *elem),
+            Type::OpaquePtr { .. } => TypeBase::OpaquePtr,
+            Type::Coroutine { arg, res, .. } => {
+                let arg = self.annotation(arg, vars);
+                let res = self.annotation(res, vars);
+                TypeBase::App(Ident::from(InternStr::new(COROUTINE)), vec![arg, res])
+            }
+            Type::Thread { res, .. } => {
+                let res = self.annotation(res, vars);
+                thread_type(res)
+            }
+            Type::Chan { elem, .. } => {
+                let elem = self.annotation(elem, vars);
+                chan_type(elem)
+            }
+            Type::Mutex { .. } => mutex_type(),
+            Type::Sem { .. } => sem_type(),
+            // the variables are fresh at the current level, an extern generalizes them
+            Type::Scheme {
+                vars: pars, body, ..
+            } => {
+                let mut vars = vars.clone();
+                for par in pars {
+                    vars.insert(*par, TypeBase::Cell(self.new_cell()));
+                }
+                self.annotation(body, &vars)
+            }
         }
     }
 
@@ -315,7 +667,7 @@ impl Infer {
         match pty {
             TypeBase::Lit(lit) => TypeBase::Lit(*lit),
             TypeBase::Var(var, _) => {
-                if let Some(cell) = map.get(&var) {
+                if let Some(cell) = map.get(var) {
                     TypeBase::Cell(cell.clone())
                 } else {
                     let cell = self.new_cell();
@@ -349,27 +701,564 @@ impl Infer {
                     .collect();
                 TypeBase::App(*cons, args2)
             }
+            TypeBase::Tuple(elems) => {
+                let elems2 = elems
+                    .iter()
+                    .map(|elem| self.instantiate_aux(map, elem))
+                    .collect();
+                TypeBase::Tuple(elems2)
+            }
+            TypeBase::SimdVec(lanes, elem) => TypeBase::SimdVec(*lanes, *elem),
+            TypeBase::OpaquePtr => TypeBase::OpaquePtr,
+        }
+    }
+
+    /// the type of values matched by `patn`, variables in it are bound monomorphically
+    fn infer_pattern(&mut self, patn: &Pattern) -> InferResult<MonoType> {
+        match patn {
+            Pattern::Var { var, .. } => {
+                let cell = TypeBase::Cell(self.new_cell());
+                self.val_env.insert(*var, cell.clone().into());
+                Ok(cell)
+            }
+            Pattern::Lit { lit, .. } => Ok(TypeBase::Lit(lit.get_lit_type())),
+            // a tuple has a single shape, so it unifies component-wise
+            Pattern::Tuple { pats, .. } => {
+                let elems = pats
+                    .iter()
+                    .map(|pat| self.infer_pattern(pat))
+                    .collect::<InferResult<Vec<_>>>()?;
+                Ok(TypeBase::Tuple(elems))
+            }
+            Pattern::Wild { .. } => Ok(TypeBase::Cell(self.new_cell())),
+            Pattern::Cons { .. } => Err(InferError::NotSupportedYet),
+            // the alternatives bind the same variables, which must get the same types
+            Pattern::Or { alts, .. } => {
+                let res = self.infer_pattern(&alts[0])?;
+                let vars: Vec<(Ident, MonoType)> = alts[0]
+                    .get_freevars()
+                    .into_iter()
+                    .map(|var| (var, self.instantiate(&self.val_env[&var])))
+                    .collect();
+                for alt in alts[1..].iter() {
+                    let mismatch = |_| InferError::Mismatch {
+                        found: *alt.span(),
+                        expect: *alts[0].span(),
+                    };
+                    let alt_ty = self.infer_pattern(alt)?;
+                    self.unify(&res, &alt_ty).map_err(mismatch)?;
+                    for (var, ty) in vars.iter() {
+                        let alt_ty = self.instantiate(&self.val_env[var]);
+                        self.unify(ty, &alt_ty).map_err(mismatch)?;
+                        self.val_env.insert(*var, ty.clone().into());
+                    }
+                }
+                Ok(res)
+            }
+            // the alias has the type of the whole value
+            Pattern::As { bind, patn, .. } => {
+                let res = self.infer_pattern(patn)?;
+                self.val_env.insert(*bind, res.clone().into());
+                Ok(res)
+            }
+        }
+    }
+
+    /// where the type of an expression was inferred, a variable's is at its definition
+    fn site(&self, expr: &Expr) -> Span {
+        match expr {
+            Expr::Var { var, .. } => self.var_sites.get(var).copied(),
+            _ => None,
+        }
+        .unwrap_or(*expr.span())
+    }
+
+    /// locate a unification failure, the type of `expr` conflicts with `expect`
+    fn mismatch(&self, err: InferError, expr: &Expr, expect: Span) -> InferError {
+        match err {
+            InferError::CantUnifyLiteralTypes
+            | InferError::CantUnifyDiffArgLens
+            | InferError::CantUnifyConstructor
+            | InferError::CantUnify
+            | InferError::OccurCheckFailed => InferError::Mismatch {
+                found: self.site(expr),
+                expect,
+            },
+            err => err,
+        }
+    }
+
+    /// an application of a function of type `func`, the arguments are unified one by one
+    /// if the function type is known, so that a failure can be located at the argument
+    fn infer_call(
+        &mut self,
+        func: MonoType,
+        func_span: Span,
+        args: &[Expr],
+    ) -> InferResult<MonoType> {
+        let arg_tys = args
+            .iter()
+            .map(|arg| self.infer_expr(arg))
+            .collect::<InferResult<Vec<_>>>()?;
+        if let TypeBase::Fun(pars, res) = resolve(func.clone()) {
+            if pars.len() == args.len() {
+                for ((par, arg_ty), arg) in pars.iter().zip(arg_tys.iter()).zip(args) {
+                    self.unify(arg_ty, par)
+                        .map_err(|err| self.mismatch(err, arg, func_span))?;
+                }
+                return Ok(*res);
+            }
+        }
+        let res = TypeBase::Cell(self.new_cell());
+        let func_ty = TypeBase::Fun(arg_tys, Box::new(res.clone()));
+        self.unify(&func, &func_ty)?;
+        Ok(res)
+    }
+
+    /// the typed holes in `expr`, which was just checked by `infer_expr`, in source order
+    pub fn typed_holes(&self, expr: &Expr) -> Vec<TypedHole> {
+        let mut found = Vec::new();
+        hole_scopes(expr, None, &mut Vec::new(), &mut found);
+        found
+            .into_iter()
+            .map(|(name, span, func, scope)| {
+                // nothing is known about a hole that type checking didn't reach
+                let (typ, scope) = match self.typed_holes.get(&span) {
+                    Some((ty, env)) => {
+                        let scope = scope
+                            .into_iter()
+                            .filter_map(|var| Some((var, env.get(&var)?.diag())))
+                            .collect();
+                        (Some(ty.diag()), scope)
+                    }
+                    None => (None, Vec::new()),
+                };
+                TypedHole {
+                    name,
+                    span,
+                    typ,
+                    func,
+                    scope,
+                }
+            })
+            .collect()
+    }
+
+    /// the types of the variadic arguments of every call checked so far,
+    /// the C backend passes them unboxed
+    pub fn varargs(&self) -> &HashMap<Span, Vec<FfiType>> {
+        &self.varargs
+    }
+
+    /// a call to a variadic extern, the fixed arguments are checked as usual,
+    /// and the others must already have a monomorphic type that C understands
+    fn infer_variadic_call(
+        &mut self,
+        func: MonoType,
+        span: Span,
+        args: &[Expr],
+    ) -> InferResult<MonoType> {
+        let TypeBase::Fun(pars, _) = &func else {
+            unreachable!()
+        };
+        let fixed = pars.len().min(args.len());
+        let res = self.infer_call(func, span, &args[..fixed])?;
+        let mut varargs = Vec::new();
+        for arg in args[fixed..].iter() {
+            let typ = match resolve(self.infer_expr(arg)?) {
+                TypeBase::Lit(LitType::Int) => FfiType::Int,
+                TypeBase::Lit(LitType::Real) => FfiType::Real,
+                TypeBase::Lit(LitType::Bool) => FfiType::Bool,
+                TypeBase::Lit(LitType::Char) => FfiType::Char,
+                // passed as a `const char*`, so it can be printed with `%s`
+                TypeBase::Lit(LitType::Str) => FfiType::Ptr,
+                TypeBase::OpaquePtr => FfiType::Ptr,
+                _ => return Err(InferError::NotFfiSafe(*arg.span())),
+            };
+            varargs.push(typ);
+        }
+        self.varargs.insert(span, varargs);
+        Ok(res)
+    }
+
+    /// the `Send` marker, whether a value of the type may be moved to another thread.
+    /// pointers and coroutines stay on the thread that made them. A function is always
+    /// `Send`, only the captures of the closure passed to the new thread are checked.
+    fn is_send(&self, typ: &MonoType) -> bool {
+        match resolve(typ.clone()) {
+            TypeBase::Lit(_) | TypeBase::SimdVec(_, _) | TypeBase::Fun(_, _) => true,
+            // not known yet, assumed to be `Send`
+            TypeBase::Var(_, _) | TypeBase::Cell(_) => true,
+            TypeBase::OpaquePtr => false,
+            TypeBase::App(name, _) if &*name.name == COROUTINE => false,
+            TypeBase::App(name, args) => {
+                args.iter().all(|arg| self.is_send(arg))
+                    && self
+                        .field_env
+                        .values()
+                        .filter(|(record, _)| *record == name)
+                        .all(|(_, field_ty)| self.is_send(field_ty))
+            }
+            TypeBase::Tuple(elems) => elems.iter().all(|elem| self.is_send(elem)),
+        }
+    }
+
+    /// the record a field belongs to, and the type of the field
+    fn lookup_field(&self, field: &Ident) -> InferResult<(Ident, &MonoType)> {
+        self.field_env
+            .get(field)
+            .map(|(record, field_ty)| (*record, field_ty))
+            .ok_or(InferError::FieldNotInScope)
+    }
+
+    /// bring an extern or a record into scope, a function is returned instead,
+    /// since it's inferred together with the rest of its group.
+    /// only externs, records and (mutually recursive) functions are supported for now
+    fn declare<'a>(&mut self, decl: &'a Decl) -> InferResult<Option<FuncDecl<'a>>> {
+        match decl {
+            Decl::Func {
+                name,
+                pars,
+                ret,
+                body,
+                span,
+                ..
+            } => {
+                self.var_sites.insert(*name, *span);
+                return Ok(Some((*name, pars, ret, body)));
+            }
+            Decl::Extern {
+                name, pars, typ, ..
+            } => {
+                self.level += 1;
+                let vars = pars
+                    .iter()
+                    .map(|par| (*par, TypeBase::Cell(self.new_cell())))
+                    .collect();
+                let ext_ty = self.annotation(typ, &vars);
+                self.level -= 1;
+                let ext_ty = self.generalize(&ext_ty);
+                self.ext_env.insert(*name, ext_ty);
+                if let Type::Fun { variadic: true, .. } = typ.unquantified() {
+                    self.ext_variadic.insert(*name);
+                }
+            }
+            Decl::Record { name, fields, .. } => {
+                for FieldDecl { field, typ, .. } in fields {
+                    let field_ty = self.annotation(typ, &HashMap::new());
+                    self.field_env.insert(*field, (*name, field_ty));
+                }
+            }
+            // an abstract type is only equal to itself, but in its block, see `abstract_env`
+            Decl::Fixity { .. } | Decl::Abstract { .. } => {}
+            _ => return Err(InferError::NotSupportedYet),
+        }
+        Ok(None)
+    }
+
+    /// infer the types of a group of mutually recursive functions,
+    /// and generalize them once the whole group is known
+    fn infer_funcs(&mut self, funcs: &[FuncDecl]) -> InferResult<()> {
+        self.level += 1;
+        let mut func_tys = Vec::new();
+        for (name, pars, ret, _) in funcs.iter() {
+            // annotated parameters are assumed before inferring any body,
+            // so they constrain the inference instead of being checked afterwards
+            let pars = pars
+                .iter()
+                .map(|(_, typ)| match typ {
+                    Some(typ) => self.annotation(typ, &HashMap::new()),
+                    None => TypeBase::Cell(self.new_cell()),
+                })
+                .collect();
+            let res = match ret {
+                Some(ret) => self.annotation(ret, &HashMap::new()),
+                None => TypeBase::Cell(self.new_cell()),
+            };
+            let func_ty = TypeBase::Fun(pars, Box::new(res));
+            self.val_env.insert(*name, func_ty.clone().into());
+            func_tys.push(func_ty);
+        }
+        for ((_, pars, ret, body), func_ty) in funcs.iter().zip(func_tys.iter()) {
+            let TypeBase::Fun(par_tys, res) = func_ty else {
+                unreachable!()
+            };
+            self.check_cancel()?;
+            for ((par, _), par_ty) in pars.iter().zip(par_tys.iter()) {
+                self.val_env.insert(*par, par_ty.clone().into());
+            }
+            let body = self.infer_expr(body)?;
+            match ret {
+                Some(ret) => self
+                    .unify(res, &body)
+                    .map_err(|_| InferError::ReturnTypeMismatch(*ret.span()))?,
+                None => self.unify(res, &body)?,
+            }
+        }
+        self.level -= 1;
+        for ((name, _, _, _), func_ty) in funcs.iter().zip(func_tys.iter()) {
+            let func_ty = self.generalize(func_ty);
+            self.val_env.insert(*name, func_ty);
+        }
+        Ok(())
+    }
+
+    /// infer the type of `expr` knowing it must be `expect`, which comes from the ascribed
+    /// type `anno`. The expected type is pushed into functions and tuples, so a clash is
+    /// reported between the smallest expression and the part of `anno` that don't fit
+    fn check_expr(&mut self, expr: &Expr, expect: &MonoType, anno: &Type) -> InferResult<()> {
+        if self.hole == Some(expr as *const Expr) {
+            return Ok(());
+        }
+        match (expr, resolve(expect.clone())) {
+            (Expr::Fun { pars, body, .. }, TypeBase::Fun(par_tys, res))
+                if pars.len() == par_tys.len() =>
+            {
+                for (par, par_ty) in pars.iter().zip(par_tys) {
+                    self.val_env.insert(*par, par_ty.into());
+                }
+                let anno = match anno {
+                    Type::Fun { res, .. } => res,
+                    anno => anno,
+                };
+                self.check_expr(body, &res, anno)
+            }
+            (Expr::Tuple { elems, .. }, TypeBase::Tuple(elem_tys))
+                if elems.len() == elem_tys.len() =>
+            {
+                for (i, (elem, elem_ty)) in elems.iter().zip(elem_tys.iter()).enumerate() {
+                    let anno = match anno {
+                        Type::Tuple { elems, .. } => &elems[i],
+                        anno => anno,
+                    };
+                    self.check_expr(elem, elem_ty, anno)?;
+                }
+                Ok(())
+            }
+            _ => {
+                let ty = self.infer_expr(expr)?;
+                // the types are shown as they were before unification got halfway
+                let ascribed = expect.diag();
+                let inferred = ty.diag();
+                self.unify(&ty, expect).map_err(|_| {
+                    InferError::AnnoMismatch(Box::new(AnnoMismatch {
+                        found: self.site(expr),
+                        inferred,
+                        expect: *anno.span(),
+                        ascribed,
+                    }))
+                })
+            }
         }
     }
 
     pub fn infer_expr(&mut self, expr: &Expr) -> InferResult<MonoType> {
+        if self.hole == Some(expr as *const Expr) {
+            return Ok(TypeBase::Cell(self.new_cell()));
+        }
         match expr {
             Expr::Lit { lit, .. } => Ok(TypeBase::Lit(lit.get_lit_type())),
-            Expr::Var { var, .. } => match self.val_env.get(&var) {
+            // a hole fits anywhere, what it has to be is only known once the
+            // whole program is checked, see `typed_holes`
+            Expr::Hole { span, .. } => {
+                let ty = TypeBase::Cell(self.new_cell());
+                self.typed_holes
+                    .insert(*span, (ty.clone(), self.val_env.clone()));
+                Ok(ty)
+            }
+            Expr::Module { .. } => {
+                panic!("modules are turned into blocks by the renamer!");
+            }
+            Expr::Try { .. } => {
+                panic!("`try` is turned into a case by the renamer!");
+            }
+            Expr::Var { var, .. } => match self.val_env.get(var) {
                 Some(pty) => Ok(self.instantiate(pty)),
                 None => Err(InferError::VarNotInScope),
             },
-            Expr::Prim { prim, args, .. } => {
-                let prim = TypeBase::get_builtin_type(*prim);
-                let args = args
-                    .iter()
-                    .map(|arg| self.infer_expr(arg))
-                    .collect::<InferResult<Vec<_>>>()?;
-                let res = TypeBase::Cell(self.new_cell());
-                let prim_ty = TypeBase::Fun(args, Box::new(res.clone()));
-                self.unify(&prim, &prim_ty)?;
+            // `Ptr` can only be cast from and to other types, so the cast is checked
+            // against the resolved argument instead of a builtin signature
+            Expr::Prim {
+                prim: Builtin::CastOpaquePtr,
+                args,
+                ..
+            } => match resolve(self.infer_expr(&args[0])?) {
+                TypeBase::OpaquePtr => Ok(TypeBase::Cell(self.new_cell())),
+                _ => Ok(TypeBase::OpaquePtr),
+            },
+            Expr::Prim {
+                prim: Builtin::Bitcast(from, to),
+                span,
+                ..
+            } if from.byte_size() != to.byte_size() => Err(InferError::BitcastSizeMismatch(*span)),
+            // an alignment known at compile time is checked here,
+            // otherwise the allocation fails at runtime
+            Expr::Prim {
+                prim: Builtin::AllocAligned,
+                args,
+                ..
+            } if matches!(&args[1], Expr::Lit { lit: LitVal::Int(align), .. }
+                    if *align <= 0 || !(*align as u64).is_power_of_two()) =>
+            {
+                Err(InferError::AlignmentNotPowerOfTwo(*args[1].span()))
+            }
+            // the arguments are checked against the specifiers of a literal format
+            Expr::Prim {
+                prim: Builtin::Format,
+                args,
+                span,
+            } => {
+                let fmt = match args.first() {
+                    Some(Expr::Lit {
+                        lit: LitVal::Str(fmt),
+                        span,
+                    }) => {
+                        parse_format(fmt.as_str()).map_err(|msg| InferError::BadFormat(*span, msg))
+                    }
+                    Some(arg) => Err(InferError::BadFormat(
+                        *arg.span(),
+                        "the format must be a string literal".to_string(),
+                    )),
+                    None => Err(InferError::BadFormat(
+                        *span,
+                        "the format is missing".to_string(),
+                    )),
+                };
+                let (types, _) = fmt?;
+                if types.len() != args.len() - 1 {
+                    let msg = format!(
+                        "the format has {} specifier(s), but {} argument(s) follow it",
+                        types.len(),
+                        args.len() - 1
+                    );
+                    return Err(InferError::BadFormat(*span, msg));
+                }
+                let pars = std::iter::once(LitType::Str)
+                    .chain(types)
+                    .map(TypeBase::Lit)
+                    .collect();
+                let typ = TypeBase::Fun(pars, Box::new(TypeBase::Lit(LitType::Str)));
+                self.infer_call(typ, *span, args)
+            }
+            Expr::Prim { prim, args, span } => {
+                // a closure captures the variables it uses that are already in scope,
+                // since the renamer gives every binding a unique name
+                let captures = if matches!(prim, Builtin::Spawn | Builtin::SpawnWithStack) {
+                    let mut vars = Vec::new();
+                    used_vars(&args[0], &mut vars);
+                    vars.retain(|var| self.val_env.contains_key(var));
+                    vars
+                } else {
+                    Vec::new()
+                };
+                // the type of the values sent over a channel
+                let mut sent = None;
+                let prim = match prim {
+                    // any value can be prefetched, the hint is an integer
+                    Builtin::Prefetch => TypeBase::Fun(
+                        vec![TypeBase::Cell(self.new_cell()), TypeBase::Lit(LitType::Int)],
+                        Box::new(TypeBase::Lit(LitType::Unit)),
+                    ),
+                    // the value passed to the scheduler has the type of the value resumed with
+                    Builtin::Yield => {
+                        let val = TypeBase::Cell(self.new_cell());
+                        TypeBase::Fun(vec![val.clone()], Box::new(val))
+                    }
+                    Builtin::Spawn | Builtin::SpawnWithStack => {
+                        let res = TypeBase::Cell(self.new_cell());
+                        let mut pars = vec![TypeBase::Fun(Vec::new(), Box::new(res.clone()))];
+                        if *prim == Builtin::SpawnWithStack {
+                            pars.push(TypeBase::Lit(LitType::Int));
+                        }
+                        TypeBase::Fun(pars, Box::new(thread_type(res)))
+                    }
+                    Builtin::Join => {
+                        let res = TypeBase::Cell(self.new_cell());
+                        TypeBase::Fun(vec![thread_type(res.clone())], Box::new(res))
+                    }
+                    // every use of a channel shares its element type, so it is made at
+                    // the outermost level, where `generalize` leaves it alone
+                    Builtin::ChanNew => {
+                        let name = Ident::generate('t');
+                        let cell = Rc::new(RefCell::new(TypeCell::Unbound(name, 0)));
+                        let elem = TypeBase::Cell(cell);
+                        let pars = vec![TypeBase::Lit(LitType::Int)];
+                        TypeBase::Fun(pars, Box::new(chan_type(elem)))
+                    }
+                    Builtin::ChanSend => {
+                        let elem = TypeBase::Cell(self.new_cell());
+                        sent = Some(elem.clone());
+                        let pars = vec![chan_type(elem.clone()), elem];
+                        TypeBase::Fun(pars, Box::new(TypeBase::Lit(LitType::Unit)))
+                    }
+                    // the option constructors are passed in, see `desugar_chan_recv`
+                    Builtin::ChanRecv => {
+                        let elem = TypeBase::Cell(self.new_cell());
+                        let opt = TypeBase::Cell(self.new_cell());
+                        let some = TypeBase::Fun(vec![elem.clone()], Box::new(opt.clone()));
+                        let pars = vec![chan_type(elem), opt.clone(), some];
+                        TypeBase::Fun(pars, Box::new(opt))
+                    }
+                    Builtin::ChanClose => {
+                        let elem = TypeBase::Cell(self.new_cell());
+                        let pars = vec![chan_type(elem)];
+                        TypeBase::Fun(pars, Box::new(TypeBase::Lit(LitType::Unit)))
+                    }
+                    Builtin::WithMutex => {
+                        let res = TypeBase::Cell(self.new_cell());
+                        let func = TypeBase::Fun(Vec::new(), Box::new(res.clone()));
+                        TypeBase::Fun(vec![mutex_type(), func], Box::new(res))
+                    }
+                    // the result constructors are passed in, see `desugar_file_io`
+                    Builtin::ReadFile | Builtin::WriteFile => {
+                        let str = TypeBase::Lit(LitType::Str);
+                        let res = TypeBase::Cell(self.new_cell());
+                        let ok_val = if *prim == Builtin::ReadFile {
+                            str.clone()
+                        } else {
+                            TypeBase::Lit(LitType::Unit)
+                        };
+                        let ok = TypeBase::Fun(vec![ok_val], Box::new(res.clone()));
+                        let err = TypeBase::Fun(vec![str.clone()], Box::new(res.clone()));
+                        let mut pars = vec![str.clone()];
+                        if *prim == Builtin::WriteFile {
+                            pars.push(str);
+                        }
+                        pars.extend([ok, err]);
+                        TypeBase::Fun(pars, Box::new(res))
+                    }
+                    // and the list constructors, see `desugar_list_dir`
+                    Builtin::ListDir => {
+                        let str = TypeBase::Lit(LitType::Str);
+                        let res = TypeBase::Cell(self.new_cell());
+                        let list = TypeBase::Cell(self.new_cell());
+                        let ok = TypeBase::Fun(vec![list.clone()], Box::new(res.clone()));
+                        let err = TypeBase::Fun(vec![str.clone()], Box::new(res.clone()));
+                        let cons =
+                            TypeBase::Fun(vec![str.clone(), list.clone()], Box::new(list.clone()));
+                        let pars = vec![str, ok, err, list, cons];
+                        TypeBase::Fun(pars, Box::new(res))
+                    }
+                    prim => TypeBase::get_builtin_type(*prim),
+                };
+                let res = self.infer_call(prim, *span, args)?;
+                if sent.is_some_and(|elem| !self.is_send(&elem)) {
+                    return Err(InferError::ChanNotSend(*args[1].span()));
+                }
+                for capture in captures {
+                    let typ = self.instantiate(&self.val_env[&capture]);
+                    if !self.is_send(&typ) {
+                        let span = *args[0].span();
+                        return Err(InferError::NotSend { capture, span });
+                    }
+                }
                 Ok(res)
             }
+            Expr::Anno { expr, typ, .. } => {
+                let anno = self.annotation(typ, &HashMap::new());
+                self.check_expr(expr, &anno, typ)?;
+                Ok(anno)
+            }
             Expr::Fun { pars, body, .. } => {
                 let pars = pars
                     .iter()
@@ -383,29 +1272,90 @@ impl Infer {
                 Ok(TypeBase::Fun(pars, Box::new(res)))
             }
             Expr::App { func, args, .. } => {
+                let func_span = self.site(func);
                 let func = self.infer_expr(func)?;
-                let args = args
-                    .iter()
-                    .map(|arg| self.infer_expr(arg))
-                    .collect::<InferResult<Vec<_>>>()?;
-                let res = TypeBase::Cell(self.new_cell());
-                let func_ty = TypeBase::Fun(args, Box::new(res.clone()));
-                self.unify(&func, &func_ty)?;
-                Ok(res)
+                self.infer_call(func, func_span, args)
             }
-            Expr::ExtCall {
-                func: _, args: _, ..
-            } => {
-                todo!()
+            Expr::ExtCall { func, args, span } => {
+                let variadic = self.ext_variadic.contains(func);
+                let func = match self.ext_env.get(func) {
+                    Some(pty) => self.instantiate(pty),
+                    None => return Err(InferError::ExternNotInScope),
+                };
+                if variadic {
+                    self.infer_variadic_call(func, *span, args)
+                } else {
+                    self.infer_call(func, *span, args)
+                }
             }
             Expr::Cons {
                 cons: _, args: _, ..
+            } => Err(InferError::NotSupportedYet),
+            Expr::Tuple { elems, .. } => {
+                let elems = elems
+                    .iter()
+                    .map(|elem| self.infer_expr(elem))
+                    .collect::<InferResult<Vec<_>>>()?;
+                Ok(TypeBase::Tuple(elems))
+            }
+            Expr::Proj { expr, index, .. } => {
+                // the arity of tuple must be known at the projection site
+                match resolve(self.infer_expr(expr)?) {
+                    TypeBase::Tuple(elems) => elems
+                        .get(*index)
+                        .cloned()
+                        .ok_or(InferError::ProjectionOutOfRange),
+                    _ => Err(InferError::CantProjectNonTuple),
+                }
+            }
+            Expr::Record { fields, .. } => {
+                let mut res = None;
+                for FieldInit { field, expr, .. } in fields {
+                    // the field is looked up after the expression, so its type is borrowed
+                    let expr = self.infer_expr(expr)?;
+                    let (record, field_ty) = self.lookup_field(field)?;
+                    let record = TypeBase::App(record, Vec::new());
+                    self.unify(res.get_or_insert(record.clone()), &record)?;
+                    self.unify(&expr, field_ty)?;
+                }
+                Ok(res.unwrap())
+            }
+            Expr::Field { expr, field, .. } => {
+                let expr = self.infer_expr(expr)?;
+                let (record, field_ty) = self.lookup_field(field)?;
+                self.unify(&expr, &TypeBase::App(record, Vec::new()))?;
+                Ok(field_ty.clone())
+            }
+            Expr::Update { expr, fields, .. } => {
+                let res = self.infer_expr(expr)?;
+                for FieldInit { field, expr, .. } in fields {
+                    let expr = self.infer_expr(expr)?;
+                    let (record, field_ty) = self.lookup_field(field)?;
+                    self.unify(&res, &TypeBase::App(record, Vec::new()))?;
+                    self.unify(&expr, field_ty)?;
+                }
+                Ok(res)
+            }
+            Expr::Ifte {
+                cond,
+                trbr,
+                flbr,
+                span,
             } => {
-                todo!()
+                let cond_ty = self.infer_expr(cond)?;
+                self.unify(&cond_ty, &TypeBase::Lit(LitType::Bool))
+                    .map_err(|err| self.mismatch(err, cond, *span))?;
+                let trbr_ty = self.infer_expr(trbr)?;
+                let flbr_ty = self.infer_expr(flbr)?;
+                self.unify(&trbr_ty, &flbr_ty)
+                    .map_err(|err| self.mismatch(err, flbr, self.site(trbr)))?;
+                Ok(trbr_ty)
             }
             Expr::Let {
                 bind, expr, cont, ..
             } => {
+                self.check_cancel()?;
+                self.var_sites.insert(*bind, self.site(expr));
                 self.level += 1;
                 let expr = self.infer_expr(expr)?;
                 self.level -= 1;
@@ -414,41 +1364,1505 @@ impl Infer {
                 let cont = self.infer_expr(cont)?;
                 Ok(cont)
             }
-            Expr::Case {
-                expr: _, rules: _, ..
-            } => {
-                todo!()
+            Expr::Case { expr, rules, .. } => {
+                let expr = self.infer_expr(expr)?;
+                let res = TypeBase::Cell(self.new_cell());
+                for rule in rules.iter() {
+                    let patn = self.infer_pattern(&rule.patn)?;
+                    self.unify(&expr, &patn)?;
+                    if let Some(guard) = &rule.guard {
+                        let guard_ty = self.infer_expr(guard)?;
+                        self.unify(&guard_ty, &TypeBase::Lit(LitType::Bool))
+                            .map_err(|err| self.mismatch(err, guard, rule.span))?;
+                    }
+                    let body = self.infer_expr(&rule.body)?;
+                    self.unify(&res, &body)?;
+                }
+                Ok(res)
             }
-            Expr::Blk {
-                decls: _, cont: _, ..
-            } => {
-                todo!()
+            Expr::Blk { decls, cont, .. } | Expr::LetRec { decls, cont, .. } => {
+                self.sites.push(expr);
+                let abstracts: Vec<Ident> = decls
+                    .iter()
+                    .filter(|decl| matches!(decl, Decl::Abstract { .. }))
+                    .map(|decl| decl.get_name())
+                    .collect();
+                let mut funcs = Vec::new();
+                for decl in decls.iter() {
+                    match decl {
+                        Decl::Type {
+                            name, pars, typ, ..
+                        } if abstracts.contains(name) => {
+                            self.abstract_env.insert(*name, (pars.clone(), typ.clone()));
+                        }
+                        decl => funcs.extend(self.declare(decl)?),
+                    }
+                }
+                self.infer_funcs(&funcs)?;
+                for name in abstracts.iter() {
+                    self.abstract_env.remove(name);
+                }
+                self.sites.pop();
+                // the declarations are generalized by now, so a mismatch in the continuation
+                // is blamed on the continuation only
+                self.sites.push(cont.as_ref());
+                let cont = self.infer_expr(cont)?;
+                self.sites.pop();
+                Ok(cont)
             }
         }
     }
 }
 
-#[test]
-fn type_check_test() {
-    use super::parser::*;
-    use super::renamer::Renamer;
-    let string = r#"
-let combi = fun(x) => x;
-let combk = fun(x,y) => x;
-let combkc = fun(x) => fun(y) => x;
-let combs = fun(f,g,x) => f(x)(g(x));
-combi
-"#;
+/// a type with its quantified variables, as bound to a name
+pub type Scheme = TypeBase<()>;
 
-    let mut par = Parser::new(string);
-    let expr = parse_expr(&mut par).unwrap();
-    println!("{}", expr);
-    let mut rnm = Renamer::new();
-    let res = rnm.visit_expr(expr);
-    println!("{}", res);
-    let mut tych = Infer::new();
-    tych.infer_expr(&res).unwrap();
-    for (k, v) in tych.val_env.iter() {
-        println!("{k} : {v}");
+/// the types of the top-level names checked so far, so that declarations can be checked
+/// one at a time, each against the ones before it
+pub struct TypeEnv {
+    infer: Infer,
+}
+
+impl Default for TypeEnv {
+    fn default() -> Self {
+        TypeEnv::new()
+    }
+}
+
+impl TypeEnv {
+    pub fn new() -> TypeEnv {
+        TypeEnv {
+            infer: Infer::new(),
+        }
     }
+
+    /// check `decl` and bring what it declares into scope, the result is the scheme of
+    /// the function or extern it declares. A function is a recursive group on its own,
+    /// so it can't call the functions declared after it.
+    pub fn check_decl(&mut self, decl: &Decl) -> Result<Option<Scheme>, Diagnostic> {
+        let res = match self.infer.declare(decl) {
+            Ok(Some(func)) => self.infer.infer_funcs(&[func]),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            self.infer.level = 0;
+            // a function that failed to check is assumed to have any type,
+            // so that its uses in later declarations aren't reported again
+            if let Decl::Func { name, .. } = decl {
+                let any = TypeBase::Var(Ident::generate('t'), ());
+                self.infer.val_env.insert(*name, any);
+            }
+            return Err(err.diagnostic());
+        }
+        match decl {
+            Decl::Func { .. } | Decl::Extern { .. } => Ok(self.lookup(decl.get_name()).cloned()),
+            _ => Ok(None),
+        }
+    }
+
+    /// the scheme of a function or an extern checked so far
+    pub fn lookup(&self, name: Ident) -> Option<&Scheme> {
+        match self.infer.val_env.get(&name) {
+            Some(pty) => Some(pty),
+            // externs aren't renamed, so their names have no index
+            None if name.is_dummy() => self.infer.ext_env.get(&name.name),
+            None => None,
+        }
+    }
+}
+
+impl TypedHole {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let name = self.name;
+        let mut diag = Diagnostic::error("typed hole");
+        diag = match &self.typ {
+            Some(typ) => diag.line_span(self.span, format!("`?{name}` has the type {typ}")),
+            None => diag.line_span(
+                self.span,
+                format!("the type of `?{name}` is unknown, type checking stopped before it"),
+            ),
+        };
+        if let Some(func) = self.func {
+            let func = diagnostic::internal_ident(&func);
+            diag = diag.line(format!("in the function `{func}`"));
+        }
+        if !self.scope.is_empty() {
+            diag = diag.line("with these variables in scope:");
+        }
+        for (var, typ) in self.scope.iter() {
+            let var = diagnostic::internal_ident(var);
+            diag = diag.line(format!("  {var} : {typ}"));
+        }
+        diag
+    }
+}
+
+impl InferError {
+    /// the diagnostic reported for this error, mismatches that are localized
+    /// are reported by the driver instead
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self.clone() {
+            InferError::NotSupportedYet => Diagnostic::info("type checking skipped")
+                .line("the program uses features not supported by type checker yet"),
+            InferError::Cancelled => Diagnostic::info("type checking cancelled"),
+            InferError::ReturnTypeMismatch(span) => Diagnostic::error("type error")
+                .line_span(span, "the function body doesn't have this return type"),
+            InferError::AlignmentNotPowerOfTwo(span) => {
+                Diagnostic::error("type error").line_span(span, "alignment must be a power of two")
+            }
+            InferError::Mismatch { found, expect } => Diagnostic::error("type error")
+                .line("mismatched types")
+                .note_span(found, "the type was first inferred here")
+                .note_span(expect, "but this expects a different type"),
+            InferError::AnnoMismatch(mismatch) => Diagnostic::error("type error")
+                .line("mismatched types")
+                .note_span(
+                    mismatch.found,
+                    format!("this has the type {}", mismatch.inferred),
+                )
+                .note_span(
+                    mismatch.expect,
+                    format!("but it is ascribed the type {}", mismatch.ascribed),
+                ),
+            InferError::NotFfiSafe(span) => Diagnostic::error("type error").line_span(
+                span,
+                "a variadic argument must be an `Int`, `Real`, `Bool`, `Char` or `Ptr`",
+            ),
+            InferError::NotSend { capture, span } => Diagnostic::error("type error").line_span(
+                span,
+                format!(
+                    "a closure run on a new thread can't capture `{capture}`, which isn't `Send`"
+                ),
+            ),
+            InferError::ChanNotSend(span) => Diagnostic::error("type error").line_span(
+                span,
+                "a value sent over a channel must be `Send`, this one isn't",
+            ),
+            InferError::BadFormat(span, msg) => {
+                Diagnostic::error("type error").line_span(span, msg)
+            }
+            InferError::BitcastSizeMismatch(span) => Diagnostic::error("type error")
+                .line_span(span, "bitcast between types of different sizes"),
+            err => Diagnostic::error("type error").line(format!("{err:?}")),
+        }
+    }
+
+    /// the error is a conflict between two types, rather than a missing name or a restriction
+    pub fn is_mismatch(&self) -> bool {
+        matches!(
+            self,
+            InferError::CantUnifyLiteralTypes
+                | InferError::CantUnifyDiffArgLens
+                | InferError::CantUnifyConstructor
+                | InferError::CantUnify
+                | InferError::OccurCheckFailed
+                | InferError::ReturnTypeMismatch(_)
+                | InferError::Mismatch { .. }
+                | InferError::AnnoMismatch(_)
+        )
+    }
+}
+
+/// how many expressions a declaration group may have for `localize` to try,
+/// each of them costs another run of the type checker
+pub const LOCALIZE_LIMIT: usize = 256;
+
+/// the expressions blamed for a type mismatch by `localize`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Blame {
+    pub culprit: Span,
+    /// other expressions that would fix the mismatch as well, at most two
+    pub alternatives: Vec<Span>,
+}
+
+/// Find the smallest expression to blame for a type mismatch in the program `expr`.
+/// Unification solves constraints in the order they are generated, so a mismatch shows up
+/// where the second of two conflicting uses is, not at the one that is wrong. Instead, the
+/// declaration group or the block continuation where inference failed is checked again with
+/// each of its expressions assumed to have any type, which drops the constraint between the
+/// expression and its context. The expressions whose removal gets inference past it are candidates,
+/// and the smallest one is blamed, a function being called is trusted over its arguments.
+/// If removing an expression doesn't help, removing a part of it can't either, so those
+/// parts are never tried. Returns `None` if there is no mismatch to blame,
+/// or the site of the failure has more than `LOCALIZE_LIMIT` expressions.
+pub fn localize(expr: &Expr) -> Option<Blame> {
+    let mut tych = Infer::new();
+    if !tych.infer_expr(expr).err()?.is_mismatch() {
+        return None;
+    }
+    let site = tych.sites.last().copied();
+    let root = match site {
+        Some(site) => find_expr(expr, site)?,
+        None => expr,
+    };
+    if expr_size(root) > LOCALIZE_LIMIT {
+        return None;
+    }
+    let passes = |hole: &Expr| {
+        let mut tych = Infer::new();
+        tych.hole = Some(hole);
+        match tych.infer_expr(expr) {
+            Ok(_) => true,
+            // a failure after the site is someone else's problem
+            Err(_) => site.is_some_and(|site| !tych.sites.contains(&site)),
+        }
+    };
+    let mut found = Vec::new();
+    if !blame_search(root, false, &passes, &mut found) {
+        return None;
+    }
+    found.sort_by_key(|(expr, callee)| (expr_size(expr), *callee, expr.span().start.abs));
+    let mut spans = found.into_iter().map(|(expr, _)| *expr.span());
+    Some(Blame {
+        culprit: spans.next()?,
+        alternatives: spans.take(2).collect(),
+    })
+}
+
+/// whether removing `expr` fixes the mismatch, and if so the smallest parts of it
+/// that do as well are pushed to `found`, marked if they are called as a function
+fn blame_search<'a>(
+    expr: &'a Expr,
+    callee: bool,
+    passes: &dyn Fn(&Expr) -> bool,
+    found: &mut Vec<(&'a Expr, bool)>,
+) -> bool {
+    if !passes(expr) {
+        return false;
+    }
+    let callee_ptr = match expr {
+        Expr::App { func, .. } => Some(func.as_ref() as *const Expr),
+        _ => None,
+    };
+    let mut inner = false;
+    for sub in expr.subexprs() {
+        let callee = callee_ptr == Some(sub as *const Expr);
+        inner |= blame_search(sub, callee, passes, found);
+    }
+    if !inner {
+        found.push((expr, callee));
+    }
+    true
+}
+
+fn find_expr(expr: &Expr, target: *const Expr) -> Option<&Expr> {
+    if std::ptr::eq(expr, target) {
+        return Some(expr);
+    }
+    expr.subexprs()
+        .into_iter()
+        .find_map(|sub| find_expr(sub, target))
+}
+
+fn expr_size(expr: &Expr) -> usize {
+    1 + expr.subexprs().into_iter().map(expr_size).sum::<usize>()
+}
+
+fn thread_type(res: MonoType) -> MonoType {
+    TypeBase::App(Ident::from(InternStr::new(THREAD)), vec![res])
+}
+
+fn chan_type(elem: MonoType) -> MonoType {
+    TypeBase::App(Ident::from(InternStr::new(CHAN)), vec![elem])
+}
+
+fn mutex_type<P>() -> TypeBase<P> {
+    TypeBase::App(Ident::from(InternStr::new(MUTEX)), Vec::new())
+}
+
+fn sem_type<P>() -> TypeBase<P> {
+    TypeBase::App(Ident::from(InternStr::new(SEM)), Vec::new())
+}
+
+/// find the typed holes in `expr`, with the innermost named function around each one
+/// and the variables bound on the way to it
+fn hole_scopes(
+    expr: &Expr,
+    func: Option<Ident>,
+    scope: &mut Vec<Ident>,
+    found: &mut Vec<(InternStr, Span, Option<Ident>, Vec<Ident>)>,
+) {
+    let depth = scope.len();
+    match expr {
+        Expr::Hole { name, span } => found.push((*name, *span, func, scope.clone())),
+        Expr::Fun { pars, body, .. } => {
+            scope.extend(pars.iter().copied());
+            hole_scopes(body, func, scope, found);
+        }
+        Expr::Let {
+            bind, expr, cont, ..
+        } => {
+            hole_scopes(expr, func, scope, found);
+            scope.push(*bind);
+            hole_scopes(cont, func, scope, found);
+        }
+        Expr::Case { expr, rules, .. } => {
+            hole_scopes(expr, func, scope, found);
+            for rule in rules.iter() {
+                scope.extend(rule.patn.get_freevars());
+                for sub in rule.guard.iter().chain([&rule.body]) {
+                    hole_scopes(sub, func, scope, found);
+                }
+                scope.truncate(depth);
+            }
+        }
+        // the functions of a block can call each other
+        Expr::Blk { decls, cont, .. } | Expr::LetRec { decls, cont, .. } => {
+            for decl in decls.iter() {
+                if let Decl::Func { name, .. } = decl {
+                    scope.push(*name);
+                }
+            }
+            let names = scope.len();
+            for decl in decls.iter() {
+                if let Decl::Func {
+                    name, pars, body, ..
+                } = decl
+                {
+                    scope.extend(pars.iter().map(|(par, _)| *par));
+                    hole_scopes(body, Some(*name), scope, found);
+                    scope.truncate(names);
+                }
+            }
+            hole_scopes(cont, func, scope, found);
+        }
+        expr => {
+            for sub in expr.subexprs() {
+                hole_scopes(sub, func, scope, found);
+            }
+        }
+    }
+    scope.truncate(depth);
+}
+
+/// the variables used in an expression, each one once
+fn used_vars(expr: &Expr, vars: &mut Vec<Ident>) {
+    match expr {
+        Expr::Var { var, .. } if !vars.contains(var) => vars.push(*var),
+        expr => {
+            for sub in expr.subexprs() {
+                used_vars(sub, vars);
+            }
+        }
+    }
+}
+
+// follow the links until reaching a type constructor or an unbound cell
+fn resolve(ty: MonoType) -> MonoType {
+    if let TypeBase::Cell(cell) = &ty {
+        if let TypeCell::Link(link) = cell.borrow().deref() {
+            return resolve(link.clone());
+        }
+    }
+    ty
+}
+
+#[test]
+fn type_check_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let combi = fun(x) => x;
+let combk = fun(x,y) => x;
+let combkc = fun(x) => fun(y) => x;
+let combs = fun(f,g,x) => f(x)(g(x));
+combi
+"#;
+
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    println!("{}", expr);
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    println!("{}", res);
+    let mut tych = Infer::new();
+    tych.infer_expr(&res).unwrap();
+    for (k, v) in tych.val_env.iter() {
+        println!("{k} : {v}");
+    }
+}
+
+#[test]
+fn type_check_value_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    // a top-level value is generalized like a let
+    let string = r#"
+begin
+    let id = fun(x) => x;
+in
+    (id(1), id(true))
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Int, Bool)");
+}
+
+#[test]
+fn type_check_tuple_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let t = (1, true);
+(t.1, t.0)
+"#;
+
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Bool, Int)");
+
+    // projection out of range
+    let string = r#"
+(1, 2).2
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert_eq!(tych.infer_expr(&res), Err(InferError::ProjectionOutOfRange));
+}
+
+#[test]
+fn type_check_case_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let swap = fun(p) => case p of | (x, y) => { (y, x) } end;
+let first = fun(t) => case t of | (0, _) => { 0 } | (n, _) => { n } end;
+(swap, first)
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    let text = format!("{ty}");
+    // the literal fixes the first component, the wildcard stays polymorphic
+    assert!(text.starts_with("(fun((t_"));
+    assert!(text.contains(")) -> (t_"));
+    assert!(text.contains("fun((Int, t_"));
+    assert!(text.ends_with(")) -> Int)"));
+
+    // tuple patterns have a fixed arity
+    let string = "case (1, 2) of | (x, y, z) => { x } end";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+
+    // all the rules must have the same type
+    let string = "case (1, true) of | (0, b) => { b } | (n, _) => { n } end";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_ifte_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let f = fun(b, x) => if b then x else if true then x else x;
+f(false, 42)
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "Int");
+
+    // condition must be boolean
+    let string = "if 1 then 2 else 3";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+
+    // branches must have the same type
+    let string = "if true then 2 else false";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+
+    // the result of the recursive call is already linked to `acc` when the branches are
+    // unified, which is not a cycle
+    let string = r#"
+begin
+    fun go(n, acc) => if @icmpeq(n, 0) then acc else go(@isub(n, 1), acc)
+in
+    go(10, 0)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "Int");
+}
+
+#[test]
+fn type_check_prefetch_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let f = fun(x) => @prefetch(x, 3);
+let u = f((1, 2));
+f(true)
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "()");
+
+    // the hint must be an integer
+    let string = "@prefetch(1, true)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_simd_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let f = fun(a, b) => @simdstore(@simdmul(@simdadd(a, b), b));
+f(@simdload((1.0, 2.0)), @simdload((3.0, 4.0)))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Real, Real)");
+
+    // a vector is not a tuple
+    let string = "@simdadd((1.0, 2.0), @simdload((3.0, 4.0)))";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_annotation_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    fun id(x) => x
+    fun first(x: Int, y) => x
+    fun pick(b, x: Real, y) => if b then x else y
+in
+    (id(1), id(true), first, pick)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    let text = format!("{ty}");
+    // unannotated parameters are still inferred, and generalized
+    assert!(text.starts_with("(Int, Bool, fun(Int, t_"));
+    assert!(text.ends_with(") -> Int, fun(Bool, Real, Real) -> Real)"));
+
+    // an annotation that doesn't fit the body
+    let string = r#"
+begin
+    fun f(x: Bool, y) => @iadd(x, y)
+in
+    f
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_ascription_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let check = |string: &str| {
+        let mut par = Parser::new(string);
+        let expr = parse_expr(&mut par).unwrap();
+        let mut rnm = Renamer::new();
+        let res = rnm.visit_expr(expr);
+        let mut tych = Infer::new();
+        tych.infer_expr(&res).map(|ty| format!("{ty}"))
+    };
+    // the ascribed type is pushed into the function, so `x` is known to be an `Int`
+    let ty = check("let f = (fun(x) => x : fun(Int) -> Int); f").unwrap();
+    assert_eq!(ty, "fun(Int) -> Int");
+    let ty = check("let f = fun(x) => x; ((f, true) : (fun(Int) -> Int, Bool))").unwrap();
+    assert_eq!(ty, "(fun(Int) -> Int, Bool)");
+
+    // the clash is blamed on the tuple element, and on the part of the type it is checked against
+    let string = "(fun(x) => (x, 1) : fun(Bool) -> (Bool, Real))";
+    let Err(InferError::AnnoMismatch(mismatch)) = check(string) else {
+        panic!("expected an ascription mismatch");
+    };
+    let AnnoMismatch { found, expect, .. } = *mismatch;
+    assert_eq!(&string[found.start.abs..found.end.abs], "1");
+    assert_eq!(&string[expect.start.abs..expect.end.abs], "Real");
+    assert_eq!(
+        (mismatch.ascribed, mismatch.inferred),
+        ("Real".into(), "Int".into())
+    );
+}
+
+#[test]
+fn type_check_typed_hole_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    fun go(n, acc) => if @icmpeq(n, 0) then ?done else go(@isub(n, 1), ?next)
+    fun inc(x) => {
+        let y = @iadd(x, 1);
+        ?result
+    }
+in
+    @iadd(go(10, true), ?top)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    tych.infer_expr(&res).unwrap();
+    // every hole is reported in source order, with its function and the variables in scope.
+    // the types are shown as they are once the whole program is checked, but the
+    // function a hole is in isn't generalized yet
+    let holes: Vec<String> = tych
+        .typed_holes(&res)
+        .iter()
+        .map(|hole| {
+            let scope = hole
+                .scope
+                .iter()
+                .map(|(var, typ)| format!("{} : {typ}", var.name))
+                .format(", ");
+            let func = hole
+                .func
+                .map_or("-".to_string(), |func| func.name.to_string());
+            let typ = hole.typ.as_ref().unwrap();
+            diagnostic::normalize_internals(&format!(
+                "?{} : {typ} in {func} with {scope}",
+                hole.name
+            ))
+        })
+        .collect();
+    assert_eq!(
+        holes,
+        [
+            "?done : a in go with go : fun(Int, b) -> a, inc : fun(Int) -> c, n : Int, acc : b",
+            // a hole passed on as `acc` has its type
+            "?next : a in go with go : fun(Int, a) -> b, inc : fun(Int) -> c, n : Int, acc : a",
+            "?result : a in inc with go : fun(Int, b) -> c, inc : fun(Int) -> a, x : Int, y : Int",
+            "?top : Int in - with go : fun(Int, a) -> b, inc : fun(Int) -> c",
+        ]
+    );
+}
+
+#[test]
+fn type_check_scheme_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let check = |string: &str| {
+        let mut par = Parser::new(string);
+        let expr = parse_expr(&mut par).unwrap();
+        let mut rnm = Renamer::new();
+        let res = rnm.visit_expr(expr);
+        let mut tych = Infer::new();
+        tych.infer_expr(&res)
+            .map(|ty| diagnostic::normalize_internals(&ty.diag()))
+    };
+    // functions are generalized once they are inferred, so each use is instantiated anew
+    let string = r#"
+begin
+    fun id(x) => x
+    fun compose(f, g) => fun(x) => f(g(x))
+in
+    (id(1), id(true), compose(id, id)(1.5), compose)
+end
+"#;
+    let ty = check(string).unwrap();
+    assert_eq!(
+        ty,
+        "(Int, Bool, Real, fun(fun(a) -> b, fun(c) -> a) -> fun(c) -> b)"
+    );
+    // and so are local bindings
+    let ty = check("let id = fun(x) => x; (id(1), id(true))").unwrap();
+    assert_eq!(ty, "(Int, Bool)");
+
+    // an extern with a scheme is polymorphic as well
+    let string = r#"
+begin
+    extern ident : forall T. fun(T) -> T;
+    extern pair : forall A B. fun(A, B) -> (A, B);
+in
+    (#ident(1), #ident(true), #pair(1, true))
+end
+"#;
+    let ty = check(string).unwrap();
+    assert_eq!(ty, "(Int, Bool, (Int, Bool))");
+
+    // instantiating a scheme gives its body with fresh variables
+    let string = "begin extern ident : forall T. fun(T) -> T; in 1 end";
+    let mut par = Parser::new(string);
+    let Expr::Blk { decls, .. } = parse_expr(&mut par).unwrap() else {
+        panic!("expected a block");
+    };
+    let Decl::Extern { typ, .. } = &decls[0] else {
+        panic!("expected an extern");
+    };
+    let fresh = Ident::from(InternStr::new("U"));
+    let typ = typ.instantiate(|| fresh);
+    assert_eq!(format!("{typ}"), "fn (U) -> U");
+    assert_eq!(
+        format!("{}", typ.instantiate(|| unreachable!())),
+        "fn (U) -> U"
+    );
+}
+
+#[test]
+fn type_check_return_annotation_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    // the return type is known before the body is inferred
+    let string = r#"
+begin
+    fun loop(x): Real => loop(x)
+    fun swap(p: (Int, Bool)): (Bool, Int) => (p.1, p.0)
+in
+    (loop, swap)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    let text = format!("{ty}");
+    assert!(text.starts_with("(fun(t_"));
+    assert!(text.ends_with(") -> Real, fun((Int, Bool)) -> (Bool, Int))"));
+
+    let string = r#"
+begin
+    fun f(x): Bool => @iadd(x, 1)
+in
+    f
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    match tych.infer_expr(&res) {
+        Err(InferError::ReturnTypeMismatch(span)) => {
+            assert_eq!(&string[span.start.abs..span.end.abs], "Bool");
+        }
+        res => panic!("expected a return type mismatch, found {res:?}"),
+    }
+}
+
+#[test]
+fn type_check_letrec_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let n = 10;
+letrec
+    fun even(x) => if @icmpeq(x, 0) then true else odd(@isub(x, 1))
+    fun odd(x) => if @icmpeq(x, 0) then false else even(@isub(x, 1))
+in
+(even(n), odd)
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Bool, fun(Int) -> Bool)");
+}
+
+#[test]
+fn type_check_record_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    data Point = { x: Int, y: Real }
+    fun move-x(p, d) => { p with x = @iadd(p.x, d) }
+in
+    let p = move-x({ y = 1.0, x = 2 }, 3);
+    (p, p.x, p.y)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.errors().is_empty());
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Point, Int, Real)");
+
+    // the field has a different type
+    let string = r#"
+begin
+    data Point = { x: Int, y: Real }
+in
+    { x = 1, y = 2 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = Renamer::new().visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_opaque_ptr_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    extern open_file : fun(Int) -> Ptr;
+    extern close_file : fun(Ptr) -> ();
+    extern identity[T] : fun(T) -> T;
+in
+    let h = #open_file(42);
+    let u = #close_file(#identity(h));
+    let p = @castptr(3.14);
+    @iadd(@castptr(p), 1)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "Int");
+
+    // no arithmetic on pointers
+    let string = r#"
+begin
+    extern open_file : fun(Int) -> Ptr;
+in
+    @iadd(#open_file(42), 1)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+
+    // an extern must be declared before being called
+    let string = "#open_file(42)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert_eq!(tych.infer_expr(&res), Err(InferError::ExternNotInScope));
+}
+
+#[test]
+fn type_check_variadic_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    extern log_values : fun(Ptr, ...) -> ();
+    extern format : fun(Int) -> Ptr;
+in
+    let u = #log_values(#format(0), 42, 3.14, true, #format(1));
+    #log_values(#format(2))
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "()");
+    let mut varargs: Vec<_> = tych.varargs().values().cloned().collect();
+    varargs.sort_by_key(|args| args.len());
+    assert_eq!(
+        varargs,
+        [
+            vec![],
+            vec![FfiType::Int, FfiType::Real, FfiType::Bool, FfiType::Ptr],
+        ]
+    );
+
+    // the fixed arguments are checked as usual
+    let string = r#"
+begin
+    extern log_values : fun(Ptr, ...) -> ();
+in
+    #log_values(42, 42)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+
+    // a tuple is boxed, C can't take it through `...`
+    let string = r#"
+begin
+    extern log_values : fun(Ptr, ...) -> ();
+    extern format : fun(Int) -> Ptr;
+in
+    #log_values(#format(0), 1, (2, 3))
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let Err(InferError::NotFfiSafe(span)) = tych.infer_expr(&res) else {
+        panic!("a tuple was passed to `...`");
+    };
+    assert_eq!(&string[span.start.abs..span.end.abs], "(2, 3)");
+}
+
+#[test]
+fn type_check_guard_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    // the bindings of the pattern are in scope of the guard
+    let string = r#"
+case (1, 2.0) of
+| (x, y) if @icmpgt(x, 0) => { y }
+| _ => { 0.0 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.errors().is_empty());
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "Real");
+
+    let string = r#"
+case 1 of
+| x if @iadd(x, 1) => { x }
+| _ => { 0 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(matches!(
+        tych.infer_expr(&res),
+        Err(InferError::Mismatch { .. })
+    ));
+}
+
+#[test]
+fn type_check_or_pattern_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    // every alternative binds `x` at the same type
+    let string = r#"
+case (1, 2) of
+| (x, 1) | (1, x) => { x }
+| _ => { 0 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.errors().is_empty());
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "Int");
+
+    let string = r#"
+fun(t) =>
+    case t of
+    | (x, 1) | (true, x) => { x }
+    | _ => { 0 }
+    end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(matches!(
+        tych.infer_expr(&res),
+        Err(InferError::Mismatch { .. })
+    ));
+}
+
+#[test]
+fn type_check_as_pattern_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    // the alias has the type of the whole value
+    let string = r#"
+case (1, true) of
+| p @ (x, _) => { (p, x) }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.errors().is_empty());
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "((Int, Bool), Int)");
+}
+
+#[test]
+fn type_check_coroutine_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    extern coroutine_new[A, B] : fun(fun(A) -> B) -> Coroutine[A, B];
+    extern coroutine_resume[A, B] : fun(Coroutine[A, B], A) -> A;
+    fun count(n) => count(@iadd(@yield(n), 1))
+in
+    let co = #coroutine_new(count);
+    (co, #coroutine_resume(co, 0))
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.errors().is_empty());
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert!(format!("{ty}").starts_with("(Coroutine[Int, "));
+
+    // the value resumed with has the type of the value yielded
+    let string = r#"
+begin
+    extern coroutine_new[A, B] : fun(fun(A) -> B) -> Coroutine[A, B];
+    extern coroutine_resume[A, B] : fun(Coroutine[A, B], A) -> A;
+    fun flip(b) => @bnot(@yield(b))
+in
+    #coroutine_resume(#coroutine_new(flip), 1)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_thread_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let n = 1;
+let t = @spawn(fun() => (n, true));
+(t, @join(t))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Thread[(Int, Bool)], (Int, Bool))");
+
+    // a coroutine is not `Send`, neither is a tuple holding one
+    let string = r#"
+begin
+    extern coroutine_new[A, B] : fun(fun(A) -> B) -> Coroutine[A, B];
+    extern coroutine_resume[A, B] : fun(Coroutine[A, B], A) -> A;
+    fun count(n) => count(@iadd(@yield(n), 1))
+in
+    let co = (#coroutine_new(count), 1);
+    @spawnwithstack(fun() => #coroutine_resume(co.0, 0), 65536)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let Err(InferError::NotSend { capture, span }) = tych.infer_expr(&res) else {
+        panic!("capturing a coroutine should fail!")
+    };
+    assert_eq!(&*capture.name, "co");
+    assert!(string[span.start.abs..span.end.abs].starts_with("fun() =>"));
+
+    // a `Ptr` is not `Send` either
+    let string = r#"
+let p = @castptr(1);
+@join(@spawn(fun() => @castptr(p)))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(matches!(
+        tych.infer_expr(&res),
+        Err(InferError::NotSend { .. })
+    ));
+}
+
+#[test]
+fn type_check_channel_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let c = @channew(2);
+let u = @chansend(c, (1, true));
+let v = @chanclose(c);
+(c, u)
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Chan[(Int, Bool)], ())");
+
+    // a channel isn't generalized, it carries one type of values
+    let string = r#"
+let c = @channew(2);
+let u = @chansend(c, 1);
+@chansend(c, true)
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+
+    // a `Ptr` can't be sent
+    let string = r#"
+let c = @channew(2);
+@chansend(c, @castptr(1))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let Err(InferError::ChanNotSend(span)) = tych.infer_expr(&res) else {
+        panic!("sending a pointer should fail!")
+    };
+    assert_eq!(&string[span.start.abs..span.end.abs], "@castptr(1)");
+}
+
+#[test]
+fn type_check_mutex_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let m = @mutexnew();
+let u = @mutexlock(m);
+let v = @mutexunlock(m);
+(m, @withmutex(m, fun() => (1, true)))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Mutex, (Int, Bool))");
+
+    // a mutex can be shared with a thread
+    let string = r#"
+let m = @mutexnew();
+@join(@spawn(fun() => @withmutex(m, fun() => 1)))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_ok());
+
+    // the closure takes no arguments
+    let string = "@withmutex(@mutexnew(), fun(x) => x)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_semaphore_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let s = @semnew(4);
+let u = @semwait(s);
+let v = @sempost(s);
+(s, @semtrywait(s))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Sem, Bool)");
+
+    // a semaphore can be shared with a thread
+    let string = r#"
+let s = @semnew(1);
+@join(@spawn(fun() => { let u = @semwait(s); @sempost(s) }))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_ok());
+
+    // the initial count is an integer
+    let string = "@semnew(true)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+}
+
+#[test]
+fn type_check_bitcast_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+let bits = @bitcast[Real, Int](1.5);
+@bitcast[Int, Real](@iadd(bits, 1))
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    // printed result can be parsed again
+    let text = format!("{expr}");
+    assert!(text.contains("@bitcast[Real, Int](1.5)"));
+    let mut par = Parser::new(&text);
+    assert_eq!(format!("{}", parse_expr(&mut par).unwrap()), text);
+
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "Real");
+
+    // the argument must have the source type
+    let string = "@bitcast[Int, Real](true)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(tych.infer_expr(&res).is_err());
+
+    // both types must have the same size
+    let string = "@bitcast[Int, Bool](1)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    assert!(matches!(
+        tych.infer_expr(&res),
+        Err(InferError::BitcastSizeMismatch(_))
+    ));
+}
+
+#[test]
+fn type_check_alloc_aligned_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = "let n = 256; (@allocaligned(n, 64), @allocaligned(8, n))";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let ty = tych.infer_expr(&res).unwrap();
+    assert_eq!(format!("{ty}"), "(Ptr, Ptr)");
+
+    for string in ["@allocaligned(64, 48)", "@allocaligned(64, 0)"] {
+        let mut par = Parser::new(string);
+        let expr = parse_expr(&mut par).unwrap();
+        let res = rnm.visit_expr(expr);
+        let mut tych = Infer::new();
+        assert!(matches!(
+            tych.infer_expr(&res),
+            Err(InferError::AlignmentNotPowerOfTwo(_))
+        ));
+    }
+}
+
+#[test]
+fn type_localize_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let slice = |string: &str, span: Span| string[span.start.abs..span.end.abs].to_string();
+    // `g` is checked first, so the mismatch shows up at the use of `c` in `f`
+    let string = r#"
+begin
+    fun g() => f(1, 2, true, 4, 5)
+    fun f(a, b, c, d, e) => @iadd(a, @iadd(b, @iadd(c, @iadd(d, e))))
+    fun h() => f(1, 2, 3, 4, 5)
+in
+    0
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    let mut tych = Infer::new();
+    let Err(InferError::Mismatch { found, .. }) = tych.infer_expr(&res) else {
+        panic!("expected a mismatch")
+    };
+    assert_eq!(slice(string, found), "c");
+    let blame = localize(&res).unwrap();
+    assert_eq!(slice(string, blame.culprit), "true");
+    let alternatives: Vec<_> = blame
+        .alternatives
+        .iter()
+        .map(|span| slice(string, *span))
+        .collect();
+    assert_eq!(alternatives, vec!["f"]);
+
+    // `f` is generalized before the continuation of the block is checked, so a bad call
+    // there is blamed, not the body of `f`
+    let program = |ret: &str| {
+        format!(
+            r#"
+begin
+    fun f(a: Int, b, c, d, e){ret} => @iadd(a, @iadd(b, @iadd(c, @iadd(d, e))))
+    fun g(x) => f(x, 2, 3, 4, 5)
+in
+    f(1, 2, true, 4, 5)
+end
+"#
+        )
+    };
+    let blame_in = |string: &str| {
+        let mut par = Parser::new(string);
+        let expr = parse_expr(&mut par).unwrap();
+        let res = Renamer::new().visit_expr(expr);
+        localize(&res).unwrap()
+    };
+    let string = program("");
+    let blame = blame_in(&string);
+    assert_eq!(slice(&string, blame.culprit), "true");
+    assert_eq!(slice(&string, blame.alternatives[0]), "f");
+    // a mismatch in the declarations is blamed there, without the continuation
+    let string = program(": Bool");
+    let blame = blame_in(&string);
+    assert_eq!(
+        slice(&string, blame.culprit),
+        "@iadd(a, @iadd(b, @iadd(c, @iadd(d, e))))"
+    );
+
+    // nothing to blame
+    for string in ["@iadd(1, 2)", "@iadd(1, y)"] {
+        let mut par = Parser::new(string);
+        let expr = parse_expr(&mut par).unwrap();
+        let res = rnm.visit_expr(expr);
+        assert_eq!(localize(&res), None);
+    }
+
+    // too large to try every expression
+    let sum = (0..LOCALIZE_LIMIT).map(|i| i.to_string()).join(", ");
+    let string = format!("begin fun f() => ({sum}, @iadd(1, true)) in f end");
+    let mut par = Parser::new(&string);
+    let expr = parse_expr(&mut par).unwrap();
+    let res = rnm.visit_expr(expr);
+    assert_eq!(localize(&res), None);
+}
+
+#[test]
+fn type_env_test() {
+    use super::parser::*;
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    extern print_int : fun(Int) -> ();
+    fun id(x) => x
+    fun bad(x) => @iadd(x, true)
+    fun twice(f, x) => f(f(x))
+    fun uses_bad(y) => @iadd(bad(y), 1)
+in
+    #print_int(twice(id, 1))
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let Expr::Blk { decls, .. } = rnm.visit_expr(expr) else {
+        panic!("expected a block");
+    };
+    let show = |ty: Option<Scheme>| diagnostic::normalize_internals(&ty.unwrap().diag());
+    let mut env = TypeEnv::new();
+    let ty = env.check_decl(&decls[0]).unwrap();
+    assert_eq!(show(ty), "fun(Int) -> ()");
+    let ty = env.check_decl(&decls[1]).unwrap();
+    assert_eq!(show(ty), "fun(a) -> a");
+    assert!(env.check_decl(&decls[2]).unwrap_err().is_error());
+    // later declarations see the earlier ones, each instantiated anew
+    let ty = env.check_decl(&decls[3]).unwrap();
+    assert_eq!(show(ty), "fun(fun(a) -> a, a) -> a");
+    // a function that failed to check can still be used, as if it had any type
+    let ty = env.check_decl(&decls[4]).unwrap();
+    assert_eq!(show(ty), "fun(a) -> Int");
+
+    let id = decls[1].get_name();
+    assert_eq!(show(env.lookup(id).cloned()), "fun(a) -> a");
+    let print_int = decls[0].get_name();
+    assert!(env.lookup(print_int).is_some());
+    let undeclared = Ident::from(InternStr::new("undeclared"));
+    assert!(env.lookup(undeclared).is_none());
 }
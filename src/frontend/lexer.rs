@@ -1,5 +1,9 @@
+use super::diagnostic::Diagnostic;
 use super::*;
+use crate::utils::source_map::FileId;
+use std::collections::VecDeque;
 use std::fmt;
+use std::io::{self, Read};
 use std::str::Chars;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -18,12 +22,16 @@ pub enum TokenKind {
     RBrace,
     /// ":"
     Colon,
+    /// "::"
+    ColonColon,
     /// ";"
     Semi,
     /// ","
     Comma,
     /// "."
     Dot,
+    /// "...", marking a variadic extern
+    Ellipsis,
     /// "|"
     Bar,
     /// "="
@@ -34,10 +42,15 @@ pub enum TokenKind {
     EArrow,
     /// "#"
     Hash,
+    /// "\" not followed by an operator character, starting a lambda like `\x y -> e`
+    Backslash,
+    /// "@" not followed by a builtin name, as in the pattern `xs @ Cons(x, _)`
+    At,
     /// "fun"
     Fun,
     /// "let"
     Let,
+    LetRec,
     /// "begin"
     Begin,
     /// "in"
@@ -50,10 +63,26 @@ pub enum TokenKind {
     Of,
     /// "data"
     Data,
+    /// "with"
+    With,
     /// "type"
     Type,
     /// "extern"
     Extern,
+    /// "infixl"
+    Infixl,
+    /// "infixr"
+    Infixr,
+    /// "forall"
+    Forall,
+    /// "module"
+    Module,
+    /// "import"
+    Import,
+    /// "try"
+    Try,
+    /// "catch"
+    Catch,
     /// "if"
     If,
     /// "then"
@@ -68,16 +97,22 @@ pub enum TokenKind {
     LitBool,
     /// literal value `Char`
     LitChar,
+    /// literal value `Str`, like `"hello\n"`
+    LitStr,
     /// literal type `Int`
     TyInt,
     /// literal type `Real`
     TyReal,
     /// literal type `Bool`
     TyBool,
+    /// literal type `Str`
+    TyStr,
     /// literal type `Char`
     TyChar,
     /// builtin primitives
     Builtin,
+    /// typed hole "?name", standing for an expression not written yet
+    Hole,
     /// identifier(lowercase)
     LowerIdent,
     /// identifier(uppercase)
@@ -90,6 +125,8 @@ pub enum TokenKind {
     FailedToken,
     // failed block comments
     FailedBlockComment,
+    // unclosed string literals, or with an unknown escape sequence
+    FailedString,
 
     // following token kinds should be use only inside lexer
     // they are not intended to be exposed to parser
@@ -104,21 +141,34 @@ pub enum TokenKind {
 
 impl TokenKind {
     pub fn is_bad_token(&self) -> bool {
-        match self {
+        matches!(
+            self,
             TokenKind::FailedToken
-            | TokenKind::FailedBlockComment
-            | TokenKind::LineComment
-            | TokenKind::BlockComment
-            | TokenKind::EndOfFile => true,
-            _ => false,
-        }
+                | TokenKind::FailedBlockComment
+                | TokenKind::FailedString
+                | TokenKind::LineComment
+                | TokenKind::BlockComment
+                | TokenKind::EndOfFile
+        )
     }
 }
 
-#[derive(Clone, Copy)]
+/// a comment kept by the lexer in `LexMode::PreserveTrivia`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub span: Span,
+    pub text: String,
+}
+
+#[derive(Clone)]
 pub struct Token {
     pub kind: TokenKind,
     pub span: Span,
+    /// comments right before this token, always empty in `LexMode::Normal`
+    pub trivia: Vec<Comment>,
+    /// the content of a string literal, unescaped and interned,
+    /// so equal literals share a single string
+    pub lit: Option<InternStr>,
 }
 
 impl fmt::Debug for Token {
@@ -136,32 +186,44 @@ impl Spanned for Token {
     }
 }
 
+/// all keywords, none of them can be used as an identifier
+pub const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("fun", TokenKind::Fun),
+    ("let", TokenKind::Let),
+    ("letrec", TokenKind::LetRec),
+    ("begin", TokenKind::Begin),
+    ("in", TokenKind::In),
+    ("end", TokenKind::End),
+    ("case", TokenKind::Case),
+    ("of", TokenKind::Of),
+    ("if", TokenKind::If),
+    ("then", TokenKind::Then),
+    ("else", TokenKind::Else),
+    ("data", TokenKind::Data),
+    ("with", TokenKind::With),
+    ("type", TokenKind::Type),
+    ("extern", TokenKind::Extern),
+    ("infixl", TokenKind::Infixl),
+    ("infixr", TokenKind::Infixr),
+    ("forall", TokenKind::Forall),
+    ("module", TokenKind::Module),
+    ("import", TokenKind::Import),
+    ("try", TokenKind::Try),
+    ("catch", TokenKind::Catch),
+    ("true", TokenKind::LitBool),
+    ("false", TokenKind::LitBool),
+    ("Int", TokenKind::TyInt),
+    ("Real", TokenKind::TyReal),
+    ("Bool", TokenKind::TyBool),
+    ("Char", TokenKind::TyChar),
+    ("Str", TokenKind::TyStr),
+];
+
 pub fn as_keyword(str: &str) -> Option<TokenKind> {
-    let tok = match str {
-        "fun" => TokenKind::Fun,
-        "let" => TokenKind::Let,
-        "begin" => TokenKind::Begin,
-        "in" => TokenKind::In,
-        "end" => TokenKind::End,
-        "case" => TokenKind::Case,
-        "of" => TokenKind::Of,
-        "if" => TokenKind::If,
-        "then" => TokenKind::Then,
-        "else" => TokenKind::Else,
-        "data" => TokenKind::Data,
-        "type" => TokenKind::Type,
-        "extern" => TokenKind::Extern,
-        "true" => TokenKind::LitBool,
-        "false" => TokenKind::LitBool,
-        "Int" => TokenKind::TyInt,
-        "Real" => TokenKind::TyReal,
-        "Bool" => TokenKind::TyBool,
-        "Char" => TokenKind::TyChar,
-        _ => {
-            return None;
-        }
-    };
-    Some(tok)
+    KEYWORDS
+        .iter()
+        .find(|(keyword, _)| *keyword == str)
+        .map(|(_, tok)| *tok)
 }
 
 pub fn is_ident_first(ch: char) -> bool {
@@ -172,33 +234,115 @@ pub fn is_ident_body(ch: char) -> bool {
     ch.is_ascii_alphanumeric() || ch == '-' || ch == '_'
 }
 
-pub fn is_opr_char(ch: char) -> bool {
+/// could `ch` be the first character of some token (or comment)?
+pub fn is_token_first(ch: char) -> bool {
     match ch {
-        ':' | '!' | '#' | '$' | '%' | '&' | '*' | '+' | '.' | '/' | '<' | '=' | '>' | '?' | '@'
-        | '\\' | '^' | '|' | '-' | '~' => true,
-        _ => false,
+        '(' | ')' | '[' | ']' | '{' | '}' | ';' | ',' | '_' => true,
+        _ => is_opr_char(ch) || is_ident_first(ch) || ch.is_ascii_digit(),
     }
 }
 
+pub fn is_opr_char(ch: char) -> bool {
+    matches!(
+        ch,
+        ':' | '!'
+            | '#'
+            | '$'
+            | '%'
+            | '&'
+            | '*'
+            | '+'
+            | '.'
+            | '/'
+            | '<'
+            | '='
+            | '>'
+            | '?'
+            | '@'
+            | '\\'
+            | '^'
+            | '|'
+            | '-'
+            | '~'
+    )
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LexMode {
+    /// comments are discarded
+    Normal,
+    /// comments are attached to the following token, and comments at the end of file
+    /// are attached to a final `EndOfFile` token
+    PreserveTrivia,
+}
+
 pub struct Lexer<'src> {
     source: &'src str,
     chars: Chars<'src>,
     row: usize,
     col: usize,
     abs: usize,
+    mode: LexMode,
+    file: FileId,
+    trivia: Vec<Comment>,
+    // the content of the string literal just lexed
+    lit: Option<InternStr>,
+    // problems that don't make a bad token, like a bare '\r'
+    warnings: Vec<Diagnostic>,
+    finished: bool,
 }
 
+/// the UTF-8 byte-order mark, which some editors put at the start of a file
+pub const BOM: char = '\u{feff}';
+
 impl<'src> Lexer<'src> {
     pub fn new(s: &'src str) -> Self {
+        Lexer::with_mode(s, LexMode::Normal)
+    }
+
+    pub fn with_mode(s: &'src str, mode: LexMode) -> Self {
+        let mut lexer = Lexer::continuing(s, mode);
+        // a leading byte-order mark is skipped, it moves the offsets but not the columns
+        if lexer.peek_first() == Some(BOM) {
+            lexer.chars.next();
+            lexer.abs += BOM.len_utf8();
+        }
+        lexer
+    }
+
+    /// Lex text from the middle of a file, where a byte-order mark is just a bad character
+    fn continuing(s: &'src str, mode: LexMode) -> Self {
         Lexer {
             source: s,
             chars: s.chars(),
             row: 0,
             col: 0,
             abs: 0,
+            mode,
+            file: FileId::default(),
+            trivia: Vec::new(),
+            lit: None,
+            warnings: Vec::new(),
+            finished: false,
         }
     }
 
+    /// The warnings found so far, they don't stop the compilation
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Mark every span with `file`
+    pub fn with_file(mut self, file: FileId) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// Lex from a reader, which is read incrementally as tokens are requested
+    pub fn from_reader<R: Read>(reader: R) -> ReaderLexer<R> {
+        ReaderLexer::new(reader)
+    }
+
     fn peek_first(&self) -> Option<char> {
         self.chars.clone().next()
     }
@@ -209,14 +353,34 @@ impl<'src> Lexer<'src> {
         chars.next()
     }
 
+    fn peek_third(&self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next();
+        chars.next()
+    }
+
     fn next_char(&mut self) -> Option<char> {
         let ch = self.chars.next()?;
         self.abs += ch.len_utf8();
         if ch == '\n' {
             self.row += 1;
             self.col = 0;
+        } else if ch == '\r' && self.peek_first() == Some('\n') {
+            // "\r\n" is a single line break, the column is reset by '\n'
+        } else if ch == '\r' {
+            // a bare '\r' isn't a line break, but editors might show it as one
+            let start = Position::new(self.row, self.col, self.abs - 1);
+            self.col += 1;
+            let span = Span::new(start, self.get_pos()).in_file(self.file);
+            let diag = Diagnostic::warn("bare carriage return").line_span(
+                span,
+                "this '\\r' isn't followed by '\\n', so it doesn't end the line",
+            );
+            self.warnings.push(diag);
         } else {
-            self.col += ch.len_utf8();
+            // columns count characters, not bytes
+            self.col += 1;
         }
         Some(ch)
     }
@@ -257,23 +421,40 @@ impl<'src> Lexer<'src> {
     }
 
     pub fn next_token(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
         loop {
             self.skip_whitespace();
             let start = self.get_pos();
             let kind = self.next_token_kind();
+            let end = self.get_pos();
+            let span = Span::new(start, end).in_file(self.file);
             match kind {
                 TokenKind::LineComment | TokenKind::BlockComment => {
-                    // ignore comments, they will never be exposed to parser
+                    // comments will never be exposed to parser
+                    if self.mode == LexMode::PreserveTrivia {
+                        let text = self.get_slice(start.abs, end.abs).to_string();
+                        self.trivia.push(Comment { span, text });
+                    }
                     continue;
                 }
                 TokenKind::EndOfFile => {
-                    return None;
+                    self.finished = true;
+                    if self.mode == LexMode::Normal {
+                        return None;
+                    }
                 }
                 _ => {}
             }
-            let end = self.get_pos();
-            let span = Span::new(start, end);
-            return Some(Token { kind, span });
+            let trivia = std::mem::take(&mut self.trivia);
+            let lit = self.lit.take();
+            return Some(Token {
+                kind,
+                span,
+                trivia,
+                lit,
+            });
         }
     }
 
@@ -305,7 +486,12 @@ impl<'src> Lexer<'src> {
             }
             Some(':') => {
                 self.next_char();
-                TokenKind::Colon
+                if self.peek_first() == Some(':') {
+                    self.next_char();
+                    TokenKind::ColonColon
+                } else {
+                    TokenKind::Colon
+                }
             }
             Some(';') => {
                 self.next_char();
@@ -317,8 +503,16 @@ impl<'src> Lexer<'src> {
             }
             Some('.') => {
                 self.next_char();
-                TokenKind::Dot
+                if self.peek_first() == Some('.') && self.peek_second() == Some('.') {
+                    self.next_char();
+                    self.next_char();
+                    TokenKind::Ellipsis
+                } else {
+                    TokenKind::Dot
+                }
             }
+            // the pipeline operator, `|` is the bar of a rule otherwise
+            Some('|') if self.peek_second() == Some('>') => self.operator(),
             Some('|') => {
                 self.next_char();
                 TokenKind::Bar
@@ -327,12 +521,18 @@ impl<'src> Lexer<'src> {
                 self.next_char();
                 TokenKind::Hash
             }
+            Some('\\') if !self.peek_second().is_some_and(is_opr_char) => {
+                self.next_char();
+                TokenKind::Backslash
+            }
             Some('=') => match self.peek_second() {
                 Some('>') => {
                     self.next_char();
                     self.next_char();
                     TokenKind::EArrow
                 }
+                // operators like "=="
+                Some(ch) if is_opr_char(ch) => self.operator(),
                 _ => {
                     self.next_char();
                     TokenKind::Equal
@@ -361,6 +561,8 @@ impl<'src> Lexer<'src> {
                 _ => self.operator(),
             },
             Some('@') => self.builtin(),
+            Some('?') => self.hole(),
+            Some('"') => self.string(),
             Some('_') => self.wildcard(),
             Some(ch) if is_opr_char(ch) => self.operator(),
             Some(ch) if is_ident_first(ch) => self.ident_or_keyword(),
@@ -416,7 +618,7 @@ impl<'src> Lexer<'src> {
         let start = self.get_abs();
         let ch1 = self.next_char().unwrap();
         assert!(is_ident_first(ch1));
-        self.skip_while(|ch| is_ident_body(ch));
+        self.skip_while(is_ident_body);
         let end = self.get_abs();
         let slice = self.get_slice(start, end);
         if let Some(kwd) = as_keyword(slice) {
@@ -430,6 +632,40 @@ impl<'src> Lexer<'src> {
         }
     }
 
+    fn string(&mut self) -> TokenKind {
+        let ch1 = self.next_char();
+        assert_eq!(ch1, Some('"'));
+        let mut text = String::new();
+        let mut valid = true;
+        loop {
+            // the line break isn't part of a failed string, so its span stays on one line
+            if matches!(self.peek_first(), Some('\n') | None) {
+                return TokenKind::FailedString;
+            }
+            match self.next_char() {
+                Some('"') => break,
+                Some('\\') => match self.peek_first() {
+                    Some('\n') | None => return TokenKind::FailedString,
+                    _ => match self.next_char() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some('\\') => text.push('\\'),
+                        Some('"') => text.push('"'),
+                        // keep going, so the rest of the literal isn't lexed as code
+                        _ => valid = false,
+                    },
+                },
+                Some(ch) => text.push(ch),
+                None => unreachable!(),
+            }
+        }
+        if !valid {
+            return TokenKind::FailedString;
+        }
+        self.lit = Some(InternStr::new(text));
+        TokenKind::LitStr
+    }
+
     fn wildcard(&mut self) -> TokenKind {
         let ch1 = self.next_char();
         assert_eq!(ch1, Some('_'));
@@ -440,10 +676,25 @@ impl<'src> Lexer<'src> {
     fn builtin(&mut self) -> TokenKind {
         let ch1 = self.next_char();
         assert_eq!(ch1, Some('@'));
-        self.skip_while(|ch| ch.is_alphanumeric());
+        // builtins are lowercase, so `xs@Cons(x, _)` is an alias
+        if !self.peek_first().is_some_and(|ch| ch.is_ascii_lowercase()) {
+            return TokenKind::At;
+        }
+        // attributes like `@deprecated_since` are lexed as builtins too
+        self.skip_while(|ch| ch.is_alphanumeric() || ch == '_');
         TokenKind::Builtin
     }
 
+    fn hole(&mut self) -> TokenKind {
+        // holes are named in lowercase, otherwise it is an operator like `?>`
+        if !self.peek_second().is_some_and(|ch| ch.is_ascii_lowercase()) {
+            return self.operator();
+        }
+        self.next_char();
+        self.skip_while(is_ident_body);
+        TokenKind::Hole
+    }
+
     fn operator(&mut self) -> TokenKind {
         let len = self.skip_while(is_opr_char);
         assert_ne!(len, 0);
@@ -454,26 +705,69 @@ impl<'src> Lexer<'src> {
         // note: we don't accept forms like "3." or ".14"
         let len = self.skip_while(|ch| ch.is_ascii_digit());
         assert_ne!(len, 0);
-        if self.peek_first() != Some('.') {
-            TokenKind::LitInt
-        } else {
+        let mut kind = TokenKind::LitInt;
+        if self.peek_first() == Some('.') {
             self.next_char();
             let len = self.skip_while(|ch| ch.is_ascii_digit());
             if len == 0 {
-                TokenKind::FailedToken
-            } else {
-                TokenKind::LitReal
+                return TokenKind::FailedToken;
             }
+            kind = TokenKind::LitReal;
+        }
+        if self.is_exponent_start() {
+            // exponent part, like "e10", "E+10" or "e-10"
+            self.next_char();
+            if let Some('+') | Some('-') = self.peek_first() {
+                self.next_char();
+            }
+            self.skip_while(|ch| ch.is_ascii_digit());
+            kind = TokenKind::LitReal;
+        }
+        kind
+    }
+
+    fn is_exponent_start(&self) -> bool {
+        match (self.peek_first(), self.peek_second(), self.peek_third()) {
+            (Some('e' | 'E'), Some(ch), _) if ch.is_ascii_digit() => true,
+            (Some('e' | 'E'), Some('+' | '-'), Some(ch)) if ch.is_ascii_digit() => true,
+            _ => false,
         }
     }
 
     fn failed_token(&mut self) -> TokenKind {
-        // ignore all char until a whitespace
-        self.skip_while(|ch| !ch.is_whitespace());
+        // skip only the unrecognized characters, lexing continues right after them
+        self.skip_while(|ch| !ch.is_whitespace() && !is_token_first(ch));
         TokenKind::FailedToken
     }
 }
 
+/// Report every bad token in the token stream, so all lexical errors are shown at once
+pub fn lexer_diagnostics(tokens: &[Token]) -> Vec<Diagnostic> {
+    tokens
+        .iter()
+        .filter_map(|tok| match tok.kind {
+            TokenKind::FailedToken => {
+                let diag =
+                    Diagnostic::error("lexer error").line_span(tok.span, "unrecognized token");
+                Some(diag)
+            }
+            TokenKind::FailedBlockComment => {
+                let diag =
+                    Diagnostic::error("lexer error").line_span(tok.span, "unclosed block comment");
+                Some(diag)
+            }
+            TokenKind::FailedString => {
+                let diag = Diagnostic::error("lexer error").line_span(
+                    tok.span,
+                    "unclosed string literal, or unknown escape sequence",
+                );
+                Some(diag)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
 impl<'src> Iterator for Lexer<'src> {
     type Item = Token;
     fn next(&mut self) -> Option<Self::Item> {
@@ -481,6 +775,149 @@ impl<'src> Iterator for Lexer<'src> {
     }
 }
 
+/// bytes requested from the reader at a time
+const CHUNK_SIZE: usize = 4096;
+
+/// how many characters the lexer may peek past the end of a token
+const LOOKAHEAD: usize = 3;
+
+/// A lexer over an `io::Read` source, see `Lexer::from_reader`.
+/// Spans are absolute positions in the whole stream, exactly as if the input was lexed at once.
+pub struct ReaderLexer<R> {
+    reader: R,
+    /// text read but not tokenized yet
+    buf: String,
+    /// position of the beginning of `buf` in the stream
+    base: Position,
+    /// bytes of an incomplete utf-8 sequence at the end of the last chunk
+    partial: Vec<u8>,
+    tokens: VecDeque<Token>,
+    eof: bool,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> ReaderLexer<R> {
+    fn new(reader: R) -> Self {
+        ReaderLexer {
+            reader,
+            buf: String::new(),
+            base: Position::new(0, 0, 0),
+            partial: Vec::new(),
+            tokens: VecDeque::new(),
+            eof: false,
+            error: None,
+        }
+    }
+
+    /// The error that stopped reading, if any. The input before it is still tokenized.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn fill(&mut self) {
+        let mut chunk = [0; CHUNK_SIZE];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => {
+                    if !self.partial.is_empty() {
+                        self.partial.clear();
+                        self.buf.push(char::REPLACEMENT_CHARACTER);
+                    }
+                    self.eof = true;
+                }
+                Ok(n) => {
+                    self.partial.extend_from_slice(&chunk[..n]);
+                    self.decode();
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.error = Some(err);
+                    self.eof = true;
+                }
+            }
+            return;
+        }
+    }
+
+    fn decode(&mut self) {
+        loop {
+            match std::str::from_utf8(&self.partial) {
+                Ok(str) => {
+                    self.buf.push_str(str);
+                    self.partial.clear();
+                    return;
+                }
+                Err(err) => {
+                    let valid = err.valid_up_to();
+                    self.buf
+                        .push_str(std::str::from_utf8(&self.partial[..valid]).unwrap());
+                    match err.error_len() {
+                        // the sequence is cut by the chunk boundary, wait for the rest of it
+                        None => {
+                            self.partial.drain(..valid);
+                            return;
+                        }
+                        Some(len) => {
+                            self.buf.push(char::REPLACEMENT_CHARACTER);
+                            self.partial.drain(..valid + len);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tokenize the buffer. Before the end of input, a token is taken only if enough
+    /// characters follow it, since more input could still extend or change it.
+    fn lex(&mut self) {
+        let base = self.base;
+        let shift = |pos: Position| {
+            if pos.row == 0 {
+                Position::new(base.row, base.col + pos.col, base.abs + pos.abs)
+            } else {
+                Position::new(base.row + pos.row, pos.col, base.abs + pos.abs)
+            }
+        };
+        let mut lexer = if base.abs == 0 {
+            Lexer::new(&self.buf)
+        } else {
+            Lexer::continuing(&self.buf, LexMode::Normal)
+        };
+        let mut consumed = None;
+        while let Some(tok) = lexer.next_token() {
+            let Span { start, end, .. } = tok.span;
+            if !self.eof && self.buf[end.abs..].chars().nth(LOOKAHEAD).is_none() {
+                break;
+            }
+            let span = Span::new(shift(start), shift(end)).in_file(tok.span.file);
+            self.tokens.push_back(Token { span, ..tok });
+            consumed = Some(end);
+        }
+        if self.eof {
+            self.buf.clear();
+        } else if let Some(end) = consumed {
+            self.base = shift(end);
+            self.buf.drain(..end.abs);
+        }
+    }
+}
+
+impl<R: Read> Iterator for ReaderLexer<R> {
+    type Item = Token;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(tok) = self.tokens.pop_front() {
+                return Some(tok);
+            }
+            if self.eof {
+                return None;
+            }
+            self.fill();
+            self.lex();
+        }
+    }
+}
+
 #[test]
 fn lexer_test() {
     let string = r#"
@@ -513,3 +950,293 @@ end
         assert!(!tok.kind.is_bad_token());
     });
 }
+
+#[test]
+fn lexer_number_test() {
+    let string = "1 3.14 1e5 1.5e-3 2E10 6.02e+23";
+    let kinds: Vec<TokenKind> = Lexer::new(string).map(|tok| tok.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::LitInt,
+            TokenKind::LitReal,
+            TokenKind::LitReal,
+            TokenKind::LitReal,
+            TokenKind::LitReal,
+            TokenKind::LitReal,
+        ]
+    );
+
+    // "e" without digits is not an exponent
+    let string = "1e";
+    let kinds: Vec<TokenKind> = Lexer::new(string).map(|tok| tok.kind).collect();
+    assert_eq!(kinds, vec![TokenKind::LitInt, TokenKind::LowerIdent]);
+}
+
+#[test]
+fn lexer_string_test() {
+    let string = r#""a\tb" "a\tb" "" "say \"hi\"\n""#;
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    assert!(tokens.iter().all(|tok| tok.kind == TokenKind::LitStr));
+    let lits: Vec<InternStr> = tokens.iter().map(|tok| tok.lit.unwrap()).collect();
+    assert_eq!(lits[0].as_str(), "a\tb");
+    assert_eq!(lits[0], lits[1]);
+    assert_eq!(lits[2].as_str(), "");
+    assert_eq!(lits[3].as_str(), "say \"hi\"\n");
+
+    // a string can't span lines, or use an unknown escape
+    for string in ["\"abc\ndef", "\"abc", "\"a\\qb\""] {
+        let tokens: Vec<Token> = Lexer::new(string).collect();
+        assert_eq!(tokens[0].kind, TokenKind::FailedString);
+        assert_eq!(lexer_diagnostics(&tokens).len(), 1);
+    }
+}
+
+#[test]
+fn lexer_string_report_test() {
+    // the span of an unclosed string stops before the line break
+    let string = "let s = \"abc\nin s";
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    let diags = lexer_diagnostics(&tokens);
+    assert_eq!(
+        diags[0].report(string, 10),
+        r#"[Error]: lexer error
+1 | let s = "abc
+  |         ^~~~
+unclosed string literal, or unknown escape sequence
+"#
+    );
+
+    // at the end of the file
+    for string in ["let s = \"abc", "let s = \"abc\n", "let s = \"abc\\"] {
+        let tokens: Vec<Token> = Lexer::new(string).collect();
+        let diags = lexer_diagnostics(&tokens);
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0].report(string, 10).contains("1 | let s = \"abc"));
+    }
+}
+
+#[test]
+fn lexer_comment_report_test() {
+    // an unclosed block comment runs to the end of the file
+    let string = "1 /* abc\n";
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    let diags = lexer_diagnostics(&tokens);
+    assert_eq!(
+        diags[0].report(string, 10),
+        r#"[Error]: lexer error
+1 | 1 /* abc
+  |   ^~~~~~
+unclosed block comment
+"#
+    );
+
+    let string = "1 /* abc\ndef\n\n";
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    let diags = lexer_diagnostics(&tokens);
+    assert_eq!(
+        diags[0].report(string, 10),
+        r#"[Error]: lexer error
+1 | 1 /* abc
+  |   ^~~~~~
+2 | def
+  | ~~^
+unclosed block comment
+"#
+    );
+}
+
+#[test]
+fn lexer_at_test() {
+    // `@` only starts a builtin when a lowercase name follows
+    let string = "@iadd xs@Cons xs @ _";
+    let kinds: Vec<TokenKind> = Lexer::new(string).map(|tok| tok.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            TokenKind::Builtin,
+            TokenKind::LowerIdent,
+            TokenKind::At,
+            TokenKind::UpperIdent,
+            TokenKind::LowerIdent,
+            TokenKind::At,
+            TokenKind::Wild,
+        ]
+    );
+}
+
+#[test]
+fn lexer_hole_test() {
+    // `?` only starts a hole when a lowercase name follows
+    let string = "?goal ?x1 x ?> y ?Foo";
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    let kinds: Vec<TokenKind> = tokens.iter().map(|tok| tok.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            TokenKind::Hole,
+            TokenKind::Hole,
+            TokenKind::LowerIdent,
+            TokenKind::Oper,
+            TokenKind::LowerIdent,
+            TokenKind::Oper,
+            TokenKind::UpperIdent,
+        ]
+    );
+    let spans: Vec<(usize, usize)> = tokens
+        .iter()
+        .map(|tok| (tok.span.start.col, tok.span.end.col))
+        .take(2)
+        .collect();
+    assert_eq!(spans, vec![(0, 5), (6, 9)]);
+}
+
+#[test]
+fn lexer_error_recovery_test() {
+    let string = "let x = `1;\nlet y = @iadd(x,'2);\ny`";
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    let diags = lexer_diagnostics(&tokens);
+    assert_eq!(diags.len(), 3);
+    let spans: Vec<(usize, usize, usize)> = tokens
+        .iter()
+        .filter(|tok| tok.kind == TokenKind::FailedToken)
+        .map(|tok| (tok.span.start.row, tok.span.start.col, tok.span.end.col))
+        .collect();
+    assert_eq!(spans, vec![(0, 8, 9), (1, 16, 17), (2, 1, 2)]);
+    // lexing goes on right after the bad characters
+    assert_eq!(tokens[4].kind, TokenKind::LitInt);
+}
+
+#[test]
+fn lexer_trivia_test() {
+    let string = r#"// leading
+/* block */ let x = 1; // trailing
+x
+// end of file
+"#;
+    let tokens: Vec<Token> = Lexer::with_mode(string, LexMode::PreserveTrivia).collect();
+    let trivia: Vec<(TokenKind, Vec<&str>)> = tokens
+        .iter()
+        .filter(|tok| !tok.trivia.is_empty())
+        .map(|tok| {
+            let texts = tok.trivia.iter().map(|cmt| cmt.text.as_str()).collect();
+            (tok.kind, texts)
+        })
+        .collect();
+    assert_eq!(
+        trivia,
+        vec![
+            (TokenKind::Let, vec!["// leading\n", "/* block */"]),
+            (TokenKind::LowerIdent, vec!["// trailing\n"]),
+            (TokenKind::EndOfFile, vec!["// end of file\n"]),
+        ]
+    );
+    let span = tokens[0].trivia[1].span;
+    assert_eq!((span.start.row, span.start.col, span.end.col), (1, 0, 11));
+
+    // comments are dropped in normal mode, and there is no `EndOfFile` token
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    assert!(tokens.iter().all(|tok| tok.trivia.is_empty()));
+    assert_eq!(tokens.last().unwrap().kind, TokenKind::LowerIdent);
+}
+
+#[test]
+fn lexer_unicode_position_test() {
+    let string = "/* λλ */ x\r\n// 注释\r\ny `";
+    let tokens: Vec<Token> = Lexer::new(string).collect();
+    let spans: Vec<(usize, usize, usize, usize)> = tokens
+        .iter()
+        .map(|tok| {
+            let Span { start, end, .. } = tok.span;
+            (start.row, start.col, end.col, start.abs)
+        })
+        .collect();
+    assert_eq!(spans, vec![(0, 9, 10, 11), (2, 0, 1, 25), (2, 2, 3, 27)]);
+
+    // the caret is placed under the right character
+    let diags = lexer_diagnostics(&tokens);
+    assert_eq!(
+        diags[0].report(string, 10),
+        "[Error]: lexer error\n3 | y `\n  |   ^\nunrecognized token\n"
+    );
+}
+
+#[test]
+fn lexer_line_ending_test() {
+    // the byte-order mark is skipped, offsets stay byte-accurate
+    let string = "\u{feff}begin x\r\ny\rz end";
+    let mut lexer = Lexer::new(string);
+    let tokens: Vec<Token> = lexer.by_ref().collect();
+    let spans: Vec<(TokenKind, usize, usize, usize)> = tokens
+        .iter()
+        .map(|tok| {
+            let Span { start, .. } = tok.span;
+            (tok.kind, start.row, start.col, start.abs)
+        })
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            (TokenKind::Begin, 0, 0, 3),
+            (TokenKind::LowerIdent, 0, 6, 9),
+            (TokenKind::LowerIdent, 1, 0, 12),
+            (TokenKind::LowerIdent, 1, 2, 14),
+            (TokenKind::End, 1, 4, 16),
+        ]
+    );
+    assert_eq!(
+        &string[tokens[0].span.start.abs..tokens[0].span.end.abs],
+        "begin"
+    );
+
+    // the bare '\r' is warned about, the "\r\n" is not
+    let warnings = lexer.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        warnings[0].minimal_report(10),
+        "[Warn]: bare carriage return\nfrom line 2, col 2 to line 2, col 3:\nthis '\\r' isn't followed by '\\n', so it doesn't end the line\n"
+    );
+
+    // a byte-order mark anywhere else is a bad token
+    let kinds: Vec<TokenKind> = Lexer::new("x \u{feff}").map(|tok| tok.kind).collect();
+    assert_eq!(kinds, [TokenKind::LowerIdent, TokenKind::FailedToken]);
+}
+
+#[test]
+fn lexer_from_reader_test() {
+    /// returns at most `size` bytes for each read
+    struct Chunked<'a> {
+        data: &'a [u8],
+        size: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.size.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    let string =
+        "/* λλ /* nested */ */ let x-1 = 6.02e+23;\r\n// 注释\r\ny 1e ` @iadd(x,1)\n/* open";
+    let spans = |tokens: Vec<Token>| -> Vec<(TokenKind, usize, usize, usize, usize)> {
+        tokens
+            .into_iter()
+            .map(|tok| {
+                let Span { start, end, .. } = tok.span;
+                (tok.kind, start.row, start.col, end.col, end.abs)
+            })
+            .collect()
+    };
+    let expect = spans(Lexer::new(string).collect());
+    assert_eq!(expect.last().unwrap().0, TokenKind::FailedBlockComment);
+    for size in [1, 2, 3, 7, 4096] {
+        let reader = Chunked {
+            data: string.as_bytes(),
+            size,
+        };
+        assert_eq!(spans(Lexer::from_reader(reader).collect()), expect);
+    }
+}
@@ -8,4 +8,7 @@ pub mod lexer;
 pub mod parser;
 pub mod renamer;
 pub mod infer;
+pub mod exhaustiveness;
+pub mod ast_equiv;
+pub mod const_fold;
 pub mod diagnostic;
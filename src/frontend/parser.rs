@@ -1,10 +1,22 @@
-use super::lexer::{Lexer, Token, TokenKind};
+use super::lexer::{is_opr_char, Lexer, Token, TokenKind};
 use super::*;
+use crate::utils::source_map::FileId;
+use std::collections::HashMap;
 
 pub struct Parser<'src> {
     source: &'src str,
+    file: FileId,
     tokens: Vec<Token>,
     cursor: usize,
+    // placeholders `_` without an extent yet, see `parse_expr`
+    holes: Vec<(Ident, Span)>,
+    hole_count: usize,
+    // user-defined operators, with the span of their fixity declarations
+    fixities: HashMap<InternStr, (Fixity, Span)>,
+    // whether a broken declaration is skipped, see `parse_program`
+    recover: bool,
+    // the errors of the skipped declarations
+    errors: Vec<ParseError>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -13,6 +25,25 @@ pub enum ParseError {
     Unexpected(Span, TokenKind, TokenKind),
     UnexpectedMany(Span, TokenKind, &'static [TokenKind]),
     UnknownBuiltin(Span, InternStr),
+    UnknownAttribute(Span, InternStr),
+    UnknownCallConv(Span, InternStr),
+    UnknownOptLevel(Span, InternStr),
+    PolymorphicRecord(Span),
+    MisplacedAttribute(Span),
+    UnsupportedSimd(Span),
+    UnboundPlaceholder(Span),
+    /// a second fixity for an operator, with the span of the first one
+    /// or `None` for builtin operators
+    ConflictingFixity(Span, Option<Span>),
+    PrecedenceOutOfRange(Span),
+    /// an `if` expression without `else`, there is no default for the false branch
+    MissingElse(Span),
+    /// a clause with a different number of patterns than the first clause of its function,
+    /// with the number of the first clause and the number found
+    ClauseArity(Span, usize, usize),
+    /// a builtin expanded by the parser, called with another number of arguments than
+    /// it takes, with the number it takes and the number found
+    BuiltinArity(Span, usize, usize),
 }
 
 type ParseResult<T> = Result<T, ParseError>;
@@ -20,12 +51,31 @@ type ParseFunc<T> = fn(&mut Parser) -> ParseResult<T>;
 
 impl<'src> Parser<'src> {
     pub fn new(input: &'src str) -> Parser<'src> {
-        let lex = Lexer::new(input);
-        let tokens: Vec<Token> = lex.into_iter().collect();
+        Parser::with_file(input, FileId::default())
+    }
+
+    /// Parse `input` as the file `file` of a `SourceMap`
+    pub fn with_file(input: &'src str, file: FileId) -> Parser<'src> {
+        let lex = Lexer::new(input).with_file(file);
+        let mut tokens: Vec<Token> = lex.into_iter().collect();
+        // a sentinel token, so the cursor can move past the last real token
+        let end = tokens.last().map(|tok| tok.span.end).unwrap_or_default();
+        tokens.push(Token {
+            kind: TokenKind::EndOfFile,
+            span: Span::new(end, end).in_file(file),
+            trivia: Vec::new(),
+            lit: None,
+        });
         Parser {
             source: input,
-            tokens: tokens,
+            file,
+            tokens,
             cursor: 0,
+            holes: Vec::new(),
+            hole_count: 0,
+            fixities: HashMap::new(),
+            recover: false,
+            errors: Vec::new(),
         }
     }
 
@@ -45,6 +95,14 @@ impl<'src> Parser<'src> {
         }
     }
 
+    fn peek_third(&self) -> TokenKind {
+        if self.cursor < self.tokens.len() - 2 {
+            self.tokens[self.cursor + 2].kind
+        } else {
+            TokenKind::EndOfFile
+        }
+    }
+
     fn peek_span(&self) -> &Span {
         &self.tokens[self.cursor].span
     }
@@ -54,6 +112,11 @@ impl<'src> Parser<'src> {
         &self.source[span.start.abs..span.end.abs]
     }
 
+    fn peek_second_slice(&self) -> &'src str {
+        let span = &self.tokens[self.cursor + 1].span;
+        &self.source[span.start.abs..span.end.abs]
+    }
+
     fn start_pos(&self) -> Position {
         self.tokens[self.cursor].span.start
     }
@@ -62,6 +125,84 @@ impl<'src> Parser<'src> {
         self.tokens[self.cursor - 1].span.end
     }
 
+    /// the span from `start` to the end of the last consumed token
+    fn span_from(&self, start: Position) -> Span {
+        Span::new(start, self.end_pos()).in_file(self.file)
+    }
+
+    /// a fresh parameter for a placeholder, it can't clash with user names
+    /// since `_1` is not lexed as an identifier
+    fn hole(&mut self, span: Span) -> Expr {
+        let var = self.fresh_par();
+        self.holes.push((var, span));
+        Expr::Var { var, span }
+    }
+
+    /// a fresh parameter, numbered together with the placeholders
+    fn fresh_par(&mut self) -> Ident {
+        self.hole_count += 1;
+        Ident::from(InternStr::new(format!("_{}", self.hole_count)))
+    }
+
+    /// whether the next tokens start a clause `fun f(p1, ...) = e`,
+    /// of the function `name` if it is given
+    fn peek_clause(&self, name: Option<&str>) -> bool {
+        let kind = |k: usize| self.tokens.get(self.cursor + k).map(|tok| tok.kind);
+        if kind(0) != Some(TokenKind::Fun)
+            || kind(1) != Some(TokenKind::LowerIdent)
+            || kind(2) != Some(TokenKind::LParen)
+        {
+            return false;
+        }
+        if name.is_some_and(|name| name != self.peek_second_slice()) {
+            return false;
+        }
+        // the patterns end at the matching parenthesis, and a clause continues with `=`
+        let mut depth = 0;
+        for k in self.cursor + 2..self.tokens.len() {
+            match self.tokens[k].kind {
+                TokenKind::LParen => depth += 1,
+                TokenKind::RParen if depth == 1 => {
+                    return self.tokens.get(k + 1).map(|tok| tok.kind) == Some(TokenKind::Equal);
+                }
+                TokenKind::RParen => depth -= 1,
+                TokenKind::EndOfFile => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// wrap `body` into a lambda over the placeholders found since `mark`
+    fn close_holes(&mut self, mark: usize, body: Expr) -> Expr {
+        if self.holes.len() == mark {
+            return body;
+        }
+        let pars = self.holes.drain(mark..).map(|(var, _)| var).collect();
+        let span = *body.span();
+        let body = Box::new(body);
+        Expr::Fun { pars, body, span }
+    }
+
+    /// skip tokens until the start of the next declaration, or the `in` ending the declarations
+    fn synchronize(&mut self) {
+        loop {
+            match self.peek_first() {
+                TokenKind::Fun if self.peek_second() == TokenKind::LowerIdent => return,
+                TokenKind::Data
+                | TokenKind::Type
+                | TokenKind::Extern
+                | TokenKind::Infixl
+                | TokenKind::Infixr
+                | TokenKind::In
+                | TokenKind::EndOfFile => return,
+                _ => {
+                    self.next_token();
+                }
+            }
+        }
+    }
+
     fn next_token(&mut self) -> &Token {
         let tok = &self.tokens[self.cursor];
         if self.cursor < self.tokens.len() - 1 {
@@ -71,12 +212,12 @@ impl<'src> Parser<'src> {
     }
 
     fn err_unexpected(&mut self, token: TokenKind) -> ParseError {
-        let Token { kind, span } = self.peek_token();
+        let Token { kind, span, .. } = self.peek_token();
         ParseError::Unexpected(*span, *kind, token)
     }
 
     fn err_unexpected_many(&mut self, vec: &'static [TokenKind]) -> ParseError {
-        let Token { kind, span } = self.peek_token();
+        let Token { kind, span, .. } = self.peek_token();
         ParseError::UnexpectedMany(*span, *kind, vec)
     }
 
@@ -101,6 +242,16 @@ impl<'src> Parser<'src> {
     }
     */
 
+    fn match_lit_str(&mut self) -> ParseResult<InternStr> {
+        if self.peek_first() == TokenKind::LitStr {
+            let lit = self.peek_token().lit.unwrap();
+            self.next_token();
+            Ok(lit)
+        } else {
+            Err(self.err_unexpected(TokenKind::LitStr))
+        }
+    }
+
     fn match_lit_val(&mut self) -> ParseResult<LitVal> {
         match self.peek_first() {
             TokenKind::LitInt => {
@@ -123,6 +274,11 @@ impl<'src> Parser<'src> {
                 self.next_token();
                 Ok(LitVal::Bool(slice.parse().unwrap()))
             }
+            TokenKind::LitStr => {
+                let lit = self.peek_token().lit.unwrap();
+                self.next_token();
+                Ok(LitVal::Str(lit))
+            }
             TokenKind::LParen if self.peek_second() == TokenKind::RParen => {
                 self.next_token();
                 self.next_token();
@@ -159,6 +315,10 @@ impl<'src> Parser<'src> {
                 self.next_token();
                 Ok(LitType::Char)
             }
+            TokenKind::TyStr => {
+                self.next_token();
+                Ok(LitType::Str)
+            }
             TokenKind::LParen if self.peek_second() == TokenKind::RParen => {
                 self.next_token();
                 self.next_token();
@@ -170,6 +330,7 @@ impl<'src> Parser<'src> {
                     TokenKind::TyReal,
                     TokenKind::TyBool,
                     TokenKind::TyChar,
+                    TokenKind::TyStr,
                     TokenKind::LParen,
                 ];
                 Err(self.err_unexpected_many(VEC))
@@ -187,6 +348,33 @@ impl<'src> Parser<'src> {
         }
     }
 
+    /// a function name, either an identifier or an operator in parentheses like `(<+>)`
+    fn match_value_name(&mut self) -> ParseResult<Ident> {
+        if self.peek_first() == TokenKind::LParen && self.peek_second() == TokenKind::Oper {
+            self.next_token();
+            let slice = self.peek_slice();
+            self.next_token();
+            self.match_token(TokenKind::RParen)?;
+            Ok(Ident::from(InternStr::new(slice)))
+        } else {
+            self.match_lower_ident()
+        }
+    }
+
+    /// the fixity of a binary operator, either user-defined or builtin
+    fn infix_fixity(&self, op: &str) -> Option<(Option<Builtin>, Fixity)> {
+        if op == PIPE {
+            return Some((None, PIPE_FIXITY));
+        }
+        if let Some((fixity, _)) = self.fixities.get(&InternStr::new(op)) {
+            return Some((None, *fixity));
+        }
+        let prim = Builtin::from_infix(op)?;
+        let (_, prec) = prim.as_infix()?;
+        let assoc = Assoc::Left;
+        Some((Some(prim), Fixity { prec, assoc }))
+    }
+
     fn match_upper_ident(&mut self) -> ParseResult<Ident> {
         if self.peek_first() == TokenKind::UpperIdent {
             let slice = self.peek_slice();
@@ -197,6 +385,43 @@ impl<'src> Parser<'src> {
         }
     }
 
+    /// whether the next tokens are a qualified name like `Foo.bar` or `Foo.Bar`,
+    /// without spaces around the dot, and the kind of the name after the dot
+    fn peek_qualified(&self) -> Option<TokenKind> {
+        let toks = self.tokens.get(self.cursor..self.cursor + 3)?;
+        let adjacent = toks[0].span.end.abs == toks[1].span.start.abs
+            && toks[1].span.end.abs == toks[2].span.start.abs;
+        let kind = toks[2].kind;
+        let name = matches!(kind, TokenKind::LowerIdent | TokenKind::UpperIdent);
+        if toks[0].kind == TokenKind::UpperIdent
+            && toks[1].kind == TokenKind::Dot
+            && name
+            && adjacent
+        {
+            Some(kind)
+        } else {
+            None
+        }
+    }
+
+    /// a qualified name, see `peek_qualified`
+    fn match_qualified(&mut self) -> Ident {
+        let module = self.match_upper_ident().unwrap().name;
+        self.match_token(TokenKind::Dot).unwrap();
+        let name = Ident::from(InternStr::new(self.peek_slice()));
+        self.next_token();
+        qualified(module, name)
+    }
+
+    /// a constructor, which might be qualified like `Foo.Bar`
+    fn match_cons_name(&mut self) -> ParseResult<Ident> {
+        if self.peek_qualified() == Some(TokenKind::UpperIdent) {
+            Ok(self.match_qualified())
+        } else {
+            self.match_upper_ident()
+        }
+    }
+
     fn match_builtin(&mut self) -> ParseResult<Builtin> {
         if self.peek_first() == TokenKind::Builtin {
             let slice = self.peek_slice();
@@ -208,10 +433,65 @@ impl<'src> Parser<'src> {
                 "@idiv" => Builtin::IDiv,
                 "@irem" => Builtin::IRem,
                 "@ineg" => Builtin::INeg,
+                "@trunc32" => Builtin::Trunc32,
+                "@sext32" => Builtin::SignExt32,
+                "@zext32" => Builtin::ZeroExt32,
+                "@icmpeq" => Builtin::ICmpEq,
+                "@icmpne" => Builtin::ICmpNe,
+                "@icmplt" => Builtin::ICmpLt,
+                "@icmple" => Builtin::ICmpLe,
+                "@icmpgt" => Builtin::ICmpGt,
+                "@icmpge" => Builtin::ICmpGe,
                 "@radd" => Builtin::RAdd,
                 "@rsub" => Builtin::RSub,
                 "@rmul" => Builtin::RMul,
                 "@rdiv" => Builtin::RDiv,
+                "@sinh" => Builtin::Sinh,
+                "@cosh" => Builtin::Cosh,
+                "@tanh" => Builtin::Tanh,
+                "@erf" => Builtin::Erf,
+                "@erfc" => Builtin::Erfc,
+                "@prefetch" => Builtin::Prefetch,
+                "@expect" => Builtin::Expect,
+                "@simdadd" => Builtin::SimdAdd,
+                "@simdmul" => Builtin::SimdMul,
+                "@simdload" => Builtin::SimdLoad,
+                "@simdstore" => Builtin::SimdStore,
+                "@castptr" => Builtin::CastOpaquePtr,
+                "@allocaligned" => Builtin::AllocAligned,
+                "@yield" => Builtin::Yield,
+                "@spawn" => Builtin::Spawn,
+                "@join" => Builtin::Join,
+                "@spawnwithstack" => Builtin::SpawnWithStack,
+                "@channew" => Builtin::ChanNew,
+                "@chansend" => Builtin::ChanSend,
+                "@chanrecv" => Builtin::ChanRecv,
+                "@chanclose" => Builtin::ChanClose,
+                "@mutexnew" => Builtin::MutexNew,
+                "@mutexlock" => Builtin::MutexLock,
+                "@mutexunlock" => Builtin::MutexUnlock,
+                "@withmutex" => Builtin::WithMutex,
+                "@semnew" => Builtin::SemNew,
+                "@sempost" => Builtin::SemPost,
+                "@semwait" => Builtin::SemWait,
+                "@semtrywait" => Builtin::SemTryWait,
+                "@readfile" => Builtin::ReadFile,
+                "@writefile" => Builtin::WriteFile,
+                "@listdir" => Builtin::ListDir,
+                "@pathjoin" => Builtin::PathJoin,
+                "@format" => Builtin::Format,
+                "@pathbase" => Builtin::PathBase,
+                "@pathdir" => Builtin::PathDir,
+                "@listmin" => Builtin::ListMin,
+                "@listmax" => Builtin::ListMax,
+                "@bitcast" => {
+                    self.match_token(TokenKind::LBracket)?;
+                    let from = self.match_lit_type()?;
+                    self.match_token(TokenKind::Comma)?;
+                    let to = self.match_lit_type()?;
+                    self.match_token(TokenKind::RBracket)?;
+                    Builtin::Bitcast(from, to)
+                }
                 "@band" => Builtin::BAnd,
                 "@bor" => Builtin::BOr,
                 "@bnot" => Builtin::BNot,
@@ -227,6 +507,69 @@ impl<'src> Parser<'src> {
         }
     }
 
+    fn match_attr(&mut self) -> ParseResult<Attr> {
+        if self.peek_first() == TokenKind::Builtin {
+            let slice = self.peek_slice();
+            let span = *self.peek_span();
+            if slice == "@callconv" {
+                // @callconv(stdcall)
+                let start = self.start_pos();
+                self.next_token();
+                self.match_token(TokenKind::LParen)?;
+                let conv_span = *self.peek_span();
+                let conv = self.match_lower_ident()?.name;
+                self.match_token(TokenKind::RParen)?;
+                let span = self.span_from(start);
+                return match CallConv::from_name(&conv) {
+                    Some(conv) => Ok(Attr::CallConv { conv, span }),
+                    None => Err(ParseError::UnknownCallConv(conv_span, conv)),
+                };
+            }
+            if slice == "@optimize" {
+                // @optimize(never)
+                let start = self.start_pos();
+                self.next_token();
+                self.match_token(TokenKind::LParen)?;
+                let level_span = *self.peek_span();
+                let level = self.match_lower_ident()?.name;
+                self.match_token(TokenKind::RParen)?;
+                let span = self.span_from(start);
+                return match OptLevel::from_name(&level) {
+                    Some(level) => Ok(Attr::Optimize { level, span }),
+                    None => Err(ParseError::UnknownOptLevel(level_span, level)),
+                };
+            }
+            if slice == "@deprecated_since" {
+                // @deprecated_since("1.2.0", "bar")
+                let start = self.start_pos();
+                self.next_token();
+                self.match_token(TokenKind::LParen)?;
+                let since = self.match_lit_str()?;
+                self.match_token(TokenKind::Comma)?;
+                let replacement = self.match_lit_str()?;
+                self.match_token(TokenKind::RParen)?;
+                let span = self.span_from(start);
+                return Ok(Attr::Deprecated {
+                    since,
+                    replacement,
+                    span,
+                });
+            }
+            match Attr::from_name(slice, span) {
+                Some(attr) => {
+                    self.next_token();
+                    Ok(attr)
+                }
+                None => {
+                    // fail without consuming, it might be a builtin primitive
+                    Err(ParseError::UnknownAttribute(span, InternStr::new(slice)))
+                }
+            }
+        } else {
+            Err(self.err_unexpected(TokenKind::Builtin))
+        }
+    }
+
     fn option<T>(&mut self, func: ParseFunc<T>) -> ParseResult<Option<T>> {
         let last = self.cursor;
         match func(self) {
@@ -242,6 +585,26 @@ impl<'src> Parser<'src> {
         }
     }
 
+    /// like `option`, but it goes back to where it started if it fails after consuming tokens
+    fn attempt<T>(&mut self, func: ParseFunc<T>) -> Option<T> {
+        let cursor = self.cursor;
+        let holes = self.holes.len();
+        let hole_count = self.hole_count;
+        let fixities = self.fixities.clone();
+        let errors = self.errors.len();
+        match func(self) {
+            Ok(res) => Some(res),
+            Err(_) => {
+                self.cursor = cursor;
+                self.holes.truncate(holes);
+                self.hole_count = hole_count;
+                self.fixities = fixities;
+                self.errors.truncate(errors);
+                None
+            }
+        }
+    }
+
     fn many<T>(&mut self, func: ParseFunc<T>) -> ParseResult<Vec<T>> {
         let mut vec = Vec::new();
         let mut last = self.cursor;
@@ -298,67 +661,257 @@ impl<'src> Parser<'src> {
     }
 }
 
+enum Postfix {
+    // with the placeholders that are whole arguments
+    App(Vec<Expr>, Vec<(Ident, Span)>, Span),
+    Proj(usize, Span),
+    Field(Ident, Span),
+}
+
+/// A placeholder `_` in expression position is a parameter of a synthesized lambda,
+/// the parameters are in the left-to-right order of their placeholders.
+/// The lambda extends over the nearest of
+/// 1. the application, if the placeholder is a whole argument of it, so `f(_, g(_))`
+///    is `fun(a) => f(a, fun(b) => g(b))`. Applications are `f(..)`, `@prim(..)`,
+///    `#ext(..)` and `Cons(..)`.
+/// 2. the operator expression it is part of, a whole chain like `-_ * 2 + 1` counts
+///    as one operator expression, so `map(_ + 1, xs)` is `map(fun(a) => a + 1, xs)`.
+/// 3. the application argument it is part of, so `f(_.0)` is `f(fun(a) => a.0)`.
+///
+/// Any other expression stops a placeholder, so `let x = _;`, `(_)`,
+/// `(_, 1)` and `f(if c then _ else 0)` are errors.
 pub fn parse_expr(p: &mut Parser) -> ParseResult<Expr> {
+    let mark = p.holes.len();
+    let expr = parse_expr_infix(p, 0)?;
+    if p.holes.len() == mark {
+        return Ok(expr);
+    }
+    // a `@prim(..)` call or `(<+>)(..)` has closed its own placeholders,
+    // so the remaining ones are operands of an operator
+    let is_oper = match &expr {
+        Expr::Prim { .. } => true,
+        Expr::App { func, .. } => {
+            matches!(&**func, Expr::Var { var, .. } if var.name.starts_with(is_opr_char))
+        }
+        _ => false,
+    };
+    if is_oper {
+        Ok(p.close_holes(mark, expr))
+    } else {
+        Err(ParseError::UnboundPlaceholder(p.holes[mark].1))
+    }
+}
+
+/// an argument of an application, the extent of the placeholders in it
+/// unless it is a single placeholder, see `parse_expr`
+fn parse_arg(p: &mut Parser) -> ParseResult<Expr> {
+    let start = p.start_pos();
+    if p.peek_first() == TokenKind::Wild
+        && matches!(p.peek_second(), TokenKind::Comma | TokenKind::RParen)
+    {
+        p.match_token(TokenKind::Wild).unwrap();
+        let span = p.span_from(start);
+        return Ok(p.hole(span));
+    }
+    let mark = p.holes.len();
+    let expr = parse_expr_infix(p, 0)?;
+    Ok(p.close_holes(mark, expr))
+}
+
+/// precedence climbing over the binary operators of `Builtin::as_infix`
+/// and the user-defined operators, which are applications of their functions
+fn parse_expr_infix(p: &mut Parser, min_prec: u8) -> ParseResult<Expr> {
+    let mut lhs = parse_expr_prefix(p)?;
+    loop {
+        let op = match p.peek_first() {
+            TokenKind::Oper => p.infix_fixity(p.peek_slice()),
+            _ => None,
+        };
+        let (prim, fixity) = match op {
+            Some((prim, fixity)) if fixity.prec >= min_prec => (prim, fixity),
+            _ => return Ok(lhs),
+        };
+        let start = p.start_pos();
+        let var = Ident::from(InternStr::new(p.peek_slice()));
+        p.next_token();
+        let op_span = p.span_from(start);
+        // for left-associative operators the right operand binds tighter
+        let rhs = match fixity.assoc {
+            Assoc::Left => parse_expr_infix(p, fixity.prec + 1)?,
+            Assoc::Right => parse_expr_infix(p, fixity.prec)?,
+        };
+        let span = Span::merge(*lhs.span(), *rhs.span());
+        let args = vec![lhs, rhs];
+        lhs = match prim {
+            Some(prim) => Expr::Prim { prim, args, span },
+            None if var.name.as_ref() == PIPE => {
+                let [lhs, rhs] = <[Expr; 2]>::try_from(args).unwrap();
+                desugar_pipe(lhs, rhs, span)
+            }
+            None => {
+                let func = Box::new(Expr::Var { var, span: op_span });
+                Expr::App { func, args, span }
+            }
+        };
+    }
+}
+
+fn parse_expr_prefix(p: &mut Parser) -> ParseResult<Expr> {
+    let start = p.start_pos();
+    let prim = match p.peek_first() {
+        TokenKind::Oper => Builtin::from_prefix(p.peek_slice()),
+        _ => None,
+    };
+    match prim {
+        Some(prim) => {
+            p.next_token();
+            let args = vec![parse_expr_prefix(p)?];
+            let span = p.span_from(start);
+            Ok(Expr::Prim { prim, args, span })
+        }
+        None => parse_expr_postfix(p),
+    }
+}
+
+fn parse_expr_postfix(p: &mut Parser) -> ParseResult<Expr> {
     let expr = parse_expr_no_app(p)?;
-    let applys = p.many(|p| {
+    let postfixs = p.many(|p| {
         let start = p.start_pos();
-        p.match_token(TokenKind::LParen)?;
-        let args = p.sepby(TokenKind::Comma, parse_expr)?;
-        p.match_token(TokenKind::RParen)?;
-        let span = Span::new(start, p.end_pos());
-        Ok((args, span))
+        if p.peek_first() == TokenKind::Dot {
+            // tuple projection `e.0`, note that `e.0.1` is lexed as `e` `.` `0.1`
+            p.match_token(TokenKind::Dot)?;
+            if p.peek_first() == TokenKind::LowerIdent {
+                // field access `e.x`
+                let field = p.match_lower_ident().unwrap();
+                let span = p.span_from(start);
+                return Ok(Postfix::Field(field, span));
+            }
+            if p.peek_first() != TokenKind::LitInt {
+                return Err(p.err_unexpected(TokenKind::LitInt));
+            }
+            let index = p.peek_slice().parse().unwrap();
+            p.next_token();
+            let span = p.span_from(start);
+            Ok(Postfix::Proj(index, span))
+        } else {
+            p.match_token(TokenKind::LParen)?;
+            let mark = p.holes.len();
+            let args = p.sepby(TokenKind::Comma, parse_arg)?;
+            p.match_token(TokenKind::RParen)?;
+            let holes = p.holes.split_off(mark);
+            let span = p.span_from(start);
+            Ok(Postfix::App(args, holes, span))
+        }
     })?;
-    let res = applys.into_iter().fold(expr, |func, (args, span)| {
-        let span = Span::merge(func.span(), &span);
-        let func = Box::new(func);
-        Expr::App { func, args, span }
-    });
+    let res = postfixs
+        .into_iter()
+        .fold(expr, |expr, postfix| match postfix {
+            Postfix::App(args, holes, span) => {
+                let span = Span::merge(*expr.span(), span);
+                let func = Box::new(expr);
+                let expr = Expr::App { func, args, span };
+                let mark = p.holes.len();
+                p.holes.extend(holes);
+                p.close_holes(mark, expr)
+            }
+            Postfix::Proj(index, span) => {
+                let span = Span::merge(*expr.span(), span);
+                let expr = Box::new(expr);
+                Expr::Proj { expr, index, span }
+            }
+            Postfix::Field(field, span) => {
+                let span = Span::merge(*expr.span(), span);
+                let expr = Box::new(expr);
+                Expr::Field { expr, field, span }
+            }
+        });
     Ok(res)
 }
 
 fn parse_expr_no_app(p: &mut Parser) -> ParseResult<Expr> {
     let start = p.start_pos();
     match p.peek_first() {
-        TokenKind::LitInt | TokenKind::LitReal | TokenKind::LitBool | TokenKind::LitChar => {
+        TokenKind::LitInt
+        | TokenKind::LitReal
+        | TokenKind::LitBool
+        | TokenKind::LitChar
+        | TokenKind::LitStr => {
             let lit = p.match_lit_val().unwrap();
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Expr::Lit { lit, span })
         }
         TokenKind::LowerIdent => {
             let var = p.match_lower_ident().unwrap();
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
+            Ok(Expr::Var { var, span })
+        }
+        TokenKind::Wild => {
+            p.match_token(TokenKind::Wild).unwrap();
+            let span = p.span_from(start);
+            Ok(p.hole(span))
+        }
+        TokenKind::Hole => {
+            let name = InternStr::new(&p.peek_slice()[1..]);
+            p.next_token();
+            let span = p.span_from(start);
+            Ok(Expr::Hole { name, span })
+        }
+        // `Foo.bar` is the function `bar` of the module `Foo`
+        TokenKind::UpperIdent if p.peek_qualified() == Some(TokenKind::LowerIdent) => {
+            let var = p.match_qualified();
+            let span = p.span_from(start);
             Ok(Expr::Var { var, span })
         }
         TokenKind::UpperIdent => {
-            let cons = p.match_upper_ident().unwrap();
+            let cons = p.match_cons_name().unwrap();
+            let mark = p.holes.len();
             let args = if p.peek_first() == TokenKind::LParen {
                 p.match_token(TokenKind::LParen).unwrap();
-                let args = p.sepby(TokenKind::Comma, parse_expr)?;
+                let args = p.sepby(TokenKind::Comma, parse_arg)?;
                 p.match_token(TokenKind::RParen)?;
                 args
             } else {
                 // abbreviate `Cons()` to `Cons`
                 Vec::new()
             };
-            let span = Span::new(start, p.end_pos());
-            Ok(Expr::Cons { cons, args, span })
+            let span = p.span_from(start);
+            Ok(p.close_holes(mark, Expr::Cons { cons, args, span }))
         }
         TokenKind::Hash => {
             p.match_token(TokenKind::Hash).unwrap();
             let func = p.match_lower_ident()?.name;
             p.match_token(TokenKind::LParen)?;
-            let args = p.sepby(TokenKind::Comma, parse_expr)?;
+            let mark = p.holes.len();
+            let args = p.sepby(TokenKind::Comma, parse_arg)?;
             p.match_token(TokenKind::RParen)?;
-            let span = Span::new(start, p.end_pos());
-            Ok(Expr::ExtCall { func, args, span })
+            let span = p.span_from(start);
+            Ok(p.close_holes(mark, Expr::ExtCall { func, args, span }))
         }
         TokenKind::Builtin => {
             let prim = p.match_builtin().unwrap();
             p.match_token(TokenKind::LParen)?;
-            let args = p.sepby(TokenKind::Comma, parse_expr)?;
+            let mark = p.holes.len();
+            let mut args = p.sepby(TokenKind::Comma, parse_arg)?;
             p.match_token(TokenKind::RParen)?;
-            let span = Span::new(start, p.end_pos());
-            Ok(Expr::Prim { prim, args, span })
+            let span = p.span_from(start);
+            if prim == Builtin::ChanRecv {
+                desugar_chan_recv(&mut args, span);
+            }
+            if matches!(
+                prim,
+                Builtin::ReadFile | Builtin::WriteFile | Builtin::ListDir
+            ) {
+                desugar_file_io(&mut args, span);
+            }
+            if prim == Builtin::ListDir {
+                desugar_list_dir(&mut args, span);
+            }
+            if matches!(prim, Builtin::ListMin | Builtin::ListMax) {
+                let [arg] = <[Expr; 1]>::try_from(args)
+                    .map_err(|args| ParseError::BuiltinArity(span, prim.get_arity(), args.len()))?;
+                return Ok(p.close_holes(mark, desugar_list_extremum(prim, arg, span)));
+            }
+            Ok(p.close_holes(mark, Expr::Prim { prim, args, span }))
         }
         TokenKind::Fun => {
             p.match_token(TokenKind::Fun).unwrap();
@@ -367,23 +920,52 @@ fn parse_expr_no_app(p: &mut Parser) -> ParseResult<Expr> {
             p.match_token(TokenKind::RParen)?;
             p.match_token(TokenKind::EArrow)?;
             let body = Box::new(parse_expr(p)?);
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
+            Ok(Expr::Fun { pars, body, span })
+        }
+        // `\x y -> e` is short for `fun(x, y) => e`
+        TokenKind::Backslash => {
+            p.match_token(TokenKind::Backslash).unwrap();
+            let mut pars = Vec::new();
+            while p.peek_first() == TokenKind::LowerIdent {
+                pars.push(p.match_lower_ident().unwrap());
+            }
+            p.match_token(TokenKind::Arrow)?;
+            let body = Box::new(parse_expr(p)?);
+            let span = p.span_from(start);
             Ok(Expr::Fun { pars, body, span })
         }
         TokenKind::Let => {
             p.match_token(TokenKind::Let).unwrap();
-            let bind = p.match_lower_ident()?;
+            let patn = parse_pattern(p)?;
             p.match_token(TokenKind::Equal)?;
             let expr = Box::new(parse_expr(p)?);
             p.match_token(TokenKind::Semi)?;
-            let cont = Box::new(parse_expr(p)?);
-            let span = Span::new(start, p.end_pos());
-            Ok(Expr::Let {
-                bind,
-                expr,
-                cont,
-                span,
-            })
+            let cont = parse_expr(p)?;
+            let span = p.span_from(start);
+            match patn {
+                Pattern::Var { var, .. } => Ok(Expr::Let {
+                    bind: var,
+                    expr,
+                    cont: Box::new(cont),
+                    span,
+                }),
+                // `let (a, b) = e; cont` is `case e of | (a, b) => cont end`
+                patn => {
+                    let rule = Rule {
+                        span: *patn.span(),
+                        patn,
+                        guard: None,
+                        body: cont,
+                    };
+                    Ok(Expr::Case {
+                        expr,
+                        rules: vec![rule],
+                        is_let: true,
+                        span,
+                    })
+                }
+            }
         }
         TokenKind::Case => {
             p.match_token(TokenKind::Case).unwrap();
@@ -394,35 +976,158 @@ fn parse_expr_no_app(p: &mut Parser) -> ParseResult<Expr> {
                 parse_rule(p)
             })?;
             p.match_token(TokenKind::End)?;
-            let span = Span::new(start, p.end_pos());
-            Ok(Expr::Case { expr, rules, span })
+            let span = p.span_from(start);
+            Ok(Expr::Case {
+                expr,
+                rules,
+                is_let: false,
+                span,
+            })
+        }
+        TokenKind::Try => {
+            p.match_token(TokenKind::Try).unwrap();
+            p.match_token(TokenKind::LBrace)?;
+            let expr = Box::new(parse_expr(p)?);
+            p.match_token(TokenKind::RBrace)?;
+            p.match_token(TokenKind::Catch)?;
+            let handlers = p.many1(|p| {
+                p.match_token(TokenKind::Bar)?;
+                parse_rule(p)
+            })?;
+            p.match_token(TokenKind::End)?;
+            let span = p.span_from(start);
+            Ok(Expr::Try {
+                expr,
+                handlers,
+                span,
+            })
+        }
+        TokenKind::If => {
+            p.match_token(TokenKind::If).unwrap();
+            let cond = Box::new(parse_expr(p)?);
+            p.match_token(TokenKind::Then)?;
+            let trbr = Box::new(parse_expr(p)?);
+            if p.peek_first() != TokenKind::Else {
+                return Err(ParseError::MissingElse(p.span_from(start)));
+            }
+            p.match_token(TokenKind::Else).unwrap();
+            let flbr = Box::new(parse_expr(p)?);
+            let span = p.span_from(start);
+            Ok(Expr::Ifte {
+                cond,
+                trbr,
+                flbr,
+                span,
+            })
         }
         TokenKind::Begin => {
             p.match_token(TokenKind::Begin).unwrap();
-            let decls = p
-                .option(|p| {
-                    let decls = p.many(parse_decl)?;
-                    p.match_token(TokenKind::In)?;
-                    Ok(decls)
-                })?
-                .unwrap_or(Vec::new());
+            let decls = |p: &mut Parser| {
+                let decls = parse_decls(p)?;
+                p.match_token(TokenKind::In)?;
+                Ok(decls)
+            };
+            // `begin let x = 1; x end` is a `let` expression, not a value declaration,
+            // which is only known once there is no `in` after the declarations
+            let decls = if p.peek_first() == TokenKind::Let {
+                p.attempt(decls)
+            } else {
+                p.option(decls)?
+            };
+            let decls = decls.unwrap_or(Vec::new());
             let cont = Box::new(parse_expr(p)?);
             p.match_token(TokenKind::End)?;
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Expr::Blk { decls, cont, span })
         }
+        TokenKind::LetRec => {
+            p.match_token(TokenKind::LetRec).unwrap();
+            let decls = parse_decls(p)?;
+            p.match_token(TokenKind::In)?;
+            let cont = Box::new(parse_expr(p)?);
+            let span = p.span_from(start);
+            Ok(Expr::LetRec { decls, cont, span })
+        }
+        TokenKind::LParen if p.peek_second() == TokenKind::RParen => {
+            let lit = p.match_lit_val().unwrap();
+            let span = p.span_from(start);
+            Ok(Expr::Lit { lit, span })
+        }
+        // a user-defined operator as a function, like `(<+>)`
+        TokenKind::LParen
+            if p.peek_second() == TokenKind::Oper
+                && p.peek_third() == TokenKind::RParen
+                && p.fixities
+                    .contains_key(&InternStr::new(p.peek_second_slice())) =>
+        {
+            let var = p.match_value_name().unwrap();
+            let span = p.span_from(start);
+            Ok(Expr::Var { var, span })
+        }
         TokenKind::LParen => {
             p.match_token(TokenKind::LParen).unwrap();
             let mut expr = parse_expr(p)?;
+            if p.peek_first() == TokenKind::Comma {
+                // `(e,)` is a tuple with single element, while `(e)` is not a tuple
+                let mut elems = vec![expr];
+                while p.peek_first() == TokenKind::Comma {
+                    p.match_token(TokenKind::Comma).unwrap();
+                    if p.peek_first() == TokenKind::RParen {
+                        break;
+                    }
+                    elems.push(parse_expr(p)?);
+                }
+                p.match_token(TokenKind::RParen)?;
+                let span = p.span_from(start);
+                return Ok(Expr::Tuple { elems, span });
+            }
+            if p.peek_first() == TokenKind::Colon {
+                p.match_token(TokenKind::Colon).unwrap();
+                let typ = parse_type(p)?;
+                p.match_token(TokenKind::RParen)?;
+                let expr = Box::new(expr);
+                let span = p.span_from(start);
+                return Ok(Expr::Anno { expr, typ, span });
+            }
             p.match_token(TokenKind::RParen)?;
-            *expr.span_mut() = Span::new(start, p.end_pos());
+            *expr.span_mut() = p.span_from(start);
             Ok(expr)
         }
+        TokenKind::LBracket => {
+            p.match_token(TokenKind::LBracket).unwrap();
+            let elems = p.sepby(TokenKind::Comma, parse_expr)?;
+            p.match_token(TokenKind::RBracket)?;
+            let span = p.span_from(start);
+            Ok(desugar_list(elems, span, |cons, args, span| Expr::Cons {
+                cons,
+                args,
+                span,
+            }))
+        }
+        // a record literal `{ x = 1, y = 2 }`
+        TokenKind::LBrace
+            if p.peek_second() == TokenKind::LowerIdent && p.peek_third() == TokenKind::Equal =>
+        {
+            p.match_token(TokenKind::LBrace).unwrap();
+            let fields = p.sepby1(TokenKind::Comma, parse_field_init)?;
+            p.match_token(TokenKind::RBrace)?;
+            let span = p.span_from(start);
+            Ok(Expr::Record { fields, span })
+        }
         TokenKind::LBrace => {
             p.match_token(TokenKind::LBrace).unwrap();
             let mut expr = parse_expr(p)?;
+            if p.peek_first() == TokenKind::With {
+                // a record update `{ e with x = 1 }`
+                p.match_token(TokenKind::With).unwrap();
+                let fields = p.sepby1(TokenKind::Comma, parse_field_init)?;
+                p.match_token(TokenKind::RBrace)?;
+                let span = p.span_from(start);
+                let expr = Box::new(expr);
+                return Ok(Expr::Update { expr, fields, span });
+            }
             p.match_token(TokenKind::RBrace)?;
-            *expr.span_mut() = Span::new(start, p.end_pos());
+            *expr.span_mut() = p.span_from(start);
             Ok(expr)
         }
         _ => {
@@ -431,39 +1136,322 @@ fn parse_expr_no_app(p: &mut Parser) -> ParseResult<Expr> {
                 TokenKind::LitReal,
                 TokenKind::LitBool,
                 TokenKind::LitChar,
+                TokenKind::LitStr,
                 TokenKind::LowerIdent,
                 TokenKind::Builtin,
+                TokenKind::Hole,
                 TokenKind::Fun,
+                TokenKind::Backslash,
                 TokenKind::Let,
+                TokenKind::LetRec,
                 TokenKind::Case,
+                TokenKind::Try,
+                TokenKind::If,
                 TokenKind::Begin,
                 TokenKind::LParen,
+                TokenKind::LBracket,
             ];
             Err(p.err_unexpected_many(VEC))
         }
     }
 }
 
+/// `[e1, e2]` is `Cons(e1, Cons(e2, Nil))`, and `[]` is `Nil`.
+/// The constructors are resolved by name, so a list type must be in scope.
+fn desugar_list<T>(elems: Vec<T>, span: Span, cons: impl Fn(Ident, Vec<T>, Span) -> T) -> T {
+    let nil = cons(Ident::from(InternStr::new(LIST_NIL)), Vec::new(), span);
+    elems.into_iter().rev().fold(nil, |tail, head| {
+        cons(
+            Ident::from(InternStr::new(LIST_CONS)),
+            vec![head, tail],
+            span,
+        )
+    })
+}
+
+/// `x |> f(y)` is `f(y, x)` and `x |> #f(y)` is `#f(y, x)`, anything else on the right,
+/// including an operator like `x |> a <+> b`, is applied to `x` alone
+fn desugar_pipe(lhs: Expr, rhs: Expr, span: Span) -> Expr {
+    match rhs {
+        Expr::App { func, mut args, .. } if !matches!(&*func, Expr::Var { var, .. } if var.name.starts_with(is_opr_char)) =>
+        {
+            args.push(lhs);
+            Expr::App { func, args, span }
+        }
+        Expr::ExtCall { func, mut args, .. } => {
+            args.push(lhs);
+            Expr::ExtCall { func, args, span }
+        }
+        rhs => Expr::App {
+            func: Box::new(rhs),
+            args: vec![lhs],
+            span,
+        },
+    }
+}
+
+/// `@chanrecv(c)` is `@chanrecv(c, None, fun(x) => Some(x))`, the runtime builds
+/// the option with the constructors it is given. Like lists, they are resolved by name,
+/// so an option type must be in scope.
+fn desugar_chan_recv(args: &mut Vec<Expr>, span: Span) {
+    let some = Ident::from(InternStr::new(OPTION_SOME));
+    let none = Ident::from(InternStr::new(OPTION_NONE));
+    let var = Ident::from(InternStr::new("x"));
+    args.push(Expr::Cons {
+        cons: none,
+        args: Vec::new(),
+        span,
+    });
+    args.push(Expr::Fun {
+        pars: vec![var],
+        body: Box::new(Expr::Cons {
+            cons: some,
+            args: vec![Expr::Var { var, span }],
+            span,
+        }),
+        span,
+    });
+}
+
+/// `@readfile(p)` is `@readfile(p, fun(x) => Ok(x), fun(x) => Err(x))`, and `@writefile`
+/// gets the same constructors, which are resolved by name like those of `@chanrecv`
+fn desugar_file_io(args: &mut Vec<Expr>, span: Span) {
+    for cons in [RESULT_OK, RESULT_ERR] {
+        let cons = Ident::from(InternStr::new(cons));
+        let var = Ident::from(InternStr::new("x"));
+        args.push(Expr::Fun {
+            pars: vec![var],
+            body: Box::new(Expr::Cons {
+                cons,
+                args: vec![Expr::Var { var, span }],
+                span,
+            }),
+            span,
+        });
+    }
+}
+
+/// `@listdir` also gets `Nil` and `fun(x, xs) => Cons(x, xs)` after the result constructors,
+/// to build the list of names
+fn desugar_list_dir(args: &mut Vec<Expr>, span: Span) {
+    let head = Ident::from(InternStr::new("x"));
+    let tail = Ident::from(InternStr::new("xs"));
+    args.push(Expr::Cons {
+        cons: Ident::from(InternStr::new(LIST_NIL)),
+        args: Vec::new(),
+        span,
+    });
+    args.push(Expr::Fun {
+        pars: vec![head, tail],
+        body: Box::new(Expr::Cons {
+            cons: Ident::from(InternStr::new(LIST_CONS)),
+            args: vec![Expr::Var { var: head, span }, Expr::Var { var: tail, span }],
+            span,
+        }),
+        span,
+    });
+}
+
+/// `@listmin(xs)` is a fold over the list, keeping the smaller of each element and the
+/// smallest so far, and `@listmax` keeps the larger one:
+/// ```text
+/// let xs = <arg>;
+/// letrec fun go(acc, ys) =>
+///     case ys of
+///     | Cons(y, rest) => { go(if @icmplt(y, acc) then y else acc, rest) }
+///     | Nil => { acc }
+///     end
+/// in case xs of
+///     | Cons(y, rest) => { Some(go(y, rest)) }
+///     | Nil => { None }
+///     end
+/// ```
+/// there is no ordering on other types yet, so the elements are integers
+fn desugar_list_extremum(prim: Builtin, arg: Expr, span: Span) -> Expr {
+    let cmp = match prim {
+        Builtin::ListMin => Builtin::ICmpLt,
+        Builtin::ListMax => Builtin::ICmpGt,
+        _ => unreachable!(),
+    };
+    let ident = |name: &str| Ident::from(InternStr::new(name));
+    let var = |var: Ident| Expr::Var { var, span };
+    let (xs, go, acc, ys, y, rest) = (
+        ident("xs"),
+        ident("go"),
+        ident("acc"),
+        ident("ys"),
+        ident("y"),
+        ident("rest"),
+    );
+    let cons_patn = Pattern::Cons {
+        cons: ident(LIST_CONS),
+        pars: vec![
+            Pattern::Var { var: y, span },
+            Pattern::Var { var: rest, span },
+        ],
+        span,
+    };
+    let nil_patn = Pattern::Cons {
+        cons: ident(LIST_NIL),
+        pars: Vec::new(),
+        span,
+    };
+    let rule = |patn: Pattern, body: Expr| Rule {
+        patn,
+        guard: None,
+        body,
+        span,
+    };
+    let call_go = |args: Vec<Expr>| Expr::App {
+        func: Box::new(var(go)),
+        args,
+        span,
+    };
+    let pick = Expr::Ifte {
+        cond: Box::new(Expr::Prim {
+            prim: cmp,
+            args: vec![var(y), var(acc)],
+            span,
+        }),
+        trbr: Box::new(var(y)),
+        flbr: Box::new(var(acc)),
+        span,
+    };
+    let fold = Decl::Func {
+        name: go,
+        attrs: Vec::new(),
+        pars: vec![(acc, None), (ys, None)],
+        ret: None,
+        body: Box::new(Expr::Case {
+            expr: Box::new(var(ys)),
+            rules: vec![
+                rule(cons_patn.clone(), call_go(vec![pick, var(rest)])),
+                rule(nil_patn.clone(), var(acc)),
+            ],
+            is_let: false,
+            span,
+        }),
+        span,
+    };
+    let result = Expr::Case {
+        expr: Box::new(var(xs)),
+        rules: vec![
+            rule(
+                cons_patn,
+                Expr::Cons {
+                    cons: ident(OPTION_SOME),
+                    args: vec![call_go(vec![var(y), var(rest)])],
+                    span,
+                },
+            ),
+            rule(
+                nil_patn,
+                Expr::Cons {
+                    cons: ident(OPTION_NONE),
+                    args: Vec::new(),
+                    span,
+                },
+            ),
+        ],
+        is_let: false,
+        span,
+    };
+    Expr::Let {
+        bind: xs,
+        expr: Box::new(arg),
+        cont: Box::new(Expr::LetRec {
+            decls: vec![fold],
+            cont: Box::new(result),
+            span,
+        }),
+        span,
+    }
+}
+
+/// `p1 | p2 | ...`, or-patterns bind looser than `::`
 fn parse_pattern(p: &mut Parser) -> ParseResult<Pattern> {
+    let start = p.start_pos();
+    let head = parse_pattern_cons(p)?;
+    if p.peek_first() != TokenKind::Bar {
+        return Ok(head);
+    }
+    let mut alts = vec![head];
+    while p.peek_first() == TokenKind::Bar {
+        p.match_token(TokenKind::Bar).unwrap();
+        alts.push(parse_pattern_cons(p)?);
+    }
+    let span = p.span_from(start);
+    Ok(Pattern::Or { alts, span })
+}
+
+/// `p1 :: p2` is the pattern `Cons(p1, p2)`, it associates to the right
+fn parse_pattern_cons(p: &mut Parser) -> ParseResult<Pattern> {
+    let start = p.start_pos();
+    let head = parse_pattern_atom(p)?;
+    if p.peek_first() == TokenKind::ColonColon {
+        p.match_token(TokenKind::ColonColon).unwrap();
+        let tail = parse_pattern_cons(p)?;
+        let span = p.span_from(start);
+        let cons = Ident::from(InternStr::new(LIST_CONS));
+        let pars = vec![head, tail];
+        Ok(Pattern::Cons { cons, pars, span })
+    } else {
+        Ok(head)
+    }
+}
+
+fn parse_pattern_atom(p: &mut Parser) -> ParseResult<Pattern> {
     let start = p.start_pos();
     match p.peek_first() {
         TokenKind::LitInt | TokenKind::LitReal | TokenKind::LitBool | TokenKind::LitChar => {
             let lit = p.match_lit_val().unwrap();
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Pattern::Lit { lit, span })
         }
         TokenKind::LParen if p.peek_second() == TokenKind::RParen => {
             let lit = p.match_lit_val().unwrap();
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Pattern::Lit { lit, span })
         }
+        TokenKind::LParen => {
+            p.match_token(TokenKind::LParen).unwrap();
+            let patn = parse_pattern(p)?;
+            if p.peek_first() == TokenKind::Comma {
+                // `(p,)` is a tuple pattern with single element, like tuple expressions
+                let mut pats = vec![patn];
+                while p.peek_first() == TokenKind::Comma {
+                    p.match_token(TokenKind::Comma).unwrap();
+                    if p.peek_first() == TokenKind::RParen {
+                        break;
+                    }
+                    pats.push(parse_pattern(p)?);
+                }
+                p.match_token(TokenKind::RParen)?;
+                let span = p.span_from(start);
+                Ok(Pattern::Tuple { pats, span })
+            } else {
+                p.match_token(TokenKind::RParen)?;
+                Ok(patn)
+            }
+        }
         TokenKind::LowerIdent => {
             let var = p.match_lower_ident().unwrap();
-            let span = Span::new(start, p.end_pos());
+            if p.peek_first() == TokenKind::At {
+                // `x @ p` binds tighter than `::` and `|`
+                p.match_token(TokenKind::At).unwrap();
+                let patn = Box::new(parse_pattern_atom(p)?);
+                let span = p.span_from(start);
+                return Ok(Pattern::As {
+                    bind: var,
+                    patn,
+                    span,
+                });
+            }
+            let span = p.span_from(start);
             Ok(Pattern::Var { var, span })
         }
         TokenKind::UpperIdent => {
-            let cons = p.match_upper_ident().unwrap();
+            let cons = p.match_cons_name().unwrap();
             let pars = if p.peek_first() == TokenKind::LParen {
                 p.match_token(TokenKind::LParen).unwrap();
                 let pars = p.sepby(TokenKind::Comma, parse_pattern)?;
@@ -473,13 +1461,25 @@ fn parse_pattern(p: &mut Parser) -> ParseResult<Pattern> {
                 // abbreviate `| Cons() => ...` to `| Cons => ...`
                 Vec::new()
             };
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Pattern::Cons { cons, pars, span })
         }
         TokenKind::Wild => {
             let span = *p.peek_span();
+            p.match_token(TokenKind::Wild).unwrap();
             Ok(Pattern::Wild { span })
         }
+        TokenKind::LBracket => {
+            p.match_token(TokenKind::LBracket).unwrap();
+            let pats = p.sepby(TokenKind::Comma, parse_pattern)?;
+            p.match_token(TokenKind::RBracket)?;
+            let span = p.span_from(start);
+            Ok(desugar_list(pats, span, |cons, pars, span| Pattern::Cons {
+                cons,
+                pars,
+                span,
+            }))
+        }
         _ => {
             static VEC: &[TokenKind] = &[
                 TokenKind::LitInt,
@@ -490,6 +1490,7 @@ fn parse_pattern(p: &mut Parser) -> ParseResult<Pattern> {
                 TokenKind::LParen,
                 TokenKind::LowerIdent,
                 TokenKind::Wild,
+                TokenKind::LBracket,
             ];
             Err(p.err_unexpected_many(VEC))
         }
@@ -499,35 +1500,265 @@ fn parse_pattern(p: &mut Parser) -> ParseResult<Pattern> {
 fn parse_rule(p: &mut Parser) -> ParseResult<Rule> {
     let start = p.start_pos();
     let patn = parse_pattern(p)?;
+    let guard = if p.peek_first() == TokenKind::If {
+        p.match_token(TokenKind::If).unwrap();
+        Some(parse_expr(p)?)
+    } else {
+        None
+    };
     p.match_token(TokenKind::EArrow)?;
     p.match_token(TokenKind::LBrace)?;
     let body = parse_expr(p)?;
     p.match_token(TokenKind::RBrace)?;
-    let span = Span::new(start, p.end_pos());
-    Ok(Rule { patn, body, span })
+    let span = p.span_from(start);
+    Ok(Rule {
+        patn,
+        guard,
+        body,
+        span,
+    })
 }
 
-pub fn parse_decl(p: &mut Parser) -> ParseResult<Decl> {
-    let start = p.start_pos();
-    match p.peek_first() {
-        TokenKind::Fun => {
-            p.match_token(TokenKind::Fun).unwrap();
-            let name = p.match_lower_ident()?;
-            p.match_token(TokenKind::LParen)?;
-            let pars = p.sepby(TokenKind::Comma, |p| p.match_lower_ident())?;
-            p.match_token(TokenKind::RParen)?;
-            p.match_token(TokenKind::EArrow)?;
-            let body = Box::new(parse_expr(p)?);
-            let span = Span::new(start, p.end_pos());
-            Ok(Decl::Func {
-                name,
-                pars,
-                body,
-                span,
-            })
+/// `fun len(Nil) = 0 fun len(Cons(_, xs)) = ...`, the adjacent clauses of a function
+/// are one function, whose body matches its parameters against the clauses in order
+fn parse_clauses(p: &mut Parser, start: Position, attrs: Vec<Attr>) -> ParseResult<Decl> {
+    let slice = p.peek_second_slice();
+    let mut clauses: Vec<(Vec<Pattern>, Span, Expr, Span)> = Vec::new();
+    loop {
+        let clause_start = p.start_pos();
+        p.match_token(TokenKind::Fun)?;
+        p.match_lower_ident()?;
+        let pats_start = p.start_pos();
+        p.match_token(TokenKind::LParen)?;
+        let pats = p.sepby(TokenKind::Comma, parse_pattern)?;
+        p.match_token(TokenKind::RParen)?;
+        let pats_span = p.span_from(pats_start);
+        p.match_token(TokenKind::Equal)?;
+        let body = parse_expr(p)?;
+        let span = p.span_from(clause_start);
+        if let Some((first, _, _, _)) = clauses.first() {
+            if first.len() != pats.len() {
+                return Err(ParseError::ClauseArity(span, first.len(), pats.len()));
+            }
         }
-        TokenKind::Data => {
+        clauses.push((pats, pats_span, body, span));
+        if !p.peek_clause(Some(slice)) {
+            break;
+        }
+    }
+
+    let arity = clauses[0].0.len();
+    let pars: Vec<Ident> = (0..arity).map(|_| p.fresh_par()).collect();
+    let span = p.span_from(start);
+    let mut args: Vec<Expr> = pars.iter().map(|&var| Expr::Var { var, span }).collect();
+    let expr = match arity {
+        0 => Expr::Lit {
+            lit: LitVal::Unit,
+            span,
+        },
+        1 => args.pop().unwrap(),
+        _ => Expr::Tuple { elems: args, span },
+    };
+    let rules = clauses
+        .into_iter()
+        .map(|(mut pats, pats_span, body, span)| {
+            let patn = match arity {
+                0 => Pattern::Lit {
+                    lit: LitVal::Unit,
+                    span: pats_span,
+                },
+                1 => pats.pop().unwrap(),
+                _ => Pattern::Tuple {
+                    pats,
+                    span: pats_span,
+                },
+            };
+            Rule {
+                patn,
+                guard: None,
+                body,
+                span,
+            }
+        })
+        .collect();
+    let body = Box::new(Expr::Case {
+        expr: Box::new(expr),
+        rules,
+        is_let: false,
+        span,
+    });
+    Ok(Decl::Func {
+        name: Ident::from(InternStr::new(slice)),
+        attrs,
+        pars: pars.into_iter().map(|par| (par, None)).collect(),
+        ret: None,
+        body,
+        span,
+    })
+}
+
+/// `x = e` in a record literal or update
+fn parse_field_init(p: &mut Parser) -> ParseResult<FieldInit> {
+    let start = p.start_pos();
+    let field = p.match_lower_ident()?;
+    p.match_token(TokenKind::Equal)?;
+    let expr = parse_expr(p)?;
+    let span = p.span_from(start);
+    Ok(FieldInit { field, expr, span })
+}
+
+/// `x: T` in a record declaration
+fn parse_field_decl(p: &mut Parser) -> ParseResult<FieldDecl> {
+    let start = p.start_pos();
+    let field = p.match_lower_ident()?;
+    p.match_token(TokenKind::Colon)?;
+    let typ = parse_type(p)?;
+    let span = p.span_from(start);
+    Ok(FieldDecl { field, typ, span })
+}
+
+/// Parse a whole program, going on after a broken declaration to report the errors
+/// of the other ones too. Each broken declaration is dropped, up to the next keyword
+/// starting a declaration. Returns the program if the rest of it could be parsed,
+/// and all the errors, the program is only complete if there is none.
+pub fn parse_program(p: &mut Parser) -> (Option<Expr>, Vec<ParseError>) {
+    p.recover = true;
+    let res = parse_expr(p);
+    p.recover = false;
+    let mut errors = std::mem::take(&mut p.errors);
+    match res {
+        Ok(expr) => (Some(expr), errors),
+        Err(err) => {
+            errors.push(err);
+            (None, errors)
+        }
+    }
+}
+
+/// `module Foo;` and `import Bar;` at the start of a file, see `Header`
+pub fn parse_header(p: &mut Parser) -> ParseResult<Header> {
+    let module = p.option(|p| {
+        let start = p.start_pos();
+        p.match_token(TokenKind::Module)?;
+        let name = p.match_upper_ident()?.name;
+        p.match_token(TokenKind::Semi)?;
+        Ok((name, p.span_from(start)))
+    })?;
+    let imports = p.many(|p| {
+        let start = p.start_pos();
+        p.match_token(TokenKind::Import)?;
+        let name = p.match_upper_ident()?.name;
+        p.match_token(TokenKind::Semi)?;
+        Ok((name, p.span_from(start)))
+    })?;
+    Ok(Header { module, imports })
+}
+
+/// the declarations of a module after its header, which go on until the end of the file
+pub fn parse_module(p: &mut Parser) -> (Option<Vec<Decl>>, Vec<ParseError>) {
+    p.recover = true;
+    let res = parse_decls(p).and_then(|decls| {
+        p.match_token(TokenKind::EndOfFile)?;
+        Ok(decls)
+    });
+    p.recover = false;
+    let mut errors = std::mem::take(&mut p.errors);
+    match res {
+        Ok(decls) => (Some(decls), errors),
+        Err(err) => {
+            errors.push(err);
+            (None, errors)
+        }
+    }
+}
+
+/// the declarations of a block, see `parse_program` for the recovery from errors
+fn parse_decls(p: &mut Parser) -> ParseResult<Vec<Decl>> {
+    if !p.recover {
+        return p.many(parse_decl);
+    }
+    let mut decls = Vec::new();
+    loop {
+        let last = p.cursor;
+        match parse_decl(p) {
+            Ok(decl) => decls.push(decl),
+            // not a declaration at all, like the `in` after them
+            Err(_) if p.cursor == last => return Ok(decls),
+            Err(err) => {
+                p.errors.push(err);
+                p.synchronize();
+            }
+        }
+    }
+}
+
+pub fn parse_decl(p: &mut Parser) -> ParseResult<Decl> {
+    let start = p.start_pos();
+    let attrs = p.many(|p| p.match_attr())?;
+    for attr in attrs.iter() {
+        // calling conventions are for external functions, other attributes for functions
+        let allowed = match (p.peek_first(), attr) {
+            (TokenKind::Extern, Attr::CallConv { .. }) => true,
+            (TokenKind::Fun, Attr::CallConv { .. }) => false,
+            (TokenKind::Fun, _) => true,
+            _ => false,
+        };
+        if !allowed {
+            return Err(ParseError::MisplacedAttribute(*attr.span()));
+        }
+    }
+    match p.peek_first() {
+        TokenKind::Fun if p.peek_clause(None) => parse_clauses(p, start, attrs),
+        TokenKind::Fun => {
+            p.match_token(TokenKind::Fun).unwrap();
+            let name = p.match_value_name()?;
+            p.match_token(TokenKind::LParen)?;
+            let pars = p.sepby(TokenKind::Comma, |p| {
+                let par = p.match_lower_ident()?;
+                let typ = p.option(|p| {
+                    p.match_token(TokenKind::Colon)?;
+                    parse_type(p)
+                })?;
+                Ok((par, typ))
+            })?;
+            p.match_token(TokenKind::RParen)?;
+            let ret = p.option(|p| {
+                p.match_token(TokenKind::Colon)?;
+                parse_type(p)
+            })?;
+            p.match_token(TokenKind::EArrow)?;
+            let body = Box::new(parse_expr(p)?);
+            let span = p.span_from(start);
+            Ok(Decl::Func {
+                name,
+                attrs,
+                pars,
+                ret,
+                body,
+                span,
+            })
+        }
+        TokenKind::Let => {
+            p.match_token(TokenKind::Let).unwrap();
+            let name = p.match_lower_ident()?;
+            let typ = p.option(|p| {
+                p.match_token(TokenKind::Colon)?;
+                parse_type(p)
+            })?;
+            p.match_token(TokenKind::Equal)?;
+            let body = Box::new(parse_expr(p)?);
+            p.match_token(TokenKind::Semi)?;
+            let span = p.span_from(start);
+            Ok(Decl::Val {
+                name,
+                typ,
+                body,
+                span,
+            })
+        }
+        TokenKind::Data => {
             p.match_token(TokenKind::Data).unwrap();
+            let name_span = *p.peek_span();
             let name = p.match_upper_ident()?;
             let pars = p
                 .option(|p| {
@@ -538,12 +1769,23 @@ pub fn parse_decl(p: &mut Parser) -> ParseResult<Decl> {
                 })?
                 .unwrap_or(Vec::new());
             p.match_token(TokenKind::Equal)?;
+            if p.peek_first() == TokenKind::LBrace {
+                // a record `data Point = { x: Int, y: Int }`
+                if !pars.is_empty() {
+                    return Err(ParseError::PolymorphicRecord(name_span));
+                }
+                p.match_token(TokenKind::LBrace).unwrap();
+                let fields = p.sepby1(TokenKind::Comma, parse_field_decl)?;
+                p.match_token(TokenKind::RBrace)?;
+                let span = p.span_from(start);
+                return Ok(Decl::Record { name, fields, span });
+            }
             let vars = p.many1(|p| {
                 p.match_token(TokenKind::Bar)?;
                 parse_varient(p)
             })?;
             p.match_token(TokenKind::End)?;
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Decl::Data {
                 name,
                 pars,
@@ -562,10 +1804,15 @@ pub fn parse_decl(p: &mut Parser) -> ParseResult<Decl> {
                     Ok(pars)
                 })?
                 .unwrap_or(Vec::new());
+            if p.peek_first() == TokenKind::Semi {
+                p.match_token(TokenKind::Semi).unwrap();
+                let span = p.span_from(start);
+                return Ok(Decl::Abstract { name, pars, span });
+            }
             p.match_token(TokenKind::Equal)?;
-            let typ = parse_type(p)?;
+            let typ = parse_scheme(p, false)?;
             p.match_token(TokenKind::Semi)?;
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Decl::Type {
                 name,
                 pars,
@@ -585,18 +1832,64 @@ pub fn parse_decl(p: &mut Parser) -> ParseResult<Decl> {
                 })?
                 .unwrap_or(Vec::new());
             p.match_token(TokenKind::Colon)?;
-            let typ = parse_type(p)?;
+            let typ = parse_scheme(p, true)?;
             p.match_token(TokenKind::Semi)?;
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Decl::Extern {
                 name,
+                attrs,
                 pars,
                 typ,
                 span,
             })
         }
+        TokenKind::Infixl | TokenKind::Infixr => {
+            let assoc = match p.peek_first() {
+                TokenKind::Infixl => Assoc::Left,
+                _ => Assoc::Right,
+            };
+            p.next_token();
+            let prec_start = p.start_pos();
+            if p.peek_first() != TokenKind::LitInt {
+                return Err(p.err_unexpected(TokenKind::LitInt));
+            }
+            let prec = p.peek_slice().parse().unwrap_or(u8::MAX);
+            p.next_token();
+            if prec > MAX_PREC {
+                return Err(ParseError::PrecedenceOutOfRange(p.span_from(prec_start)));
+            }
+            if p.peek_first() != TokenKind::Oper {
+                return Err(p.err_unexpected(TokenKind::Oper));
+            }
+            let name = InternStr::new(p.peek_slice());
+            p.next_token();
+            p.match_token(TokenKind::Semi)?;
+            let span = p.span_from(start);
+            let fixity = Fixity { prec, assoc };
+            // declared before use, so the operator is known to the rest of the input
+            match p.fixities.get(&name) {
+                Some((prev, prev_span)) if *prev != fixity => {
+                    return Err(ParseError::ConflictingFixity(span, Some(*prev_span)));
+                }
+                None if Builtin::from_infix(&name).is_some() || name.as_ref() == PIPE => {
+                    return Err(ParseError::ConflictingFixity(span, None));
+                }
+                _ => {}
+            }
+            p.fixities.insert(name, (fixity, span));
+            let oper = Ident::from(name);
+            Ok(Decl::Fixity { oper, fixity, span })
+        }
         _ => {
-            static VEC: &[TokenKind] = &[TokenKind::Fun, TokenKind::Data, TokenKind::Type];
+            static VEC: &[TokenKind] = &[
+                TokenKind::Fun,
+                TokenKind::Let,
+                TokenKind::Data,
+                TokenKind::Type,
+                TokenKind::Extern,
+                TokenKind::Infixl,
+                TokenKind::Infixr,
+            ];
             Err(p.err_unexpected_many(VEC))
         }
     }
@@ -613,50 +1906,123 @@ pub fn parse_varient(p: &mut Parser) -> ParseResult<Varient> {
             Ok(pars)
         })?
         .unwrap_or(Vec::new());
-    let span = Span::new(start, p.end_pos());
+    let span = p.span_from(start);
     Ok(Varient { cons, pars, span })
 }
 
 fn parse_type(p: &mut Parser) -> ParseResult<Type> {
     let start = p.start_pos();
     match p.peek_first() {
-        TokenKind::TyInt | TokenKind::TyReal | TokenKind::TyBool | TokenKind::TyChar => {
+        TokenKind::TyInt
+        | TokenKind::TyReal
+        | TokenKind::TyBool
+        | TokenKind::TyChar
+        | TokenKind::TyStr => {
             let lit = p.match_lit_type().unwrap();
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Type::Lit { lit, span })
         }
         TokenKind::LParen if p.peek_second() == TokenKind::RParen => {
             let lit = p.match_lit_type().unwrap();
-            let span = Span::new(start, p.end_pos());
+            let span = p.span_from(start);
             Ok(Type::Lit { lit, span })
         }
+        TokenKind::LParen => {
+            p.match_token(TokenKind::LParen).unwrap();
+            let mut typ = parse_type(p)?;
+            if p.peek_first() == TokenKind::Comma {
+                let mut elems = vec![typ];
+                while p.peek_first() == TokenKind::Comma {
+                    p.match_token(TokenKind::Comma).unwrap();
+                    if p.peek_first() == TokenKind::RParen {
+                        break;
+                    }
+                    elems.push(parse_type(p)?);
+                }
+                p.match_token(TokenKind::RParen)?;
+                let span = p.span_from(start);
+                return Ok(Type::Tuple { elems, span });
+            }
+            p.match_token(TokenKind::RParen)?;
+            *typ.span_mut() = p.span_from(start);
+            Ok(typ)
+        }
+        TokenKind::UpperIdent if p.peek_slice() == "Ptr" => {
+            p.match_upper_ident().unwrap();
+            let span = p.span_from(start);
+            Ok(Type::OpaquePtr { span })
+        }
+        TokenKind::UpperIdent if p.peek_slice() == COROUTINE => {
+            p.match_upper_ident().unwrap();
+            p.match_token(TokenKind::LBracket)?;
+            let arg = Box::new(parse_type(p)?);
+            p.match_token(TokenKind::Comma)?;
+            let res = Box::new(parse_type(p)?);
+            p.match_token(TokenKind::RBracket)?;
+            let span = p.span_from(start);
+            Ok(Type::Coroutine { arg, res, span })
+        }
+        TokenKind::UpperIdent if p.peek_slice() == THREAD => {
+            p.match_upper_ident().unwrap();
+            p.match_token(TokenKind::LBracket)?;
+            let res = Box::new(parse_type(p)?);
+            p.match_token(TokenKind::RBracket)?;
+            let span = p.span_from(start);
+            Ok(Type::Thread { res, span })
+        }
+        TokenKind::UpperIdent if p.peek_slice() == CHAN => {
+            p.match_upper_ident().unwrap();
+            p.match_token(TokenKind::LBracket)?;
+            let elem = Box::new(parse_type(p)?);
+            p.match_token(TokenKind::RBracket)?;
+            let span = p.span_from(start);
+            Ok(Type::Chan { elem, span })
+        }
+        TokenKind::UpperIdent if p.peek_slice() == MUTEX => {
+            p.match_upper_ident().unwrap();
+            let span = p.span_from(start);
+            Ok(Type::Mutex { span })
+        }
+        TokenKind::UpperIdent if p.peek_slice() == SEM => {
+            p.match_upper_ident().unwrap();
+            let span = p.span_from(start);
+            Ok(Type::Sem { span })
+        }
+        TokenKind::UpperIdent if p.peek_slice() == "Simd" => {
+            p.match_upper_ident().unwrap();
+            p.match_token(TokenKind::LBracket)?;
+            let elem = p.match_lit_type()?;
+            p.match_token(TokenKind::Comma)?;
+            if p.peek_first() != TokenKind::LitInt {
+                return Err(p.err_unexpected(TokenKind::LitInt));
+            }
+            let lanes = p.peek_slice().parse().unwrap_or(0);
+            p.next_token();
+            p.match_token(TokenKind::RBracket)?;
+            let span = p.span_from(start);
+            if elem != LitType::Real || lanes != SIMD_LANES {
+                return Err(ParseError::UnsupportedSimd(span));
+            }
+            Ok(Type::SimdVec { lanes, elem, span })
+        }
         TokenKind::UpperIdent => {
             let var = p.match_upper_ident().unwrap();
             if p.peek_first() == TokenKind::LBracket {
                 p.match_token(TokenKind::LBracket)?;
                 let args = p.sepby1(TokenKind::Comma, parse_type)?;
                 p.match_token(TokenKind::RBracket)?;
-                let span = Span::new(start, p.end_pos());
+                let span = p.span_from(start);
                 Ok(Type::App {
                     cons: var,
                     args,
                     span,
                 })
             } else {
-                let span = Span::new(start, p.end_pos());
+                let span = p.span_from(start);
                 Ok(Type::Var { var, span })
             }
         }
-        TokenKind::Fun => {
-            p.match_token(TokenKind::Fun).unwrap();
-            p.match_token(TokenKind::LParen)?;
-            let pars = p.sepby(TokenKind::Comma, parse_type)?;
-            p.match_token(TokenKind::RParen)?;
-            p.match_token(TokenKind::Arrow)?;
-            let res = Box::new(parse_type(p)?);
-            let span = Span::new(start, p.end_pos());
-            Ok(Type::Fun { pars, res, span })
-        }
+        TokenKind::Fun => parse_fun_type(p, false),
         _ => {
             static VEC: &[TokenKind] = &[
                 TokenKind::TyInt,
@@ -665,12 +2031,74 @@ fn parse_type(p: &mut Parser) -> ParseResult<Type> {
                 TokenKind::TyChar,
                 TokenKind::UpperIdent,
                 TokenKind::Fun,
+                TokenKind::LParen,
             ];
             Err(p.err_unexpected_many(VEC))
         }
     }
 }
 
+/// `forall T U. T`, or a type without quantified variables.
+/// only the outermost function type of an extern can be variadic, as told by `variadic_ok`
+fn parse_scheme(p: &mut Parser, variadic_ok: bool) -> ParseResult<Type> {
+    let start = p.start_pos();
+    let vars = p
+        .option(|p| {
+            p.match_token(TokenKind::Forall)?;
+            let mut vars = vec![p.match_upper_ident()?];
+            while p.peek_first() == TokenKind::UpperIdent {
+                vars.push(p.match_upper_ident()?);
+            }
+            p.match_token(TokenKind::Dot)?;
+            Ok(vars)
+        })?
+        .unwrap_or(Vec::new());
+    let typ = if p.peek_first() == TokenKind::Fun {
+        parse_fun_type(p, variadic_ok)?
+    } else {
+        parse_type(p)?
+    };
+    if vars.is_empty() {
+        return Ok(typ);
+    }
+    let body = Box::new(typ);
+    let span = p.span_from(start);
+    Ok(Type::Scheme { vars, body, span })
+}
+
+/// `fun(T1, ..., Tn) -> T`, a trailing `...` is accepted if `variadic_ok`
+fn parse_fun_type(p: &mut Parser, variadic_ok: bool) -> ParseResult<Type> {
+    let start = p.start_pos();
+    p.match_token(TokenKind::Fun)?;
+    p.match_token(TokenKind::LParen)?;
+    let mut pars = Vec::new();
+    let mut variadic = false;
+    if p.peek_first() != TokenKind::RParen {
+        loop {
+            if variadic_ok && p.peek_first() == TokenKind::Ellipsis {
+                p.next_token();
+                variadic = true;
+                break;
+            }
+            pars.push(parse_type(p)?);
+            if p.peek_first() != TokenKind::Comma {
+                break;
+            }
+            p.next_token();
+        }
+    }
+    p.match_token(TokenKind::RParen)?;
+    p.match_token(TokenKind::Arrow)?;
+    let res = Box::new(parse_type(p)?);
+    let span = p.span_from(start);
+    Ok(Type::Fun {
+        pars,
+        res,
+        variadic,
+        span,
+    })
+}
+
 #[test]
 fn parser_test() {
     let string = r#"
@@ -706,3 +2134,1033 @@ end
     assert!(res.is_ok());
     println!("{}", res.unwrap());
 }
+
+#[test]
+fn parser_tuple_test() {
+    let string = r#"
+begin
+    type Pair = (Int, Bool);
+    type Single = (Int,);
+in
+    let t = (1, (2,), ());
+    let u = (t);
+    u.1
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let text = format!("{expr}");
+    assert!(text.contains("type Pair = (Int, Bool);"));
+    assert!(text.contains("type Single = (Int,);"));
+    assert!(text.contains("let t = (1, (2,), ());"));
+    assert!(text.contains("let u = t;"));
+    assert!(text.contains("u.1"));
+
+    let string = r#"
+case t of
+| ((x, _), (y,)) => { x }
+| (z) => { z }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let text = format!("{expr}");
+    assert!(text.contains("((x, _), (y,)) =>"));
+    assert!(text.contains("| z =>"));
+}
+
+#[test]
+fn parser_simd_test() {
+    let string = r#"
+begin
+    type Vec2 = Simd[Real, 2];
+in
+    @simdadd(x, y)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let text = format!("{expr}");
+    assert!(text.contains("type Vec2 = Simd[Real, 2];"));
+
+    for string in ["type V = Simd[Int, 2];", "type V = Simd[Real, 4];"] {
+        let mut par = Parser::new(string);
+        assert!(matches!(
+            parse_decl(&mut par),
+            Err(ParseError::UnsupportedSimd(_))
+        ));
+    }
+}
+
+#[test]
+fn parser_ifte_test() {
+    // nested
+    let string = "if a then if b then 1 else 2 else 3";
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    match &expr1 {
+        Expr::Ifte { trbr, flbr, .. } => {
+            assert!(matches!(trbr.as_ref(), Expr::Ifte { .. }));
+            assert!(matches!(flbr.as_ref(), Expr::Lit { .. }));
+        }
+        _ => panic!("test failed!"),
+    }
+
+    // chained
+    let string = "if a then 1 else if b then 2 else 3";
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    match &expr1 {
+        Expr::Ifte { trbr, flbr, .. } => {
+            assert!(matches!(trbr.as_ref(), Expr::Lit { .. }));
+            assert!(matches!(flbr.as_ref(), Expr::Ifte { .. }));
+        }
+        _ => panic!("test failed!"),
+    }
+
+    // printed result can be parsed again
+    let text1 = format!("{expr1}");
+    let mut par = Parser::new(&text1);
+    let expr2 = parse_expr(&mut par).unwrap();
+    assert_eq!(text1, format!("{expr2}"));
+
+    // the false branch can't be omitted
+    let string = "let x = if a then 1; x";
+    let mut par = Parser::new(string);
+    match parse_expr(&mut par) {
+        Err(ParseError::MissingElse(span)) => {
+            assert_eq!(&string[span.start.abs..span.end.abs], "if a then 1");
+        }
+        res => panic!("expected a missing else, found {res:?}"),
+    }
+}
+
+#[test]
+fn parser_real_literal_test() {
+    let string = "f(1.0, 2.5, 1.5e-3, 2E10, 1e300)";
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let text = format!("{expr1}");
+    assert_eq!(text, "f(1.0, 2.5, 0.0015, 20000000000.0, 1e300)");
+    let mut par = Parser::new(&text);
+    let expr2 = parse_expr(&mut par).unwrap();
+    match (expr1, expr2) {
+        (Expr::App { args: args1, .. }, Expr::App { args: args2, .. }) => {
+            for (arg1, arg2) in args1.iter().zip(args2.iter()) {
+                match (arg1, arg2) {
+                    (Expr::Lit { lit: lit1, .. }, Expr::Lit { lit: lit2, .. }) => {
+                        assert_eq!(lit1, lit2);
+                    }
+                    _ => panic!("test failed!"),
+                }
+            }
+        }
+        _ => panic!("test failed!"),
+    }
+}
+
+#[test]
+fn parser_span_test() {
+    let string = r#"let r = f(1, g(2)).0;
+let h = fun(x, y) => case x of | Some(z) => { z } | None => { y } end;
+begin
+    fun k(x) => x
+in
+    k(r)
+end"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let text = |span: &Span| &string[span.start.abs..span.end.abs];
+    assert_eq!(text(expr.span()), string);
+    let (proj, cont) = match &expr {
+        Expr::Let { expr, cont, .. } => (expr.as_ref(), cont.as_ref()),
+        _ => panic!("test failed!"),
+    };
+    assert_eq!(text(proj.span()), "f(1, g(2)).0");
+    match proj {
+        Expr::Proj { expr: app, .. } => {
+            assert_eq!(text(app.span()), "f(1, g(2))");
+            match app.as_ref() {
+                Expr::App { args, .. } => assert_eq!(text(args[1].span()), "g(2)"),
+                _ => panic!("test failed!"),
+            }
+        }
+        _ => panic!("test failed!"),
+    }
+    let (fun, blk) = match cont {
+        Expr::Let { expr, cont, .. } => (expr.as_ref(), cont.as_ref()),
+        _ => panic!("test failed!"),
+    };
+    assert_eq!(
+        text(fun.span()),
+        "fun(x, y) => case x of | Some(z) => { z } | None => { y } end"
+    );
+    match fun {
+        Expr::Fun { body, .. } => {
+            assert_eq!(
+                text(body.span()),
+                "case x of | Some(z) => { z } | None => { y } end"
+            );
+            match body.as_ref() {
+                Expr::Case { rules, .. } => {
+                    assert_eq!(text(&rules[0].span), "Some(z) => { z }");
+                    assert_eq!(text(rules[0].patn.span()), "Some(z)");
+                }
+                _ => panic!("test failed!"),
+            }
+        }
+        _ => panic!("test failed!"),
+    }
+    assert_eq!(text(blk.span()), &string[string.find("begin").unwrap()..]);
+    match blk {
+        Expr::Blk { decls, cont, .. } => {
+            assert_eq!(text(decls[0].span()), "fun k(x) => x");
+            assert_eq!(text(cont.span()), "k(r)");
+        }
+        _ => panic!("test failed!"),
+    }
+}
+
+#[test]
+fn parser_infix_test() {
+    use itertools::Itertools;
+    fn sexp(expr: &Expr) -> String {
+        match expr {
+            Expr::Prim { prim, args, .. } => {
+                format!("({prim} {})", args.iter().map(sexp).join(" "))
+            }
+            other => format!("{other}"),
+        }
+    }
+    let parse = |string: &str| parse_expr(&mut Parser::new(string)).unwrap();
+
+    let cases = [
+        // precedence
+        ("1 + 2 * 3", "(iadd 1 (imul 2 3))"),
+        ("1 * 2 + 3", "(iadd (imul 1 2) 3)"),
+        ("a + 1 <= b * 2", "(icmple (iadd a 1) (imul b 2))"),
+        ("(1 + 2) * 3", "(imul (iadd 1 2) 3)"),
+        // associativity
+        ("1 - 2 - 3", "(isub (isub 1 2) 3)"),
+        ("a == b == c", "(icmpeq (icmpeq a b) c)"),
+        // unary minus and subtraction
+        ("x - -1", "(isub x (ineg 1))"),
+        ("-x - 1", "(isub (ineg x) 1)"),
+        ("-x * y", "(imul (ineg x) y)"),
+        ("- -x", "(ineg (ineg x))"),
+        ("!b != c", "(icmpne (bnot b) c)"),
+        ("x >= @iadd(y, 1)", "(icmpge x (iadd y 1))"),
+    ];
+    for (string, expect) in cases {
+        assert_eq!(sexp(&parse(string)), expect);
+    }
+    assert_eq!(format!("{}", parse("f(-x).0 > y")), "f(-x).0 > y");
+
+    // the printer adds parentheses only when they are needed
+    let cases = [
+        ("(1 + 2) * 3", "(1 + 2) * 3"),
+        ("1 + (2 * 3)", "1 + 2 * 3"),
+        ("(1 - 2) - 3", "1 - 2 - 3"),
+        ("1 - (2 - 3)", "1 - (2 - 3)"),
+        ("- (-x)", "-(-x)"),
+        ("-(x + 1)", "-(x + 1)"),
+        ("(f + g)(x)", "(f + g)(x)"),
+        ("a < b == (c < d)", "a < b == (c < d)"),
+    ];
+    for (string, expect) in cases {
+        let expr = parse(string);
+        let text = format!("{expr}");
+        assert_eq!(text, expect);
+        assert_eq!(sexp(&parse(&text)), sexp(&expr));
+    }
+}
+
+#[test]
+fn parser_placeholder_test() {
+    use crate::utils::printer::set_sugar;
+    let string = "map(_ + 1, xs)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let text = format!("{expr}");
+    assert!(text.starts_with("map(\\_1 -> "));
+    assert!(text.contains("_1 + 1"));
+
+    // the inner placeholder belongs to the application of `g`
+    let string = "f(_, g(_))";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Fun { pars, body, .. } = &expr else {
+        panic!("expected a lambda, found {expr}");
+    };
+    assert_eq!(pars.len(), 1);
+    let Expr::App { args, .. } = &**body else {
+        panic!("expected an application, found {body}");
+    };
+    assert_eq!(
+        args[0],
+        Expr::Var {
+            var: pars[0],
+            span: *args[0].span()
+        }
+    );
+    assert!(matches!(&args[1], Expr::Fun { pars, .. } if pars.len() == 1));
+
+    // an operator chain is one extent, parameters are in left-to-right order
+    let string = "let h = -_ * 2 + _; @iadd(_.0, 1)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    set_sugar(true);
+    let text = format!("{expr}");
+    set_sugar(false);
+    assert!(text.contains("let h = -_ * 2 + _;"));
+    assert!(text.contains("\\_3 -> "));
+
+    for string in ["let x = _; x", "(_)", "(_, 1)", "f(if c then _ else 0)"] {
+        let mut par = Parser::new(string);
+        assert!(matches!(
+            parse_expr(&mut par),
+            Err(ParseError::UnboundPlaceholder(_))
+        ));
+    }
+}
+
+#[test]
+fn parser_fixity_test() {
+    use super::renamer::Renamer;
+    let string = r#"
+begin
+    infixl 6 <+>;
+    infixr 5 ++;
+    fun (<+>)(a, b) => @iadd(a, b)
+    fun (++)(a, b) => @imul(a, b)
+in
+    let x = 1 <+> 2 * 3 <+> 4 ++ 5 ++ 6;
+    let y = (1 ++ 2) ++ 3 <+> 4;
+    (<+>)(x, y)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let text = format!("{expr}");
+    assert!(text.contains("infixl 6 <+>;"));
+    assert!(text.contains("fun (<+>)(a, b) = a + b;"));
+    assert!(text.contains("let x = 1 <+> 2 * 3 <+> 4 ++ 5 ++ 6;"));
+    assert!(text.contains("let y = (1 ++ 2) ++ 3 <+> 4;"));
+    // an application of the operator is printed in infix form as well
+    assert!(text.contains("  x <+> y\n"));
+
+    // the operators are resolved like other functions
+    let mut rnm = Renamer::new();
+    let expr = rnm.visit_expr(expr);
+    assert!(rnm.errors().is_empty());
+    let Expr::Blk { decls, cont, .. } = &expr else {
+        panic!("expected a block, found {expr}");
+    };
+    let Expr::Let { expr: bind, .. } = &**cont else {
+        panic!("expected a let, found {cont}");
+    };
+    let Expr::App { func, .. } = &**bind else {
+        panic!("expected an application, found {bind}");
+    };
+    assert_eq!(
+        **func,
+        Expr::Var {
+            var: decls[3].get_name(),
+            span: *func.span()
+        }
+    );
+
+    for string in [
+        "begin infixl 6 <+>; infixr 6 <+>; in 0 end",
+        "begin infixl 3 +; in 0 end",
+    ] {
+        let mut par = Parser::new(string);
+        assert!(matches!(
+            parse_expr(&mut par),
+            Err(ParseError::ConflictingFixity(_, _))
+        ));
+    }
+    let string = "begin infixl 10 <+>; in 0 end";
+    let mut par = Parser::new(string);
+    assert!(matches!(
+        parse_expr(&mut par),
+        Err(ParseError::PrecedenceOutOfRange(_))
+    ));
+}
+
+#[test]
+fn parser_annotation_test() {
+    let string = r#"
+begin
+    fun f(x: Int, y, z: (Real, fun(Int) -> Bool)) => x
+    fun g(): fun(Int) -> Int => 0
+in
+    f
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Blk { decls, .. } = &expr else {
+        panic!("expected a block, found {expr}");
+    };
+    let Decl::Func { pars, .. } = &decls[0] else {
+        panic!("expected a function, found {}", decls[0]);
+    };
+    let annotated: Vec<bool> = pars.iter().map(|(_, typ)| typ.is_some()).collect();
+    assert_eq!(annotated, [true, false, true]);
+    let text = format!("{expr}");
+    assert!(text.contains("fun f(x: Int, y, z: (Real, fn (Int) -> Bool)) = x;"));
+    assert!(text.contains("fun g(): fn (Int) -> Int = 0;"));
+    let Decl::Func { ret, .. } = &decls[1] else {
+        panic!("expected a function, found {}", decls[1]);
+    };
+    assert!(ret.is_some());
+}
+
+#[test]
+fn parser_ascription_test() {
+    let string = "(fun(x) => (x, 1) : fun(Int) -> (Int, Int))";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Anno {
+        expr: inner,
+        typ,
+        span,
+    } = &expr
+    else {
+        panic!("expected an ascription, found {expr}");
+    };
+    assert!(matches!(inner.as_ref(), Expr::Fun { .. }));
+    assert!(matches!(typ, Type::Fun { .. }));
+    assert_eq!((span.start.abs, span.end.abs), (0, string.len()));
+    assert_eq!(
+        format!("{expr}"),
+        "(\\x -> (x, 1) : fn (Int) -> (Int, Int))"
+    );
+
+    // without the type it is just parentheses
+    let mut par = Parser::new("(1)");
+    let expr = parse_expr(&mut par).unwrap();
+    assert!(matches!(expr, Expr::Lit { .. }));
+}
+
+#[test]
+fn parser_typed_hole_test() {
+    let string = "f(?arg, _ + ?step)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::App { args, .. } = &expr else {
+        panic!("expected an application, found {expr}");
+    };
+    let Expr::Hole { name, span } = &args[0] else {
+        panic!("expected a typed hole, found {}", args[0]);
+    };
+    assert_eq!(name.as_str(), "arg");
+    assert_eq!(&string[span.start.abs..span.end.abs], "?arg");
+    // a typed hole is an ordinary expression, unlike the placeholder `_`
+    assert!(matches!(args[1], Expr::Fun { .. }));
+    assert!(format!("{expr}").contains("?step"));
+}
+
+#[test]
+fn parser_forall_test() {
+    let string = r#"
+begin
+    extern ident : forall T. fun(T) -> T;
+    extern printf : forall A B. fun(Str, ...) -> B;
+    type Endo = forall T. fun(T) -> T;
+in
+    1
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Blk { decls, .. } = &expr else {
+        panic!("expected a block, found {expr}");
+    };
+    let Decl::Extern { typ, .. } = &decls[0] else {
+        panic!("expected an extern, found {}", decls[0]);
+    };
+    assert_eq!(format!("{typ}"), "forall T. fn (T) -> T");
+    // a variadic extern can still be quantified
+    let Decl::Extern { typ, .. } = &decls[1] else {
+        panic!("expected an extern, found {}", decls[1]);
+    };
+    assert_eq!(format!("{typ}"), "forall A B. fn (Str, ...) -> B");
+    assert!(matches!(
+        typ.unquantified(),
+        Type::Fun { variadic: true, .. }
+    ));
+    let Decl::Type { typ, .. } = &decls[2] else {
+        panic!("expected a type alias, found {}", decls[2]);
+    };
+    assert!(matches!(typ, Type::Scheme { vars, .. } if vars.len() == 1));
+
+    // a quantifier needs at least one variable
+    let mut par = Parser::new("begin extern f : forall. Int; in 1 end");
+    assert!(parse_expr(&mut par).is_err());
+}
+
+#[test]
+fn parser_let_test() {
+    // a chain of lets is an expression of its own, without a block around it
+    let string = "let x = 1; let y = @iadd(x, 2); @imul(x, y)";
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let Expr::Let {
+        bind,
+        expr,
+        cont,
+        span,
+    } = &expr1
+    else {
+        panic!("expected let, found {expr1}");
+    };
+    assert_eq!(format!("{bind}"), "x");
+    assert!(matches!(expr.as_ref(), Expr::Lit { .. }));
+    // the outer let spans from the keyword to the end of its body
+    assert_eq!(&string[span.start.abs..span.end.abs], string);
+    let Expr::Let {
+        bind, cont, span, ..
+    } = cont.as_ref()
+    else {
+        panic!("expected let, found {cont}");
+    };
+    assert_eq!(format!("{bind}"), "y");
+    assert_eq!(
+        &string[span.start.abs..span.end.abs],
+        "let y = @iadd(x, 2); @imul(x, y)"
+    );
+    assert!(matches!(cont.as_ref(), Expr::Prim { .. }));
+}
+
+#[test]
+fn parser_letrec_test() {
+    let string = r#"
+letrec
+    fun even(n) => if n == 0 then true else odd(n - 1)
+    fun odd(n) => if n == 0 then false else even(n - 1)
+in
+even(10)
+"#;
+    let mut par = Parser::new(string);
+    let expr1 = parse_expr(&mut par).unwrap();
+    let Expr::LetRec { decls, cont, .. } = &expr1 else {
+        panic!("expected letrec, found {expr1}");
+    };
+    assert_eq!(decls.len(), 2);
+    assert!(matches!(cont.as_ref(), Expr::App { .. }));
+
+    let text1 = format!("{expr1}");
+    assert!(text1.starts_with("letrec\n"));
+    assert!(text1.contains("\nin\neven(10)"));
+}
+
+#[test]
+fn parser_record_test() {
+    let string = r#"
+begin
+    data Point = { x: Int, y: Int }
+in
+    let p = { x = 1, y = 2 };
+    { p with y = p.x }.y
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Blk { decls, .. } = &expr else {
+        panic!("expected block, found {expr}");
+    };
+    assert!(matches!(&decls[0], Decl::Record { fields, .. } if fields.len() == 2));
+    let text = format!("{expr}");
+    assert!(text.contains("data Point = { x: Int, y: Int }"));
+    assert!(text.contains("let p = { x = 1, y = 2 };"));
+    assert!(text.contains("{ p with y = p.x }.y"));
+
+    // a block in braces is still a block
+    let mut par = Parser::new("{ x }");
+    assert!(matches!(parse_expr(&mut par), Ok(Expr::Var { .. })));
+
+    let mut par = Parser::new("begin data Box[T] = { item: T } in 0 end");
+    assert!(matches!(
+        parse_expr(&mut par),
+        Err(ParseError::PolymorphicRecord(_))
+    ));
+}
+
+#[test]
+fn parser_guard_test() {
+    let string = r#"
+case xs of
+| Cons(x, _) if @icmpgt(x, 0) => { x }
+| _ => { 0 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Case { rules, .. } = &expr else {
+        panic!("expected case, found {expr}");
+    };
+    assert!(rules[0].guard.is_some());
+    assert!(rules[1].guard.is_none());
+    assert!(format!("{expr}").contains("Cons(x, _) if x > 0 => x"));
+}
+
+#[test]
+fn parser_or_pattern_test() {
+    let string = r#"
+case xs of
+| Cons(0 | 1, _) | Nil => { 0 }
+| _ => { 1 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Case { rules, .. } = &expr else {
+        panic!("expected case, found {expr}");
+    };
+    let Pattern::Or { alts, .. } = &rules[0].patn else {
+        panic!("expected or-pattern, found {}", rules[0].patn);
+    };
+    assert_eq!(alts.len(), 2);
+    assert!(matches!(alts[0], Pattern::Cons { .. }));
+    assert_eq!(rules[0].patn.expand_or().len(), 3);
+    assert!(format!("{expr}").contains("Cons(0 | 1, _) | Nil => 0"));
+}
+
+#[test]
+fn parser_as_pattern_test() {
+    let string = r#"
+case xs of
+| all @ Cons(x, tl@Cons(_, _)) | all@Nil => { all }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Case { rules, .. } = &expr else {
+        panic!("expected case, found {expr}");
+    };
+    let Pattern::Or { alts, .. } = &rules[0].patn else {
+        panic!("expected or-pattern, found {}", rules[0].patn);
+    };
+    assert!(matches!(alts[0], Pattern::As { .. }));
+    let vars: Vec<String> = rules[0]
+        .patn
+        .get_freevars()
+        .iter()
+        .map(|var| var.to_string())
+        .collect();
+    assert_eq!(vars, ["all", "tl", "x"]);
+    assert!(format!("{expr}").contains("all @ Cons(x, tl @ Cons(_, _)) | all @ Nil => all"));
+}
+
+#[test]
+fn parser_variadic_test() {
+    let string = r#"
+begin
+    extern log_values : fun(Ptr, ...) -> ();
+in
+    #log_values(#format(0), 1, 2.0)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Blk { decls, .. } = &expr else {
+        panic!("expected block, found {expr}");
+    };
+    assert!(matches!(
+        &decls[0],
+        Decl::Extern { typ: Type::Fun { pars, variadic: true, .. }, .. } if pars.len() == 1
+    ));
+    assert!(format!("{expr}").contains("(Ptr, ...) -> ()"));
+
+    // only an extern can be variadic, and `...` must come last
+    for string in [
+        "begin fun f(g: fun(Int, ...) -> ()) => 0 in 0 end",
+        "begin extern f : fun(..., Int) -> (); in 0 end",
+        "begin extern f : fun(fun(Int, ...) -> ()) -> (); in 0 end",
+    ] {
+        let mut par = Parser::new(string);
+        assert!(parse_expr(&mut par).is_err(), "{string}");
+    }
+}
+
+#[test]
+fn parser_list_test() {
+    let string = "[1, 2, 3]";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    assert_eq!(format!("{expr}"), "Cons(1, Cons(2, Cons(3, Nil())))");
+
+    let string = "[]";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    assert_eq!(format!("{expr}"), "Nil()");
+
+    // `::` associates to the right, and list patterns can be nested in it
+    let string = r#"
+case xs of
+| [] => { 0 }
+| [x, y] => { 2 }
+| x :: y :: rest => { 3 }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Case { rules, .. } = &expr else {
+        panic!("expected a case, found {expr}");
+    };
+    let pats: Vec<String> = rules.iter().map(|rule| format!("{}", rule.patn)).collect();
+    assert_eq!(
+        pats,
+        ["Nil", "Cons(x, Cons(y, Nil))", "Cons(x, Cons(y, rest))"]
+    );
+}
+
+#[test]
+fn parser_clauses_test() {
+    let string = r#"
+begin
+    fun take(0, _) = Nil
+    fun take(_, Nil) = Nil
+    fun take(n, Cons(x, xs)) = Cons(x, take(@isub(n, 1), xs))
+    fun len(Nil) = 0
+    fun len(Cons(_, xs)) = @iadd(1, len(xs))
+in
+    len(take(2, xs))
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Blk { decls, .. } = &expr else {
+        panic!("expected block, found {expr}");
+    };
+    // adjacent clauses of the same name are one function, in clause order
+    assert_eq!(decls.len(), 2);
+    let Decl::Func { pars, body, .. } = &decls[0] else {
+        panic!("expected function, found {}", decls[0]);
+    };
+    assert_eq!(pars.len(), 2);
+    let Expr::Case { expr, rules, .. } = body.as_ref() else {
+        panic!("expected case, found {body}");
+    };
+    assert!(matches!(expr.as_ref(), Expr::Tuple { elems, .. } if elems.len() == 2));
+    let patns: Vec<String> = rules.iter().map(|rule| rule.patn.to_string()).collect();
+    assert_eq!(patns, ["(0, _)", "(_, Nil)", "(n, Cons(x, xs))"]);
+    // with a single parameter, the parameter itself is matched
+    let Decl::Func { body, .. } = &decls[1] else {
+        panic!("expected function, found {}", decls[1]);
+    };
+    assert!(
+        matches!(body.as_ref(), Expr::Case { expr, .. } if matches!(expr.as_ref(), Expr::Var { .. }))
+    );
+
+    // every clause must have as many patterns as the first one
+    let string = "fun f(Nil, y) = y\nfun f(Cons(x, xs)) = x";
+    let mut par = Parser::new(string);
+    let Err(ParseError::ClauseArity(span, 2, 1)) = parse_decl(&mut par) else {
+        panic!("test failed!");
+    };
+    assert_eq!(
+        &string[span.start.abs..span.end.abs],
+        "fun f(Cons(x, xs)) = x"
+    );
+}
+
+#[test]
+fn parser_recovery_test() {
+    let string = r#"
+begin
+    fun broken1(x) => @iadd(x, )
+    fun broken2(x y) => x
+    data Broken3 =
+    | A(Int
+    end
+    fun good(x) => @iadd(x, 1)
+in
+    good(1)
+end
+"#;
+    let mut par = Parser::new(string);
+    let (expr, errors) = parse_program(&mut par);
+    // every broken declaration is reported, and the valid one is kept
+    assert_eq!(errors.len(), 3);
+    let rows: Vec<usize> = errors
+        .iter()
+        .map(|err| match err {
+            ParseError::Unexpected(span, _, _) | ParseError::UnexpectedMany(span, _, _) => {
+                span.start.row
+            }
+            _ => panic!("test failed!"),
+        })
+        .collect();
+    assert_eq!(rows, [2, 3, 6]);
+    let Some(Expr::Blk { decls, .. }) = expr else {
+        panic!("test failed!");
+    };
+    assert_eq!(decls.len(), 1);
+    assert_eq!(decls[0].get_name().to_string(), "good");
+
+    // without recovery, the first error fails the parse
+    let mut par = Parser::new(string);
+    assert!(parse_expr(&mut par).is_err());
+
+    // an error outside of the declarations can't be recovered from
+    let mut par = Parser::new("begin fun f(x) => x in f(1 end");
+    let (expr, errors) = parse_program(&mut par);
+    assert!(expr.is_none());
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn parser_module_test() {
+    let string = r#"
+module Shapes;
+import Util;
+import Geometry;
+
+fun area(s) => Util.square(Geometry.Side(s))
+fun perimeter(s) => Geometry.Side (s).x
+"#;
+    let mut par = Parser::new(string);
+    let header = parse_header(&mut par).unwrap();
+    assert_eq!(header.module.unwrap().0.as_str(), "Shapes");
+    let imports: Vec<&str> = header
+        .imports
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(imports, ["Util", "Geometry"]);
+    let (span1, span2) = (header.imports[0].1, header.imports[1].1);
+    assert_eq!(&string[span1.start.abs..span1.end.abs], "import Util;");
+    assert_eq!(&string[span2.start.abs..span2.end.abs], "import Geometry;");
+
+    let (decls, errors) = parse_module(&mut par);
+    assert!(errors.is_empty());
+    let decls = decls.unwrap();
+    // a qualified name has no spaces around the dot
+    let Decl::Func { body, .. } = &decls[0] else {
+        panic!("expected a function");
+    };
+    let Expr::App { func, args, .. } = body.as_ref() else {
+        panic!("expected an application, found {body}");
+    };
+    assert!(matches!(func.as_ref(), Expr::Var { var, .. } if var.name.as_str() == "Util.square"));
+    assert!(matches!(&args[0], Expr::Cons { cons, .. } if cons.name.as_str() == "Geometry.Side"));
+    let Decl::Func { body, .. } = &decls[1] else {
+        panic!("expected a function");
+    };
+    assert!(matches!(body.as_ref(), Expr::Field { .. }));
+
+    // a module has nothing but declarations
+    let mut par = Parser::new("module Main;\nfun f() => 1\nf()");
+    parse_header(&mut par).unwrap();
+    let (decls, errors) = parse_module(&mut par);
+    assert!(decls.is_none());
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn parser_try_test() {
+    let string = r#"
+try { f(1) } catch
+| Err(0) => { 1 }
+| Err(code) if @icmpgt(code, 9) => { code }
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Try { expr, handlers, .. } = &expr else {
+        panic!("expected a try, found {expr}");
+    };
+    assert!(matches!(expr.as_ref(), Expr::App { .. }));
+    assert_eq!(handlers.len(), 2);
+    assert!(handlers[1].guard.is_some());
+
+    // the expression is in braces, and at least one handler is needed
+    let mut par = Parser::new("try f(1) catch | Err(e) => { 0 } end");
+    assert!(parse_expr(&mut par).is_err());
+    let mut par = Parser::new("try { f(1) } catch end");
+    assert!(parse_expr(&mut par).is_err());
+}
+
+#[test]
+fn parser_value_test() {
+    let string = r#"
+begin
+    let pi: Real = 3.14159;
+    fun area(r) => @rmul(pi, @rmul(r, r))
+in
+    area(2.0)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Blk { decls, .. } = &expr else {
+        panic!("expected a block, found {expr}");
+    };
+    assert!(matches!(&decls[0], Decl::Val { typ: Some(_), .. }));
+    assert!(matches!(&decls[1], Decl::Func { .. }));
+
+    // a block starting with a let expression is still a let expression
+    let mut par = Parser::new("begin let x = 1; x end");
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Blk { decls, cont, .. } = &expr else {
+        panic!("expected a block, found {expr}");
+    };
+    assert!(decls.is_empty());
+    assert!(matches!(cont.as_ref(), Expr::Let { .. }));
+}
+
+#[test]
+fn parser_let_pattern_test() {
+    // a pattern other than a variable makes a case of the let
+    let mut par = Parser::new("let (a, b) = (1, 2); a");
+    let expr = parse_expr(&mut par).unwrap();
+    let Expr::Case {
+        rules,
+        is_let: true,
+        ..
+    } = &expr
+    else {
+        panic!("expected a let case, found {expr}");
+    };
+    assert_eq!(rules.len(), 1);
+    assert!(matches!(rules[0].patn, Pattern::Tuple { .. }));
+    assert!(matches!(rules[0].body, Expr::Var { .. }));
+    assert_eq!(format!("{expr}"), "let (a, b) = (1, 2);\na");
+
+    let mut par = Parser::new("let x = 1; x");
+    let expr = parse_expr(&mut par).unwrap();
+    assert!(matches!(expr, Expr::Let { .. }));
+}
+
+#[test]
+fn parser_lambda_test() {
+    // the same tree, except for the spans
+    fn same(expr1: &Expr, expr2: &Expr) -> bool {
+        let this = match (expr1, expr2) {
+            (Expr::Fun { pars: pars1, .. }, Expr::Fun { pars: pars2, .. }) => pars1 == pars2,
+            (Expr::Var { var: var1, .. }, Expr::Var { var: var2, .. }) => var1 == var2,
+            (Expr::Lit { lit: lit1, .. }, Expr::Lit { lit: lit2, .. }) => lit1 == lit2,
+            (Expr::Prim { prim: prim1, .. }, Expr::Prim { prim: prim2, .. }) => prim1 == prim2,
+            (expr1, expr2) => std::mem::discriminant(expr1) == std::mem::discriminant(expr2),
+        };
+        let subs1 = expr1.subexprs();
+        let subs2 = expr2.subexprs();
+        this && subs1.len() == subs2.len() && subs1.iter().zip(subs2).all(|(e1, e2)| same(e1, e2))
+    }
+    let parse = |string: &str| parse_expr(&mut Parser::new(string)).unwrap();
+    for (short, long) in [
+        (r"\x y -> @iadd(x, y)", "fun(x, y) => @iadd(x, y)"),
+        (r"\x -> \y -> (x, y)", "fun(x) => fun(y) => (x, y)"),
+        (r"\ -> 42", "fun() => 42"),
+        (r"f(\x -> x, 1)", "f(fun(x) => x, 1)"),
+        (r"\x -> let y = x; y", "fun(x) => let y = x; y"),
+    ] {
+        let (short, long) = (parse(short), parse(long));
+        assert!(same(&short, &long), "{short} is not {long}");
+    }
+
+    // the short form is printed when the body is simple
+    assert_eq!(
+        format!("{}", parse("fun(x) => fun(y) => (x, y)")),
+        r"\x -> \y -> (x, y)"
+    );
+    assert_eq!(
+        format!("{}", parse("fun(x) => let y = x; y")),
+        "fn (x) {\n  let y = x;\n  y\n}"
+    );
+
+    // `\` followed by another operator character is still an operator
+    let kinds: Vec<TokenKind> = Lexer::new(r"a \\ b").map(|tok| tok.kind).collect();
+    assert_eq!(kinds[1], TokenKind::Oper);
+}
+
+#[test]
+fn parser_list_extremum_test() {
+    let parse = |string: &str| parse_expr(&mut Parser::new(string));
+    let expanded = parse(
+        r#"
+let xs = f(1);
+letrec
+    fun go(acc, ys) =>
+        case ys of
+        | Cons(y, rest) => { go(if @icmpgt(y, acc) then y else acc, rest) }
+        | Nil => { acc }
+        end
+in
+    case xs of
+    | Cons(y, rest) => { Some(go(y, rest)) }
+    | Nil => { None }
+    end
+"#,
+    )
+    .unwrap();
+    let builtin = parse("@listmax(f(1))").unwrap();
+    assert_eq!(format!("{builtin}"), format!("{expanded}"));
+
+    let Err(ParseError::BuiltinArity(_, 1, 2)) = parse("@listmin(xs, ys)") else {
+        panic!("test failed!");
+    };
+}
+
+#[test]
+fn parser_pipe_test() {
+    let parse = |string: &str| parse_expr(&mut Parser::new(string)).unwrap();
+    // the left operand is the last argument of an application, or the only one
+    let Expr::App { func, args, .. } = parse("x |> f |> g(1)") else {
+        panic!("test failed!");
+    };
+    assert!(matches!(&*func, Expr::Var { var, .. } if var.name.as_ref() == "g"));
+    assert!(matches!(&args[..], [Expr::Lit { .. }, Expr::App { .. }]));
+    let Expr::ExtCall { args, .. } = parse("x |> #show()") else {
+        panic!("test failed!");
+    };
+    assert_eq!(args.len(), 1);
+
+    // it binds looser than the builtin operators
+    let Expr::App { args, .. } = parse("1 + 2 * 3 |> f(y - 1)") else {
+        panic!("test failed!");
+    };
+    assert!(matches!(
+        &args[..],
+        [
+            Expr::Prim {
+                prim: Builtin::ISub,
+                ..
+            },
+            Expr::Prim {
+                prim: Builtin::IAdd,
+                ..
+            }
+        ]
+    ));
+    let Expr::Prim {
+        prim: Builtin::IAdd,
+        args,
+        ..
+    } = parse("(x |> f) + 1")
+    else {
+        panic!("test failed!");
+    };
+    assert!(matches!(&args[0], Expr::App { .. }));
+
+    // pipelines are printed back as they are written, calls are not turned into them
+    for string in [
+        "x |> f |> g(1)",
+        "1 + 2 * 3 |> f(y - 1)",
+        "(x |> f) + 1",
+        "x |> (\\y -> y)",
+        "g(1, f(x))",
+    ] {
+        assert_eq!(format!("{}", parse(string)), string);
+    }
+
+    // its fixity can't be changed
+    let mut par = Parser::new("infixl 3 |>;");
+    assert!(matches!(
+        parse_decl(&mut par),
+        Err(ParseError::ConflictingFixity(_, None))
+    ));
+}
@@ -1,14 +1,16 @@
+use crate::utils::source_map::FileId;
 use std::default::Default;
 use std::fmt;
 use std::hash::Hash;
 
-/// module `position`
-/// In this module we define structure `Position`,
-/// structure `Span` and trait `Spanned`
+// module `position`
+// In this module we define structure `Position`,
+// structure `Span` and trait `Spanned`
 
 /// A `Position` is a location in the source code.
-/// The `abs` field is the absolute index of the character,
-/// while `row` and `col` fields are line number and column number
+/// The `abs` field is the absolute byte index of the character,
+/// while `row` and `col` fields are line number and column number.
+/// Columns are counted in `char`s, so they stay right after multi-byte characters.
 ///
 /// # Example
 ///
@@ -19,8 +21,7 @@ use std::hash::Hash;
 /// ```
 /// Here the letter 'i' in word "This" has Position
 /// Position { row: 2, col: 3, abs: 16 }
-
-#[derive(Clone, Copy, Default, Hash)]
+#[derive(Clone, Copy, Default)]
 pub struct Position {
     pub row: usize,
     pub col: usize,
@@ -28,17 +29,23 @@ pub struct Position {
 }
 
 impl PartialEq for Position {
-    // Only comparing field `abs` is enough. Same in `PartialOrd` and `Ord`
+    // Only comparing field `abs` is enough. Same in `Hash`, `PartialOrd` and `Ord`
     fn eq(&self, other: &Self) -> bool {
         self.abs == other.abs
     }
 }
 
+impl Hash for Position {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.abs.hash(state)
+    }
+}
+
 impl Eq for Position {}
 
 impl PartialOrd for Position {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.abs.partial_cmp(&other.abs)
+        Some(self.cmp(other))
     }
 }
 
@@ -67,21 +74,40 @@ impl fmt::Display for Position {
 }
 
 /// A `Span` is a structure of two position.
-/// It marks the `start` and the `end` of a slice in source code.
+/// It marks the `start` and the `end` of a slice in source code,
+/// and `file` tells which file of the `SourceMap` it belongs to.
 #[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
 pub struct Span {
     pub start: Position,
     pub end: Position,
+    pub file: FileId,
 }
 
 impl Span {
     pub fn new(start: Position, end: Position) -> Span {
-        Span { start, end }
+        Span {
+            start,
+            end,
+            file: FileId::default(),
+        }
+    }
+    pub fn in_file(self, file: FileId) -> Span {
+        Span { file, ..self }
     }
-    pub fn merge(lhs: &Span, rhs: &Span) -> Span {
+    /// the smallest span covering both `lhs` and `rhs`, which must be in the same file
+    pub fn merge(lhs: Span, rhs: Span) -> Span {
+        debug_assert_eq!(lhs.file, rhs.file);
         let start = lhs.start.min(rhs.start);
         let end = lhs.end.max(rhs.end);
-        Span { start, end }
+        Span {
+            start,
+            end,
+            file: lhs.file,
+        }
+    }
+    /// the smallest span covering all `spans`, `None` if there is no span
+    pub fn merge_all(spans: impl IntoIterator<Item = Span>) -> Option<Span> {
+        spans.into_iter().reduce(Span::merge)
     }
 }
 
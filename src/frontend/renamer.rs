@@ -1,13 +1,33 @@
 use super::*;
 use crate::utils::env_map::EnvMap;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 pub struct Renamer {
     /// map a dummy identifier to an unique Identifier
     val_map: EnvMap<Ident, Ident>,
     typ_map: EnvMap<Ident, Ident>,
     cons_map: EnvMap<Ident, Ident>,
+    field_map: EnvMap<Ident, Ident>,
     ext_set: HashSet<InternStr>,
+    /// map an unique record to its unique fields, in declaration order
+    record_fields: HashMap<Ident, Vec<Ident>>,
+    /// map an unique field to the unique record it belongs to
+    field_owner: HashMap<Ident, Ident>,
+    /// the variables of the first alternative of the enclosing or-patterns,
+    /// the other alternatives bind the same unique identifiers
+    alt_binds: HashMap<Ident, Ident>,
+    /// map an unique constructor to the abstract type its data represents
+    abstract_cons: HashMap<Ident, Ident>,
+    /// the abstract types whose representation is visible, those of the blocks
+    /// whose declarations are being visited
+    transparent: HashSet<Ident>,
+    /// map an unique declaration of a module to the name of its module
+    module_of: HashMap<Ident, InternStr>,
+    /// map an unique declaration of a module to the modules declaring its name before,
+    /// then its own, when its bare name hides theirs
+    ambiguous: HashMap<Ident, Vec<InternStr>>,
+    /// the module whose declarations are being visited
+    current: Option<InternStr>,
     error: Vec<RenameError>,
 }
 
@@ -19,6 +39,31 @@ pub enum RenameError {
     UndefinedExternalFunction(Span, InternStr),
     MultipuleDefinition(Span, Ident),
     MultipuleExternalDefinition(Span, InternStr),
+    UnboundedField(Span, Ident),
+    /// a field declared or initialized twice
+    DuplicateField(Span, Ident),
+    /// a record literal without some field of the record
+    MissingField(Span, Ident),
+    /// a field of another record than the first field of the literal or update
+    FieldOfOtherRecord(Span, Ident),
+    /// a variable bound by the alternative at `span` of an or-pattern, but not by another one
+    OrPatternBinding(Span, Ident),
+    /// a value of a block needed to compute itself, directly or through functions
+    RecursiveValue(Span, Ident),
+    /// an abstract type without a `type` or `data` of the same name in its block
+    AbstractWithoutRepr(Span, Ident),
+    /// a constructor of the abstract type, used outside of the block declaring it
+    AbstractConstruct(Span, Ident, Ident),
+    /// a pattern of a constructor of the abstract type, outside of the block declaring it
+    AbstractMatch(Span, Ident, Ident),
+    /// a bare name declared by several of the modules in scope
+    AmbiguousImport(Span, Ident, Vec<InternStr>),
+}
+
+impl Default for Renamer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Renamer {
@@ -27,7 +72,16 @@ impl Renamer {
             val_map: EnvMap::new(),
             typ_map: EnvMap::new(),
             cons_map: EnvMap::new(),
+            field_map: EnvMap::new(),
             ext_set: HashSet::new(),
+            record_fields: HashMap::new(),
+            field_owner: HashMap::new(),
+            alt_binds: HashMap::new(),
+            abstract_cons: HashMap::new(),
+            transparent: HashSet::new(),
+            module_of: HashMap::new(),
+            ambiguous: HashMap::new(),
+            current: None,
             error: Vec::new(),
         }
     }
@@ -36,12 +90,14 @@ impl Renamer {
         self.val_map.enter_scope();
         self.typ_map.enter_scope();
         self.cons_map.enter_scope();
+        self.field_map.enter_scope();
     }
 
     fn leave_scope(&mut self) {
         self.val_map.leave_scope();
         self.typ_map.leave_scope();
         self.cons_map.leave_scope();
+        self.field_map.leave_scope();
     }
 
     fn intro_val_var(&mut self, var: Ident) -> Ident {
@@ -62,28 +118,83 @@ impl Renamer {
         ident
     }
 
-    fn lookup_val_var(&mut self, ident: Ident) -> Option<Ident> {
+    fn intro_field_var(&mut self, var: Ident) -> Ident {
+        let ident = var.uniquify();
+        self.field_map.insert(var, ident);
+        ident
+    }
+
+    fn lookup_val_var(&self, ident: Ident) -> Option<Ident> {
         self.val_map.get(&ident).copied()
     }
 
-    fn lookup_typ_var(&mut self, ident: Ident) -> Option<Ident> {
+    fn lookup_typ_var(&self, ident: Ident) -> Option<Ident> {
         self.typ_map.get(&ident).copied()
     }
 
-    fn lookup_cons_var(&mut self, ident: Ident) -> Option<Ident> {
+    fn lookup_cons_var(&self, ident: Ident) -> Option<Ident> {
         self.cons_map.get(&ident).copied()
     }
 
+    fn lookup_field_var(&self, ident: Ident) -> Option<Ident> {
+        self.field_map.get(&ident).copied()
+    }
+
+    /// a declaration of a module introduced by its bare name, which may hide the same name
+    /// from the modules before it
+    fn intro_module_var(&mut self, module: InternStr, prev: Option<Ident>, ident: Ident) {
+        let owner = prev.and_then(|prev| Some((prev, *self.module_of.get(&prev)?)));
+        if let Some((prev, owner)) = owner {
+            if owner != module {
+                let mut modules = self
+                    .ambiguous
+                    .get(&prev)
+                    .cloned()
+                    .unwrap_or_else(|| vec![owner]);
+                modules.push(module);
+                self.ambiguous.insert(ident, modules);
+            }
+        }
+        self.module_of.insert(ident, module);
+    }
+
+    /// a bare name is ambiguous outside of its own module when several modules declare it,
+    /// only its qualified names can be used there
+    fn check_ambiguous(&mut self, span: Span, name: Ident, ident: Ident) {
+        if name.name.as_ref().contains('.') || self.current == self.module_of.get(&ident).copied() {
+            return;
+        }
+        if let Some(modules) = self.ambiguous.get(&ident) {
+            self.error
+                .push(RenameError::AmbiguousImport(span, name, modules.clone()));
+        }
+    }
+
+    /// the abstract type of an unique constructor, if its representation is hidden here
+    fn hidden_data(&self, cons: Ident) -> Option<Ident> {
+        self.abstract_cons
+            .get(&cons)
+            .copied()
+            .filter(|typ| !self.transparent.contains(typ))
+    }
+
+    pub fn errors(&self) -> &[RenameError] {
+        &self.error
+    }
+
     pub fn visit_expr(&mut self, expr: Expr) -> Expr {
         match expr {
             Expr::Lit { lit, span } => Expr::Lit { lit, span },
+            Expr::Hole { name, span } => Expr::Hole { name, span },
             Expr::Var { var, span } => {
                 assert!(var.is_dummy());
+                let name = var;
                 let var = self.lookup_val_var(var).unwrap_or_else(|| {
                     self.error
                         .push(RenameError::UnboundedValueVariable(span, var));
                     var
                 });
+                self.check_ambiguous(span, name, var);
                 Expr::Var { var, span }
             }
             Expr::Prim { prim, args, span } => {
@@ -117,14 +228,64 @@ impl Renamer {
                 Expr::ExtCall { func, args, span }
             }
             Expr::Cons { cons, args, span } => {
+                let name = cons;
                 let cons = self.lookup_cons_var(cons).unwrap_or_else(|| {
                     self.error
                         .push(RenameError::UnboundedConstructorVariable(span, cons));
                     cons
                 });
+                self.check_ambiguous(span, name, cons);
+                if let Some(typ) = self.hidden_data(cons) {
+                    self.error
+                        .push(RenameError::AbstractConstruct(span, cons, typ));
+                }
                 let args = args.into_iter().map(|arg| self.visit_expr(arg)).collect();
                 Expr::Cons { cons, args, span }
             }
+            Expr::Tuple { elems, span } => {
+                let elems = elems
+                    .into_iter()
+                    .map(|elem| self.visit_expr(elem))
+                    .collect();
+                Expr::Tuple { elems, span }
+            }
+            Expr::Proj { expr, index, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                Expr::Proj { expr, index, span }
+            }
+            Expr::Record { fields, span } => {
+                let fields = self.visit_field_inits(fields);
+                // every field of the record must be initialized
+                let owner = fields
+                    .first()
+                    .and_then(|init| self.field_owner.get(&init.field));
+                if let Some(owner) = owner {
+                    for field in self.record_fields[owner].iter() {
+                        if fields.iter().all(|init| init.field != *field) {
+                            self.error.push(RenameError::MissingField(span, *field));
+                        }
+                    }
+                }
+                Expr::Record { fields, span }
+            }
+            Expr::Field { expr, field, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                let field = self.lookup_field_var(field).unwrap_or_else(|| {
+                    self.error.push(RenameError::UnboundedField(span, field));
+                    field
+                });
+                Expr::Field { expr, field, span }
+            }
+            Expr::Update { expr, fields, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                let fields = self.visit_field_inits(fields);
+                Expr::Update { expr, fields, span }
+            }
+            Expr::Anno { expr, typ, span } => {
+                let expr = Box::new(self.visit_expr(*expr));
+                let typ = self.visit_type(typ);
+                Expr::Anno { expr, typ, span }
+            }
             Expr::Let {
                 bind,
                 expr,
@@ -144,80 +305,379 @@ impl Renamer {
                     span,
                 }
             }
-            Expr::Case { expr, rules, span } => {
+            Expr::Ifte {
+                cond,
+                trbr,
+                flbr,
+                span,
+            } => {
+                let cond = Box::new(self.visit_expr(*cond));
+                let trbr = Box::new(self.visit_expr(*trbr));
+                let flbr = Box::new(self.visit_expr(*flbr));
+                Expr::Ifte {
+                    cond,
+                    trbr,
+                    flbr,
+                    span,
+                }
+            }
+            Expr::Case {
+                expr,
+                rules,
+                is_let,
+                span,
+            } => {
                 let expr = Box::new(self.visit_expr(*expr));
                 let rules = rules
                     .into_iter()
                     .map(|rule| self.visit_rule(rule))
                     .collect();
-                Expr::Case { expr, rules, span }
+                Expr::Case {
+                    expr,
+                    rules,
+                    is_let,
+                    span,
+                }
             }
+            Expr::Try {
+                expr,
+                handlers,
+                span,
+            } => self.visit_expr(desugar_try(*expr, handlers, span)),
             Expr::Blk { decls, cont, span } => {
-                self.enter_scope();
-                // todo: multiple definition error
-                for decl in &decls {
-                    assert!(decl.get_name().is_dummy());
-                    match decl {
-                        Decl::Func { name, .. } => {
-                            self.intro_val_var(*name);
+                let (decls, cont) = self.visit_decls(None, decls, *cont);
+                let (decls, cont) = self.order_values(decls, cont, span);
+                Expr::Blk { decls, cont, span }
+            }
+            Expr::LetRec { decls, cont, span } => {
+                let (decls, cont) = self.visit_decls(None, decls, *cont);
+                let (decls, cont) = self.order_values(decls, cont, span);
+                Expr::LetRec { decls, cont, span }
+            }
+            Expr::Module {
+                name,
+                decls,
+                cont,
+                span,
+            } => {
+                let (decls, cont) = self.visit_decls(Some(name), decls, *cont);
+                let (decls, cont) = self.order_values(decls, cont, span);
+                Expr::Blk { decls, cont, span }
+            }
+        }
+    }
+
+    /// fields of a record literal or update, they must belong to the same record
+    fn visit_field_inits(&mut self, fields: Vec<FieldInit>) -> Vec<FieldInit> {
+        let mut owner = None;
+        let mut seen = HashSet::new();
+        fields
+            .into_iter()
+            .map(|FieldInit { field, expr, span }| {
+                if !seen.insert(field) {
+                    self.error.push(RenameError::DuplicateField(span, field));
+                }
+                let field = match self.lookup_field_var(field) {
+                    Some(field) => {
+                        let owner2 = self.field_owner[&field];
+                        if *owner.get_or_insert(owner2) != owner2 {
+                            self.error
+                                .push(RenameError::FieldOfOtherRecord(span, field));
                         }
-                        Decl::Data { name, vars, .. } => {
-                            self.intro_typ_var(*name);
-                            for var in vars {
-                                self.intro_cons_var(var.cons);
-                            }
+                        field
+                    }
+                    None => {
+                        self.error.push(RenameError::UnboundedField(span, field));
+                        field
+                    }
+                };
+                let expr = self.visit_expr(expr);
+                FieldInit { field, expr, span }
+            })
+            .collect()
+    }
+
+    /// all the names declared are in scope of every declaration and `cont`,
+    /// the functions and constructors of a module also by their qualified names
+    fn visit_decls(
+        &mut self,
+        module: Option<InternStr>,
+        decls: Vec<Decl>,
+        cont: Expr,
+    ) -> (Vec<Decl>, Box<Expr>) {
+        self.enter_scope();
+        // an abstract type and its representation are the same name
+        let mut abstracts = Vec::new();
+        for decl in &decls {
+            if let Decl::Abstract { name, span, .. } = decl {
+                if !decls.iter().any(|repr| {
+                    matches!(repr, Decl::Type { .. } | Decl::Data { .. })
+                        && repr.get_name() == *name
+                }) {
+                    self.error
+                        .push(RenameError::AbstractWithoutRepr(*span, *name));
+                }
+                abstracts.push((*name, self.intro_typ_var(*name)));
+            }
+        }
+        let abstract_of = |name: &Ident| {
+            abstracts
+                .iter()
+                .find(|(dummy, _)| dummy == name)
+                .map(|(_, ident)| *ident)
+        };
+        // todo: multiple definition error
+        for decl in &decls {
+            assert!(decl.get_name().is_dummy());
+            match decl {
+                Decl::Func { name, .. } | Decl::Val { name, .. } => {
+                    let prev = self.lookup_val_var(*name);
+                    let ident = self.intro_val_var(*name);
+                    if let Some(module) = module {
+                        self.val_map.insert(qualified(module, *name), ident);
+                        self.intro_module_var(module, prev, ident);
+                    }
+                }
+                Decl::Data { name, vars, .. } => {
+                    let hidden = abstract_of(name);
+                    if hidden.is_none() {
+                        self.intro_typ_var(*name);
+                    }
+                    for var in vars {
+                        let prev = self.lookup_cons_var(var.cons);
+                        let ident = self.intro_cons_var(var.cons);
+                        if let Some(module) = module {
+                            self.cons_map.insert(qualified(module, var.cons), ident);
+                            self.intro_module_var(module, prev, ident);
                         }
-                        Decl::Type { name, .. } => {
-                            self.intro_typ_var(*name);
+                        if let Some(typ) = hidden {
+                            self.abstract_cons.insert(ident, typ);
                         }
-                        Decl::Extern { name, span, .. } => {
-                            if self.ext_set.contains(&name) {
-                                self.error
-                                    .push(RenameError::MultipuleExternalDefinition(*span, *name));
+                    }
+                }
+                Decl::Record { name, fields, .. } => {
+                    let record = self.intro_typ_var(*name);
+                    let mut seen = HashSet::new();
+                    let fields: Vec<Ident> = fields
+                        .iter()
+                        .map(|FieldDecl { field, span, .. }| {
+                            if !seen.insert(*field) {
+                                self.error.push(RenameError::DuplicateField(*span, *field));
                             }
-                            self.ext_set.insert(*name);
-                        }
+                            let field = self.intro_field_var(*field);
+                            self.field_owner.insert(field, record);
+                            field
+                        })
+                        .collect();
+                    self.record_fields.insert(record, fields);
+                }
+                Decl::Type { name, .. } => {
+                    if abstract_of(name).is_none() {
+                        self.intro_typ_var(*name);
+                    }
+                }
+                Decl::Abstract { .. } => {}
+                Decl::Extern { name, span, .. } => {
+                    if self.ext_set.contains(name) {
+                        self.error
+                            .push(RenameError::MultipuleExternalDefinition(*span, *name));
+                    }
+                    self.ext_set.insert(*name);
+                }
+                Decl::Fixity { .. } => {}
+            }
+        }
+        self.transparent
+            .extend(abstracts.iter().map(|(_, ident)| *ident));
+        let outer = self.current;
+        self.current = module.or(outer);
+        let decls = decls
+            .into_iter()
+            .map(|decl| self.visit_decl(decl))
+            .collect();
+        self.current = outer;
+        for (_, ident) in abstracts.iter() {
+            self.transparent.remove(ident);
+        }
+        let cont = Box::new(self.visit_expr(cont));
+        self.leave_scope();
+        (decls, cont)
+    }
+
+    /// the values of a block are computed before the rest of it, in the order they depend
+    /// on each other. they become `let`s around the groups of functions they need or are
+    /// needed by, and a value needed to compute itself, even through a function, is an error
+    fn order_values(
+        &mut self,
+        decls: Vec<Decl>,
+        cont: Box<Expr>,
+        span: Span,
+    ) -> (Vec<Decl>, Box<Expr>) {
+        if !decls.iter().any(|decl| matches!(decl, Decl::Val { .. })) {
+            return (decls, cont);
+        }
+        let (items, decls): (Vec<Decl>, Vec<Decl>) = decls
+            .into_iter()
+            .partition(|decl| matches!(decl, Decl::Func { .. } | Decl::Val { .. }));
+        let index: HashMap<Ident, usize> = items
+            .iter()
+            .enumerate()
+            .map(|(i, decl)| (decl.get_name(), i))
+            .collect();
+        let graph: Vec<Vec<usize>> = items
+            .iter()
+            .map(|decl| {
+                let mut deps = Vec::new();
+                if let Decl::Func { body, .. } | Decl::Val { body, .. } = decl {
+                    mentions(body, &index, &mut deps);
+                }
+                deps
+            })
+            .collect();
+        let mut items: Vec<Option<Decl>> = items.into_iter().map(Some).collect();
+        let mut groups = Vec::new();
+        for comp in components(&graph) {
+            let recursive = comp.len() > 1 || graph[comp[0]].contains(&comp[0]);
+            let group: Vec<Decl> = comp.iter().map(|i| items[*i].take().unwrap()).collect();
+            for decl in group.iter() {
+                if let Decl::Val { name, span, .. } = decl {
+                    if recursive {
+                        self.error.push(RenameError::RecursiveValue(*span, *name));
                     }
                 }
-                let decls = decls
-                    .into_iter()
-                    .map(|decl| self.visit_decl(decl))
-                    .collect();
-                let cont = Box::new(self.visit_expr(*cont));
-                self.leave_scope();
-                Expr::Blk { decls, cont, span }
             }
+            groups.push(group);
         }
+        let cont = groups.into_iter().rev().fold(cont, |cont, group| {
+            let (vals, funcs): (Vec<Decl>, Vec<Decl>) = group
+                .into_iter()
+                .partition(|decl| matches!(decl, Decl::Val { .. }));
+            let cont = if funcs.is_empty() {
+                cont
+            } else {
+                Box::new(Expr::Blk {
+                    decls: funcs,
+                    cont,
+                    span,
+                })
+            };
+            vals.into_iter().rev().fold(cont, |cont, val| {
+                let Decl::Val {
+                    name,
+                    typ,
+                    body,
+                    span,
+                } = val
+                else {
+                    unreachable!()
+                };
+                let expr = match typ {
+                    Some(typ) => Box::new(Expr::Anno {
+                        expr: body,
+                        typ,
+                        span,
+                    }),
+                    None => body,
+                };
+                Box::new(Expr::Let {
+                    bind: name,
+                    expr,
+                    cont,
+                    span,
+                })
+            })
+        });
+        (decls, cont)
     }
 
     pub fn visit_rule(&mut self, rule: Rule) -> Rule {
-        let Rule { patn, body, span } = rule;
+        let Rule {
+            patn,
+            guard,
+            body,
+            span,
+        } = rule;
         self.enter_scope();
         let patn = self.visit_patn(patn);
+        let guard = guard.map(|guard| self.visit_expr(guard));
         let body = self.visit_expr(body);
         self.leave_scope();
-        Rule { patn, body, span }
+        Rule {
+            patn,
+            guard,
+            body,
+            span,
+        }
     }
 
     pub fn visit_patn(&mut self, patn: Pattern) -> Pattern {
         match patn {
             Pattern::Var { var, span } => {
                 assert!(var.is_dummy());
-                let var = self.intro_val_var(var);
+                let var = match self.alt_binds.get(&var) {
+                    Some(var) => *var,
+                    None => self.intro_val_var(var),
+                };
                 Pattern::Var { var, span }
             }
             Pattern::Lit { lit, span } => Pattern::Lit { lit, span },
             Pattern::Cons { cons, pars, span } => {
                 assert!(cons.is_dummy());
+                let name = cons;
                 let cons = self.lookup_cons_var(cons).unwrap_or_else(|| {
                     self.error
                         .push(RenameError::UnboundedConstructorVariable(span, cons));
                     cons.uniquify()
                 });
+                self.check_ambiguous(span, name, cons);
+                if let Some(typ) = self.hidden_data(cons) {
+                    self.error.push(RenameError::AbstractMatch(span, cons, typ));
+                }
                 let pars = pars.into_iter().map(|par| self.visit_patn(par)).collect();
                 Pattern::Cons { cons, pars, span }
             }
+            Pattern::Tuple { pats, span } => {
+                let pats = pats.into_iter().map(|pat| self.visit_patn(pat)).collect();
+                Pattern::Tuple { pats, span }
+            }
             Pattern::Wild { span } => Pattern::Wild { span },
+            Pattern::Or { alts, span } => {
+                let firsts = alts[0].get_freevars();
+                for alt in alts[1..].iter() {
+                    let vars = alt.get_freevars();
+                    for var in vars.iter().filter(|var| !firsts.contains(var)) {
+                        let err = RenameError::OrPatternBinding(*alt.span(), *var);
+                        self.error.push(err);
+                    }
+                    for var in firsts.iter().filter(|var| !vars.contains(var)) {
+                        let err = RenameError::OrPatternBinding(*alts[0].span(), *var);
+                        self.error.push(err);
+                    }
+                }
+                let mut alts = alts.into_iter();
+                let first = self.visit_patn(alts.next().unwrap());
+                let saved = self.alt_binds.clone();
+                for var in firsts {
+                    if !self.alt_binds.contains_key(&var) {
+                        let ident = self.lookup_val_var(var).unwrap();
+                        self.alt_binds.insert(var, ident);
+                    }
+                }
+                let alts = std::iter::once(first)
+                    .chain(alts.map(|alt| self.visit_patn(alt)))
+                    .collect();
+                self.alt_binds = saved;
+                Pattern::Or { alts, span }
+            }
+            Pattern::As { bind, patn, span } => {
+                assert!(bind.is_dummy());
+                let bind = match self.alt_binds.get(&bind) {
+                    Some(bind) => *bind,
+                    None => self.intro_val_var(bind),
+                };
+                let patn = Box::new(self.visit_patn(*patn));
+                Pattern::As { bind, patn, span }
+            }
         }
     }
 
@@ -225,7 +685,9 @@ impl Renamer {
         match decl {
             Decl::Func {
                 name,
+                attrs,
                 pars,
+                ret,
                 body,
                 span,
             } => {
@@ -237,13 +699,38 @@ impl Renamer {
                 });
                 let pars = pars
                     .into_iter()
-                    .map(|par| self.intro_val_var(par))
+                    .map(|(par, typ)| {
+                        (self.intro_val_var(par), typ.map(|typ| self.visit_type(typ)))
+                    })
                     .collect();
+                let ret = ret.map(|typ| self.visit_type(typ));
                 let body = Box::new(self.visit_expr(*body));
                 self.leave_scope();
                 Decl::Func {
                     name,
+                    attrs,
                     pars,
+                    ret,
+                    body,
+                    span,
+                }
+            }
+            Decl::Val {
+                name,
+                typ,
+                body,
+                span,
+            } => {
+                let name = self.lookup_val_var(name).unwrap_or_else(|| {
+                    self.error
+                        .push(RenameError::UnboundedValueVariable(span, name));
+                    name.uniquify()
+                });
+                let typ = typ.map(|typ| self.visit_type(typ));
+                let body = Box::new(self.visit_expr(*body));
+                Decl::Val {
+                    name,
+                    typ,
                     body,
                     span,
                 }
@@ -272,6 +759,19 @@ impl Renamer {
                     span,
                 }
             }
+            Decl::Record { name, fields, span } => {
+                let name = self.lookup_typ_var(name).unwrap();
+                let fields = fields
+                    .into_iter()
+                    .zip(self.record_fields[&name].clone())
+                    .map(|(FieldDecl { typ, span, .. }, field)| FieldDecl {
+                        field,
+                        typ: self.visit_type(typ),
+                        span,
+                    })
+                    .collect();
+                Decl::Record { name, fields, span }
+            }
             Decl::Type {
                 name,
                 pars,
@@ -293,8 +793,19 @@ impl Renamer {
                     span,
                 }
             }
+            Decl::Abstract { name, pars, span } => {
+                self.enter_scope();
+                let name = self.lookup_typ_var(name).unwrap();
+                let pars = pars
+                    .into_iter()
+                    .map(|par| self.intro_typ_var(par))
+                    .collect();
+                self.leave_scope();
+                Decl::Abstract { name, pars, span }
+            }
             Decl::Extern {
                 name,
+                attrs,
                 pars,
                 typ,
                 span,
@@ -308,11 +819,20 @@ impl Renamer {
                 self.leave_scope();
                 Decl::Extern {
                     name,
+                    attrs,
                     pars,
                     typ,
                     span,
                 }
             }
+            Decl::Fixity { oper, fixity, span } => {
+                let oper = self.lookup_val_var(oper).unwrap_or_else(|| {
+                    self.error
+                        .push(RenameError::UnboundedValueVariable(span, oper));
+                    oper
+                });
+                Decl::Fixity { oper, fixity, span }
+            }
         }
     }
 
@@ -336,10 +856,20 @@ impl Renamer {
                 });
                 Type::Var { var, span }
             }
-            Type::Fun { pars, res, span } => {
+            Type::Fun {
+                pars,
+                res,
+                variadic,
+                span,
+            } => {
                 let pars = pars.into_iter().map(|par| self.visit_type(par)).collect();
                 let res = Box::new(self.visit_type(*res));
-                Type::Fun { pars, res, span }
+                Type::Fun {
+                    pars,
+                    res,
+                    variadic,
+                    span,
+                }
             }
             Type::App { cons, args, span } => {
                 assert!(cons.is_dummy());
@@ -351,8 +881,140 @@ impl Renamer {
                 let args = args.into_iter().map(|arg| self.visit_type(arg)).collect();
                 Type::App { cons, args, span }
             }
+            Type::Tuple { elems, span } => {
+                let elems = elems
+                    .into_iter()
+                    .map(|elem| self.visit_type(elem))
+                    .collect();
+                Type::Tuple { elems, span }
+            }
+            Type::SimdVec { lanes, elem, span } => Type::SimdVec { lanes, elem, span },
+            Type::OpaquePtr { span } => Type::OpaquePtr { span },
+            Type::Mutex { span } => Type::Mutex { span },
+            Type::Sem { span } => Type::Sem { span },
+            Type::Coroutine { arg, res, span } => {
+                let arg = Box::new(self.visit_type(*arg));
+                let res = Box::new(self.visit_type(*res));
+                Type::Coroutine { arg, res, span }
+            }
+            Type::Thread { res, span } => {
+                let res = Box::new(self.visit_type(*res));
+                Type::Thread { res, span }
+            }
+            Type::Chan { elem, span } => {
+                let elem = Box::new(self.visit_type(*elem));
+                Type::Chan { elem, span }
+            }
+            Type::Scheme { vars, body, span } => {
+                self.enter_scope();
+                let vars = vars
+                    .into_iter()
+                    .map(|var| self.intro_typ_var(var))
+                    .collect();
+                let body = Box::new(self.visit_type(*body));
+                self.leave_scope();
+                Type::Scheme { vars, body, span }
+            }
+        }
+    }
+}
+
+/// the declarations of `index` that `expr` refers to, names are unique after renaming
+fn mentions(expr: &Expr, index: &HashMap<Ident, usize>, deps: &mut Vec<usize>) {
+    if let Expr::Var { var, .. } = expr {
+        if let Some(i) = index.get(var) {
+            if !deps.contains(i) {
+                deps.push(*i);
+            }
         }
     }
+    for sub in expr.subexprs() {
+        mentions(sub, index, deps);
+    }
+}
+
+/// the strongly connected components of a graph given by the successors of each node,
+/// each component comes after the components it has edges to (Tarjan's algorithm)
+fn components(graph: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    struct Tarjan<'a> {
+        graph: &'a [Vec<usize>],
+        order: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        comps: Vec<Vec<usize>>,
+        visited: usize,
+    }
+    impl Tarjan<'_> {
+        fn visit(&mut self, node: usize) {
+            let order = self.visited;
+            self.visited += 1;
+            self.order[node] = Some(order);
+            self.low[node] = order;
+            self.stack.push(node);
+            self.on_stack[node] = true;
+            for &next in self.graph[node].iter() {
+                match self.order[next] {
+                    None => {
+                        self.visit(next);
+                        self.low[node] = self.low[node].min(self.low[next]);
+                    }
+                    Some(order) if self.on_stack[next] => {
+                        self.low[node] = self.low[node].min(order);
+                    }
+                    Some(_) => {}
+                }
+            }
+            if self.low[node] == order {
+                let start = self.stack.iter().rposition(|n| *n == node).unwrap();
+                let mut comp = self.stack.split_off(start);
+                for n in comp.iter() {
+                    self.on_stack[*n] = false;
+                }
+                comp.sort();
+                self.comps.push(comp);
+            }
+        }
+    }
+    let mut tarjan = Tarjan {
+        graph,
+        order: vec![None; graph.len()],
+        low: vec![0; graph.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; graph.len()],
+        comps: Vec::new(),
+        visited: 0,
+    };
+    for node in 0..graph.len() {
+        if tarjan.order[node].is_none() {
+            tarjan.visit(node);
+        }
+    }
+    tarjan.comps
+}
+
+/// `try { e } catch | r1 ... end` is `case e of | Ok(x) => { x } | r1 ... end`.
+/// the constructors are resolved by name, like those of `@readfile`, so a result type
+/// must be in scope, and the handlers are checked against its error type like any rule
+fn desugar_try(expr: Expr, handlers: Vec<Rule>, span: Span) -> Expr {
+    let var = Ident::from(InternStr::new("x"));
+    let ok = Rule {
+        patn: Pattern::Cons {
+            cons: Ident::from(InternStr::new(RESULT_OK)),
+            pars: vec![Pattern::Var { var, span }],
+            span,
+        },
+        guard: None,
+        body: Expr::Var { var, span },
+        span,
+    };
+    let rules = std::iter::once(ok).chain(handlers).collect();
+    Expr::Case {
+        expr: Box::new(expr),
+        rules,
+        is_let: false,
+        span,
+    }
 }
 
 #[test]
@@ -403,3 +1065,370 @@ end
         }
     }
 }
+
+#[test]
+fn renamer_let_scope_test() {
+    use super::parser::*;
+    // the binding is visible in the body, but not in its own definition
+    let string = "let x = 1; let x = @iadd(x, 1); let y = y; @iadd(x, y)";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+
+    assert_eq!(rnm.error.len(), 1);
+    let RenameError::UnboundedValueVariable(span, var) = rnm.error[0] else {
+        panic!("test failed!");
+    };
+    assert_eq!(format!("{var}"), "y");
+    assert_eq!(span.start.abs, string.find("= y").unwrap() + 2);
+
+    // the second `x` refers to the first one, and the body to the second
+    let Expr::Let { bind: x1, cont, .. } = &res else {
+        panic!("test failed!");
+    };
+    let Expr::Let {
+        bind: x2,
+        expr,
+        cont,
+        ..
+    } = cont.as_ref()
+    else {
+        panic!("test failed!");
+    };
+    assert_ne!(x1, x2);
+    let Expr::Prim { args, .. } = expr.as_ref() else {
+        panic!("test failed!");
+    };
+    assert!(matches!(args[0], Expr::Var { var, .. } if var == *x1));
+    let Expr::Let { cont, .. } = cont.as_ref() else {
+        panic!("test failed!");
+    };
+    let Expr::Prim { args, .. } = cont.as_ref() else {
+        panic!("test failed!");
+    };
+    assert!(matches!(args[0], Expr::Var { var, .. } if var == *x2));
+}
+
+#[test]
+fn renamer_clauses_test() {
+    use super::parser::*;
+    // the parameters of the clauses can't clash with placeholders or user names
+    let string = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun apply(f, x) => f(x)
+    fun g(Nil, y) = y
+    fun g(Cons(y, _), x) = apply(@iadd(_, y), x)
+in
+    g(Nil, 1)
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.error.is_empty());
+
+    let Expr::Blk { decls, .. } = &res else {
+        panic!("test failed!");
+    };
+    let Decl::Func { pars, body, .. } = &decls[2] else {
+        panic!("test failed!");
+    };
+    let Expr::Case { expr, .. } = body.as_ref() else {
+        panic!("test failed!");
+    };
+    let Expr::Tuple { elems, .. } = expr.as_ref() else {
+        panic!("test failed!");
+    };
+    for ((par, _), elem) in pars.iter().zip(elems.iter()) {
+        assert!(matches!(elem, Expr::Var { var, .. } if var == par));
+    }
+    assert_ne!(pars[0].0, pars[1].0);
+}
+
+#[test]
+fn renamer_shadowing_test() {
+    use super::parser::*;
+    // each binding of `x` is a different variable, and each use refers to the nearest one
+    let string = "let x = 1; let f = fun(x) => @iadd(x, 1); case (f(x), x) of | (x, y) => { @iadd(x, y) } end";
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.error.is_empty());
+
+    let var = |expr: &Expr| match expr {
+        Expr::Var { var, .. } => *var,
+        _ => panic!("test failed!"),
+    };
+    let Expr::Let { bind: x1, cont, .. } = &res else {
+        panic!("test failed!");
+    };
+    let Expr::Let { expr, cont, .. } = cont.as_ref() else {
+        panic!("test failed!");
+    };
+    let Expr::Fun { pars, body, .. } = expr.as_ref() else {
+        panic!("test failed!");
+    };
+    let x2 = pars[0];
+    let Expr::Prim { args, .. } = body.as_ref() else {
+        panic!("test failed!");
+    };
+    assert_eq!(var(&args[0]), x2);
+    let Expr::Case { expr, rules, .. } = cont.as_ref() else {
+        panic!("test failed!");
+    };
+    let Expr::Tuple { elems, .. } = expr.as_ref() else {
+        panic!("test failed!");
+    };
+    let Expr::App { args, .. } = &elems[0] else {
+        panic!("test failed!");
+    };
+    assert_eq!(var(&args[0]), *x1);
+    assert_eq!(var(&elems[1]), *x1);
+    let Pattern::Tuple { pats, .. } = &rules[0].patn else {
+        panic!("test failed!");
+    };
+    let Pattern::Var { var: x3, .. } = pats[0] else {
+        panic!("test failed!");
+    };
+    let Expr::Prim { args, .. } = &rules[0].body else {
+        panic!("test failed!");
+    };
+    assert_eq!(var(&args[0]), x3);
+
+    // the same name, but different variables, none of them the unrenamed name
+    let x0 = Ident::from(x3.name);
+    let xs = [x0, *x1, x2, x3];
+    for i in 0..xs.len() {
+        for j in i + 1..xs.len() {
+            assert_ne!(xs[i], xs[j]);
+        }
+    }
+}
+
+#[test]
+fn renamer_module_test() {
+    use super::parser::*;
+    // the declarations of a module are also in scope by their qualified names,
+    // which a local declaration doesn't hide
+    let string = r#"
+begin
+    fun area(s) => 0
+in
+    (area(1), Shapes.area(Shapes.Square(1)), Square(2))
+end
+"#;
+    let mut par = Parser::new(string);
+    let cont = parse_expr(&mut par).unwrap();
+    let mut par = Parser::new("module Shapes;\ndata Shape = | Square(Int) end\nfun area(s) => 1");
+    parse_header(&mut par).unwrap();
+    let (decls, _) = parse_module(&mut par);
+    let expr = Expr::Module {
+        name: InternStr::new("Shapes"),
+        decls: decls.unwrap(),
+        cont: Box::new(cont),
+        span: Span::default(),
+    };
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.error.is_empty());
+
+    let Expr::Blk { decls, cont, .. } = &res else {
+        panic!("test failed!");
+    };
+    let (Decl::Data { vars, .. }, Decl::Func { name: area, .. }) = (&decls[0], &decls[1]) else {
+        panic!("test failed!");
+    };
+    let Expr::Blk { decls, cont, .. } = cont.as_ref() else {
+        panic!("test failed!");
+    };
+    let local = decls[0].get_name();
+    let Expr::Tuple { elems, .. } = cont.as_ref() else {
+        panic!("test failed!");
+    };
+    let func = |expr: &Expr| match expr {
+        Expr::App { func, .. } => match func.as_ref() {
+            Expr::Var { var, .. } => *var,
+            _ => panic!("test failed!"),
+        },
+        _ => panic!("test failed!"),
+    };
+    assert_eq!(func(&elems[0]), local);
+    assert_eq!(func(&elems[1]), *area);
+    assert!(matches!(&elems[2], Expr::Cons { cons, .. } if *cons == vars[0].cons));
+
+    // a qualified name is only bound by its module
+    let mut par = Parser::new("Util.square(2)");
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    rnm.visit_expr(expr);
+    assert_eq!(rnm.error.len(), 1);
+}
+
+#[test]
+fn renamer_ambiguous_import_test() {
+    use super::parser::*;
+    // a bare name declared by two modules is ambiguous after both of them,
+    // but each module still uses its own declaration by the bare name
+    let module = |name: &str, source: &str, cont: Expr| {
+        let mut par = Parser::new(source);
+        parse_header(&mut par).unwrap();
+        let (decls, _) = parse_module(&mut par);
+        Expr::Module {
+            name: InternStr::new(name),
+            decls: decls.unwrap(),
+            cont: Box::new(cont),
+            span: Span::default(),
+        }
+    };
+    let program = |main: &str| {
+        let mut par = Parser::new(main);
+        let cont = parse_expr(&mut par).unwrap();
+        let cont = module(
+            "Circles",
+            "module Circles;\ndata Circle = | Unit end\nfun area(c) => 3\nfun twice(c) => area(c)",
+            cont,
+        );
+        module(
+            "Shapes",
+            "module Shapes;\ndata Shape = | Unit end\nfun area(s) => 1",
+            cont,
+        )
+    };
+
+    let mut rnm = Renamer::new();
+    rnm.visit_expr(program(
+        "(Shapes.area(Shapes.Unit), Circles.area(Circles.Unit))",
+    ));
+    assert!(rnm.error.is_empty());
+
+    let mut rnm = Renamer::new();
+    rnm.visit_expr(program("(area(1), Unit)"));
+    assert_eq!(rnm.error.len(), 2);
+    match &rnm.error[0] {
+        RenameError::AmbiguousImport(_, name, modules) => {
+            assert_eq!(name.name.as_ref(), "area");
+            let modules: Vec<&str> = modules.iter().map(|module| module.as_ref()).collect();
+            assert_eq!(modules, ["Shapes", "Circles"]);
+        }
+        _ => panic!("test failed!"),
+    }
+    assert!(matches!(&rnm.error[1], RenameError::AmbiguousImport(..)));
+
+    // a local declaration hides both of them
+    let mut rnm = Renamer::new();
+    rnm.visit_expr(program("begin fun area(x) => 0 in area(1) end"));
+    assert!(rnm.error.is_empty());
+}
+
+#[test]
+fn renamer_try_test() {
+    use super::parser::*;
+    // `try` is a case whose first rule returns the successful result
+    let string = r#"
+begin
+    data Result[T, E] = | Ok(T) | Err(E) end
+in
+    try { Err(1) } catch | Err(x) => { x } end
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.error.is_empty());
+
+    let Expr::Blk { decls, cont, .. } = &res else {
+        panic!("test failed!");
+    };
+    let Decl::Data { vars, .. } = &decls[0] else {
+        panic!("test failed!");
+    };
+    let Expr::Case { rules, .. } = cont.as_ref() else {
+        panic!("test failed!");
+    };
+    assert_eq!(rules.len(), 2);
+    let Pattern::Cons { cons, pars, .. } = &rules[0].patn else {
+        panic!("test failed!");
+    };
+    assert_eq!(*cons, vars[0].cons);
+    assert!(
+        matches!(&rules[0].body, Expr::Var { var, .. } if [*var] == pars[0].get_freevars()[..])
+    );
+    // the variable of the first rule is not the one of the handler
+    assert_ne!(rules[0].patn.get_freevars(), rules[1].patn.get_freevars());
+
+    // the result constructors must be in scope
+    let mut par = Parser::new("try { 1 } catch | Err(x) => { x } end");
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    rnm.visit_expr(expr);
+    assert_eq!(rnm.error.len(), 2);
+}
+
+#[test]
+fn renamer_value_test() {
+    use super::parser::*;
+    // values are computed in the order of their dependencies
+    let string = r#"
+begin
+    let four = twice(two);
+    fun twice(x) => @iadd(x, x)
+    let two = 2;
+in
+    four
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    let res = rnm.visit_expr(expr);
+    assert!(rnm.error.is_empty());
+
+    // walk down the block, collecting the order of the lets and the functions
+    let mut order = Vec::new();
+    let mut expr = &res;
+    loop {
+        match expr {
+            Expr::Blk { decls, cont, .. } => {
+                order.extend(decls.iter().map(|decl| decl.get_name().name.as_str()));
+                expr = cont;
+            }
+            Expr::Let { bind, cont, .. } => {
+                order.push(bind.name.as_str());
+                expr = cont;
+            }
+            _ => break,
+        }
+    }
+    assert_eq!(order.len(), 3);
+    let pos = |name| order.iter().position(|x| *x == name).unwrap();
+    assert!(pos("two") < pos("four"));
+    assert!(pos("twice") < pos("four"));
+
+    // only functions can be recursive
+    let string = r#"
+begin
+    let x = x;
+    let y = f();
+    fun f() => y
+in
+    x
+end
+"#;
+    let mut par = Parser::new(string);
+    let expr = parse_expr(&mut par).unwrap();
+    let mut rnm = Renamer::new();
+    rnm.visit_expr(expr);
+    assert_eq!(rnm.error.len(), 2);
+    assert!(rnm
+        .error
+        .iter()
+        .all(|err| matches!(err, RenameError::RecursiveValue(_, _))));
+}
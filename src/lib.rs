@@ -1,3 +1,4 @@
 pub mod backend;
+pub mod debug;
 pub mod frontend;
 pub mod utils;
@@ -16,6 +16,46 @@ fn main() {
         )
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(
+            Arg::new("VERBOSE_INTERNALS")
+                .long("verbose-internals")
+                .global(true)
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .help("show raw internal names in diagnostics, for debugging the compiler"),
+        )
+        .arg(
+            Arg::new("BETTER_ERRORS")
+                .long("better-errors")
+                .global(true)
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .help("blame type errors on the smallest expression, at the cost of more checking"),
+        )
+        .arg(
+            Arg::new("NO_DIV_CHECK")
+                .long("no-div-check")
+                .global(true)
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .help("don't check divisors for zero at runtime, for performance-sensitive builds"),
+        )
+        .arg(
+            Arg::new("INLINE_THRESHOLD")
+                .long("inline-threshold")
+                .global(true)
+                .required(false)
+                .value_parser(clap::value_parser!(usize))
+                .help("inline the functions of at most this many IR nodes at every call, 0 turns it off"),
+        )
+        .arg(
+            Arg::new("SUGAR")
+                .long("sugar")
+                .global(true)
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .help("print simple lambdas as placeholders like `_ + 1` when dumping"),
+        )
         .subcommand(
             Command::new("compile")
                 .about("compile norem source file to target language")
@@ -40,6 +80,71 @@ fn main() {
                         .help("print intermediate result of compiliation"),
                 ),
         )
+        .subcommand(
+            Command::new("check")
+                .about("check norem source file and print its diagnostics and type")
+                .arg(
+                    Arg::new("INPUT")
+                        .required(true)
+                        .help("path of input norem source file"),
+                )
+                .arg(
+                    Arg::new("DECLS")
+                        .long("decls")
+                        .required(false)
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "check the top-level declarations one at a time and print their types",
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("build")
+                .about("build a norem source file or an IR file to C code or to the IR")
+                .arg(
+                    Arg::new("INPUT")
+                        .required(true)
+                        .help("path of input norem source file, or IR file with `--from-ir`"),
+                )
+                .arg(
+                    Arg::new("FROM_IR")
+                        .long("from-ir")
+                        .required(false)
+                        .action(ArgAction::SetTrue)
+                        .help("read the input as IR printed by `--emit=ir`"),
+                )
+                .arg(
+                    Arg::new("EMIT")
+                        .long("emit")
+                        .required(false)
+                        .value_parser(["c", "ir"])
+                        .default_value("c")
+                        .help("what to write, C code or the IR before optimization"),
+                )
+                .arg(
+                    Arg::new("RUN_IR")
+                        .long("run-ir")
+                        .required(false)
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["EMIT", "OUTPUT"])
+                        .help("build the input IR file and run it, instead of writing a file"),
+                )
+                .arg(
+                    Arg::new("OUTPUT")
+                        .short('o')
+                        .long("output")
+                        .required(false)
+                        .help("path for saving output file"),
+                )
+                .arg(
+                    Arg::new("LIBRARY")
+                        .short('l')
+                        .long("library")
+                        .required(false)
+                        .requires("RUN_IR")
+                        .help("path of external library, for `--run-ir`"),
+                ),
+        )
         .subcommand(
             Command::new("link")
                 .about("link compiled norem file with external library")
@@ -61,8 +166,66 @@ fn main() {
                         .help("path for saving output file"),
                 ),
         )
+        .subcommand(
+            Command::new("test")
+                .about("run functions marked with `@test` in norem source file")
+                .arg(
+                    Arg::new("INPUT")
+                        .required(true)
+                        .help("path of input norem source file"),
+                )
+                .arg(
+                    Arg::new("LIBRARY")
+                        .short('l')
+                        .long("library")
+                        .required(false)
+                        .help("path of external library"),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("run functions marked with `@bench` in norem source file")
+                .arg(
+                    Arg::new("INPUT")
+                        .required(true)
+                        .help("path of input norem source file"),
+                )
+                .arg(
+                    Arg::new("LIBRARY")
+                        .short('l')
+                        .long("library")
+                        .required(false)
+                        .help("path of external library"),
+                )
+                .arg(
+                    Arg::new("JSON")
+                        .long("json")
+                        .required(false)
+                        .help("path for saving benchmark result as json"),
+                ),
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("print documentation generated from the compiler itself")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("REFERENCE")
+                        .long("reference")
+                        .required(false)
+                        .action(ArgAction::SetTrue)
+                        .help("print the language reference in markdown"),
+                ),
+        )
         .get_matches();
 
+    norem::frontend::diagnostic::set_verbose_internals(matches.get_flag("VERBOSE_INTERNALS"));
+    norem::utils::printer::set_sugar(matches.get_flag("SUGAR"));
+    driver::set_better_errors(matches.get_flag("BETTER_ERRORS"));
+    driver::set_div_check(!matches.get_flag("NO_DIV_CHECK"));
+    if let Some(size) = matches.get_one::<usize>("INLINE_THRESHOLD") {
+        driver::set_inline_threshold(*size);
+    }
+
     match matches.subcommand().unwrap() {
         ("compile", sub_matches) => {
             let input: PathBuf = sub_matches
@@ -94,6 +257,69 @@ fn main() {
                 }
             }
         }
+        ("check", sub_matches) => {
+            let input: PathBuf = sub_matches
+                .get_one::<String>("INPUT")
+                .map(|x| x.into())
+                .unwrap();
+
+            let decls = sub_matches.get_flag("DECLS");
+            match driver::run_check(&input, decls) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(err) => {
+                    println!("{err}");
+                    println!("checking failed!");
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("build", sub_matches) => {
+            let input: PathBuf = sub_matches
+                .get_one::<String>("INPUT")
+                .map(|x| x.into())
+                .unwrap();
+
+            if sub_matches.get_flag("RUN_IR") {
+                let library: Option<PathBuf> =
+                    sub_matches.get_one::<String>("LIBRARY").map(|x| x.into());
+                match driver::run_ir(&input, library.as_ref()) {
+                    Ok(res) => {
+                        use std::io::Write;
+                        std::io::stdout().write_all(&res.stdout).unwrap();
+                        std::io::stderr().write_all(&res.stderr).unwrap();
+                        std::process::exit(res.status.code().unwrap_or(1));
+                    }
+                    Err(err) => {
+                        println!("{err}");
+                        println!("running failed!");
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            let from_ir = sub_matches.get_flag("FROM_IR");
+            let (emit, default) = match sub_matches.get_one::<String>("EMIT").unwrap().as_str() {
+                "ir" => (driver::Emit::Ir, "output.anf"),
+                _ => (driver::Emit::C, "output.c"),
+            };
+
+            let output: PathBuf = sub_matches
+                .get_one::<String>("OUTPUT")
+                .map(|x| x.into())
+                .unwrap_or(PathBuf::from(default));
+
+            match driver::run_build(&input, from_ir, emit, &output) {
+                Ok(()) => {
+                    println!("build successed.");
+                }
+                Err(err) => {
+                    println!("{err}");
+                    println!("build failed!");
+                    std::process::exit(1);
+                }
+            }
+        }
         ("link", sub_matches) => {
             let code: PathBuf = sub_matches
                 .get_one::<String>("CODE")
@@ -128,6 +354,62 @@ fn main() {
                 }
             }
         }
+        ("test", sub_matches) => {
+            let input: PathBuf = sub_matches
+                .get_one::<String>("INPUT")
+                .map(|x| x.into())
+                .unwrap();
+
+            let library: Option<PathBuf> =
+                sub_matches.get_one::<String>("LIBRARY").map(|x| x.into());
+
+            match driver::run_tests(&input, library.as_ref()) {
+                Ok(0) => {
+                    println!("all tests passed.");
+                }
+                Ok(_) => {
+                    println!("some tests failed!");
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    println!("{err}");
+                    println!("testing failed!");
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("bench", sub_matches) => {
+            let input: PathBuf = sub_matches
+                .get_one::<String>("INPUT")
+                .map(|x| x.into())
+                .unwrap();
+
+            let library: Option<PathBuf> =
+                sub_matches.get_one::<String>("LIBRARY").map(|x| x.into());
+
+            let json: Option<PathBuf> = sub_matches.get_one::<String>("JSON").map(|x| x.into());
+
+            match driver::run_benches(&input, library.as_ref()) {
+                Ok(results) => {
+                    print!("{}", driver::bench_table(&results));
+                    if let Some(json) = json {
+                        if let Err(err) = std::fs::write(json, driver::bench_json(&results)) {
+                            println!("{err}");
+                        }
+                    }
+                }
+                Err(err) => {
+                    println!("{err}");
+                    println!("benchmarking failed!");
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("explain", sub_matches) => {
+            if sub_matches.get_flag("REFERENCE") {
+                print!("{}", norem::utils::reference::language_reference());
+            }
+        }
         _ => unreachable!("Exhausted list of subcommands and subcommand_required prevents `None`"),
     }
 }
@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared between a compilation and whoever started it, so a compilation whose result
+/// isn't needed anymore can be abandoned. The phases check it between declarations and files,
+/// and return `Cancelled` once it is set.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    flag: Arc<AtomicBool>,
+}
+
+/// the compilation was abandoned, nothing it produced is kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// ask every compilation holding a clone of this token to stop
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn cancel_test() {
+    let token = CancellationToken::new();
+    let clone = token.clone();
+    assert_eq!(clone.check(), Ok(()));
+    token.cancel();
+    assert_eq!(clone.check(), Err(Cancelled));
+    // a new token isn't affected
+    assert!(!CancellationToken::new().is_cancelled());
+}
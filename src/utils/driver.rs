@@ -1,22 +1,52 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::Display;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use crate::backend;
+use crate::backend::anf::MExpr;
+use crate::backend::simple_opt::PassCtx;
 use crate::frontend;
+use crate::frontend::ast::{
+    Attr, Builtin, Decl, Expr, Header, OptLevel, Type, LIST_CONS, LIST_NIL, MAX_PREC, OPTION,
+    OPTION_NONE, OPTION_SOME, RESULT, RESULT_ERR, RESULT_OK, SIMD_LANES,
+};
+use crate::frontend::diagnostic::{self, Diagnostic};
+use crate::frontend::infer::{Blame, Infer, InferError, TypeEnv, TypedHole};
+use crate::frontend::lexer::{self, Lexer, Token};
+use crate::frontend::parser::ParseError;
+use crate::frontend::position::{Span, Spanned};
+use crate::frontend::renamer::RenameError;
+use crate::utils::cancel::{CancellationToken, Cancelled};
+use crate::utils::intern::{Ident, InternStr};
+use crate::utils::source_map::{FileId, SourceMap};
 
 #[derive(Debug)]
 pub enum TopError {
+    LexError(usize),
     ParseError(crate::frontend::parser::ParseError),
     IOError(std::io::Error),
+    LinkError(String),
+    TypeError(InferError),
+    HoleError(usize),
+    ModuleError(usize),
+    LowerError(Vec<Diagnostic>),
+    IrError(usize),
+    /// the compilation was cancelled through its token, this is not a failure
+    Cancelled,
 }
 
 impl Display for TopError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            TopError::LexError(n) => {
+                write!(f, "Error: {n} error(s) occured during lexer phase")?;
+            }
             TopError::ParseError(err) => {
                 write!(f, "Error: an error occured during parser phase")?;
                 write!(f, "Cause: {err:?}")?;
@@ -25,6 +55,33 @@ impl Display for TopError {
                 write!(f, "Error: an IO error occured!")?;
                 write!(f, "Cause: {err:?}")?;
             }
+            TopError::LinkError(err) => {
+                write!(f, "Error: an error occured during linking")?;
+                write!(f, "Cause: {err}")?;
+            }
+            TopError::TypeError(err) => {
+                write!(f, "Error: an error occured during type checking")?;
+                write!(f, "Cause: {err:?}")?;
+            }
+            TopError::HoleError(n) => {
+                write!(f, "Error: {n} typed hole(s) left in the program")?;
+            }
+            TopError::ModuleError(n) => {
+                write!(f, "Error: {n} error(s) occured while loading the modules")?;
+            }
+            TopError::LowerError(diags) => {
+                let n = diags.len();
+                write!(f, "Error: {n} error(s) occured while lowering the program")?;
+            }
+            TopError::IrError(n) => {
+                write!(f, "Error: {n} error(s) occured while reading the IR")?;
+            }
+            TopError::Cancelled => {
+                write!(
+                    f,
+                    "Cancelled: the compilation was abandoned before it finished"
+                )?;
+            }
         }
         Ok(())
     }
@@ -38,78 +95,1144 @@ impl From<frontend::parser::ParseError> for TopError {
     }
 }
 
+impl From<Cancelled> for TopError {
+    fn from(_: Cancelled) -> Self {
+        TopError::Cancelled
+    }
+}
+
 impl From<std::io::Error> for TopError {
     fn from(value: std::io::Error) -> Self {
         TopError::IOError(value)
     }
 }
 
+/// Print all lexer diagnostics, and fail if there is any
+fn check_lexer(map: &SourceMap, file: FileId) -> Result<(), TopError> {
+    let mut lexer = Lexer::new(map.source(file)).with_file(file);
+    let tokens: Vec<Token> = lexer.by_ref().collect();
+    let diags = lexer::lexer_diagnostics(&tokens);
+    for diag in lexer.warnings().iter().chain(diags.iter()) {
+        print!("{}", diag.report_in(map, 10));
+    }
+    if diags.is_empty() {
+        Ok(())
+    } else {
+        Err(TopError::LexError(diags.len()))
+    }
+}
+
+/// What comes after the header of a file
+enum FileBody {
+    Main(Expr),
+    Module(Vec<Decl>),
+}
+
+/// Parse a file, printing the diagnostic of every syntax error, and fail if there is any.
+/// a file with a `module` header has only declarations, any other is a main program
+fn parse_file(map: &SourceMap, file: FileId) -> Result<(Header, FileBody), TopError> {
+    let mut par = frontend::parser::Parser::with_file(map.source(file), file);
+    let (header, body, mut errors) = match frontend::parser::parse_header(&mut par) {
+        Ok(header) if header.module.is_some() => {
+            let (decls, errors) = frontend::parser::parse_module(&mut par);
+            (header, decls.map(FileBody::Module), errors)
+        }
+        Ok(header) => {
+            let (expr, errors) = frontend::parser::parse_program(&mut par);
+            (header, expr.map(FileBody::Main), errors)
+        }
+        Err(err) => (Header::default(), None, vec![err]),
+    };
+    for err in errors.iter() {
+        print!("{}", parse_error_diagnostic(err).report_in(map, 10));
+    }
+    match body {
+        Some(body) if errors.is_empty() => Ok((header, body)),
+        _ => Err(TopError::ParseError(errors.swap_remove(0))),
+    }
+}
+
+/// Lex and parse a main program and every module it imports, directly or not.
+/// Each module is nested around the modules importing it, see `Expr::Module`
+fn load_program(
+    map: &mut SourceMap,
+    file: FileId,
+    cancel: &CancellationToken,
+) -> Result<Expr, TopError> {
+    check_lexer(map, file)?;
+    let (header, body) = parse_file(map, file)?;
+    let expr = match body {
+        FileBody::Main(expr) => expr,
+        FileBody::Module(_) => {
+            let (name, span) = header.module.unwrap();
+            let diag = Diagnostic::error("not a main program")
+                .line_span(span, format!("the module `{name}` can only be imported"));
+            print!("{}", diag.report_in(map, 10));
+            return Err(TopError::ModuleError(1));
+        }
+    };
+    let mut loader = ModuleLoader {
+        cancel: cancel.clone(),
+        ..ModuleLoader::default()
+    };
+    loader.visit(map, file, header.imports, &mut Vec::new())?;
+    for diag in loader.diags.iter() {
+        print!("{}", diag.report_in(map, 10));
+    }
+    if !loader.diags.is_empty() {
+        return Err(TopError::ModuleError(loader.diags.len()));
+    }
+    let expr = loader.order.into_iter().rev().fold(expr, |cont, module| {
+        let cont = Box::new(cont);
+        Expr::Module {
+            name: module.name,
+            decls: module.decls,
+            cont,
+            span: module.span,
+        }
+    });
+    Ok(expr)
+}
+
+/// A module file, `span` is its `module` header
+struct ModuleFile {
+    name: InternStr,
+    file: FileId,
+    span: Span,
+    imports: Vec<(InternStr, Span)>,
+    decls: Vec<Decl>,
+}
+
+/// Finds the file of every module imported, see `load_program`
+#[derive(Default)]
+struct ModuleLoader {
+    /// the modules loaded, every module comes after the modules it imports
+    order: Vec<ModuleFile>,
+    loaded: HashSet<InternStr>,
+    diags: Vec<Diagnostic>,
+    /// checked before loading each file
+    cancel: CancellationToken,
+}
+
+impl ModuleLoader {
+    /// Load the modules imported by `from` and their own imports.
+    /// `path` is the chain of imports leading to `from`, with the span of each import
+    fn visit(
+        &mut self,
+        map: &mut SourceMap,
+        from: FileId,
+        imports: Vec<(InternStr, Span)>,
+        path: &mut Vec<(InternStr, Span)>,
+    ) -> Result<(), TopError> {
+        for (name, span) in imports {
+            if let Some(start) = path.iter().position(|(module, _)| *module == name) {
+                self.diags
+                    .push(import_cycle_diagnostic(&path[start..], name, span));
+                continue;
+            }
+            if !self.loaded.insert(name) {
+                continue;
+            }
+            self.cancel.check()?;
+            let Some(module) = self.load(map, from, name, span)? else {
+                continue;
+            };
+            path.push((name, span));
+            self.visit(map, module.file, module.imports.clone(), path)?;
+            path.pop();
+            self.order.push(module);
+        }
+        Ok(())
+    }
+
+    /// Read and parse the module `name` imported by `from` at `span`,
+    /// which is the file `name.nrm` in the same directory
+    fn load(
+        &mut self,
+        map: &mut SourceMap,
+        from: FileId,
+        name: InternStr,
+        span: Span,
+    ) -> Result<Option<ModuleFile>, TopError> {
+        let dir = map.path(from).parent().unwrap_or(Path::new(""));
+        let path = dir.join(format!("{name}.nrm"));
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let diag = Diagnostic::error("module not found").line_span(
+                    span,
+                    format!("there is no file `{}` for this module", path.display()),
+                );
+                self.diags.push(diag);
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let file = map.add_file(&path, source);
+        check_lexer(map, file)?;
+        match parse_file(map, file)? {
+            (
+                Header {
+                    module: Some((module, header)),
+                    imports,
+                },
+                FileBody::Module(decls),
+            ) if module == name => Ok(Some(ModuleFile {
+                name,
+                file,
+                span: header,
+                imports,
+                decls,
+            })),
+            (
+                Header {
+                    module: Some((module, header)),
+                    ..
+                },
+                _,
+            ) => {
+                let diag = Diagnostic::error("wrong module name")
+                    .line_span(span, format!("the module `{name}` is imported here"))
+                    .line_span(
+                        header,
+                        format!("but the file declares the module `{module}`"),
+                    );
+                self.diags.push(diag);
+                Ok(None)
+            }
+            (Header { module: None, .. }, _) => {
+                let diag = Diagnostic::error("not a module").line_span(
+                    span,
+                    format!("`{}` doesn't start with `module {name};`", path.display()),
+                );
+                self.diags.push(diag);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// `path` starts with the import of `name`, which is imported again at `span`
+fn import_cycle_diagnostic(path: &[(InternStr, Span)], name: InternStr, span: Span) -> Diagnostic {
+    let cycle: Vec<String> = path
+        .iter()
+        .map(|(module, _)| module.to_string())
+        .chain([name.to_string()])
+        .collect();
+    let mut diag = Diagnostic::error("import cycle").line(cycle.join(" -> "));
+    let edges = path
+        .windows(2)
+        .map(|edge| (edge[0].0, edge[1].0, edge[1].1));
+    let last = (path[path.len() - 1].0, name, span);
+    for (from, to, span) in edges.chain([last]) {
+        diag = diag.line_span(span, format!("`{from}` imports `{to}`"));
+    }
+    diag
+}
+
+/// Print the diagnostic of every typed hole, and fail if there is any.
+/// the program is type checked only to report what the holes should be
+fn check_holes(map: &SourceMap, expr: &Expr) -> Result<(), TopError> {
+    if !has_holes(expr) {
+        return Ok(());
+    }
+    let mut rnm = frontend::renamer::Renamer::new();
+    let expr = rnm.visit_expr(expr.clone());
+    let mut tych = Infer::new();
+    let _ = tych.infer_expr(&expr);
+    let holes = tych.typed_holes(&expr);
+    for hole in holes.iter() {
+        print!("{}", hole.diagnostic().report_in(map, 10));
+    }
+    Err(TopError::HoleError(holes.len()))
+}
+
 pub fn compile_source(source: String, dump: bool) -> Result<String, TopError> {
-    let mut par = frontend::parser::Parser::new(&source);
-    let expr = frontend::parser::parse_expr(&mut par)?;
+    let mut map = SourceMap::new();
+    let file = map.add_file("<input>", source);
+    compile_file(&mut map, file, dump)
+}
 
+/// Compile a file and the modules it imports, which are added to `map`
+pub fn compile_file(map: &mut SourceMap, file: FileId, dump: bool) -> Result<String, TopError> {
+    compile_file_cancellable(map, file, dump, &CancellationToken::new())
+}
+
+/// Same as `compile_file`, but gives up with `TopError::Cancelled` once `cancel` is cancelled.
+/// the token is checked before each file and between the phases
+pub fn compile_file_cancellable(
+    map: &mut SourceMap,
+    file: FileId,
+    dump: bool,
+    cancel: &CancellationToken,
+) -> Result<String, TopError> {
+    let expr = load_program(map, file, cancel)?;
+    cancel.check()?;
+    check_holes(map, &expr)?;
+    check_deprecated(map, &expr);
+    check_send_skipped(map, &expr);
+    cancel.check()?;
+    let (expr, ctx, nounroll) = lower_expr(expr, dump).map_err(|err| report_lowering(map, err))?;
+    cancel.check()?;
+    Ok(compile_ir(expr, &ctx, nounroll, dump))
+}
+
+/// Print the diagnostics of a lowering error, which can't be reported without the source
+fn report_lowering(map: &SourceMap, err: TopError) -> TopError {
+    if let TopError::LowerError(diags) = &err {
+        for diag in diags.iter() {
+            print!("{}", diag.report_in(map, 10));
+        }
+    }
+    err
+}
+
+pub fn compile_expr(expr: Expr, dump: bool) -> Result<String, TopError> {
+    let (expr, ctx, nounroll) = lower_expr(expr, dump)?;
+    Ok(compile_ir(expr, &ctx, nounroll, dump))
+}
+
+/// Lower a program to the IR, as it is printed by `--emit=ir`,
+/// together with the attributes that the IR doesn't carry
+pub fn lower_expr(
+    expr: Expr,
+    dump: bool,
+) -> Result<(MExpr, PassCtx, HashSet<InternStr>), TopError> {
     let mut rnm = frontend::renamer::Renamer::new();
     let expr = rnm.visit_expr(expr);
-    let expr = backend::normalize::Normalize::run(&expr);
+    if dump {
+        println!("renamer:\n{expr}");
+    }
+    // a recursive value has no order to be computed in, the later passes can't lower it,
+    // the representation of an abstract type stays hidden even if the program is run,
+    // and a name declared by several modules has no single declaration to refer to
+    let recursive: Vec<Diagnostic> = rnm
+        .errors()
+        .iter()
+        .filter(|err| {
+            matches!(
+                err,
+                RenameError::RecursiveValue(..)
+                    | RenameError::AbstractWithoutRepr(..)
+                    | RenameError::AbstractConstruct(..)
+                    | RenameError::AbstractMatch(..)
+                    | RenameError::AmbiguousImport(..)
+            )
+        })
+        .map(rename_error_diagnostic)
+        .collect();
+    if !recursive.is_empty() {
+        return Err(TopError::LowerError(recursive));
+    }
+    // a `case` that misses a value would abort at runtime
+    let non_exhaustive: Vec<Diagnostic> = frontend::exhaustiveness::check_expr(&expr)
+        .into_iter()
+        .filter(Diagnostic::is_error)
+        .collect();
+    if !non_exhaustive.is_empty() {
+        return Err(TopError::LowerError(non_exhaustive));
+    }
+    let nounroll = nounroll_funcs(&expr);
+    let ctx = backend::simple_opt::PassCtx::new(opt_levels(&expr));
+    // only variadic calls, threads, formats and abstract types need types for now,
+    // other programs aren't checked
+    let variadic = has_variadic_extern(&expr);
+    let varargs =
+        if variadic || spawns_thread(&expr) || uses_format(&expr) || declares_abstract(&expr) {
+            let mut tych = Infer::new();
+            match tych.infer_expr(&expr) {
+                Ok(_) => Some(tych.varargs().clone()),
+                // `Send`, formats and abstract types are only checked as far as the type checker
+                // goes, the normalizer checks what it can of the formats by itself, and the
+                // skipped `Send` checks are warned about by `check_send_skipped`
+                Err(InferError::NotSupportedYet) if !variadic => None,
+                Err(err) => return Err(TopError::TypeError(err)),
+            }
+        } else {
+            Some(HashMap::new())
+        };
+    let expr = frontend::const_fold::const_fold_expr(expr);
+    if dump {
+        println!("ast-fold:\n{expr}");
+    }
+    let expr = backend::normalize::Normalize::run_checked(&expr, varargs, div_check())
+        .map_err(TopError::LowerError)?;
     if dump {
         println!("normalize:\n{expr}");
     }
-    let expr = backend::simple_opt::DeadElim::run(expr);
+    Ok((expr, ctx, nounroll))
+}
+
+/// Optimize the IR and generate C code from it
+pub fn compile_ir(expr: MExpr, ctx: &PassCtx, nounroll: HashSet<InternStr>, dump: bool) -> String {
+    let expr = backend::simple_opt::UnreachElim::run_with_ctx(expr, ctx);
+    if dump {
+        println!("unreach-elim:\n{expr}");
+    }
+    let expr = backend::copy_prop::copy_propagate_with_ctx(expr, ctx);
+    if dump {
+        println!("copy-prop:\n{expr}");
+    }
+    let expr = backend::simple_opt::DeadElim::run_with_ctx(expr, ctx);
     if dump {
         println!("dead-elim:\n{expr}");
     }
-    let expr = backend::simple_opt::ConstFold::run(expr);
+    let expr = backend::simple_opt::ConstFold::run_with_ctx(expr, ctx);
     if dump {
         println!("const-fold:\n{expr}");
     }
-    let expr = backend::simple_opt::LinearInline::run(expr);
+    let expr = backend::simple_opt::LinearInline::run_with_ctx(expr, ctx);
     if dump {
         println!("linear-inline:\n{expr}");
     }
+    let expr = backend::inline::inline_small_functions_with_ctx(expr, &[], inline_threshold(), ctx);
+    if dump {
+        println!("inline-small:\n{expr}");
+    }
+    // the functions inlined at all their calls are left unused
+    let expr = backend::simple_opt::DeadElim::run_with_ctx(expr, ctx);
+    if dump {
+        println!("dead-elim:\n{expr}");
+    }
+    let expr = backend::dce::eliminate_dead_bindings_with_ctx(expr, ctx);
+    if dump {
+        println!("dce:\n{expr}");
+    }
     let expr = backend::clos_conv::ClosConv::run(expr);
     if dump {
         println!("clos-conv:\n{expr}");
     }
-    let expr = backend::simple_opt::DeadElim::run(expr);
+    let expr = backend::simple_opt::DeadElim::run_with_ctx(expr, ctx);
     if dump {
         println!("dead-elim:\n{expr}");
     }
-    let expr = backend::simple_opt::ConstFold::run(expr);
+    let expr = backend::simple_opt::ConstFold::run_with_ctx(expr, ctx);
     if dump {
         println!("const-fold:\n{expr}");
     }
-    let expr = backend::simple_opt::LinearInline::run(expr);
+    let expr = backend::simple_opt::LinearInline::run_with_ctx(expr, ctx);
     if dump {
         println!("linear-inline:\n{expr}");
     }
-    let text = backend::codegen::Codegen::run(&expr);
+    let expr = backend::tail_call::mark_tail_calls(expr);
+    if dump {
+        println!("tail-call:\n{expr}");
+    }
+    let text = backend::codegen::Codegen::run_with_nounroll(&expr, nounroll);
     if dump {
         println!("codegen:\n{text}");
     }
-    Ok(text)
+    text
+}
+
+static BETTER_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Blame type mismatches on the smallest expression that explains them,
+/// which type checks the program again for every expression tried, see `infer::localize`
+pub fn set_better_errors(flag: bool) {
+    BETTER_ERRORS.store(flag, Ordering::Relaxed);
+}
+
+fn better_errors() -> bool {
+    BETTER_ERRORS.load(Ordering::Relaxed)
+}
+
+static DIV_CHECK: AtomicBool = AtomicBool::new(true);
+
+/// Check every division by a divisor that isn't a literal for zero at runtime,
+/// turning it off leaves such a division undefined behavior, just like in C
+pub fn set_div_check(flag: bool) {
+    DIV_CHECK.store(flag, Ordering::Relaxed);
+}
+
+fn div_check() -> bool {
+    DIV_CHECK.load(Ordering::Relaxed)
+}
+
+static INLINE_THRESHOLD: AtomicUsize = AtomicUsize::new(backend::inline::INLINE_THRESHOLD);
+
+/// Inline the functions with at most `size` IR nodes at every call site, 0 turns it off
+pub fn set_inline_threshold(size: usize) {
+    INLINE_THRESHOLD.store(size, Ordering::Relaxed);
+}
+
+fn inline_threshold() -> usize {
+    INLINE_THRESHOLD.load(Ordering::Relaxed)
+}
+
+fn blame_diagnostic(blame: &Blame) -> Diagnostic {
+    let mut diag = Diagnostic::error("type error").line_span(
+        blame.culprit,
+        "this expression has a type that doesn't fit where it is used",
+    );
+    for span in blame.alternatives.iter() {
+        diag = diag.note_span(*span, "or this one might be wrong instead");
+    }
+    diag
+}
+
+/// Which phases of `compile_partial` finished without any error
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseFlags {
+    pub lexed: bool,
+    pub parsed: bool,
+    pub renamed: bool,
+    pub checked: bool,
+}
+
+/// Everything `compile_partial` managed to produce, each phase output is owned
+/// separately, so a later phase failing doesn't take the earlier ones away.
+#[derive(Debug)]
+pub struct PartialOutput {
+    pub tokens: Vec<Token>,
+    /// the parsed AST, `None` if the parser failed,
+    /// without the broken declarations if it recovered from them
+    pub ast: Option<Expr>,
+    /// the AST after renaming, available whenever `ast` is complete
+    pub renamed: Option<Expr>,
+    /// the type of the whole program, if type checking succeeded
+    pub ty: Option<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub phases: PhaseFlags,
+}
+
+/// Run the frontend as far as possible and collect whatever it produced,
+/// errors are reported as diagnostics instead of aborting the pipeline.
+pub fn compile_partial(source: &str) -> PartialOutput {
+    compile_partial_with_blame(source, better_errors())
+}
+
+/// Same as `compile_partial`, with type mismatches localized if `localize` is set
+pub fn compile_partial_with_blame(source: &str, localize: bool) -> PartialOutput {
+    compile_partial_cancellable(source, localize, &CancellationToken::new())
+        .expect("a new token is never cancelled")
+}
+
+/// Same as `compile_partial_with_blame`, but gives up once `cancel` is cancelled,
+/// for tools that start a new check whenever the source changes
+pub fn compile_partial_cancellable(
+    source: &str,
+    localize: bool,
+    cancel: &CancellationToken,
+) -> Result<PartialOutput, Cancelled> {
+    let mut diagnostics = Vec::new();
+    let mut phases = PhaseFlags::default();
+
+    let mut lexer = Lexer::new(source);
+    let tokens: Vec<Token> = lexer.by_ref().collect();
+    diagnostics.extend(lexer::lexer_diagnostics(&tokens));
+    phases.lexed = diagnostics.is_empty();
+    diagnostics.extend(lexer.warnings().iter().cloned());
+    cancel.check()?;
+
+    let mut par = frontend::parser::Parser::new(source);
+    let (ast, errors) = frontend::parser::parse_program(&mut par);
+    diagnostics.extend(errors.iter().map(parse_error_diagnostic));
+    phases.parsed = ast.is_some() && errors.is_empty();
+    cancel.check()?;
+
+    // the uses of dropped declarations would be reported as unbound names
+    let renamed = ast.clone().filter(|_| phases.parsed).map(|expr| {
+        let mut rnm = frontend::renamer::Renamer::new();
+        let expr = rnm.visit_expr(expr);
+        diagnostics.extend(rnm.errors().iter().map(rename_error_diagnostic));
+        phases.renamed = rnm.errors().is_empty();
+        expr
+    });
+    cancel.check()?;
+
+    // type checking an ill-scoped program makes no sense
+    let ty = match &renamed {
+        Some(expr) if phases.renamed => {
+            let mut tych = Infer::new().with_cancel(cancel.clone());
+            let res = tych.infer_expr(expr);
+            if res == Err(InferError::Cancelled) {
+                return Err(Cancelled);
+            }
+            // holes don't fail type checking, but each one is reported
+            diagnostics.extend(tych.typed_holes(expr).iter().map(TypedHole::diagnostic));
+            let blame = match &res {
+                Err(err) if err.is_mismatch() && localize => frontend::infer::localize(expr),
+                _ => None,
+            };
+            match res {
+                Ok(ty) => {
+                    phases.checked = true;
+                    Some(diagnostic::normalize_internals(&ty.diag()))
+                }
+                Err(_) if blame.is_some() => {
+                    diagnostics.push(blame_diagnostic(blame.as_ref().unwrap()));
+                    None
+                }
+                Err(err) => {
+                    diagnostics.push(err.diagnostic());
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    cancel.check()?;
+
+    if let Some(expr) = renamed.as_ref().filter(|_| phases.renamed) {
+        diagnostics.extend(frontend::exhaustiveness::check_expr(expr));
+        diagnostics.extend(deprecation_warnings(expr));
+        diagnostics.extend(frontend::const_fold::const_fold_diags(expr.clone()).1);
+    }
+
+    Ok(PartialOutput {
+        tokens,
+        ast,
+        renamed,
+        ty,
+        diagnostics,
+        phases,
+    })
+}
+
+fn parse_error_diagnostic(err: &ParseError) -> Diagnostic {
+    let diag = Diagnostic::error("parser error");
+    match err {
+        ParseError::LexerError(span, msg) => diag.line_span(*span, *msg),
+        ParseError::Unexpected(span, found, expect) => {
+            diag.line_span(*span, format!("expect {expect:?}, found {found:?}"))
+        }
+        ParseError::UnexpectedMany(span, found, expect) => {
+            diag.line_span(*span, format!("expect one of {expect:?}, found {found:?}"))
+        }
+        ParseError::UnknownBuiltin(span, name) => {
+            diag.line_span(*span, format!("unknown builtin {name}"))
+        }
+        ParseError::UnknownAttribute(span, name) => {
+            diag.line_span(*span, format!("unknown attribute {name}"))
+        }
+        ParseError::UnknownCallConv(span, name) => {
+            diag.line_span(*span, format!("unknown calling convention {name}"))
+        }
+        ParseError::PolymorphicRecord(span) => {
+            diag.line_span(*span, "records can't have type parameters yet")
+        }
+        ParseError::UnknownOptLevel(span, name) => diag
+            .line_span(*span, format!("unknown optimization level {name}"))
+            .line("expected `never` or `size`"),
+        ParseError::MisplacedAttribute(span) => {
+            diag.line_span(*span, "this attribute is not allowed here")
+        }
+        ParseError::UnsupportedSimd(span) => diag
+            .line_span(*span, "unsupported vector type")
+            .line(format!("only Simd[Real, {SIMD_LANES}] is supported")),
+        ParseError::UnboundPlaceholder(span) => diag
+            .line_span(
+                *span,
+                "placeholder `_` is not inside an application or operator",
+            )
+            .line("write the function explicitly, like `fun(x) => ...`"),
+        ParseError::ConflictingFixity(span, Some(prev)) => diag
+            .line_span(*span, "conflicting fixity declaration")
+            .line_span(*prev, "the operator was declared here first"),
+        ParseError::ConflictingFixity(span, None) => {
+            diag.line_span(*span, "can't change the fixity of a builtin operator")
+        }
+        ParseError::PrecedenceOutOfRange(span) => {
+            diag.line_span(*span, format!("precedence must be from 0 to {MAX_PREC}"))
+        }
+        ParseError::MissingElse(span) => diag
+            .line_span(*span, "`if` expression without `else`")
+            .line("both branches are needed, write `else ()` for a unit result"),
+        ParseError::ClauseArity(span, expect, found) => diag.line_span(
+            *span,
+            format!("this clause has {found} patterns, but the first clause has {expect}"),
+        ),
+        ParseError::BuiltinArity(span, expect, found) => diag.line_span(
+            *span,
+            format!("this builtin takes {expect} argument(s), but {found} are given"),
+        ),
+    }
+}
+
+fn rename_error_diagnostic(err: &RenameError) -> Diagnostic {
+    let diag = Diagnostic::error("scope error");
+    match err {
+        RenameError::UnboundedValueVariable(span, var) => {
+            diag.line_span(*span, format!("unbound variable {var}"))
+        }
+        RenameError::UnboundedTypeVariable(span, var) => {
+            diag.line_span(*span, format!("unbound type {var}"))
+        }
+        RenameError::UnboundedConstructorVariable(span, var)
+            if [LIST_CONS, LIST_NIL].contains(&var.name.as_ref()) =>
+        {
+            diag.line_span(*span, format!("unbound constructor {var}"))
+                .line(format!(
+                    "list syntax needs a list type in scope, like \
+                    `data List[T] = | {LIST_CONS}(T, List[T]) | {LIST_NIL} end`"
+                ))
+        }
+        RenameError::UnboundedConstructorVariable(span, var)
+            if [OPTION_SOME, OPTION_NONE].contains(&var.name.as_ref()) =>
+        {
+            diag.line_span(*span, format!("unbound constructor {var}"))
+                .line(format!(
+                    "`@chanrecv` needs an option type in scope, like \
+                    `data {OPTION}[T] = | {OPTION_SOME}(T) | {OPTION_NONE} end`"
+                ))
+        }
+        RenameError::UnboundedConstructorVariable(span, var)
+            if [RESULT_OK, RESULT_ERR].contains(&var.name.as_ref()) =>
+        {
+            diag.line_span(*span, format!("unbound constructor {var}"))
+                .line(format!(
+                    "`try`, `@readfile` and `@writefile` need a result type in scope, like \
+                    `data {RESULT}[T, E] = | {RESULT_OK}(T) | {RESULT_ERR}(E) end`"
+                ))
+        }
+        RenameError::UnboundedConstructorVariable(span, var) => {
+            diag.line_span(*span, format!("unbound constructor {var}"))
+        }
+        RenameError::UndefinedExternalFunction(span, func) => {
+            diag.line_span(*span, format!("undefined external function {func}"))
+        }
+        RenameError::MultipuleDefinition(span, var) => {
+            diag.line_span(*span, format!("multiple definition of {var}"))
+        }
+        RenameError::MultipuleExternalDefinition(span, func) => diag.line_span(
+            *span,
+            format!("multiple definition of external function {func}"),
+        ),
+        RenameError::UnboundedField(span, field) => {
+            diag.line_span(*span, format!("unknown field {}", field.name))
+        }
+        RenameError::DuplicateField(span, field) => {
+            diag.line_span(*span, format!("field {} is given twice", field.name))
+        }
+        RenameError::MissingField(span, field) => {
+            diag.line_span(*span, format!("missing field {}", field.name))
+        }
+        RenameError::FieldOfOtherRecord(span, field) => diag.line_span(
+            *span,
+            format!("field {} belongs to another record", field.name),
+        ),
+        RenameError::OrPatternBinding(span, var) => diag.line_span(
+            *span,
+            format!(
+                "variable {} is bound here, but not in every alternative",
+                var.name
+            ),
+        ),
+        RenameError::RecursiveValue(span, var) => diag
+            .line_span(
+                *span,
+                format!("value {} is needed to compute itself", var.name),
+            )
+            .line("only functions can be recursive, a value is computed before its uses"),
+        RenameError::AbstractWithoutRepr(span, typ) => diag
+            .line_span(
+                *span,
+                format!("abstract type {} has no representation", typ.name),
+            )
+            .line(format!(
+                "declare it with `type {0} = ...;` or `data {0} = ...` in the same block",
+                typ.name
+            )),
+        RenameError::AbstractConstruct(span, cons, typ) => diag
+            .line_span(
+                *span,
+                format!(
+                    "can't construct {} of the abstract type {}",
+                    cons.name, typ.name
+                ),
+            )
+            .line("only the declarations next to it can use its constructors"),
+        RenameError::AbstractMatch(span, cons, typ) => diag
+            .line_span(
+                *span,
+                format!(
+                    "can't match {} of the abstract type {}",
+                    cons.name, typ.name
+                ),
+            )
+            .line("only the declarations next to it can look inside it"),
+        RenameError::AmbiguousImport(span, name, modules) => {
+            let names: Vec<String> = modules
+                .iter()
+                .map(|module| format!("`{module}.{}`", name.name))
+                .collect();
+            let modules: Vec<&str> = modules.iter().map(|module| module.as_ref()).collect();
+            diag.line_span(
+                *span,
+                format!("{} is declared by {}", name.name, modules.join(" and ")),
+            )
+            .line(format!("use one of {} instead", names.join(", ")))
+        }
+    }
+}
+
+/// Collect the functions marked with `@deprecated_since(...)` in every block
+fn deprecated_funcs(expr: &Expr, funcs: &mut HashMap<Ident, (InternStr, InternStr)>) {
+    if let Expr::Blk { decls, .. } | Expr::LetRec { decls, .. } | Expr::Module { decls, .. } = expr
+    {
+        for decl in decls {
+            if let Decl::Func { name, attrs, .. } = decl {
+                for attr in attrs {
+                    if let Attr::Deprecated {
+                        since, replacement, ..
+                    } = attr
+                    {
+                        funcs.insert(*name, (*since, *replacement));
+                    }
+                }
+            }
+        }
+    }
+    for sub in expr.subexprs() {
+        deprecated_funcs(sub, funcs);
+    }
+}
+
+/// Warn about every use of a deprecated function in a renamed program
+fn deprecation_warnings(expr: &Expr) -> Vec<Diagnostic> {
+    fn visit(
+        expr: &Expr,
+        funcs: &HashMap<Ident, (InternStr, InternStr)>,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        if let Expr::Var { var, span } = expr {
+            if let Some((since, replacement)) = funcs.get(var) {
+                let diag = Diagnostic::warn("deprecated function").line_span(
+                    *span,
+                    format!(
+                        "'{}' has been deprecated since {since}; use '{replacement}' instead",
+                        var.name
+                    ),
+                );
+                diags.push(diag);
+            }
+        }
+        for sub in expr.subexprs() {
+            visit(sub, funcs, diags);
+        }
+    }
+    let mut funcs = HashMap::new();
+    deprecated_funcs(expr, &mut funcs);
+    let mut diags = Vec::new();
+    if !funcs.is_empty() {
+        visit(expr, &funcs, &mut diags);
+    }
+    diags
+}
+
+/// Print a warning for every use of a deprecated function, they don't stop the compilation
+fn check_deprecated(map: &SourceMap, expr: &Expr) {
+    let mut funcs = HashMap::new();
+    deprecated_funcs(expr, &mut funcs);
+    if funcs.is_empty() {
+        return;
+    }
+    let mut rnm = frontend::renamer::Renamer::new();
+    let expr = rnm.visit_expr(expr.clone());
+    for diag in deprecation_warnings(&expr) {
+        print!("{}", diag.report_in(map, 10));
+    }
+}
+
+/// A warning for every thread started and every value sent over a channel, when the type
+/// checker skips the program and can't check them for `Send`
+fn send_skipped_warnings(expr: &Expr) -> Vec<Diagnostic> {
+    fn visit(expr: &Expr, diags: &mut Vec<Diagnostic>) {
+        if let Expr::Prim { prim, span, .. } = expr {
+            let msg = match prim {
+                Builtin::Spawn | Builtin::SpawnWithStack => {
+                    "what this thread captures isn't checked for `Send`"
+                }
+                Builtin::ChanSend => "this sent value isn't checked for `Send`",
+                _ => "",
+            };
+            if !msg.is_empty() {
+                diags.push(
+                    Diagnostic::warn("unchecked thread")
+                        .line_span(*span, msg)
+                        .line("the type checker doesn't support this program yet"),
+                );
+            }
+        }
+        for sub in expr.subexprs() {
+            visit(sub, diags);
+        }
+    }
+    let mut diags = Vec::new();
+    if !spawns_thread(expr) {
+        return diags;
+    }
+    let mut rnm = frontend::renamer::Renamer::new();
+    let expr = rnm.visit_expr(expr.clone());
+    if Infer::new().infer_expr(&expr) == Err(InferError::NotSupportedYet) {
+        visit(&expr, &mut diags);
+    }
+    diags
+}
+
+/// Print the warnings of `send_skipped_warnings`, they don't stop the compilation
+fn check_send_skipped(map: &SourceMap, expr: &Expr) {
+    for diag in send_skipped_warnings(expr) {
+        print!("{}", diag.report_in(map, 10));
+    }
+}
+
+/// Collect toplevel functions marked with `@nounroll`
+fn nounroll_funcs(expr: &Expr) -> HashSet<InternStr> {
+    let decls = match expr {
+        Expr::Blk { decls, .. } => decls,
+        _ => return HashSet::new(),
+    };
+    decls
+        .iter()
+        .filter_map(|decl| match decl {
+            Decl::Func { name, attrs, .. }
+                if attrs
+                    .iter()
+                    .any(|attr| matches!(attr, Attr::NoUnroll { .. })) =>
+            {
+                Some(name.name)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collect the `@optimize(...)` levels of toplevel functions
+fn opt_levels(expr: &Expr) -> HashMap<InternStr, OptLevel> {
+    let decls = match expr {
+        Expr::Blk { decls, .. } => decls,
+        _ => return HashMap::new(),
+    };
+    decls
+        .iter()
+        .filter_map(|decl| match decl {
+            Decl::Func { name, attrs, .. } => attrs.iter().find_map(|attr| match attr {
+                Attr::Optimize { level, .. } => Some((name.name, *level)),
+                _ => None,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether any toplevel extern is declared with a trailing `...`
+fn has_variadic_extern(expr: &Expr) -> bool {
+    let decls = match expr {
+        Expr::Blk { decls, .. } => decls,
+        _ => return false,
+    };
+    decls.iter().any(|decl| {
+        matches!(
+            decl,
+            Decl::Extern { typ, .. }
+                if matches!(typ.unquantified(), Type::Fun { variadic: true, .. })
+        )
+    })
+}
+
+/// Whether there is a typed hole `?name` anywhere
+fn has_holes(expr: &Expr) -> bool {
+    matches!(expr, Expr::Hole { .. }) || expr.subexprs().into_iter().any(has_holes)
+}
+
+/// Whether a thread is started or a value is sent over a channel anywhere,
+/// the captures of the closure and the sent values are checked for `Send`
+fn spawns_thread(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Prim {
+            prim: Builtin::Spawn | Builtin::SpawnWithStack | Builtin::ChanSend,
+            ..
+        }
+    ) || expr.subexprs().into_iter().any(spawns_thread)
+}
+
+/// Whether `@format` is used anywhere, its arguments are checked against the format
+fn uses_format(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Prim {
+            prim: Builtin::Format,
+            ..
+        }
+    ) || expr.subexprs().into_iter().any(uses_format)
+}
+
+/// Whether an abstract type is declared anywhere, it must not be mixed up with its
+/// representation outside of its block
+fn declares_abstract(expr: &Expr) -> bool {
+    let decls = match expr {
+        Expr::Blk { decls, .. } | Expr::LetRec { decls, .. } => decls.as_slice(),
+        _ => &[],
+    };
+    decls
+        .iter()
+        .any(|decl| matches!(decl, Decl::Abstract { .. }))
+        || expr.subexprs().into_iter().any(declares_abstract)
 }
 
 pub fn run_compile(input: &PathBuf, output: &PathBuf, dump: bool) -> Result<(), TopError> {
     let source = fs::read_to_string(input)?;
-    let result = compile_source(source, dump)?;
+    let mut map = SourceMap::new();
+    let file = map.add_file(input, source);
+    let result = compile_file(&mut map, file, dump)?;
     let mut target = fs::File::create(output)?;
-    target.write(result.as_bytes())?;
+    target.write_all(result.as_bytes())?;
+    Ok(())
+}
+
+/// What `norem build` writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emit {
+    C,
+    Ir,
+}
+
+/// Parse and validate an IR file, printing every diagnostic
+fn parse_ir_file(map: &SourceMap, file: FileId) -> Result<MExpr, TopError> {
+    backend::anf_parse::parse_ir_in(map.source(file), file).map_err(|diags| {
+        for diag in diags.iter() {
+            print!("{}", diag.report_in(map, 10));
+        }
+        TopError::IrError(diags.len())
+    })
+}
+
+/// Build a source file, or an IR file if `from_ir` is set, to C code or to the IR.
+/// An IR file is optimized as if no function had attributes.
+pub fn build_file(
+    map: &mut SourceMap,
+    file: FileId,
+    from_ir: bool,
+    emit: Emit,
+) -> Result<String, TopError> {
+    let (expr, ctx, nounroll) = if from_ir {
+        let expr = parse_ir_file(map, file)?;
+        (expr, PassCtx::new(HashMap::new()), HashSet::new())
+    } else {
+        let expr = load_program(map, file, &CancellationToken::new())?;
+        check_holes(map, &expr)?;
+        check_send_skipped(map, &expr);
+        lower_expr(expr, false).map_err(|err| report_lowering(map, err))?
+    };
+    match emit {
+        Emit::C => Ok(compile_ir(expr, &ctx, nounroll, false)),
+        Emit::Ir => Ok(crate::utils::printer::print_ir(&expr)),
+    }
+}
+
+pub fn run_build(
+    input: &PathBuf,
+    from_ir: bool,
+    emit: Emit,
+    output: &PathBuf,
+) -> Result<(), TopError> {
+    let source = fs::read_to_string(input)?;
+    let mut map = SourceMap::new();
+    let file = map.add_file(input, source);
+    let result = build_file(&mut map, file, from_ir, emit)?;
+    fs::write(output, result)?;
     Ok(())
 }
 
+/// Build an IR file, link it with `library` and run it, returning what it printed
+pub fn run_ir(input: &PathBuf, library: Option<&PathBuf>) -> Result<process::Output, TopError> {
+    let dir = std::env::temp_dir();
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let code = dir.join(format!("norem-{stem}.ir.temp.c"));
+    let output = dir.join(format!("norem-{stem}.ir.out"));
+    run_build(input, true, Emit::C, &code)?;
+    link_files(&code, library, &output)?;
+    fs::remove_file(code)?;
+    let res = process::Command::new(&output).output()?;
+    fs::remove_file(output)?;
+    Ok(res)
+}
+
+/// Print the diagnostics of every phase and the type of the program,
+/// returns whether there was no error
+/// Type check the top-level declarations of a renamed program one at a time, so an error
+/// in one of them doesn't keep the others from being checked. Gives the type of every
+/// function and extern, or why it failed, in the order they are declared.
+pub fn check_module(expr: &Expr) -> Vec<(Ident, Result<String, Diagnostic>)> {
+    let (Expr::Blk { decls, .. } | Expr::LetRec { decls, .. }) = expr else {
+        return Vec::new();
+    };
+    let mut env = TypeEnv::new();
+    let mut types = Vec::new();
+    for decl in decls {
+        match env.check_decl(decl) {
+            Ok(Some(ty)) => {
+                let ty = diagnostic::normalize_internals(&ty.diag());
+                types.push((decl.get_name(), Ok(ty)));
+            }
+            Ok(None) => {}
+            Err(diag) => types.push((decl.get_name(), Err(diag))),
+        }
+    }
+    types
+}
+
+/// Print the diagnostics of a program and its type, with `decls` set the types of
+/// its top-level declarations are printed as well
+pub fn run_check(input: &PathBuf, decls: bool) -> Result<bool, TopError> {
+    let source = fs::read_to_string(input)?;
+    let mut map = SourceMap::new();
+    let file = map.add_file(input, source);
+    let out = compile_partial(map.source(file));
+    for diag in out.diagnostics.iter() {
+        print!("{}", diag.report_in(&map, 10));
+    }
+    if let Some(ty) = out.ty {
+        println!("{ty}");
+    }
+    let mut ok = !out.diagnostics.iter().any(Diagnostic::is_error);
+    if let Some(expr) = out.renamed.as_ref().filter(|_| decls && out.phases.renamed) {
+        for (name, ty) in check_module(expr) {
+            match ty {
+                Ok(ty) => println!("{} : {ty}", name.name),
+                Err(diag) => {
+                    println!("{} : ?", name.name);
+                    // the first error is usually reported for the whole program already
+                    if !out.diagnostics.contains(&diag) {
+                        print!("{}", diag.report_in(&map, 10));
+                    }
+                    ok &= !diag.is_error();
+                }
+            }
+        }
+    }
+    Ok(ok)
+}
+
 pub fn run_link(code: &PathBuf, library: &PathBuf, output: &PathBuf) -> Result<(), TopError> {
+    link_files(code, Some(library), output)
+}
+
+fn link_files(code: &PathBuf, library: Option<&PathBuf>, output: &PathBuf) -> Result<(), TopError> {
     if cfg!(target_os = "windows") {
         println!(
             "[WARNING] You are running one a windows os.\
 Please make sure a C compiler is installed and 'cc' command is avaliable."
         );
     }
-    process::Command::new("cc")
-        .arg(&code)
-        .arg(&library)
+    let mut cmd = process::Command::new("cc");
+    cmd.arg(code);
+    if let Some(library) = library {
+        cmd.arg(library);
+    }
+    let res = cmd
         .arg("-o")
         .arg(output)
+        .arg("-lm")
+        .arg("-pthread")
         .output()?;
+    if !res.status.success() {
+        let msg = String::from_utf8_lossy(&res.stderr).into_owned();
+        return Err(TopError::LinkError(msg));
+    }
     Ok(())
 }
 
@@ -125,3 +1248,1043 @@ pub fn run_compile_link(
     fs::remove_file(temp)?;
     Ok(())
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestKind {
+    Test,
+    Bench,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCase {
+    pub name: Ident,
+    pub kind: TestKind,
+}
+
+/// Functions named with this prefix are treated as tests even without `@test`.
+/// This convention is deprecated and only kept for old test files.
+pub static TEST_PREFIX: &str = "test-";
+
+/// Collect `@test` and `@bench` functions from the toplevel block of a program.
+pub fn discover_tests(expr: &Expr) -> (Vec<TestCase>, Vec<Diagnostic>) {
+    let mut cases = Vec::new();
+    let mut diags = Vec::new();
+    let decls = match main_program(expr) {
+        Expr::Blk { decls, .. } => decls,
+        _ => return (cases, diags),
+    };
+    for decl in decls {
+        if let Decl::Func {
+            name,
+            attrs,
+            pars,
+            span,
+            ..
+        } = decl
+        {
+            let kind = if attrs.iter().any(|attr| matches!(attr, Attr::Test { .. })) {
+                TestKind::Test
+            } else if attrs.iter().any(|attr| matches!(attr, Attr::Bench { .. })) {
+                TestKind::Bench
+            } else if name.name.starts_with(TEST_PREFIX) {
+                let diag = Diagnostic::warn("deprecated test declaration")
+                    .line_span(*span, "tests discovered by name prefix are deprecated")
+                    .line(format!("help: mark `{name}` with `@test` instead"));
+                diags.push(diag);
+                TestKind::Test
+            } else {
+                continue;
+            };
+            if !pars.is_empty() {
+                let diag = Diagnostic::error("invalid test declaration")
+                    .line_span(*span, format!("`{name}` should not take any parameter"));
+                diags.push(diag);
+                continue;
+            }
+            cases.push(TestCase { name: *name, kind });
+        }
+    }
+    (cases, diags)
+}
+
+/// Replace the body of the toplevel block with a call to the test function.
+/// the main program inside the modules it imports
+fn main_program(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Module { cont, .. } => main_program(cont),
+        _ => expr,
+    }
+}
+
+fn test_program(expr: &Expr, case: &TestCase) -> Expr {
+    match expr {
+        Expr::Module {
+            name,
+            decls,
+            cont,
+            span,
+        } => Expr::Module {
+            name: *name,
+            decls: decls.clone(),
+            cont: Box::new(test_program(cont, case)),
+            span: *span,
+        },
+        Expr::Blk { decls, cont, span } => {
+            let cont_span = *cont.span();
+            let func = Box::new(Expr::Var {
+                var: case.name,
+                span: cont_span,
+            });
+            let cont = Box::new(Expr::App {
+                func,
+                args: Vec::new(),
+                span: cont_span,
+            });
+            Expr::Blk {
+                decls: decls.clone(),
+                cont,
+                span: *span,
+            }
+        }
+        _ => unreachable!("tests can only be discovered in a toplevel block"),
+    }
+}
+
+fn build_test(
+    expr: &Expr,
+    case: &TestCase,
+    library: Option<&PathBuf>,
+) -> Result<PathBuf, TopError> {
+    let dir = std::env::temp_dir();
+    let code = dir.join(format!("norem-{}.temp.c", case.name));
+    let output = dir.join(format!("norem-{}.out", case.name));
+    let text = compile_expr(test_program(expr, case), false)?;
+    fs::write(&code, text)?;
+    link_files(&code, library, &output)?;
+    fs::remove_file(code)?;
+    Ok(output)
+}
+
+pub fn load_tests(input: &PathBuf) -> Result<(Expr, Vec<TestCase>), TopError> {
+    let source = fs::read_to_string(input)?;
+    let mut map = SourceMap::new();
+    let file = map.add_file(input, source);
+    let expr = load_program(&mut map, file, &CancellationToken::new())?;
+    check_holes(&map, &expr)?;
+    let (cases, diags) = discover_tests(&expr);
+    for diag in diags {
+        print!("{}", diag.report_in(&map, 10));
+    }
+    Ok((expr, cases))
+}
+
+/// Returns the number of failed tests
+pub fn run_tests(input: &PathBuf, library: Option<&PathBuf>) -> Result<usize, TopError> {
+    let (expr, cases) = load_tests(input)?;
+    let mut passed = 0;
+    let mut failed = 0;
+    for case in cases.iter().filter(|case| case.kind == TestKind::Test) {
+        let output = build_test(&expr, case, library)?;
+        let res = process::Command::new(&output).output()?;
+        fs::remove_file(output)?;
+        if res.status.success() {
+            println!("test {} ... ok", case.name);
+            passed += 1;
+        } else {
+            println!("test {} ... FAILED", case.name);
+            failed += 1;
+        }
+    }
+    println!("{passed} passed; {failed} failed");
+    Ok(failed)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchResult {
+    pub name: String,
+    pub warmup: usize,
+    pub iterations: usize,
+    pub mean: Duration,
+    pub min: Duration,
+}
+
+pub static BENCH_WARMUP: usize = 3;
+pub static BENCH_ITERATIONS: usize = 10;
+
+pub fn run_benches(
+    input: &PathBuf,
+    library: Option<&PathBuf>,
+) -> Result<Vec<BenchResult>, TopError> {
+    let (expr, cases) = load_tests(input)?;
+    let mut results = Vec::new();
+    for case in cases.iter().filter(|case| case.kind == TestKind::Bench) {
+        let output = build_test(&expr, case, library)?;
+        for _ in 0..BENCH_WARMUP {
+            process::Command::new(&output).output()?;
+        }
+        let mut times = Vec::new();
+        for _ in 0..BENCH_ITERATIONS {
+            let start = Instant::now();
+            process::Command::new(&output).output()?;
+            times.push(start.elapsed());
+        }
+        fs::remove_file(output)?;
+        results.push(BenchResult {
+            name: case.name.to_string(),
+            warmup: BENCH_WARMUP,
+            iterations: BENCH_ITERATIONS,
+            mean: times.iter().sum::<Duration>() / BENCH_ITERATIONS as u32,
+            min: times.into_iter().min().unwrap(),
+        });
+    }
+    Ok(results)
+}
+
+pub fn bench_table(results: &[BenchResult]) -> String {
+    let width = results
+        .iter()
+        .map(|res| res.name.len())
+        .chain(std::iter::once("benchmark".len()))
+        .max()
+        .unwrap();
+    let mut text = format!("{:<width$} | {:>12} | {:>12}\n", "benchmark", "mean", "min");
+    for res in results {
+        let mean = format!("{:?}", res.mean);
+        let min = format!("{:?}", res.min);
+        text.push_str(&format!("{:<width$} | {mean:>12} | {min:>12}\n", res.name));
+    }
+    text
+}
+
+pub fn bench_json(results: &[BenchResult]) -> String {
+    let items = results
+        .iter()
+        .map(|res| {
+            format!(
+                "{{\"name\":\"{}\",\"warmup\":{},\"iterations\":{},\"mean_ns\":{},\"min_ns\":{}}}",
+                res.name,
+                res.warmup,
+                res.iterations,
+                res.mean.as_nanos(),
+                res.min.as_nanos()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{\"benchmarks\":[{items}]}}")
+}
+
+/// Read back what `bench_json` wrote, `None` if it is anything else.
+/// The names are written without escapes, so they can't contain `"` or `,`.
+pub fn parse_bench_json(text: &str) -> Option<Vec<BenchResult>> {
+    let items = text
+        .trim()
+        .strip_prefix("{\"benchmarks\":[")?
+        .strip_suffix("]}")?;
+    if items.is_empty() {
+        return Some(Vec::new());
+    }
+    items
+        .strip_prefix('{')?
+        .strip_suffix('}')?
+        .split("},{")
+        .map(|item| {
+            let mut fields = HashMap::new();
+            for field in item.split(',') {
+                let (key, value) = field.split_once(':')?;
+                fields.insert(key.strip_prefix('"')?.strip_suffix('"')?, value);
+            }
+            let number = |key: &str| fields.get(key)?.parse::<u64>().ok();
+            Some(BenchResult {
+                name: fields
+                    .get("name")?
+                    .strip_prefix('"')?
+                    .strip_suffix('"')?
+                    .to_string(),
+                warmup: number("warmup")? as usize,
+                iterations: number("iterations")? as usize,
+                mean: Duration::from_nanos(number("mean_ns")?),
+                min: Duration::from_nanos(number("min_ns")?),
+            })
+        })
+        .collect()
+}
+
+/// The benchmarks of `results` whose minimum is more than `percent` % above the one
+/// of the same benchmark in `baseline`, with how many percent it is above.
+/// A benchmark missing from the baseline is new, and can't have regressed.
+pub fn bench_regressions(
+    baseline: &[BenchResult],
+    results: &[BenchResult],
+    percent: f64,
+) -> Vec<(String, f64)> {
+    results
+        .iter()
+        .filter_map(|res| {
+            let base = baseline.iter().find(|base| base.name == res.name)?;
+            let slower = (res.min.as_nanos() as f64 / base.min.as_nanos() as f64 - 1.0) * 100.0;
+            (slower > percent).then(|| (res.name.clone(), slower))
+        })
+        .collect()
+}
+
+#[test]
+fn discover_tests_test() {
+    let string = r#"
+begin
+    fun helper() => 42
+    @test fun parses-empty() => helper()
+    @bench fun sum-large() => @iadd(1, 2)
+    fun test-old-style() => 0
+    @test fun bad-test(x) => x
+in
+    0
+end
+"#;
+    let mut par = frontend::parser::Parser::new(string);
+    let expr = frontend::parser::parse_expr(&mut par).unwrap();
+    let (cases, diags) = discover_tests(&expr);
+    let cases: Vec<(String, TestKind)> = cases
+        .into_iter()
+        .map(|case| (case.name.to_string(), case.kind))
+        .collect();
+    assert_eq!(
+        cases,
+        vec![
+            ("parses-empty".to_string(), TestKind::Test),
+            ("sum-large".to_string(), TestKind::Bench),
+            ("test-old-style".to_string(), TestKind::Test),
+        ]
+    );
+    // one deprecation note and one rejected test
+    assert_eq!(diags.len(), 2);
+}
+
+#[test]
+fn misplaced_attribute_test() {
+    use crate::frontend::parser::ParseError;
+    let string = r#"
+begin
+    @test data Foo =
+    | Foo
+    end
+in
+    0
+end
+"#;
+    let mut par = frontend::parser::Parser::new(string);
+    let res = frontend::parser::parse_expr(&mut par);
+    assert!(matches!(res, Err(ParseError::MisplacedAttribute(_))));
+
+    let string = r#"
+begin
+    @callconv(stdcall) fun f(x) => x
+in
+    0
+end
+"#;
+    let mut par = frontend::parser::Parser::new(string);
+    let res = frontend::parser::parse_expr(&mut par);
+    assert!(matches!(res, Err(ParseError::MisplacedAttribute(_))));
+}
+
+#[test]
+fn call_conv_test() {
+    use crate::frontend::parser::ParseError;
+    let string = r#"
+begin
+    fun f(x) => #foo(x, 2)
+    @callconv(fastcall) extern foo : Int;
+in
+    f(1)
+end
+"#;
+    let text = compile_source(string.to_string(), false).unwrap();
+    assert!(text.contains("(void* (__attribute__((fastcall)) *)(void*, void*))foo;"));
+
+    let string = r#"
+begin
+    @callconv(pascal) extern foo : Int;
+in
+    0
+end
+"#;
+    let mut par = frontend::parser::Parser::new(string);
+    let res = frontend::parser::parse_expr(&mut par);
+    assert!(matches!(res, Err(ParseError::UnknownCallConv(_, _))));
+}
+
+#[test]
+fn optimize_attr_test() {
+    let string = r#"
+begin
+    @optimize(never)
+    fun f(x) => @iadd(@imul(2, 3), x)
+    @optimize(size)
+    fun g(x) => @iadd(@imul(2, 3), x)
+in
+    @iadd(f(1), g(2))
+end
+"#;
+    let text = compile_source(string.to_string(), false).unwrap();
+    // the never-optimized function still computes 2 * 3 at runtime
+    assert_eq!(text.matches(")*(int64_t)").count(), 1);
+
+    let string = r#"
+begin
+    @optimize(fast)
+    fun f(x) => x;
+in
+    f(1)
+end
+"#;
+    let mut par = frontend::parser::Parser::new(string);
+    let res = frontend::parser::parse_expr(&mut par);
+    assert!(matches!(res, Err(ParseError::UnknownOptLevel(_, _))));
+}
+
+#[test]
+fn compile_exhaustiveness_test() {
+    // a `case` that misses a value fails the compilation instead of aborting at runtime
+    let source = r#"
+begin
+    data List[T] =
+    | Cons(T,List[T])
+    | Nil
+    end
+    fun head(lst) =>
+        case lst of
+        | Cons(h, _) => { h }
+        end
+in
+    head(Nil)
+end
+"#;
+    let Err(TopError::LowerError(diags)) = compile_source(source.to_string(), false) else {
+        panic!("expected a lowering error")
+    };
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0]
+        .report(source, 10)
+        .contains("pattern `Nil` is not covered"));
+
+    // a refutable `let` is only a warning
+    let source = "let 2 = @iadd(1, 1); 3";
+    assert!(compile_source(source.to_string(), false).is_ok());
+}
+
+#[test]
+fn const_fold_overflow_test() {
+    // an overflow is left unfolded by both folders, instead of crashing the compiler
+    let text = compile_source("@iadd(9223372036854775807, 1)".to_string(), false).unwrap();
+    assert!(text.contains("9223372036854775807"));
+    let source = "@ineg(@isub(-9223372036854775807, 1))";
+    let text = compile_source(source.to_string(), false).unwrap();
+    assert!(text.contains("-(int64_t)"));
+}
+
+#[test]
+fn deprecated_test() {
+    let source = r#"
+begin
+    @deprecated_since("1.2.0", "bar")
+    fun foo(x) => @iadd(x, 1)
+    fun bar(x) => @iadd(x, 1)
+in
+    @iadd(foo(1), bar(2))
+end
+"#;
+    let out = compile_partial(source);
+    assert_eq!(out.ty.as_deref(), Some("Int"));
+    assert_eq!(out.diagnostics.len(), 1);
+    let report = out.diagnostics[0].report(source, 10);
+    assert_eq!(
+        report,
+        r#"[Warn]: deprecated function
+7 |     @iadd(foo(1), bar(2))
+  |           ^~~
+'foo' has been deprecated since 1.2.0; use 'bar' instead
+"#
+    );
+
+    // both the version and the replacement are needed
+    let mut par = frontend::parser::Parser::new(
+        r#"begin @deprecated_since("1.2.0") fun foo(x) => x in foo(1) end"#,
+    );
+    assert!(frontend::parser::parse_expr(&mut par).is_err());
+}
+
+#[test]
+fn format_test() {
+    let out = compile_partial(r#"fun(x, y) => @format("x = %d, y = %f", x, y)"#);
+    assert_eq!(out.ty.as_deref(), Some("fun(Int, Real) -> Str"));
+    assert!(out.diagnostics.is_empty());
+
+    // too few arguments for the specifiers
+    let source = r#"@format("x = %d, y = %f", 1)"#;
+    let out = compile_partial(source);
+    assert_eq!(out.ty, None);
+    assert_eq!(
+        out.diagnostics[0].report(source, 10),
+        r#"[Error]: type error
+1 | @format("x = %d, y = %f", 1)
+  | ^~~~~~~~~~~~~~~~~~~~~~~~~~~~
+the format has 2 specifier(s), but 1 argument(s) follow it
+"#
+    );
+
+    // `%d` takes an `Int`
+    let source = r#"@format("x = %d", 1.5)"#;
+    let out = compile_partial(source);
+    assert_eq!(out.ty, None);
+    assert_eq!(
+        out.diagnostics[0].report(source, 10),
+        r#"[Error]: type error
+mismatched types
+  [Note]: the type was first inferred here
+  1 | @format("x = %d", 1.5)
+    |                   ^~~
+  [Note]: but this expects a different type
+  1 | @format("x = %d", 1.5)
+    | ^~~~~~~~~~~~~~~~~~~~~~
+"#
+    );
+
+    // the format must be known at compile time
+    let out = compile_partial(r#"fun(f) => @format(f, 1)"#);
+    assert_eq!(out.ty, None);
+    let out = compile_partial(r#"@format("%q")"#);
+    assert_eq!(out.ty, None);
+}
+
+#[test]
+fn format_untyped_test() {
+    // the type checker doesn't support data types yet, so it can't check the arguments
+    let program = |format: &str| {
+        format!(
+            r#"
+begin
+    data Box =
+    | Box(Int)
+    end
+    fun unbox(b) =>
+        case b of
+        | Box(x) => {{ x }}
+        end
+in
+    {format}
+end
+"#
+        )
+    };
+    let bad_format = |format: &str| match compile_source(program(format), false) {
+        Err(TopError::LowerError(diags)) => {
+            assert_eq!(diags.len(), 1);
+            diags[0].report(&program(format), 10)
+        }
+        _ => panic!("`{format}` is accepted"),
+    };
+
+    // literals are still checked against the format
+    assert!(compile_source(program(r#"@format("%d %s", 3, "a")"#), false).is_ok());
+    let report = bad_format(r#"@format("%d", "a")"#);
+    assert!(report.contains("this argument doesn't fit its `Int` specifier"));
+    // a constructor is never a literal
+    let report = bad_format(r#"@format("%s", Box(3))"#);
+    assert!(report.contains("this argument doesn't fit its `Str` specifier"));
+    // any other argument can't be checked
+    let report = bad_format(r#"@format("%s", unbox(Box(3)))"#);
+    assert!(report.contains("must be a literal `Str`"));
+}
+
+#[test]
+fn send_skipped_test() {
+    // the type checker doesn't support data types yet, so it can't check what a thread
+    // captures, the compilation goes on with a warning
+    let program = |body: &str| {
+        format!(
+            r#"
+begin
+    data Box =
+    | Box(Int)
+    end
+    fun unbox(b) =>
+        case b of
+        | Box(x) => {{ x }}
+        end
+in
+    {body}
+end
+"#
+        )
+    };
+    let parse = |source: &str| {
+        let mut par = frontend::parser::Parser::new(source);
+        frontend::parser::parse_expr(&mut par).unwrap()
+    };
+    let source = program("let b = Box(3);\n    @join(@spawn(fun() => unbox(b)))");
+    let diags = send_skipped_warnings(&parse(&source));
+    assert_eq!(diags.len(), 1);
+    assert!(!diags[0].is_error());
+    let report = diags[0].report(&source, 10);
+    assert!(report.contains("what this thread captures isn't checked for `Send`"));
+    assert!(compile_source(source, false).is_ok());
+
+    // a checked program has nothing to warn about
+    let source = "let b = 3;\n@join(@spawn(fun() => b))";
+    assert!(send_skipped_warnings(&parse(source)).is_empty());
+}
+
+#[test]
+fn cancel_check_test() {
+    use std::thread;
+    // each function returns a bigger tuple than the last one, so checking is quadratic
+    let mut source = String::from("begin\nfun f0(x) => (x, x)\n");
+    for k in 1..2000 {
+        source.push_str(&format!("fun f{k}(x) => (f{}(x), x)\n", k - 1));
+    }
+    source.push_str("in f1999(1) end\n");
+
+    let token = CancellationToken::new();
+    let handle = {
+        let token = token.clone();
+        // the types are nested 2000 deep, more than the default stack of a thread
+        thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(move || compile_partial_cancellable(&source, false, &token).map(|_| ()))
+            .unwrap()
+    };
+    thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    token.cancel();
+    let res = handle.join().unwrap();
+    assert_eq!(res, Err(Cancelled));
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // the phases are checked even before type checking
+    let token = CancellationToken::new();
+    token.cancel();
+    assert!(compile_partial_cancellable("1", false, &token).is_err());
+    let mut map = SourceMap::new();
+    let file = map.add_file("<input>", "1".to_string());
+    let res = compile_file_cancellable(&mut map, file, false, &token);
+    assert!(matches!(res, Err(TopError::Cancelled)));
+}
+
+#[test]
+fn bench_json_test() {
+    let results = vec![BenchResult {
+        name: "sum-large".to_string(),
+        warmup: 3,
+        iterations: 10,
+        mean: Duration::from_nanos(1500),
+        min: Duration::from_nanos(1000),
+    }];
+    assert_eq!(
+        bench_json(&results),
+        r#"{"benchmarks":[{"name":"sum-large","warmup":3,"iterations":10,"mean_ns":1500,"min_ns":1000}]}"#
+    );
+}
+
+#[test]
+fn bench_baseline_test() {
+    let result = |name: &str, min: u64| BenchResult {
+        name: name.to_string(),
+        warmup: 3,
+        iterations: 10,
+        mean: Duration::from_nanos(min * 2),
+        min: Duration::from_nanos(min),
+    };
+    let baseline = vec![result("wide/lex", 1000), result("wide/parse", 2000)];
+    assert_eq!(
+        parse_bench_json(&bench_json(&baseline)),
+        Some(baseline.clone())
+    );
+    assert_eq!(parse_bench_json(&bench_json(&[])), Some(Vec::new()));
+    assert_eq!(parse_bench_json("{\"benchmarks\":[{\"name\":1}]}"), None);
+    assert_eq!(parse_bench_json(""), None);
+
+    // only the benchmarks slower by more than the percentage are reported
+    let results = vec![
+        result("wide/lex", 1100),
+        result("wide/parse", 3000),
+        result("wide/infer", 9000),
+    ];
+    assert_eq!(
+        bench_regressions(&baseline, &results, 20.0),
+        vec![("wide/parse".to_string(), 50.0)]
+    );
+    assert!(bench_regressions(&baseline, &results, 50.0).is_empty());
+    assert_eq!(bench_regressions(&baseline, &results, 5.0).len(), 2);
+}
+
+#[test]
+fn compile_partial_test() {
+    use crate::frontend::lexer::TokenKind;
+    // lexer error and parser error at the same time
+    let out = compile_partial("let x = 3. ;");
+    assert!(!out.tokens.is_empty());
+    assert!(out
+        .tokens
+        .iter()
+        .any(|tok| tok.kind == TokenKind::FailedToken));
+    assert!(out.ast.is_none());
+    assert!(out.renamed.is_none());
+    assert_eq!(out.diagnostics.len(), 2);
+    assert_eq!(out.phases, PhaseFlags::default());
+
+    // scope error, the renamed AST is still available
+    let out = compile_partial("let x = 1; @iadd(x, y)");
+    assert!(out.ast.is_some());
+    assert!(out.renamed.is_some());
+    assert!(out.ty.is_none());
+    assert_eq!(out.diagnostics.len(), 1);
+    assert!(out.phases.lexed && out.phases.parsed);
+    assert!(!out.phases.renamed && !out.phases.checked);
+
+    // type error
+    let out = compile_partial("let x = true; @iadd(x, 1)");
+    assert!(out.renamed.is_some());
+    assert!(out.ty.is_none());
+    assert_eq!(out.diagnostics.len(), 1);
+    assert!(out.phases.renamed && !out.phases.checked);
+
+    let out = compile_partial("let x = 1; @iadd(x, 2)");
+    assert_eq!(out.ty.as_deref(), Some("Int"));
+    assert!(out.diagnostics.is_empty());
+    assert!(out.phases.lexed && out.phases.parsed && out.phases.renamed && out.phases.checked);
+
+    // a mismatched return annotation points at the annotation
+    let out = compile_partial("begin fun f(x): Bool => @iadd(x, 1) in f end");
+    assert!(out.ty.is_none());
+    assert_eq!(out.diagnostics.len(), 1);
+    assert!(out.diagnostics[0]
+        .minimal_report(10)
+        .contains("doesn't have this return type"));
+
+    // a type mismatch notes where both conflicting types come from
+    let source = "let x = true;\n@iadd(x, 1)";
+    let out = compile_partial(source);
+    assert_eq!(out.diagnostics.len(), 1);
+    assert_eq!(
+        out.diagnostics[0].report(source, 10),
+        r#"[Error]: type error
+mismatched types
+  [Note]: the type was first inferred here
+  1 | let x = true;
+    |         ^~~~
+  [Note]: but this expects a different type
+  2 | @iadd(x, 1)
+    | ^~~~~~~~~~~
+"#
+    );
+
+    // receiving from a channel needs an option type
+    let out = compile_partial("@chanrecv(@channew(1))");
+    assert_eq!(out.diagnostics.len(), 2);
+    let report = out.diagnostics[0].minimal_report(10);
+    assert!(report.contains("`@chanrecv` needs an option type in scope"));
+
+    // and reading a file needs a result type
+    let out = compile_partial("@readfile(\"a.txt\")");
+    assert_eq!(out.diagnostics.len(), 2);
+    let report = out.diagnostics[0].minimal_report(10);
+    assert!(report.contains("`try`, `@readfile` and `@writefile` need a result type in scope"));
+
+    // a variable missing from one alternative points at that alternative
+    let source = "case (1, 2) of | (x, 1) | (1, _) => { 0 } | _ => { 1 } end";
+    let out = compile_partial(source);
+    assert!(out.renamed.is_some() && !out.phases.renamed);
+    assert_eq!(out.diagnostics.len(), 1);
+    let report = out.diagnostics[0].report(source, 10);
+    assert!(report.contains("variable x is bound here, but not in every alternative"));
+
+    // a variadic argument that C can't take points at the argument
+    let source = "begin extern log : fun(Int, ...) -> (); in #log(1, (2, 3)) end";
+    let out = compile_partial(source);
+    assert_eq!(out.diagnostics.len(), 1);
+    let report = out.diagnostics[0].report(source, 10);
+    assert!(report.contains("must be an `Int`, `Real`, `Bool`, `Char` or `Ptr`"));
+    assert!(report.contains("^~~~~~"));
+
+    // list syntax without a list type
+    let out = compile_partial("[1, 2]");
+    assert!(out.renamed.is_some());
+    assert!(out.diagnostics[0]
+        .minimal_report(10)
+        .contains("list syntax needs a list type in scope"));
+
+    // field diagnostics point at the field
+    let out = compile_partial("begin data P = { x: Int, x: Int } in 0 end");
+    assert!(out.diagnostics[0]
+        .minimal_report(10)
+        .contains("field x is given twice"));
+    let out = compile_partial("begin data P = { x: Int, y: Int } in { x = 1 }.z end");
+    let reports: Vec<String> = out
+        .diagnostics
+        .iter()
+        .map(|diag| diag.minimal_report(10))
+        .collect();
+    assert_eq!(reports.len(), 2);
+    assert!(reports[0].contains("missing field y"));
+    assert!(reports[1].contains("unknown field z"));
+
+    // type variables don't leak the internal counter
+    let out = compile_partial("fun(x, y) => (y, x)");
+    assert_eq!(out.ty.as_deref(), Some("fun(a, b) -> (b, a)"));
+
+    // redundant rule is reported, the program is still well-typed
+    let out = compile_partial("case 1 of | x => { x } | 2 => { 2 } end");
+    assert_eq!(out.ty.as_deref(), Some("Int"));
+    assert_eq!(out.diagnostics.len(), 1);
+    assert!(out.diagnostics[0]
+        .minimal_report(10)
+        .starts_with("[Warn]: redundant rule"));
+}
+
+#[test]
+fn abstract_type_test() {
+    // the functions next to `Meters` see that it is an `Int`, the rest of the block doesn't
+    let block = |cont: &str| {
+        format!(
+            "begin
+                type Meters;
+                type Meters = Int;
+                fun meters(n: Int): Meters => n
+                fun add(a: Meters, b: Meters): Meters => @iadd(a, b)
+                fun value(m: Meters): Int => m
+            in
+                {cont}
+            end"
+        )
+    };
+    let out = compile_partial(&block("value(add(meters(1), meters(2)))"));
+    assert!(out.diagnostics.is_empty());
+    assert_eq!(out.ty.as_deref(), Some("Int"));
+    let out = compile_partial(&block("add(meters(1), meters(2))"));
+    assert_eq!(out.ty.as_deref(), Some("Meters"));
+    for cont in ["add(meters(1), 2)", "@iadd(meters(1), 2)"] {
+        let out = compile_partial(&block(cont));
+        assert!(out.ty.is_none());
+        assert_eq!(out.diagnostics.len(), 1);
+    }
+
+    // the representation must be in the same block
+    let out = compile_partial("begin type Meters; in 0 end");
+    assert_eq!(out.diagnostics.len(), 1);
+    assert!(out.diagnostics[0]
+        .minimal_report(10)
+        .contains("abstract type Meters has no representation"));
+}
+
+#[test]
+fn check_module_test() {
+    let source = "begin
+    fun inc(x) => @iadd(x, 1)
+    fun bad(x) => @iadd(x, true)
+    fun twice(x) => inc(inc(x))
+in
+    twice(1)
+end";
+    let out = compile_partial(source);
+    let types: Vec<_> = check_module(&out.renamed.unwrap())
+        .into_iter()
+        .map(|(name, ty)| (name.name.to_string(), ty.ok()))
+        .collect();
+    let inc = Some("fun(Int) -> Int".to_string());
+    let expect = vec![
+        ("inc".to_string(), inc.clone()),
+        ("bad".to_string(), None),
+        ("twice".to_string(), inc),
+    ];
+    assert_eq!(types, expect);
+
+    // a program without declarations has nothing to check one at a time
+    let out = compile_partial("@iadd(1, 2)");
+    assert!(check_module(&out.renamed.unwrap()).is_empty());
+}
+
+#[test]
+fn compile_partial_recovery_test() {
+    // each broken function gets its own diagnostic, and nothing is reported
+    // about the uses of the dropped ones
+    let source = r#"
+begin
+    fun f(x) => @iadd(x, )
+    fun g(x) => if x then 1
+    fun h(x y) => x
+    fun good(x) => @iadd(f(x), h(g(x)))
+in
+    good(1)
+end
+"#;
+    let out = compile_partial(source);
+    assert!(out.ast.is_some());
+    assert!(out.renamed.is_none());
+    assert!(!out.phases.parsed);
+    assert_eq!(out.diagnostics.len(), 3);
+    assert!(out
+        .diagnostics
+        .iter()
+        .all(|diag| diag.minimal_report(10).starts_with("[Error]: parser error")));
+}
+
+#[test]
+fn compile_partial_line_ending_test() {
+    // a file saved on Windows, with a byte-order mark and "\r\n" line endings
+    let source = "\u{feff}begin\r\n    fun f(x) => `x\r\nin\r\n    f(1)\r\nend\r\n";
+    let out = compile_partial(source);
+    assert!(!out.phases.lexed);
+    assert_eq!(out.diagnostics.len(), 2);
+    assert_eq!(
+        out.diagnostics[0].report(source, 10),
+        "[Error]: lexer error\n2 |     fun f(x) => `x\n  |                 ^\nunrecognized token\n"
+    );
+
+    // a bare '\r' is only a warning
+    let source = "\u{feff}begin\r\n    fun f(x) => x\r\nin\r    f(1)\r\nend\r\n";
+    let out = compile_partial(source);
+    assert!(out.phases.lexed);
+    assert!(out.phases.checked);
+    assert_eq!(out.diagnostics.len(), 1);
+    let mut map = SourceMap::new();
+    map.add_file("main.nrm", source);
+    let report = out.diagnostics[0].report_in(&map, 10);
+    assert!(report.starts_with("[Warn]: bare carriage return\n--> main.nrm:3:3\n"));
+}
+
+#[test]
+fn compile_partial_typed_hole_test() {
+    // each hole gets its own report, instead of an unbound variable
+    let source = r#"begin
+    fun add(x, y) => @iadd(x, ?rhs)
+in
+    add(1, ?arg)
+end
+"#;
+    let out = compile_partial(source);
+    assert!(out.phases.renamed);
+    assert!(out.phases.checked);
+    assert_eq!(out.diagnostics.len(), 2);
+    assert_eq!(
+        out.diagnostics[0].report(source, 10),
+        r#"[Error]: typed hole
+2 |     fun add(x, y) => @iadd(x, ?rhs)
+  |                               ^~~~
+`?rhs` has the type Int
+in the function `add`
+with these variables in scope:
+  add : fun(Int, a) -> Int
+  x : Int
+  y : a
+"#
+    );
+    assert!(out.diagnostics[1]
+        .minimal_report(10)
+        .contains("`?arg` has the type a\n"));
+}
+
+#[test]
+fn compile_partial_blame_test() {
+    // the wrong argument of a 5-argument call, the standard report blames the use of `c`
+    let source = r#"begin
+    fun g() => f(1, 2, true, 4, 5)
+    fun f(a, b, c, d, e) => @iadd(a, @iadd(b, @iadd(c, @iadd(d, e))))
+    fun h() => f(1, 2, 3, 4, 5)
+in
+    0
+end
+"#;
+    let out = compile_partial_with_blame(source, false);
+    assert_eq!(out.diagnostics.len(), 1);
+    assert!(out.diagnostics[0]
+        .report(source, 10)
+        .contains("3 |     fun f(a, b, c, d, e) => @iadd(a, @iadd(b, @iadd(c, @iadd(d, e))))\n    |                                                     ^\n"));
+
+    let out = compile_partial_with_blame(source, true);
+    assert_eq!(out.diagnostics.len(), 1);
+    assert_eq!(
+        out.diagnostics[0].report(source, 10),
+        r#"[Error]: type error
+2 |     fun g() => f(1, 2, true, 4, 5)
+  |                        ^~~~
+this expression has a type that doesn't fit where it is used
+  [Note]: or this one might be wrong instead
+  2 |     fun g() => f(1, 2, true, 4, 5)
+    |                ^
+"#
+    );
+
+    // too large to localize, the standard report is used instead
+    let elems: Vec<String> = (0..frontend::infer::LOCALIZE_LIMIT)
+        .map(|i| i.to_string())
+        .collect();
+    let elems = elems.join(", ");
+    let source = format!("begin fun f() => ({elems}, @iadd(1, true)) in f end");
+    let out = compile_partial_with_blame(&source, true);
+    assert_eq!(out.diagnostics.len(), 1);
+    assert!(out.diagnostics[0]
+        .report(&source, 10)
+        .contains("mismatched types"));
+}
+
+#[test]
+fn load_program_test() {
+    let dir = std::env::temp_dir().join("norem-load-program");
+    fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, text: &str| fs::write(dir.join(name), text).unwrap();
+
+    // every module is loaded once, after the modules it imports
+    write(
+        "Left.nrm",
+        "module Left;\nimport Base;\nfun left() => base()",
+    );
+    write(
+        "Right.nrm",
+        "module Right;\nimport Base;\nfun right() => Base.base()",
+    );
+    write("Base.nrm", "module Base;\nfun base() => 1");
+    let mut map = SourceMap::new();
+    let file = map.add_file(dir.join("main.nrm"), "import Left;\nimport Right;\n0");
+    let mut expr = load_program(&mut map, file, &CancellationToken::new()).unwrap();
+    let mut names = Vec::new();
+    while let Expr::Module { name, cont, .. } = expr {
+        names.push(name.to_string());
+        expr = *cont;
+    }
+    assert_eq!(names, ["Base", "Left", "Right"]);
+
+    // an import cycle is reported with every import in it
+    write("Even.nrm", "module Even;\nimport Odd;\nfun even(n) => 1");
+    write(
+        "Odd.nrm",
+        "module Odd;\nimport Base;\nimport Even;\nfun odd(n) => 1",
+    );
+    let mut map = SourceMap::new();
+    let file = map.add_file(dir.join("main.nrm"), "import Even;\n0");
+    let (header, _) = parse_file(&map, file).unwrap();
+    let mut loader = ModuleLoader::default();
+    loader
+        .visit(&mut map, file, header.imports, &mut Vec::new())
+        .unwrap();
+    assert_eq!(loader.diags.len(), 1);
+    let report = loader.diags[0].report_in(&map, 10);
+    let lines: Vec<&str> = report
+        .lines()
+        .filter(|line| !line.starts_with("-->"))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "[Error]: import cycle",
+            "Even -> Odd -> Even",
+            "2 | import Odd;",
+            "  | ^~~~~~~~~~~",
+            "`Even` imports `Odd`",
+            "3 | import Even;",
+            "  | ^~~~~~~~~~~~",
+            "`Odd` imports `Even`",
+        ]
+    );
+    assert!(report.contains("Odd.nrm:3:1\n"));
+}
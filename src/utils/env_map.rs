@@ -38,6 +38,15 @@ pub struct EnvMap<K, V> {
     scopes: Vec<usize>,
 }
 
+impl<K, V> Default for EnvMap<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Many implementations are just the same as HashMap
 impl<K, V> EnvMap<K, V>
 where
@@ -87,28 +96,28 @@ where
     }
 
     /// Returns a reference to the value corresponding to the key.
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.base_map.get(k)
     }
 
     /// Returns the key-value pair corresponding to the supplied key.
-    pub fn get_key_value<Q: ?Sized>(&self, k: &Q) -> Option<(&K, &V)>
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.base_map.get_key_value(k)
     }
 
     /// Returns `true` if the map contains a value for the specified key.
-    pub fn contains_key<Q: ?Sized>(&self, k: &Q) -> bool
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.base_map.contains_key(k)
     }
@@ -124,6 +133,29 @@ where
         }
     }
 
+    /// Returns a reference to the value of the key, inserting the value of `f` if there is none.
+    /// The reference borrows the map, so no scope can be left while it is alive:
+    ///
+    /// ```compile_fail
+    /// use norem::utils::env_map::EnvMap;
+    /// let mut env = EnvMap::new();
+    /// env.enter_scope();
+    /// let v = env.get_or_insert_with(1, || 'a');
+    /// env.leave_scope();
+    /// assert_eq!(*v, 'a');
+    /// ```
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, k: K, f: F) -> &V {
+        if !self.base_map.contains_key(&k) {
+            self.history.push(EnvOpr::Insert(k.clone()));
+        }
+        self.base_map.entry(k).or_insert_with(f)
+    }
+
+    /// Gets the entry of a key, for modifying its value in place
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V> {
+        Entry { env: self, key: k }
+    }
+
     /// Removes a key from the map
     pub fn remove(&mut self, k: &K) -> bool {
         if let Some(old) = self.base_map.remove(k) {
@@ -135,11 +167,53 @@ where
         }
     }
 
+    /// Returns the value corresponding to the key, and the depth of the scope it was bound in.
+    /// Bindings made outside of any scope have depth 0.
+    /// It searches the history, so it takes time linear in the number of operations.
+    pub fn lookup_depth(&self, k: &K) -> Option<(&V, usize)> {
+        let v = self.base_map.get(k)?;
+        let pos = self
+            .history
+            .iter()
+            .rposition(|opr| matches!(opr, EnvOpr::Update(k2, _) | EnvOpr::Insert(k2) if k2 == k))
+            .unwrap();
+        let depth = self.scopes.partition_point(|&pivot| pivot <= pos);
+        Some((v, depth))
+    }
+
+    /// Combines the bindings of two environments, `on_conflict` gives the value of a key
+    /// bound in both from the value in `a` and the value in `b`.
+    /// The scopes are not kept, every binding of the result is made outside of any scope.
+    pub fn merge<F>(a: EnvMap<K, V>, b: EnvMap<K, V>, on_conflict: F) -> EnvMap<K, V>
+    where
+        F: Fn(K, V, V) -> V,
+    {
+        let mut base_map = a.base_map;
+        for (k, v2) in b.base_map {
+            let v = match base_map.remove(&k) {
+                Some(v1) => on_conflict(k.clone(), v1, v2),
+                None => v2,
+            };
+            base_map.insert(k, v);
+        }
+        let mut env = EnvMap::with_capacity(base_map.len());
+        for (k, v) in base_map {
+            env.insert(k, v);
+        }
+        env
+    }
+
     /// Enter a new scope, record the current pivot of history
     pub fn enter_scope(&mut self) {
         self.scopes.push(self.history.len())
     }
 
+    /// Enter a new scope, which is left when the returned guard is dropped
+    pub fn enter_scope_guarded(&mut self) -> ScopeGuard<'_, K, V> {
+        self.enter_scope();
+        ScopeGuard { env: self }
+    }
+
     /// Leave from a scope, unwind the history and recover.
     pub fn leave_scope(&mut self) {
         let n = self.scopes.pop().unwrap();
@@ -168,6 +242,84 @@ where
     }
 }
 
+/// `Entry` is a key of an `EnvMap`, every change made through it is recorded in the history,
+/// so [`EnvMap::leave_scope`] undoes it like an insert. There is no `&mut V` to the values,
+/// a change that isn't recorded couldn't be undone.
+pub struct Entry<'a, K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    env: &'a mut EnvMap<K, V>,
+    key: K,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Modifies the value in place if there is one, a copy of the old value is recorded
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self
+    where
+        V: Clone,
+    {
+        if let Some(v) = self.env.base_map.get_mut(&self.key) {
+            self.env
+                .history
+                .push(EnvOpr::Update(self.key.clone(), v.clone()));
+            f(v);
+        }
+        self
+    }
+
+    /// Returns a reference to the value, inserting the value of `f` if there is none.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a V {
+        self.env.get_or_insert_with(self.key, f)
+    }
+}
+
+/// `ScopeGuard` gives access to an `EnvMap` inside a scope,
+/// and leaves the scope when it is dropped
+pub struct ScopeGuard<'a, K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    env: &'a mut EnvMap<K, V>,
+}
+
+impl<K, V> std::ops::Deref for ScopeGuard<'_, K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    type Target = EnvMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        self.env
+    }
+}
+
+impl<K, V> std::ops::DerefMut for ScopeGuard<'_, K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.env
+    }
+}
+
+impl<K, V> Drop for ScopeGuard<'_, K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn drop(&mut self) {
+        self.env.leave_scope();
+    }
+}
+
 impl<K, V> std::ops::Index<&K> for EnvMap<K, V>
 where
     K: Hash + Eq,
@@ -196,6 +348,120 @@ fn env_map_test() {
     assert_eq!(env.get(&3), None);
 }
 
+#[test]
+fn env_map_guard_test() {
+    let mut env = EnvMap::new();
+    env.insert(1, 'a');
+    env.insert(2, 'b');
+    {
+        let mut env = env.enter_scope_guarded();
+        env.insert(1, 'c');
+        env.remove(&2);
+        {
+            let mut env = env.enter_scope_guarded();
+            env.insert(2, 'd');
+            env.insert(3, 'e');
+            // shadowing is visible from the depth
+            assert_eq!(env.lookup_depth(&1), Some((&'c', 1)));
+            assert_eq!(env.lookup_depth(&2), Some((&'d', 2)));
+            assert_eq!(env.lookup_depth(&3), Some((&'e', 2)));
+        }
+        assert_eq!(env.lookup_depth(&1), Some((&'c', 1)));
+        assert_eq!(env.lookup_depth(&2), None);
+        assert_eq!(env.lookup_depth(&3), None);
+    }
+    assert_eq!(env.lookup_depth(&1), Some((&'a', 0)));
+    assert_eq!(env.lookup_depth(&2), Some((&'b', 0)));
+    assert_eq!(env.lookup_depth(&3), None);
+    assert_eq!(env.scopes.len(), 0);
+    assert_eq!(env.history.len(), 2);
+}
+
+#[test]
+fn env_map_entry_test() {
+    use std::cell::Cell;
+    thread_local! {
+        static CLONES: Cell<usize> = const { Cell::new(0) };
+    }
+    // counts how many times it is cloned
+    #[derive(Debug, PartialEq)]
+    struct Counted(Vec<i32>);
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.with(|n| n.set(n.get() + 1));
+            Counted(self.0.clone())
+        }
+    }
+
+    let mut env = EnvMap::new();
+    env.insert('a', Counted(vec![1]));
+    env.enter_scope();
+    // looking up and inserting never clone
+    assert_eq!(
+        env.get_or_insert_with('a', || unreachable!()),
+        &Counted(vec![1])
+    );
+    assert_eq!(
+        env.get_or_insert_with('b', || Counted(vec![2])),
+        &Counted(vec![2])
+    );
+    assert_eq!(
+        env.entry('c').or_insert_with(|| Counted(vec![3])),
+        &Counted(vec![3])
+    );
+    assert_eq!(CLONES.with(Cell::get), 0);
+    // modifying in place copies the old value once, to restore it later
+    env.entry('a').and_modify(|v| v.0.push(4));
+    env.entry('d').and_modify(|_| unreachable!());
+    assert_eq!(CLONES.with(Cell::get), 1);
+    assert_eq!(env.get(&'a'), Some(&Counted(vec![1, 4])));
+    assert_eq!(env.get(&'d'), None);
+    env.leave_scope();
+    assert_eq!(env.get(&'a'), Some(&Counted(vec![1])));
+    assert_eq!(env.get(&'b'), None);
+    assert_eq!(env.get(&'c'), None);
+    assert_eq!(env.history.len(), 1);
+}
+
+#[test]
+fn env_map_merge_test() {
+    let mut a = EnvMap::new();
+    a.insert("x", 1);
+    a.enter_scope();
+    a.insert("y", 2);
+    let mut b = EnvMap::new();
+    b.insert("z", 3);
+    let env = EnvMap::merge(a.clone(), b, |_, _, _| unreachable!());
+    assert_eq!(env.get("x"), Some(&1));
+    assert_eq!(env.get("y"), Some(&2));
+    assert_eq!(env.get("z"), Some(&3));
+    // the scope of `a` is gone, everything is bound at depth 0
+    assert_eq!(env.lookup_depth(&"y"), Some((&2, 0)));
+    assert_eq!(env.scopes.len(), 0);
+
+    // the callback sees both values of a key bound twice
+    let mut b = EnvMap::new();
+    b.insert("y", 4);
+    b.insert("w", 5);
+    let env = EnvMap::merge(a, b, |k, v1, v2| {
+        assert_eq!((k, v1, v2), ("y", 2, 4));
+        v1
+    });
+    assert_eq!(env.get("y"), Some(&2));
+    assert_eq!(env.get("w"), Some(&5));
+    assert_eq!(env.iter().count(), 3);
+}
+
+#[test]
+#[should_panic(expected = "`x` is defined in both")]
+fn env_map_merge_conflict_test() {
+    let mut a = EnvMap::new();
+    a.insert("x", 1);
+    let mut b = EnvMap::new();
+    b.insert("x", 2);
+    EnvMap::merge(a, b, |k, _, _| panic!("`{k}` is defined in both"));
+}
+
 #[derive(Clone, Debug)]
 pub struct FreeSet<T> {
     /// The wrapped HashSet allow us to do all the work.
@@ -204,6 +470,15 @@ pub struct FreeSet<T> {
     set_vec: Vec<HashSet<T>>,
 }
 
+impl<T> Default for FreeSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> FreeSet<T>
 where
     T: Eq + Hash + Clone,
@@ -240,10 +515,10 @@ where
     }
 
     /// Returns true if the set contains a value.
-    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool
+    pub fn contains<Q>(&self, value: &Q) -> bool
     where
         T: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.base_set.contains(value)
     }
@@ -254,10 +529,10 @@ where
     }
 
     /// Removes a value from the set. Returns whether the value was present in the set.
-    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> bool
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
     where
         T: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.base_set.remove(value)
     }
@@ -1,11 +1,20 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::{fmt, ops, sync};
+use std::{fmt, mem, ops, sync};
 
 lazy_static::lazy_static! {
     static ref INTERNER: sync::Mutex<Interner> = sync::Mutex::new(Interner::new());
+    // strings of interners dropped by `with_fresh_interner`, they are never freed
+    // because `InternStr::as_ref` hands out `&'static str`
+    static ref RETIRED: sync::Mutex<Vec<Vec<String>>> = sync::Mutex::new(Vec::new());
 }
 
-struct Interner {
+thread_local! {
+    // set by `with_fresh_interner`, it takes priority over the global interner
+    static FRESH: RefCell<Option<Interner>> = const { RefCell::new(None) };
+}
+
+pub struct Interner {
     str_to_idx: HashMap<String, usize>,
     idx_to_str: Vec<String>,
 }
@@ -25,8 +34,7 @@ impl Interner {
 
     fn intern<S: Into<String>>(&mut self, s: S) -> InternStr {
         let s: String = s.into();
-        // we assume there is no "empty identifier"
-        assert_ne!(s.as_str(), "");
+        // there is no empty identifier, but a string literal can be empty
         if let Some(idx) = self.str_to_idx.get(&s) {
             InternStr(*idx)
         } else {
@@ -37,9 +45,55 @@ impl Interner {
         }
     }
 
-    fn get_str<'a>(&'a self, s: InternStr) -> &'a str {
+    fn get_str(&self, s: InternStr) -> &str {
         &self.idx_to_str[s.0]
     }
+
+    /// all interned strings, in the order they were interned
+    pub fn iter(&self) -> impl Iterator<Item = (InternStr, &str)> {
+        self.idx_to_str
+            .iter()
+            .enumerate()
+            .map(|(idx, s)| (InternStr(idx), s.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.idx_to_str.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idx_to_str.is_empty()
+    }
+}
+
+fn with_current<R>(f: impl FnOnce(&mut Interner) -> R) -> R {
+    FRESH.with(|fresh| match fresh.borrow_mut().as_mut() {
+        Some(int) => f(int),
+        None => f(&mut INTERNER.lock().unwrap()),
+    })
+}
+
+/// run `f` with the interner of the current thread, `f` must not intern new strings
+pub fn with_interner<R>(f: impl FnOnce(&Interner) -> R) -> R {
+    with_current(|int| f(int))
+}
+
+/// run `f` with a fresh interner on the current thread, so test cases don't see
+/// each other's strings. Other threads keep using the global interner.
+/// An `InternStr` created inside `f` must not be used after `f` returns.
+pub fn with_fresh_interner<R>(f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Interner>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let fresh = FRESH.with(|fresh| mem::replace(&mut *fresh.borrow_mut(), self.0.take()));
+            if let Some(fresh) = fresh {
+                RETIRED.lock().unwrap().push(fresh.idx_to_str);
+            }
+        }
+    }
+    let prev = FRESH.with(|fresh| fresh.borrow_mut().replace(Interner::new()));
+    let _restore = Restore(prev);
+    f()
 }
 
 #[derive(Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord)]
@@ -47,7 +101,23 @@ pub struct InternStr(usize);
 
 impl InternStr {
     pub fn new<S: Into<String>>(s: S) -> InternStr {
-        INTERNER.lock().unwrap().intern(s)
+        with_current(|int| int.intern(s))
+    }
+
+    /// the interned string, every equal `InternStr` gives the same address
+    pub fn as_str(&self) -> &'static str {
+        with_current(|int| {
+            let s: &str = int.get_str(*self);
+            // No interner ever frees a string (see `RETIRED`),
+            // so this is safe to extend lifetime to static
+            let s: &'static str = unsafe { std::mem::transmute(s) };
+            s
+        })
+    }
+
+    /// the position in the interner, unique among the strings interned so far
+    pub fn index(&self) -> usize {
+        self.0
     }
 }
 
@@ -60,12 +130,7 @@ impl ops::Deref for InternStr {
 
 impl AsRef<str> for InternStr {
     fn as_ref(&self) -> &'static str {
-        let interner = INTERNER.lock().unwrap();
-        let s: &str = interner.get_str(*self);
-        // The interner is static global and it never removes a string,
-        // so this is safe to extend lifetime to static
-        let s: &'static str = unsafe { std::mem::transmute(s) };
-        s
+        self.as_str()
     }
 }
 
@@ -115,7 +180,7 @@ impl Ident {
     // no need to lookup interner
     pub fn generate(ch: char) -> Ident {
         assert!(ch.is_ascii_alphabetic() && ch.is_ascii_lowercase());
-        let n = ch as u8 - 'a' as u8;
+        let n = ch as u8 - b'a';
         let ident = InternStr(n as usize);
         Ident {
             name: ident,
@@ -138,6 +203,16 @@ impl Ident {
             }
         }
     }
+
+    /// make sure `uniquify` never hands out `index` again,
+    /// for identifiers that were read back from text instead of generated
+    pub fn reserve(index: usize) {
+        unsafe {
+            if COUNTER <= index {
+                COUNTER = index + 1;
+            }
+        }
+    }
 }
 
 impl From<InternStr> for Ident {
@@ -179,6 +254,24 @@ fn intern_test() {
     assert_eq!(format!("{}", s4), "bar");
 }
 
+#[test]
+fn intern_str_literal_test() {
+    use crate::frontend::ast::LitVal;
+    // equal strings are stored once, so they share one address
+    let s1 = InternStr::new("hello, world\n");
+    let s2 = InternStr::new(String::from("hello, world\n"));
+    assert_eq!(s1.as_str().as_ptr(), s2.as_str().as_ptr());
+    assert_eq!(s1.index(), s2.index());
+    assert_eq!(LitVal::Str(s1), LitVal::Str(s2));
+
+    let s3 = InternStr::new("hello");
+    assert_ne!(s1.as_str().as_ptr(), s3.as_str().as_ptr());
+    assert_ne!(LitVal::Str(s1), LitVal::Str(s3));
+
+    // unlike identifiers, a string literal can be empty
+    assert_eq!(InternStr::new("").as_str(), "");
+}
+
 #[test]
 fn uniquify_test() {
     // test function `Ident::uniquify`
@@ -202,3 +295,27 @@ fn uniquify_test() {
     assert_ne!(x1, x2);
     assert_eq!(x1.name, x2.name);
 }
+
+#[test]
+fn fresh_interner_test() {
+    let outer = InternStr::new("outer_only");
+    with_fresh_interner(|| {
+        // only the single-letter names are preloaded
+        assert_eq!(with_interner(|int| int.len()), 26);
+        let s1 = InternStr::new("fresh_only");
+        assert_eq!(format!("{s1}"), "fresh_only");
+        let strs: Vec<_> = with_interner(|int| int.iter().map(|(_, s)| s.to_string()).collect());
+        assert!(strs.contains(&"fresh_only".to_string()));
+        assert!(!strs.contains(&"outer_only".to_string()));
+
+        // nested fresh interners are isolated as well
+        with_fresh_interner(|| {
+            assert_eq!(with_interner(|int| int.len()), 26);
+        });
+        assert_eq!(with_interner(|int| int.len()), 27);
+        let last = with_interner(|int| int.iter().last().map(|(idx, s)| (idx, s.to_string())));
+        assert_eq!(last, Some((s1, "fresh_only".to_string())));
+    });
+    assert_eq!(format!("{outer}"), "outer_only");
+    assert!(with_interner(|int| int.iter().any(|(s, _)| s == outer)));
+}
@@ -1,4 +1,8 @@
+pub mod cancel;
 pub mod env_map;
 pub mod intern;
 pub mod printer;
+pub mod reference;
+pub mod source_map;
+pub mod test_support;
 pub mod driver;
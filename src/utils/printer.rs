@@ -1,40 +1,175 @@
 use crate::backend::anf::*;
 use crate::frontend::ast::*;
+use crate::frontend::lexer::is_opr_char;
+use crate::frontend::position::Spanned;
+use crate::utils::intern::{Ident, InternStr};
 use itertools::Itertools;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt::{self, Debug, Display};
 
 pub struct INDT;
 pub struct DEDT;
 pub struct NWLN;
 
+/// Pretty printer with explicit indentation context.
+/// `INDT`, `DEDT` and `NWLN` markers inside `Display` impls work on the printer
+/// that is currently writing a node, so each top-level node gets its own context.
+pub struct PrettyPrinter {
+    indent: usize,
+    buf: String,
+}
+
+/// Nodes whose `Display` impl is indentation-aware
+pub trait PrettyPrint: Display {}
+
+impl PrettyPrint for Expr {}
+impl PrettyPrint for Rule {}
+impl PrettyPrint for Decl {}
+impl PrettyPrint for MExpr {}
+impl PrettyPrint for MDecl {}
+
+pub fn pretty_print<T: PrettyPrint>(node: &T) -> String {
+    let mut pp = PrettyPrinter::new();
+    pp.write_node(node);
+    pp.finish()
+}
+
+/// print a program in the IR interchange format, read back by `backend::parse_ir`
+pub fn print_ir(expr: &MExpr) -> String {
+    use crate::backend::anf_parse::{IR_HEADER, IR_VERSION};
+    format!("{IR_HEADER} {IR_VERSION}\n{}\n", pretty_print(expr))
+}
+
+impl PrettyPrinter {
+    pub fn new() -> PrettyPrinter {
+        PrettyPrinter {
+            indent: 0,
+            buf: String::new(),
+        }
+    }
+
+    pub fn indent(&mut self) {
+        self.indent += 1;
+    }
+
+    pub fn dedent(&mut self) {
+        assert!(self.indent > 0, "dedent below indentation level 0");
+        self.indent -= 1;
+    }
+
+    /// start a new line with current indentation, returns what was written
+    pub fn newline(&mut self) -> &str {
+        let start = self.buf.len();
+        self.buf.push('\n');
+        self.buf.push_str(&" ".repeat(self.indent * 2));
+        &self.buf[start..]
+    }
+
+    pub fn write_node<T: Display + ?Sized>(&mut self, node: &T) {
+        use std::fmt::Write;
+        let guard = PrinterGuard::enter(self.indent);
+        write!(self.buf, "{node}").unwrap();
+        self.indent = guard.indent();
+    }
+
+    pub fn finish(self) -> String {
+        self.buf
+    }
+}
+
+impl Default for PrettyPrinter {
+    fn default() -> Self {
+        PrettyPrinter::new()
+    }
+}
+
 thread_local! {
-    static INDT_LEVEL: Cell<usize> = Cell::new(0);
+    // indentation level of the printer writing on this thread, if there is one
+    static ACTIVE_INDENT: Cell<Option<usize>> = const { Cell::new(None) };
+    // print lambdas back as placeholders, see `set_sugar`
+    static SUGAR: Cell<bool> = const { Cell::new(false) };
+    // user-defined operators from the fixity declarations printed so far
+    static FIXITIES: RefCell<HashMap<InternStr, Fixity>> = RefCell::new(HashMap::new());
+}
+
+/// Print lambdas that the placeholder sugar can express, like `fun(x) => x + 1`,
+/// back as placeholders like `_ + 1`. It is set for the current thread.
+pub fn set_sugar(flag: bool) {
+    SUGAR.with(|c| c.set(flag));
+}
+
+/// Marks a `PrettyPrinter` as active on this thread until dropped
+struct PrinterGuard;
+
+impl PrinterGuard {
+    fn enter(indent: usize) -> PrinterGuard {
+        ACTIVE_INDENT.with(|c| {
+            if c.get().is_some() {
+                panic!(
+                    "re-entrant pretty printing: a node is already being printed on this thread, \
+                    write nested nodes with `Display` instead of starting a new printer"
+                );
+            }
+            c.set(Some(indent));
+        });
+        PrinterGuard
+    }
+
+    fn indent(&self) -> usize {
+        ACTIVE_INDENT.with(|c| c.get().unwrap())
+    }
+}
+
+impl Drop for PrinterGuard {
+    fn drop(&mut self) {
+        ACTIVE_INDENT.with(|c| c.set(None));
+    }
+}
+
+/// run `func` with an active printer, starting a fresh one unless `self` is
+/// nested inside a node that is already being printed
+fn scoped<F>(f: &mut fmt::Formatter, func: F) -> fmt::Result
+where
+    F: FnOnce(&mut fmt::Formatter) -> fmt::Result,
+{
+    if ACTIVE_INDENT.with(|c| c.get().is_some()) {
+        func(f)
+    } else {
+        let _guard = PrinterGuard::enter(0);
+        func(f)
+    }
+}
+
+fn update_indent(func: impl FnOnce(usize) -> usize) -> usize {
+    ACTIVE_INDENT.with(|c| {
+        let indent = c
+            .get()
+            .expect("indentation markers can only be used by an active `PrettyPrinter`");
+        let indent = func(indent);
+        c.set(Some(indent));
+        indent
+    })
 }
 
 impl Display for INDT {
     fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
-        INDT_LEVEL.with(|c| {
-            let x = c.get();
-            c.set(x + 1);
-        });
+        update_indent(|x| x + 1);
         Ok(())
     }
 }
 
 impl Display for DEDT {
     fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
-        INDT_LEVEL.with(|c| {
-            let x = c.get();
-            c.set(x - 1);
-        });
+        update_indent(|x| x - 1);
         Ok(())
     }
 }
 
 impl Display for NWLN {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        INDT_LEVEL.with(|c| write!(f, "\n{:width$}", "", width = c.get() * 2))
+        let indent = update_indent(|x| x);
+        write!(f, "\n{:width$}", "", width = indent * 2)
     }
 }
 
@@ -60,9 +195,11 @@ impl Display for LitVal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             LitVal::Int(x) => write!(f, "{x}"),
-            LitVal::Real(x) => write!(f, "{x}"),
+            // `Debug` keeps the fraction part, so `1.0` is not printed as `1`
+            LitVal::Real(x) => write!(f, "{x:?}"),
             LitVal::Bool(x) => write!(f, "{x}"),
             LitVal::Char(x) => write!(f, "{x}"),
+            LitVal::Str(x) => write!(f, "{:?}", x.as_str()),
             LitVal::Unit => write!(f, "()"),
         }
     }
@@ -75,6 +212,7 @@ impl Display for LitType {
             LitType::Real => write!(f, "Real"),
             LitType::Bool => write!(f, "Bool"),
             LitType::Char => write!(f, "Char"),
+            LitType::Str => write!(f, "Str"),
             LitType::Unit => write!(f, "()"),
         }
     }
@@ -89,72 +227,464 @@ impl Display for Builtin {
             Builtin::IDiv => write!(f, "idiv"),
             Builtin::IRem => write!(f, "irem"),
             Builtin::INeg => write!(f, "ineg"),
+            Builtin::Trunc32 => write!(f, "trunc32"),
+            Builtin::SignExt32 => write!(f, "sext32"),
+            Builtin::ZeroExt32 => write!(f, "zext32"),
+            Builtin::ICmpEq => write!(f, "icmpeq"),
+            Builtin::ICmpNe => write!(f, "icmpne"),
+            Builtin::ICmpLt => write!(f, "icmplt"),
+            Builtin::ICmpLe => write!(f, "icmple"),
+            Builtin::ICmpGt => write!(f, "icmpgt"),
+            Builtin::ICmpGe => write!(f, "icmpge"),
             Builtin::RAdd => write!(f, "radd"),
             Builtin::RSub => write!(f, "rsub"),
             Builtin::RMul => write!(f, "rmul"),
             Builtin::RDiv => write!(f, "rdiv"),
+            Builtin::Sinh => write!(f, "sinh"),
+            Builtin::Cosh => write!(f, "cosh"),
+            Builtin::Tanh => write!(f, "tanh"),
+            Builtin::Erf => write!(f, "erf"),
+            Builtin::Erfc => write!(f, "erfc"),
             Builtin::BAnd => write!(f, "band"),
             Builtin::BOr => write!(f, "bor"),
             Builtin::BNot => write!(f, "bnot"),
+            Builtin::Prefetch => write!(f, "prefetch"),
+            Builtin::Expect => write!(f, "expect"),
+            Builtin::SimdAdd => write!(f, "simdadd"),
+            Builtin::SimdMul => write!(f, "simdmul"),
+            Builtin::SimdLoad => write!(f, "simdload"),
+            Builtin::SimdStore => write!(f, "simdstore"),
+            Builtin::CastOpaquePtr => write!(f, "castptr"),
+            Builtin::AllocAligned => write!(f, "allocaligned"),
+            Builtin::Yield => write!(f, "yield"),
+            Builtin::Spawn => write!(f, "spawn"),
+            Builtin::Join => write!(f, "join"),
+            Builtin::SpawnWithStack => write!(f, "spawnwithstack"),
+            Builtin::ChanNew => write!(f, "channew"),
+            Builtin::ChanSend => write!(f, "chansend"),
+            Builtin::ChanRecv => write!(f, "chanrecv"),
+            Builtin::ChanClose => write!(f, "chanclose"),
+            Builtin::MutexNew => write!(f, "mutexnew"),
+            Builtin::MutexLock => write!(f, "mutexlock"),
+            Builtin::MutexUnlock => write!(f, "mutexunlock"),
+            Builtin::WithMutex => write!(f, "withmutex"),
+            Builtin::SemNew => write!(f, "semnew"),
+            Builtin::SemPost => write!(f, "sempost"),
+            Builtin::SemWait => write!(f, "semwait"),
+            Builtin::SemTryWait => write!(f, "semtrywait"),
+            Builtin::ReadFile => write!(f, "readfile"),
+            Builtin::WriteFile => write!(f, "writefile"),
+            Builtin::ListDir => write!(f, "listdir"),
+            Builtin::PathJoin => write!(f, "pathjoin"),
+            Builtin::Format => write!(f, "format"),
+            Builtin::ListMin => write!(f, "listmin"),
+            Builtin::ListMax => write!(f, "listmax"),
+            Builtin::PathBase => write!(f, "pathbase"),
+            Builtin::PathDir => write!(f, "pathdir"),
+            Builtin::Bitcast(from, to) => write!(f, "bitcast[{from}, {to}]"),
         }
     }
 }
 
-impl Display for Expr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Expr::Lit { lit, .. } => {
-                write!(f, "{lit}")
-            }
+// precedence of printed expressions, binary operators are between them,
+// shifted by one from their declared precedences
+const PREC_OPEN: u8 = 0;
+const PREC_PREFIX: u8 = MAX_PREC + 2;
+const PREC_ATOM: u8 = MAX_PREC + 3;
+
+/// the symbol and fixity of an expression printed as a binary operator,
+/// user-defined operators are printed so if their fixity is known
+fn infix_form(expr: &Expr) -> Option<(&str, Fixity)> {
+    match expr {
+        Expr::Prim { prim, args, .. } if args.len() == 2 => {
+            let (op, prec) = prim.as_infix()?;
+            let assoc = Assoc::Left;
+            Some((op, Fixity { prec, assoc }))
+        }
+        Expr::App { func, args, .. } if args.len() == 2 => match &**func {
             Expr::Var { var, .. } => {
-                write!(f, "{var}")
-            }
-            Expr::Prim { prim, args, .. } => {
-                let args = args.iter().format(&", ");
-                write!(f, "@{prim}({args})")
+                let fixity = FIXITIES.with(|fix| fix.borrow().get(&var.name).copied())?;
+                Some((var.name.as_ref(), fixity))
             }
-            Expr::Fun { pars, body, .. } => {
-                let pars = pars.iter().format(&", ");
-                write!(f, "fn ({pars}) {{{INDT}{NWLN}{body}{DEDT}{NWLN}}}")
-            }
-            Expr::App { func, args, .. } => {
-                let args = args.iter().format(&", ");
-                write!(f, "{func}({args})")
-            }
-            Expr::ExtCall { func, args, .. } => {
-                let args = args.iter().format(&", ");
-                write!(f, "#{func}({args})")
-            }
-            Expr::Cons { cons, args, .. } => {
-                let args = args.iter().format(&", ");
-                write!(f, "{cons}({args})")
-            }
-            Expr::Let {
-                bind, expr, cont, ..
-            } => {
-                write!(f, "let {bind} = {expr};{NWLN}{cont}")
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// the operand, the function and the other arguments of an application written as a
+/// pipeline `x |> f(y)`, which is the only way its last argument comes before the function
+fn pipe_form(expr: &Expr) -> Option<(&Expr, &Expr, &[Expr])> {
+    match expr {
+        Expr::App { func, args, .. } => {
+            let (last, rest) = args.split_last()?;
+            (last.span().start.abs < func.span().start.abs).then_some((last, &**func, rest))
+        }
+        _ => None,
+    }
+}
+
+fn expr_prec(expr: &Expr) -> u8 {
+    if pipe_form(expr).is_some() {
+        return PIPE_FIXITY.prec + 1;
+    }
+    if let Some((_, fixity)) = infix_form(expr) {
+        return fixity.prec + 1;
+    }
+    match expr {
+        Expr::Prim { prim, args, .. } if prim.as_prefix().is_some() && args.len() == 1 => {
+            PREC_PREFIX
+        }
+        // these extend as far to the right as possible
+        Expr::Fun { .. }
+        | Expr::Let { .. }
+        | Expr::Case { is_let: true, .. }
+        | Expr::LetRec { .. }
+        | Expr::Ifte { .. } => PREC_OPEN,
+        _ => PREC_ATOM,
+    }
+}
+
+/// whether the operands of a binary operator need parentheses
+fn infix_parens(args: &[Expr], fixity: Fixity) -> [bool; 2] {
+    let prec = fixity.prec + 1;
+    let (lhs, rhs) = (expr_prec(&args[0]), expr_prec(&args[1]));
+    match fixity.assoc {
+        Assoc::Left => [lhs < prec, rhs <= prec],
+        Assoc::Right => [lhs <= prec, rhs < prec],
+    }
+}
+
+/// an operand of some operator, wrapped in parentheses if needed
+struct Operand<'a>(&'a Expr, bool);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Operand(expr, paren) = self;
+        if *paren {
+            write!(f, "({expr})")
+        } else {
+            write!(f, "{expr}")
+        }
+    }
+}
+
+/// the positions where a placeholder would belong to a lambda around `expr`,
+/// see `parser::parse_expr` for the rules
+fn hole_positions<'a>(expr: &'a mut Expr, top: bool, out: &mut Vec<&'a mut Expr>) {
+    if matches!(expr, Expr::Var { .. }) {
+        if !top {
+            out.push(expr);
+        }
+        return;
+    }
+    let parens = match infix_form(expr) {
+        Some((_, fixity)) => Some(infix_parens(expr_args(expr), fixity)),
+        None if expr_prec(expr) == PREC_PREFIX => {
+            Some([expr_prec(&expr_args(expr)[0]) <= PREC_PREFIX, true])
+        }
+        None => None,
+    };
+    let args = match expr {
+        Expr::Prim { args, .. }
+        | Expr::App { args, .. }
+        | Expr::ExtCall { args, .. }
+        | Expr::Cons { args, .. } => args,
+        _ => return,
+    };
+    if let Some(parens) = parens {
+        // operands of an operator chain, unless they are printed in parentheses
+        for (arg, paren) in args.iter_mut().zip(parens) {
+            if !paren {
+                hole_positions(arg, false, out);
             }
-            Expr::Blk { decls, cont, .. } => {
-                if decls.is_empty() {
-                    write!(f, "begin{INDT}{NWLN}{cont}{DEDT}{NWLN}end")
-                } else {
-                    write!(f, "begin{INDT}")?;
+        }
+    } else if top {
+        // whole arguments of an application
+        let vars = args
+            .iter_mut()
+            .filter(|arg| matches!(arg, Expr::Var { .. }));
+        out.extend(vars);
+    }
+}
+
+fn expr_args(expr: &Expr) -> &[Expr] {
+    match expr {
+        Expr::Prim { args, .. }
+        | Expr::App { args, .. }
+        | Expr::ExtCall { args, .. }
+        | Expr::Cons { args, .. } => args,
+        _ => &[],
+    }
+}
+
+fn count_var(expr: &Expr, var: Ident) -> usize {
+    match expr {
+        Expr::Lit { .. } | Expr::Hole { .. } => 0,
+        Expr::Var { var: var2, .. } => (*var2 == var) as usize,
+        Expr::Prim { args, .. } | Expr::ExtCall { args, .. } | Expr::Cons { args, .. } => {
+            args.iter().map(|arg| count_var(arg, var)).sum()
+        }
+        Expr::App { func, args, .. } => {
+            count_var(func, var) + args.iter().map(|arg| count_var(arg, var)).sum::<usize>()
+        }
+        Expr::Tuple { elems, .. } => elems.iter().map(|elem| count_var(elem, var)).sum(),
+        Expr::Fun { body, .. } => count_var(body, var),
+        Expr::Proj { expr, .. } | Expr::Field { expr, .. } | Expr::Anno { expr, .. } => {
+            count_var(expr, var)
+        }
+        Expr::Record { fields, .. } => fields.iter().map(|field| count_var(&field.expr, var)).sum(),
+        Expr::Update { expr, fields, .. } => {
+            count_var(expr, var)
+                + fields
+                    .iter()
+                    .map(|field| count_var(&field.expr, var))
+                    .sum::<usize>()
+        }
+        Expr::Let { expr, cont, .. } => count_var(expr, var) + count_var(cont, var),
+        Expr::Case { expr, rules, .. }
+        | Expr::Try {
+            expr,
+            handlers: rules,
+            ..
+        } => {
+            count_var(expr, var)
+                + rules
+                    .iter()
+                    .map(|rule| {
+                        rule.guard.as_ref().map_or(0, |guard| count_var(guard, var))
+                            + count_var(&rule.body, var)
+                    })
+                    .sum::<usize>()
+        }
+        Expr::Ifte {
+            cond, trbr, flbr, ..
+        } => count_var(cond, var) + count_var(trbr, var) + count_var(flbr, var),
+        Expr::Blk { decls, cont, .. }
+        | Expr::LetRec { decls, cont, .. }
+        | Expr::Module { decls, cont, .. } => {
+            let decls: usize = decls
+                .iter()
+                .map(|decl| match decl {
+                    Decl::Func { body, .. } => count_var(body, var),
+                    _ => 0,
+                })
+                .sum();
+            decls + count_var(cont, var)
+        }
+    }
+}
+
+/// the body of a lambda with its parameters replaced by placeholders,
+/// if each parameter is used once, in order, where the placeholder would go
+fn resugar(pars: &[Ident], body: &Expr) -> Option<Expr> {
+    if pars.is_empty() || pars.iter().any(|par| count_var(body, *par) != 1) {
+        return None;
+    }
+    let mut body = body.clone();
+    let mut positions = Vec::new();
+    hole_positions(&mut body, true, &mut positions);
+    let mut holes: Vec<&mut Expr> = positions
+        .into_iter()
+        .filter(|expr| matches!(expr, Expr::Var { var, .. } if pars.contains(var)))
+        .collect();
+    let in_order = holes.len() == pars.len()
+        && holes
+            .iter()
+            .zip(pars)
+            .all(|(expr, par)| matches!(expr, Expr::Var { var, .. } if var == par));
+    if !in_order {
+        return None;
+    }
+    for hole in holes.iter_mut() {
+        if let Expr::Var { var, .. } = hole {
+            *var = Ident::from(InternStr::new("_"));
+        }
+    }
+    Some(body)
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        scoped(f, |f| {
+            match self {
+                Expr::Lit { lit, .. } => {
+                    write!(f, "{lit}")
+                }
+                Expr::Var { var, .. } => {
+                    if var.name.starts_with(is_opr_char) {
+                        write!(f, "({var})")
+                    } else {
+                        write!(f, "{var}")
+                    }
+                }
+                _ if pipe_form(self).is_some() => {
+                    let (lhs, func, args) = pipe_form(self).unwrap();
+                    let lhs = Operand(lhs, expr_prec(lhs) <= PIPE_FIXITY.prec);
+                    let func = Operand(func, expr_prec(func) < PREC_ATOM);
+                    if args.is_empty() {
+                        write!(f, "{lhs} {PIPE} {func}")
+                    } else {
+                        let args = args.iter().format(", ");
+                        write!(f, "{lhs} {PIPE} {func}({args})")
+                    }
+                }
+                _ if infix_form(self).is_some() => {
+                    let (op, fixity) = infix_form(self).unwrap();
+                    let args = expr_args(self);
+                    let [lhs, rhs] = infix_parens(args, fixity);
+                    let lhs = Operand(&args[0], lhs);
+                    let rhs = Operand(&args[1], rhs);
+                    write!(f, "{lhs} {op} {rhs}")
+                }
+                Expr::Prim { prim, args, .. } => match prim.as_prefix() {
+                    Some(op) if args.len() == 1 => {
+                        // `- -x` would be lexed as a single operator `--`
+                        let arg = Operand(&args[0], expr_prec(&args[0]) <= PREC_PREFIX);
+                        write!(f, "{op}{arg}")
+                    }
+                    _ => {
+                        let args = args.iter().format(", ");
+                        write!(f, "@{prim}({args})")
+                    }
+                },
+                Expr::Fun { pars, body, .. } => {
+                    if SUGAR.with(|c| c.get()) {
+                        if let Some(body) = resugar(pars, body) {
+                            return write!(f, "{body}");
+                        }
+                    }
+                    if body.is_simple() {
+                        let pars = pars.iter().map(|par| format!("{par} ")).join("");
+                        return write!(f, "\\{pars}-> {body}");
+                    }
+                    let pars = pars.iter().format(", ");
+                    write!(f, "fn ({pars}) {{{INDT}{NWLN}{body}{DEDT}{NWLN}}}")
+                }
+                Expr::App { func, args, .. } => {
+                    let func = Operand(func, expr_prec(func) < PREC_ATOM);
+                    let args = args.iter().format(", ");
+                    write!(f, "{func}({args})")
+                }
+                Expr::ExtCall { func, args, .. } => {
+                    let args = args.iter().format(", ");
+                    write!(f, "#{func}({args})")
+                }
+                Expr::Cons { cons, args, .. } => {
+                    let args = args.iter().format(", ");
+                    write!(f, "{cons}({args})")
+                }
+                Expr::Tuple { elems, .. } => {
+                    if elems.len() == 1 {
+                        write!(f, "({},)", elems[0])
+                    } else {
+                        let elems = elems.iter().format(", ");
+                        write!(f, "({elems})")
+                    }
+                }
+                Expr::Proj { expr, index, .. } => {
+                    let expr = Operand(expr, expr_prec(expr) < PREC_ATOM);
+                    write!(f, "{expr}.{index}")
+                }
+                Expr::Record { fields, .. } => {
+                    let fields = fields.iter().format(", ");
+                    write!(f, "{{ {fields} }}")
+                }
+                Expr::Field { expr, field, .. } => {
+                    let expr = Operand(expr, expr_prec(expr) < PREC_ATOM);
+                    write!(f, "{expr}.{field}")
+                }
+                Expr::Update { expr, fields, .. } => {
+                    let fields = fields.iter().format(", ");
+                    write!(f, "{{ {expr} with {fields} }}")
+                }
+                Expr::Anno { expr, typ, .. } => {
+                    write!(f, "({expr} : {typ})")
+                }
+                Expr::Hole { name, .. } => {
+                    write!(f, "?{name}")
+                }
+                Expr::Let {
+                    bind, expr, cont, ..
+                } => {
+                    write!(f, "let {bind} = {expr};{NWLN}{cont}")
+                }
+                Expr::Blk { decls, cont, .. } => {
+                    for decl in decls {
+                        if let Decl::Fixity { oper, fixity, .. } = decl {
+                            FIXITIES.with(|fix| fix.borrow_mut().insert(oper.name, *fixity));
+                        }
+                    }
+                    if decls.is_empty() {
+                        write!(f, "begin{INDT}{NWLN}{cont}{DEDT}{NWLN}end")
+                    } else {
+                        write!(f, "begin{INDT}")?;
+                        for decl in decls {
+                            write!(f, "{NWLN}{decl}")?;
+                        }
+                        write!(f, "{DEDT}{NWLN}in{INDT}{NWLN}{cont}{DEDT}{NWLN}end")
+                    }
+                }
+                Expr::LetRec { decls, cont, .. } => {
+                    for decl in decls {
+                        if let Decl::Fixity { oper, fixity, .. } = decl {
+                            FIXITIES.with(|fix| fix.borrow_mut().insert(oper.name, *fixity));
+                        }
+                    }
+                    write!(f, "letrec{INDT}")?;
+                    for decl in decls {
+                        write!(f, "{NWLN}{decl}")?;
+                    }
+                    write!(f, "{DEDT}{NWLN}in{NWLN}{cont}")
+                }
+                // not valid syntax, a module only comes from a file of its own
+                Expr::Module {
+                    name, decls, cont, ..
+                } => {
+                    for decl in decls {
+                        if let Decl::Fixity { oper, fixity, .. } = decl {
+                            FIXITIES.with(|fix| fix.borrow_mut().insert(oper.name, *fixity));
+                        }
+                    }
+                    write!(f, "module {name} begin{INDT}")?;
                     for decl in decls {
                         write!(f, "{NWLN}{decl}")?;
                     }
                     write!(f, "{DEDT}{NWLN}in{INDT}{NWLN}{cont}{DEDT}{NWLN}end")
                 }
-            }
-            Expr::Case { expr, rules, .. } => {
-                // Void can't be defined by user
-                assert!(!rules.is_empty());
-                write!(f, "case {expr} of")?;
-                for rule in rules {
-                    write!(f, "{NWLN}| {rule}")?;
+                Expr::Case {
+                    expr,
+                    rules,
+                    is_let: true,
+                    ..
+                } => {
+                    let Rule { patn, body, .. } = &rules[0];
+                    write!(f, "let {patn} = {expr};{NWLN}{body}")
+                }
+                Expr::Case { expr, rules, .. } => {
+                    // Void can't be defined by user
+                    assert!(!rules.is_empty());
+                    write!(f, "case {expr} of")?;
+                    for rule in rules {
+                        write!(f, "{NWLN}| {rule}")?;
+                    }
+                    write!(f, "{NWLN}end")
+                }
+                Expr::Try { expr, handlers, .. } => {
+                    write!(f, "try {{ {expr} }} catch")?;
+                    for rule in handlers {
+                        write!(f, "{NWLN}| {rule}")?;
+                    }
+                    write!(f, "{NWLN}end")
+                }
+                Expr::Ifte {
+                    cond, trbr, flbr, ..
+                } => {
+                    write!(f, "if {cond} then{INDT}{NWLN}{trbr}{DEDT}{NWLN}")?;
+                    write!(f, "else{INDT}{NWLN}{flbr}{DEDT}")
                 }
-                write!(f, "{NWLN}end")
             }
-        }
+        })
     }
 }
 
@@ -171,24 +701,83 @@ impl Display for Pattern {
                 if pars.is_empty() {
                     write!(f, "{cons}")
                 } else {
-                    let pars = pars.iter().format(&", ");
+                    let pars = pars.iter().format(", ");
                     write!(f, "{cons}({pars})")
                 }
             }
+            Pattern::Tuple { pats, .. } => {
+                if pats.len() == 1 {
+                    write!(f, "({},)", pats[0])
+                } else {
+                    let pats = pats.iter().format(", ");
+                    write!(f, "({pats})")
+                }
+            }
             Pattern::Wild { .. } => {
                 write!(f, "_")
             }
+            Pattern::Or { alts, .. } => {
+                write!(f, "{}", alts.iter().format(" | "))
+            }
+            Pattern::As { bind, patn, .. } => {
+                write!(f, "{bind} @ {patn}")
+            }
         }
     }
 }
 
 impl Display for Rule {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let Rule { patn, body, .. } = self;
-        if body.is_simple() {
-            write!(f, "{patn} => {body}")
-        } else {
-            write!(f, "{patn} => {INDT}{NWLN}{body}{DEDT}{NWLN}")
+        scoped(f, |f| {
+            let Rule {
+                patn, guard, body, ..
+            } = self;
+            write!(f, "{patn}")?;
+            if let Some(guard) = guard {
+                write!(f, " if {guard}")?;
+            }
+            if body.is_simple() {
+                write!(f, " => {body}")
+            } else {
+                write!(f, " => {INDT}{NWLN}{body}{DEDT}{NWLN}")
+            }
+        })
+    }
+}
+
+impl Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptLevel::Never => write!(f, "never"),
+            OptLevel::Size => write!(f, "size"),
+        }
+    }
+}
+
+impl Display for CallConv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallConv::C => write!(f, "c"),
+            CallConv::Stdcall => write!(f, "stdcall"),
+            CallConv::Fastcall => write!(f, "fastcall"),
+            CallConv::Vectorcall => write!(f, "vectorcall"),
+            CallConv::Aapcs => write!(f, "aapcs"),
+        }
+    }
+}
+
+impl Display for Attr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Attr::Test { .. } => write!(f, "@test"),
+            Attr::Bench { .. } => write!(f, "@bench"),
+            Attr::NoUnroll { .. } => write!(f, "@nounroll"),
+            Attr::Cold { .. } => write!(f, "@cold"),
+            Attr::CallConv { conv, .. } => write!(f, "@callconv({conv})"),
+            Attr::Optimize { level, .. } => write!(f, "@optimize({level})"),
+            Attr::Deprecated {
+                since, replacement, ..
+            } => write!(f, "@deprecated_since({since:?}, {replacement:?})"),
         }
     }
 }
@@ -199,58 +788,136 @@ impl Display for Varient {
         if pars.is_empty() {
             write!(f, "{cons}")
         } else {
-            let pars = pars.iter().format(&", ");
+            let pars = pars.iter().format(", ");
             write!(f, "{cons}[{pars}]")
         }
     }
 }
 
+impl Display for FieldDecl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let FieldDecl { field, typ, .. } = self;
+        write!(f, "{field}: {typ}")
+    }
+}
+
+impl Display for FieldInit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let FieldInit { field, expr, .. } = self;
+        write!(f, "{field} = {expr}")
+    }
+}
+
 impl Display for Decl {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Decl::Func {
-                name, pars, body, ..
-            } => {
-                let pars = pars.iter().format(&", ");
-                if body.is_simple() {
-                    write!(f, "fun {name}({pars}) = {body};")
-                } else {
-                    write!(f, "fun {name}({pars}) ={INDT}{NWLN}{body}{DEDT}")
+        scoped(f, |f| {
+            match self {
+                Decl::Func {
+                    name,
+                    attrs,
+                    pars,
+                    ret,
+                    body,
+                    ..
+                } => {
+                    for attr in attrs {
+                        write!(f, "{attr} ")?;
+                    }
+                    let pars = pars
+                        .iter()
+                        .map(|(par, typ)| match typ {
+                            Some(typ) => format!("{par}: {typ}"),
+                            None => format!("{par}"),
+                        })
+                        .format(", ");
+                    let name = if name.name.starts_with(is_opr_char) {
+                        format!("({name})")
+                    } else {
+                        format!("{name}")
+                    };
+                    let ret = match ret {
+                        Some(ret) => format!(": {ret}"),
+                        None => String::new(),
+                    };
+                    if body.is_simple() {
+                        write!(f, "fun {name}({pars}){ret} = {body};")
+                    } else {
+                        write!(f, "fun {name}({pars}){ret} ={INDT}{NWLN}{body}{DEDT}")
+                    }
                 }
-            }
-            Decl::Data {
-                name, pars, vars, ..
-            } => {
-                if pars.is_empty() {
-                    write!(f, "data {name} =")?;
-                } else {
-                    let pars = pars.iter().format(&", ");
-                    write!(f, "data {name}[{pars}] =")?;
+                Decl::Val {
+                    name, typ, body, ..
+                } => {
+                    let typ = match typ {
+                        Some(typ) => format!(": {typ}"),
+                        None => String::new(),
+                    };
+                    if body.is_simple() {
+                        write!(f, "let {name}{typ} = {body};")
+                    } else {
+                        write!(f, "let {name}{typ} ={INDT}{NWLN}{body};{DEDT}")
+                    }
                 }
-                // Void can't be defined by user
-                assert!(!vars.is_empty());
-                for var in vars {
-                    write!(f, "{NWLN}| {var}")?;
+                Decl::Data {
+                    name, pars, vars, ..
+                } => {
+                    if pars.is_empty() {
+                        write!(f, "data {name} =")?;
+                    } else {
+                        let pars = pars.iter().format(", ");
+                        write!(f, "data {name}[{pars}] =")?;
+                    }
+                    // Void can't be defined by user
+                    assert!(!vars.is_empty());
+                    for var in vars {
+                        write!(f, "{NWLN}| {var}")?;
+                    }
+                    write!(f, "{NWLN}end")
                 }
-                write!(f, "{NWLN}end")
-            }
-            Decl::Type {
-                name, pars, typ, ..
-            } => {
-                if pars.is_empty() {
-                    write!(f, "type {name} = {typ};")
-                } else {
-                    let pars = pars.iter().format(&", ");
-                    write!(f, "type {name}[{pars}] = {typ};")
+                Decl::Record { name, fields, .. } => {
+                    let fields = fields.iter().format(", ");
+                    write!(f, "data {name} = {{ {fields} }}")
+                }
+                Decl::Type {
+                    name, pars, typ, ..
+                } => {
+                    if pars.is_empty() {
+                        write!(f, "type {name} = {typ};")
+                    } else {
+                        let pars = pars.iter().format(", ");
+                        write!(f, "type {name}[{pars}] = {typ};")
+                    }
+                }
+                Decl::Abstract { name, pars, .. } => {
+                    if pars.is_empty() {
+                        write!(f, "type {name};")
+                    } else {
+                        let pars = pars.iter().format(", ");
+                        write!(f, "type {name}[{pars}];")
+                    }
+                }
+                Decl::Extern {
+                    name,
+                    attrs,
+                    pars,
+                    typ,
+                    ..
+                } => {
+                    for attr in attrs {
+                        write!(f, "{attr} ")?;
+                    }
+                    let pars = pars.iter().format(", ");
+                    write!(f, "extern {name}({pars}) : {typ};")
+                }
+                Decl::Fixity { oper, fixity, .. } => {
+                    let kwd = match fixity.assoc {
+                        Assoc::Left => "infixl",
+                        Assoc::Right => "infixr",
+                    };
+                    write!(f, "{kwd} {} {};", fixity.prec, oper.name)
                 }
             }
-            Decl::Extern {
-                name, pars, typ, ..
-            } => {
-                let pars = pars.iter().format(&", ");
-                write!(f, "extern {name}({pars}) : {typ};")
-            }
-        }
+        })
     }
 }
 
@@ -263,15 +930,43 @@ impl Display for Type {
             Type::Var { var, .. } => {
                 write!(f, "{var}")
             }
-            Type::Fun { pars, res, .. } => {
-                let pars = pars.iter().format(&", ");
+            Type::Fun {
+                pars,
+                res,
+                variadic,
+                ..
+            } => {
+                let pars = pars
+                    .iter()
+                    .map(|par| par.to_string())
+                    .chain(variadic.then(|| "...".to_string()))
+                    .format(", ");
                 write!(f, "fn ({pars}) -> {res}")
             }
             Type::App { cons, args, .. } => {
                 assert!(!args.is_empty());
-                let args = args.iter().format(&", ");
+                let args = args.iter().format(", ");
                 write!(f, "{cons}[{args}]")
             }
+            Type::Tuple { elems, .. } => {
+                if elems.len() == 1 {
+                    write!(f, "({},)", elems[0])
+                } else {
+                    let elems = elems.iter().format(", ");
+                    write!(f, "({elems})")
+                }
+            }
+            Type::SimdVec { lanes, elem, .. } => write!(f, "Simd[{elem}, {lanes}]"),
+            Type::OpaquePtr { .. } => write!(f, "Ptr"),
+            Type::Coroutine { arg, res, .. } => write!(f, "Coroutine[{arg}, {res}]"),
+            Type::Thread { res, .. } => write!(f, "Thread[{res}]"),
+            Type::Chan { elem, .. } => write!(f, "Chan[{elem}]"),
+            Type::Mutex { .. } => write!(f, "Mutex"),
+            Type::Sem { .. } => write!(f, "Sem"),
+            Type::Scheme { vars, body, .. } => {
+                let vars = vars.iter().format(" ");
+                write!(f, "forall {vars}. {body}")
+            }
         }
     }
 }
@@ -281,10 +976,26 @@ impl Display for Atom {
         match self {
             Atom::Var(x) => write!(f, "{x}"),
             Atom::Int(x) => write!(f, "{x}"),
-            Atom::Real(x) => write!(f, "{x}"),
+            // always with a fraction or an exponent, so it reads back as a real
+            Atom::Real(x) if x.is_nan() && x.is_sign_negative() => write!(f, "-NaN"),
+            Atom::Real(x) => write!(f, "{x:?}"),
             Atom::Bool(x) => write!(f, "{x}"),
-            Atom::Char(x) => write!(f, "{x}"),
+            Atom::Char(x) => write!(f, "{x:?}"),
+            Atom::Str(x) => write!(f, "{:?}", x.as_str()),
             Atom::Unit => write!(f, "()"),
+            Atom::Const(x) => write!(f, "{{{}}}", x.fields().iter().format(", ")),
+        }
+    }
+}
+
+impl Display for FfiType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FfiType::Int => write!(f, "Int"),
+            FfiType::Real => write!(f, "Real"),
+            FfiType::Bool => write!(f, "Bool"),
+            FfiType::Char => write!(f, "Char"),
+            FfiType::Ptr => write!(f, "Ptr"),
         }
     }
 }
@@ -294,6 +1005,15 @@ impl Display for UnOpPrim {
         match self {
             UnOpPrim::Move => write!(f, "move"),
             UnOpPrim::INeg => write!(f, "ineg"),
+            UnOpPrim::Trunc32 => write!(f, "trunc32"),
+            UnOpPrim::SignExt32 => write!(f, "sext32"),
+            UnOpPrim::ZeroExt32 => write!(f, "zext32"),
+            UnOpPrim::BNot => write!(f, "bnot"),
+            UnOpPrim::Sinh => write!(f, "sinh"),
+            UnOpPrim::Cosh => write!(f, "cosh"),
+            UnOpPrim::Tanh => write!(f, "tanh"),
+            UnOpPrim::Erf => write!(f, "erf"),
+            UnOpPrim::Erfc => write!(f, "erfc"),
         }
     }
 }
@@ -304,13 +1024,30 @@ impl Display for BinOpPrim {
             BinOpPrim::IAdd => write!(f, "iadd"),
             BinOpPrim::ISub => write!(f, "isub"),
             BinOpPrim::IMul => write!(f, "imul"),
+            BinOpPrim::IDiv => write!(f, "idiv"),
+            BinOpPrim::IRem => write!(f, "irem"),
+            BinOpPrim::ICmpEq => write!(f, "icmpeq"),
+            BinOpPrim::ICmpNe => write!(f, "icmpne"),
+            BinOpPrim::ICmpLt => write!(f, "icmplt"),
+            BinOpPrim::ICmpLe => write!(f, "icmple"),
+            BinOpPrim::ICmpGt => write!(f, "icmpgt"),
+            BinOpPrim::ICmpGe => write!(f, "icmpge"),
+            BinOpPrim::RAdd => write!(f, "radd"),
+            BinOpPrim::RSub => write!(f, "rsub"),
+            BinOpPrim::RMul => write!(f, "rmul"),
+            BinOpPrim::RDiv => write!(f, "rdiv"),
+            BinOpPrim::Prefetch => write!(f, "prefetch"),
+            BinOpPrim::AllocAligned => write!(f, "allocaligned"),
+            BinOpPrim::Expect => write!(f, "expect"),
+            BinOpPrim::SimdAdd => write!(f, "simdadd"),
+            BinOpPrim::SimdMul => write!(f, "simdmul"),
         }
     }
 }
 
 impl Display for MExpr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
+        scoped(f, |f| match self {
             MExpr::LetIn { decls, cont } => {
                 write!(f, "letrec{INDT}")?;
                 for decl in decls {
@@ -341,21 +1078,41 @@ impl Display for MExpr {
                 args,
                 cont,
             } => {
-                let args = args.iter().format(&", ");
+                let args = args.iter().format(", ");
                 write!(f, "let {bind} = {func}({args});{NWLN}{cont}")
             }
             MExpr::ExtCall {
                 bind,
                 func,
+                call_conv,
+                abi,
                 args,
                 cont,
             } => {
-                let args = args.iter().format(&", ");
-                write!(f, "let {bind} = {func}({args});{NWLN}{cont}")
+                let args = args.iter().format(", ");
+                write!(f, "let {bind} = ")?;
+                if *call_conv != CallConv::C {
+                    write!(f, "{call_conv} ")?;
+                }
+                write!(f, "#{func}({args})")?;
+                if let Some(varargs) = &abi.varargs {
+                    write!(f, " varargs({})", varargs.iter().format(", "))?;
+                }
+                if abi.unit_ret {
+                    write!(f, " void")?;
+                }
+                write!(f, ";{NWLN}{cont}")
             }
             MExpr::Retn { arg1 } => {
                 write!(f, "return {arg1}")
             }
+            MExpr::TailCall { func, args } => {
+                let args = args.iter().format(", ");
+                write!(f, "return {func}({args})")
+            }
+            MExpr::Panic { msg } => {
+                write!(f, "panic {:?}", msg.as_str())
+            }
             MExpr::Alloc { bind, size, cont } => {
                 write!(f, "let {bind} = alloc[{size}];{NWLN}{cont}")
             }
@@ -388,10 +1145,12 @@ impl Display for MExpr {
                 arg1,
                 brch1,
                 brch2,
+                cold,
                 cont,
             } => {
-                write!(f, "let {bind} = if({arg1}) then")?;
-                write!(f, "{INDT}{NWLN}{brch1}{DEDT}{NWLN}else")?;
+                let (cold1, cold2) = (cold_mark(cold, 0), cold_mark(cold, 1));
+                write!(f, "let {bind} = if({arg1}) then{cold1}")?;
+                write!(f, "{INDT}{NWLN}{brch1}{DEDT}{NWLN}else{cold2}")?;
                 write!(f, "{INDT}{NWLN}{brch2}{DEDT}{NWLN};{NWLN}{cont}")
             }
             MExpr::Switch {
@@ -399,32 +1158,47 @@ impl Display for MExpr {
                 arg1,
                 brchs,
                 dflt,
+                cold,
                 cont,
             } => {
                 write!(f, "let {bind} = switch({arg1}) {{{INDT}")?;
-                for (i, brch) in brchs.iter() {
-                    write!(f, "{NWLN}case {i}:{INDT}{NWLN}{brch}{DEDT}")?;
+                for (k, (i, brch)) in brchs.iter().enumerate() {
+                    let mark = cold_mark(cold, k);
+                    write!(f, "{NWLN}case {i}{mark}:{INDT}{NWLN}{brch}{DEDT}")?;
                 }
                 if let Some(dflt) = dflt {
-                    write!(f, "{NWLN}default:{INDT}{NWLN}{dflt}{DEDT}")?;
+                    let mark = cold_mark(cold, brchs.len());
+                    write!(f, "{NWLN}default{mark}:{INDT}{NWLN}{dflt}{DEDT}")?;
                 }
                 write!(f, "{DEDT}{NWLN}}}{NWLN}{cont}")
             }
-        }
+        })
+    }
+}
+
+/// cold branches are marked in dumps
+fn cold_mark(cold: &Cold, index: usize) -> &'static str {
+    if cold.is_cold(index) {
+        " cold"
+    } else {
+        ""
     }
 }
 
 impl Display for MDecl {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let MDecl { func, pars, body } = self;
-        let pars = pars.iter().format(&", ");
-        write!(f, "fun {func}({pars}) = {INDT}{NWLN}{body}{DEDT}")
+        scoped(f, |f| {
+            let MDecl { func, pars, body } = self;
+            let pars = pars.iter().format(", ");
+            write!(f, "fun {func}({pars}) = {INDT}{NWLN}{body}{DEDT}")
+        })
     }
 }
 
 #[test]
 pub fn printer_ident_test() {
-    let string1 = format!(
+    let mut pp = PrettyPrinter::new();
+    pp.write_node(&format_args!(
         "\n\
         hello{INDT}{NWLN}\
         world{INDT}{NWLN}\
@@ -434,7 +1208,8 @@ pub fn printer_ident_test() {
         world{DEDT}{NWLN}\
         hello world!\n\
     "
-    );
+    ));
+    let string1 = pp.finish();
 
     let string2 = r#"
 hello
@@ -448,3 +1223,38 @@ hello world!
 
     assert_eq!(string1, string2)
 }
+
+#[test]
+pub fn pretty_printer_test() {
+    use crate::backend::anf_build::*;
+    let decl = fun(
+        "f",
+        vec!["x"],
+        chain(vec![iadd("y", v("x"), i(1)), retn(v("y"))]),
+    );
+    let text = pretty_print(&decl);
+    assert_eq!(text, format!("{decl}"));
+
+    // printers keep their own indentation
+    let mut pp = PrettyPrinter::new();
+    pp.indent();
+    assert_eq!(pp.newline(), "\n  ");
+    pp.write_node(&decl);
+    pp.dedent();
+    pp.newline();
+    assert_eq!(
+        pp.finish(),
+        "\n  fun f(x) = \n    let y = iadd(x,1);\n    return y\n"
+    );
+
+    // starting a printer while printing a node is an error
+    struct Reentrant;
+    impl Display for Reentrant {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let decl = fun("g", vec![], retn(i(0)));
+            write!(f, "{}", pretty_print(&decl))
+        }
+    }
+    let res = std::panic::catch_unwind(|| PrettyPrinter::new().write_node(&Reentrant));
+    assert!(res.is_err());
+}
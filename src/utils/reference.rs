@@ -0,0 +1,189 @@
+use crate::frontend::ast::{
+    Attr, Builtin, CallConv, OptLevel, CHAN, MAX_PREC, OPTION, PIPE, PIPE_FIXITY, RESULT,
+};
+use crate::frontend::lexer::KEYWORDS;
+use crate::utils::driver::compile_partial;
+use itertools::Itertools;
+use std::fmt::Write;
+
+/// Generate the language reference in Markdown.
+/// Every table is built from the registries used by the compiler itself,
+/// so the reference can't drift away from the implementation.
+pub fn language_reference() -> String {
+    let mut text = String::new();
+    writeln!(text, "# Norem Language Reference").unwrap();
+    writeln!(text).unwrap();
+    writeln!(
+        text,
+        "This document is generated by `norem explain --reference`."
+    )
+    .unwrap();
+    operators(&mut text);
+    builtins(&mut text);
+    attributes(&mut text);
+    keywords(&mut text);
+    text
+}
+
+fn operators(text: &mut String) {
+    writeln!(text).unwrap();
+    writeln!(text, "## Operators").unwrap();
+    writeln!(text).unwrap();
+    writeln!(
+        text,
+        "Higher precedence binds tighter. Prefix operators bind tighter than any binary operator."
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "User-defined operators take a precedence from 0 to {MAX_PREC}, \
+        declared with `infixl` or `infixr`."
+    )
+    .unwrap();
+    writeln!(text).unwrap();
+    writeln!(text, "| Operator | Precedence | Associativity | Builtin |").unwrap();
+    writeln!(text, "|---|---|---|---|").unwrap();
+    let infixes = Builtin::ALL
+        .iter()
+        .filter_map(|prim| prim.as_infix().map(|(op, prec)| (op, prec, prim)))
+        .sorted_by_key(|(_, prec, _)| std::cmp::Reverse(*prec));
+    for (op, prec, prim) in infixes {
+        writeln!(text, "| `{op}` | {prec} | left | `@{prim}` |").unwrap();
+    }
+    // the bar is escaped in a table
+    let pipe = PIPE.replace('|', "\\|");
+    let prec = PIPE_FIXITY.prec;
+    writeln!(
+        text,
+        "| `{pipe}` | {prec} | left | `x {pipe} f(y)` is `f(y, x)` |"
+    )
+    .unwrap();
+    for prim in Builtin::ALL {
+        if let Some(op) = prim.as_prefix() {
+            writeln!(text, "| `{op}` (prefix) | - | - | `@{prim}` |").unwrap();
+        }
+    }
+}
+
+/// the type of a primitive, as inferred by the type checker
+fn builtin_type(prim: Builtin) -> String {
+    // the options and results they return are data types, which the type checker doesn't handle yet
+    match prim {
+        Builtin::ChanRecv => return format!("fun({CHAN}[a]) -> {OPTION}[a]"),
+        Builtin::ReadFile => return format!("fun(Str) -> {RESULT}[Str, Str]"),
+        Builtin::WriteFile => return format!("fun(Str, Str) -> {RESULT}[(), Str]"),
+        Builtin::ListDir => return format!("fun(Str) -> {RESULT}[List[Str], Str]"),
+        // the arguments after the format depend on its specifiers
+        Builtin::Format => return "fun(Str, ...) -> Str".to_string(),
+        // only integers are compared, there is no ordering for other types
+        Builtin::ListMin | Builtin::ListMax => return format!("fun(List[Int]) -> {OPTION}[Int]"),
+        _ => {}
+    }
+    let pars = (0..prim.get_arity()).map(|i| format!("x{i}")).join(", ");
+    let source = format!("fun({pars}) => @{prim}({pars})");
+    compile_partial(&source)
+        .ty
+        .unwrap_or_else(|| panic!("failed to infer the type of @{prim}"))
+}
+
+fn builtins(text: &mut String) {
+    writeln!(text).unwrap();
+    writeln!(text, "## Builtins").unwrap();
+    writeln!(text).unwrap();
+    writeln!(text, "| Builtin | Arity | Type | Description |").unwrap();
+    writeln!(text, "|---|---|---|---|").unwrap();
+    for prim in Builtin::ALL {
+        let arity = prim.get_arity();
+        let typ = builtin_type(*prim);
+        let doc = prim.doc();
+        writeln!(text, "| `@{prim}` | {arity} | `{typ}` | {doc} |").unwrap();
+    }
+}
+
+fn attributes(text: &mut String) {
+    writeln!(text).unwrap();
+    writeln!(text, "## Attributes").unwrap();
+    writeln!(text).unwrap();
+    writeln!(text, "| Attribute | Description |").unwrap();
+    writeln!(text, "|---|---|").unwrap();
+    for attr in Attr::all() {
+        let doc = attr.doc();
+        match attr {
+            Attr::CallConv { .. } => {
+                let convs = CallConv::ALL
+                    .iter()
+                    .map(|conv| format!("`{conv}`"))
+                    .join(", ");
+                writeln!(text, "| `@callconv(...)` | {doc}, one of {convs} |").unwrap();
+            }
+            Attr::Optimize { .. } => {
+                let levels = OptLevel::ALL
+                    .iter()
+                    .map(|level| format!("`{level}`"))
+                    .join(", ");
+                writeln!(text, "| `@optimize(...)` | {doc}, one of {levels} |").unwrap();
+            }
+            Attr::Deprecated { .. } => {
+                writeln!(
+                    text,
+                    "| `@deprecated_since(version, replacement)` | {doc} |"
+                )
+                .unwrap();
+            }
+            attr => writeln!(text, "| `{attr}` | {doc} |").unwrap(),
+        }
+    }
+}
+
+fn keywords(text: &mut String) {
+    writeln!(text).unwrap();
+    writeln!(text, "## Keywords").unwrap();
+    writeln!(text).unwrap();
+    writeln!(text, "These words can't be used as identifiers.").unwrap();
+    writeln!(text).unwrap();
+    let keywords = KEYWORDS
+        .iter()
+        .map(|(keyword, _)| format!("`{keyword}`"))
+        .join(", ");
+    writeln!(text, "{keywords}").unwrap();
+}
+
+#[test]
+fn reference_structure_test() {
+    // reorganizing the reference should be deliberate
+    let text = language_reference();
+    let headings: Vec<&str> = text.lines().filter(|line| line.starts_with('#')).collect();
+    assert_eq!(
+        headings,
+        [
+            "# Norem Language Reference",
+            "## Operators",
+            "## Builtins",
+            "## Attributes",
+            "## Keywords",
+        ]
+    );
+    assert!(text.contains("| `*` | 7 | left | `@imul` |"));
+    assert!(text.contains("| `@iadd` | 2 | `fun(Int, Int) -> Int` |"));
+    assert!(text.contains("| `@bitcast[Int, Real]` | 1 | `fun(Int) -> Real` |"));
+}
+
+#[test]
+fn reference_registry_test() {
+    use crate::frontend::lexer::as_keyword;
+    use crate::frontend::parser::{parse_expr, Parser};
+    // every registered builtin must be documented and reachable from the syntax
+    for prim in Builtin::ALL {
+        assert!(!prim.doc().is_empty(), "@{prim} is not documented");
+        let pars = (0..prim.get_arity()).map(|i| format!("x{i}")).join(", ");
+        let source = format!("@{prim}({pars})");
+        let mut par = Parser::new(&source);
+        assert!(parse_expr(&mut par).is_ok(), "@{prim} can't be parsed");
+    }
+    for attr in Attr::all() {
+        assert!(!attr.doc().is_empty(), "{attr} is not documented");
+    }
+    for (keyword, tok) in KEYWORDS {
+        assert_eq!(as_keyword(keyword), Some(*tok));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::frontend::position::Span;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// `FileId` is the index of a file in a `SourceMap`.
+/// Spans made without a `SourceMap` have the default id, which is the first file.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FileId(pub u32);
+
+struct SourceFile {
+    path: PathBuf,
+    text: String,
+}
+
+/// `SourceMap` owns the source text of every file in a compilation,
+/// so that a `Span` can be traced back to its file.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+/// A human-readable location, `line` and `col` start from 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location<'a> {
+    pub file: &'a Path,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.col)
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { files: Vec::new() }
+    }
+
+    pub fn add_file<P: Into<PathBuf>, S: Into<String>>(&mut self, path: P, text: S) -> FileId {
+        let file = FileId(self.files.len() as u32);
+        self.files.push(SourceFile {
+            path: path.into(),
+            text: text.into(),
+        });
+        file
+    }
+
+    pub fn path(&self, file: FileId) -> &Path {
+        &self.files[file.0 as usize].path
+    }
+
+    pub fn source(&self, file: FileId) -> &str {
+        &self.files[file.0 as usize].text
+    }
+
+    /// the location where `span` starts
+    pub fn lookup(&self, span: Span) -> Location<'_> {
+        Location {
+            file: self.path(span.file),
+            line: span.start.row as u32 + 1,
+            col: span.start.col as u32 + 1,
+        }
+    }
+
+    pub fn source_slice(&self, span: Span) -> &str {
+        &self.source(span.file)[span.start.abs..span.end.abs]
+    }
+}
+
+#[test]
+fn source_map_test() {
+    use crate::frontend::lexer::Lexer;
+
+    let mut map = SourceMap::new();
+    let file1 = map.add_file("main.nrm", "let x = 1;\nx");
+    let file2 = map.add_file("lib/util.nrm", "fun f(x) =>\n    @iadd(x, 1)");
+    assert_eq!((file1, file2), (FileId(0), FileId(1)));
+
+    let tokens: Vec<_> = Lexer::new(map.source(file2)).with_file(file2).collect();
+    assert!(tokens.iter().all(|tok| tok.span.file == file2));
+    let span = tokens[6].span;
+    assert_eq!(map.source_slice(span), "@iadd");
+    assert_eq!(map.lookup(span).to_string(), "lib/util.nrm:2:5");
+
+    // merged spans stay in their file
+    let span = Span::merge(tokens[6].span, tokens[11].span);
+    assert_eq!(map.source_slice(span), "@iadd(x, 1)");
+
+    let tokens: Vec<_> = Lexer::new(map.source(file1)).with_file(file1).collect();
+    let span = tokens.last().unwrap().span;
+    assert_eq!(map.lookup(span).to_string(), "main.nrm:2:1");
+}
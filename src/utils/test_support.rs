@@ -0,0 +1,84 @@
+//! Generated programs of any size, for the benchmarks of the frontend phases
+//! and the tests that check they scale.
+
+use std::fmt::Write;
+
+/// the nesting of `deep_program` at the recursion limit of the frontend, a release build
+/// overflows the 8 MiB stack of a main thread a little deeper than this
+pub const DEEP_LIMIT: usize = 900;
+
+/// the stack of the threads running the frontend on generated programs, a debug build
+/// needs several times more of it for each level of `deep_program` than a release one
+pub const DEEP_STACK: usize = 128 * 1024 * 1024;
+
+/// `n` small functions, each calling the one before it
+pub fn wide_program(n: usize) -> String {
+    let mut text = String::from("begin\n");
+    writeln!(text, "    fun f0(x) => @iadd(x, 1)").unwrap();
+    for k in 1..n.max(1) {
+        writeln!(text, "    fun f{k}(x) => @iadd(f{}(x), {k})", k - 1).unwrap();
+    }
+    writeln!(text, "in\n    f{}(0)\nend", n.max(1) - 1).unwrap();
+    text
+}
+
+/// an arithmetic expression nested `depth` deep
+pub fn deep_program(depth: usize) -> String {
+    let mut text = String::new();
+    for k in 0..depth {
+        write!(text, "@iadd({k}, ").unwrap();
+    }
+    text.push('0');
+    text.push_str(&")".repeat(depth));
+    text.push('\n');
+    text
+}
+
+/// `n` functions returning a tuple of literals of every kind
+pub fn literal_program(n: usize) -> String {
+    let mut text = String::from("begin\n");
+    for k in 0..n.max(1) {
+        writeln!(
+            text,
+            "    fun l{k}() => ({k}, {k}.5, -{k}, \"lit{k}\\n\", true, false, ())"
+        )
+        .unwrap();
+    }
+    writeln!(text, "in\n    l0()\nend").unwrap();
+    text
+}
+
+/// `n` functions, each matching a pair of integers against a few rules
+pub fn match_program(n: usize) -> String {
+    let mut text = String::from("begin\n");
+    for k in 0..n.max(1) {
+        writeln!(text, "    fun m{k}(x, y) => {{").unwrap();
+        writeln!(text, "        case (x, y) of").unwrap();
+        writeln!(text, "        | (0, _) => {{ {k} }}").unwrap();
+        writeln!(text, "        | (_, 0) => {{ @iadd(x, {k}) }}").unwrap();
+        writeln!(text, "        | (1, 1) => {{ @imul(x, {k}) }}").unwrap();
+        writeln!(
+            text,
+            "        | (a, b) if @icmplt(a, b) => {{ @isub(b, a) }}"
+        )
+        .unwrap();
+        writeln!(text, "        | (a, b) => {{ @isub(a, b) }}").unwrap();
+        writeln!(text, "        end").unwrap();
+        writeln!(text, "    }}").unwrap();
+    }
+    writeln!(text, "in\n    m0(1, 2)\nend").unwrap();
+    text
+}
+
+/// a kind of generated program, the sizes it is measured at and its generator
+pub type BenchInput = (&'static str, Vec<usize>, fn(usize) -> String);
+
+/// every kind of generated program, with the sizes they are measured at, smallest first
+pub fn bench_inputs() -> Vec<BenchInput> {
+    vec![
+        ("wide", vec![100, 1000, 10000], wide_program),
+        ("deep", vec![50, 300, DEEP_LIMIT], deep_program),
+        ("literal", vec![100, 1000, 10000], literal_program),
+        ("match", vec![10, 100, 1000], match_program),
+    ]
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver::{self, TopError};
+
+#[test]
+fn test_abstract_type() {
+    // `Counter.nrm` declares the abstract type, `main.nrm` only uses its functions
+    let input = PathBuf::from("examples/abstract_type/main.nrm");
+    let library = PathBuf::from("examples/abstract_type/lib.c");
+    let temp = PathBuf::from("target/examples/abstract_type.temp.c");
+    let output = PathBuf::from("target/examples/abstract_type.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/abstract_type.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "42\n1\n");
+}
+
+#[test]
+fn test_abstract_type_hidden() {
+    // an importer can neither construct nor match the representation
+    for (name, msg) in [
+        (
+            "construct",
+            "can't construct Box of the abstract type Counter",
+        ),
+        ("match", "can't match Box of the abstract type Counter"),
+    ] {
+        let input = PathBuf::from(format!("examples/abstract_type/{name}.nrm"));
+        let temp = PathBuf::from(format!("target/examples/abstract_type.{name}.temp.c"));
+        let Err(TopError::LowerError(diags)) = driver::run_compile(&input, &temp, false) else {
+            panic!("{name}.nrm should not compile");
+        };
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0].minimal_report(10).contains(msg));
+    }
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_aligned_alloc() {
+    let input = PathBuf::from("examples/aligned_alloc.nrm");
+    let library = PathBuf::from("examples/aligned_alloc.c");
+    let temp = PathBuf::from("target/examples/aligned_alloc.temp.c");
+    let output = PathBuf::from("target/examples/aligned_alloc.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/aligned_alloc.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "0\n0\n0\n");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_as_pattern() {
+    let input = PathBuf::from("examples/as_pattern.nrm");
+    let library = PathBuf::from("examples/as_pattern.c");
+    let temp = PathBuf::from("target/examples/as_pattern.temp.c");
+    let output = PathBuf::from("target/examples/as_pattern.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/as_pattern.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "3\n12\n1\n30\n40\n3\n");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_channel() {
+    let input = PathBuf::from("examples/channel.nrm");
+    let library = PathBuf::from("examples/channel.c");
+    let temp = PathBuf::from("target/examples/channel.temp.c");
+    let output = PathBuf::from("target/examples/channel.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/channel.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "500500\n7\n");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_clauses() {
+    let input = PathBuf::from("examples/clauses.nrm");
+    let library = PathBuf::from("examples/clauses.c");
+    let temp = PathBuf::from("target/examples/clauses.temp.c");
+    let output = PathBuf::from("target/examples/clauses.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/clauses.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "5\n6\n");
+}
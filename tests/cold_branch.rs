@@ -0,0 +1,25 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_cold_branch() {
+    let input = PathBuf::from("examples/cold_branch.nrm");
+    let library = PathBuf::from("examples/cold_branch.c");
+    let temp = PathBuf::from("target/examples/cold_branch.temp.c");
+    let output = PathBuf::from("target/examples/cold_branch.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    // the `@cold` call and the match failure are both kept out of the hot path
+    let text = fs::read_to_string(&temp).unwrap();
+    assert!(text.contains("if(__builtin_expect(!("));
+    assert!(text.contains("_cold1)\nnorem_panic(\"pattern match failed!\");"));
+    let res = process::Command::new("target/examples/cold_branch.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "55\n1000\n12\n");
+}
@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_const_table() {
+    let input = PathBuf::from("examples/const_table.nrm");
+    let library = PathBuf::from("examples/const_table.c");
+    let temp = PathBuf::from("target/examples/const_table.temp.c");
+    let output = PathBuf::from("target/examples/const_table.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    // the tables are static data instead of being built at startup
+    let code = std::fs::read_to_string(&temp).unwrap();
+    assert!(code.contains("static void* norem_const_data["));
+    // aligned for pages of up to 64 KiB, the page size is only known at runtime
+    assert!(code.contains("__attribute__((aligned(65536)))"));
+    assert!(code.contains("sysconf(_SC_PAGESIZE)"));
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/const_table.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "22140\n1600\nzero\n");
+    // the tables are read-only, so writing to one from C is reported
+    let stderr = String::from_utf8(res.stderr).unwrap();
+    assert_eq!(stderr, "mutation of constant data!\n");
+    assert_eq!(res.status.code(), Some(1));
+}
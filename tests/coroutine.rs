@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_coroutine() {
+    let input = PathBuf::from("examples/coroutine.nrm");
+    let library = PathBuf::from("examples/coroutine.c");
+    let temp = PathBuf::from("target/examples/coroutine.temp.c");
+    let output = PathBuf::from("target/examples/coroutine.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/coroutine.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "1\n9\n25\n107\n");
+}
@@ -0,0 +1,28 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_div_check() {
+    let input = PathBuf::from("examples/div_check.nrm");
+    let library = PathBuf::from("examples/div_check.c");
+    let temp = PathBuf::from("target/examples/div_check.temp.c");
+    let output = PathBuf::from("target/examples/div_check.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    // only the divisor of `average` isn't a literal, so it is the only one checked
+    let text = fs::read_to_string(&temp).unwrap();
+    assert_eq!(
+        text.matches("norem_panic(\"division by zero!\");").count(),
+        1
+    );
+    let res = process::Command::new("target/examples/div_check.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "25\n2\n5\ndivision by zero!\n");
+    assert!(!res.status.success());
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+
+extern crate norem;
+use norem::utils::driver;
+
+// every `examples/X.nrm` with an `examples/X.expected` file is compiled with
+// `examples/X.c` as its library, and its stdout is compared with the file
+#[test]
+fn test_examples() {
+    if process::Command::new("cc")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        println!("skipping examples, `cc` is not available");
+        return;
+    }
+    fs::create_dir_all("target/examples").unwrap();
+    let mut names: Vec<String> = fs::read_dir("examples")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "expected"))
+        .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert!(!names.is_empty());
+
+    let mut failed = Vec::new();
+    for name in names.iter() {
+        let input = PathBuf::from(format!("examples/{name}.nrm"));
+        let library = PathBuf::from(format!("examples/{name}.c"));
+        let expected = fs::read_to_string(format!("examples/{name}.expected")).unwrap();
+        let temp = PathBuf::from(format!("target/examples/{name}.temp.c"));
+        let output = PathBuf::from(format!("target/examples/{name}.out"));
+        compile(&input, &temp);
+        driver::run_link(&temp, &library, &output).unwrap();
+        let res = process::Command::new(&output).output().unwrap();
+        let stdout = String::from_utf8(res.stdout).unwrap();
+        if !res.status.success() || stdout != expected {
+            println!("example `{name}` differs from `examples/{name}.expected`:");
+            println!("{stdout}");
+            failed.push(name.as_str());
+        }
+    }
+    assert!(failed.is_empty(), "failed examples: {failed:?}");
+}
+
+// the passes recurse over the syntax tree, and unoptimized builds need more
+// than the default stack of a test thread for the larger examples
+fn compile(input: &Path, temp: &Path) {
+    let (input, temp) = (input.to_path_buf(), temp.to_path_buf());
+    thread::Builder::new()
+        .stack_size(16 * 1024 * 1024)
+        .spawn(move || driver::run_compile(&input, &temp, false).unwrap())
+        .unwrap()
+        .join()
+        .unwrap();
+}
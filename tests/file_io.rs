@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_file_io() {
+    let input = PathBuf::from("examples/file_io.nrm");
+    let library = PathBuf::from("examples/file_io.c");
+    let temp = PathBuf::from("target/examples/file_io.temp.c");
+    let output = PathBuf::from("target/examples/file_io.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/file_io.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "[written]\n[first line\n\tsecond line]\n[written]\n[]\n\
+        [No such file or directory]\n[No such file or directory]\n"
+    );
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_format() {
+    let input = PathBuf::from("examples/format.nrm");
+    let library = PathBuf::from("examples/format.c");
+    let temp = PathBuf::from("target/examples/format.temp.c");
+    let output = PathBuf::from("target/examples/format.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/format.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "[(3, 1.50)]\n[norem has -42 lines]\n[    7|ab  |]\n[100% done]\n[0.25 1.000000e+03]\n"
+    );
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_guard() {
+    let input = PathBuf::from("examples/guard.nrm");
+    let library = PathBuf::from("examples/guard.c");
+    let temp = PathBuf::from("target/examples/guard.temp.c");
+    let output = PathBuf::from("target/examples/guard.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/guard.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "4\n-1\n0\n1\n2\n");
+}
@@ -0,0 +1,23 @@
+use std::fs;
+use std::path::PathBuf;
+
+extern crate norem;
+use norem::backend;
+use norem::utils::driver;
+use norem::utils::printer;
+
+#[test]
+fn test_ir_constructs() {
+    let input = PathBuf::from("examples/ir_constructs.anf");
+    let library = PathBuf::from("examples/ir_constructs.c");
+    // the hand-written IR is in printed form, so it reads back to the same text
+    let text = fs::read_to_string(&input).unwrap();
+    let expr = backend::parse_ir(&text).unwrap();
+    assert_eq!(printer::print_ir(&expr), text);
+    let res = driver::run_ir(&input, Some(&library)).unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "120\nzero\none\nmany\t\"quoted\"\\\nA\n7 2.75 z 1 127\n42\n"
+    );
+}
@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_let_pattern() {
+    let input = PathBuf::from("examples/let_pattern.nrm");
+    let library = PathBuf::from("examples/let_pattern.c");
+    let temp = PathBuf::from("target/examples/let_pattern.temp.c");
+    let output = PathBuf::from("target/examples/let_pattern.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/let_pattern.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    // the last `let` doesn't match, so the program aborts
+    assert_eq!(stdout, "2\n1\n7\npattern match failed!\n");
+    assert_eq!(res.status.code(), Some(1));
+}
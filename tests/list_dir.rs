@@ -0,0 +1,29 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_list_dir() {
+    // the program adds two files to a directory holding only an empty subdirectory
+    let dir = PathBuf::from("target/examples/list_dir");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("sub")).unwrap();
+
+    let input = PathBuf::from("examples/list_dir.nrm");
+    let library = PathBuf::from("examples/list_dir.c");
+    let temp = PathBuf::from("target/examples/list_dir.temp.c");
+    let output = PathBuf::from("target/examples/list_dir.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/list_dir.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "[a.txt]\n[b.txt]\n[sub]\n[No such file or directory]\n"
+    );
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_list_minmax() {
+    let input = PathBuf::from("examples/list_minmax.nrm");
+    let library = PathBuf::from("examples/list_minmax.c");
+    let temp = PathBuf::from("target/examples/list_minmax.temp.c");
+    let output = PathBuf::from("target/examples/list_minmax.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/list_minmax.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "-15\n42\n5\nnone\n");
+}
@@ -0,0 +1,21 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_modules() {
+    // `main.nrm` imports `Shapes.nrm` and `Util.nrm`, which `Shapes` imports as well
+    let input = PathBuf::from("examples/modules/main.nrm");
+    let library = PathBuf::from("examples/modules/lib.c");
+    let temp = PathBuf::from("target/examples/modules.temp.c");
+    let output = PathBuf::from("target/examples/modules.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/modules.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "9\n10\n0\n3\n");
+}
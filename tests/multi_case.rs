@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_multi_case() {
+    let input = PathBuf::from("examples/multi_case.nrm");
+    let library = PathBuf::from("examples/multi_case.c");
+    let temp = PathBuf::from("target/examples/multi_case.temp.c");
+    let output = PathBuf::from("target/examples/multi_case.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/multi_case.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "14\n1\n0\n1\n2\n3\n4\n");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_mutex() {
+    let input = PathBuf::from("examples/mutex.nrm");
+    let library = PathBuf::from("examples/mutex.c");
+    let temp = PathBuf::from("target/examples/mutex.temp.c");
+    let output = PathBuf::from("target/examples/mutex.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/mutex.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "20000\n20001\n");
+}
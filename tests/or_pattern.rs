@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_or_pattern() {
+    let input = PathBuf::from("examples/or_pattern.nrm");
+    let library = PathBuf::from("examples/or_pattern.c");
+    let temp = PathBuf::from("target/examples/or_pattern.temp.c");
+    let output = PathBuf::from("target/examples/or_pattern.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/or_pattern.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "3\n7\n9\n-1\n3\n-1\n");
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_path() {
+    let input = PathBuf::from("examples/path.nrm");
+    let library = PathBuf::from("examples/path.c");
+    let temp = PathBuf::from("target/examples/path.temp.c");
+    let output = PathBuf::from("target/examples/path.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/path.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    let expected = [
+        "/usr/lib",
+        "/etc/hosts",
+        "libc.so",
+        "/usr/lib",
+        "/",
+        "/",
+        "src/main.rs",
+        "main.rs",
+        ".",
+        "src/main.rs",
+        "backend",
+        "src",
+        "main.rs",
+        "src",
+        ".",
+        ".",
+    ];
+    let expected: String = expected.iter().map(|line| format!("[{line}]\n")).collect();
+    assert_eq!(stdout, expected);
+}
@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_real_special() {
+    let input = PathBuf::from("examples/real_special.nrm");
+    let library = PathBuf::from("examples/real_special.c");
+    let temp = PathBuf::from("target/examples/real_special.temp.c");
+    let output = PathBuf::from("target/examples/real_special.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/real_special.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    let bits: Vec<u64> = stdout
+        .lines()
+        .map(|line| u64::from_str_radix(line, 16).unwrap())
+        .collect();
+    // the constant folder and the C code agree bit for bit
+    let (folded, runtime): (Vec<_>, Vec<_>) = bits.chunks(2).map(|x| (x[0], x[1])).unzip();
+    assert_eq!(folded, runtime);
+    let inf = f64::INFINITY.to_bits();
+    let neg_inf = f64::NEG_INFINITY.to_bits();
+    let neg_zero = (-0.0f64).to_bits();
+    assert_eq!(folded[0], inf);
+    assert_eq!(folded[1], neg_inf);
+    assert!(f64::from_bits(folded[2]).is_nan());
+    assert!(f64::from_bits(folded[3]).is_nan());
+    assert_eq!(folded[4], neg_zero);
+    assert!(f64::from_bits(folded[5]).is_nan());
+    assert_eq!(folded[6], inf);
+}
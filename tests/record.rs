@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_record() {
+    let input = PathBuf::from("examples/record.nrm");
+    let library = PathBuf::from("examples/record.c");
+    let temp = PathBuf::from("target/examples/record.temp.c");
+    let output = PathBuf::from("target/examples/record.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/record.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "6\n10\n2\n42\n");
+}
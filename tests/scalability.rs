@@ -0,0 +1,30 @@
+extern crate norem;
+use norem::utils::driver;
+use norem::utils::test_support::*;
+
+#[test]
+fn test_generated_programs() {
+    // the smallest and the largest of every kind go through the frontend without a diagnostic,
+    // the deepest program is at the recursion limit of a release build
+    std::thread::Builder::new()
+        .stack_size(DEEP_STACK)
+        .spawn(check_generated_programs)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn check_generated_programs() {
+    for (kind, sizes, generate) in bench_inputs() {
+        for size in [sizes[0], *sizes.last().unwrap()] {
+            let source = generate(size);
+            let out = driver::compile_partial(&source);
+            assert!(
+                out.diagnostics.is_empty(),
+                "{kind} program of size {size}: {:?}",
+                out.diagnostics
+            );
+            assert!(out.phases.checked, "{kind} program of size {size}");
+        }
+    }
+}
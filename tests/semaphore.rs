@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_semaphore() {
+    let input = PathBuf::from("examples/semaphore.nrm");
+    let library = PathBuf::from("examples/semaphore.c");
+    let temp = PathBuf::from("target/examples/semaphore.temp.c");
+    let output = PathBuf::from("target/examples/semaphore.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/semaphore.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "10");
+    // how many workers actually overlap depends on the scheduler, but never more than 4
+    let peak: i64 = lines[1].parse().unwrap();
+    assert!((1..=4).contains(&peak));
+    assert_eq!(lines[2], "4");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_string() {
+    let input = PathBuf::from("examples/string.nrm");
+    let library = PathBuf::from("examples/string.c");
+    let temp = PathBuf::from("target/examples/string.temp.c");
+    let output = PathBuf::from("target/examples/string.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/string.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "hello\tworld\nsay \"hi\"\ntrue\nfalse\n");
+}
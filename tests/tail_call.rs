@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_tail_call() {
+    let input = PathBuf::from("examples/tail_call.nrm");
+    let library = PathBuf::from("examples/tail_call.c");
+    let temp = PathBuf::from("target/examples/tail_call.temp.c");
+    let output = PathBuf::from("target/examples/tail_call.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/tail_call.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "5000000050000000\n1000000\n");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_thread() {
+    let input = PathBuf::from("examples/thread.nrm");
+    let library = PathBuf::from("examples/thread.c");
+    let temp = PathBuf::from("target/examples/thread.temp.c");
+    let output = PathBuf::from("target/examples/thread.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/thread.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "1055\n5000050000\n");
+}
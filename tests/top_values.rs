@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_top_values() {
+    let input = PathBuf::from("examples/top_values.nrm");
+    let library = PathBuf::from("examples/top_values.c");
+    let temp = PathBuf::from("target/examples/top_values.temp.c");
+    let output = PathBuf::from("target/examples/top_values.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/top_values.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "42\n12\n1\n");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_try_catch() {
+    let input = PathBuf::from("examples/try_catch.nrm");
+    let library = PathBuf::from("examples/try_catch.c");
+    let temp = PathBuf::from("target/examples/try_catch.temp.c");
+    let output = PathBuf::from("target/examples/try_catch.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/try_catch.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "5\n100\n2000\n[No such file or directory]\n");
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+use std::process;
+
+extern crate norem;
+use norem::utils::driver;
+
+#[test]
+fn test_variadic() {
+    let input = PathBuf::from("examples/variadic.nrm");
+    let library = PathBuf::from("examples/variadic.c");
+    let temp = PathBuf::from("target/examples/variadic.temp.c");
+    let output = PathBuf::from("target/examples/variadic.out");
+    driver::run_compile(&input, &temp, false).unwrap();
+    driver::run_link(&temp, &library, &output).unwrap();
+    let res = process::Command::new("target/examples/variadic.out")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(res.stdout).unwrap();
+    assert_eq!(stdout, "42 3.75 hello\n1 7\ndone\n");
+}